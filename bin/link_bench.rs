@@ -0,0 +1,345 @@
+//! Server1 ↔ Server2 link microbenchmark
+//!
+//! Sweeps chunk sizes, concurrency levels and write codecs against a live Server2 and prints one
+//! CSV row of throughput and latency per configuration, so transfer parameters can be picked for
+//! the network a deployment actually runs on.
+//!
+//! For every concurrency level the benchmark stores a pathset on Server2, then for every chunk
+//! size it writes chunks with each codec (`full` buckets as in `/chunk_write`, or `delta` with
+//! half of each bucket's blocks changed as in `/chunk_write_deltas`) and reads the written
+//! buckets back with `/read_paths_client`. Writes overwrite buckets of Server2's tree, so run it
+//! against a Server2 that isn't serving a deployment.
+//!
+//! ```text
+//! link_bench [server2_addr] [--chunks 64,256,512] [--concurrency 1,4,16] [--codecs full,delta] [--requests 32]
+//! ```
+
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use axum::body::Bytes;
+use futures::{stream, StreamExt};
+use myco_rs::{
+    constants::{BLOCK_SIZE, D, Z},
+    dtypes::{Block, Bucket, BucketDelta, Key},
+    rpc_types::{Capabilities, GetCapabilitiesResponse, GetEpochResponse, ReadPathsClientRequest, StorePathIndicesRequest},
+    tls,
+    transport::CHUNK_HEADER,
+};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// How the buckets of a write chunk are encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Codec {
+    /// Full buckets, sent to `/chunk_write`
+    Full,
+    /// Deltas from the buckets Server2 holds, sent to `/chunk_write_deltas`
+    Delta,
+}
+
+impl Codec {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "full" => Ok(Codec::Full),
+            "delta" => Ok(Codec::Delta),
+            _ => Err(format!("unknown codec: {}", name)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Full => "full",
+            Codec::Delta => "delta",
+        }
+    }
+
+    fn endpoint(self) -> &'static str {
+        match self {
+            Codec::Full => "chunk_write",
+            Codec::Delta => "chunk_write_deltas",
+        }
+    }
+}
+
+/// The parameters to sweep.
+struct Sweep {
+    addr: String,
+    /// Buckets per request, capped at Server2's write chunk
+    chunks: Vec<usize>,
+    /// Requests in flight at once
+    concurrency: Vec<usize>,
+    codecs: Vec<Codec>,
+    /// Requests per configuration
+    requests: usize,
+}
+
+impl Sweep {
+    fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut sweep = Sweep {
+            addr: "https://127.0.0.1:3003".to_string(),
+            // The last is capped to Server2's write chunk.
+            chunks: vec![64, 256, usize::MAX],
+            concurrency: vec![1, 4, 16],
+            codecs: vec![Codec::Full, Codec::Delta],
+            requests: 32,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--chunks" => sweep.chunks = parse_list(value()?)?,
+                "--concurrency" => sweep.concurrency = parse_list(value()?)?,
+                "--codecs" => {
+                    sweep.codecs = value()?.split(',').map(Codec::parse).collect::<Result<_, _>>()?
+                }
+                "--requests" => {
+                    sweep.requests = value()?.parse().map_err(|_| format!("invalid {}", arg))?
+                }
+                addr if !addr.starts_with("--") => sweep.addr = addr.to_string(),
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+        if sweep.chunks.contains(&0) || sweep.concurrency.contains(&0) || sweep.requests == 0 {
+            return Err("chunk sizes, concurrency levels and requests must be positive".to_string());
+        }
+        Ok(sweep)
+    }
+}
+
+fn parse_list(value: &str) -> Result<Vec<usize>, String> {
+    value
+        .split(',')
+        .map(|item| item.parse().map_err(|_| format!("invalid number: {}", item)))
+        .collect()
+}
+
+/// A request body, with the epoch and chunk index of a write.
+type RequestBody = (Bytes, Option<(u64, usize)>);
+
+/// Throughput and latency of one configuration.
+struct Measurement {
+    bytes: usize,
+    elapsed: Duration,
+    latencies: Vec<Duration>,
+}
+
+impl Measurement {
+    fn row(mut self, op: &str, codec: &str, chunk: usize, concurrency: usize) -> String {
+        self.latencies.sort();
+        let percentile = |p: usize| {
+            let idx = (self.latencies.len() * p / 100).min(self.latencies.len() - 1);
+            self.latencies[idx].as_secs_f64() * 1000.0
+        };
+        format!(
+            "{},{},{},{},{},{},{:.2},{:.2},{:.2}",
+            op,
+            codec,
+            chunk,
+            concurrency,
+            self.latencies.len(),
+            self.bytes,
+            self.bytes as f64 / self.elapsed.as_secs_f64() / 1e6,
+            percentile(50),
+            percentile(99),
+        )
+    }
+}
+
+struct Link {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl Link {
+    async fn get<R: serde::de::DeserializeOwned>(&self, endpoint: &str) -> Result<R, Box<dyn Error>> {
+        let response = self
+            .client
+            .get(format!("{}/{}", self.base_url, endpoint))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(bincode::deserialize(&response.bytes().await?)?)
+    }
+
+    /// Post `body` to `endpoint`, returning the size of the response.
+    async fn post(&self, endpoint: &str, body: Bytes, chunk: Option<(u64, usize)>) -> Result<usize, Box<dyn Error>> {
+        let mut request = self
+            .client
+            .post(format!("{}/{}", self.base_url, endpoint))
+            .header("Content-Type", "application/octet-stream")
+            .body(body);
+        if let Some((epoch, chunk_idx)) = chunk {
+            request = request.header(CHUNK_HEADER, format!("{}/{}", epoch, chunk_idx));
+        }
+        let response = request.send().await?.error_for_status()?;
+        Ok(response.bytes().await?.len())
+    }
+
+    /// Send `count` requests, `concurrency` at a time, cycling through `bodies`.
+    async fn measure(
+        &self,
+        endpoint: &str,
+        bodies: &[RequestBody],
+        count: usize,
+        concurrency: usize,
+        count_response: bool,
+    ) -> Result<Measurement, Box<dyn Error>> {
+        let start = Instant::now();
+        let results: Vec<_> = stream::iter((0..count).map(|i| {
+            let (body, chunk) = bodies[i % bodies.len()].clone();
+            async move {
+                let sent = body.len();
+                let request_start = Instant::now();
+                let received = self.post(endpoint, body, chunk).await?;
+                let bytes = if count_response { received } else { sent };
+                Ok::<_, Box<dyn Error>>((bytes, request_start.elapsed()))
+            }
+        }))
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+        let elapsed = start.elapsed();
+
+        let mut measurement = Measurement {
+            bytes: 0,
+            elapsed,
+            latencies: Vec::with_capacity(count),
+        };
+        for result in results {
+            let (bytes, latency) = result?;
+            measurement.bytes += bytes;
+            measurement.latencies.push(latency);
+        }
+        Ok(measurement)
+    }
+}
+
+fn random_block(rng: &mut ChaCha20Rng) -> Block {
+    let mut data = vec![0u8; BLOCK_SIZE];
+    rng.fill_bytes(&mut data);
+    Block::new(data)
+}
+
+fn full_bucket(rng: &mut ChaCha20Rng) -> Bucket {
+    let mut bucket = Bucket::default();
+    for _ in 0..Z {
+        bucket.push(random_block(rng));
+    }
+    bucket
+}
+
+/// `bucket` with half of its blocks replaced.
+fn changed_bucket(bucket: &Bucket, rng: &mut ChaCha20Rng) -> Bucket {
+    let mut changed = bucket.clone();
+    for i in (0..changed.len()).step_by(2) {
+        changed[i] = random_block(rng);
+    }
+    changed
+}
+
+/// The body of one write request per chunk of `held`, laid out like a `ChunkWriteRequest`.
+fn write_bodies(
+    held: &[Vec<Bucket>],
+    codec: Codec,
+    prf_key: &Key,
+    epoch: u64,
+    rng: &mut ChaCha20Rng,
+) -> Result<Vec<RequestBody>, bincode::Error> {
+    held.iter()
+        .enumerate()
+        .map(|(chunk_idx, buckets)| {
+            let body = match codec {
+                Codec::Full => bincode::serialize(&(buckets, chunk_idx, prf_key, epoch))?,
+                Codec::Delta => {
+                    let deltas: Vec<_> = buckets
+                        .iter()
+                        .map(|bucket| BucketDelta::between(bucket, changed_bucket(bucket, rng)))
+                        .collect();
+                    bincode::serialize(&(deltas, chunk_idx, prf_key, epoch))?
+                }
+            };
+            Ok((Bytes::from(body), Some((epoch, chunk_idx))))
+        })
+        .collect()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let sweep = Sweep::from_args(&args)?;
+
+    let trust = tls::client_trust();
+    let (builder, base_url) = trust.http_client_builder(&sweep.addr)?;
+    let link = Link {
+        client: builder.build()?,
+        base_url,
+    };
+    let capabilities = link
+        .get::<GetCapabilitiesResponse>("capabilities")
+        .await
+        .map_or_else(|_| Capabilities::local(), |response| response.capabilities);
+    let write_chunk = capabilities.write_chunk_buckets;
+    let epoch = link.get::<GetEpochResponse>("epoch").await?.info.epoch;
+    let prf_key = Key::random(&mut ChaCha20Rng::from_entropy());
+    let mut rng = ChaCha20Rng::from_entropy();
+
+    let num_buckets = (1 << (D + 1)) - 1;
+    eprintln!(
+        "Server2 at {}: epoch {}, {} buckets per write chunk",
+        sweep.addr, epoch, write_chunk
+    );
+    println!("op,codec,chunk_buckets,concurrency,requests,bytes,throughput_mb_s,p50_ms,p99_ms");
+    for &concurrency in &sweep.concurrency {
+        // Give every request in flight its own write chunk of the pathset.
+        let pathset_size = concurrency * write_chunk;
+        if pathset_size > num_buckets {
+            eprintln!("Skipping concurrency {}: pathset exceeds the tree", concurrency);
+            continue;
+        }
+        let request = StorePathIndicesRequest {
+            pathset: (0..pathset_size).collect(),
+        };
+        link.post("store_path_indices", bincode::serialize(&request)?.into(), None)
+            .await?;
+
+        for &chunk in &sweep.chunks {
+            let chunk = chunk.min(write_chunk);
+            let held: Vec<Vec<Bucket>> = (0..concurrency)
+                .map(|_| (0..chunk).map(|_| full_bucket(&mut rng)).collect())
+                .collect();
+
+            for &codec in &sweep.codecs {
+                let bodies = write_bodies(&held, codec, &prf_key, epoch, &mut rng)?;
+                if codec == Codec::Delta {
+                    // Deltas are computed against the full buckets, so put those back first.
+                    let full = write_bodies(&held, Codec::Full, &prf_key, epoch, &mut rng)?;
+                    link.measure(Codec::Full.endpoint(), &full, concurrency, concurrency, false)
+                        .await?;
+                }
+                let measurement = link
+                    .measure(codec.endpoint(), &bodies, sweep.requests, concurrency, false)
+                    .await?;
+                println!("{}", measurement.row("write", codec.name(), chunk, concurrency));
+            }
+
+            // Read the written buckets back.
+            let bodies = (0..concurrency)
+                .map(|chunk_idx| {
+                    let start = chunk_idx * write_chunk;
+                    let request = ReadPathsClientRequest {
+                        indices: (start..start + chunk).collect(),
+                    };
+                    Ok((Bytes::from(bincode::serialize(&request)?), None))
+                })
+                .collect::<Result<Vec<_>, bincode::Error>>()?;
+            let measurement = link
+                .measure("read_paths_client", &bodies, sweep.requests, concurrency, true)
+                .await?;
+            println!("{}", measurement.row("read", "full", chunk, concurrency));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Server2 backup tool
+//!
+//! Takes epoch-aligned backups of a running Server2 into a directory and restores any epoch kept
+//! there as a snapshot Server2 can start from (see [`myco_rs::backup`]). Backups are taken over
+//! Server2's `/admin/backup` route, signed with `MYCO_ADMIN_TOKEN`. To back up a tenant of a
+//! multi-tenant Server2, give the tenant's address, `https://host/t/<tenant>`.
+//!
+//! ```text
+//! myco-backup backup <server2_addr> <dir> [--full]
+//! myco-backup list <dir>
+//! myco-backup restore <dir> <epoch> <snapshot_path>
+//! myco-backup prune <dir> <epoch>
+//! ```
+//!
+//! `backup` takes an incremental backup on top of the newest one in the directory, or a full one
+//! if there is none or `--full` is given.
+
+use std::{error::Error, path::Path};
+
+use axum::http::Method;
+use myco_rs::{
+    admin::{OperatorAuth, ADMIN_TOKEN_ENV},
+    backup,
+    rpc_types::{BackupRequest, BackupResponse},
+    tenant,
+    tls,
+};
+
+const USAGE: &str = "usage:
+  myco-backup backup <server2_addr> <dir> [--full]
+  myco-backup list <dir>
+  myco-backup restore <dir> <epoch> <snapshot_path>
+  myco-backup prune <dir> <epoch>";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["backup", addr, dir, rest @ ..] => {
+            let full = match rest {
+                [] => false,
+                ["--full"] => true,
+                _ => return Err(USAGE.into()),
+            };
+            take_backup(addr, Path::new(dir), full).await
+        }
+        ["list", dir] => {
+            for entry in backup::list_backups(Path::new(dir))? {
+                match entry.since {
+                    Some(since) => println!("{}\tsince {}", entry.epoch, since),
+                    None => println!("{}\tfull", entry.epoch),
+                }
+            }
+            Ok(())
+        }
+        ["restore", dir, epoch, snapshot_path] => {
+            let server2 = backup::restore(Path::new(dir), epoch.parse()?)?;
+            server2.save_snapshot(Path::new(snapshot_path))?;
+            println!("Restored epoch {} to {}", server2.epoch, snapshot_path);
+            Ok(())
+        }
+        ["prune", dir, epoch] => {
+            let removed = backup::prune(Path::new(dir), epoch.parse()?)?;
+            println!("Removed {} backups", removed);
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Take a backup of the Server2 at `addr` into `dir`.
+async fn take_backup(addr: &str, dir: &Path, full: bool) -> Result<(), Box<dyn Error>> {
+    let auth = OperatorAuth::from_env()
+        .ok_or_else(|| format!("{} must be set to take backups", ADMIN_TOKEN_ENV))?;
+    std::fs::create_dir_all(dir)?;
+    let since = match full {
+        true => None,
+        false => backup::latest_epoch(dir)?,
+    };
+
+    let (builder, base_url) = tls::client_trust().http_client_builder(addr)?;
+    let body = bincode::serialize(&BackupRequest { since })?;
+    let path = tenant::route_path(&base_url, "/admin/backup");
+    let headers = auth.signed_headers(&Method::POST, &path, &body);
+    let response = builder
+        .build()?
+        .post(format!("{}/admin/backup", base_url))
+        .headers(headers)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    let BackupResponse { backup } = bincode::deserialize(&response.bytes().await?)?;
+
+    let path = backup::write_backup(dir, &backup)?;
+    println!(
+        "Backed up epoch {} ({} buckets) to {}",
+        backup.epoch,
+        backup.buckets.len(),
+        path.display()
+    );
+    Ok(())
+}
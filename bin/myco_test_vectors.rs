@@ -0,0 +1,47 @@
+//! Test vector tool
+//!
+//! Emits the canonical test vectors of [`myco_rs::test_vectors`] as JSON, or verifies vectors
+//! produced by another implementation (or an earlier version of this crate) against this one.
+//!
+//! ```text
+//! myco-test-vectors emit [<path>]
+//! myco-test-vectors verify <path>
+//! ```
+//!
+//! `emit` writes to standard output unless a path is given.
+
+use std::error::Error;
+
+use myco_rs::test_vectors;
+
+const USAGE: &str = "usage:
+  myco-test-vectors emit [<path>]
+  myco-test-vectors verify <path>";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["emit", rest @ ..] => {
+            let json = test_vectors::to_json(&test_vectors::generate()?);
+            match rest {
+                [] => println!("{json}"),
+                [path] => std::fs::write(path, json + "\n")?,
+                _ => return Err(USAGE.into()),
+            }
+            Ok(())
+        }
+        ["verify", path] => {
+            let vectors = test_vectors::from_json(&std::fs::read_to_string(path)?)?;
+            test_vectors::verify(&vectors)?;
+            println!(
+                "{} key, {} path and {} block vectors match",
+                vectors.keys.len(),
+                vectors.paths.len(),
+                vectors.blocks.len()
+            );
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    }
+}
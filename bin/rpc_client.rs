@@ -7,13 +7,14 @@
 #![allow(private_bounds)]
 
 use myco_rs::{
-    client::Client, constants::{BATCH_SIZE, DELTA, LATENCY_BENCH_COUNT, MESSAGE_SIZE, NUM_CLIENTS}, dtypes::Key, network::{RemoteServer1Access, RemoteServer2Access}
+    admin::{OperatorAuth, ADMIN_TOKEN_ENV}, client::Client, constants::{BATCH_SIZE, DELTA, LATENCY_BENCH_COUNT, MESSAGE_SIZE, NUM_CLIENTS}, crypto, dtypes::Key, idempotency::{IdempotencyKey, IDEMPOTENCY_KEY_HEADER}, store::{MessageStore, STORE_PATH_ENV}, tenant, tls, transport::{TransportConfig, TransportOptions}
 };
 #[cfg(feature = "perf-logging")]
 use myco_rs::logging::calculate_and_append_averages;
 use rand::{Rng, SeedableRng};
+use reqwest::Method;
 use rand_chacha::ChaCha20Rng;
-use std::error::Error;
+use std::{error::Error, time::Duration};
 use tokio::{self};
 use futures::future::join_all;
 
@@ -21,6 +22,7 @@ use futures::future::join_all;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    crypto::self_test()?;
     let args: Vec<String> = std::env::args().collect();
     let binding1 = "https://127.0.0.1:3001".to_string();
     let binding2 = "https://127.0.0.1:3003".to_string();
@@ -37,9 +39,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // Initialize a single client instead of multiple
     let client_name = "SimClient_0".to_string();
-    let s1_access = Box::new(RemoteServer1Access::new(s1_addr).await?);
-    let s2_access = Box::new(RemoteServer2Access::new(s2_addr).await?);
+    let trust = tls::client_trust();
+    // Keep connections alive across the measurement iterations
+    let options = TransportOptions {
+        tcp_keepalive: Some(Duration::from_secs(60)),
+        pool_idle_timeout: Some(Duration::from_secs(300)),
+        ..Default::default()
+    };
+    let s1_access = TransportConfig::from_addr(s1_addr, &trust)?
+        .with_options(options.clone())
+        .server1_access()
+        .await?;
+    let s2_access = TransportConfig::from_addr(s2_addr, &trust)?
+        .with_options(options.clone())
+        .server2_access()
+        .await?;
     let mut simulation_client = Client::new(client_name, s1_access, s2_access);
+    if let Ok(path) = std::env::var(STORE_PATH_ENV) {
+        simulation_client.set_store(MessageStore::open(std::path::Path::new(&path))?);
+    }
     for key in simulation_keys.iter() {
         simulation_client.setup(key)?;
     }
@@ -50,14 +68,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
         println!("\nMeasurement iteration {}/{}", iteration + 1, LATENCY_BENCH_COUNT);
         
         {        
-            let client = reqwest::Client::builder()
-                .danger_accept_invalid_certs(true)  // Only for development
-                .pool_idle_timeout(Some(std::time::Duration::from_secs(300))) // Keep connections alive
-                .tcp_keepalive(Some(std::time::Duration::from_secs(60)))      // Enable TCP keepalive
-                .build()?;
+            let (builder, s1_addr) = trust.http_client_builder(s1_addr)?;
+            let client = options.apply(builder).build()?;
 
             let request = myco_rs::rpc_types::BatchInitRequest {
                 num_writes: NUM_CLIENTS,
+                idempotency_key: Some(IdempotencyKey::random(&mut rng)),
             };
             let request_bytes = bincode::serialize(&request).unwrap();
             
@@ -83,6 +99,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             let response = client
                 .get(format!("{}/batch_write", s1_addr))
+                .header(
+                    IDEMPOTENCY_KEY_HEADER,
+                    IdempotencyKey::random(&mut rng).to_string(),
+                )
                 .send()
                 .await?;
             let response_bytes = response.bytes().await?;
@@ -101,29 +121,30 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 let res = simulation_client
                     .async_read(simulation_keys_subset, simulation_client.id.clone(), 0, batch_size)
                     .await;
-                println!("Read messages: {:?}", res.unwrap());
+                let payloads: Vec<_> = res.unwrap().into_iter().map(|m| m.payload).collect();
+                println!("Read messages: {:?}", payloads);
             }
         }
     }
 
-    // Add this section at the end of main, before calculate_and_append_averages
-    let client = reqwest::Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()?;
-
-    // Finalize Server1 benchmark
-    let response = client
-        .post(format!("{}/finalize_benchmark", s1_addr))
-        .send()
-        .await?;
-    assert!(response.status().is_success());
-
-    // Finalize Server2 benchmark
-    let response = client
-        .post(format!("{}/finalize_benchmark", s2_addr))
-        .send()
-        .await?;
-    assert!(response.status().is_success());
+    // Finalize the server benchmarks. These are operator routes, signed with the admin token.
+    match OperatorAuth::from_env() {
+        Some(auth) => {
+            for addr in [s1_addr, s2_addr] {
+                let (builder, addr) = trust.http_client_builder(addr)?;
+                let path = tenant::route_path(&addr, "/finalize_benchmark");
+                let headers = auth.signed_headers(&Method::POST, &path, &[]);
+                let response = builder
+                    .build()?
+                    .post(format!("{}/finalize_benchmark", addr))
+                    .headers(headers)
+                    .send()
+                    .await?;
+                assert!(response.status().is_success());
+            }
+        }
+        None => println!("{} is not set, not finalizing the server benchmarks", ADMIN_TOKEN_ENV),
+    }
 
     // Calculate client averages
     #[cfg(feature = "perf-logging")]
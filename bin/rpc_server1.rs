@@ -23,24 +23,41 @@ use axum::{
 };
 use axum_server::tls_rustls::RustlsConfig;
 use myco_rs::{
-    constants::{DELTA, LATENCY_BENCH_COUNT},
-    utils::generate_test_certificates,
+    admin::{self, ADMIN_TOKEN_ENV, EPOCH_INTERVAL_ENV},
+    bandwidth,
+    constants::{DELTA, LATENCY_BENCH_COUNT, NUM_CLIENTS},
+    crypto,
     dtypes::Key,
     error::MycoError,
-    network::RemoteServer2Access,
-    rpc_types::{
-        BatchInitRequest, BatchInitResponse, BatchWriteResponse, QueueWriteRequest,
-        QueueWriteResponse,
+    hardening,
+    framed,
+    key_sharing::SharedSecrets,
+    registration,
+    logging::{self, MetricsSink},
+    memory,
+    serve,
+    standby,
+    statsd,
+    tenant::{self, TenantId},
+    tls,
+    transport::{self, TransportConfig},
+    server1::{
+        self,
+        http::{self, AppState},
+        Server1,
     },
-    server1::Server1,
 };
 use serde::{Deserialize, Serialize};
 use std::{fs, path::Path, process::Command};
-use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::{Mutex, RwLock};
 use tower::ServiceBuilder;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+// Count allocations so the admin memory endpoint can report them.
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
+
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
 struct Ports {
@@ -48,12 +65,6 @@ struct Ports {
     https: u16,
 }
 
-#[derive(Clone)]
-struct AppState {
-    server1: Arc<RwLock<Server1>>,
-    batch_write_count: Arc<Mutex<usize>>,
-}
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
@@ -63,6 +74,7 @@ async fn main() {
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
+    crypto::self_test().unwrap();
 
     let args: Vec<String> = std::env::args().collect();
     let s2_addr = args
@@ -76,112 +88,136 @@ async fn main() {
         https: 3001,
     };
 
-    // configure certificate and private key used by https
-    let cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("certs")
-        .join("server-cert.pem");
-    let key_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("certs")
-        .join("server-key.pem");
-
-    // Generate certificates if they don't exist
-    if !cert_path.exists() || !key_path.exists() {
-        generate_test_certificates().map_err(|e| MycoError::CertificateError(e.to_string())).unwrap();
-    }
-
-    let config = RustlsConfig::from_pem_file(cert_path, key_path)
-        .await
-        .unwrap();
-
-    // Initialize Server1 with Server2 access using the provided or default address
-    let s2_access = Box::new(RemoteServer2Access::new(&s2_addr).await.unwrap());
-    let server1 = Server1::new(s2_access);
-    let state = AppState {
-        server1: Arc::new(RwLock::new(server1)),
-        batch_write_count: Arc::new(Mutex::new(0)),
+    // configure certificate and private key used by https, obtained over ACME if configured
+    let (config, cert_path, key_path) = tls::server_config().await.unwrap();
+
+    let metrics = statsd::metrics_sink_from_env().unwrap();
+    let tenants = tenant::tenants_from_env().unwrap();
+
+    let mut states = vec![];
+    let app = if tenants.is_empty() {
+        let mut server1 = start_server1(&s2_addr, None, metrics.clone()).await;
+        if let Some(secrets) = SharedSecrets::from_env().await.unwrap() {
+            server1.set_secret_compute(Arc::new(secrets));
+        }
+        if let Some(key) = standby::replication_key_from_env().unwrap() {
+            server1.set_replication_key(key);
+        }
+        if let Some(replica) = standby::standby_from_env().unwrap() {
+            server1.set_replica(Arc::new(replica));
+        }
+        let bandwidth = server1.bandwidth_meter().clone();
+        let state = AppState::new(server1);
+
+        // Accept client writes over the framed TLS transport as well, if configured.
+        if let Ok(framed_addr) = std::env::var(framed::FRAMED_ADDR_ENV) {
+            let acceptor = framed::tls_acceptor(&cert_path, &key_path).unwrap();
+            let listener = tokio::net::TcpListener::bind(&framed_addr).await.unwrap();
+            let framed_state = state.clone();
+            tokio::spawn(framed::serve(listener, Some(acceptor), Some(bandwidth), move |command| {
+                let state = framed_state.clone();
+                async move { transport::handle_server1_command(&state.server1, &state.control, command).await }
+            }));
+        }
+
+        let app = app(&state, metrics).await;
+        states.push(state);
+        app
+    } else {
+        if std::env::var(framed::FRAMED_ADDR_ENV).is_ok() {
+            tracing::warn!("the framed transport isn't tenanted, only serving HTTPS");
+        }
+        let mut apps = vec![];
+        for tenant in tenants {
+            let server1 = start_server1(&s2_addr, Some(&tenant), metrics.clone()).await;
+            let state = AppState::new(server1);
+            apps.push((tenant, app(&state, metrics.clone()).await));
+            states.push(state);
+        }
+        tenant::nest_tenants(apps)
     };
 
-    let app = Router::new()
-        .route("/queue_write", post(queue_write))
-        .route("/batch_write", get(batch_write))
-        .route("/batch_init", post(batch_init))
-        .route("/finalize_benchmark", post(handle_finalize_benchmark))
-        .layer(
-            ServiceBuilder::new().layer(axum::extract::DefaultBodyLimit::max(
-                1024 * 1024 * 1024 * 1024,
-            )),
-        ) // Set the max request body size.
-        .with_state(state);
-
     // run tcp server
     let addr = SocketAddr::from(([0, 0, 0, 0], ports.https));
     tracing::debug!("listening on {}", addr);
     let listener = std::net::TcpListener::bind(addr).unwrap();
-    axum_server::from_tcp_rustls(listener, config)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
-
-/// Queue a write onto Server1. Uses the shared app state for Server1 to queue the write.
-async fn queue_write(State(state): State<AppState>, bytes: Bytes) -> Result<Bytes, StatusCode> {
-    println!("Received request: /queue_write");
-    let request: QueueWriteRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    // TODO: This should not need a Mutex/RwLock once Server1 is refactored to make the queue_write method threadsafe with DashMap.
-    state
-        .server1
-        .write()
-        .await
-        .queue_write(request.ct, request.f, request.k_oblv_t, request.cs)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&QueueWriteResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    serve::serve_tls(listener, config, app, serve::shutdown_signal(), async move {
+        for state in states {
+            if let Err(e) = serve::shutdown_server1(&state.server1, &state.control).await {
+                tracing::error!("failed to finish the in-flight epoch: {}", e);
+            }
+        }
+    })
+    .await
+    .unwrap();
 }
 
-/// Queue a write onto Server1. Uses the shared app state for Server1 to queue the write.
-async fn batch_write(State(state): State<AppState>) -> Result<Bytes, StatusCode> {
-    println!("Received request: /batch_write");
+/// A Server1 writing to the Server2 at `s2_addr`, configured from the environment. A tenant's
+/// Server1 writes to that tenant on Server2 and may have its own quotas.
+async fn start_server1(s2_addr: &str, tenant: Option<&TenantId>, metrics: Arc<dyn MetricsSink>) -> Server1 {
+    let s2_addr = match tenant {
+        Some(tenant) => tenant::tenant_url(s2_addr, tenant),
+        None => s2_addr.to_string(),
+    };
+    let mut write_quota = server1::write_quota_from_env().unwrap();
+    let mut registration_limit = registration::limit_from_env().unwrap();
+    if let Some(tenant) = tenant {
+        write_quota = tenant::limit_from_env(server1::WRITE_QUOTA_ENV, tenant, write_quota).unwrap();
+        registration_limit =
+            tenant::limit_from_env(registration::MAX_REGISTRATIONS_ENV, tenant, registration_limit).unwrap();
+    }
 
-    state
-        .server1
-        .write()
-        .await
-        .async_batch_write()
+    // Initialize Server1 with Server2 access using the provided or default address
+    let s2_access = TransportConfig::from_addr(&s2_addr, &tls::client_trust())
+        .unwrap()
+        .server2_access()
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&BatchWriteResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        .unwrap();
+    let mut server1 = Server1::new(s2_access);
+    server1.set_nu(server1::nu_from_env().unwrap()).unwrap();
+    server1.set_write_quota(write_quota);
+    server1.set_anonymity_gate(server1::AnonymityGate::from_env().unwrap());
+    server1.set_auto_batch_init(server1::auto_batch_init_from_env().unwrap());
+    server1.set_delta_writes(server1::delta_writes_from_env().unwrap()).unwrap();
+    server1.set_prefetch(server1::prefetch_from_env().unwrap());
+    server1.registrations.set_limit(registration_limit);
+    server1.set_metrics_sink(metrics);
+    server1
 }
 
-/// Queue a write onto Server1. Uses the shared app state for Server1 to queue the write.
-async fn batch_init(State(state): State<AppState>, bytes: Bytes) -> Result<Bytes, StatusCode> {
-    println!("Received request: /batch_init");
-    let request: BatchInitRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    // TODO: This should not need a Mutex/RwLock once Server1 is refactored to make the queue_write method threadsafe with DashMap.
-    state
-        .server1
-        .write()
-        .await
-        .async_batch_init(request.num_writes)
-        .await;
+/// The app serving the Server1 in `state`, starting its epoch scheduler if one is configured.
+async fn app(state: &AppState, metrics: Arc<dyn MetricsSink>) -> Router {
+    // Advance epochs on a timer if an interval is configured, otherwise wait for batch_init/batch_write.
+    if let Some(interval) = std::env::var(EPOCH_INTERVAL_ENV)
+        .ok()
+        .and_then(|ms| ms.parse::<u64>().ok())
+    {
+        tokio::spawn(admin::run_epoch_scheduler(
+            state.server1.clone(),
+            state.control.clone(),
+            std::time::Duration::from_millis(interval),
+            NUM_CLIENTS,
+        ));
+    }
 
-    bincode::serialize(&BatchInitResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
+    let mut router = http::router();
+    #[cfg(feature = "debug-json")]
+    let mut json_routes = http::json_routes();
+
+    // Only expose the admin API and benchmark routes when a token is configured.
+    if let Ok(token) = std::env::var(ADMIN_TOKEN_ENV) {
+        router = router
+            .merge(http::benchmark_router(admin::OperatorAuth::new(token.clone())))
+            .nest("/admin", admin::router(state.admin_state(token)));
+        #[cfg(feature = "debug-json")]
+        json_routes.extend(admin::json_routes());
+    }
 
-// Add this new endpoint handler
-async fn handle_finalize_benchmark(State(state): State<AppState>) -> Result<Bytes, StatusCode> {
-    println!("Received request: /finalize_benchmark");
-    #[cfg(feature = "perf-logging")]
-    myco_rs::logging::calculate_and_append_averages("server1_latency.csv", "server1_bytes.csv");
-    Ok(Bytes::from("Benchmark finalized"))
+    let bandwidth = state.server1.read().await.bandwidth_meter().clone();
+    let app = bandwidth::meter(hardening::harden(router), bandwidth);
+    let app = logging::instrument(app, "server1", metrics)
+        .with_state(state.clone());
+    #[cfg(feature = "debug-json")]
+    let app = myco_rs::json::debug_routes(app, &json_routes);
+    app
 }
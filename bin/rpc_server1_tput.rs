@@ -5,6 +5,7 @@
 #![allow(dead_code)]
 #![allow(unused_parens)]
 #![allow(private_bounds)]
+#![allow(clippy::await_holding_lock)]
 
 use axum::body::Bytes;
 use axum::{extract::State, http::StatusCode, routing::post, Router};
@@ -13,18 +14,18 @@ use futures::future::join_all;
 use myco_rs::{
     client::Client,
     constants::{BATCH_SIZE, FIXED_SEED_TPUT_RNG, NUM_CLIENTS, THROUGHPUT_ITERATIONS},
-    utils::generate_test_certificates,
+    crypto,
     dtypes::Key,
-    error::MycoError,
     network::{LocalServer1Access, RemoteServer2Access},
-    server1::Server1,
+    server1::{self, Server1},
+    tls,
+    transport::TransportOptions,
 };
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 use std::sync::RwLock;
 use std::{
     net::SocketAddr,
-    path::PathBuf,
     sync::{Arc, Mutex as StdMutex},
     time::Instant,
 };
@@ -44,6 +45,7 @@ struct Server1TputState {
 async fn main() {
     // Setup logging
     tracing_subscriber::fmt::init();
+    crypto::self_test().unwrap();
 
     // Get Server2 address from command line args
     let args: Vec<String> = std::env::args().collect();
@@ -54,27 +56,20 @@ async fn main() {
 
     println!("Connecting to Server2 at: {}", s2_addr);
 
-    // Setup HTTPS certificates
-    let cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("certs")
-        .join("server-cert.pem");
-    let key_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("certs")
-        .join("server-key.pem");
+    // configure certificate and private key used by https
+    let (cert_path, key_path) = tls::server_identity().unwrap();
 
-    if !cert_path.exists() || !key_path.exists() {
-        generate_test_certificates().map_err(|e| MycoError::CertificateError(e.to_string())).unwrap();
-    }
-
-    let config = RustlsConfig::from_pem_file(cert_path, key_path)
+    let config = RustlsConfig::from_pem_file(&cert_path, &key_path)
         .await
         .unwrap();
 
     // Initialize Server2 connection using provided address
-    let s2_access = Box::new(RemoteServer2Access::new(&s2_addr).await.unwrap());
+    let trust = tls::client_trust();
+    let s2_access = Box::new(RemoteServer2Access::new(&s2_addr, &trust, &TransportOptions::default()).await.unwrap());
 
     // Initialize Server1 and state
-    let server1 = Server1::new(s2_access);
+    let mut server1 = Server1::new(s2_access);
+    server1.set_nu(server1::nu_from_env().unwrap()).unwrap();
     let server1 = Arc::new(RwLock::new(server1));
 
     // Generate simulation keys
@@ -91,7 +86,7 @@ async fn main() {
         let client_name = format!("WriterClient_{}", i);
         let s1_access = Box::new(LocalServer1Access::new(server1.clone()));
         // We will never use this here, but it's required by the Client constructor.
-        let s2_access = Box::new(RemoteServer2Access::new(&s2_addr).await.unwrap());
+        let s2_access = Box::new(RemoteServer2Access::new(&s2_addr, &trust, &TransportOptions::default()).await.unwrap());
         let mut client = Client::new(client_name, s1_access, s2_access);
 
         // Setup keys for this client
@@ -116,11 +111,6 @@ async fn main() {
     // Start timing
     *state.start_time.lock().unwrap() = Some(Instant::now());
 
-    let client = reqwest::Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .unwrap();
-
     for iteration in 0..THROUGHPUT_ITERATIONS {
         println!(
             "\nThroughput iteration {}/{}",
@@ -136,7 +126,8 @@ async fn main() {
             .write()
             .unwrap()
             .async_batch_init(NUM_CLIENTS)
-            .await;
+            .await
+            .expect("Batch init failed");
 
         println!("Batch init finished");
 
@@ -163,7 +154,7 @@ async fn main() {
             .unwrap()
             .async_batch_write()
             .await
-            .map_err(|e| MycoError::DatabaseError(format!("Failed to batch write: {}", e))).unwrap();
+            .expect("Failed to batch write");
 
         println!("Batch write finished");
 
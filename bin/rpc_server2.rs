@@ -22,20 +22,27 @@ use axum::{
 };
 use axum_server::tls_rustls::RustlsConfig;
 use myco_rs::{
+    admin,
+    bandwidth,
     constants::{DELTA, LATENCY_BENCH_COUNT},
-    utils::generate_test_certificates,
+    crypto,
     dtypes::{Bucket, Key, Path},
     error::MycoError,
+    framed,
+    hardening,
+    journal,
+    logging::{self, MetricsSink},
+    memory,
     network::RemoteServer2Access,
-    rpc_types::{
-        ChunkReadPathsClientRequest, ChunkReadPathsClientResponse, ChunkReadPathsRequest,
-        ChunkReadPathsResponse, ChunkWriteRequest, ChunkWriteResponse, FinalizeEpochRequest,
-        FinalizeEpochResponse, GetPrfKeysResponse, ReadPathsClientRequest, ReadPathsRequest,
-        ReadPathsResponse, ReadRequest, ReadResponse, StorePathIndicesRequest,
-        StorePathIndicesResponse, WriteRequest, WriteResponse,
+    serve,
+    statsd,
+    tenant,
+    tls,
+    transport,
+    server2::{
+        http::{self, AppState},
+        Server2,
     },
-    server1::Server1,
-    server2::Server2,
 };
 use serde::{Deserialize, Serialize};
 use std::{
@@ -49,6 +56,10 @@ use tokio::sync::RwLock;
 use tower::ServiceBuilder;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+// Count allocations so the admin memory endpoint can report them.
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
+
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
 struct Ports {
@@ -56,12 +67,6 @@ struct Ports {
     https: u16,
 }
 
-#[derive(Clone)]
-struct AppState {
-    server2: Arc<RwLock<Server2>>,
-    write_count: Arc<Mutex<usize>>,
-}
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
@@ -71,236 +76,115 @@ async fn main() {
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
+    crypto::self_test().unwrap();
 
     let ports = Ports {
         http: 3004,
         https: 3003,
     };
 
-    // configure certificate and private key used by https
-    let cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("certs")
-        .join("server-cert.pem");
-    let key_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("certs")
-        .join("server-key.pem");
-
-    // Generate certificates if they don't exist
-    if !cert_path.exists() || !key_path.exists() {
-        generate_test_certificates().map_err(|e| MycoError::CertificateError(e.to_string())).unwrap();
-    }
-
-    let config = RustlsConfig::from_pem_file(cert_path, key_path)
-        .await
-        .unwrap();
-
-    let server2 = Server2::new();
-    let state = AppState {
-        server2: Arc::new(RwLock::new(server2)),
-        write_count: Arc::new(Mutex::new(0)),
+    // configure certificate and private key used by https, obtained over ACME if configured
+    let (config, cert_path, key_path) = tls::server_config().await.unwrap();
+
+    // Restore from the snapshot flushed at the last shutdown, if there is one.
+    let snapshot_path = std::env::var(serve::SNAPSHOT_PATH_ENV).ok().map(PathBuf::from);
+    let metrics = statsd::metrics_sink_from_env().unwrap();
+    let auth = admin::OperatorAuth::from_env();
+    let tenants = tenant::tenants_from_env().unwrap();
+
+    let mut instances = vec![];
+    let app = if tenants.is_empty() {
+        let state = start_server2(
+            snapshot_path.clone(),
+            journal::journal_from_env().unwrap(),
+            metrics.clone(),
+        );
+
+        // Serve Server1 and clients over the framed TLS transport as well, if configured.
+        if let Ok(framed_addr) = std::env::var(framed::FRAMED_ADDR_ENV) {
+            let acceptor = framed::tls_acceptor(&cert_path, &key_path).unwrap();
+            let listener = tokio::net::TcpListener::bind(&framed_addr).await.unwrap();
+            let server2 = state.server2.clone();
+            let bandwidth = state.server2.read().await.bandwidth_meter().clone();
+            tokio::spawn(framed::serve(listener, Some(acceptor), Some(bandwidth), move |command| {
+                let server2 = server2.clone();
+                async move { transport::handle_server2_command(&server2, command).await }
+            }));
+        }
+
+        let app = app(&state, auth, metrics).await;
+        instances.push((state, snapshot_path));
+        app
+    } else {
+        if std::env::var(framed::FRAMED_ADDR_ENV).is_ok() {
+            tracing::warn!("the framed transport isn't tenanted, only serving HTTPS");
+        }
+        let mut apps = vec![];
+        for tenant in tenants {
+            let snapshot_path = snapshot_path
+                .as_deref()
+                .map(|path| tenant::tenant_file(path, &tenant));
+            let state = start_server2(
+                snapshot_path.clone(),
+                journal::tenant_journal_from_env(&tenant).unwrap(),
+                metrics.clone(),
+            );
+            apps.push((tenant, app(&state, auth.clone(), metrics.clone()).await));
+            instances.push((state, snapshot_path));
+        }
+        tenant::nest_tenants(apps)
     };
 
-    let app = Router::new()
-        .route("/read_paths", post(handle_read_paths))
-        .route("/read_paths_client", post(handle_read_paths_client))
-        .route("/chunk_read_paths_client", post(handle_chunk_read_paths_client))
-        .route("/write", post(handle_write))
-        .route("/chunk_write", post(handle_chunk_write))
-        .route("/chunk_read_paths", post(handle_chunk_read_paths))
-        .route("/store_path_indices", post(handle_store_path_indices))
-        .route("/finalize_epoch", post(handle_finalize_epoch))
-        .route("/get_prf_keys", get(handle_get_prf_keys))
-        .route("/finalize_benchmark", post(handle_finalize_benchmark))
-        .layer(
-            ServiceBuilder::new().layer(axum::extract::DefaultBodyLimit::max(
-                1024 * 1024 * 1024 * 1024,
-            )),
-        ) // Set the max request body size.
-        .with_state(state);
-
     // run tcp server
     let addr = SocketAddr::from(([0, 0, 0, 0], ports.https));
     tracing::debug!("listening on {}", addr);
     let listener = std::net::TcpListener::bind(addr).unwrap();
-    axum_server::from_tcp_rustls(listener, config)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
-
-async fn handle_read_paths(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    println!("Received request: /read_paths");
-    // TODO: Optimize the request to be smaller by sending the list of paths rather than the indices, and computing it client side. (E.g. just send leaves)
-    let request: ReadPathsRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let buckets = state
-        .server2
-        .write()
-        .await
-        .read_and_store_path_indices(request.indices)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&ReadPathsResponse { buckets })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    serve::serve_tls(listener, config, app, serve::shutdown_signal(), async move {
+        for (state, snapshot_path) in instances {
+            if let Err(e) = serve::shutdown_server2(&state.server2, snapshot_path.as_deref()).await {
+                tracing::error!("failed to flush snapshot: {}", e);
+            }
+        }
+    })
+    .await
+    .unwrap();
 }
 
-/// Store the pathset indices.
-async fn handle_store_path_indices(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    println!("Received request: /store_path_indices");
-    let request: StorePathIndicesRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    state
-        .server2
-        .write()
-        .await
-        .store_path_indices(request.pathset);
-
-    bincode::serialize(&StorePathIndicesResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-/// Read a chunk of buckets from the server.
-async fn handle_chunk_read_paths(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    {
-        let mut count = state.write_count.lock().unwrap();
-        *count += 1;
+/// Start a Server2 from the snapshot at `snapshot_path`, if there is one, and roll it forward
+/// through `journal`.
+fn start_server2(
+    snapshot_path: Option<PathBuf>,
+    journal: Option<(journal::Journal, Vec<journal::JournalRecord>)>,
+    metrics: Arc<dyn MetricsSink>,
+) -> AppState {
+    let mut server2 = match &snapshot_path {
+        Some(path) if path.exists() => Server2::load_snapshot(path).unwrap(),
+        _ => Server2::new(),
+    };
+    // Roll forward the epochs committed to the journal since the snapshot.
+    if let Some((journal, records)) = journal {
+        let replayed = server2.replay_journal(records).unwrap();
+        tracing::info!("replayed {} epochs from the journal", replayed);
+        server2.set_journal(journal);
     }
-
-    let request: ChunkReadPathsRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let buckets = state
-        .server2
-        .read()
-        .await
-        .read_pathset_chunk(request.chunk_idx)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&ChunkReadPathsResponse { buckets })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    server2.set_metrics_sink(metrics);
+    AppState::new(server2)
 }
 
-async fn handle_read_paths_client(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    println!("Received request: /read_paths_client");
-    let request: ReadPathsClientRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let buckets = state
-        .server2
-        .read()
-        .await
-        .read_paths_client(request.indices)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&ReadPathsResponse { buckets })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-async fn handle_chunk_read_paths_client(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    println!("Received request: /chunk_read_paths_client");
-    let request: ChunkReadPathsClientRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let buckets = state
-        .server2
-        .read()
-        .await
-        .read_paths_client_chunk(request.chunk_idx, request.indices)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&ChunkReadPathsClientResponse { buckets })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-
-async fn handle_chunk_write(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    let request: ChunkWriteRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    state
-        .server2
-        .write()
-        .await
-        .chunk_write(request.buckets, request.chunk_idx);
-
-    bincode::serialize(&ChunkWriteResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-async fn handle_finalize_epoch(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    println!("Received request: /finalize_epoch");
-    let request: FinalizeEpochRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    state.server2.write().await.finalize_epoch(&request.prf_key);
-
-    bincode::serialize(&FinalizeEpochResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-async fn handle_write(State(state): State<AppState>, bytes: Bytes) -> Result<Bytes, StatusCode> {
-    let request: WriteRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    state.server2.write().await.write(request.buckets);
-    state.server2.write().await.add_prf_key(&request.prf_key);
-
-    bincode::serialize(&WriteResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-async fn handle_get_prf_keys(State(state): State<AppState>) -> Result<Bytes, StatusCode> {
-    println!("Received request: /get_prf_keys");
-    
-    let keys = state
-        .server2
-        .read()
-        .await
-        .get_prf_keys()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&GetPrfKeysResponse { keys })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-async fn handle_finalize_benchmark(State(state): State<AppState>) -> Result<Bytes, StatusCode> {
-    println!("Received request: /finalize_benchmark");
-    #[cfg(feature = "perf-logging")]
-    myco_rs::logging::calculate_and_append_averages(
-        "server2_latency.csv",
-        "server2_bytes.csv",
-    );
-    Ok(Bytes::from("Benchmark finalized"))
+/// The app serving the Server2 in `state`.
+async fn app(state: &AppState, auth: Option<admin::OperatorAuth>, metrics: Arc<dyn MetricsSink>) -> Router {
+    // Only expose the benchmark and admin routes when an admin token is configured.
+    let mut router = http::router();
+    if let Some(auth) = auth {
+        router = router
+            .merge(http::benchmark_router(auth.clone()))
+            .merge(http::admin_router(auth));
+    }
+    let bandwidth = state.server2.read().await.bandwidth_meter().clone();
+    let app = bandwidth::meter(hardening::harden(router), bandwidth);
+    let app = logging::instrument(app, "server2", metrics)
+        .with_state(state.clone());
+    #[cfg(feature = "debug-json")]
+    let app = myco_rs::json::debug_routes(app, &http::json_routes());
+    app
 }
@@ -8,32 +8,36 @@
 
 use axum::body::Bytes;
 use axum::{
-    extract::State,
+    extract::{FromRef, State},
     http::StatusCode,
     routing::{get, post},
     Router,
 };
 use axum_server::tls_rustls::RustlsConfig;
 use myco_rs::{
+    bandwidth,
     client::Client,
     constants::{BATCH_SIZE, FIXED_SEED_TPUT_RNG, NUM_CLIENTS, THROUGHPUT_ITERATIONS},
-    utils::{generate_test_certificates, get_path_indices},
+    crypto,
+    utils::get_path_indices,
     dtypes::{Key, Path},
     error::MycoError,
+    hardening,
+    logging,
     rpc_types::{
-        ChunkReadPathsRequest, ChunkReadPathsResponse, ChunkWriteRequest, ChunkWriteResponse,
-        EpochNumberResponse, FinalizeEpochRequest, FinalizeEpochResponse, ReadPathsRequest,
+        ChunkReadPathsRequest, ChunkWriteRequest, ChunkWriteResponse,
+        EpochNumberResponse, ErrorResponse, FinalizeEpochRequest, FinalizeEpochResponse, ReadPathsRequest,
         ReadPathsResponse, StorePathIndicesRequest, StorePathIndicesResponse,
     },
-    server2::Server2,
+    server2::{http, Server2},
+    tls,
     tree::SparseBinaryTree,
-    crypto::{kdf, prf},
+    crypto::{client_pseudonym, kdf, location_prf, prf},
 };
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
 use std::{
     net::SocketAddr,
-    path::PathBuf,
     sync::{Arc, Mutex},
 };
 use tokio::sync::RwLock;
@@ -50,8 +54,19 @@ struct AppState {
     simulation_k_prf: Arc<Vec<Vec<u8>>>,
 }
 
+// Lets the shared Server2 handlers run against the throughput state.
+impl FromRef<AppState> for http::AppState {
+    fn from_ref(state: &AppState) -> Self {
+        http::AppState {
+            server2: state.server2.clone(),
+            write_count: state.write_count.clone(),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    crypto::self_test().unwrap();
 
     // Get bind address from command line args
     let args: Vec<String> = std::env::args().collect();
@@ -63,28 +78,9 @@ async fn main() {
     println!("Server2 binding to: {}", bind_addr);
 
     // configure certificate and private key used by https
-    let cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("target")
-        .join("certs")
-        .join("server-cert.pem");
-    let key_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("target")
-        .join("certs")
-        .join("server-key.pem");
-
-    // Create target/certs directory if it doesn't exist
-    std::fs::create_dir_all(
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("target")
-            .join("certs")
-    ).map_err(|e| MycoError::CertificateError(e.to_string())).unwrap();
-
-    // Generate certificates if they don't exist
-    if !cert_path.exists() || !key_path.exists() {
-        generate_test_certificates().map_err(|e| MycoError::CertificateError(e.to_string())).unwrap();
-    }
+    let (cert_path, key_path) = tls::server_identity().unwrap();
 
-    let config = RustlsConfig::from_pem_file(cert_path, key_path)
+    let config = RustlsConfig::from_pem_file(&cert_path, &key_path)
         .await
         .unwrap();
 
@@ -108,6 +104,7 @@ async fn main() {
         simulation_k_prf.push(kdf(&k.0, "PRF").unwrap());
     }
 
+    let bandwidth = server2.bandwidth_meter().clone();
     let state = AppState {
         server2: Arc::new(RwLock::new(server2)),
         write_count: Arc::new(Mutex::new(0)),
@@ -117,16 +114,14 @@ async fn main() {
         simulation_k_prf: Arc::new(simulation_k_prf),
     };
 
-    let app = Router::new()
-        .route("/chunk_write", post(handle_chunk_write))
-        .route("/chunk_read_paths", post(handle_chunk_read_paths))
-        .route("/store_path_indices", post(handle_store_path_indices))
-        .route("/finalize_epoch", post(handle_finalize_epoch))
-        .layer(
-            ServiceBuilder::new().layer(axum::extract::DefaultBodyLimit::max(
-                1024 * 1024 * 1024 * 1024,
-            )),
-        )
+    let router = Router::new()
+        .route("/chunk_write", post(http::handle_chunk_write))
+        .route("/chunk_read_paths", post(http::handle_chunk_read_paths))
+        .route("/store_path_indices", post(http::handle_store_path_indices))
+        .route("/store_path_leaves", post(http::handle_store_path_leaves))
+        .route("/finalize_epoch", post(handle_finalize_epoch));
+    let app = bandwidth::meter(hardening::harden(router), bandwidth);
+    let app = logging::instrument(app, "server2", Arc::new(logging::PerfLog))
         .with_state(state);
 
     // run tcp server with provided bind address
@@ -138,76 +133,19 @@ async fn main() {
         .unwrap();
 }
 
-async fn handle_store_path_indices(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    let request: StorePathIndicesRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    state
-        .server2
-        .write()
-        .await
-        .store_path_indices(request.pathset);
-
-    bincode::serialize(&StorePathIndicesResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-async fn handle_chunk_read_paths(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    {
-        let mut count = state.write_count.lock().unwrap();
-        *count += 1;
-    }
-
-    let request: ChunkReadPathsRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let buckets = state
-        .server2
-        .read()
-        .await
-        .read_pathset_chunk(request.chunk_idx)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    bincode::serialize(&ChunkReadPathsResponse { buckets })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-async fn handle_chunk_write(
+/// This finalizes the epoch AND simulates the client reading from S2.
+async fn handle_finalize_epoch(
     State(state): State<AppState>,
     bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    let request: ChunkWriteRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
+) -> Result<Bytes, ErrorResponse> {
+    let request: FinalizeEpochRequest = hardening::decode(&bytes)?;
 
+    println!("Finalizing epoch");
     state
         .server2
         .write()
         .await
-        .chunk_write(request.buckets, request.chunk_idx);
-
-    bincode::serialize(&ChunkWriteResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-}
-
-/// This finalizes the epoch AND simulates the client reading from S2.
-async fn handle_finalize_epoch(
-    State(state): State<AppState>,
-    bytes: Bytes,
-) -> Result<Bytes, StatusCode> {
-    let request: FinalizeEpochRequest =
-        bincode::deserialize(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    println!("Finalizing epoch");
-    state.server2.write().await.finalize_epoch(&request.prf_key);
+        .finalize_epoch(request.epoch, &request.prf_key)?;
 
     // Now, let's read with all of the simulated clients.
     // New: Perform reads after each batch write
@@ -219,8 +157,7 @@ async fn handle_finalize_epoch(
         .server2
         .read()
         .await
-        .get_prf_keys()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .get_prf_keys()?;
 
     println!("Starting to perform the client reads");
     let futures = (0..NUM_CLIENTS).map(|i| {
@@ -235,9 +172,7 @@ async fn handle_finalize_epoch(
             server_keys.clone(),
         )
     });
-    futures::future::try_join_all(futures)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    futures::future::try_join_all(futures).await?;
 
     println!("Client reads finished");
 
@@ -252,9 +187,7 @@ async fn handle_finalize_epoch(
         myco_rs::logging::calculate_and_append_averages("server2_latency.csv", "server2_bytes.csv");
     }
 
-    bincode::serialize(&FinalizeEpochResponse { success: true })
-        .map(Bytes::from)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    hardening::encode(&FinalizeEpochResponse { success: true })
 }
 
 async fn read_without_client(
@@ -268,17 +201,17 @@ async fn read_without_client(
 ) -> Result<(), MycoError> {
     let epoch = current_epoch - 1;
     let cs: Vec<u8> = cs.into_bytes();
-    let k_s1_t = current_prf_keys.get(current_prf_keys.len() - 1).unwrap();
+    let k_s1_t = current_prf_keys.last().unwrap();
 
     let mut paths = Vec::new();
     let mut key_data = Vec::new();
 
     for i in 0..simulation_k_msg.len() {
-        let k_oblv_t = kdf(&simulation_k_oblv[i], &epoch.to_string())
-            .map_err(|_| MycoError::NoMessageFound)?;
+        let k_oblv_t = kdf(&simulation_k_oblv[i], &epoch.to_string())?;
         let f = prf(&simulation_k_prf[i], &epoch.to_be_bytes())?;
 
-        let l = prf(k_s1_t.0.as_slice(), &[&f[..], &cs[..]].concat())?;
+        let pseudonym = client_pseudonym(&simulation_k_prf[i], &cs, epoch)?;
+        let l = location_prf(&k_s1_t.0, &f, &pseudonym)?;
         let l_path = Path::from(l);
         paths.push(l_path);
         key_data.push((simulation_k_msg[i].clone(), k_oblv_t));
@@ -290,8 +223,7 @@ async fn read_without_client(
     server2
         .read()
         .await
-        .read_paths_client(indices.clone())
-        .map_err(|_| MycoError::NoMessageFound)?;
+        .read_paths_client(indices.clone())?;
 
     Ok(())
 }
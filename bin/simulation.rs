@@ -11,10 +11,10 @@ use myco_rs::{
     constants::{DB_SIZE, DELTA, NUM_CLIENTS},
     utils::calculate_bucket_usage,
     dtypes::Key,
-    error::MycoError,
     network::{LocalServer1Access, LocalServer2Access},
     server1::Server1,
     server2::Server2,
+    simulation::{SimulationMode, SIMULATION_MODE_ENV},
 };
 use rand::{Rng, SeedableRng};
 use rayon::iter::{
@@ -28,13 +28,15 @@ use std::{
 };
 use std::io::Write;
 
-fn run_multi_client_simulation(num_clients: usize, num_epochs: usize) {
+fn run_multi_client_simulation(num_clients: usize, num_epochs: usize, mode: SimulationMode) {
     use rand_chacha::ChaCha20Rng;
     use std::time::Duration;
 
     let s2 = Arc::new(Mutex::new(Server2::new()));
     let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
-    let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+    let mut server1 = Server1::new(s2_access.clone());
+    server1.set_simulation_mode(mode);
+    let s1 = Arc::new(RwLock::new(server1));
     let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
 
     let mut rng = ChaCha20Rng::from_entropy();
@@ -47,8 +49,9 @@ fn run_multi_client_simulation(num_clients: usize, num_epochs: usize) {
     for i in 0..num_clients {
         let client_name = format!("Client_{}", i);
         let mut client = Client::new(client_name, s1_access.clone(), s2_access.clone());
+        client.set_simulation_mode(mode);
 
-        client.setup(&key).map_err(|e| MycoError::DatabaseError(format!("Setup failed: {}", e))).unwrap();
+        client.setup(&key).expect("Setup failed");
 
         clients.push(client);
     }
@@ -68,10 +71,11 @@ fn run_multi_client_simulation(num_clients: usize, num_epochs: usize) {
         let write_start_time = std::time::Instant::now();
         clients.par_iter_mut().for_each(|client| {
             let message: Vec<u8> = (0..16).map(|_| rng.clone().gen()).collect();
-            #[cfg(feature = "no-enc")]
-            client.fake_write().map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
-            #[cfg(not(feature = "no-enc"))]
-            client.write(&message, &key).map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
+            if mode.encrypts() {
+                client.write(&message, &key).expect("Write failed");
+            } else {
+                client.fake_write().expect("Write failed");
+            }
         });
         let write_duration = write_start_time.elapsed();
 
@@ -85,9 +89,9 @@ fn run_multi_client_simulation(num_clients: usize, num_epochs: usize) {
         // Note: These operations are on the order of microseconds.
         for client in clients.iter() {
             let read_start_time = std::time::Instant::now();
-            let read_result: Vec<u8> = client
+            let read_result = client
                 .read(&key, client.id.clone(), 0)
-                .map_err(|e| MycoError::DatabaseError(format!("Read failed: {}", e))).unwrap();
+                .expect("Read failed");
             let client_read_duration = read_start_time.elapsed();
             total_read_duration += client_read_duration;
         }
@@ -115,13 +119,15 @@ fn run_multi_client_simulation(num_clients: usize, num_epochs: usize) {
     );
 }
 
-fn run_simulation(num_epochs: usize) {
+fn run_simulation(num_epochs: usize, mode: SimulationMode) {
     use rand_chacha::ChaCha20Rng;
     use std::time::Duration;
 
     let s2 = Arc::new(Mutex::new(Server2::new()));
     let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
-    let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+    let mut server1 = Server1::new(s2_access.clone());
+    server1.set_simulation_mode(mode);
+    let s1 = Arc::new(RwLock::new(server1));
     let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
 
     let mut rng = ChaCha20Rng::from_entropy();
@@ -132,12 +138,13 @@ fn run_simulation(num_epochs: usize) {
     let mut clients = Vec::new();
     for i in 0..NUM_CLIENTS {
         let mut client = Client::new(format!("Client_{}", i), s1_access.clone(), s2_access.clone());
-        client.setup(&key).map_err(|e| MycoError::DatabaseError(format!("Setup failed: {}", e))).unwrap();
+        client.set_simulation_mode(mode);
+        client.setup(&key).expect("Setup failed");
         clients.push(client);
     }
 
     // Track bucket usage statistics over time
-    let check_interval = 1000 as usize;
+    let check_interval: usize = 1000;
     let usage_stats: Vec<(usize, usize, f64, f64, f64)> = Vec::new();
     let k_msg = clients[0].keys.get(&key).unwrap().0.clone();
 
@@ -154,10 +161,11 @@ fn run_simulation(num_epochs: usize) {
         let write_start_time = std::time::Instant::now();
         clients.par_iter_mut().for_each(|client| {
             let message: Vec<u8> = (0..16).map(|_| rng.clone().gen()).collect();
-            #[cfg(feature = "no-enc")]
-            client.fake_write().map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
-            #[cfg(not(feature = "no-enc"))]
-            client.write(&message, &key).map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
+            if mode.encrypts() {
+                client.write(&message, &key).expect("Write failed");
+            } else {
+                client.fake_write().expect("Write failed");
+            }
         });
         let write_duration = write_start_time.elapsed();
 
@@ -179,6 +187,7 @@ fn run_simulation(num_epochs: usize) {
         //         &s2.lock().unwrap().tree,
         //         &s1.read().unwrap().metadata,
         //         &k_msg,
+        //         mode,
         //     );
         //     usage_stats.push(stats);
 
@@ -232,13 +241,15 @@ fn run_simulation(num_epochs: usize) {
     }
 }
 
-fn run_local_latency_benchmark() {
+fn run_local_latency_benchmark(mode: SimulationMode) {
     use rand_chacha::ChaCha20Rng;
     use std::time::Duration;
 
     let s2 = Arc::new(Mutex::new(Server2::new()));
     let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
-    let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+    let mut server1 = Server1::new(s2_access.clone());
+    server1.set_simulation_mode(mode);
+    let s1 = Arc::new(RwLock::new(server1));
     let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
 
     let mut rng = ChaCha20Rng::from_entropy();
@@ -253,7 +264,8 @@ fn run_local_latency_benchmark() {
             s1_access.clone(),
             s2_access.clone(),
         );
-        client.setup(&key).map_err(|e| MycoError::DatabaseError(format!("Setup failed: {}", e))).unwrap();
+        client.set_simulation_mode(mode);
+        client.setup(&key).expect("Setup failed");
         keys.push(key);
         clients.push(client);
     }
@@ -273,12 +285,13 @@ fn run_local_latency_benchmark() {
             .enumerate()
             .for_each(|(client_idx, (client, key))| {
                 let message: Vec<u8> = (0..16).map(|_| rng.clone().gen()).collect();
-                #[cfg(feature = "no-enc")]
-                client.fake_write().map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
-                #[cfg(not(feature = "no-enc"))]
-                client.write(&message, key).map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
+                if mode.encrypts() {
+                    client.write(&message, key).expect("Write failed");
+                } else {
+                    client.fake_write().expect("Write failed");
+                }
 
-                if (epoch * NUM_CLIENTS + client_idx) % 1000 == 0 {
+                if (epoch * NUM_CLIENTS + client_idx).is_multiple_of(1000) {
                     println!("Progress: Write {} in epoch {}", client_idx, epoch);
                 }
             });
@@ -304,10 +317,11 @@ fn run_local_latency_benchmark() {
         // Measure write
         let start = std::time::Instant::now();
         let message: Vec<u8> = (0..16).map(|_| rng.clone().gen()).collect();
-        #[cfg(feature = "no-enc")]
-        clients[0].fake_write().map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
-        #[cfg(not(feature = "no-enc"))]
-        clients[0].write(&message, &keys[0]).map_err(|e| MycoError::DatabaseError(format!("Write failed: {}", e))).unwrap();
+        if mode.encrypts() {
+            clients[0].write(&message, &keys[0]).expect("Write failed");
+        } else {
+            clients[0].fake_write().expect("Write failed");
+        }
         write_times.push(start.elapsed());
 
         // Measure batch_write
@@ -319,7 +333,7 @@ fn run_local_latency_benchmark() {
         let start = std::time::Instant::now();
         clients[0]
             .read(&keys[0], clients[0].id.clone(), 0)
-            .map_err(|e| MycoError::DatabaseError(format!("Read failed: {}", e))).unwrap();
+            .expect("Read failed");
         read_times.push(start.elapsed());
     }
 
@@ -334,10 +348,10 @@ fn run_local_latency_benchmark() {
     use std::io::Write;
 
     // Create directory if it doesn't exist
-    create_dir_all("test_sims").map_err(|e| MycoError::DatabaseError(format!("Failed to create directory: {}", e))).unwrap();
+    create_dir_all("test_sims").expect("Failed to create directory");
 
     // Open file for writing
-    let mut file = File::create("test_sims/latency").map_err(|e| MycoError::DatabaseError(format!("Failed to create latency file: {}", e))).unwrap();
+    let mut file = File::create("test_sims/latency").expect("Failed to create latency file");
 
 
     // Write results to file
@@ -358,20 +372,29 @@ fn run_local_latency_benchmark() {
 }
 
 fn main() {
-    #[cfg(feature = "no-enc")]
-    println!("Running simulation in NO ENCRYPTION mode");
-
-    #[cfg(not(feature = "no-enc"))]
-    println!("Running simulation in STANDARD ENCRYPTION mode");
-
     let args: Vec<String> = std::env::args().collect();
 
     let simulation_type = &args[1];
 
+    // Turning encryption off takes both the flag and the environment variable.
+    let mode = if args[2..].iter().any(|arg| arg == "--no-enc") {
+        SimulationMode::no_encryption().unwrap_or_else(|e| {
+            eprintln!("--no-enc: {} (set {}=no-enc)", e, SIMULATION_MODE_ENV);
+            std::process::exit(1);
+        })
+    } else {
+        SimulationMode::Off
+    };
+    if mode.encrypts() {
+        println!("Running simulation in STANDARD ENCRYPTION mode");
+    } else {
+        println!("Running simulation in NO ENCRYPTION mode");
+    }
+
     match simulation_type.as_str() {
-        "sim" => run_simulation(DELTA*DELTA*DELTA),
-        "multi" => run_multi_client_simulation(NUM_CLIENTS, DELTA),
-        "benchmark" => run_local_latency_benchmark(),
+        "sim" => run_simulation(DELTA*DELTA*DELTA, mode),
+        "multi" => run_multi_client_simulation(NUM_CLIENTS, DELTA, mode),
+        "benchmark" => run_local_latency_benchmark(mode),
         _ => panic!("Unknown simulation type. Use: single, multi, or benchmark"),
     }
 }
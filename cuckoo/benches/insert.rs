@@ -0,0 +1,68 @@
+//! Compares inserting items one by one with `insert_batch`.
+//!
+//! Run with `cargo bench -p cuckoo`.
+
+use std::time::{Duration, Instant};
+
+use cuckoo::{Item, Table};
+
+const NUM_BUCKETS: usize = 1 << 14;
+const BUCKET_DEPTH: usize = 4;
+const ITEM_SIZE: usize = 32;
+const LOAD_FACTOR: f64 = 0.8;
+const RUNS: u32 = 5;
+
+fn table() -> Table {
+    Table::new(
+        NUM_BUCKETS,
+        BUCKET_DEPTH,
+        ITEM_SIZE,
+        None,
+        0,
+        b"bench_key_1".to_vec(),
+        b"bench_key_2".to_vec(),
+    )
+    .unwrap()
+}
+
+/// Items filling the table to `LOAD_FACTOR`, without buckets.
+fn items() -> Vec<Item> {
+    let count = (NUM_BUCKETS as f64 * BUCKET_DEPTH as f64 * LOAD_FACTOR) as u64;
+    (0..count)
+        .map(|seq_no| Item::new(seq_no, vec![seq_no as u8; ITEM_SIZE], seq_no, 0, 0))
+        .collect()
+}
+
+/// Average time of `run` on a fresh table. For `insert` this includes computing the buckets of the
+/// items, which `insert_batch` does itself.
+fn bench(name: &str, run: impl Fn(&mut Table, Vec<Item>)) {
+    let mut total = Duration::ZERO;
+    for _ in 0..RUNS {
+        let mut table = table();
+        let items = items();
+        let start = Instant::now();
+        run(&mut table, items);
+        total += start.elapsed();
+    }
+    println!("{:<12} {:>10.2?}", name, total / RUNS);
+}
+
+fn main() {
+    println!(
+        "inserting {} items into {} buckets of depth {}",
+        items().len(),
+        NUM_BUCKETS,
+        BUCKET_DEPTH
+    );
+
+    bench("insert", |table, items| {
+        for mut item in items {
+            (item.bucket1, item.bucket2) = table.buckets(item.seq_no).unwrap();
+            table.insert(&item).unwrap();
+        }
+    });
+
+    bench("insert_batch", |table, items| {
+        table.insert_batch(&items).unwrap();
+    });
+}
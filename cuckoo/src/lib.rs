@@ -1,10 +1,18 @@
+use hmac::{Hmac, Mac};
 use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 const MAX_EVICTIONS: usize = 500;
+/// Number of items kept aside when no slot is found for them within `MAX_EVICTIONS` evictions.
+pub const STASH_SIZE: usize = 4;
+#[cfg(test)]
 const RANDOM_SEED: u64 = 12345;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
     pub id: u64,
     pub data: Vec<u8>,
@@ -13,7 +21,7 @@ pub struct Item {
     pub bucket2: usize,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct ItemLocation {
     id: u64,
     filled: bool,
@@ -30,17 +38,88 @@ pub enum Error {
     NoSpaceAfterEviction,
     #[error("HMAC error: {0}")]
     HmacError(String),
+    #[error("Serialization error: {0}")]
+    SerializationError(String),
+}
+
+/// Running totals of what inserts had to do to place items, for tuning the table parameters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counters {
+    /// Items moved out of their slot to make room for another.
+    pub evictions: u64,
+    /// Items that went to the stash after the maximum number of evictions.
+    pub stashed: u64,
+    /// Items returned from an insert because the stash was full.
+    pub left_out: u64,
+    /// Times the table grew.
+    pub grows: u64,
+}
+
+/// Maps a key and a sequence number to a bucket hash, which the table reduces modulo the number of
+/// buckets. Each table hashes with two keys, giving every item two candidate buckets.
+///
+/// Any `Fn(&[u8], u64) -> usize` is a hasher, so a faster keyed hash such as SipHash or BLAKE3 can
+/// be plugged in with a closure.
+pub trait BucketHasher: Send + Sync {
+    fn hash(&self, key: &[u8], seq_no: u64) -> Result<usize, Error>;
+}
+
+impl<F> BucketHasher for F
+where
+    F: Fn(&[u8], u64) -> usize + Send + Sync,
+{
+    fn hash(&self, key: &[u8], seq_no: u64) -> Result<usize, Error> {
+        Ok(self(key, seq_no))
+    }
 }
 
-pub struct Table {
+/// Myco's HKDF-based PRF, the default hasher. This is the construction of `myco_rs::crypto::prf`,
+/// HKDF-SHA256 with a fixed salt and the big-endian sequence number as info, reimplemented here so
+/// that Myco can use the table without a dependency cycle.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MycoPrf;
+
+impl BucketHasher for MycoPrf {
+    fn hash(&self, key: &[u8], seq_no: u64) -> Result<usize, Error> {
+        let hmac = |key: &[u8], parts: &[&[u8]]| -> Result<Vec<u8>, Error> {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+                .map_err(|e| Error::HmacError(e.to_string()))?;
+            for part in parts {
+                mac.update(part);
+            }
+            Ok(mac.finalize().into_bytes().to_vec())
+        };
+        let salt = Sha256::digest(b"MC-OSAM-Salt");
+        let prk = hmac(&salt, &[key])?;
+        let okm = hmac(&prk, &[&seq_no.to_be_bytes(), &[1]])?;
+        Ok(usize::from_be_bytes(okm[0..8].try_into().unwrap()))
+    }
+}
+
+/// A cuckoo hash table. The whole state, including the eviction RNG, can be serialized, so a table
+/// restored with [`Table::from_bytes`] behaves exactly like the one saved with [`Table::to_bytes`].
+/// The hasher isn't part of the state: a restored table gets a default one, so only tables with a
+/// hasher implementing `Default` can be restored, with [`Table::from_bytes_with_default_hasher`].
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "", deserialize = "H: Default"))]
+pub struct Table<H: BucketHasher = MycoPrf> {
     num_buckets: usize,
     bucket_depth: usize,
     item_size: usize,
     data: Vec<u8>,
-    rng: StdRng,
+    rng: ChaCha12Rng,
     index: Vec<ItemLocation>,
     key1: Vec<u8>,
     key2: Vec<u8>,
+    /// Number of filled slots.
+    len: usize,
+    /// Items that didn't fit in their buckets.
+    stash: Vec<Item>,
+    /// Load factor above which the table grows instead of evicting items.
+    max_load_factor: Option<f64>,
+    counters: Counters,
+    #[serde(skip)]
+    hasher: H,
 }
 
 impl Table {
@@ -52,6 +131,37 @@ impl Table {
         rand_seed: u64,
         key1: Vec<u8>,
         key2: Vec<u8>,
+    ) -> Option<Self> {
+        Self::with_hasher(
+            num_buckets,
+            bucket_depth,
+            item_size,
+            data,
+            rand_seed,
+            key1,
+            key2,
+            MycoPrf,
+        )
+    }
+
+    /// Restore a table serialized with [`Table::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::from_bytes_with_default_hasher(bytes)
+    }
+}
+
+impl<H: BucketHasher> Table<H> {
+    /// Create a table placing items with `hasher` instead of Myco's PRF.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_hasher(
+        num_buckets: usize,
+        bucket_depth: usize,
+        item_size: usize,
+        data: Option<Vec<u8>>,
+        rand_seed: u64,
+        key1: Vec<u8>,
+        key2: Vec<u8>,
+        hasher: H,
     ) -> Option<Self> {
         let expected_size = num_buckets * bucket_depth * item_size;
         let data = match data {
@@ -65,37 +175,268 @@ impl Table {
             bucket_depth,
             item_size,
             data,
-            rng: StdRng::seed_from_u64(rand_seed),
+            rng: ChaCha12Rng::seed_from_u64(rand_seed),
             index: vec![ItemLocation::default(); num_buckets * bucket_depth],
             key1,
             key2,
+            len: 0,
+            stash: Vec::new(),
+            max_load_factor: None,
+            counters: Counters::default(),
+            hasher,
         })
     }
+    /// Number of items in the table, including the stash.
+    pub fn len(&self) -> usize {
+        self.len + self.stash.len()
+    }
+
+    /// Whether the table holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of slots in the table.
+    pub fn capacity(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Fraction of the slots that are filled, not counting the stash.
+    pub fn load_factor(&self) -> f64 {
+        if self.capacity() == 0 {
+            return 0.0;
+        }
+        self.len as f64 / self.capacity() as f64
+    }
+
+    /// Histogram of bucket loads: entry `i` is the number of buckets holding `i` items, for `i`
+    /// from 0 to the bucket depth.
+    pub fn load_histogram(&self) -> Vec<usize> {
+        let mut histogram = vec![0; self.bucket_depth + 1];
+        for bucket in self.index.chunks(self.bucket_depth.max(1)) {
+            histogram[bucket.iter().filter(|loc| loc.filled).count()] += 1;
+        }
+        histogram
+    }
+
+    /// Counters accumulated since the table was created.
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Iterate over the items in the table, including the stash, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = Item> + '_ {
+        (0..self.index.len())
+            .filter_map(|i| self.get_item(i))
+            .chain(self.stash.iter().cloned())
+    }
+
+    /// Make [`Table::insert`] grow the table whenever an insert would take it above `factor`, or
+    /// when an item would be left out after the maximum number of evictions. Since growing moves
+    /// items to new buckets, items for an automatically growing table should get their buckets from
+    /// [`Table::buckets`] right before they are inserted.
+    pub fn set_max_load_factor(&mut self, factor: Option<f64>) -> Result<(), Error> {
+        if factor.is_some_and(|f| !(f > 0.0 && f <= 1.0)) {
+            return Err(Error::InvalidInput);
+        }
+        self.max_load_factor = factor;
+        Ok(())
+    }
+
+    /// The two buckets of sequence number `seq_no` at the table's current size.
+    pub fn buckets(&self, seq_no: u64) -> Result<(usize, usize), Error> {
+        Ok((self.prf(&self.key1, seq_no)?, self.prf(&self.key2, seq_no)?))
+    }
+
+    /// Serialize the table.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        bincode::serialize(self).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+
+    /// Restore a table with a `Default` hasher serialized with [`Table::to_bytes`].
+    pub fn from_bytes_with_default_hasher(bytes: &[u8]) -> Result<Self, Error>
+    where
+        H: Default,
+    {
+        let table: Self =
+            bincode::deserialize(bytes).map_err(|e| Error::SerializationError(e.to_string()))?;
+        // A table without buckets or slots can't place anything, and `prf` would divide by zero.
+        let slots = table
+            .num_buckets
+            .checked_mul(table.bucket_depth)
+            .filter(|&slots| slots > 0);
+        let Some(slots) = slots else {
+            return Err(Error::SerializationError(
+                "serialized table has no slots".to_string(),
+            ));
+        };
+        if table.index.len() != slots
+            || Some(table.data.len()) != slots.checked_mul(table.item_size)
+            || table.index.iter().filter(|loc| loc.filled).count() != table.len
+            || table.stash.len() > STASH_SIZE
+            || table
+                .stash
+                .iter()
+                .any(|item| item.data.len() != table.item_size)
+        {
+            return Err(Error::SerializationError(
+                "serialized table state is inconsistent".to_string(),
+            ));
+        }
+        Ok(table)
+    }
 
     fn prf(&self, key: &[u8], seq_no: u64) -> Result<usize, Error> {
-        let input = seq_no.to_be_bytes();
-        let result = myco_rs::crypto::prf(key, &input)
-            .map_err(|e| Error::HmacError(e.to_string()))?;
-        
-        Ok(usize::from_be_bytes(result[0..8].try_into().unwrap()) % self.num_buckets)
+        Ok(self.hasher.hash(key, seq_no)? % self.num_buckets)
     }
 
+    /// Insert an item. If no slot is found for it within the maximum number of evictions, the item
+    /// left over goes to the stash, and only once the stash is full is it returned; it may be an
+    /// item inserted earlier. With a maximum load factor set, the table grows instead, and no item
+    /// is ever left out.
     pub fn insert(&mut self, item: &Item) -> Result<Option<Item>, Error> {
         if item.data.len() != self.item_size {
             return Err(Error::InvalidInput);
         }
 
-        let bucket1 = self.prf(&self.key1, item.seq_no)?;
-        let bucket2 = self.prf(&self.key2, item.seq_no)?;
+        if self.buckets(item.seq_no)? != (item.bucket1, item.bucket2) {
+            return Err(Error::InvalidInput);
+        }
+
+        let mut item = item.clone();
+        if self.grow_for(1)? {
+            item = self.relocate(item)?;
+        }
+        self.place_or_grow(item)
+    }
 
-        if bucket1 != item.bucket1 || bucket2 != item.bucket2 {
+    /// Insert a batch of items. The table computes the buckets of the items in parallel, so their
+    /// `bucket1` and `bucket2` are ignored, and first places items wherever they fit without
+    /// evictions, so the eviction loop only runs for the few that don't. Returns the items left
+    /// out, as [`Table::insert`] would. If any item has the wrong size, none are inserted.
+    pub fn insert_batch(&mut self, items: &[Item]) -> Result<Vec<Item>, Error> {
+        if items.iter().any(|item| item.data.len() != self.item_size) {
             return Err(Error::InvalidInput);
         }
 
+        let mut items = items.to_vec();
+        self.grow_for(items.len())?;
+        let buckets: Vec<(usize, usize)> = items
+            .par_iter()
+            .map(|item| self.buckets(item.seq_no))
+            .collect::<Result<_, _>>()?;
+        for (item, buckets) in items.iter_mut().zip(buckets) {
+            (item.bucket1, item.bucket2) = buckets;
+        }
+
+        let mut unplaced = Vec::new();
+        for item in items {
+            let (first_bucket, other_bucket) = if self.rng.gen_bool(0.5) {
+                (item.bucket1, item.bucket2)
+            } else {
+                (item.bucket2, item.bucket1)
+            };
+            if !self.try_insert_to_bucket(first_bucket, &item)
+                && !self.try_insert_to_bucket(other_bucket, &item)
+            {
+                unplaced.push(item);
+            }
+        }
+
+        let num_buckets = self.num_buckets;
+        let mut left_out = Vec::new();
+        for mut item in unplaced {
+            // Growing while placing an earlier item moves the buckets.
+            if self.num_buckets != num_buckets {
+                item = self.relocate(item)?;
+            }
+            left_out.extend(self.place_or_grow(item)?);
+        }
+        Ok(left_out)
+    }
+
+    /// Grow the table as far as the maximum load factor requires to add `additional` items.
+    /// Returns whether it grew.
+    fn grow_for(&mut self, additional: usize) -> Result<bool, Error> {
+        let Some(max_load_factor) = self.max_load_factor else {
+            return Ok(false);
+        };
+        let mut grew = false;
+        while (self.len() + additional) as f64 > max_load_factor * self.capacity() as f64 {
+            self.grow()?;
+            grew = true;
+        }
+        Ok(grew)
+    }
+
+    /// Place an item, growing the table instead of leaving an item out if a maximum load factor is
+    /// set.
+    fn place_or_grow(&mut self, item: Item) -> Result<Option<Item>, Error> {
+        let mut pending = item;
+        loop {
+            match self.place(&pending)? {
+                Some(evicted) if self.max_load_factor.is_some() => {
+                    self.grow()?;
+                    pending = self.relocate(evicted)?;
+                }
+                left_out => return Ok(left_out),
+            }
+        }
+    }
+
+    /// Double the number of buckets and move every item to its buckets in the larger table. If the
+    /// items don't all fit, the table keeps doubling until they do.
+    pub fn grow(&mut self) -> Result<(), Error> {
+        let items: Vec<Item> = self.iter().collect();
+        self.counters.grows += 1;
+        let mut num_buckets = (self.num_buckets * 2).max(1);
+
+        'rehash: loop {
+            self.num_buckets = num_buckets;
+            self.data = vec![0; num_buckets * self.bucket_depth * self.item_size];
+            self.index = vec![ItemLocation::default(); num_buckets * self.bucket_depth];
+            self.len = 0;
+            self.stash.clear();
+
+            for item in &items {
+                let item = self.relocate(item.clone())?;
+                if self.place(&item)?.is_some() {
+                    num_buckets *= 2;
+                    continue 'rehash;
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// Update the buckets of an item to the table's current size.
+    fn relocate(&self, mut item: Item) -> Result<Item, Error> {
+        (item.bucket1, item.bucket2) = self.buckets(item.seq_no)?;
+        Ok(item)
+    }
+
+    /// Insert an item whose buckets have been checked, evicting items as needed and stashing the
+    /// item left over.
+    fn place(&mut self, item: &Item) -> Result<Option<Item>, Error> {
+        match self.cuckoo(item)? {
+            Some(left_over) if self.stash.len() < STASH_SIZE => {
+                self.stash.push(left_over);
+                self.counters.stashed += 1;
+                Ok(None)
+            }
+            Some(left_over) => {
+                self.counters.left_out += 1;
+                Ok(Some(left_over))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn cuckoo(&mut self, item: &Item) -> Result<Option<Item>, Error> {
         let (first_bucket, other_bucket) = if self.rng.gen_bool(0.5) {
-            (bucket1, bucket2)
+            (item.bucket1, item.bucket2)
         } else {
-            (bucket2, bucket1)
+            (item.bucket2, item.bucket1)
         };
 
         if self.try_insert_to_bucket(first_bucket, item) {
@@ -123,6 +464,85 @@ impl Table {
         Ok(Some(current_item))
     }
 
+    /// Look up the item with sequence number `seq_no`, probing both of its buckets and the stash.
+    pub fn get(&self, seq_no: u64) -> Option<Item> {
+        self.find(seq_no, |loc| loc.seq_no == seq_no).or_else(|| {
+            self.stash
+                .iter()
+                .find(|item| item.seq_no == seq_no)
+                .cloned()
+        })
+    }
+
+    /// Whether the item `id` with sequence number `seq_no` is in the table. Items are placed by
+    /// sequence number, so it is needed to know which buckets to probe.
+    pub fn contains(&self, id: u64, seq_no: u64) -> bool {
+        self.find(seq_no, |loc| loc.id == id && loc.seq_no == seq_no)
+            .is_some()
+            || self
+                .stash
+                .iter()
+                .any(|item| item.id == id && item.seq_no == seq_no)
+    }
+
+    /// Remove the item with sequence number `seq_no`, returning it.
+    pub fn remove(&mut self, seq_no: u64) -> Option<Item> {
+        match self.find_index(seq_no, |loc| loc.seq_no == seq_no) {
+            Some(item_index) => self.clear(item_index),
+            None => self.unstash(|item| item.seq_no == seq_no),
+        }
+    }
+
+    /// Remove the item `id`, returning it. Without a sequence number there are no buckets to
+    /// probe, so this scans the whole table.
+    pub fn remove_by_id(&mut self, id: u64) -> Option<Item> {
+        match self.index.iter().position(|loc| loc.filled && loc.id == id) {
+            Some(item_index) => self.clear(item_index),
+            None => self.unstash(|item| item.id == id),
+        }
+    }
+
+    fn find(&self, seq_no: u64, matches: impl Fn(&ItemLocation) -> bool) -> Option<Item> {
+        self.find_index(seq_no, matches)
+            .and_then(|item_index| self.get_item(item_index))
+    }
+
+    fn find_index(&self, seq_no: u64, matches: impl Fn(&ItemLocation) -> bool) -> Option<usize> {
+        let bucket1 = self.prf(&self.key1, seq_no).ok()?;
+        let bucket2 = self.prf(&self.key2, seq_no).ok()?;
+
+        [bucket1, bucket2].into_iter().find_map(|bucket_index| {
+            let start = bucket_index * self.bucket_depth;
+            let end = (bucket_index + 1) * self.bucket_depth;
+            (start..end).find(|&i| self.index[i].filled && matches(&self.index[i]))
+        })
+    }
+
+    fn clear(&mut self, item_index: usize) -> Option<Item> {
+        let item = self.get_item(item_index)?;
+        let data_start = item_index * self.item_size;
+        self.data[data_start..data_start + self.item_size].fill(0);
+        self.index[item_index] = ItemLocation::default();
+        self.len -= 1;
+
+        // Move a stashed item into the freed slot if it belongs there.
+        let bucket_index = item_index / self.bucket_depth;
+        if let Some(i) = self
+            .stash
+            .iter()
+            .position(|item| item.bucket1 == bucket_index || item.bucket2 == bucket_index)
+        {
+            let stashed = self.stash.swap_remove(i);
+            self.try_insert_to_bucket(bucket_index, &stashed);
+        }
+        Some(item)
+    }
+
+    fn unstash(&mut self, matches: impl Fn(&Item) -> bool) -> Option<Item> {
+        let i = self.stash.iter().position(matches)?;
+        Some(self.stash.swap_remove(i))
+    }
+
     fn try_insert_to_bucket(&mut self, bucket_index: usize, item: &Item) -> bool {
         let start = bucket_index * self.bucket_depth;
         let end = (bucket_index + 1) * self.bucket_depth;
@@ -138,6 +558,7 @@ impl Table {
                     bucket2: item.bucket2,
                     seq_no: item.seq_no,
                 };
+                self.len += 1;
                 return true;
             }
         }
@@ -160,6 +581,8 @@ impl Table {
         let evict_idx = bucket_index * self.bucket_depth + self.rng.gen_range(0..self.bucket_depth);
         let evicted_item = self.get_item(evict_idx).unwrap();
         self.index[evict_idx].filled = false;
+        self.len -= 1;
+        self.counters.evictions += 1;
 
         if !self.try_insert_to_bucket(bucket_index, item) {
             return Err(Error::NoSpaceAfterEviction);
@@ -204,7 +627,6 @@ impl PartialEq for Item {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::prelude::*;
 
     const TEST_ITEM_SIZE: usize = 64;
     const TEST_KEY1: &[u8] = b"test_key_1_for_prf_computation";
@@ -235,6 +657,19 @@ mod tests {
         Item::new(id, data, seq_no, bucket1, bucket2)
     }
 
+    fn num_elements(table: &Table) -> usize {
+        table.index.iter().filter(|loc| loc.filled).count() + table.stash.len()
+    }
+
+    fn holds(table: &Table, item: &Item) -> bool {
+        table.index.iter().any(|loc| {
+            loc.filled
+                && loc.id == item.id
+                && loc.bucket1 == item.bucket1
+                && loc.bucket2 == item.bucket2
+        }) || table.stash.iter().any(|stashed| stashed == item)
+    }
+
     #[test]
     fn test_get_capacity() {
         let table = create_test_table(10, 2);
@@ -271,7 +706,7 @@ mod tests {
         assert!(result.unwrap().is_none());
 
         assert!(table.index.iter().any(|loc| loc.filled && loc.id == 1));
-        assert_eq!(1, table.index.iter().filter(|loc| loc.filled).count());
+        assert_eq!(1, table.len());
     }
 
     #[test]
@@ -309,12 +744,10 @@ mod tests {
             match table.insert(&item) {
                 Ok(None) => {
                     count += 1;
-                    let found = table.index.iter().any(|loc| {
-                        loc.filled && loc.id == id && loc.bucket1 == item.bucket1 && loc.bucket2 == item.bucket2
-                    });
+                    let found = holds(&table, &item);
                     assert!(found, "Insert() succeeded, but item not found in table");
 
-                    let actual_count = table.index.iter().filter(|loc| loc.filled).count();
+                    let actual_count = num_elements(&table);
                     assert_eq!(
                         count, actual_count,
                         "Number of successful inserts ({}) does not match actual elements ({})",
@@ -329,7 +762,7 @@ mod tests {
             }
         }
 
-        let actual_count = table.index.iter().filter(|loc| loc.filled).count();
+        let actual_count = num_elements(&table);
         assert_eq!(
             count, actual_count,
             "Number of successful inserts ({}) does not match actual elements ({})",
@@ -339,27 +772,27 @@ mod tests {
 
         for entry in entries {
             if Some(&entry) != evicted.as_ref() {
-                let found = table.index.iter().any(|loc| {
-                    loc.filled
-                        && loc.bucket1 == entry.bucket1
-                        && loc.bucket2 == entry.bucket2
-                        && loc.id == entry.id
-                });
+                let found = holds(&table, &entry);
                 assert!(
                     found,
                     "Cannot find element believed to be in table. item {} of {}",
                     count, max_count
                 );
 
+                let mut removed = false;
                 for loc in table.index.iter_mut() {
                     if loc.filled && loc.id == entry.id {
                         loc.filled = false;
-                        count -= 1;
+                        removed = true;
                         break;
                     }
                 }
+                if !removed {
+                    table.stash.retain(|item| item.id != entry.id);
+                }
+                count -= 1;
 
-                let actual_count = table.index.iter().filter(|loc| loc.filled).count();
+                let actual_count = num_elements(&table);
                 assert_eq!(
                     count, actual_count,
                     "GetNumElements()={} returned value that didn't match expected={}",
@@ -368,7 +801,7 @@ mod tests {
             }
         }
 
-        let final_count = table.index.iter().filter(|loc| loc.filled).count();
+        let final_count = num_elements(&table);
         assert_eq!(
             0, final_count,
             "GetNumElements() returns {} when table should be empty",
@@ -413,4 +846,318 @@ mod tests {
         let bucket1_c = table.prf(TEST_KEY1, 1).unwrap();
         assert_ne!(bucket1_a, bucket1_c);
     }
+
+    #[test]
+    fn test_lookup() {
+        let mut table = create_test_table(100, 4);
+        assert!(table.get(0).is_none());
+
+        let mut stored = Vec::new();
+        for seq_no in 0..200u64 {
+            let data = get_bytes(&seq_no.to_string());
+            let item = create_test_item(&table, seq_no + 1000, data, seq_no);
+            assert!(table.insert(&item).unwrap().is_none());
+            stored.push(item);
+        }
+
+        for item in &stored {
+            let found = table.get(item.seq_no).expect("inserted item not found");
+            assert_eq!(item.id, found.id);
+            assert_eq!(item.data, found.data);
+            assert_eq!(item.seq_no, found.seq_no);
+            assert!(table.contains(item.id, item.seq_no));
+            assert!(!table.contains(item.id + 1, item.seq_no));
+        }
+
+        assert!(table.get(200).is_none());
+        assert!(!table.contains(1200, 200));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut table = create_test_table(10, 2);
+        let item1 = create_test_item(&table, 1, get_bytes("value1"), 0);
+        let item2 = create_test_item(&table, 2, get_bytes("value2"), 1);
+        table.insert(&item1).unwrap();
+        table.insert(&item2).unwrap();
+
+        let removed = table.remove(0).expect("item not removed");
+        assert_eq!(get_bytes("value1"), removed.data);
+        assert!(table.get(0).is_none());
+        assert!(table.remove(0).is_none());
+        assert!(table.contains(2, 1));
+
+        let removed = table.remove_by_id(2).expect("item not removed");
+        assert_eq!(1, removed.seq_no);
+        assert!(table.remove_by_id(2).is_none());
+        assert_eq!(0, table.index.iter().filter(|loc| loc.filled).count());
+        assert!(table.data.iter().all(|&b| b == 0));
+
+        // Reinsert after delete
+        let result = table.insert(&item1);
+        assert!(result.unwrap().is_none());
+        assert_eq!(get_bytes("value1"), table.get(0).unwrap().data);
+    }
+
+    #[test]
+    fn test_remove_with_evictions() {
+        let num_buckets = 20;
+        let depth = 2;
+        let mut table = create_test_table(num_buckets, depth);
+
+        // Fill the table until an item is left out, so that items have been moved around by
+        // evictions.
+        let mut stored = Vec::new();
+        let mut seq_no = 0u64;
+        let evicted = loop {
+            let item = create_test_item(&table, seq_no, get_bytes(&seq_no.to_string()), seq_no);
+            seq_no += 1;
+            let result = table.insert(&item).unwrap();
+            stored.push(item);
+            if let Some(evicted) = result {
+                break evicted;
+            }
+        };
+        stored.retain(|item| item.seq_no != evicted.seq_no);
+
+        // Every item that stayed in the table can still be found and removed.
+        for item in &stored[..stored.len() / 2] {
+            assert_eq!(item.seq_no, table.remove(item.seq_no).unwrap().seq_no);
+        }
+
+        // The freed slots take the evicted item back, and the rest are left in place.
+        assert!(table.insert(&evicted).unwrap().is_none());
+        assert!(table.contains(evicted.id, evicted.seq_no));
+        for item in &stored[stored.len() / 2..] {
+            assert_eq!(item.id, table.remove_by_id(item.id).unwrap().id);
+        }
+        assert_eq!(1, table.index.iter().filter(|loc| loc.filled).count());
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut table = create_test_table(10, 2);
+        for seq_no in 0..10u64 {
+            let item = create_test_item(&table, seq_no, get_bytes(&seq_no.to_string()), seq_no);
+            assert!(table.insert(&item).unwrap().is_none());
+        }
+
+        let mut restored = Table::from_bytes(&table.to_bytes().unwrap()).unwrap();
+        for seq_no in 0..10u64 {
+            let item = restored.get(seq_no).expect("item lost in serialization");
+            assert_eq!(get_bytes(&seq_no.to_string()), item.data);
+        }
+        assert_eq!(table.data, restored.data);
+
+        // The RNG state is restored too, so both tables make the same eviction choices.
+        for seq_no in 10..20u64 {
+            let item = create_test_item(&table, seq_no, get_bytes(&seq_no.to_string()), seq_no);
+            let evicted = table.insert(&item).unwrap();
+            let restored_evicted = restored.insert(&item).unwrap();
+            assert_eq!(evicted.map(|e| e.seq_no), restored_evicted.map(|e| e.seq_no));
+        }
+        assert_eq!(table.data, restored.data);
+
+        assert!(Table::from_bytes(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_deserialization_rejects_tables_without_slots() {
+        for (num_buckets, bucket_depth) in [(0, 2), (10, 0), (usize::MAX, 2)] {
+            let mut table = create_test_table(10, 2);
+            table.num_buckets = num_buckets;
+            table.bucket_depth = bucket_depth;
+            table.index.clear();
+            table.data.clear();
+            assert!(matches!(
+                Table::from_bytes(&table.to_bytes().unwrap()),
+                Err(Error::SerializationError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_grow() {
+        let mut table = create_test_table(10, 2);
+        for seq_no in 0..15u64 {
+            let item = create_test_item(&table, seq_no, get_bytes(&seq_no.to_string()), seq_no);
+            assert!(table.insert(&item).unwrap().is_none());
+        }
+
+        table.grow().unwrap();
+        assert_eq!(40, table.capacity());
+        assert_eq!(15, table.len());
+        for seq_no in 0..15u64 {
+            let item = table.get(seq_no).expect("item lost when growing");
+            assert_eq!(get_bytes(&seq_no.to_string()), item.data);
+            assert_eq!(table.buckets(seq_no).unwrap(), (item.bucket1, item.bucket2));
+        }
+
+        // Items with buckets computed for the old size are rejected.
+        let stale = create_test_item(&create_test_table(10, 2), 15, get_bytes("15"), 15);
+        if table.buckets(15).unwrap() != (stale.bucket1, stale.bucket2) {
+            assert!(table.insert(&stale).is_err());
+        }
+    }
+
+    #[test]
+    fn test_grow_on_load_factor() {
+        let mut table = create_test_table(4, 2);
+        assert!(table.set_max_load_factor(Some(1.5)).is_err());
+        table.set_max_load_factor(Some(0.5)).unwrap();
+
+        for seq_no in 0..100u64 {
+            let (bucket1, bucket2) = table.buckets(seq_no).unwrap();
+            let item = Item::new(seq_no, get_bytes(&seq_no.to_string()), seq_no, bucket1, bucket2);
+            assert!(table.insert(&item).unwrap().is_none());
+            assert!(table.len() as f64 <= 0.5 * table.capacity() as f64);
+        }
+
+        assert_eq!(100, table.len());
+        assert_eq!(256, table.capacity());
+        for seq_no in 0..100u64 {
+            assert!(table.contains(seq_no, seq_no));
+        }
+    }
+
+    #[test]
+    fn test_stash() {
+        let mut table = create_test_table(20, 2);
+        let mut stored = Vec::new();
+        let mut seq_no = 0u64;
+        let left_out = loop {
+            let item = create_test_item(&table, seq_no, get_bytes(&seq_no.to_string()), seq_no);
+            seq_no += 1;
+            let result = table.insert(&item).unwrap();
+            stored.push(item);
+            if let Some(left_out) = result {
+                break left_out;
+            }
+        };
+        stored.retain(|item| item.seq_no != left_out.seq_no);
+
+        // Items are only left out once the stash is full, and stashed items can be looked up.
+        assert_eq!(STASH_SIZE, table.stash.len());
+        assert_eq!(stored.len(), table.len());
+        for item in &stored {
+            assert!(table.contains(item.id, item.seq_no));
+        }
+
+        // Removing a stashed item makes room in the stash.
+        let stashed = table.stash[0].clone();
+        assert_eq!(stashed.seq_no, table.remove(stashed.seq_no).unwrap().seq_no);
+        assert!(table.get(stashed.seq_no).is_none());
+        assert!(table.insert(&left_out).unwrap().is_none());
+        assert!(table.contains(left_out.id, left_out.seq_no));
+
+        // Growing moves the stash back into the table.
+        table.grow().unwrap();
+        assert_eq!(stored.len(), table.len());
+        assert!(table.stash.len() < STASH_SIZE);
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let mut table = create_test_table(100, 4);
+        let items: Vec<Item> = (0..300u64)
+            .map(|seq_no| create_test_item(&table, seq_no, get_bytes(&seq_no.to_string()), seq_no))
+            .collect();
+
+        // An invalid item rejects the whole batch.
+        let mut invalid = items.clone();
+        invalid[150].data = vec![0, 0];
+        assert!(table.insert_batch(&invalid).is_err());
+        assert!(table.is_empty());
+
+        let left_out = table.insert_batch(&items).unwrap();
+        assert_eq!(items.len(), table.len() + left_out.len());
+        for item in &items {
+            if !left_out.contains(item) {
+                assert_eq!(item.data, table.get(item.seq_no).unwrap().data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_batch_grows() {
+        let mut table = create_test_table(4, 2);
+        table.set_max_load_factor(Some(0.9)).unwrap();
+        // Buckets are computed by the table, so they can be left unset.
+        let items: Vec<Item> = (0..100u64)
+            .map(|seq_no| Item::new(seq_no, get_bytes(&seq_no.to_string()), seq_no, 0, 0))
+            .collect();
+
+        assert!(table.insert_batch(&items).unwrap().is_empty());
+        assert_eq!(100, table.len());
+        for item in &items {
+            assert!(table.contains(item.id, item.seq_no));
+        }
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut table = create_test_table(10, 4);
+        assert_eq!(40, table.capacity());
+        assert_eq!(vec![10, 0, 0, 0, 0], table.load_histogram());
+        assert_eq!(Counters::default(), table.counters());
+
+        let mut inserted = 0;
+        for seq_no in 0..40u64 {
+            let item = create_test_item(&table, seq_no, get_bytes(&seq_no.to_string()), seq_no);
+            if table.insert(&item).unwrap().is_none() {
+                inserted += 1;
+            }
+        }
+
+        assert_eq!(inserted, table.len());
+        assert_eq!(inserted, table.iter().count());
+        let mut seq_nos: Vec<u64> = table.iter().map(|item| item.seq_no).collect();
+        seq_nos.sort();
+        seq_nos.dedup();
+        assert_eq!(inserted, seq_nos.len());
+
+        let histogram = table.load_histogram();
+        assert_eq!(10, histogram.iter().sum::<usize>());
+        let filled: usize = histogram.iter().enumerate().map(|(load, n)| load * n).sum();
+        assert_eq!(table.len() - table.stash.len(), filled);
+        assert_eq!(filled as f64 / 40.0, table.load_factor());
+
+        // Filling every slot takes evictions.
+        let counters = table.counters();
+        assert!(counters.evictions > 0);
+        assert_eq!(table.stash.len() as u64, counters.stashed);
+        assert_eq!(40 - inserted as u64, counters.left_out);
+
+        table.grow().unwrap();
+        assert_eq!(1, table.counters().grows);
+        assert_eq!(inserted, table.iter().count());
+    }
+
+    #[test]
+    fn test_custom_hasher() {
+        let hasher = |key: &[u8], seq_no: u64| key.len() + seq_no as usize;
+        let mut table = Table::with_hasher(
+            10,
+            2,
+            TEST_ITEM_SIZE,
+            None,
+            RANDOM_SEED,
+            b"a".to_vec(),
+            b"bb".to_vec(),
+            hasher,
+        )
+        .unwrap();
+
+        assert_eq!((4, 5), table.buckets(3).unwrap());
+        assert_eq!((0, 1), table.buckets(9).unwrap());
+        let item = Item::new(1, get_bytes("value1"), 3, 4, 5);
+        assert!(table.insert(&item).unwrap().is_none());
+        assert_eq!(get_bytes("value1"), table.get(3).unwrap().data);
+
+        // Buckets from the default hasher don't match.
+        let item = create_test_item(&create_test_table(10, 2), 2, get_bytes("value2"), 7);
+        if (item.bucket1, item.bucket2) != (8, 9) {
+            assert!(table.insert(&item).is_err());
+        }
+    }
 }
@@ -0,0 +1,377 @@
+//! ACME certificate provisioning
+//!
+//! Obtains the certificate an internet-facing server presents from an ACME CA such as Let's
+//! Encrypt, instead of the self-signed certificate in `certs/`. Domain ownership is proven with
+//! HTTP-01 challenges answered on a plain HTTP listener, and the certificate is renewed in the
+//! background once it is [`RENEW_AFTER`] old. Only built with the `acme` feature.
+//!
+//! The certificate, its key and the ACME account credentials are kept in a cache directory, so
+//! restarting a server reuses them instead of ordering a new certificate.
+
+use std::{
+    net::SocketAddr,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use dashmap::DashMap;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, LetsEncrypt,
+    NewAccount, NewOrder, OrderStatus,
+};
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+
+use crate::error::MycoError;
+
+/// Environment variable holding a comma-separated list of domains to obtain a certificate for.
+/// ACME provisioning is enabled when it is set.
+pub const DOMAINS_ENV: &str = "MYCO_ACME_DOMAINS";
+
+/// Environment variable holding a comma-separated list of contact URIs for the ACME account,
+/// e.g. `mailto:ops@example.com`.
+pub const CONTACT_ENV: &str = "MYCO_ACME_CONTACT";
+
+/// Environment variable overriding the ACME directory URL. Defaults to Let's Encrypt production.
+pub const DIRECTORY_ENV: &str = "MYCO_ACME_DIRECTORY";
+
+/// Environment variable overriding the directory the certificate and account are cached in.
+pub const CACHE_DIR_ENV: &str = "MYCO_ACME_CACHE_DIR";
+
+/// Environment variable overriding the address HTTP-01 challenges are answered on.
+pub const CHALLENGE_ADDR_ENV: &str = "MYCO_ACME_CHALLENGE_ADDR";
+
+/// Age after which a certificate is renewed. Let's Encrypt certificates are valid for 90 days.
+pub const RENEW_AFTER: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
+/// How often the renewal task checks the age of the certificate.
+pub const RENEW_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Number of times the order status is polled before giving up.
+const ORDER_POLL_ATTEMPTS: u32 = 10;
+
+/// Pending HTTP-01 challenges, mapping tokens to key authorizations.
+pub type ChallengeTokens = Arc<DashMap<String, String>>;
+
+/// Where and how to obtain a certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AcmeConfig {
+    /// Domains the certificate is valid for.
+    pub domains: Vec<String>,
+    /// Contact URIs registered with the ACME account.
+    pub contact: Vec<String>,
+    /// ACME directory URL.
+    pub directory_url: String,
+    /// Directory the certificate, key and account credentials are cached in.
+    pub cache_dir: PathBuf,
+    /// Address the HTTP-01 challenge listener binds to. The CA always connects on port 80.
+    pub challenge_addr: SocketAddr,
+}
+
+impl AcmeConfig {
+    /// A configuration for `domains` using Let's Encrypt production and the default cache
+    /// directory and challenge address.
+    pub fn new(domains: Vec<String>) -> Self {
+        Self {
+            domains,
+            contact: Vec::new(),
+            directory_url: LetsEncrypt::Production.url().to_string(),
+            cache_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("certs")
+                .join("acme"),
+            challenge_addr: SocketAddr::from(([0, 0, 0, 0], 80)),
+        }
+    }
+
+    /// Read the configuration from [`DOMAINS_ENV`], [`CONTACT_ENV`], [`DIRECTORY_ENV`],
+    /// [`CACHE_DIR_ENV`] and [`CHALLENGE_ADDR_ENV`]. Returns `None` if no domains are set.
+    pub fn from_env() -> Result<Option<Self>, MycoError> {
+        let domains = split_list(std::env::var(DOMAINS_ENV).unwrap_or_default());
+        if domains.is_empty() {
+            return Ok(None);
+        }
+
+        let mut config = Self::new(domains);
+        config.contact = split_list(std::env::var(CONTACT_ENV).unwrap_or_default());
+        if let Ok(directory_url) = std::env::var(DIRECTORY_ENV) {
+            config.directory_url = directory_url;
+        }
+        if let Ok(cache_dir) = std::env::var(CACHE_DIR_ENV) {
+            config.cache_dir = PathBuf::from(cache_dir);
+        }
+        if let Ok(addr) = std::env::var(CHALLENGE_ADDR_ENV) {
+            config.challenge_addr = addr.parse().map_err(|e| {
+                MycoError::ConfigError(format!("invalid {} {}: {}", CHALLENGE_ADDR_ENV, addr, e))
+            })?;
+        }
+        Ok(Some(config))
+    }
+
+    /// Path of the cached PEM certificate chain.
+    pub fn cert_path(&self) -> PathBuf {
+        self.cache_dir.join("cert.pem")
+    }
+
+    /// Path of the cached PKCS#8 private key.
+    pub fn key_path(&self) -> PathBuf {
+        self.cache_dir.join("key.pem")
+    }
+
+    fn credentials_path(&self) -> PathBuf {
+        self.cache_dir.join("account.json")
+    }
+
+    /// Whether the cached certificate is missing or older than [`RENEW_AFTER`].
+    pub fn needs_renewal(&self) -> bool {
+        if !self.key_path().exists() {
+            return true;
+        }
+        std::fs::metadata(self.cert_path())
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .is_ok_and(|age| age >= RENEW_AFTER)
+            })
+            .unwrap_or(true)
+    }
+}
+
+fn split_list(list: String) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn acme_error(e: impl std::fmt::Display) -> MycoError {
+    MycoError::CertificateError(format!("ACME: {}", e))
+}
+
+/// Router answering HTTP-01 challenges from `tokens` under `/.well-known/acme-challenge/`.
+pub fn challenge_router(tokens: ChallengeTokens) -> Router {
+    Router::new()
+        .route("/.well-known/acme-challenge/:token", get(challenge_handler))
+        .with_state(tokens)
+}
+
+async fn challenge_handler(
+    State(tokens): State<ChallengeTokens>,
+    Path(token): Path<String>,
+) -> Result<String, StatusCode> {
+    tokens
+        .get(&token)
+        .map(|key_authorization| key_authorization.clone())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Restore the ACME account cached in `config`, or create and cache a new one.
+async fn account(config: &AcmeConfig) -> Result<Account, MycoError> {
+    let credentials_path = config.credentials_path();
+    if credentials_path.exists() {
+        let credentials: AccountCredentials =
+            serde_json::from_slice(&std::fs::read(&credentials_path)?).map_err(acme_error)?;
+        return Account::from_credentials(credentials)
+            .await
+            .map_err(acme_error);
+    }
+
+    let contact: Vec<&str> = config.contact.iter().map(String::as_str).collect();
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        &config.directory_url,
+        None,
+    )
+    .await
+    .map_err(acme_error)?;
+    write_private(
+        &credentials_path,
+        &serde_json::to_string(&credentials).map_err(acme_error)?,
+    )?;
+    Ok(account)
+}
+
+/// Order a certificate for `config.domains` and write it to the cache directory. `tokens` must be
+/// served by [`challenge_router`] on port 80 of every domain while this runs.
+pub async fn provision(config: &AcmeConfig, tokens: &ChallengeTokens) -> Result<(), MycoError> {
+    std::fs::create_dir_all(&config.cache_dir)?;
+    let account = account(config).await?;
+
+    let identifiers: Vec<Identifier> = config
+        .domains
+        .iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect();
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await
+        .map_err(acme_error)?;
+
+    let mut challenges = Vec::new();
+    for authorization in order.authorizations().await.map_err(acme_error)? {
+        match authorization.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => {
+                return Err(acme_error(format!(
+                    "authorization for {:?} is {:?}",
+                    authorization.identifier, status
+                )))
+            }
+        }
+
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.r#type == ChallengeType::Http01)
+            .ok_or_else(|| {
+                acme_error(format!(
+                    "no HTTP-01 challenge offered for {:?}",
+                    authorization.identifier
+                ))
+            })?;
+        tokens.insert(
+            challenge.token.clone(),
+            order.key_authorization(challenge).as_str().to_string(),
+        );
+        challenges.push((challenge.token.clone(), challenge.url.clone()));
+    }
+
+    let result = finish_order(&mut order, config, &challenges).await;
+    for (token, _) in &challenges {
+        tokens.remove(token);
+    }
+    result
+}
+
+async fn finish_order(
+    order: &mut instant_acme::Order,
+    config: &AcmeConfig,
+    challenges: &[(String, String)],
+) -> Result<(), MycoError> {
+    for (_, url) in challenges {
+        order.set_challenge_ready(url).await.map_err(acme_error)?;
+    }
+
+    // Back off exponentially until the CA has validated the challenges.
+    let mut delay = Duration::from_millis(250);
+    let mut attempts = 0;
+    loop {
+        tokio::time::sleep(delay).await;
+        let state = order.refresh().await.map_err(acme_error)?;
+        match state.status {
+            OrderStatus::Ready => break,
+            OrderStatus::Invalid => {
+                return Err(acme_error(format!("order is invalid: {:?}", state.error)))
+            }
+            _ => {}
+        }
+
+        attempts += 1;
+        if attempts >= ORDER_POLL_ATTEMPTS {
+            return Err(acme_error(format!("order is still {:?}", state.status)));
+        }
+        delay *= 2;
+    }
+
+    let mut params = CertificateParams::new(config.domains.clone()).map_err(acme_error)?;
+    params.distinguished_name = DistinguishedName::new();
+    let key = KeyPair::generate().map_err(acme_error)?;
+    let csr = params.serialize_request(&key).map_err(acme_error)?;
+    order.finalize(csr.der()).await.map_err(acme_error)?;
+
+    let cert_chain = loop {
+        match order.certificate().await.map_err(acme_error)? {
+            Some(cert_chain) => break cert_chain,
+            None => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    };
+
+    write_identity(config, &cert_chain, &key.serialize_pem())
+}
+
+/// Write the key before the certificate, since the certificate's age decides renewal.
+fn write_identity(config: &AcmeConfig, cert_chain: &str, key: &str) -> Result<(), MycoError> {
+    write_private(&config.key_path(), key)?;
+    std::fs::write(config.cert_path(), cert_chain)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_private(path: &FsPath, contents: &str) -> Result<(), MycoError> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &FsPath, contents: &str) -> Result<(), MycoError> {
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Start answering challenges on `config.challenge_addr`, obtain a certificate if the cached one
+/// needs renewal, and keep renewing it in the background.
+///
+/// Returns the TLS configuration for the HTTPS listener, which is reloaded after every renewal.
+pub async fn start(config: AcmeConfig) -> Result<RustlsConfig, MycoError> {
+    let tokens = ChallengeTokens::default();
+    let listener = tokio::net::TcpListener::bind(config.challenge_addr).await?;
+    let router = challenge_router(tokens.clone());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    if config.needs_renewal() {
+        tracing::info!("obtaining a certificate for {:?}", config.domains);
+        provision(&config, &tokens).await?;
+    }
+
+    let tls_config = RustlsConfig::from_pem_file(config.cert_path(), config.key_path()).await?;
+    tokio::spawn(run_renewal(config, tokens, tls_config.clone()));
+    Ok(tls_config)
+}
+
+/// Renew the certificate once it needs renewal and reload `tls_config` with it. Failures are
+/// logged and retried after [`RENEW_CHECK_INTERVAL`].
+pub async fn run_renewal(config: AcmeConfig, tokens: ChallengeTokens, tls_config: RustlsConfig) {
+    let mut interval = tokio::time::interval(RENEW_CHECK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if !config.needs_renewal() {
+            continue;
+        }
+
+        tracing::info!("renewing the certificate for {:?}", config.domains);
+        let result = match provision(&config, &tokens).await {
+            Ok(()) => tls_config
+                .reload_from_pem_file(config.cert_path(), config.key_path())
+                .await
+                .map_err(MycoError::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!("failed to renew the certificate: {}", e);
+        }
+    }
+}
@@ -0,0 +1,553 @@
+//! Admin control API
+//!
+//! Operator endpoints for managing Server1's epochs outside of benchmarks: pausing and resuming
+//! the epoch scheduler, forcing the current epoch to be written out, inspecting queue depth and
+//! pathset size, and draining the server before maintenance. `stats` reports aggregate write
+//! counts and bytes on the wire per epoch so operators can check the size of the anonymity set
+//! and the bandwidth model, `memory` reports the memory held by the trees and the write queue
+//! (see [`crate::memory`]), and `latency` reports the operation latencies of the current and last
+//! epochs as JSON (see [`crate::logging`]). `key_share` keeps the share of a peer replica's epoch
+//! key (see [`crate::key_sharing`]). A standby S1 receives the primary's state at `replicate` and
+//! takes its open epoch over at `failover` (see [`crate::standby`]).
+//!
+//! All routes, and the `/finalize_benchmark` routes of both servers, are guarded by
+//! [`require_operator`]. Requests either carry the admin token as a bearer token or are signed
+//! with it: an HMAC-SHA256 over the method, path, timestamp, a random nonce and the body hash,
+//! sent in [`SIGNATURE_HEADER`] alongside [`TIMESTAMP_HEADER`] and [`NONCE_HEADER`]. Signed
+//! requests never put the token on the wire and are only accepted within [`SIGNATURE_MAX_AGE`] of
+//! their timestamp. The nonces accepted within that window are remembered, so a captured request
+//! can't be replayed either.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{OriginalUri, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use ring::hmac;
+use tokio::sync::RwLock;
+
+use crate::{
+    error::MycoError,
+    key_sharing::{KeyShare, ShareHolder},
+    logging::LatencyBreakdown,
+    rpc_types::{AdminStatsResponse, AdminStatusResponse, FailoverRequest, MemoryStatsResponse},
+    server1::Server1,
+    standby::ReplicationEvent,
+};
+
+/// How often a held-back batch write rechecks the anonymity gate.
+const ANONYMITY_GATE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Environment variable holding the admin bearer token. Admin routes are disabled when it is unset.
+pub const ADMIN_TOKEN_ENV: &str = "MYCO_ADMIN_TOKEN";
+
+/// Header carrying the unix time, in seconds, at which an operator request was signed.
+pub const TIMESTAMP_HEADER: &str = "x-myco-timestamp";
+
+/// Header carrying the hex-encoded random nonce of a signed operator request.
+pub const NONCE_HEADER: &str = "x-myco-nonce";
+
+/// Bytes of a request nonce.
+pub const NONCE_SIZE: usize = 16;
+
+/// Header carrying the hex-encoded HMAC-SHA256 signature of an operator request.
+pub const SIGNATURE_HEADER: &str = "x-myco-signature";
+
+/// How far a signed request's timestamp may drift from the server clock, in either direction.
+pub const SIGNATURE_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Largest body buffered to check a request signature.
+const MAX_SIGNED_BODY_SIZE: usize = 64 * 1024;
+
+/// Environment variable holding the epoch interval in milliseconds. When set, Server1 advances
+/// epochs on its own instead of waiting for batch_init/batch_write requests.
+pub const EPOCH_INTERVAL_ENV: &str = "MYCO_EPOCH_INTERVAL_MS";
+
+/// Shared epoch lifecycle flags, consulted by the scheduler and the write path.
+#[derive(Debug, Default)]
+pub struct EpochControl {
+    paused: AtomicBool,
+    draining: AtomicBool,
+    epoch_open: AtomicBool,
+}
+
+impl EpochControl {
+    /// Create a new EpochControl with the scheduler running and no batch initialized.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the scheduler from advancing epochs. Writes are still accepted.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resume the scheduler and stop draining.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.draining.store(false, Ordering::SeqCst);
+    }
+
+    /// Stop accepting writes and pause the scheduler.
+    pub fn start_drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        self.pause();
+    }
+
+    /// Whether the scheduler is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Whether the server is draining.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Whether a batch has been initialized and not yet written out.
+    pub fn is_epoch_open(&self) -> bool {
+        self.epoch_open.load(Ordering::SeqCst)
+    }
+
+    /// Record that a batch has been initialized (`true`) or written out (`false`).
+    pub fn set_epoch_open(&self, open: bool) {
+        self.epoch_open.store(open, Ordering::SeqCst);
+    }
+
+    /// Whether new client writes should be accepted.
+    pub fn accepting_writes(&self) -> bool {
+        self.is_epoch_open() && !self.is_draining()
+    }
+}
+
+/// Credentials checked by [`require_operator`].
+#[derive(Clone)]
+pub struct OperatorAuth {
+    token: Arc<String>,
+    /// The nonces of the signed requests accepted within the last [`SIGNATURE_MAX_AGE`], with
+    /// their timestamps.
+    seen: Arc<Mutex<HashMap<Vec<u8>, u64>>>,
+}
+
+impl OperatorAuth {
+    /// Authenticate operators with the given admin token.
+    pub fn new(token: impl Into<String>) -> Self {
+        Self::with_token(Arc::new(token.into()))
+    }
+
+    fn with_token(token: Arc<String>) -> Self {
+        Self {
+            token,
+            seen: Arc::default(),
+        }
+    }
+
+    /// Read the admin token from [`ADMIN_TOKEN_ENV`]. Returns `None` when it is unset, in which
+    /// case operator routes should not be mounted.
+    pub fn from_env() -> Option<Self> {
+        std::env::var(ADMIN_TOKEN_ENV).ok().map(Self::new)
+    }
+
+    /// Sign a request for `path` (including any mount prefix, excluding the query string) made at
+    /// `timestamp` seconds since the unix epoch, with the random `nonce`.
+    pub fn sign(&self, method: &Method, path: &str, timestamp: u64, nonce: &[u8], body: &[u8]) -> String {
+        hex::encode(hmac::sign(
+            &self.key(),
+            &signed_message(method, path, timestamp, nonce, body),
+        ))
+    }
+
+    fn key(&self) -> hmac::Key {
+        hmac::Key::new(hmac::HMAC_SHA256, self.token.as_bytes())
+    }
+
+    /// Headers authenticating a request signed now.
+    pub fn signed_headers(&self, method: &Method, path: &str, body: &[u8]) -> HeaderMap {
+        let timestamp = unix_time();
+        let nonce: [u8; NONCE_SIZE] = ChaCha20Rng::from_entropy().gen();
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(
+            NONCE_HEADER,
+            HeaderValue::from_str(&hex::encode(nonce)).expect("hex is a valid header value"),
+        );
+        headers.insert(
+            SIGNATURE_HEADER,
+            HeaderValue::from_str(&self.sign(method, path, timestamp, &nonce, body))
+                .expect("hex is a valid header value"),
+        );
+        headers
+    }
+
+    fn verify_bearer(&self, headers: &HeaderMap) -> Option<bool> {
+        let provided = headers
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+        Some(
+            ring::constant_time::verify_slices_are_equal(provided.as_bytes(), self.token.as_bytes())
+                .is_ok(),
+        )
+    }
+
+    fn verify_signature(&self, method: &Method, path: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+        let header = |name| headers.get(name).and_then(|value: &HeaderValue| value.to_str().ok());
+        let (Some(timestamp), Some(nonce), Some(signature)) = (
+            header(TIMESTAMP_HEADER).and_then(|t| t.parse::<u64>().ok()),
+            header(NONCE_HEADER)
+                .and_then(|n| hex::decode(n).ok())
+                .filter(|nonce| nonce.len() == NONCE_SIZE),
+            header(SIGNATURE_HEADER).and_then(|s| hex::decode(s).ok()),
+        ) else {
+            return false;
+        };
+        let now = unix_time();
+        if now.abs_diff(timestamp) > SIGNATURE_MAX_AGE.as_secs() {
+            return false;
+        }
+        let message = signed_message(method, path, timestamp, &nonce, body);
+        if hmac::verify(&self.key(), &message, &signature).is_err() {
+            return false;
+        }
+
+        // Nonces are kept until their timestamp leaves the window, after which the timestamp
+        // check turns a replay away. Only verified requests get here, so the set stays small.
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, &mut seen_at| now.abs_diff(seen_at) <= SIGNATURE_MAX_AGE.as_secs());
+        seen.insert(nonce, timestamp).is_none()
+    }
+}
+
+/// The bytes covered by a request signature.
+fn signed_message(method: &Method, path: &str, timestamp: u64, nonce: &[u8], body: &[u8]) -> Vec<u8> {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        timestamp,
+        hex::encode(nonce),
+        hex::encode(ring::digest::digest(&ring::digest::SHA256, body))
+    )
+    .into_bytes()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Middleware rejecting requests that carry neither the admin bearer token nor a valid signature.
+pub async fn require_operator(
+    State(auth): State<OperatorAuth>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    match auth.verify_bearer(request.headers()) {
+        Some(true) => return Ok(next.run(request).await),
+        Some(false) => return Err(StatusCode::UNAUTHORIZED),
+        None => {}
+    }
+
+    // Nested routers see a stripped URI, but the signature covers the full path.
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let (parts, body) = request.into_parts();
+    let body = axum::body::to_bytes(body, MAX_SIGNED_BODY_SIZE)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+    if !auth.verify_signature(&parts.method, &path, &parts.headers, &body) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// State shared by the admin routes.
+#[derive(Clone)]
+pub struct AdminState {
+    /// The Server1 instance being managed.
+    pub server1: Arc<RwLock<Server1>>,
+    /// The epoch lifecycle flags shared with the scheduler and write path.
+    pub control: Arc<EpochControl>,
+    /// The bearer token required on every admin request.
+    pub token: Arc<String>,
+}
+
+/// Write out the current epoch and, unless the server is draining, initialize the next one.
+///
+/// The next batch is sized like the previous one. When no batch is open only the initialization
+/// step runs.
+pub async fn advance_epoch(
+    server1: &RwLock<Server1>,
+    control: &EpochControl,
+) -> Result<(), MycoError> {
+    let mut server1 = server1.write().await;
+    if control.is_epoch_open() {
+        control.set_epoch_open(false);
+        server1.async_batch_write().await?;
+    }
+    if !control.is_draining() {
+        let num_clients = server1.num_clients;
+        server1.async_batch_init(num_clients).await?;
+        control.set_epoch_open(true);
+    }
+    Ok(())
+}
+
+/// Initialize a batch ahead of a write if Server1 initializes batches automatically and none is
+/// open, unless the server is draining.
+pub async fn auto_batch_init(server1: &mut Server1, control: &EpochControl) -> Result<(), MycoError> {
+    if control.is_draining() {
+        return Ok(());
+    }
+    if let Some(num_clients) = server1.pending_auto_batch_init() {
+        server1.async_batch_init(num_clients).await?;
+        control.set_epoch_open(true);
+    }
+    Ok(())
+}
+
+/// Wait until Server1's anonymity gate lets the current epoch be written out.
+pub async fn wait_for_anonymity_gate(server1: &RwLock<Server1>) {
+    while let Some(remaining) = server1.read().await.anonymity_gate_remaining() {
+        tokio::time::sleep(remaining.min(ANONYMITY_GATE_POLL_INTERVAL)).await;
+    }
+}
+
+/// Run epochs on a fixed interval until the task is dropped.
+///
+/// The first batch is initialized for `num_writes` clients. Ticks are skipped while the scheduler
+/// is paused or while the open epoch is still waiting for writers under the anonymity gate.
+pub async fn run_epoch_scheduler(
+    server1: Arc<RwLock<Server1>>,
+    control: Arc<EpochControl>,
+    interval: Duration,
+    num_writes: usize,
+) {
+    if !control.is_epoch_open() {
+        match server1.write().await.async_batch_init(num_writes).await {
+            Ok(()) => control.set_epoch_open(true),
+            Err(e) => tracing::error!("Epoch scheduler failed to initialize the first batch: {}", e),
+        }
+    }
+
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if control.is_paused() {
+            continue;
+        }
+        if control.is_epoch_open() && server1.read().await.anonymity_gate_remaining().is_some() {
+            continue;
+        }
+        if let Err(e) = advance_epoch(&server1, &control).await {
+            tracing::error!("Epoch scheduler failed to advance epoch: {}", e);
+        }
+    }
+}
+
+/// The routes of [`router`], mounted under `/admin`, mirrored by [`crate::json::debug_routes`].
+/// They still require operator credentials; bearer tokens carry over, signatures don't.
+#[cfg(feature = "debug-json")]
+pub fn json_routes() -> Vec<crate::json::JsonRoute> {
+    use crate::json::JsonRoute;
+    vec![
+        JsonRoute::bodyless::<AdminStatusResponse>(Method::GET, "/admin/status"),
+        JsonRoute::bodyless::<AdminStatsResponse>(Method::GET, "/admin/stats"),
+        JsonRoute::bodyless::<MemoryStatsResponse>(Method::GET, "/admin/memory"),
+        JsonRoute::bodyless::<AdminStatusResponse>(Method::POST, "/admin/pause"),
+        JsonRoute::bodyless::<AdminStatusResponse>(Method::POST, "/admin/resume"),
+        JsonRoute::bodyless::<AdminStatusResponse>(Method::POST, "/admin/batch_write"),
+        JsonRoute::bodyless::<AdminStatusResponse>(Method::POST, "/admin/drain"),
+    ]
+}
+
+/// Build the admin router. Mount it under `/admin`.
+pub fn router<S>(state: AdminState) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/status", get(handle_status))
+        .route("/stats", get(handle_stats))
+        .route("/memory", get(handle_memory))
+        .route("/latency", get(handle_latency))
+        .route("/pause", post(handle_pause))
+        .route("/resume", post(handle_resume))
+        .route("/batch_write", post(handle_batch_write))
+        .route("/drain", post(handle_drain))
+        .route("/key_share", get(handle_key_share).post(handle_hold_key_share))
+        .route("/replicate", post(handle_replicate))
+        .route("/failover", post(handle_failover))
+        .route_layer(middleware::from_fn_with_state(
+            OperatorAuth::with_token(state.token.clone()),
+            require_operator,
+        ))
+        .with_state(state)
+}
+
+async fn status(state: &AdminState) -> AdminStatusResponse {
+    let server1 = state.server1.read().await;
+    AdminStatusResponse {
+        epoch: server1.epoch,
+        paused: state.control.is_paused(),
+        draining: state.control.is_draining(),
+        epoch_open: state.control.is_epoch_open(),
+        queue_depth: server1.queue_depth(),
+        pathset_size: server1.pathset_size(),
+    }
+}
+
+async fn status_response(state: &AdminState) -> Result<Bytes, StatusCode> {
+    bincode::serialize(&status(state).await)
+        .map(Bytes::from)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn handle_status(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    status_response(&state).await
+}
+
+async fn handle_stats(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    let server1 = state.server1.read().await;
+    bincode::serialize(&AdminStatsResponse {
+        current: server1.write_stats(),
+        previous: server1.last_write_stats(),
+        bandwidth: server1.bandwidth_stats(),
+        previous_bandwidth: server1.bandwidth_meter().last_stats(),
+    })
+    .map(Bytes::from)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn handle_latency(State(state): State<AdminState>) -> Json<LatencyBreakdown> {
+    Json(state.server1.read().await.latency_breakdown())
+}
+
+async fn handle_memory(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    bincode::serialize(&MemoryStatsResponse {
+        stats: state.server1.read().await.memory_stats(),
+    })
+    .map(Bytes::from)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn handle_pause(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    state.control.pause();
+    status_response(&state).await
+}
+
+async fn handle_resume(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    state.control.resume();
+    // A drained server has no open batch, so start one to accept writes again.
+    if !state.control.is_epoch_open() {
+        advance_epoch(&state.server1, &state.control)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    status_response(&state).await
+}
+
+async fn handle_batch_write(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    if !state.control.is_epoch_open() {
+        return Err(StatusCode::CONFLICT);
+    }
+    advance_epoch(&state.server1, &state.control)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    status_response(&state).await
+}
+
+/// The share of a peer replica's epoch key this server keeps, if any.
+async fn handle_key_share(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    let vault = state.server1.read().await.peer_key_share().clone();
+    let share = vault
+        .share()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    bincode::serialize(&share)
+        .map(Bytes::from)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Keep a share of a peer replica's epoch key, replacing the one kept so far.
+async fn handle_hold_key_share(
+    State(state): State<AdminState>,
+    body: Bytes,
+) -> Result<Bytes, StatusCode> {
+    let share: KeyShare = bincode::deserialize(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let vault = state.server1.read().await.peer_key_share().clone();
+    vault
+        .hold(share)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Bytes::new())
+}
+
+/// Apply a batch of the primary's replication events. Conflicts if this server has yet to receive
+/// a snapshot of the primary's state.
+async fn handle_replicate(
+    State(state): State<AdminState>,
+    body: Bytes,
+) -> Result<Bytes, StatusCode> {
+    let events: Vec<ReplicationEvent> =
+        bincode::deserialize(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let standby = state.server1.read().await.standby().clone();
+    let mut standby = standby.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    for event in events {
+        standby.apply(event).map_err(|_| StatusCode::CONFLICT)?;
+    }
+    Ok(Bytes::new())
+}
+
+/// Take over from the crashed primary this server stands by for, opening its epoch for writes.
+async fn handle_failover(
+    State(state): State<AdminState>,
+    body: Bytes,
+) -> Result<Bytes, StatusCode> {
+    let request: FailoverRequest =
+        bincode::deserialize(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if state.control.is_epoch_open() {
+        return Err(StatusCode::CONFLICT);
+    }
+    let mut server1 = state.server1.write().await;
+    server1
+        .take_over(request.num_writes)
+        .await
+        .map_err(|_| StatusCode::CONFLICT)?;
+    state.control.set_epoch_open(server1.is_batch_open());
+    drop(server1);
+    status_response(&state).await
+}
+
+/// Stop accepting writes, write out the in-flight epoch and leave the scheduler paused.
+async fn handle_drain(State(state): State<AdminState>) -> Result<Bytes, StatusCode> {
+    state.control.start_drain();
+    advance_epoch(&state.server1, &state.control)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    status_response(&state).await
+}
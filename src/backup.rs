@@ -0,0 +1,173 @@
+//! Server2 backups
+//!
+//! Server2's snapshots (see [`crate::serve::SNAPSHOT_PATH_ENV`]) hold the whole tree, which is
+//! huge at full depth. Backups are epoch-aligned instead: a full [`Backup`] is taken once, and
+//! every backup after it only holds the buckets written since the one before, along with the PRF
+//! keys. Server2 serves them at `/admin/backup` ([`Server2::backup`]) and the `myco-backup` tool
+//! keeps them in a directory, one file per backup.
+//!
+//! Any epoch a backup was taken at can be restored ([`restore`]), by applying the full backup and
+//! the incremental ones up to it in order, and written out as a snapshot for Server2 to start
+//! from. [`prune`] drops the backups before an epoch, folding them into a full backup at the
+//! first one kept.
+
+use std::{
+    fs,
+    path::{Path as FsPath, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dtypes::{Bucket, Key},
+    error::MycoError,
+    server2::Server2,
+    tree::{self, StateParams},
+};
+
+/// Extension of backup files.
+pub const BACKUP_EXTENSION: &str = "backup";
+
+/// The tree and PRF keys of Server2 as of an epoch, in full or as the buckets written since an
+/// earlier backup.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    /// Server2's epoch when the backup was taken. Every epoch before it is included.
+    pub epoch: u64,
+    /// The epoch of the backup this one builds on, or `None` for a full backup.
+    pub since: Option<u64>,
+    /// Tree indices of the buckets included.
+    pub indices: Vec<usize>,
+    /// The buckets at `indices`.
+    pub buckets: Vec<Bucket>,
+    /// The PRF keys held.
+    pub prf_keys: Vec<Key>,
+    /// Number of PRF keys published so far.
+    pub prf_key_cursor: u64,
+}
+
+/// A backup file in a backup directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupEntry {
+    /// The epoch the backup was taken at.
+    pub epoch: u64,
+    /// The epoch of the backup it builds on, or `None` for a full backup.
+    pub since: Option<u64>,
+    /// The file.
+    pub path: PathBuf,
+}
+
+/// The file name of a backup taken at `epoch` on top of `since`.
+fn file_name(epoch: u64, since: Option<u64>) -> String {
+    match since {
+        Some(since) => format!("{:020}-{:020}.{}", epoch, since, BACKUP_EXTENSION),
+        None => format!("{:020}-full.{}", epoch, BACKUP_EXTENSION),
+    }
+}
+
+/// The epoch and base of the backup in a file named `name`, if it is a backup file.
+fn parse_file_name(name: &str) -> Option<(u64, Option<u64>)> {
+    let (epoch, since) = name
+        .strip_suffix(BACKUP_EXTENSION)?
+        .strip_suffix('.')?
+        .split_once('-')?;
+    let since = match since {
+        "full" => None,
+        since => Some(since.parse().ok()?),
+    };
+    Some((epoch.parse().ok()?, since))
+}
+
+/// The backups in `dir`, oldest first.
+pub fn list_backups(dir: &FsPath) -> Result<Vec<BackupEntry>, MycoError> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir).map_err(MycoError::IoError)? {
+        let path = entry.map_err(MycoError::IoError)?.path();
+        let parsed = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_file_name);
+        if let Some((epoch, since)) = parsed {
+            entries.push(BackupEntry { epoch, since, path });
+        }
+    }
+    entries.sort_by_key(|entry| entry.epoch);
+    Ok(entries)
+}
+
+/// The epoch of the newest backup in `dir`, which the next incremental backup builds on.
+pub fn latest_epoch(dir: &FsPath) -> Result<Option<u64>, MycoError> {
+    Ok(list_backups(dir)?.last().map(|entry| entry.epoch))
+}
+
+/// Write `backup` to `dir`, versioned like the snapshots. The file is written to a temporary
+/// file first and then renamed into place.
+pub fn write_backup(dir: &FsPath, backup: &Backup) -> Result<PathBuf, MycoError> {
+    let path = dir.join(file_name(backup.epoch, backup.since));
+    let bytes = tree::encode_state(StateParams::local(), backup)?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes).map_err(MycoError::IoError)?;
+    fs::rename(&tmp_path, &path).map_err(MycoError::IoError)?;
+    Ok(path)
+}
+
+/// Read the backup in `path`. Backups written by a build with different tree parameters are
+/// rejected with [`MycoError::IncompatibleState`].
+pub fn read_backup(path: &FsPath) -> Result<Backup, MycoError> {
+    let bytes = fs::read(path).map_err(MycoError::IoError)?;
+    let (header, state) = tree::decode_state_header(&bytes)?;
+    let header = header.ok_or_else(|| {
+        MycoError::IncompatibleState(format!("{} is not a backup", path.display()))
+    })?;
+    header.params.check_compatible(&StateParams::local())?;
+    bincode::deserialize(state).map_err(|e| MycoError::DeserializationError(Some(e)))
+}
+
+/// Server2 as of `epoch`, restored from the backups in `dir`. `epoch` must be one a backup was
+/// taken at.
+pub fn restore(dir: &FsPath, epoch: u64) -> Result<Server2, MycoError> {
+    let entries = list_backups(dir)?;
+    let find = |epoch: u64| {
+        entries
+            .iter()
+            .find(|entry| entry.epoch == epoch)
+            .ok_or_else(|| MycoError::ConfigError(format!("no backup of epoch {} is kept", epoch)))
+    };
+
+    // Walk back to the full backup the chain starts from.
+    let mut chain = vec![find(epoch)?];
+    while let Some(since) = chain[chain.len() - 1].since {
+        chain.push(find(since)?);
+    }
+
+    let mut server2 = Server2::new();
+    for entry in chain.into_iter().rev() {
+        server2.apply_backup(read_backup(&entry.path)?)?;
+    }
+    Ok(server2)
+}
+
+/// Drop the backups taken before `epoch`. The first backup kept is replaced by a full backup if it
+/// builds on one being dropped. Returns the number of files removed.
+pub fn prune(dir: &FsPath, epoch: u64) -> Result<usize, MycoError> {
+    let entries = list_backups(dir)?;
+    let Some(first_kept) = entries.iter().find(|entry| entry.epoch >= epoch) else {
+        return Err(MycoError::ConfigError(format!(
+            "no backup of epoch {} or later is kept",
+            epoch
+        )));
+    };
+
+    let mut removed = 0;
+    if first_kept.since.is_some() {
+        let full = restore(dir, first_kept.epoch)?.backup(None)?;
+        write_backup(dir, &full)?;
+        fs::remove_file(&first_kept.path).map_err(MycoError::IoError)?;
+        removed += 1;
+    }
+    for entry in entries.iter().filter(|entry| entry.epoch < first_kept.epoch) {
+        fs::remove_file(&entry.path).map_err(MycoError::IoError)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
@@ -0,0 +1,211 @@
+//! Per-epoch bandwidth accounting
+//!
+//! Each server keeps a [`BandwidthMeter`] counting the bytes it receives and sends, split by the
+//! part of the protocol they belong to ([`Traffic`]). [`meter`] wraps a router so every route is
+//! counted, and [`crate::framed::serve`] counts the frames of the framed listener. When the server
+//! closes an epoch, the meter keeps the epoch's totals and reports them to its [`MetricsSink`], so
+//! a deployment can be checked against the paper's bandwidth model.
+//!
+//! Every link is counted by the server serving it: Server1 counts the clients' writes, Server2
+//! counts Server1's batch reads and writes and the clients' reads.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+
+use crate::{
+    dtypes::{BandwidthStats, TrafficBytes},
+    logging::{self, MetricsSink},
+    network::{Command, ReadType, WriteType},
+    tenant,
+};
+
+/// The part of the protocol some traffic belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Traffic {
+    /// Server1 reading the pathset of the next write from Server2
+    BatchInit,
+    /// Server1 writing an epoch's buckets, notifications and storage report to Server2
+    BatchWrite,
+    /// Clients reading paths, PRF keys and notifications from Server2
+    ClientReads,
+    /// Clients writing messages to Server1
+    ClientWrites,
+    /// Everything else: registrations, epoch queries, administration
+    Other,
+}
+
+impl Traffic {
+    const ALL: [Traffic; 5] = [
+        Traffic::BatchInit,
+        Traffic::BatchWrite,
+        Traffic::ClientReads,
+        Traffic::ClientWrites,
+        Traffic::Other,
+    ];
+
+    /// Classify a request to the matched `route` of either server, of any tenant.
+    pub fn of_route(method: &Method, route: &str) -> Self {
+        match tenant::strip_tenant(route) {
+            "/read_paths" | "/chunk_read_paths" | "/store_path_indices" | "/store_path_leaves"
+            | "/prefetch_path_leaves" => Traffic::BatchInit,
+            "/write" | "/chunk_write" | "/chunk_write_deltas" | "/finalize_epoch" | "/storage" => {
+                Traffic::BatchWrite
+            }
+            "/notifications" if method == Method::POST => Traffic::BatchWrite,
+            "/read" | "/read_paths_client" | "/chunk_read_paths_client" | "/get_prf_keys"
+            | "/get_prf_keys_since" | "/notifications" | "/pending_epochs" => Traffic::ClientReads,
+            "/queue_write" => Traffic::ClientWrites,
+            _ => Traffic::Other,
+        }
+    }
+
+    /// Classify a command received over the framed transport.
+    pub fn of_command(command: &Command) -> Self {
+        match command {
+            Command::Server1Write(..) => Traffic::ClientWrites,
+            Command::Server2Read(
+                ReadType::ReadPaths(_) | ReadType::ReadLeafPaths(_) | ReadType::PrefetchLeafPaths(_),
+            )
+            | Command::Server2Write(WriteType::PathLeaves(_)) => Traffic::BatchInit,
+            Command::Server2Read(
+                ReadType::Read(_)
+                | ReadType::ReadPathsClient(_)
+                | ReadType::GetPrfKeys
+                | ReadType::GetPrfKeysSince(_)
+                | ReadType::GetNotifications
+                | ReadType::PendingEpochs(_),
+            ) => Traffic::ClientReads,
+            Command::Server2Write(_) => Traffic::BatchWrite,
+            _ => Traffic::Other,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Traffic::BatchInit => "batch_init",
+            Traffic::BatchWrite => "batch_write",
+            Traffic::ClientReads => "client_reads",
+            Traffic::ClientWrites => "client_writes",
+            Traffic::Other => "other",
+        }
+    }
+}
+
+/// Bytes received and sent by a server, per [`Traffic`] class and epoch.
+pub struct BandwidthMeter {
+    /// Prefix of the metrics reported at the end of an epoch, e.g. `server2`
+    prefix: &'static str,
+    sink: Arc<dyn MetricsSink>,
+    /// Bytes received and sent in the current epoch, indexed by [`Traffic`]
+    received: [AtomicUsize; 5],
+    sent: [AtomicUsize; 5],
+    /// Totals of the last completed epoch
+    last: Mutex<Option<BandwidthStats>>,
+}
+
+impl BandwidthMeter {
+    /// Create a meter reporting each epoch's totals to `sink`.
+    pub fn new(prefix: &'static str, sink: Arc<dyn MetricsSink>) -> Self {
+        Self {
+            prefix,
+            sink,
+            received: Default::default(),
+            sent: Default::default(),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Count `received` and `sent` bytes of `traffic` towards the current epoch.
+    pub fn record(&self, traffic: Traffic, received: usize, sent: usize) {
+        self.received[traffic as usize].fetch_add(received, Ordering::Relaxed);
+        self.sent[traffic as usize].fetch_add(sent, Ordering::Relaxed);
+    }
+
+    /// Totals of the current epoch so far, which is `epoch`.
+    pub fn stats(&self, epoch: u64) -> BandwidthStats {
+        self.totals(epoch, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    /// Totals of the last completed epoch, if any.
+    pub fn last_stats(&self) -> Option<BandwidthStats> {
+        *self.last.lock().unwrap()
+    }
+
+    /// Close `epoch`: keep its totals, report them to the sink as
+    /// `<prefix>_epoch_<class>_received` and `_sent`, and start counting from zero.
+    pub fn finish_epoch(&self, epoch: u64) -> BandwidthStats {
+        let stats = self.totals(epoch, |counter| counter.swap(0, Ordering::Relaxed));
+        for traffic in Traffic::ALL {
+            let bytes = stats.get(traffic);
+            let name = format!("{}_epoch_{}", self.prefix, traffic.name());
+            self.sink.record_bytes(&format!("{}_received", name), bytes.received);
+            self.sink.record_bytes(&format!("{}_sent", name), bytes.sent);
+        }
+        *self.last.lock().unwrap() = Some(stats);
+        stats
+    }
+
+    fn totals(&self, epoch: u64, read: impl Fn(&AtomicUsize) -> usize) -> BandwidthStats {
+        let bytes = |traffic: Traffic| TrafficBytes {
+            received: read(&self.received[traffic as usize]),
+            sent: read(&self.sent[traffic as usize]),
+        };
+        BandwidthStats {
+            epoch,
+            batch_init: bytes(Traffic::BatchInit),
+            batch_write: bytes(Traffic::BatchWrite),
+            client_reads: bytes(Traffic::ClientReads),
+            client_writes: bytes(Traffic::ClientWrites),
+            other: bytes(Traffic::Other),
+        }
+    }
+}
+
+impl BandwidthStats {
+    /// The bytes of one traffic class.
+    pub fn get(&self, traffic: Traffic) -> TrafficBytes {
+        match traffic {
+            Traffic::BatchInit => self.batch_init,
+            Traffic::BatchWrite => self.batch_write,
+            Traffic::ClientReads => self.client_reads,
+            Traffic::ClientWrites => self.client_writes,
+            Traffic::Other => self.other,
+        }
+    }
+}
+
+/// Count the request and response bodies of every route of `router` towards `meter`.
+///
+/// Bodies are counted as they are read and sent, so a request rejected before its body was read
+/// only counts its response.
+pub fn meter<S>(router: Router<S>, meter: Arc<BandwidthMeter>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(middleware::from_fn_with_state(meter, record_bandwidth))
+}
+
+async fn record_bandwidth(State(meter): State<Arc<BandwidthMeter>>, request: Request, next: Next) -> Response {
+    let traffic = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(Traffic::Other, |route| Traffic::of_route(request.method(), route.as_str()));
+
+    let request_meter = meter.clone();
+    let request = request.map(|body| {
+        logging::counted(body, move |bytes| request_meter.record(traffic, bytes, 0))
+    });
+    next.run(request)
+        .await
+        .map(|body| logging::counted(body, move |bytes| meter.record(traffic, 0, bytes)))
+}
@@ -11,11 +11,285 @@
 //! any gaps) to maintain privacy.
 
 use crate::{
-    constants::{BATCH_SIZE, BLOCK_SIZE, D}, utils::{get_path_indices, trim_zeros}, dtypes::{Bucket, Key, Path}, error::MycoError, logging::LatencyMetric, network::{Server1Access, Server2Access}, tree::SparseBinaryTree, crypto::{decrypt, encrypt, kdf, prf, EncryptionType}
+    constants::{BATCH_SIZE, BLOCK_SIZE, D, DELTA, MAX_PENDING_EPOCH_TAGS, MESSAGE_SIZE, PRECOMPUTE_EPOCHS, SYNC_BATCH_PATHS, WRITE_TOKEN_EPOCHS}, utils::{get_path_indices, pad, unpad, Padding}, dtypes::{Bucket, ContactBundle, EpochInfo, Key, Path}, envelope::{ContentType, Envelope}, error::MycoError, sequence::{self, Sequenced}, store::MessageStore, logging::LatencyMetric, network::{Server1Access, Server2Access}, notification::{notification_tag, NotificationIndex}, tree::SparseBinaryTree, distributed::{seal_location, LocationRequest, SealedWrite}, crypto::{client_pseudonym, kdf, location_prf, mailbox_access_tag, mailbox_address, mailbox_guard_key, prf, EncryptionType}, mailbox::MailboxGuard, registration::AccountCredentials, simulation::SimulationMode, write_tokens::TokenRequest
 };
+use dashmap::DashMap;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
-use std::collections::HashMap;
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
+use x25519_dalek::PublicKey;
+use zeroize::{Zeroize, Zeroizing};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Number of times a write rejected because its epoch had closed is requeued for the next one.
+const REQUEUE_ATTEMPTS: usize = 5;
+
+/// Shortest wait before requeueing a write, used when Server1 gives no opening time.
+const MIN_REQUEUE_DELAY: Duration = Duration::from_millis(50);
+
+/// Longest wait before requeueing a write, however far off Server1 says the next epoch is.
+const MAX_REQUEUE_DELAY: Duration = Duration::from_secs(5);
+
+/// How long to wait before requeueing a write that failed with `err`, or `None` if the failure
+/// isn't a closed or initializing epoch.
+fn requeue_delay(err: &MycoError) -> Option<Duration> {
+    let next_epoch_opens_at = match err {
+        MycoError::EpochClosed { next_epoch_opens_at } => next_epoch_opens_at,
+        // The batch is being set up and opens as soon as that's done.
+        MycoError::EpochInitializing => return Some(MIN_REQUEUE_DELAY),
+        _ => return None,
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let until_open = Duration::from_millis(next_epoch_opens_at.unwrap_or(now).saturating_sub(now));
+    Some(until_open.clamp(MIN_REQUEUE_DELAY, MAX_REQUEUE_DELAY))
+}
+
+/// The error a failed write to Server1 is reported as. Rejections the caller can act on are passed
+/// on as they are, anything else is put down to the transport.
+fn queue_write_error(err: MycoError) -> MycoError {
+    match err {
+        MycoError::EpochClosed { .. }
+        | MycoError::EpochInitializing
+        | MycoError::WriteQuotaExceeded
+        | MycoError::MailboxAccessDenied
+        | MycoError::ConfigError(_) => err,
+        _ => MycoError::transport("queue_write", err),
+    }
+}
+
+/// A write of an envelope to a contact, derived for one epoch.
+struct PreparedWrite {
+    ct: Vec<u8>,
+    f: Vec<u8>,
+    k_oblv_t: Key,
+    cs: Vec<u8>,
+    token: Vec<u8>,
+    access_tag: Option<Vec<u8>>,
+}
+
+/// Values derived from a contact key for a single epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpochKeys {
+    /// The PRF output the message location is derived from.
+    pub f: Vec<u8>,
+    /// The oblivious key the message is encrypted under on Server2.
+    pub k_oblv_t: Vec<u8>,
+}
+
+/// A message read from Server2, with where and when it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceivedMessage {
+    /// The message payload, unwrapped from its envelope.
+    pub payload: Vec<u8>,
+    /// The contact key the message was read under.
+    pub contact: Key,
+    /// The epoch the message was written in.
+    pub epoch: usize,
+    /// Number of buckets on the path that was read.
+    pub path_len: usize,
+    /// Depth of the bucket the message was found in, the root being at depth 0.
+    pub found_at: usize,
+    /// The sender's sequence number of the message.
+    pub sequence: u64,
+    /// The sequence number the client expected next from the sender, one past the highest it had
+    /// delivered from them before.
+    pub expected: u64,
+}
+
+impl ReceivedMessage {
+    /// Sequence numbers the sender used before this message that were never delivered, e.g.
+    /// because their epochs were missed. Empty for a message sent after those delivered so far,
+    /// or for a late one filling an earlier gap.
+    pub fn missing(&self) -> std::ops::Range<u64> {
+        sequence::missing(self.expected, self.sequence)
+    }
+}
+
+impl EpochKeys {
+    /// Derive the values for `epoch` from a contact's oblivious and PRF keys.
+    pub fn derive(k_oblv: &[u8], k_prf: &[u8], epoch: usize) -> Result<Self, MycoError> {
+        Ok(Self {
+            f: prf(k_prf, &epoch.to_be_bytes())?,
+            k_oblv_t: kdf(k_oblv, &epoch.to_string())?,
+        })
+    }
+}
+
+/// Derived keys per (contact, epoch), shared with the background precomputation.
+pub type EpochKeyCache = Arc<DashMap<(Key, usize), EpochKeys>>;
+
+/// Contact keys handed to the precomputation, wiped once it's done with them.
+type ContactKeys = (Key, Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>);
+
+/// Fill `cache` with the derived keys of `contacts` for `epochs`, and drop entries too old to be
+/// read any more. Nothing is inserted once `generation` has moved on from `started`, so a
+/// precomputation racing with [`Client::forget`] can't bring back a forgotten contact's keys.
+fn precompute(
+    cache: &EpochKeyCache,
+    generation: &RwLock<u64>,
+    started: u64,
+    contacts: Vec<ContactKeys>,
+    epochs: std::ops::Range<usize>,
+) {
+    let oldest = epochs.start.saturating_sub(DELTA);
+    cache.retain(|(_, epoch), _| *epoch >= oldest);
+
+    contacts
+        .into_par_iter()
+        .flat_map_iter(|contact| epochs.clone().map(move |epoch| (contact.clone(), epoch)))
+        .for_each(|((k, k_oblv, k_prf), epoch)| {
+            let entry = (k, epoch);
+            if cache.contains_key(&entry) {
+                return;
+            }
+            if let Ok(derived) = EpochKeys::derive(&k_oblv, &k_prf, epoch) {
+                let current = generation.read().unwrap();
+                if *current == started {
+                    cache.insert(entry, derived);
+                }
+            }
+        });
+}
+
+/// Server2's PRF keys as last synced by a client, so reads only fetch the keys published since.
+///
+/// The cache is kept across reads and only refreshed when Server2's epoch endpoint reports new
+/// keys. If Server2's epoch or key cursor ever goes backwards (e.g. after restoring an older
+/// snapshot), the cache is invalidated and refetched.
+#[derive(Clone, Debug, Default)]
+pub struct PrfKeyCache {
+    /// Number of the first cached key, as counted by Server2.
+    pub start: u64,
+    /// The cached keys, oldest first. At most [`DELTA`] are kept.
+    pub keys: VecDeque<Key>,
+    /// Server2's epoch and key cursor when the cache was last synced.
+    pub synced: EpochInfo,
+}
+
+impl PrfKeyCache {
+    /// The cursor to sync from: the number of the key after the newest cached one.
+    pub fn cursor(&self) -> u64 {
+        self.start + self.keys.len() as u64
+    }
+
+    /// Merge keys returned by [`Server2Access::get_prf_keys_since`]. Keys that don't continue the
+    /// cache replace it.
+    pub fn apply(&mut self, start: u64, keys: Vec<Key>) {
+        if start != self.cursor() {
+            self.start = start;
+            self.keys.clear();
+        }
+        self.keys.extend(keys);
+        while self.keys.len() > DELTA {
+            self.keys.pop_front();
+            self.start += 1;
+        }
+    }
+
+    /// Whether Server2 went back to an earlier epoch or key cursor since the last sync.
+    pub fn is_rolled_back(&self, info: &EpochInfo) -> bool {
+        info.epoch < self.synced.epoch || info.prf_key_cursor < self.synced.prf_key_cursor
+    }
+
+    /// Whether the cache holds every key Server2 reports as published.
+    pub fn is_current(&self, info: &EpochInfo) -> bool {
+        !self.is_rolled_back(info) && self.cursor() == info.prf_key_cursor
+    }
+
+    /// Drop all cached keys.
+    pub fn invalidate(&mut self) {
+        *self = Self::default();
+    }
+
+    /// The key published `epoch_past` epochs before the newest one.
+    pub fn get(&self, epoch_past: usize) -> Option<&Key> {
+        self.keys
+            .len()
+            .checked_sub(epoch_past + 1)
+            .and_then(|i| self.keys.get(i))
+    }
+}
+
+/// Paths a client read in one of its epochs, see [`Client::set_read_budget`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadCounts {
+    /// The client's epoch.
+    pub epoch: usize,
+    /// Paths read for real reads.
+    pub real: usize,
+    /// Paths read for fake reads.
+    pub fake: usize,
+    /// Paths of reads refused because they would have exceeded the budget.
+    pub refused: usize,
+}
+
+impl ReadCounts {
+    /// Paths read, real or fake.
+    pub fn total(&self) -> usize {
+        self.real + self.fake
+    }
+}
+
+/// A client's read budget and the reads counted against it.
+#[derive(Debug, Default)]
+struct ReadBudget {
+    /// Paths to read in every epoch, if enforced.
+    budget: Option<usize>,
+    /// Reads of the client's current epoch.
+    current: ReadCounts,
+    /// Reads of the last epoch the client read in before the current one.
+    last: Option<ReadCounts>,
+}
+
+impl ReadBudget {
+    /// Start counting the client's epoch `epoch`, if it isn't already.
+    fn roll(&mut self, epoch: usize) {
+        if self.current.epoch != epoch {
+            let current = std::mem::replace(&mut self.current, ReadCounts { epoch, ..Default::default() });
+            self.last = Some(current);
+        }
+    }
+
+    /// Count `paths` reads in `epoch`, or refuse them if they would exceed the budget.
+    fn charge(&mut self, epoch: usize, paths: usize, real: bool) -> Result<(), MycoError> {
+        self.roll(epoch);
+        if let Some(budget) = self.budget {
+            if self.current.total() + paths > budget {
+                self.current.refused += paths;
+                return Err(MycoError::ReadBudgetExceeded { epoch, budget });
+            }
+        }
+        if real {
+            self.current.real += paths;
+        } else {
+            self.current.fake += paths;
+        }
+        Ok(())
+    }
+
+    /// Paths still to read in `epoch` to spend the budget.
+    fn remaining(&mut self, epoch: usize) -> usize {
+        self.roll(epoch);
+        self.budget
+            .map_or(0, |budget| budget.saturating_sub(self.current.total()))
+    }
+}
+
+/// A message found on a path: its contact key, envelope, path length and the depth it was found at.
+type PathMatch = (Key, Envelope, usize, usize);
+
+/// A contact's keys: message key, oblivious key and PRF key.
+type ContactKeySet = (Vec<u8>, Vec<u8>, Vec<u8>);
 
 /// A Myco client (user).
 pub struct Client {
@@ -24,11 +298,34 @@ pub struct Client {
     /// The current epoch of the client.
     pub epoch: usize,
     /// The client's keys.
-    pub keys: HashMap<Key, (Vec<u8>, Vec<u8>, Vec<u8>)>,
+    pub keys: HashMap<Key, ContactKeySet>,
     /// Access to Server1.
     pub s1: Box<dyn Server1Access>,
     /// Access to Server2.
     pub s2: Box<dyn Server2Access>,
+    /// Server2's PRF keys, kept across reads and synced incrementally.
+    pub prf_keys: Mutex<PrfKeyCache>,
+    /// Derived keys for recent and upcoming epochs, filled in the background.
+    pub epoch_keys: EpochKeyCache,
+    /// Bumped whenever a contact is forgotten, invalidating precomputations still running.
+    key_generation: Arc<RwLock<u64>>,
+    /// The registered account write tokens are fetched for, if any.
+    registration: Option<AccountCredentials>,
+    /// Server1-issued write tokens by epoch, see [`Client::fetch_write_tokens`].
+    write_tokens: HashMap<usize, Vec<u8>>,
+    /// Messages already delivered, so re-reading an epoch doesn't deliver them twice, and the
+    /// sequence numbers sent to and expected from each contact.
+    store: Mutex<MessageStore>,
+    /// Access keys of the contacts whose mailboxes only accept writes from approved senders.
+    mailbox_access: HashMap<Key, Key>,
+    /// How message plaintexts are padded. Both ends of a contact have to use the same scheme.
+    padding: Padding,
+    /// Whether messages are really encrypted.
+    simulation: SimulationMode,
+    /// Paths to read per epoch and the reads made so far.
+    read_budget: Mutex<ReadBudget>,
+    /// The back S1's sealing key, if Server1 is a front S1 in distributed trust mode.
+    back_key: Option<PublicKey>,
 }
 
 impl Client {
@@ -40,58 +337,535 @@ impl Client {
             keys: HashMap::new(),
             s1,
             s2,
+            prf_keys: Mutex::new(PrfKeyCache::default()),
+            epoch_keys: EpochKeyCache::default(),
+            key_generation: Arc::new(RwLock::new(0)),
+            registration: None,
+            write_tokens: HashMap::new(),
+            store: Mutex::new(MessageStore::in_memory()),
+            mailbox_access: HashMap::new(),
+            padding: Padding::default(),
+            simulation: SimulationMode::Off,
+            read_budget: Mutex::new(ReadBudget::default()),
+            back_key: None,
+        }
+    }
+
+    /// Replace the store of delivered messages and sequence numbers, e.g. with one persisted
+    /// across restarts.
+    pub fn set_store(&mut self, store: MessageStore) {
+        self.store = Mutex::new(store);
+    }
+
+    /// Set how message plaintexts are padded, see [`Padding`]. Messages written with another
+    /// scheme can no longer be read.
+    pub fn set_padding(&mut self, padding: Padding) {
+        self.padding = padding;
+    }
+
+    /// Run in `simulation` mode, see [`SimulationMode`]. Both ends of a contact, and Server1, have
+    /// to run in the same mode.
+    pub fn set_simulation_mode(&mut self, simulation: SimulationMode) {
+        self.simulation = simulation;
+    }
+
+    /// Write through a front S1 in distributed trust mode, sealing every write to the back S1's
+    /// `back_key`, see [`crate::distributed`]. The key has to come from the back S1 itself.
+    pub fn set_distributed_trust(&mut self, back_key: PublicKey) {
+        self.back_key = Some(back_key);
+    }
+
+    /// Read exactly `budget` paths in every epoch, or stop enforcing a budget with `None`. Reads
+    /// that would take an epoch past the budget are refused with
+    /// [`MycoError::ReadBudgetExceeded`], and every write tops the epoch it ends up to the budget
+    /// with fake reads first (see [`Client::top_up_reads`]). A read of `n` contacts at once counts
+    /// as `n` paths.
+    pub fn set_read_budget(&mut self, budget: Option<usize>) {
+        self.read_budget.lock().unwrap().budget = budget;
+    }
+
+    /// The paths read so far in the client's current epoch.
+    pub fn read_counts(&self) -> ReadCounts {
+        let mut read_budget = self.read_budget.lock().unwrap();
+        read_budget.roll(self.epoch);
+        read_budget.current
+    }
+
+    /// The paths read in the last epoch the client read in before the current one.
+    pub fn last_read_counts(&self) -> Option<ReadCounts> {
+        let mut read_budget = self.read_budget.lock().unwrap();
+        read_budget.roll(self.epoch);
+        read_budget.last
+    }
+
+    /// Count `paths` real reads against the read budget of the current epoch.
+    fn charge_reads(&self, paths: usize) -> Result<(), MycoError> {
+        self.read_budget.lock().unwrap().charge(self.epoch, paths, true)
+    }
+
+    /// Make fake reads until the current epoch's read budget is spent. Returns the number of fake
+    /// reads made, none if no budget is set.
+    pub async fn top_up_reads(&self) -> Result<usize, MycoError> {
+        let remaining = self.read_budget.lock().unwrap().remaining(self.epoch);
+        let mut left = remaining;
+        while left > 0 {
+            // Batched like a sync's reads, so the padding looks like one more real read
+            let paths = left.min(SYNC_BATCH_PATHS);
+            self.fake_read_paths(paths).await?;
+            left -= paths;
+        }
+        Ok(remaining)
+    }
+
+    fn contacts(&self) -> Vec<ContactKeys> {
+        self.keys
+            .iter()
+            .map(|(k, (_, k_oblv, k_prf))| {
+                (k.clone(), Zeroizing::new(k_oblv.clone()), Zeroizing::new(k_prf.clone()))
+            })
+            .collect()
+    }
+
+    /// Derive the keys of every contact for the next `epochs` epochs, blocking until done.
+    pub fn precompute_epochs(&self, epochs: usize) {
+        let started = *self.key_generation.read().unwrap();
+        precompute(
+            &self.epoch_keys,
+            &self.key_generation,
+            started,
+            self.contacts(),
+            self.epoch..self.epoch + epochs,
+        );
+    }
+
+    /// Derive the keys of every contact for the next [`PRECOMPUTE_EPOCHS`] epochs in the
+    /// background.
+    pub fn spawn_precompute(&self) {
+        let cache = self.epoch_keys.clone();
+        let generation = self.key_generation.clone();
+        let started = *generation.read().unwrap();
+        let contacts = self.contacts();
+        let epochs = self.epoch..self.epoch + PRECOMPUTE_EPOCHS;
+        rayon::spawn(move || precompute(&cache, &generation, started, contacts, epochs));
+    }
+
+    /// The derived keys of contact `k` for `epoch`, from the precomputed cache if available.
+    pub fn epoch_keys(&self, k: &Key, epoch: usize) -> Result<EpochKeys, MycoError> {
+        if let Some(derived) = self.epoch_keys.get(&(k.clone(), epoch)) {
+            return Ok(derived.clone());
+        }
+        let (_, k_oblv, k_prf) = self.keys.get(k).ok_or(MycoError::UnknownContact)?;
+        EpochKeys::derive(k_oblv, k_prf, epoch)
+    }
+
+    /// The pseudonym the client with ID `cs` writes to contact `k` under in `epoch`.
+    fn pseudonym(&self, k: &Key, cs: &[u8], epoch: usize) -> Result<Vec<u8>, MycoError> {
+        let (_, _, k_prf) = self.keys.get(k).ok_or(MycoError::UnknownContact)?;
+        client_pseudonym(k_prf, cs, epoch)
+    }
+
+    /// Tag writes to contact `k` with the mailbox access key `k_access` shared by the owner of the
+    /// mailbox, or stop tagging them with `None` (see [`crate::mailbox`]).
+    pub fn set_mailbox_access(&mut self, k: &Key, k_access: Option<Key>) {
+        match k_access {
+            Some(k_access) => self.mailbox_access.insert(k.clone(), k_access),
+            None => self.mailbox_access.remove(k),
+        };
+    }
+
+    /// The access tag of a write of `ct` to contact `k`, if its mailbox is guarded.
+    fn access_tag(&self, k: &Key, f: &[u8], cs: &[u8], ct: &[u8]) -> Result<Option<Vec<u8>>, MycoError> {
+        let Some(k_access) = self.mailbox_access.get(k) else {
+            return Ok(None);
+        };
+        let guard_key = mailbox_guard_key(&k_access.0, &mailbox_address(f, cs))?;
+        mailbox_access_tag(&guard_key, ct).map(Some)
+    }
+
+    /// Fetch write tokens from Server1 for the account registered with
+    /// [`Client::set_registration`], or stop fetching them with `None`. Tokens already fetched
+    /// are kept.
+    pub fn set_registration(&mut self, credentials: Option<AccountCredentials>) {
+        self.registration = credentials;
+    }
+
+    /// Fetch write tokens for the current epoch and the ones after it, up to
+    /// [`WRITE_TOKEN_EPOCHS`], skipping epochs the client holds a token for and dropping those of
+    /// past epochs (see [`crate::write_tokens`]). Returns the number of tokens fetched.
+    ///
+    /// Writes fetch tokens themselves when they find none for their epoch. Without a registration
+    /// they carry no token, which Server1 only accepts while it enforces no write quota.
+    pub async fn fetch_write_tokens(&mut self) -> Result<usize, MycoError> {
+        let credentials = self
+            .registration
+            .clone()
+            .ok_or(MycoError::UnknownRegistration)?;
+        let reached = self
+            .s2
+            .get_epoch()
+            .await
+            .map_err(|e| MycoError::transport("get_epoch", e))?
+            .epoch as usize;
+        let start = self.epoch.max(reached);
+        self.write_tokens.retain(|epoch, _| *epoch >= start);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut requests: HashMap<u64, TokenRequest> = (start..start + WRITE_TOKEN_EPOCHS)
+            .filter(|epoch| !self.write_tokens.contains_key(epoch))
+            .map(|epoch| (epoch as u64, TokenRequest::new(epoch as u64, &mut rng)))
+            .collect();
+        if requests.is_empty() {
+            return Ok(0);
         }
+        let blinded = requests
+            .values()
+            .map(|request| (request.epoch, request.blinded()))
+            .collect();
+        let issued = self
+            .s1
+            .issue_write_tokens(credentials, blinded)
+            .await
+            .map_err(|e| match e {
+                MycoError::UnknownRegistration => e,
+                _ => MycoError::transport("issue_write_tokens", e),
+            })?;
+
+        let fetched = issued.len();
+        for (epoch, evaluated) in issued {
+            let request = requests.remove(&epoch).ok_or_else(|| {
+                MycoError::ProtocolError(format!("write token for unrequested epoch {}", epoch))
+            })?;
+            self.write_tokens
+                .insert(epoch as usize, request.finish(&evaluated)?);
+        }
+        Ok(fetched)
+    }
+
+    /// The write token for `epoch`, fetching tokens first if the client is registered and holds
+    /// none for it. Empty if there is none.
+    async fn write_token(&mut self, epoch: usize) -> Result<Vec<u8>, MycoError> {
+        if self.registration.is_some() && !self.write_tokens.contains_key(&epoch) {
+            self.fetch_write_tokens().await?;
+        }
+        Ok(self.write_tokens.get(&epoch).cloned().unwrap_or_default())
+    }
+
+    /// Guard our mailbox for writes of the client with ID `cs` to contact `k` in `epochs`, so that
+    /// Server1 only accepts writes to it tagged with the access key `k_access`.
+    pub async fn guard_mailbox(
+        &self,
+        k: &Key,
+        cs: String,
+        k_access: &Key,
+        epochs: std::ops::Range<usize>,
+    ) -> Result<(), MycoError> {
+        let guards = epochs
+            .map(|epoch| {
+                let EpochKeys { f, .. } = self.epoch_keys(k, epoch)?;
+                let address = mailbox_address(&f, &self.pseudonym(k, cs.as_bytes(), epoch)?);
+                let key = Key::new(mailbox_guard_key(&k_access.0, &address)?);
+                Ok(MailboxGuard { address, key })
+            })
+            .collect::<Result<Vec<_>, MycoError>>()?;
+        self.s1
+            .guard_mailboxes(guards)
+            .await
+            .map_err(|e| match e {
+                MycoError::MailboxAccessDenied => e,
+                _ => MycoError::transport("guard_mailboxes", e),
+            })
+    }
+
+    /// Bring the PRF key cache up to date. Asks Server2 for its epoch first and only fetches keys
+    /// when new ones were published, invalidating the cache if Server2 rolled back.
+    pub async fn sync_prf_keys(&self) -> Result<(), MycoError> {
+        let info = self
+            .s2
+            .get_epoch()
+            .await
+            .map_err(|e| MycoError::transport("get_epoch", e))?;
+        let cursor = {
+            let mut cache = self.prf_keys.lock().unwrap();
+            if cache.is_rolled_back(&info) {
+                cache.invalidate();
+            }
+            if cache.is_current(&info) {
+                cache.synced = info;
+                return Ok(());
+            }
+            cache.cursor()
+        };
+
+        let (start, keys) = self
+            .s2
+            .get_prf_keys_since(cursor)
+            .await
+            .map_err(|e| MycoError::transport("get_prf_keys_since", e))?;
+        let mut cache = self.prf_keys.lock().unwrap();
+        cache.apply(start, keys);
+        cache.synced = info;
+        Ok(())
+    }
+
+    /// The cached S1 key of the epoch `epoch_past` epochs before the newest one.
+    fn cached_prf_key(&self, epoch_past: usize) -> Result<Key, MycoError> {
+        self.prf_keys
+            .lock()
+            .unwrap()
+            .get(epoch_past)
+            .cloned()
+            .ok_or(MycoError::PrfKeyUnavailable { epoch_past })
+    }
+
+    /// The epoch `epoch_past` epochs before the newest one.
+    fn past_epoch(&self, epoch_past: usize) -> Result<usize, MycoError> {
+        self.epoch
+            .checked_sub(epoch_past + 1)
+            .ok_or(MycoError::EpochOutOfRange {
+                epoch_past,
+                epoch: self.epoch,
+            })
     }
 
     /// Setup the client with a key.
     pub fn setup(&mut self, k: &Key) -> Result<(), MycoError> {
         let end_to_end_latency = LatencyMetric::new("client_setup_end_to_end");
+        self.add_contact(k)?;
+        self.spawn_precompute();
+        end_to_end_latency.finish();
+        Ok(())
+    }
+
+    /// Derive and insert the keys of contact `k`, without precomputing its epoch keys.
+    pub(crate) fn add_contact(&mut self, k: &Key) -> Result<(), MycoError> {
         let k_msg = kdf(&k.0, "MSG")?;
         let k_oblv = kdf(&k.0, "ORAM")?;
         let k_prf = kdf(&k.0, "PRF")?;
 
         // Insert keys into the client
         self.keys.insert(k.clone(), (k_msg, k_oblv, k_prf));
-        end_to_end_latency.finish();
         Ok(())
     }
 
-    /// Asynchronously write a message to Server1.
-    pub async fn async_write(&mut self, msg: &[u8], k: &Key) -> Result<(), MycoError> {
-        let end_to_end_latency = LatencyMetric::new("client_write_end_to_end");
-        let local_latency = LatencyMetric::new("client_write_local");
-        let epoch = self.epoch;
-        let cs = self.id.clone().into_bytes();
+    /// Drop contact `k` and everything derived from it, wiping the key material.
+    pub fn forget(&mut self, k: &Key) {
+        if let Some((_, (mut k_msg, mut k_oblv, mut k_prf))) = self.keys.remove_entry(k) {
+            k_msg.zeroize();
+            k_oblv.zeroize();
+            k_prf.zeroize();
+        }
+        self.mailbox_access.remove(k);
+        let mut generation = self.key_generation.write().unwrap();
+        *generation += 1;
+        self.epoch_keys.retain(|(contact, _), derived| {
+            if contact != k {
+                return true;
+            }
+            derived.f.zeroize();
+            derived.k_oblv_t.zeroize();
+            false
+        });
+    }
 
-        let (k_msg, k_oblv, k_prf) = self.keys.get(k).unwrap();
-        let f = prf(k_prf, &epoch.to_be_bytes())?; // PRF for this epoch
-        let k_oblv_t = kdf(k_oblv, &epoch.to_string())?; // Oblivious key for this epoch
-        let ct = encrypt(k_msg, msg, EncryptionType::Encrypt)?; // Encrypt the message
+    /// Revoke contact `k`: optionally write it a final tombstone envelope in the current epoch,
+    /// then delete and wipe its keys and forget the messages delivered from it. The contact is no
+    /// longer precomputed or read, and any later use of it fails with
+    /// [`MycoError::UnknownContact`].
+    pub fn revoke(&mut self, k: &Key, send_tombstone: bool) -> Result<(), MycoError> {
+        if !self.keys.contains_key(k) {
+            return Err(MycoError::UnknownContact);
+        }
+        let result = if send_tombstone {
+            self.next_envelope(&[], k).and_then(|tombstone| {
+                self.write_envelope(&tombstone.with_content_type(ContentType::Tombstone), k)
+            })
+        } else {
+            Ok(())
+        };
+        self.forget(k);
+        self.store.lock().unwrap().forget(k)?;
+        result
+    }
 
-        self.epoch += 1;
-        local_latency.finish();
+    /// Export contact `k` as a [`ContactBundle`] payload for a QR code, together with the server
+    /// addresses the importer should use and, optionally, this client's identity key.
+    pub fn export_contact(
+        &self,
+        k: &Key,
+        server1: &str,
+        server2: &str,
+        identity_key: Option<[u8; 32]>,
+    ) -> Result<String, MycoError> {
+        if !self.keys.contains_key(k) {
+            return Err(MycoError::UnknownContact);
+        }
+        ContactBundle {
+            id: self.id.clone(),
+            identity_key,
+            key: k.clone(),
+            server1: server1.to_string(),
+            server2: server2.to_string(),
+        }
+        .encode()
+    }
 
-        // Upload the message to Server1
-        self.s1
-            .queue_write(ct, f, Key::new(k_oblv_t), cs)
-            .await
-            .map_err(|_| MycoError::NoMessageFound)?;
+    /// Import a contact from a [`ContactBundle`] payload, setting up its key. Returns the bundle,
+    /// whose ID is the one to read the contact's messages under.
+    pub fn import_contact(&mut self, encoded: &str) -> Result<ContactBundle, MycoError> {
+        let bundle = ContactBundle::decode(encoded)?;
+        self.setup(&bundle.key)?;
+        Ok(bundle)
+    }
+
+    /// Wrap `msg` in a binary envelope carrying the next sequence number for contact `k`.
+    fn next_envelope(&self, msg: &[u8], k: &Key) -> Result<Envelope, MycoError> {
+        let sequence = self.store.lock().unwrap().next_sequence(k)?;
+        Ok(Envelope::new(msg.to_vec()).with_sequence(sequence))
+    }
+
+    /// Asynchronously write a message to Server1, wrapped in an envelope.
+    pub async fn async_write(&mut self, msg: &[u8], k: &Key) -> Result<(), MycoError> {
+        let envelope = self.next_envelope(msg, k)?;
+        self.async_write_envelope(&envelope, k).await
+    }
+
+    /// Asynchronously write an envelope to Server1.
+    ///
+    /// A write rejected because Server1's epoch has closed is requeued for the next epoch, up to
+    /// [`REQUEUE_ATTEMPTS`] times.
+    pub async fn async_write_envelope(&mut self, envelope: &Envelope, k: &Key) -> Result<(), MycoError> {
+        let end_to_end_latency = LatencyMetric::new("client_write_end_to_end");
+        self.queue_envelope(envelope, k, tokio::time::sleep).await?;
         end_to_end_latency.finish();
         Ok(())
     }
 
-    /// Write a message to Server1.
+    /// Write a message to Server1, wrapped in an envelope.
     pub fn write(&mut self, msg: &[u8], k: &Key) -> Result<(), MycoError> {
-        let epoch = self.epoch;
-        let cs = self.id.clone().into_bytes();
+        let envelope = self.next_envelope(msg, k)?;
+        self.write_envelope(&envelope, k)
+    }
+
+    /// Write an envelope to Server1, requeueing it like [`Client::async_write_envelope`] if the
+    /// epoch closed.
+    pub fn write_envelope(&mut self, envelope: &Envelope, k: &Key) -> Result<(), MycoError> {
+        futures::executor::block_on(self.queue_envelope(envelope, k, |delay| {
+            std::thread::sleep(delay);
+            std::future::ready(())
+        }))
+    }
+
+    /// Everything Server1 needs to queue a write of `envelope` to contact `k` in `epoch`, carrying
+    /// the epoch's write `token`.
+    fn prepare_write(
+        &self,
+        envelope: &Envelope,
+        k: &Key,
+        epoch: usize,
+        token: Vec<u8>,
+    ) -> Result<PreparedWrite, MycoError> {
+        let EpochKeys { f, k_oblv_t } = self.epoch_keys(k, epoch)?; // PRF and oblivious key for this epoch
+        let cs = self.pseudonym(k, self.id.as_bytes(), epoch)?; // Our pseudonym towards k for this epoch
+        let (k_msg, _, _) = self.keys.get(k).ok_or(MycoError::UnknownContact)?;
+        let plaintext = pad(&envelope.encode()?, MESSAGE_SIZE, self.padding)?;
+        let ct = self.simulation.encrypt(k_msg, &plaintext, EncryptionType::Encrypt)?; // Encrypt the message
+        let access_tag = self.access_tag(k, &f, &cs, &ct)?; // Tag the write if the mailbox is guarded
+        Ok(PreparedWrite {
+            ct,
+            f,
+            k_oblv_t: Key::new(k_oblv_t),
+            cs,
+            token,
+            access_tag,
+        })
+    }
+
+    /// Queue `envelope` for contact `k` with Server1, waiting with `sleep` before each requeue.
+    ///
+    /// A requeued write is derived afresh for the epoch Server2 has reached by then, so a write
+    /// that missed the close of its epoch lands in the next one where its recipient looks for it.
+    async fn queue_envelope<S, F>(&mut self, envelope: &Envelope, k: &Key, sleep: S) -> Result<(), MycoError>
+    where
+        S: Fn(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        let local_latency = LatencyMetric::new("client_write_local");
+        let mut epoch = self.epoch;
+        let token = self.write_token(epoch).await?;
+        let mut write = self.prepare_write(envelope, k, epoch, token)?;
+        self.top_up_reads().await?; // The write ends the epoch, so spend what's left of its reads
+        local_latency.finish();
+
+        // Upload the message to Server1, requeueing it if the epoch closed in the meantime
+        let mut attempt = 0;
+        let accepted = loop {
+            let result = self.submit_write(&write).await;
+            let delay = match result.as_ref().err().and_then(requeue_delay) {
+                Some(delay) if attempt < REQUEUE_ATTEMPTS => delay,
+                _ => break result.map_err(queue_write_error)?,
+            };
+            sleep(delay).await;
+            attempt += 1;
+
+            let reached = self
+                .s2
+                .get_epoch()
+                .await
+                .map_err(|e| MycoError::transport("get_epoch", e))?
+                .epoch as usize;
+            if reached > epoch {
+                epoch = reached;
+                let token = self.write_token(epoch).await?;
+                write = self.prepare_write(envelope, k, epoch, token)?;
+            }
+        };
+        self.advance_epoch(epoch, accepted)
+    }
 
-        let (k_msg, k_oblv, k_prf) = self.keys.get(k).unwrap(); // Get the keys for this key 
-        let f = prf(k_prf, &epoch.to_be_bytes())?; // PRF for this epoch
-        let k_oblv_t = kdf(k_oblv, &epoch.to_string())?; // Oblivious key for this epoch
-        let ct = encrypt(k_msg, msg, EncryptionType::Encrypt)?; // Encrypt the message
+    /// Hand `write` to Server1. In distributed trust mode the front S1 only evaluates its layer of
+    /// the location blindly, and the location input and oblivious key are sealed to the back S1.
+    async fn submit_write(&self, write: &PreparedWrite) -> Result<u64, MycoError> {
+        let Some(back_key) = &self.back_key else {
+            return self
+                .s1
+                .queue_write(
+                    write.ct.clone(),
+                    write.f.clone(),
+                    write.k_oblv_t.clone(),
+                    write.cs.clone(),
+                    write.token.clone(),
+                    write.access_tag.clone(),
+                )
+                .await;
+        };
+        let request = LocationRequest::new(&write.f, &write.cs, &mut ChaCha20Rng::from_entropy());
+        let (epoch, evaluated) = self.s1.evaluate_location(request.blinded()).await?;
+        let x = request.finish(&evaluated)?;
+        self.s1
+            .queue_sealed_write(SealedWrite {
+                epoch,
+                ct: write.ct.clone(),
+                sealed: seal_location(back_key, &x, &write.k_oblv_t)?,
+                address: mailbox_address(&write.f, &write.cs),
+                token: write.token.clone(),
+                access_tag: write.access_tag.clone(),
+            })
+            .await
+    }
 
-        self.epoch += 1;
-        futures::executor::block_on(self.s1.queue_write(ct, f, Key::new(k_oblv_t), cs)) // Upload the message to Server1
+    /// Move on from `accepted`, the epoch Server1 queued a write derived for `written` into.
+    ///
+    /// The client's epoch follows Server1's receipts rather than counting writes, so a rejected
+    /// write doesn't move it and a skipped epoch is caught up on. A write queued into another epoch
+    /// than its own fails with [`MycoError::WriteEpochMismatch`]: its recipient won't look for it
+    /// there, and it has to be written again.
+    fn advance_epoch(&mut self, written: usize, accepted: u64) -> Result<(), MycoError> {
+        self.epoch = accepted as usize + 1;
+        self.spawn_precompute();
+        if accepted != written as u64 {
+            return Err(MycoError::WriteEpochMismatch { written, accepted });
+        }
+        Ok(())
     }
 
     /// Asynchronously read messages from Server2.
@@ -101,7 +875,7 @@ impl Client {
         cs: String,
         epoch_past: usize,
         batch_size: usize,
-    ) -> Result<Vec<Vec<u8>>, MycoError> {
+    ) -> Result<Vec<ReceivedMessage>, MycoError> {
         if keys.len() != batch_size {
             return Err(MycoError::InvalidBatchSize);
         }
@@ -109,26 +883,18 @@ impl Client {
         let end_to_end_latency =
             LatencyMetric::new(&format!("client_read_end_to_end_{}", batch_size));
         let mut local_latency = LatencyMetric::new(&format!("client_read_local_{}", batch_size));
-        let epoch = self.epoch - 1 - epoch_past;
+        let epoch = self.past_epoch(epoch_past)?;
         let cs: Vec<u8> = cs.into_bytes(); // Convert the client ID to a byte vector
 
-        // Get PRF keys from server2
+        // Fetch the PRF keys published since the last read from server2
         local_latency.pause();
         let get_prf_keys_latency =
             LatencyMetric::new(&format!("client_read_get_prf_keys_{}", batch_size));
-        let server_keys = self
-            .s2
-            .get_prf_keys()
-            .await
-            .map_err(|_| MycoError::NoMessageFound)?;
+        self.sync_prf_keys().await?;
         get_prf_keys_latency.finish();
         local_latency.resume();
 
-        if server_keys.is_empty() || epoch_past >= server_keys.len() {
-            return Err(MycoError::NoMessageFound);
-        }
-
-        let k_s1_t = server_keys.get(server_keys.len() - 1 - epoch_past).unwrap(); // Get the S1 key for this epoch
+        let k_s1_t = self.cached_prf_key(epoch_past)?; // Get the S1 key for this epoch
 
         // Calculate paths for all keys
         let mut paths = Vec::with_capacity(batch_size);
@@ -136,20 +902,20 @@ impl Client {
 
         // For each key, derive the necessary cryptographic values for the current epoch
         for k in keys {
-            let (k_msg, k_oblv, k_prf) = self.keys.get(&k).unwrap(); 
-            let k_oblv_t =
-                kdf(k_oblv, &epoch.to_string()).map_err(|_| MycoError::NoMessageFound)?;
-            let f = prf(&k_prf, &epoch.to_be_bytes())?;
+            let (k_msg, _, _) = self.keys.get(&k).ok_or(MycoError::UnknownContact)?;
+            let EpochKeys { f, k_oblv_t } = self.epoch_keys(&k, epoch)?;
 
-            // Calculate the path location using the server's key and the derived PRF value
-            let l = prf(k_s1_t.0.as_slice(), &[&f[..], &cs[..]].concat())?;
+            // Calculate the path location using the server's key, the derived PRF value and the
+            // writer's pseudonym
+            let l = location_prf(&k_s1_t.0, &f, &self.pseudonym(&k, &cs, epoch)?)?;
             let l_path = Path::from(l);
             paths.push(l_path);
-            key_data.push((k_msg.clone(), k_oblv_t));
+            key_data.push((k, k_msg.clone(), k_oblv_t));
         }
 
         // Get path indices and read paths
         let indices = get_path_indices(paths.clone());
+        self.charge_reads(batch_size)?;
 
         local_latency.pause();
         let read_latency = LatencyMetric::new(&format!("client_read_read_paths_{}", batch_size));
@@ -157,39 +923,28 @@ impl Client {
             .s2
             .read_paths_client(indices.clone(), batch_size)
             .await
-            .map_err(|_| MycoError::NoMessageFound)?;
+            .map_err(|e| MycoError::transport("read_paths_client", e))?;
         read_latency.finish();
         local_latency.resume();
 
-        // Try to decrypt messages from all paths
-        let mut messages = Vec::new();
-
         // First, convert buckets into a BinaryTree
         let bucket_tree = SparseBinaryTree::new_with_data(buckets, indices);
+        let found = self.search_paths(&bucket_tree, key_data, &paths)?;
 
-        // Now process each key along its specific path
-        for ((k_msg, k_oblv_t), path) in key_data.into_iter().zip(paths.iter()) {
-            let mut found = false;
-            // Only check buckets along this key's path
-            let path_buckets = bucket_tree.get_all_nodes_along_path(&path);
-
-            // Iterate over each bucket along the path to find and decrypt the message
-            for bucket in path_buckets {
-                for block in bucket.iter() {
-                    // Attempt to decrypt the block with the oblivious key
-                    if let Ok(ct) = decrypt(&k_oblv_t, &block.0) {
-                        // If successful, attempt to decrypt the ciphertext with the message key
-                        if let Ok(msg) = decrypt(&k_msg, &ct) {
-                            // If decryption is successful, trim any padding and add the message to the list
-                            messages.push(trim_zeros(&msg));
-                            found = true;
-                            break; // Exit the loop once the message is found
-                        }
-                    }
-                }
-                if found {
-                    break; // Exit the outer loop if the message has been found
-                }
+        // Unwrap the envelopes in key order, skipping those an earlier read already delivered
+        let mut messages = Vec::new();
+        for (contact, envelope, path_len, found_at) in found.into_iter().flatten() {
+            if self.record_delivery(&contact, &cs, epoch, &envelope)? {
+                let expected = self.advance_sequence(&contact, &cs, &envelope)?;
+                messages.push(ReceivedMessage {
+                    sequence: envelope.header.sequence,
+                    expected,
+                    payload: envelope.payload,
+                    contact,
+                    epoch,
+                    path_len,
+                    found_at,
+                });
             }
         }
 
@@ -199,65 +954,373 @@ impl Client {
         Ok(messages)
     }
 
-    /// Read messages from Server2.
-    pub fn read(&self, k: &Key, cs: String, epoch_past: usize) -> Result<Vec<u8>, MycoError> {
-        let epoch = self.epoch - 1 - epoch_past;
-        let cs = cs.into_bytes();
+    /// Search each key's path in `bucket_tree` for its message, given the key with its message and
+    /// oblivious keys. Returns the envelope found on each path, if any, with the number of buckets
+    /// on the path and the depth it was found at.
+    fn search_paths(
+        &self,
+        bucket_tree: &SparseBinaryTree<Bucket>,
+        key_data: Vec<(Key, Vec<u8>, Vec<u8>)>,
+        paths: &[Path],
+    ) -> Result<Vec<Option<PathMatch>>, MycoError> {
+        // Search the paths in parallel, as the trial decryptions dominate reads of large batches.
+        // Only buckets along the key's own path are checked.
+        let padding = self.padding;
+        let simulation = self.simulation;
+        key_data
+            .into_par_iter()
+            .zip(paths.par_iter())
+            .map(|((k, k_msg, k_oblv_t), path)| {
+                let path_buckets = bucket_tree.get_all_nodes_along_path(path);
+                let path_len = path_buckets.len();
+                path_buckets
+                    .into_iter()
+                    .enumerate()
+                    .find_map(|(depth, bucket)| {
+                        bucket
+                            .iter()
+                            .filter(|block| block.matches_hint(&k_oblv_t))
+                            // Decrypt the block with the oblivious key, then the ciphertext with
+                            // the message key
+                            .find_map(|block| {
+                                let ct = simulation.decrypt(&k_oblv_t, block.ciphertext()).ok()?;
+                                simulation.decrypt(&k_msg, &ct).ok()
+                            })
+                            .map(|msg| (depth, msg))
+                    })
+                    .map(|(found_at, msg)| {
+                        unpad(&msg, padding)
+                            .and_then(|msg| Envelope::decode(&msg))
+                            .map(|envelope| (k, envelope, path_len, found_at))
+                    })
+                    .transpose()
+            })
+            .collect()
+    }
 
-        // Retrieve the cryptographic keys for the given key and derive the necessary values for the current epoch
-        let (k_msg, k_oblv, k_prf) = self.keys.get(&k).unwrap();
-        let k_oblv_t = kdf(k_oblv, &epoch.to_string()).map_err(|_| MycoError::NoMessageFound)?;
-        let f = prf(&k_prf, &epoch.to_be_bytes())?;
-
-        let keys = futures::executor::block_on(self.s2.get_prf_keys())
-            .map_err(|_| MycoError::NoMessageFound)?;
-        if keys.is_empty() {
-            return Err(MycoError::NoMessageFound);
+    /// Catch up on everything `contacts` wrote in the last [`DELTA`] epochs, each contact given as
+    /// a contact key with the ID of the client writing to it: find the epochs still holding one of
+    /// their writes (see [`Client::pending_epochs`]), read the contacts' paths in those epochs,
+    /// [`SYNC_BATCH_PATHS`] at a time, and decrypt them. Returns the messages no earlier read
+    /// delivered, grouped by contact in the order of `contacts` and in the order each contact sent
+    /// them, whatever order their paths turned up in. Each reports the sequence numbers the
+    /// contact used since the previous delivered message that never turned up (see
+    /// [`ReceivedMessage::missing`]).
+    pub async fn sync(&self, contacts: &[(Key, String)]) -> Result<Vec<ReceivedMessage>, MycoError> {
+        let end_to_end_latency = LatencyMetric::new("client_sync_end_to_end");
+        let pending = self.pending_epochs(contacts).await?;
+
+        // Every contact's path in every pending epoch, as the index doesn't say who wrote.
+        let mut reads = Vec::with_capacity(pending.len() * contacts.len());
+        for epoch_past in pending {
+            let epoch = self.past_epoch(epoch_past)?;
+            let k_s1_t = self.cached_prf_key(epoch_past)?;
+            for (position, (k, cs)) in contacts.iter().enumerate() {
+                let (k_msg, _, _) = self.keys.get(k).ok_or(MycoError::UnknownContact)?;
+                let EpochKeys { f, k_oblv_t } = self.epoch_keys(k, epoch)?;
+                let cs = cs.as_bytes();
+                let l = location_prf(&k_s1_t.0, &f, &self.pseudonym(k, cs, epoch)?)?;
+                reads.push((Path::from(l), (k.clone(), k_msg.clone(), k_oblv_t), cs, epoch, position));
+            }
+        }
+
+        self.charge_reads(reads.len())?;
+        let mut delivered = Vec::new();
+        for batch in reads.chunks(SYNC_BATCH_PATHS) {
+            let paths: Vec<Path> = batch.iter().map(|(path, ..)| path.clone()).collect();
+            let indices = get_path_indices(paths.clone());
+            let buckets = self
+                .s2
+                .read_paths_client(indices.clone(), paths.len())
+                .await
+                .map_err(|e| MycoError::transport("read_paths_client", e))?;
+            let bucket_tree = SparseBinaryTree::new_with_data(buckets, indices);
+            let key_data = batch.iter().map(|(_, key_data, ..)| key_data.clone()).collect();
+            let found = self.search_paths(&bucket_tree, key_data, &paths)?;
+
+            for (found, (_, _, cs, epoch, position)) in found.into_iter().zip(batch) {
+                let Some((contact, envelope, path_len, found_at)) = found else {
+                    continue;
+                };
+                if self.record_delivery(&contact, cs, *epoch, &envelope)? {
+                    delivered.push((*position, cs, contact, envelope, *epoch, path_len, found_at));
+                }
+            }
+        }
+
+        // Put each contact's messages back in send order before looking for gaps between them.
+        delivered.sort_by_key(|(position, _, _, envelope, ..)| (*position, envelope.header.sequence));
+        let mut messages = Vec::with_capacity(delivered.len());
+        for (_, cs, contact, envelope, epoch, path_len, found_at) in delivered {
+            let expected = self.advance_sequence(&contact, cs, &envelope)?;
+            messages.push(ReceivedMessage {
+                sequence: envelope.header.sequence,
+                expected,
+                payload: envelope.payload,
+                contact,
+                epoch,
+                path_len,
+                found_at,
+            });
+        }
+
+        end_to_end_latency.finish();
+        Ok(messages)
+    }
+
+    /// Read a message from Server2 and return its envelope, or `None` if an earlier `read_new` or
+    /// `async_read` already delivered it.
+    pub fn read_new(&self, k: &Key, cs: String, epoch_past: usize) -> Result<Option<Envelope>, MycoError> {
+        let epoch = self.past_epoch(epoch_past)?;
+        let sender = cs.clone().into_bytes();
+        let envelope = self.read_envelope(k, cs, epoch_past)?;
+        if !self.record_delivery(k, &sender, epoch, &envelope)? {
+            return Ok(None);
         }
+        self.advance_sequence(k, &sender, &envelope)?;
+        Ok(Some(envelope))
+    }
+
+    /// Record a message as delivered in the store, returning `false` if it already was.
+    fn record_delivery(&self, k: &Key, cs: &[u8], epoch: usize, envelope: &Envelope) -> Result<bool, MycoError> {
+        self.store
+            .lock()
+            .unwrap()
+            .insert(k, cs, epoch, envelope.header.sequence)
+    }
+
+    /// Move the sequence number expected from `cs` past a delivered envelope, returning the one
+    /// expected before.
+    fn advance_sequence(&self, k: &Key, cs: &[u8], envelope: &Envelope) -> Result<u64, MycoError> {
+        self.store
+            .lock()
+            .unwrap()
+            .advance(k, cs, envelope.header.sequence)
+    }
 
-        // Add bounds checking
-        if epoch_past >= keys.len() {
-            return Err(MycoError::NoMessageFound);
+    /// Record an envelope read back from `epoch` as delivered, returning the sequence number
+    /// expected from `cs` before it. An envelope delivered before leaves the expected number as
+    /// it is, so reading it again doesn't report the same gap twice.
+    fn deliver(&self, k: &Key, cs: &[u8], epoch: usize, envelope: &Envelope) -> Result<u64, MycoError> {
+        if self.record_delivery(k, cs, epoch, envelope)? {
+            self.advance_sequence(k, cs, envelope)
+        } else {
+            Ok(self.store.lock().unwrap().expected(k, cs))
         }
+    }
+
+    /// Read a message from Server2 and return its payload, with where and when it was found.
+    pub fn read(&self, k: &Key, cs: String, epoch_past: usize) -> Result<ReceivedMessage, MycoError> {
+        let epoch = self.past_epoch(epoch_past)?;
+        let sender = cs.clone().into_bytes();
+        let (envelope, path_len, found_at) = self.find_envelope(k, cs, epoch_past)?;
+        let expected = self.deliver(k, &sender, epoch, &envelope)?;
+        Ok(ReceivedMessage {
+            sequence: envelope.header.sequence,
+            expected,
+            payload: envelope.payload,
+            contact: k.clone(),
+            epoch,
+            path_len,
+            found_at,
+        })
+    }
+
+    /// Read a message from Server2 and return its envelope.
+    pub fn read_envelope(&self, k: &Key, cs: String, epoch_past: usize) -> Result<Envelope, MycoError> {
+        self.find_envelope(k, cs, epoch_past)
+            .map(|(envelope, _, _)| envelope)
+    }
+
+    /// Read a message from Server2 and return its envelope, the number of buckets on its path
+    /// and the depth of the bucket it was found in.
+    fn find_envelope(
+        &self,
+        k: &Key,
+        cs: String,
+        epoch_past: usize,
+    ) -> Result<(Envelope, usize, usize), MycoError> {
+        let epoch = self.past_epoch(epoch_past)?;
+        let cs = cs.into_bytes();
+
+        // Retrieve the cryptographic keys for the given key and derive the necessary values for the current epoch
+        let (k_msg, _, _) = self.keys.get(k).ok_or(MycoError::UnknownContact)?;
+        let EpochKeys { f, k_oblv_t } = self.epoch_keys(k, epoch)?;
+
+        futures::executor::block_on(self.sync_prf_keys())?;
 
         // Retrieve the server's key for the specified past epoch and calculate the path location
-        let k_s1_t = keys.get(keys.len() - 1 - epoch_past).unwrap();
-        let l = prf(k_s1_t.0.as_slice(), &[&f[..], &cs[..]].concat())?;
+        let k_s1_t = self.cached_prf_key(epoch_past)?;
+        let l = location_prf(&k_s1_t.0, &f, &self.pseudonym(k, &cs, epoch)?)?;
         let l_path = Path::from(l);
 
         // Calculate path indices and read the corresponding paths from Server2
         let indices = get_path_indices(vec![l_path]);
+        self.charge_reads(1)?;
         let path = futures::executor::block_on(self.s2.read_paths_client(indices, BATCH_SIZE))
-            .map_err(|_| MycoError::NoMessageFound)?;
+            .map_err(|e| MycoError::transport("read_paths_client", e))?;
 
-        for bucket in path {
-            for block in bucket {
-                if let Ok(ct) = decrypt(&k_oblv_t, &block.0) {
-                    return decrypt(k_msg, &ct).map(|buf| trim_zeros(&buf));
+        let path_len = path.len();
+        for (depth, bucket) in path.into_iter().enumerate() {
+            for block in bucket.iter().filter(|block| block.matches_hint(&k_oblv_t)) {
+                if let Ok(ct) = self.simulation.decrypt(&k_oblv_t, block.ciphertext()) {
+                    let msg = unpad(&self.simulation.decrypt(k_msg, &ct)?, self.padding)?;
+                    return Ok((Envelope::decode(&msg)?, path_len, depth));
                 }
             }
         }
         Err(MycoError::NoMessageFound)
     }
 
-    /// Generate fake write data.
-    pub fn fake_write(&self) -> Result<(), MycoError> {
+    /// Fetch the notification index of the newest epoch, or `None` if Server2 has none for it.
+    pub async fn notifications(&self) -> Result<Option<NotificationIndex>, MycoError> {
+        self.sync_prf_keys().await?;
+        let notifications = self
+            .s2
+            .get_notifications()
+            .await
+            .map_err(|e| MycoError::transport("get_notifications", e))?;
+        let cursor = self.prf_keys.lock().unwrap().synced.prf_key_cursor;
+        match notifications {
+            Some((index_cursor, bytes)) if index_cursor == cursor => {
+                Ok(Some(NotificationIndex::from_bytes(&bytes)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether contact `k` (with ID `cs`) wrote in the newest epoch according to `index`, as
+    /// returned by [`Client::notifications`].
+    pub fn has_notification(&self, index: &NotificationIndex, k: &Key, cs: &str) -> Result<bool, MycoError> {
+        let epoch = self.past_epoch(0)?;
+        let EpochKeys { f, .. } = self.epoch_keys(k, epoch)?;
+        let k_s1_t = self.cached_prf_key(0)?;
+        let cs = self.pseudonym(k, cs.as_bytes(), epoch)?;
+        index.contains_location(&location_prf(&k_s1_t.0, &f, &cs)?)
+    }
+
+    /// Which of the last [`DELTA`] epochs still hold a write of one of `contacts`, each a contact
+    /// key with the ID of the client writing to it, so a returning client only reads those. Returns
+    /// the matching epochs as `epoch_past` values for [`Client::read`], oldest first.
+    ///
+    /// Server2 answers from the notification index of each epoch, so it learns how many of the
+    /// read tags match in which epochs, but not which writes they belong to.
+    pub async fn pending_epochs(&self, contacts: &[(Key, String)]) -> Result<Vec<usize>, MycoError> {
+        self.sync_prf_keys().await?;
+        let newest = self.prf_keys.lock().unwrap().synced.epoch.checked_sub(1);
+        let Some(newest) = newest else {
+            return Ok(vec![]);
+        };
+        let mut tags = Vec::new();
+        for epoch_past in 0..DELTA {
+            let (Ok(epoch), Ok(k_s1_t)) = (self.past_epoch(epoch_past), self.cached_prf_key(epoch_past)) else {
+                break;
+            };
+            for (k, cs) in contacts {
+                let EpochKeys { f, .. } = self.epoch_keys(k, epoch)?;
+                let cs = self.pseudonym(k, cs.as_bytes(), epoch)?;
+                let tag = notification_tag(&location_prf(&k_s1_t.0, &f, &cs)?)?;
+                tags.push((newest - epoch_past as u64, tag));
+            }
+        }
+
+        let mut epochs = Vec::new();
+        for chunk in tags.chunks(MAX_PENDING_EPOCH_TAGS) {
+            let pending = self
+                .s2
+                .pending_epochs(chunk.to_vec())
+                .await
+                .map_err(|e| MycoError::transport("pending_epochs", e))?;
+            epochs.extend(pending.into_iter().map(|epoch| (newest - epoch) as usize));
+        }
+        epochs.sort_unstable_by(|a, b| b.cmp(a));
+        epochs.dedup();
+        Ok(epochs)
+    }
+
+    /// Read a message from Server2 along with the sequence number expected from its sender, so the
+    /// messages sent before it that were never read show up as [`Sequenced::missing`].
+    pub fn read_sequenced(&self, k: &Key, cs: String, epoch_past: usize) -> Result<Sequenced, MycoError> {
+        let epoch = self.past_epoch(epoch_past)?;
+        let envelope = self.read_envelope(k, cs.clone(), epoch_past)?;
+        let expected = self.deliver(k, cs.as_bytes(), epoch, &envelope)?;
+        Ok(Sequenced { envelope, expected })
+    }
+
+    /// Catch up on the messages sender `cs` wrote to contact `k` in the last `epochs` epochs.
+    /// Epochs without a message are skipped, and the messages found are returned in the order they
+    /// were sent rather than the order of their epochs.
+    pub fn catch_up(&self, k: &Key, cs: String, epochs: usize) -> Result<Vec<Sequenced>, MycoError> {
+        let mut envelopes = Vec::new();
+        for epoch_past in 0..epochs.min(self.epoch).min(DELTA) {
+            match self.read_envelope(k, cs.clone(), epoch_past) {
+                Ok(envelope) => envelopes.push((self.past_epoch(epoch_past)?, envelope)),
+                Err(MycoError::NoMessageFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        envelopes.sort_by_key(|(_, envelope)| envelope.header.sequence);
+        envelopes
+            .into_iter()
+            .map(|(epoch, envelope)| {
+                let expected = self.deliver(k, cs.as_bytes(), epoch, &envelope)?;
+                Ok(Sequenced { envelope, expected })
+            })
+            .collect()
+    }
+
+    /// Generate fake write data. Like a real write, it first tops up the epoch's reads, goes through
+    /// the front S1's blind evaluation in distributed trust mode and moves the client on from the
+    /// epoch Server1 queued it into.
+    pub fn fake_write(&mut self) -> Result<(), MycoError> {
+        futures::executor::block_on(self.top_up_reads())?;
         let mut rng = ChaCha20Rng::from_entropy();
         let l: Vec<u8> = (0..D).map(|_| rng.gen()).collect();
 
         // Generate random data for a fake write operation
         let k_oblv_t: Key = Key::random(&mut rng);
         let ct: Vec<u8> = (0..BLOCK_SIZE).map(|_| rng.gen()).collect();
-        let cs: Vec<u8> = self.id.clone().into_bytes();
-        futures::executor::block_on(self.s1.queue_write(ct, l, k_oblv_t, cs))
+        let cs: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
+        let token = futures::executor::block_on(self.write_token(self.epoch))?;
+        let write = PreparedWrite {
+            ct,
+            f: l,
+            k_oblv_t,
+            cs,
+            token,
+            access_tag: None,
+        };
+        let accepted = futures::executor::block_on(self.submit_write(&write))?;
+        self.epoch = accepted as usize + 1;
+        self.spawn_precompute();
+        Ok(())
     }
 
     /// Generate fake read data.
-    pub fn fake_read(&self) -> Vec<Bucket> {
-        let mut rng = ChaCha20Rng::from_entropy();
-        let l: Vec<u8> = (0..D).map(|_| rng.gen()).collect();
+    ///
+    /// Once the epoch's read budget is spent, the read is skipped and no buckets are returned.
+    pub fn fake_read(&self) -> Result<Vec<Bucket>, MycoError> {
+        match futures::executor::block_on(self.fake_read_paths(1)) {
+            Err(MycoError::ReadBudgetExceeded { .. }) => Ok(vec![]),
+            result => result,
+        }
+    }
 
-        let indices = get_path_indices(vec![Path::from(l)]);
-        futures::executor::block_on(self.s2.read_paths_client(indices, BATCH_SIZE)).unwrap()
+    /// Read `paths` random paths in one request, counted as fake reads against the read budget.
+    async fn fake_read_paths(&self, paths: usize) -> Result<Vec<Bucket>, MycoError> {
+        self.read_budget.lock().unwrap().charge(self.epoch, paths, false)?;
+        let indices = {
+            let mut rng = ChaCha20Rng::from_entropy();
+            get_path_indices(
+                (0..paths)
+                    .map(|_| Path::from((0..D).map(|_| rng.gen()).collect::<Vec<u8>>()))
+                    .collect(),
+            )
+        };
+        self.s2
+            .read_paths_client(indices, paths)
+            .await
+            .map_err(|e| MycoError::transport("read_paths_client", e))
     }
 }
@@ -19,8 +19,13 @@ pub const NUM_CLIENTS: usize = DB_SIZE / DELTA;
 
 /// Parameter controlling number of paths sampled per client write.
 /// Set to 1 since each client writes exactly one message per epoch.
+/// This is the default; deployments can raise it at runtime with `Server1::set_nu`.
 pub const NU: usize = 1;
 
+/// Largest path sampling factor a Server1 accepts. The framed transport's frame limit is sized for
+/// this many paths per client.
+pub const MAX_NU: usize = 8;
+
 /// Size of each bucket in the binary tree.
 /// Set to 50 based on empirical analysis showing this prevents overflow
 /// while allowing efficient message percolation.
@@ -48,11 +53,21 @@ pub const NONCE_SIZE: usize = 12;
 /// Size of the authentication tag for AES-GCM
 pub const TAG_SIZE: usize = 16;
 
+/// Size of the key commitment carried by every ciphertext, see [`crate::crypto::encrypt`]
+pub const COMMITMENT_SIZE: usize = 32;
+
+/// Size of the key hint in front of every block, see [`crate::crypto::key_hint`]
+pub const KEY_HINT_SIZE: usize = 4;
+
 /// Total block size including encrypted message and metadata
-pub const BLOCK_SIZE: usize = INNER_BLOCK_SIZE + NONCE_SIZE + TAG_SIZE;
+pub const BLOCK_SIZE: usize =
+    KEY_HINT_SIZE + INNER_BLOCK_SIZE + NONCE_SIZE + COMMITMENT_SIZE + TAG_SIZE;
 
 /// Size of inner encrypted block including message and metadata
-pub const INNER_BLOCK_SIZE: usize = MESSAGE_SIZE + NONCE_SIZE + TAG_SIZE;
+pub const INNER_BLOCK_SIZE: usize = MESSAGE_SIZE + NONCE_SIZE + COMMITMENT_SIZE + TAG_SIZE;
+
+/// Size of the length prefix of a padded plaintext, see [`crate::utils::Padding`]
+pub const LENGTH_PREFIX_SIZE: usize = 2;
 
 /// Size of plaintext message payload in bytes.
 /// Set to 228 bytes to match block sizes used in prior PIR systems.
@@ -75,4 +90,72 @@ pub const NUM_BUCKETS_PER_READ_PATHS_CHUNK: usize =
     MAX_REQUEST_SIZE_READ_PATHS / BUCKET_SIZE_BYTES;
 
 /// Fixed seed for throughput benchmark RNG to ensure reproducible results
-pub const FIXED_SEED_TPUT_RNG: [u8; 32] = [1u8; 32];
\ No newline at end of file
+pub const FIXED_SEED_TPUT_RNG: [u8; 32] = [1u8; 32];
+
+/// Size of a bincode-encoded bucket holding Z full blocks, including the length prefixes.
+pub const ENCODED_BUCKET_SIZE: usize = 8 + Z * (8 + BLOCK_SIZE);
+
+/// Slack added to every request body limit for struct framing, keys and other small fields.
+pub const REQUEST_OVERHEAD: usize = 64 * 1024;
+
+/// Maximum body size for control requests (batch_init, finalize_epoch, ...).
+pub const MAX_CONTROL_BODY_SIZE: usize = REQUEST_OVERHEAD;
+
+/// Maximum body size for a client's queue_write request carrying a single block.
+pub const MAX_QUEUE_WRITE_BODY_SIZE: usize = BLOCK_SIZE + REQUEST_OVERHEAD;
+
+/// Maximum body size for requests carrying a list of bucket indices, bounded by the tree size.
+pub const MAX_INDICES_BODY_SIZE: usize = (2 << D) * 8 + REQUEST_OVERHEAD;
+
+/// Maximum body size for a single chunk_write request.
+pub const MAX_CHUNK_WRITE_BODY_SIZE: usize =
+    NUM_BUCKETS_PER_BATCH_WRITE_CHUNK * ENCODED_BUCKET_SIZE + REQUEST_OVERHEAD;
+
+/// Maximum body size for a single chunk_write_deltas request. A bucket's delta is never larger
+/// than the bucket and the tag of its variant.
+pub const MAX_CHUNK_WRITE_DELTAS_BODY_SIZE: usize =
+    NUM_BUCKETS_PER_BATCH_WRITE_CHUNK * (4 + ENCODED_BUCKET_SIZE) + REQUEST_OVERHEAD;
+
+/// Maximum body size for publishing a notification index, allowing for up to four cuckoo table
+/// slots of at most 64 encoded bytes per client.
+pub const MAX_NOTIFICATIONS_BODY_SIZE: usize = NUM_CLIENTS * 4 * 64 + REQUEST_OVERHEAD;
+
+/// Maximum body size for publishing an epoch's storage report, allowing for one storage tag and
+/// block count of at most 32 encoded bytes per client.
+pub const MAX_STORAGE_REPORT_BODY_SIZE: usize = NUM_CLIENTS * 32 + REQUEST_OVERHEAD;
+
+/// Most read tags a client can ask about in one pending epochs query: one per epoch of the last
+/// DELTA for up to 64 contacts.
+pub const MAX_PENDING_EPOCH_TAGS: usize = 64 * DELTA;
+
+/// Maximum body size for a pending epochs query, allowing for an epoch number and a tag of at most
+/// 48 encoded bytes per read tag.
+pub const MAX_PENDING_EPOCHS_BODY_SIZE: usize = MAX_PENDING_EPOCH_TAGS * (8 + 48) + REQUEST_OVERHEAD;
+
+/// Maximum body size for an unchunked write on the `/write` route, capped at one chunk like
+/// `/chunk_write`. Epochs with larger pathsets are written in chunks.
+pub const MAX_WRITE_BODY_SIZE: usize = MAX_CHUNK_WRITE_BODY_SIZE;
+
+/// Size of a full epoch's pathset written in one piece at the largest sampling factor, as the
+/// framed transport sends it.
+pub const MAX_EPOCH_WRITE_SIZE: usize =
+    NUM_CLIENTS * MAX_NU * (D + 1) * ENCODED_BUCKET_SIZE + REQUEST_OVERHEAD;
+
+/// Number of upcoming epochs whose per-contact derived keys a client precomputes in the
+/// background, so writes and reads don't run the KDF and PRF on the hot path.
+pub const PRECOMPUTE_EPOCHS: usize = 8;
+
+/// Most paths [`crate::client::Client::sync`] reads from Server2 in one request.
+pub const SYNC_BATCH_PATHS: usize = 64;
+
+/// Size of a Server1-issued write token in bytes, see [`crate::write_tokens`].
+pub const WRITE_TOKEN_SIZE: usize = 64;
+
+/// Number of epochs, starting with the current one, Server1 issues write tokens for in advance.
+pub const WRITE_TOKEN_EPOCHS: usize = 16;
+
+/// Size in bytes of the storage tag Server2 accounts a write token's blocks under.
+pub const STORAGE_TAG_SIZE: usize = 8;
+
+/// Number of storage tags with the most blocks listed in Server2's storage stats.
+pub const STORAGE_STATS_TOP: usize = 10;
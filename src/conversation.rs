@@ -0,0 +1,291 @@
+//! Conversation
+//!
+//! A [`Conversation`] is a reliable, ordered message stream with a single contact, built on top of
+//! a [`Client`]. Applications call [`Conversation::send`] with whole messages of any size and
+//! [`Conversation::recv`] once per epoch, and the conversation takes care of the rest:
+//!
+//! - Every epoch it writes exactly one frame, carrying the next fragment of a queued message, a
+//!   retransmission, or just an acknowledgement, so an idle conversation still provides cover
+//!   traffic.
+//! - Messages larger than an envelope are split into fragments and reassembled on the other side.
+//! - Frames carry a cumulative acknowledgement; fragments that stay unacknowledged are resent, and
+//!   received fragments are delivered in order, exactly once.
+//! - The contact keys are ratcheted every epoch, and each epoch's key is forgotten once used, so a
+//!   compromised client can't decrypt earlier epochs.
+//!
+//! Both sides must create the conversation in the same epoch and call `recv` once in every epoch,
+//! since a client's epoch only advances when one of its writes is accepted.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::Client,
+    constants::DELTA,
+    crypto::kdf,
+    dtypes::Key,
+    envelope::{ContentType, Envelope, MAX_PAYLOAD_SIZE},
+    error::MycoError,
+};
+
+/// Number of epochs after which an unacknowledged fragment is sent again.
+pub const RETRANSMIT_EPOCHS: usize = 4;
+
+/// Bytes a frame adds around the fragment it carries.
+const FRAME_OVERHEAD: usize = 8 + 2 + 1 + 8;
+
+/// Largest part of a message carried by a single frame.
+pub const FRAGMENT_SIZE: usize = MAX_PAYLOAD_SIZE - FRAME_OVERHEAD;
+
+/// Position of a fragment in the stream: message sequence number and fragment index.
+type Position = (u64, u16);
+
+/// What a conversation writes each epoch, as the payload of an envelope whose header describes
+/// the fragment, if any.
+#[derive(Debug, Serialize, Deserialize)]
+struct Frame {
+    /// Position of the next fragment the sender expects from its peer.
+    ack: Position,
+    /// The fragment, or `None` for a frame carrying only the acknowledgement.
+    data: Option<Vec<u8>>,
+}
+
+/// A message received in a conversation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// The sender's sequence number of the message.
+    pub sequence: u64,
+    /// What the payload holds.
+    pub content_type: ContentType,
+    /// The message.
+    pub payload: Vec<u8>,
+}
+
+/// A fragment waiting to be acknowledged.
+#[derive(Debug)]
+struct Pending {
+    envelope: Envelope,
+    /// Epoch the fragment was last sent in.
+    sent_at: Option<usize>,
+}
+
+/// A key ratcheted forward once per epoch.
+#[derive(Debug)]
+struct Ratchet {
+    /// Epoch the chain key belongs to.
+    epoch: usize,
+    chain_key: Key,
+}
+
+impl Ratchet {
+    fn new(root: &Key, epoch: usize) -> Self {
+        Self {
+            epoch,
+            chain_key: root.clone(),
+        }
+    }
+
+    /// Advance the chain to `epoch` and return the contact key for it. Earlier chain keys are
+    /// overwritten, so `epoch` must not be behind the chain.
+    fn key_for(&mut self, epoch: usize) -> Result<Key, MycoError> {
+        if epoch < self.epoch {
+            return Err(MycoError::ProtocolError(format!(
+                "ratchet already past epoch {}",
+                epoch
+            )));
+        }
+        while self.epoch < epoch {
+            self.chain_key = Key::new(kdf(&self.chain_key.0, "RATCHET")?);
+            self.epoch += 1;
+        }
+        Ok(Key::new(kdf(&self.chain_key.0, "EPOCH")?))
+    }
+}
+
+/// A reliable, ordered message stream with a single contact.
+pub struct Conversation {
+    client: Client,
+    /// The contact's client ID.
+    peer: String,
+    send_ratchet: Ratchet,
+    recv_ratchet: Ratchet,
+    /// Next epoch to read the contact's frame from.
+    next_read: usize,
+    /// Sequence number of the next message sent.
+    next_sequence: u64,
+    /// Fragments sent or waiting to be sent, until the contact acknowledges them.
+    outbox: VecDeque<Pending>,
+    /// Position of the next fragment expected from the contact.
+    expected: Position,
+    /// Fragments received ahead of `expected`.
+    received: BTreeMap<Position, Envelope>,
+    /// Fragments of the message being reassembled.
+    partial: Vec<Envelope>,
+}
+
+impl Conversation {
+    /// Start a conversation with `peer` in the client's current epoch. `send_key` protects the
+    /// messages this side writes and `recv_key` the ones the contact writes, so the contact must
+    /// pass them the other way round.
+    pub fn new(client: Client, peer: String, send_key: Key, recv_key: Key) -> Self {
+        let epoch = client.epoch;
+        Self {
+            client,
+            peer,
+            send_ratchet: Ratchet::new(&send_key, epoch),
+            recv_ratchet: Ratchet::new(&recv_key, epoch),
+            next_read: epoch,
+            next_sequence: 0,
+            outbox: VecDeque::new(),
+            expected: (0, 0),
+            received: BTreeMap::new(),
+            partial: Vec::new(),
+        }
+    }
+
+    /// The underlying client.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// End the conversation and return the underlying client.
+    pub fn into_client(self) -> Client {
+        self.client
+    }
+
+    /// Number of fragments not yet acknowledged by the contact.
+    pub fn unacknowledged(&self) -> usize {
+        self.outbox.len()
+    }
+
+    /// Queue a message to the contact. It is sent by the following calls to [`Conversation::recv`].
+    pub fn send(&mut self, payload: &[u8]) -> Result<(), MycoError> {
+        let sequence = self.next_sequence;
+        let fragments = Envelope::split_with_size(payload, ContentType::Binary, sequence, FRAGMENT_SIZE)?;
+        self.next_sequence += 1;
+        self.outbox
+            .extend(fragments.into_iter().map(|envelope| Pending {
+                envelope,
+                sent_at: None,
+            }));
+        Ok(())
+    }
+
+    /// Run one epoch of the conversation: read the contact's frames from the epochs since the last
+    /// call, then write this epoch's frame. Returns the messages completed, in order.
+    pub fn recv(&mut self) -> Result<Vec<Message>, MycoError> {
+        let mut messages = Vec::new();
+        let current = self.client.epoch;
+        // Frames older than DELTA epochs have expired from the tree.
+        let first = self.next_read.max(current.saturating_sub(DELTA));
+        for epoch in first..current {
+            if let Some(envelope) = self.read_frame(epoch)? {
+                messages.extend(self.receive(envelope)?);
+            }
+        }
+        self.next_read = current;
+        self.write_frame()?;
+        Ok(messages)
+    }
+
+    /// Read the contact's frame from `epoch`, if it wrote one.
+    fn read_frame(&mut self, epoch: usize) -> Result<Option<Envelope>, MycoError> {
+        let k = self.recv_ratchet.key_for(epoch)?;
+        self.client.add_contact(&k)?;
+        let epoch_past = self.client.epoch - 1 - epoch;
+        let result = self.client.read_envelope(&k, self.peer.clone(), epoch_past);
+        self.client.forget(&k);
+        match result {
+            Ok(envelope) => Ok(Some(envelope)),
+            Err(MycoError::NoMessageFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Process a frame from the contact, returning the messages it completes.
+    fn receive(&mut self, envelope: Envelope) -> Result<Vec<Message>, MycoError> {
+        let frame: Frame =
+            bincode::deserialize(&envelope.payload).map_err(|e| MycoError::DeserializationError(Some(e)))?;
+        while self
+            .outbox
+            .front()
+            .is_some_and(|pending| position(&pending.envelope) < frame.ack)
+        {
+            self.outbox.pop_front();
+        }
+
+        let Some(data) = frame.data else {
+            return Ok(Vec::new());
+        };
+        let at = position(&envelope);
+        if at >= self.expected {
+            self.received.insert(
+                at,
+                Envelope {
+                    header: envelope.header,
+                    payload: data,
+                },
+            );
+        }
+
+        let mut messages = Vec::new();
+        while let Some(fragment) = self.received.remove(&self.expected) {
+            let header = fragment.header;
+            self.partial.push(fragment);
+            if header.fragment.index + 1 < header.fragment.count {
+                self.expected = (header.sequence, header.fragment.index + 1);
+                continue;
+            }
+            let payload = Envelope::join(std::mem::take(&mut self.partial))?;
+            messages.push(Message {
+                sequence: header.sequence,
+                content_type: header.content_type,
+                payload,
+            });
+            self.expected = (header.sequence + 1, 0);
+        }
+        Ok(messages)
+    }
+
+    /// Write this epoch's frame: the first fragment due to be sent, or just the acknowledgement.
+    fn write_frame(&mut self) -> Result<(), MycoError> {
+        let epoch = self.client.epoch;
+        let due = self.outbox.iter_mut().find(|pending| {
+            pending
+                .sent_at
+                .is_none_or(|sent_at| epoch - sent_at >= RETRANSMIT_EPOCHS)
+        });
+        let (header, data) = match due {
+            Some(pending) => {
+                pending.sent_at = Some(epoch);
+                (pending.envelope.header, Some(pending.envelope.payload.clone()))
+            }
+            None => (Envelope::default().header, None),
+        };
+        let frame = Frame {
+            ack: self.expected,
+            data,
+        };
+        let envelope = Envelope {
+            header,
+            payload: bincode::serialize(&frame).map_err(|e| MycoError::SerializationFailed(Some(e)))?,
+        };
+
+        let k = self.send_ratchet.key_for(epoch)?;
+        self.client.add_contact(&k)?;
+        let result = self.client.write_envelope(&envelope, &k);
+        self.client.forget(&k);
+        match result {
+            // The frame landed in an epoch the contact won't read it from, e.g. after an earlier
+            // frame was rejected. The client has caught up with Server1, and the frame is resent
+            // like any lost one.
+            Err(MycoError::WriteEpochMismatch { .. }) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+fn position(envelope: &Envelope) -> Position {
+    (envelope.header.sequence, envelope.header.fragment.index)
+}
@@ -1,9 +1,18 @@
 //! Crypto helper functions
+//!
+//! [`self_test`] checks the primitives against known-answer vectors and the block layout against
+//! the size constants. The server and client binaries run it at startup, so a miscompiled build or
+//! inconsistent constants fail before any message is written under them.
 
-use ring::{digest, hkdf};
+use ring::{digest, hkdf, hmac};
+use crate::distributed::front_layer;
 use crate::error::MycoError;
-use crate::constants::{INNER_BLOCK_SIZE, MESSAGE_SIZE};
-use crate::utils::pad_message;
+use crate::constants::{
+    BLOCK_SIZE, COMMITMENT_SIZE, INNER_BLOCK_SIZE, KEY_HINT_SIZE, LAMBDA, MESSAGE_SIZE, NONCE_SIZE,
+    STORAGE_TAG_SIZE,
+};
+use crate::envelope::{Envelope, MAX_PAYLOAD_SIZE};
+use crate::utils::{pad, pad_message, Padding};
 use aes_gcm::aead::{AeadInPlace, KeyInit};
 use aes_gcm::{Aes128Gcm, Nonce};
 use rand::Rng;
@@ -63,6 +72,131 @@ pub fn prf(key: &[u8], input: &[u8]) -> Result<Vec<u8>, MycoError> {
     Ok(result)
 }
 
+/// Derives the per-epoch pseudonym a client writes under, sent to Server1 in place of its ID.
+///
+/// The pseudonym is keyed with the contact's PRF key, so a reader holding the contact key can
+/// derive it from the sender's ID, while Server1 can neither link a client's pseudonyms across
+/// epochs nor check a guessed ID against them.
+///
+/// # Arguments
+/// * `k_prf` - The contact's PRF key
+/// * `cs` - The writing client's ID bytes
+/// * `epoch` - The epoch the write is made in
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The 32-byte pseudonym
+/// * `Err(MycoError)` - If the PRF fails
+pub fn client_pseudonym(k_prf: &[u8], cs: &[u8], epoch: usize) -> Result<Vec<u8>, MycoError> {
+    prf(k_prf, &[&b"client-pseudonym"[..], &epoch.to_be_bytes(), cs].concat())
+}
+
+/// Derives a message location from a published epoch key, the client's PRF value and its
+/// pseudonym.
+///
+/// The epoch key is treated as a sequence of `LAMBDA / 8`-byte key shares applied as nested
+/// layers, innermost first. A regular single-share key reduces to `prf(k_s1_t, f || cs)`. A key
+/// published in distributed trust mode starts with the front S1's share, whose layer is the
+/// blinded evaluation of [`front_layer`], and each further share applies a PRF layer, in the same
+/// order the S1 instances evaluated them.
+///
+/// # Arguments
+/// * `epoch_key` - The published k_s1_t for the epoch, possibly made up of several shares
+/// * `f` - The client's PRF output for the epoch
+/// * `cs` - The client's pseudonym for the epoch, see [`client_pseudonym`]
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The 32-byte location value, to be converted into a `Path`
+/// * `Err(MycoError)` - If any PRF layer fails
+pub fn location_prf(epoch_key: &[u8], f: &[u8], cs: &[u8]) -> Result<Vec<u8>, MycoError> {
+    let mut shares = epoch_key.chunks(LAMBDA / 8);
+    let first = shares.next().unwrap_or_default();
+    if epoch_key.len() <= LAMBDA / 8 {
+        return prf(first, &[f, cs].concat());
+    }
+    shares.try_fold(front_layer(first, f, cs), |l, share| prf(share, &l))
+}
+
+/// Derives the tag Server2 accounts the blocks written under a write token to.
+///
+/// Server1 reports each epoch's block counts by tag rather than by token, so Server2 never holds
+/// a value Server1 accepts as a token. Tokens are per epoch, and so are the tags.
+///
+/// # Arguments
+/// * `token` - The client's write token for the epoch, see [`crate::write_tokens`]
+///
+/// # Returns
+/// * `Vec<u8>` - The `STORAGE_TAG_SIZE`-byte storage tag
+pub fn storage_tag(token: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, &[&b"storage-tag"[..], token].concat()).as_ref()
+        [..STORAGE_TAG_SIZE]
+        .to_vec()
+}
+
+/// Derives the key hint Server1 stores in front of a block it encrypted under `k_oblv`.
+///
+/// Readers compare the hint against their own before trial-decrypting a block, which skips the
+/// AEAD for all but a `2^-32` fraction of the blocks of other keys. The hint is a PRF of the
+/// block's fresh nonce, so it changes whenever the block is re-encrypted and looks random to
+/// anyone without `k_oblv`.
+///
+/// # Arguments
+/// * `k_oblv` - The oblivious key the block is encrypted under
+/// * `ciphertext` - The block's ciphertext, starting with its nonce
+///
+/// # Returns
+/// * `Vec<u8>` - The `KEY_HINT_SIZE`-byte hint
+pub fn key_hint(k_oblv: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, MycoError> {
+    let nonce = &ciphertext[..NONCE_SIZE.min(ciphertext.len())];
+    let mut hint = prf(k_oblv, &[&b"key-hint"[..], nonce].concat())?;
+    hint.truncate(KEY_HINT_SIZE);
+    Ok(hint)
+}
+
+/// Derives the address Server1 knows a mailbox by for one epoch.
+///
+/// The address is a hash of the values a write to the mailbox carries, so the mailbox owner can
+/// compute it ahead of the epoch while Server1 learns nothing before the first write arrives.
+///
+/// # Arguments
+/// * `f` - The PRF output of the contact for the epoch
+/// * `cs` - The writer's pseudonym for the epoch, see [`client_pseudonym`]
+///
+/// # Returns
+/// * `Vec<u8>` - The 32-byte mailbox address
+pub fn mailbox_address(f: &[u8], cs: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, &[&b"mailbox-address"[..], f, cs].concat())
+        .as_ref()
+        .to_vec()
+}
+
+/// Derives the key guarding a mailbox address from the access key shared with approved writers.
+///
+/// Server1 is given only the derived key, which is specific to one address and therefore useless
+/// for any other epoch or mailbox.
+///
+/// # Arguments
+/// * `k_access` - The access key the mailbox owner shares with approved writers
+/// * `address` - The mailbox address, see [`mailbox_address`]
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The 32-byte guard key
+/// * `Err(MycoError)` - If the PRF fails
+pub fn mailbox_guard_key(k_access: &[u8], address: &[u8]) -> Result<Vec<u8>, MycoError> {
+    prf(k_access, &[&b"mailbox-guard"[..], address].concat())
+}
+
+/// Computes the access tag a write to a guarded mailbox carries.
+///
+/// # Arguments
+/// * `guard_key` - The guard key of the mailbox address, see [`mailbox_guard_key`]
+/// * `ct` - The ciphertext being written
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The 32-byte access tag
+/// * `Err(MycoError)` - If the PRF fails
+pub fn mailbox_access_tag(guard_key: &[u8], ct: &[u8]) -> Result<Vec<u8>, MycoError> {
+    prf(guard_key, &[&b"mailbox-access"[..], ct].concat())
+}
 
 /// An enum representing the type of encryption to perform
 #[derive(Debug)]
@@ -74,7 +208,27 @@ pub enum EncryptionType {
 }
 
 
-/// Encrypt a padded message using AES-GCM encryption
+/// Derive the AES-GCM key and the key commitment of an encryption under `key` with `nonce`.
+///
+/// Both are HMAC-SHA256 outputs keyed with `key`, so a ciphertext whose commitment checks out
+/// under two different keys requires an HMAC collision. This makes the construction
+/// key-committing, which AES-GCM alone is not.
+fn committed_key(key: &[u8], nonce: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let enc_key = hmac::sign(&key, &[&b"myco-aead-key"[..], nonce].concat());
+    let commitment = hmac::sign(&key, &[&b"myco-aead-commitment"[..], nonce].concat());
+    (
+        enc_key.as_ref()[..LAMBDA / 8].to_vec(),
+        commitment.as_ref()[..COMMITMENT_SIZE].to_vec(),
+    )
+}
+
+/// Encrypt a padded message using key-committing AES-GCM encryption
+///
+/// The ciphertext is `nonce || commitment || AES-GCM(k_enc, message)`, where `k_enc` and the
+/// commitment are derived from `key` and the nonce (see [`committed_key`]). Reads trial-decrypt
+/// blocks under many keys, and the commitment keeps a crafted block from decrypting validly under
+/// more than one of them.
 ///
 /// # Arguments
 /// * `key` - The encryption key
@@ -88,63 +242,114 @@ pub fn encrypt(
     message: &[u8],
     encryption_type: EncryptionType,
 ) -> Result<Vec<u8>, MycoError> {
-    // Get the appropriate padding size based on encryption type
+    let nonce_bytes = rand::thread_rng().gen::<[u8; NONCE_SIZE]>();
+    encrypt_with_nonce(key, &nonce_bytes, pad_plaintext(message, encryption_type))
+}
+
+/// Encrypt an already padded `buffer` as [`encrypt`] does, under the given nonce
+pub(crate) fn encrypt_with_nonce(
+    key: &[u8],
+    nonce_bytes: &[u8; NONCE_SIZE],
+    mut buffer: Vec<u8>,
+) -> Result<Vec<u8>, MycoError> {
+    if key.is_empty() {
+        return Err(MycoError::EncryptionFailed);
+    }
+    let nonce = Nonce::from_slice(nonce_bytes);
+    let (enc_key, commitment) = committed_key(key, nonce_bytes);
+    let cipher = Aes128Gcm::new_from_slice(&enc_key)
+        .map_err(|_| MycoError::EncryptionFailed)?;
+
+    cipher
+        .encrypt_in_place(nonce, b"", &mut buffer)
+        .map_err(|_| MycoError::EncryptionFailed)?;
+
+    Ok([nonce.as_slice(), &commitment, buffer.as_slice()].concat())
+}
+
+
+/// Pad a message to the plaintext size [`encrypt`] uses for `encryption_type`
+pub fn pad_plaintext(message: &[u8], encryption_type: EncryptionType) -> Vec<u8> {
     let padding_size = match encryption_type {
         EncryptionType::Encrypt => MESSAGE_SIZE,
         EncryptionType::DoubleEncrypt => INNER_BLOCK_SIZE,
     };
-
-    // Use cfg_if to handle the different compilation features
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "no-enc")] {
-            // In no-enc mode, just pad the message and return it
-            Ok(pad_message(message, padding_size))
-        } else {
-            // Full encryption implementation
-            {
-                let cipher = Aes128Gcm::new_from_slice(key)
-                    .map_err(|_| MycoError::EncryptionFailed)?;
-                
-                let nonce_bytes = rand::thread_rng().gen::<[u8; 12]>();
-                let nonce = Nonce::from_slice(&nonce_bytes);
-                
-                let mut buffer = pad_message(message, padding_size);
-                
-                cipher
-                    .encrypt_in_place(nonce, b"", &mut buffer)
-                    .map_err(|_| MycoError::EncryptionFailed)?;
-                
-                Ok([nonce.as_slice(), buffer.as_slice()].concat())
-            }
-        }
-    }
+    pad_message(message, padding_size)
 }
 
-/// Decrypt a ciphertext
+/// Decrypt a ciphertext produced by [`encrypt`], rejecting it unless it commits to `key`
 pub fn decrypt(key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, MycoError> {
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "no-enc")] {
-            // In no-enc mode, just return the input
-            Ok(ciphertext.to_vec())
-        } else {
-            {
-                if ciphertext.len() < 12 {
-                    return Err(MycoError::NoMessageFound);
-                }
-
-                let cipher = Aes128Gcm::new_from_slice(key)
-                    .map_err(|_| MycoError::NoMessageFound)?;
-                
-                let (nonce, ciphertext) = ciphertext.split_at(12);
-                let nonce = Nonce::from_slice(nonce);
-                
-                let mut buffer = Vec::from(ciphertext);
-                cipher
-                    .decrypt_in_place(nonce, b"", &mut buffer)
-                    .map_err(|_| MycoError::NoMessageFound)?;
-                
-                Ok(buffer)
-            }
-        }
+    if ciphertext.len() < NONCE_SIZE + COMMITMENT_SIZE || key.is_empty() {
+        return Err(MycoError::DecryptionFailed);
     }
-}
\ No newline at end of file
+
+    let (nonce, ciphertext) = ciphertext.split_at(NONCE_SIZE);
+    let (commitment, ciphertext) = ciphertext.split_at(COMMITMENT_SIZE);
+    let (enc_key, expected) = committed_key(key, nonce);
+    // Blocks of other keys are turned away here, before any AES work.
+    ring::constant_time::verify_slices_are_equal(commitment, &expected)
+        .map_err(|_| MycoError::DecryptAuthFailed)?;
+
+    let cipher = Aes128Gcm::new_from_slice(&enc_key)
+        .map_err(|_| MycoError::DecryptionFailed)?;
+    let nonce = Nonce::from_slice(nonce);
+
+    let mut buffer = Vec::from(ciphertext);
+    cipher
+        .decrypt_in_place(nonce, b"", &mut buffer)
+        .map_err(|_| MycoError::DecryptAuthFailed)?;
+
+    Ok(buffer)
+}
+
+/// Key of the known-answer vectors of [`self_test`].
+const KAT_KEY: [u8; 16] = [0x0b; 16];
+
+/// Nonce of the AEAD known-answer vector of [`self_test`].
+const KAT_NONCE: [u8; NONCE_SIZE] = [0x0c; NONCE_SIZE];
+
+/// Input of the known-answer vectors of [`self_test`].
+const KAT_INPUT: &str = "myco self-test";
+
+/// `kdf(KAT_KEY, KAT_INPUT)`.
+const KAT_KDF: &str = "8f95a231537250dbe7414d0403df9ae1";
+
+/// `prf(KAT_KEY, KAT_INPUT)`.
+const KAT_PRF: &str = "8f95a231537250dbe7414d0403df9ae1dcdcf4329f5a96a470b633f7148fc1cf";
+
+/// The encryption of `KAT_INPUT` under `KAT_KEY` with `KAT_NONCE`, unpadded.
+const KAT_AEAD: &str = "0c0c0c0c0c0c0c0c0c0c0c0cfb03f5130cd46f99e782833ecdb8a64797117215cc327ab19b\
+                        0695eb60d86fad992da540dc1d39c3b6f1f5cc7d7c618147184709eb9ae0ce2a06a6576d85";
+
+/// Check the KDF, PRF and AEAD against known-answer vectors, and that a full-size message
+/// encrypts to exactly [`INNER_BLOCK_SIZE`] and then [`BLOCK_SIZE`] bytes.
+///
+/// # Returns
+/// * `Ok(())` - If every check passes
+/// * `Err(MycoError::SelfTestFailed)` - Naming the first check that failed
+pub fn self_test() -> Result<(), MycoError> {
+    let check = |name: &str, ok: bool| {
+        ok.then_some(())
+            .ok_or_else(|| MycoError::SelfTestFailed(name.to_string()))
+    };
+    let matches = |value: &[u8], expected: &str| hex::encode(value) == expected;
+
+    check("kdf", matches(&kdf(&KAT_KEY, KAT_INPUT)?, KAT_KDF))?;
+    check("prf", matches(&prf(&KAT_KEY, KAT_INPUT.as_bytes())?, KAT_PRF))?;
+    let ct = encrypt_with_nonce(&KAT_KEY, &KAT_NONCE, KAT_INPUT.as_bytes().to_vec())?;
+    check("aead encrypt", matches(&ct, KAT_AEAD))?;
+    check("aead decrypt", decrypt(&KAT_KEY, &ct)? == KAT_INPUT.as_bytes())?;
+    let mut tampered = ct;
+    *tampered.last_mut().unwrap() ^= 1;
+    check("aead authentication", decrypt(&KAT_KEY, &tampered).is_err())?;
+
+    // The largest envelope fills a message, which Server1 wraps into exactly one block.
+    let envelope = Envelope::new(vec![0; MAX_PAYLOAD_SIZE]).encode()?;
+    let message = pad(&envelope, MESSAGE_SIZE, Padding::default())?;
+    check("message size", message.len() == MESSAGE_SIZE)?;
+    let inner = encrypt(&KAT_KEY, &message, EncryptionType::Encrypt)?;
+    check("inner block size", inner.len() == INNER_BLOCK_SIZE)?;
+    let outer = encrypt(&KAT_KEY, &inner, EncryptionType::DoubleEncrypt)?;
+    check("block size", KEY_HINT_SIZE + outer.len() == BLOCK_SIZE)?;
+    check("block round trip", decrypt(&KAT_KEY, &decrypt(&KAT_KEY, &outer)?)? == message)
+}
@@ -0,0 +1,197 @@
+//! Multiple devices per identity
+//!
+//! A user's devices share one [`Identity`]: the client ID and the contact keys. Read paths only
+//! depend on those and the epoch, so every device holding the identity derives the same paths and
+//! can read the user's messages. A [`ReadDuty`] decides whether each device reads every contact or
+//! the devices divide the contacts between them, so the user's reads aren't multiplied by the
+//! number of devices.
+//!
+//! A new device is linked with a [`DeviceLink`]: the existing device shows a [`LinkCode`] out of
+//! band (e.g. as a QR code), and then sends the identity to the new device in a [`Conversation`]
+//! keyed by the code, so the identity itself never leaves Myco.
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::Client,
+    conversation::Conversation,
+    crypto::{kdf, prf},
+    dtypes::Key,
+    error::MycoError,
+};
+
+/// What devices sharing an identity have in common.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    /// The client ID the devices write and are read under.
+    pub id: String,
+    /// The contact keys.
+    pub contacts: Vec<Key>,
+}
+
+impl Identity {
+    /// The identity of `client`.
+    pub fn of(client: &Client) -> Self {
+        let mut contacts: Vec<Key> = client.keys.keys().cloned().collect();
+        contacts.sort_by(|a, b| a.0.cmp(&b.0));
+        Self {
+            id: client.id.clone(),
+            contacts,
+        }
+    }
+
+    /// Give `client` this identity, setting up every contact it doesn't have yet.
+    pub fn apply(&self, client: &mut Client) -> Result<(), MycoError> {
+        client.id = self.id.clone();
+        for k in &self.contacts {
+            if !client.keys.contains_key(k) {
+                client.setup(k)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which contacts a device reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadDuty {
+    /// Read every contact.
+    All,
+    /// Read the share of the contacts assigned to device `index` out of `devices`.
+    Share {
+        /// Index of this device, from 0.
+        index: usize,
+        /// Number of devices dividing the contacts.
+        devices: usize,
+    },
+}
+
+impl ReadDuty {
+    /// Whether the device reads contact `k`. Every device assigns contacts the same way, so each
+    /// contact is read by exactly one device sharing the work.
+    pub fn reads(&self, k: &Key) -> Result<bool, MycoError> {
+        match *self {
+            ReadDuty::All => Ok(true),
+            ReadDuty::Share { index, devices } => {
+                if index >= devices {
+                    return Err(MycoError::ConfigError(format!(
+                        "device {} out of {} devices",
+                        index, devices
+                    )));
+                }
+                let hash = prf(&k.0, b"READ-DUTY")?;
+                let bucket = u64::from_be_bytes(hash[..8].try_into().unwrap());
+                Ok(bucket % devices as u64 == index as u64)
+            }
+        }
+    }
+
+    /// The contacts of `client` the device reads.
+    pub fn contacts(&self, client: &Client) -> Result<Vec<Key>, MycoError> {
+        let mut contacts = Vec::new();
+        for k in client.keys.keys() {
+            if self.reads(k)? {
+                contacts.push(k.clone());
+            }
+        }
+        contacts.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(contacts)
+    }
+}
+
+/// What the existing device shows the new one to link it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkCode {
+    /// The identity's client ID.
+    pub id: String,
+    /// Epoch the link starts in.
+    pub epoch: usize,
+    /// Secret the link's conversation keys are derived from.
+    pub secret: Key,
+}
+
+impl LinkCode {
+    /// Keys the existing device sends and receives under.
+    fn keys(&self) -> Result<(Key, Key), MycoError> {
+        Ok((
+            Key::new(kdf(&self.secret.0, "LINK-EXISTING")?),
+            Key::new(kdf(&self.secret.0, "LINK-NEW")?),
+        ))
+    }
+}
+
+/// One side of a device-linking handshake.
+pub struct DeviceLink {
+    conversation: Conversation,
+    /// Whether this is the new device, waiting for the identity.
+    new_device: bool,
+    /// The identity, once the new device has received it.
+    identity: Option<Identity>,
+}
+
+impl DeviceLink {
+    /// Start linking a new device to `client`'s identity. Returns the code to show the new device,
+    /// which must accept it within the same epoch.
+    pub fn offer(client: Client) -> Result<(LinkCode, Self), MycoError> {
+        let code = LinkCode {
+            id: client.id.clone(),
+            epoch: client.epoch,
+            secret: Key::random(&mut ChaCha20Rng::from_entropy()),
+        };
+        let identity = bincode::serialize(&Identity::of(&client))
+            .map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+        let (send_key, recv_key) = code.keys()?;
+        let mut conversation = Conversation::new(client, code.id.clone(), send_key, recv_key);
+        conversation.send(&identity)?;
+        Ok((
+            code,
+            Self {
+                conversation,
+                new_device: false,
+                identity: None,
+            },
+        ))
+    }
+
+    /// Link `client`, a new device, to the identity the code was shown for.
+    pub fn accept(mut client: Client, code: &LinkCode) -> Result<Self, MycoError> {
+        client.id = code.id.clone();
+        client.epoch = code.epoch;
+        let (recv_key, send_key) = code.keys()?;
+        Ok(Self {
+            conversation: Conversation::new(client, code.id.clone(), send_key, recv_key),
+            new_device: true,
+            identity: None,
+        })
+    }
+
+    /// Run one epoch of the handshake. Returns whether linking is done on this side: on the
+    /// existing device once the new one has acknowledged the identity, and on the new device once
+    /// it has received it.
+    pub fn step(&mut self) -> Result<bool, MycoError> {
+        let messages = self.conversation.recv()?;
+        if !self.new_device {
+            return Ok(self.conversation.unacknowledged() == 0);
+        }
+        if let Some(message) = messages.into_iter().next() {
+            self.identity = Some(
+                bincode::deserialize(&message.payload)
+                    .map_err(|e| MycoError::DeserializationError(Some(e)))?,
+            );
+        }
+        Ok(self.identity.is_some())
+    }
+
+    /// Finish linking and return the client, with the identity applied on the new device.
+    pub fn into_client(self) -> Result<Client, MycoError> {
+        let mut client = self.conversation.into_client();
+        if let Some(identity) = self.identity {
+            identity.apply(&mut client)?;
+        } else if self.new_device {
+            return Err(MycoError::ProtocolError("device link not complete".to_string()));
+        }
+        Ok(client)
+    }
+}
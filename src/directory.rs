@@ -0,0 +1,304 @@
+//! Key directory
+//!
+//! Contact keys are symmetric and have to be agreed on before two clients can talk. Instead of
+//! exchanging them by hand, clients can publish a [`PrekeyBundle`] to a [`KeyDirectory`] and derive
+//! the contact key for anyone whose bundle they look up: each bundle carries an X25519 prekey
+//! signed by the owner's Ed25519 identity key, and both sides of a contact compute the same key
+//! from their own prekey secret and the other's prekey.
+//!
+//! The directory only has to serve bundles; it never sees a contact key. Deployments with an
+//! existing PKI implement [`KeyDirectory`] on top of it. [`HttpKeyDirectory`] is a reference client
+//! for a directory served over HTTP, and [`router`] serves a [`MemoryKeyDirectory`] with the same
+//! endpoints:
+//!
+//! - `GET /bundles/:id` returns the bincoded bundle published for `id`, or 404.
+//! - `PUT /bundles/:id` publishes a bincoded bundle. Bundles with an invalid signature are rejected
+//!   with 400, and bundles changing the identity key already published for `id` with 409.
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    routing::get,
+    Router,
+};
+use dashmap::DashMap;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{
+    client::Client,
+    crypto::kdf,
+    dtypes::Key,
+    error::MycoError,
+    hardening::{self, BINCODE_CONTENT_TYPE},
+    tls::TlsTrust,
+};
+
+/// Domain separator of the prekey signature.
+const PREKEY_CONTEXT: &[u8] = b"myco-prekey";
+
+/// A client's published keys.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrekeyBundle {
+    /// The client ID the bundle belongs to.
+    pub id: String,
+    /// Ed25519 identity key.
+    pub identity_key: [u8; 32],
+    /// X25519 prekey contact keys are derived from.
+    pub prekey: [u8; 32],
+    /// Signature of the ID and prekey by the identity key.
+    pub signature: Vec<u8>,
+}
+
+impl PrekeyBundle {
+    fn signed_message(id: &str, prekey: &[u8; 32]) -> Vec<u8> {
+        [
+            PREKEY_CONTEXT,
+            &(id.len() as u64).to_be_bytes(),
+            id.as_bytes(),
+            prekey,
+        ]
+        .concat()
+    }
+
+    /// Check that the prekey is signed by the identity key.
+    pub fn verify(&self) -> Result<(), MycoError> {
+        let invalid = || MycoError::ProtocolError(format!("invalid prekey bundle for {}", self.id));
+        let identity_key = VerifyingKey::from_bytes(&self.identity_key).map_err(|_| invalid())?;
+        let signature = Signature::from_slice(&self.signature).map_err(|_| invalid())?;
+        identity_key
+            .verify_strict(&Self::signed_message(&self.id, &self.prekey), &signature)
+            .map_err(|_| invalid())
+    }
+}
+
+/// A client's secret identity and prekey.
+pub struct IdentityKeys {
+    id: String,
+    identity_key: SigningKey,
+    prekey: StaticSecret,
+}
+
+impl IdentityKeys {
+    /// Generate fresh keys for client `id`.
+    pub fn generate(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            identity_key: SigningKey::generate(&mut OsRng),
+            prekey: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    /// The bundle to publish.
+    pub fn bundle(&self) -> PrekeyBundle {
+        let prekey = PublicKey::from(&self.prekey).to_bytes();
+        let signature = self
+            .identity_key
+            .sign(&PrekeyBundle::signed_message(&self.id, &prekey));
+        PrekeyBundle {
+            id: self.id.clone(),
+            identity_key: self.identity_key.verifying_key().to_bytes(),
+            prekey,
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+
+    /// Derive the contact key shared with the owner of `bundle`. The owner derives the same key
+    /// from this client's bundle.
+    pub fn contact_key(&self, bundle: &PrekeyBundle) -> Result<Key, MycoError> {
+        bundle.verify()?;
+        let shared = self.prekey.diffie_hellman(&PublicKey::from(bundle.prekey));
+        if !shared.was_contributory() {
+            return Err(MycoError::ProtocolError(format!(
+                "non-contributory prekey for {}",
+                bundle.id
+            )));
+        }
+        let (first, second) = if self.id <= bundle.id {
+            (&self.id, &bundle.id)
+        } else {
+            (&bundle.id, &self.id)
+        };
+        Ok(Key::new(kdf(
+            shared.as_bytes(),
+            &format!("CONTACT {} {} {}", first.len(), first, second),
+        )?))
+    }
+}
+
+/// Where clients publish and look up prekey bundles.
+#[async_trait]
+pub trait KeyDirectory: Send + Sync {
+    /// Look up the bundle published for client `id`.
+    async fn lookup(&self, id: &str) -> Result<PrekeyBundle, MycoError>;
+    /// Publish a bundle under its client ID.
+    async fn publish(&self, bundle: &PrekeyBundle) -> Result<(), MycoError>;
+}
+
+/// Look up `contact` in `directory`, derive the contact key and set `client` up with it.
+pub async fn add_contact(
+    client: &mut Client,
+    keys: &IdentityKeys,
+    directory: &dyn KeyDirectory,
+    contact: &str,
+) -> Result<Key, MycoError> {
+    let bundle = directory.lookup(contact).await?;
+    if bundle.id != contact {
+        return Err(MycoError::ProtocolError(format!(
+            "directory returned the bundle of {} for {}",
+            bundle.id, contact
+        )));
+    }
+    let k = keys.contact_key(&bundle)?;
+    client.setup(&k)?;
+    Ok(k)
+}
+
+/// A key directory kept in memory. Once an identity key is published for an ID, only bundles with
+/// the same identity key are accepted for it.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryKeyDirectory {
+    bundles: Arc<DashMap<String, PrekeyBundle>>,
+}
+
+#[async_trait]
+impl KeyDirectory for MemoryKeyDirectory {
+    async fn lookup(&self, id: &str) -> Result<PrekeyBundle, MycoError> {
+        self.bundles
+            .get(id)
+            .map(|bundle| bundle.clone())
+            .ok_or_else(|| MycoError::ProtocolError(format!("no prekey bundle for {}", id)))
+    }
+
+    async fn publish(&self, bundle: &PrekeyBundle) -> Result<(), MycoError> {
+        bundle.verify()?;
+        let mut entry = self
+            .bundles
+            .entry(bundle.id.clone())
+            .or_insert_with(|| bundle.clone());
+        if entry.identity_key != bundle.identity_key {
+            return Err(MycoError::ProtocolError(format!(
+                "identity key of {} cannot be replaced",
+                bundle.id
+            )));
+        }
+        *entry = bundle.clone();
+        Ok(())
+    }
+}
+
+/// Client for a key directory served over HTTP.
+pub struct HttpKeyDirectory {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpKeyDirectory {
+    /// Create a client for the directory at `base_url`, verifying its certificate according to
+    /// `trust`.
+    pub fn new(base_url: &str, trust: &TlsTrust) -> Result<Self, MycoError> {
+        let (builder, base_url) = trust.http_client_builder(base_url)?;
+        let client = builder
+            .build()
+            .map_err(|e| MycoError::network("failed to create HTTP client", e))?;
+        Ok(Self { client, base_url })
+    }
+
+    fn url(&self, id: &str) -> Result<reqwest::Url, MycoError> {
+        let invalid = || MycoError::ConfigError(format!("invalid directory URL {}", self.base_url));
+        let mut url = reqwest::Url::parse(&self.base_url).map_err(|_| invalid())?;
+        url.path_segments_mut()
+            .map_err(|_| invalid())?
+            .pop_if_empty()
+            .extend(["bundles", id]);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl KeyDirectory for HttpKeyDirectory {
+    async fn lookup(&self, id: &str) -> Result<PrekeyBundle, MycoError> {
+        let response = self
+            .client
+            .get(self.url(id)?)
+            .send()
+            .await
+            .map_err(|e| MycoError::network("directory lookup", e))?;
+        if !response.status().is_success() {
+            return Err(MycoError::ProtocolError(format!(
+                "directory lookup of {} returned {}",
+                id,
+                response.status()
+            )));
+        }
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| MycoError::network("directory lookup", e))?;
+        bincode::deserialize(&bytes).map_err(|e| MycoError::DeserializationError(Some(e)))
+    }
+
+    async fn publish(&self, bundle: &PrekeyBundle) -> Result<(), MycoError> {
+        let body = bincode::serialize(bundle).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+        let response = self
+            .client
+            .put(self.url(&bundle.id)?)
+            .header("Content-Type", BINCODE_CONTENT_TYPE)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| MycoError::network("directory publish", e))?;
+        if !response.status().is_success() {
+            return Err(MycoError::ProtocolError(format!(
+                "directory publish of {} returned {}",
+                bundle.id,
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Build the router of the reference directory.
+pub fn router() -> Router<MemoryKeyDirectory> {
+    Router::new().route("/bundles/:id", get(handle_lookup).put(handle_publish))
+}
+
+/// Return the bundle published for an ID.
+pub async fn handle_lookup(
+    State(directory): State<MemoryKeyDirectory>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Bytes, StatusCode> {
+    let bundle = directory
+        .lookup(&id)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    bincode::serialize(&bundle)
+        .map(Bytes::from)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Publish a bundle under the ID in the URL.
+pub async fn handle_publish(
+    State(directory): State<MemoryKeyDirectory>,
+    UrlPath(id): UrlPath<String>,
+    bytes: Bytes,
+) -> StatusCode {
+    let bundle: PrekeyBundle = match hardening::decode(&bytes) {
+        Ok(bundle) => bundle,
+        Err(err) => return err.status(),
+    };
+    if bundle.id != id || bundle.verify().is_err() {
+        return StatusCode::BAD_REQUEST;
+    }
+    match directory.publish(&bundle).await {
+        Ok(()) => StatusCode::OK,
+        Err(_) => StatusCode::CONFLICT,
+    }
+}
@@ -0,0 +1,418 @@
+//! Distributed trust mode
+//!
+//! In the standard deployment a single S1 holds k_s1_t and evaluates `prf(k_s1_t, f || cs)` for
+//! every write, so it knows exactly which tree location each client's message is headed for. This
+//! research mode splits the location key and the knowledge needed to use it across two S1
+//! instances that must both participate in every epoch:
+//!
+//! - The *front* S1 ([`FrontServer1`]) is the only instance clients talk to, so it knows who wrote
+//!   what. It holds a per-epoch key share `k_front`, which it only ever applies blindly: a client
+//!   hashes `f || cs` to a Ristretto255 point `P`, sends `r · P` ([`LocationRequest`]) and strips
+//!   `r` off the front's answer, leaving `x = H(k_front · P)`. The client seals `x` and its
+//!   oblivious key to the back S1 ([`seal_location`]), so the front forwards writes whose location
+//!   input it never sees. At the end of the epoch it shuffles them and passes them on without
+//!   client IDs.
+//! - The *back* S1 is a regular [`Server1`](crate::server1::Server1) behind a
+//!   [`LocalBackServer1Access`], which opens the sealed writes. Its k_s1_t acts as the outer layer,
+//!   `l = prf(k_back, x)`, and it performs the batch write as usual. It learns where each write
+//!   goes, but not who made it: the writes arrive shuffled, without client IDs, under blind write
+//!   tokens (see [`crate::write_tokens`]) and with per-epoch pseudonyms folded into `x`.
+//!
+//! Neither instance can map a write to its writer and location, during the epoch or after it.
+//! Once the front has forwarded its writes it releases `k_front` to the back, which publishes
+//! `k_front || k_back` to S2 so readers can compute `l` from `f || cs` with
+//! [`location_prf`](crate::crypto::location_prf). Anyone can read the published key, the front
+//! included, but computing a location from it takes `f`, which only the two ends of a contact hold:
+//! the front saw nothing of the writes it received but blinded points, sealed boxes and
+//! ciphertexts. The back holds locations and `x` values, but no client IDs to link them to. The
+//! split holds against either instance on its own that follows the protocol; a front evaluating
+//! some clients' requests under a different key, or handing them a substitute sealing key, can
+//! tell their writes apart, so clients must get the back's sealing key from the back itself.
+//!
+//! Mailbox guards (see [`crate::mailbox`]) are enforced by the front on the mailbox address the
+//! client sends along, a hash of `f || cs` that doesn't help with the location. Write tokens are
+//! issued and checked by the back, which holds the registrations; the front passes token requests
+//! and tokens on.
+
+use std::sync::{Arc, Mutex, RwLock};
+
+use axum::async_trait;
+use curve25519_dalek::{ristretto::CompressedRistretto, RistrettoPoint, Scalar};
+use rand::{seq::SliceRandom, CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use sha2::Sha512;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{
+    constants::{LAMBDA, NONCE_SIZE},
+    crypto::{decrypt, encrypt_with_nonce, kdf},
+    dtypes::Key,
+    error::MycoError,
+    logging::LatencyMetric,
+    mailbox::{MailboxGuard, MailboxGuards},
+    network::Server1Access,
+    registration::AccountCredentials,
+    server1::Server1,
+};
+
+/// Size of a location input `x` in bytes.
+const LOCATION_INPUT_SIZE: usize = 32;
+
+/// The point a client's location input `f || cs` is hashed to.
+fn location_point(f: &[u8], cs: &[u8]) -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(&[&b"myco-location"[..], f, cs].concat())
+}
+
+/// The scalar the front applies for key share `k_front`.
+fn location_scalar(k_front: &[u8]) -> Scalar {
+    Scalar::hash_from_bytes::<Sha512>(&[&b"myco-location-key"[..], k_front].concat())
+}
+
+/// The location input `x = H(k_front · P)` for an evaluated point `k_front · P`.
+fn location_input(evaluated: &RistrettoPoint) -> Vec<u8> {
+    digest::digest(
+        &digest::SHA256,
+        &[&b"myco-location-input"[..], evaluated.compress().as_bytes()].concat(),
+    )
+    .as_ref()
+    .to_vec()
+}
+
+/// Decompress a point sent by the other side, rejecting the identity.
+fn decompress(bytes: &[u8]) -> Option<RistrettoPoint> {
+    CompressedRistretto::from_slice(bytes)
+        .ok()?
+        .decompress()
+        .filter(|point| *point != RistrettoPoint::default())
+}
+
+/// The front's layer of the location of `f || cs` under its published share `k_front`, as
+/// [`location_prf`](crate::crypto::location_prf) computes it for readers.
+pub fn front_layer(k_front: &[u8], f: &[u8], cs: &[u8]) -> Vec<u8> {
+    location_input(&(location_scalar(k_front) * location_point(f, cs)))
+}
+
+/// A client's blinded request for the front's layer of its location, kept until the front has
+/// evaluated it.
+pub struct LocationRequest {
+    point: RistrettoPoint,
+    blind: Scalar,
+}
+
+impl LocationRequest {
+    /// A request for the front's layer of the location of `f || cs`.
+    pub fn new<R: RngCore + CryptoRng>(f: &[u8], cs: &[u8], rng: &mut R) -> Self {
+        Self {
+            point: location_point(f, cs),
+            blind: Scalar::random(rng),
+        }
+    }
+
+    /// The blinded point to send to the front S1.
+    pub fn blinded(&self) -> Vec<u8> {
+        (self.blind * self.point).compress().to_bytes().to_vec()
+    }
+
+    /// Unblind the front's answer into the location input `x`.
+    pub fn finish(self, evaluated: &[u8]) -> Result<Vec<u8>, MycoError> {
+        let evaluated = decompress(evaluated).ok_or_else(|| {
+            MycoError::ProtocolError("invalid location evaluation from the front S1".to_string())
+        })?;
+        Ok(location_input(&(self.blind.invert() * evaluated)))
+    }
+}
+
+/// Seal the location input `x` and oblivious key `k_oblv_t` of a write to the back S1's sealing
+/// key, so the front S1 forwards them without seeing either.
+pub fn seal_location(back_key: &PublicKey, x: &[u8], k_oblv_t: &Key) -> Result<Vec<u8>, MycoError> {
+    let mut rng = ChaCha20Rng::from_entropy();
+    let ephemeral = StaticSecret::random_from_rng(&mut rng);
+    let shared = ephemeral.diffie_hellman(back_key);
+    if !shared.was_contributory() {
+        return Err(MycoError::ConfigError("non-contributory back S1 sealing key".to_string()));
+    }
+    let key = kdf(shared.as_bytes(), "myco-sealed-location")?;
+    let mut nonce = [0; NONCE_SIZE];
+    rng.fill_bytes(&mut nonce);
+    let sealed = encrypt_with_nonce(&key, &nonce, [x, &k_oblv_t.0[..]].concat())?;
+    Ok([PublicKey::from(&ephemeral).as_bytes(), &sealed[..]].concat())
+}
+
+/// Open a box made by [`seal_location`] into the location input and oblivious key.
+fn open_location(sealing_key: &StaticSecret, sealed: &[u8]) -> Result<(Vec<u8>, Key), MycoError> {
+    let malformed = || MycoError::MalformedRequest("invalid sealed location".to_string());
+    if sealed.len() < 32 {
+        return Err(malformed());
+    }
+    let (ephemeral, sealed) = sealed.split_at(32);
+    let ephemeral: [u8; 32] = ephemeral.try_into().map_err(|_| malformed())?;
+    let shared = sealing_key.diffie_hellman(&PublicKey::from(ephemeral));
+    let key = kdf(shared.as_bytes(), "myco-sealed-location")?;
+    let opened = decrypt(&key, sealed).map_err(|_| malformed())?;
+    if opened.len() != LOCATION_INPUT_SIZE + LAMBDA / 8 {
+        return Err(malformed());
+    }
+    let (x, k_oblv_t) = opened.split_at(LOCATION_INPUT_SIZE);
+    Ok((x.to_vec(), Key::new(k_oblv_t.to_vec())))
+}
+
+/// A write in distributed trust mode, as a client hands it to the front S1.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SealedWrite {
+    /// The front epoch whose key share the location input was evaluated under.
+    pub epoch: u64,
+    /// The message ciphertext.
+    pub ct: Vec<u8>,
+    /// The location input and oblivious key, see [`seal_location`].
+    pub sealed: Vec<u8>,
+    /// The mailbox address, see [`crate::crypto::mailbox_address`]. Dropped by the front.
+    pub address: Vec<u8>,
+    /// The write token for the epoch.
+    pub token: Vec<u8>,
+    /// The access tag, if the mailbox is guarded. Dropped by the front.
+    pub access_tag: Option<Vec<u8>>,
+}
+
+/// A write awaiting forwarding: (ct, sealed, token).
+type QueuedWrite = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+/// The client-facing S1 instance in distributed trust mode.
+pub struct FrontServer1 {
+    /// The current epoch of the server.
+    pub epoch: u64,
+    /// This instance's key share for the current epoch.
+    k_share: Key,
+    /// Writes awaiting forwarding.
+    queue: Vec<QueuedWrite>,
+    /// Access to the back S1 instance.
+    pub back: Box<dyn Server1Access>,
+    /// Guards restricting writes to mailboxes to approved senders.
+    mailbox_guards: MailboxGuards,
+}
+
+impl FrontServer1 {
+    /// Create a new FrontServer1 forwarding to the given back S1 instance.
+    pub fn new(back: Box<dyn Server1Access>) -> Self {
+        Self {
+            epoch: 0,
+            k_share: Key::new(vec![]),
+            queue: vec![],
+            back,
+            mailbox_guards: MailboxGuards::new(),
+        }
+    }
+
+    /// Start a new epoch with a fresh key share.
+    pub fn batch_init(&mut self) {
+        let mut rng = ChaCha20Rng::from_entropy();
+        self.k_share = Key::random(&mut rng);
+        self.queue.clear();
+    }
+
+    /// Apply this epoch's key share to a client's blinded location point, see
+    /// [`LocationRequest`]. Returns the epoch the evaluation is for with the evaluated point.
+    pub fn evaluate_location(&self, blinded: &[u8]) -> Result<(u64, Vec<u8>), MycoError> {
+        if self.k_share.0.is_empty() {
+            return Err(MycoError::EpochClosed {
+                next_epoch_opens_at: None,
+            });
+        }
+        let blinded = decompress(blinded)
+            .ok_or_else(|| MycoError::MalformedRequest("invalid blinded location".to_string()))?;
+        let evaluated = location_scalar(&self.k_share.0) * blinded;
+        Ok((self.epoch, evaluated.compress().to_bytes().to_vec()))
+    }
+
+    /// Queue a client write sealed to the back S1. Returns the epoch the write was queued into.
+    ///
+    /// A write evaluated under an earlier epoch's key share is turned away as arriving after its
+    /// epoch closed, so the client evaluates and seals it again.
+    pub fn queue_sealed_write(&mut self, write: SealedWrite) -> Result<u64, MycoError> {
+        if write.epoch != self.epoch || self.k_share.0.is_empty() {
+            return Err(MycoError::EpochClosed {
+                next_epoch_opens_at: None,
+            });
+        }
+        self.mailbox_guards
+            .check_address(&write.address, &write.ct, write.access_tag.as_deref())?;
+        self.queue.push((write.ct, write.sealed, write.token));
+        Ok(self.epoch)
+    }
+
+    /// Guard mailbox addresses on behalf of their owner, see [`crate::mailbox`].
+    pub fn guard_mailboxes(&mut self, guards: Vec<MailboxGuard>) -> Result<(), MycoError> {
+        self.mailbox_guards.guard(guards, self.epoch)
+    }
+
+    /// Have the back S1 issue write tokens to a registered account, see
+    /// [`Server1::issue_write_tokens`](crate::server1::Server1::issue_write_tokens).
+    pub fn issue_write_tokens(
+        &self,
+        credentials: AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
+        futures::executor::block_on(self.back.issue_write_tokens(credentials, requests))
+    }
+
+    /// Number of writes queued for the current epoch.
+    pub fn queue_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Shuffle and forward all queued writes to the back S1, then release this epoch's key share.
+    ///
+    /// The returned share must be handed to the back S1 (see
+    /// [`Server1::add_upstream_key_share`](crate::server1::Server1::add_upstream_key_share)) before
+    /// it performs its batch write.
+    pub async fn async_flush(&mut self) -> Result<Key, MycoError> {
+        let flush_latency = LatencyMetric::new("front_server1_flush");
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.shuffle(&mut rng);
+
+        for (ct, sealed, token) in queue {
+            // The token is passed on so the back S1 can enforce its write quota.
+            let write = SealedWrite {
+                epoch: self.epoch,
+                ct,
+                sealed,
+                address: vec![],
+                token,
+                access_tag: None,
+            };
+            self.back.queue_sealed_write(write).await?;
+        }
+
+        self.epoch += 1;
+        self.mailbox_guards.expire(self.epoch);
+        flush_latency.finish();
+        Ok(std::mem::replace(&mut self.k_share, Key::new(vec![])))
+    }
+
+    /// Shuffle and forward all queued writes to the back S1, then release this epoch's key share.
+    pub fn flush(&mut self) -> Result<Key, MycoError> {
+        futures::executor::block_on(self.async_flush())
+    }
+}
+
+/// Local access to a FrontServer1 - direct memory access
+#[derive(Clone)]
+pub struct LocalFrontServer1Access {
+    /// The server instance
+    pub server: Arc<Mutex<FrontServer1>>,
+}
+
+impl LocalFrontServer1Access {
+    /// Create a new LocalFrontServer1Access instance
+    pub fn new(server: Arc<Mutex<FrontServer1>>) -> Self {
+        Self { server }
+    }
+}
+
+#[async_trait]
+impl Server1Access for LocalFrontServer1Access {
+    async fn queue_write(
+        &self,
+        _ct: Vec<u8>,
+        _f: Vec<u8>,
+        _k_oblv_t: Key,
+        _cs: Vec<u8>,
+        _token: Vec<u8>,
+        _access_tag: Option<Vec<u8>>,
+    ) -> Result<u64, MycoError> {
+        Err(MycoError::ConfigError(
+            "the front S1 only takes sealed writes, see Client::set_distributed_trust".to_string(),
+        ))
+    }
+
+    async fn guard_mailboxes(&self, guards: Vec<MailboxGuard>) -> Result<(), MycoError> {
+        self.server.lock()?.guard_mailboxes(guards)
+    }
+
+    async fn issue_write_tokens(
+        &self,
+        credentials: AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
+        self.server.lock()?.issue_write_tokens(credentials, requests)
+    }
+
+    async fn evaluate_location(&self, blinded: Vec<u8>) -> Result<(u64, Vec<u8>), MycoError> {
+        self.server.lock()?.evaluate_location(&blinded)
+    }
+
+    async fn queue_sealed_write(&self, write: SealedWrite) -> Result<u64, MycoError> {
+        self.server.lock()?.queue_sealed_write(write)
+    }
+}
+
+/// Local access to the back S1 instance in distributed trust mode, which opens the writes sealed
+/// to it before queueing them with its Server1.
+#[derive(Clone)]
+pub struct LocalBackServer1Access {
+    /// The server instance
+    pub server: Arc<RwLock<Server1>>,
+    /// The key writes are sealed to.
+    sealing_key: StaticSecret,
+}
+
+impl LocalBackServer1Access {
+    /// Create a new LocalBackServer1Access instance with a fresh sealing key
+    pub fn new(server: Arc<RwLock<Server1>>) -> Self {
+        let mut rng = ChaCha20Rng::from_entropy();
+        Self {
+            server,
+            sealing_key: StaticSecret::random_from_rng(&mut rng),
+        }
+    }
+
+    /// The public key clients seal their writes to, see [`seal_location`].
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.sealing_key)
+    }
+}
+
+#[async_trait]
+impl Server1Access for LocalBackServer1Access {
+    async fn queue_write(
+        &self,
+        _ct: Vec<u8>,
+        _f: Vec<u8>,
+        _k_oblv_t: Key,
+        _cs: Vec<u8>,
+        _token: Vec<u8>,
+        _access_tag: Option<Vec<u8>>,
+    ) -> Result<u64, MycoError> {
+        Err(MycoError::ConfigError(
+            "the back S1 only takes sealed writes forwarded by the front".to_string(),
+        ))
+    }
+
+    async fn guard_mailboxes(&self, _guards: Vec<MailboxGuard>) -> Result<(), MycoError> {
+        Err(MycoError::ConfigError(
+            "mailbox guards are set on the front S1".to_string(),
+        ))
+    }
+
+    async fn issue_write_tokens(
+        &self,
+        credentials: AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
+        self.server
+            .write()
+            .unwrap()
+            .issue_write_tokens(&credentials, requests)
+    }
+
+    async fn queue_sealed_write(&self, write: SealedWrite) -> Result<u64, MycoError> {
+        let (x, k_oblv_t) = open_location(&self.sealing_key, &write.sealed)?;
+        // The back S1 evaluates prf(k_back, x || cs), so an empty cs leaves just the outer layer.
+        self.server
+            .write()
+            .unwrap()
+            .queue_write(write.ct, x, k_oblv_t, vec![], write.token, None)
+    }
+}
@@ -6,7 +6,10 @@
 //! - `Key`: Cryptographic keys used for encryption and PRF operations
 //! - `Path`: Binary paths used in the tree data structure
 //! - `Bucket`: Storage units containing encrypted message blocks
+//! - `SparseBuckets`: Bucket lists with the empty buckets left out, for Server1's batch-init reads
+//! - `BucketDelta`: The change to a bucket between epochs, for Server1's delta writes
 //! - `Metadata`: Associated metadata for message blocks including paths and timestamps
+//! - `ContactBundle`: Compact contact details for sharing as a QR code
 //! 
 //! These types form the foundation for Myco's metadata-hiding encrypted messaging system,
 //! enabling secure communication while obscuring patterns of interaction between users.
@@ -17,9 +20,11 @@ use std::ops::{Index, IndexMut};
 
 use rand::{seq::SliceRandom, Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+use ring::digest;
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
-use crate::{tree::TreeValue, constants::{BLOCK_SIZE, D, LAMBDA, Z}};
+use crate::{tree::{self, TreeValue}, constants::{BLOCK_SIZE, D, KEY_HINT_SIZE, LAMBDA, Z}, crypto::key_hint, error::MycoError, utils::{base32_decode, base32_encode}};
 
 pub(crate) type Timestamp = u64;
 
@@ -38,6 +43,11 @@ impl Metadata {
         self.0.len()
     }
 
+    /// Check if the Metadata has no entries
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// Add a new entry to the Metadata
     pub fn push(&mut self, path: Path, key: Key, timestamp: Timestamp) {
         self.0.push((path, key, timestamp));
@@ -123,6 +133,46 @@ impl Path {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// The path without its last direction, or `None` for the root
+    pub fn parent(&self) -> Option<Path> {
+        let (_, rest) = self.0.split_last()?;
+        Some(Path(rest.to_vec()))
+    }
+
+    /// The path with its last direction flipped, or `None` for the root
+    pub fn sibling(&self) -> Option<Path> {
+        let (&last, rest) = self.0.split_last()?;
+        let flipped = match last {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        };
+        Some(Path([rest, &[flipped]].concat()))
+    }
+
+    /// The strict ancestors of the path, from its parent up to the root
+    pub fn ancestors(&self) -> impl Iterator<Item = Path> + '_ {
+        (0..self.0.len()).rev().map(|depth| Path(self.0[..depth].to_vec()))
+    }
+
+    /// The index of the node the path leads to, see [`tree::path_index`]
+    pub fn to_index(&self) -> usize {
+        self.0
+            .iter()
+            .fold(tree::ROOT_INDEX, |index, &direction| tree::child_index(index, direction))
+    }
+
+    /// The path leading to the node at `index`. Its depth is `index.ilog2()`; index 0 isn't a
+    /// node and gives the root's empty path.
+    pub fn from_index(index: usize) -> Self {
+        let depth = index.checked_ilog2().unwrap_or(0);
+        Path(
+            (0..depth)
+                .rev()
+                .map(|bit| Direction::from(((index >> bit) & 1) as u8))
+                .collect(),
+        )
+    }
 }
 
 impl Iterator for Path {
@@ -148,7 +198,7 @@ impl<'a> IntoIterator for &'a Path {
 
 impl From<Path> for Vec<u8> {
     fn from(path: Path) -> Self {
-        let num_bytes = (path.0.len() + 7) / 8;
+        let num_bytes = path.0.len().div_ceil(8);
         let mut bytes = vec![0u8; num_bytes];
 
         for (i, direction) in path.0.iter().enumerate() {
@@ -176,17 +226,8 @@ impl From<Vec<u8>> for Path {
 }
 
 impl From<usize> for Path {
-    fn from(value: usize) -> Self {
-        let mut directions = Vec::new();
-        let mut value = value;
-        if value > 1 {
-            value = value >> 1;
-            while value > 0 {
-                directions.push(Direction::from((value & 1) as u8));
-                value >>= 1;
-            }
-        }
-        Path(directions)
+    fn from(index: usize) -> Self {
+        Path::from_index(index)
     }
 }
 
@@ -200,6 +241,27 @@ impl Block {
         Block(data)
     }
 
+    /// Create a block from a ciphertext under `k_oblv`, prefixed with its key hint
+    pub fn with_hint(k_oblv: &[u8], ciphertext: Vec<u8>) -> Result<Self, MycoError> {
+        Ok(Block([key_hint(k_oblv, &ciphertext)?, ciphertext].concat()))
+    }
+
+    /// The block's key hint, see [`key_hint`]
+    pub fn hint(&self) -> &[u8] {
+        &self.0[..KEY_HINT_SIZE.min(self.0.len())]
+    }
+
+    /// The block's ciphertext, without its key hint
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.0[KEY_HINT_SIZE.min(self.0.len())..]
+    }
+
+    /// Whether the block's hint matches `k_oblv`, i.e. whether it's worth trial-decrypting
+    pub fn matches_hint(&self, k_oblv: &[u8]) -> bool {
+        key_hint(k_oblv, self.ciphertext())
+            .is_ok_and(|hint| ring::constant_time::verify_slices_are_equal(&hint, self.hint()).is_ok())
+    }
+
     /// Create a new random Block instance with a given size
     pub fn new_random() -> Self {
         let mut rng = ChaCha20Rng::from_entropy(); // Use ChaCha20Rng
@@ -274,6 +336,153 @@ impl Bucket {
     }
 }
 
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+/// A list of buckets with the empty ones left out, marked in a presence bitmap instead
+///
+/// Buckets that have never been written are empty, and early in a deployment that's most of them.
+/// Server2 answers Server1's batch-init reads in this form. Clients never see it, and Server1
+/// learns nothing from it that the full buckets wouldn't tell it.
+pub struct SparseBuckets {
+    /// Number of buckets, empty ones included
+    len: u64,
+    /// Bit `i % 8` of byte `i / 8` is set if bucket `i` is non-empty
+    present: Vec<u8>,
+    /// The non-empty buckets, in order
+    buckets: Vec<Bucket>,
+}
+
+impl SparseBuckets {
+    /// Leave the empty buckets of `buckets` out.
+    pub fn new(buckets: Vec<Bucket>) -> Self {
+        let present = Self::bitmap(buckets.iter().map(|bucket| !bucket.is_empty()));
+        Self {
+            len: buckets.len() as u64,
+            present,
+            buckets: buckets.into_iter().filter(|bucket| !bucket.is_empty()).collect(),
+        }
+    }
+
+    /// Assemble parts received from Server2. They're checked when the buckets are put back
+    /// together.
+    pub fn from_parts(len: u64, present: Vec<u8>, buckets: Vec<Bucket>) -> Self {
+        Self {
+            len,
+            present,
+            buckets,
+        }
+    }
+
+    /// The presence bitmap of buckets whose non-emptiness is given by `present`.
+    pub fn bitmap(present: impl IntoIterator<Item = bool>) -> Vec<u8> {
+        let mut bitmap = Vec::new();
+        for (i, present) in present.into_iter().enumerate() {
+            if i % 8 == 0 {
+                bitmap.push(0);
+            }
+            if present {
+                *bitmap.last_mut().unwrap() |= 1 << (i % 8);
+            }
+        }
+        bitmap
+    }
+
+    /// Number of buckets, empty ones included.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Whether there are no buckets at all.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of non-empty buckets.
+    pub fn present(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Put the empty buckets back in. Fails unless the bitmap covers exactly `len` buckets and
+    /// marks as many as were sent, all of them non-empty.
+    pub fn into_buckets(self) -> Result<Vec<Bucket>, MycoError> {
+        let malformed = |what: &str| MycoError::ProtocolError(format!("sparse buckets: {}", what));
+        if self.present.len() as u64 != self.len.div_ceil(8) {
+            return Err(malformed("bitmap doesn't match the bucket count"));
+        }
+        if !self.len.is_multiple_of(8) && self.present.last().is_some_and(|byte| byte >> (self.len % 8) != 0) {
+            return Err(malformed("bits set past the last bucket"));
+        }
+        let marked: u32 = self.present.iter().map(|byte| byte.count_ones()).sum();
+        if marked as usize != self.buckets.len() || self.buckets.iter().any(Bucket::is_empty) {
+            return Err(malformed("bitmap doesn't match the buckets sent"));
+        }
+
+        let mut present = self.buckets.into_iter();
+        Ok((0..self.len as usize)
+            .map(|i| match self.present[i / 8] >> (i % 8) & 1 {
+                1 => present.next().unwrap_or_default(),
+                _ => Bucket::default(),
+            })
+            .collect())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+/// A bucket written by Server1, sent as the change from the bucket Server2 holds at its index
+///
+/// Server1 re-encrypts every block it keeps and pads buckets with fresh random blocks, so that
+/// Server2 can't tell which blocks stayed in a bucket. Deltas only pay off where blocks are carried
+/// over as they are, which gives exactly that away, so they are limited to simulations with the
+/// encryption turned off in a [`SimulationMode`](crate::simulation::SimulationMode).
+pub enum BucketDelta {
+    /// The whole new bucket.
+    Full(Bucket),
+    /// The blocks that changed, by position, in a bucket as long as the old one.
+    Blocks(Vec<(u32, Block)>),
+}
+
+impl BucketDelta {
+    /// The delta turning `old` into `new`. A bucket whose length changed, or whose blocks all
+    /// changed, is sent in full. Any smaller delta is smaller than the bucket, as blocks dwarf the
+    /// positions sent with them.
+    pub fn between(old: &Bucket, new: Bucket) -> Self {
+        if old.len() != new.len() {
+            return BucketDelta::Full(new);
+        }
+        let changed: Vec<(u32, Block)> = new
+            .iter()
+            .zip(old.iter())
+            .enumerate()
+            .filter(|(_, (new, old))| new != old)
+            .map(|(i, (new, _))| (i as u32, new.clone()))
+            .collect();
+        if changed.len() == new.len() && !new.is_empty() {
+            return BucketDelta::Full(new);
+        }
+        BucketDelta::Blocks(changed)
+    }
+
+    /// Apply the delta to `old`, the bucket it was computed against.
+    pub fn apply(self, old: &Bucket) -> Result<Bucket, MycoError> {
+        match self {
+            BucketDelta::Full(bucket) => Ok(bucket),
+            BucketDelta::Blocks(changed) => {
+                let mut bucket = old.clone();
+                for (i, block) in changed {
+                    let slot = bucket.0.get_mut(i as usize).ok_or_else(|| {
+                        MycoError::MalformedRequest(format!(
+                            "delta changes block {} of a bucket of {}",
+                            i,
+                            old.len()
+                        ))
+                    })?;
+                    *slot = block;
+                }
+                Ok(bucket)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 /// A cryptographic key, represented as a vector of bytes
 pub struct Key(pub Vec<u8>);
@@ -290,3 +499,222 @@ impl Key {
     }
 }
 
+impl Drop for Key {
+    /// Wipe the key material, so dropped keys don't linger in memory.
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+/// Server2's current epoch and PRF key cursor, used by clients to keep their key caches in sync
+pub struct EpochInfo {
+    /// Server2's current epoch
+    pub epoch: u64,
+    /// Number of PRF keys Server2 has published so far
+    pub prf_key_cursor: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+/// Aggregate write counts for one Server1 epoch, used to check the size of the anonymity set
+pub struct WriteStats {
+    /// The epoch the writes were made in
+    pub epoch: u64,
+    /// Number of writes queued
+    pub writes: usize,
+    /// Number of distinct issued write tokens seen, i.e. the number of registered clients that
+    /// wrote
+    pub distinct_writers: usize,
+}
+
+impl WriteStats {
+    /// Estimated share of padding traffic: writes beyond the first under each write token.
+    ///
+    /// Real and fake writes look the same to Server1, and a client normally sends one of either
+    /// per epoch, so extra writes under a token are traffic that doesn't grow the anonymity set.
+    pub fn padding_ratio(&self) -> f64 {
+        if self.writes == 0 {
+            return 0.0;
+        }
+        self.writes.saturating_sub(self.distinct_writers) as f64 / self.writes as f64
+    }
+}
+
+/// Number of blocks written in one epoch per storage tag, reported by Server1 to Server2
+pub type StorageReport = Vec<(Vec<u8>, usize)>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Approximate storage use of the live epochs on Server2, accounted by anonymous storage tags
+/// (see [`crate::crypto::storage_tag`]) rather than by client
+pub struct StorageStats {
+    /// Number of live epochs accounted for
+    pub epochs: usize,
+    /// Number of blocks written in those epochs
+    pub blocks: usize,
+    /// Number of distinct storage tags the blocks were written under
+    pub tags: usize,
+    /// The hex encoded storage tags with the most blocks and their block counts, largest first
+    pub heaviest: Vec<(String, usize)>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Running totals of the process's heap allocations, see [`crate::memory::CountingAllocator`]
+pub struct AllocatorStats {
+    /// Bytes currently allocated
+    pub allocated_bytes: usize,
+    /// Most bytes allocated at any one time
+    pub peak_bytes: usize,
+    /// Number of allocations made, including reallocations
+    pub allocations: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Memory held by a server, for capacity planning. Tree and metadata sizes are estimates of the
+/// heap bytes they own (see [`crate::memory::HeapSize`])
+pub struct MemoryStats {
+    /// Bytes held by the bucket trees
+    pub tree_bytes: usize,
+    /// Bytes held by the metadata trees on Server1, or the PRF keys on Server2
+    pub metadata_bytes: usize,
+    /// Number of writes queued for the current epoch. Always 0 on Server2
+    pub queue_depth: usize,
+    /// Allocator totals, if the counting allocator is installed
+    pub allocator: Option<AllocatorStats>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Aggregate read counts for one Server2 epoch, used to check the size of the anonymity set
+pub struct ReadStats {
+    /// The epoch the reads were made in
+    pub epoch: u64,
+    /// Number of client reads served. Clients read once per epoch, so this estimates the number
+    /// of distinct readers.
+    pub reads: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Bytes a server received and sent for one kind of traffic
+pub struct TrafficBytes {
+    /// Bytes of request bodies (or frames) received
+    pub received: usize,
+    /// Bytes of response bodies (or frames) sent
+    pub sent: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Bytes a server received and sent in one epoch, by part of the protocol
+pub struct BandwidthStats {
+    /// The epoch the traffic was counted in
+    pub epoch: u64,
+    /// Reads of the pathset for Server1's batch init
+    pub batch_init: TrafficBytes,
+    /// Server1's batch writes, notification indices and storage reports
+    pub batch_write: TrafficBytes,
+    /// Clients' path, PRF key and notification reads
+    pub client_reads: TrafficBytes,
+    /// Clients' writes
+    pub client_writes: TrafficBytes,
+    /// All other traffic
+    pub other: TrafficBytes,
+}
+
+/// Prefix of an encoded [`ContactBundle`], including the format version.
+pub const CONTACT_BUNDLE_PREFIX: &str = "MYCO1:";
+
+/// Bytes of SHA-256 appended to an encoded [`ContactBundle`] to catch scanning errors.
+const CONTACT_BUNDLE_CHECKSUM_SIZE: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// What a client shares to be added as a contact, compact enough for a QR code
+pub struct ContactBundle {
+    /// The sharing client's ID
+    pub id: String,
+    /// The sharing client's identity key, if it publishes one (see [`crate::directory`])
+    pub identity_key: Option<[u8; 32]>,
+    /// The initial contact key
+    pub key: Key,
+    /// Address of Server1
+    pub server1: String,
+    /// Address of Server2
+    pub server2: String,
+}
+
+impl ContactBundle {
+    /// Encode the bundle as `MYCO1:` followed by base32, using only characters of the QR code
+    /// alphanumeric mode.
+    ///
+    /// The bytes are the key, ID and server addresses, each prefixed by a one-byte length, then
+    /// the identity key if present, and a truncated SHA-256 checksum.
+    pub fn encode(&self) -> Result<String, MycoError> {
+        let mut bytes = Vec::new();
+        for field in [
+            self.key.0.as_slice(),
+            self.id.as_bytes(),
+            self.server1.as_bytes(),
+            self.server2.as_bytes(),
+        ] {
+            let len = u8::try_from(field.len()).map_err(|_| MycoError::SerializationFailed(None))?;
+            bytes.push(len);
+            bytes.extend_from_slice(field);
+        }
+        if let Some(identity_key) = &self.identity_key {
+            bytes.extend_from_slice(identity_key);
+        }
+        let checksum = digest::digest(&digest::SHA256, &bytes);
+        bytes.extend_from_slice(&checksum.as_ref()[..CONTACT_BUNDLE_CHECKSUM_SIZE]);
+        Ok(format!("{}{}", CONTACT_BUNDLE_PREFIX, base32_encode(&bytes)))
+    }
+
+    /// Decode a bundle produced by [`ContactBundle::encode`]. The prefix is matched case
+    /// insensitively, as QR scanners may change the case.
+    pub fn decode(encoded: &str) -> Result<Self, MycoError> {
+        let encoded = encoded.trim();
+        let prefix_len = CONTACT_BUNDLE_PREFIX.len();
+        if !encoded
+            .get(..prefix_len)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(CONTACT_BUNDLE_PREFIX))
+        {
+            return Err(MycoError::ProtocolError(
+                "not a contact bundle of a supported version".to_string(),
+            ));
+        }
+        let bytes = base32_decode(&encoded[prefix_len..])?;
+        let (body, checksum) = bytes
+            .split_at_checked(bytes.len().saturating_sub(CONTACT_BUNDLE_CHECKSUM_SIZE))
+            .filter(|(_, checksum)| checksum.len() == CONTACT_BUNDLE_CHECKSUM_SIZE)
+            .ok_or(MycoError::DeserializationError(None))?;
+        if digest::digest(&digest::SHA256, body).as_ref()[..CONTACT_BUNDLE_CHECKSUM_SIZE] != *checksum {
+            return Err(MycoError::DeserializationError(None));
+        }
+
+        let mut rest = body;
+        let mut fields = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let (&len, tail) = rest.split_first().ok_or(MycoError::DeserializationError(None))?;
+            let (field, tail) = tail
+                .split_at_checked(len as usize)
+                .ok_or(MycoError::DeserializationError(None))?;
+            fields.push(field);
+            rest = tail;
+        }
+        if fields[0].is_empty() {
+            return Err(MycoError::DeserializationError(None));
+        }
+        let identity_key = match rest.len() {
+            0 => None,
+            32 => Some(rest.try_into().unwrap()),
+            _ => return Err(MycoError::DeserializationError(None)),
+        };
+        let text = |field: &[u8]| {
+            String::from_utf8(field.to_vec()).map_err(|_| MycoError::DeserializationError(None))
+        };
+        Ok(Self {
+            key: Key::new(fields[0].to_vec()),
+            id: text(fields[1])?,
+            server1: text(fields[2])?,
+            server2: text(fields[3])?,
+            identity_key,
+        })
+    }
+}
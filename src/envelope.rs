@@ -0,0 +1,214 @@
+//! Message envelope
+//!
+//! Every message a client writes is wrapped in an [`Envelope`]: a small bincode-encoded header
+//! (schema version, content type, sequence number and fragment position) followed by the payload,
+//! all inside the fixed `MESSAGE_SIZE` plaintext. Applications get typed framing instead of
+//! inventing their own inside the payload, and readers get the payload back byte for byte, trailing
+//! zeros included.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::{LENGTH_PREFIX_SIZE, MESSAGE_SIZE},
+    error::MycoError,
+};
+
+/// Current envelope schema version. Envelopes with any other version are rejected.
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// Largest number of bytes the encoded header and the payload's length prefix take up.
+pub const ENVELOPE_OVERHEAD: usize = 27;
+
+/// Largest payload that fits in a single envelope, once the envelope is padded with a length
+/// prefix.
+pub const MAX_PAYLOAD_SIZE: usize = MESSAGE_SIZE - LENGTH_PREFIX_SIZE - ENVELOPE_OVERHEAD;
+
+/// What an envelope's payload holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentType {
+    /// Opaque bytes.
+    #[default]
+    Binary,
+    /// UTF-8 text.
+    Text,
+    /// A JSON document.
+    Json,
+    /// An application-defined type.
+    Application(u16),
+    /// Final message to a revoked contact. The payload is empty.
+    Tombstone,
+    /// A sender key or cover message of a group session (see [`crate::group`]).
+    Group,
+}
+
+/// Position of an envelope among the fragments of a message too large for a single envelope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fragment {
+    /// Index of this fragment, starting at 0.
+    pub index: u16,
+    /// Total number of fragments of the message.
+    pub count: u16,
+}
+
+impl Default for Fragment {
+    fn default() -> Self {
+        Self { index: 0, count: 1 }
+    }
+}
+
+/// The envelope header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    /// Envelope schema version.
+    pub version: u8,
+    /// What the payload holds.
+    pub content_type: ContentType,
+    /// Sender-assigned sequence number of the message. All fragments of a message share it.
+    pub sequence: u64,
+    /// Position of this envelope among the message's fragments.
+    pub fragment: Fragment,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            version: ENVELOPE_VERSION,
+            content_type: ContentType::default(),
+            sequence: 0,
+            fragment: Fragment::default(),
+        }
+    }
+}
+
+/// A header and payload, encoded into a message's plaintext.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// The header.
+    pub header: Header,
+    /// The payload.
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Create a single-fragment binary envelope around `payload`.
+    pub fn new(payload: Vec<u8>) -> Self {
+        Self {
+            header: Header::default(),
+            payload,
+        }
+    }
+
+    /// Set the content type.
+    pub fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.header.content_type = content_type;
+        self
+    }
+
+    /// Set the sequence number.
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.header.sequence = sequence;
+        self
+    }
+
+    /// Set the fragment position.
+    pub fn with_fragment(mut self, index: u16, count: u16) -> Self {
+        self.header.fragment = Fragment { index, count };
+        self
+    }
+
+    /// Split a message into as many envelopes as needed, all sharing `sequence`.
+    pub fn split(
+        payload: &[u8],
+        content_type: ContentType,
+        sequence: u64,
+    ) -> Result<Vec<Self>, MycoError> {
+        Self::split_with_size(payload, content_type, sequence, MAX_PAYLOAD_SIZE)
+    }
+
+    /// Split a message into envelopes of at most `size` payload bytes, all sharing `sequence`.
+    pub fn split_with_size(
+        payload: &[u8],
+        content_type: ContentType,
+        sequence: u64,
+        size: usize,
+    ) -> Result<Vec<Self>, MycoError> {
+        if size == 0 || size > MAX_PAYLOAD_SIZE {
+            return Err(MycoError::ProtocolError(format!("invalid fragment size {}", size)));
+        }
+        let chunks: Vec<&[u8]> = if payload.is_empty() {
+            vec![payload]
+        } else {
+            payload.chunks(size).collect()
+        };
+        let count = u16::try_from(chunks.len()).map_err(|_| {
+            MycoError::ProtocolError(format!("message of {} bytes has too many fragments", payload.len()))
+        })?;
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                Self::new(chunk.to_vec())
+                    .with_content_type(content_type)
+                    .with_sequence(sequence)
+                    .with_fragment(index as u16, count)
+            })
+            .collect())
+    }
+
+    /// Reassemble the payload of a message from its fragments, given in any order.
+    pub fn join(mut fragments: Vec<Self>) -> Result<Vec<u8>, MycoError> {
+        fragments.sort_by_key(|envelope| envelope.header.fragment.index);
+        let first = fragments
+            .first()
+            .ok_or_else(|| MycoError::ProtocolError("no fragments to join".to_string()))?
+            .header;
+        let complete = fragments.len() == first.fragment.count as usize
+            && fragments.iter().enumerate().all(|(index, envelope)| {
+                envelope.header.sequence == first.sequence
+                    && envelope.header.fragment
+                        == Fragment {
+                            index: index as u16,
+                            count: first.fragment.count,
+                        }
+            });
+        if !complete {
+            return Err(MycoError::ProtocolError(format!(
+                "incomplete fragments for message {}",
+                first.sequence
+            )));
+        }
+        Ok(fragments.into_iter().flat_map(|envelope| envelope.payload).collect())
+    }
+
+    /// Encode the envelope into message plaintext.
+    pub fn encode(&self) -> Result<Vec<u8>, MycoError> {
+        if self.payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(MycoError::ProtocolError(format!(
+                "payload of {} bytes exceeds the {} bytes an envelope holds",
+                self.payload.len(),
+                MAX_PAYLOAD_SIZE
+            )));
+        }
+        let fragment = self.header.fragment;
+        if fragment.index >= fragment.count {
+            return Err(MycoError::ProtocolError(format!(
+                "invalid fragment {} of {}",
+                fragment.index, fragment.count
+            )));
+        }
+        bincode::serialize(self).map_err(|e| MycoError::SerializationFailed(Some(e)))
+    }
+
+    /// Decode an envelope from message plaintext. Padding after the envelope is ignored.
+    pub fn decode(bytes: &[u8]) -> Result<Self, MycoError> {
+        let envelope: Self =
+            bincode::deserialize(bytes).map_err(|e| MycoError::DeserializationError(Some(e)))?;
+        if envelope.header.version != ENVELOPE_VERSION {
+            return Err(MycoError::ProtocolError(format!(
+                "unsupported envelope version {}",
+                envelope.header.version
+            )));
+        }
+        Ok(envelope)
+    }
+}
@@ -1,6 +1,11 @@
 //! # Myco Error Types
 //!
 //! This module contains the error types used throughout the Myco library.
+//!
+//! Every [`MycoError`] maps to a stable numeric [`ErrorCode`], which is what crosses process
+//! boundaries: servers answer failed commands with the code and message of the error, and FFI
+//! bindings can return the code as a plain integer.
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -21,6 +26,41 @@ pub enum MycoError {
     /// Error that occurs when no message is found
     #[error("No message found")]
     NoMessageFound,
+    /// Error that occurs when a ciphertext fails authentication, e.g. because it was encrypted
+    /// under a different key
+    #[error("Decryption authentication failed")]
+    DecryptAuthFailed,
+    /// Error that occurs when the startup self-test of the crypto primitives fails, see
+    /// [`crate::crypto::self_test`]
+    #[error("Crypto self-test failed: {0}")]
+    SelfTestFailed(String),
+    /// Error that occurs when a published test vector doesn't match what this crate computes, see
+    /// [`crate::test_vectors`]
+    #[error("Test vector mismatch: {0}")]
+    TestVectorMismatch(String),
+    /// Error that occurs when the client has no PRF key for an epoch, e.g. because it expired
+    #[error("No PRF key available {epoch_past} epochs back")]
+    PrfKeyUnavailable {
+        /// How many epochs before the newest one the key was requested for
+        epoch_past: usize,
+    },
+    /// Error that occurs when an epoch before the first one is requested
+    #[error("Epoch {epoch_past} epochs back is out of range at epoch {epoch}")]
+    EpochOutOfRange {
+        /// How many epochs back the request went
+        epoch_past: usize,
+        /// The client's current epoch
+        epoch: usize,
+    },
+    /// Error that occurs when a call to a server fails
+    #[error("Transport error in {operation}: {source}")]
+    TransportError {
+        /// The server call that failed
+        operation: &'static str,
+        /// The underlying failure
+        #[source]
+        source: anyhow::Error,
+    },
     /// Error that occurs when a bucket is not found
     #[error("Bucket not found")]
     BucketNotFound,
@@ -36,21 +76,24 @@ pub enum MycoError {
     /// Error that occurs when the LCA is not found
     #[error("LCA not found")]
     LcaNotFound,
-    /// Error that occurs when serialization fails
+    /// Error that occurs when serialization fails, with the bincode error if bincode failed
     #[error("Serialization failed")]
-    SerializationFailed,
-    /// Error that occurs when deserialization fails
+    SerializationFailed(#[source] Option<bincode::Error>),
+    /// Error that occurs when deserialization fails, with the bincode error if bincode failed
     #[error("Deserialization failed")]
-    DeserializationError,
+    DeserializationError(#[source] Option<bincode::Error>),
+    /// Error that occurs when serialized state was written by an incompatible build
+    #[error("Incompatible state: {0}")]
+    IncompatibleState(String),
     /// Error that occurs when an invalid command is received
     #[error("Invalid command")]
     InvalidCommand,
     /// Error that occurs when an IO error occurs
     #[error("{0}")]
-    IoError(std::io::Error),
+    IoError(#[source] std::io::Error),
     /// Error that occurs when a TLS error occurs
     #[error("{0}")]
-    TlsError(rustls::Error),
+    TlsError(#[source] rustls::Error),
     /// Error that occurs when an invalid server name is received
     #[error("Invalid server name")]
     InvalidServerName,
@@ -78,18 +121,372 @@ pub enum MycoError {
     /// Error that occurs when a configuration error occurs
     #[error("Configuration error: {0}")]
     ConfigError(String),
-    /// Error that occurs when a database error occurs
-    #[error("Database error: {0}")]
-    DatabaseError(String),
-    /// Error that occurs when a network error occurs
-    #[error("Network error: {0}")]
-    NetworkError(String),
+    /// Error that occurs when an HTTP request fails
+    #[error("Network error in {context}: {source}")]
+    NetworkError {
+        /// What the request was for
+        context: String,
+        /// The underlying failure
+        #[source]
+        source: reqwest::Error,
+    },
     /// Error that occurs when a protocol error occurs
     #[error("Protocol error: {0}")]
     ProtocolError(String),
+    /// Error that occurs when a server rejects a request it cannot decode or accept
+    #[error("Malformed request: {0}")]
+    MalformedRequest(String),
     /// Error that occurs when a certificate error occurs
     #[error("Certificate error: {0}")]
     CertificateError(String),
+    /// Error that occurs when a contact is unknown to the client, e.g. because it was revoked
+    #[error("Unknown contact")]
+    UnknownContact,
+    /// Error that occurs when a write token has been used up for the current epoch
+    #[error("Write quota exceeded")]
+    WriteQuotaExceeded,
+    /// Error that occurs when a write arrives while no epoch is accepting writes
+    #[error("Epoch closed, next epoch opens at {next_epoch_opens_at:?}")]
+    EpochClosed {
+        /// Estimated time the next epoch opens, in milliseconds since the Unix epoch, if known
+        next_epoch_opens_at: Option<u64>,
+    },
+    /// Error that occurs when Server1 gives up on writing out an epoch
+    #[error("Batch write of epoch {epoch} aborted: {reason}")]
+    BatchWriteAborted {
+        /// The epoch whose batch write was aborted
+        epoch: u64,
+        /// What went wrong
+        reason: String,
+    },
+    /// Error that occurs when Server2 is asked to write an epoch it has already moved past
+    #[error("Write for epoch {epoch} rejected, Server2 is at epoch {current}")]
+    StaleEpoch {
+        /// The epoch of the rejected write
+        epoch: u64,
+        /// The epoch Server2 is at
+        current: u64,
+    },
+    /// Error that occurs when an epoch is finalized with a PRF key Server2 still holds
+    #[error("PRF key reused")]
+    PrfKeyReused,
+    /// Error that occurs when a registration is unknown or its secret doesn't match
+    #[error("Unknown registration")]
+    UnknownRegistration,
+    /// Error that occurs when Server1 holds as many registrations as it accepts
+    #[error("Registration limit reached")]
+    RegistrationLimitReached,
+    /// Error that occurs when a write to a guarded mailbox lacks a valid access tag, or a guard
+    /// would replace another one
+    #[error("Mailbox access denied")]
+    MailboxAccessDenied,
+    /// Error that occurs when a write arrives while Server1 is still initializing the epoch's batch
+    #[error("Epoch initializing")]
+    EpochInitializing,
+    /// Error that occurs when a read would take the client past its read budget for the epoch
+    #[error("Read budget of {budget} paths exhausted in epoch {epoch}")]
+    ReadBudgetExceeded {
+        /// The client's epoch
+        epoch: usize,
+        /// The number of paths the client reads per epoch
+        budget: usize,
+    },
+    /// Error that occurs when Server1 queues a write into another epoch than the one its location
+    /// and keys were derived for, so the recipient won't find it there
+    #[error("Write derived for epoch {written} was queued into epoch {accepted}")]
+    WriteEpochMismatch {
+        /// The epoch the write was derived for
+        written: usize,
+        /// The epoch Server1 queued the write into
+        accepted: u64,
+    },
+    /// Error that occurs when a write carries a write token Server1 didn't issue for the epoch
+    #[error("Invalid write token")]
+    InvalidWriteToken,
+    /// Error that occurs on a server and is passed on to the caller. Errors without fields are
+    /// rebuilt as themselves instead.
+    #[error("{message}")]
+    Remote {
+        /// The code of the error on the server
+        code: ErrorCode,
+        /// The message of the error on the server
+        message: String,
+    },
+}
+
+/// Stable numeric codes of [`MycoError`] variants, for transfer over RPC and FFI. Codes are grouped
+/// by area and never reused.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// [`MycoError::HkdfExpansionFailed`]
+    HkdfExpansionFailed = 100,
+    /// [`MycoError::HkdfFillFailed`]
+    HkdfFillFailed = 101,
+    /// [`MycoError::EncryptionFailed`]
+    EncryptionFailed = 102,
+    /// [`MycoError::DecryptionFailed`]
+    DecryptionFailed = 103,
+    /// [`MycoError::DecryptAuthFailed`]
+    DecryptAuthFailed = 104,
+    /// [`MycoError::SelfTestFailed`]
+    SelfTestFailed = 105,
+    /// [`MycoError::TestVectorMismatch`]
+    TestVectorMismatch = 106,
+    /// [`MycoError::NoMessageFound`]
+    NoMessageFound = 200,
+    /// [`MycoError::PrfKeyUnavailable`]
+    PrfKeyUnavailable = 201,
+    /// [`MycoError::EpochOutOfRange`]
+    EpochOutOfRange = 202,
+    /// [`MycoError::UnknownContact`]
+    UnknownContact = 203,
+    /// [`MycoError::WriteQuotaExceeded`]
+    WriteQuotaExceeded = 204,
+    /// [`MycoError::EpochClosed`]
+    EpochClosed = 205,
+    /// [`MycoError::InvalidBatchSize`]
+    InvalidBatchSize = 206,
+    /// [`MycoError::InvalidCommand`]
+    InvalidCommand = 207,
+    /// [`MycoError::ProtocolError`]
+    ProtocolError = 208,
+    /// [`MycoError::MalformedRequest`]
+    MalformedRequest = 209,
+    /// [`MycoError::StaleEpoch`]
+    StaleEpoch = 210,
+    /// [`MycoError::PrfKeyReused`]
+    PrfKeyReused = 211,
+    /// [`MycoError::UnknownRegistration`]
+    UnknownRegistration = 212,
+    /// [`MycoError::RegistrationLimitReached`]
+    RegistrationLimitReached = 213,
+    /// [`MycoError::MailboxAccessDenied`]
+    MailboxAccessDenied = 214,
+    /// [`MycoError::EpochInitializing`]
+    EpochInitializing = 215,
+    /// [`MycoError::ReadBudgetExceeded`]
+    ReadBudgetExceeded = 216,
+    /// [`MycoError::WriteEpochMismatch`]
+    WriteEpochMismatch = 217,
+    /// [`MycoError::InvalidWriteToken`]
+    InvalidWriteToken = 218,
+    /// [`MycoError::BucketNotFound`]
+    BucketNotFound = 300,
+    /// [`MycoError::MetadataBucketNotFound`]
+    MetadataBucketNotFound = 301,
+    /// [`MycoError::BucketIndexError`]
+    BucketIndexError = 302,
+    /// [`MycoError::MetadataIndexError`]
+    MetadataIndexError = 303,
+    /// [`MycoError::LcaNotFound`]
+    LcaNotFound = 304,
+    /// [`MycoError::BatchWriteAborted`]
+    BatchWriteAborted = 305,
+    /// [`MycoError::SerializationFailed`]
+    SerializationFailed = 400,
+    /// [`MycoError::DeserializationError`]
+    DeserializationError = 401,
+    /// [`MycoError::ParseIntError`]
+    ParseIntError = 402,
+    /// [`MycoError::ParseFloatError`]
+    ParseFloatError = 403,
+    /// [`MycoError::IncompatibleState`]
+    IncompatibleState = 404,
+    /// [`MycoError::IoError`]
+    IoError = 500,
+    /// [`MycoError::TlsError`]
+    TlsError = 501,
+    /// [`MycoError::InvalidServerName`]
+    InvalidServerName = 502,
+    /// [`MycoError::CertificateError`]
+    CertificateError = 503,
+    /// [`MycoError::NetworkError`]
+    NetworkError = 504,
+    /// [`MycoError::TransportError`]
+    TransportError = 505,
+    /// [`MycoError::MutexLockFailed`]
+    MutexLockFailed = 600,
+    /// [`MycoError::ThreadJoinFailed`]
+    ThreadJoinFailed = 601,
+    /// [`MycoError::ChannelSendError`]
+    ChannelSendError = 602,
+    /// [`MycoError::ChannelReceiveError`]
+    ChannelReceiveError = 603,
+    /// [`MycoError::ConfigError`]
+    ConfigError = 700,
+}
+
+impl ErrorCode {
+    /// All codes, in ascending order.
+    pub const ALL: [ErrorCode; 48] = [
+        ErrorCode::HkdfExpansionFailed,
+        ErrorCode::HkdfFillFailed,
+        ErrorCode::EncryptionFailed,
+        ErrorCode::DecryptionFailed,
+        ErrorCode::DecryptAuthFailed,
+        ErrorCode::SelfTestFailed,
+        ErrorCode::TestVectorMismatch,
+        ErrorCode::NoMessageFound,
+        ErrorCode::PrfKeyUnavailable,
+        ErrorCode::EpochOutOfRange,
+        ErrorCode::UnknownContact,
+        ErrorCode::WriteQuotaExceeded,
+        ErrorCode::EpochClosed,
+        ErrorCode::InvalidBatchSize,
+        ErrorCode::InvalidCommand,
+        ErrorCode::ProtocolError,
+        ErrorCode::MalformedRequest,
+        ErrorCode::StaleEpoch,
+        ErrorCode::PrfKeyReused,
+        ErrorCode::UnknownRegistration,
+        ErrorCode::RegistrationLimitReached,
+        ErrorCode::MailboxAccessDenied,
+        ErrorCode::EpochInitializing,
+        ErrorCode::ReadBudgetExceeded,
+        ErrorCode::WriteEpochMismatch,
+        ErrorCode::InvalidWriteToken,
+        ErrorCode::BucketNotFound,
+        ErrorCode::MetadataBucketNotFound,
+        ErrorCode::BucketIndexError,
+        ErrorCode::MetadataIndexError,
+        ErrorCode::LcaNotFound,
+        ErrorCode::BatchWriteAborted,
+        ErrorCode::SerializationFailed,
+        ErrorCode::DeserializationError,
+        ErrorCode::ParseIntError,
+        ErrorCode::ParseFloatError,
+        ErrorCode::IncompatibleState,
+        ErrorCode::IoError,
+        ErrorCode::TlsError,
+        ErrorCode::InvalidServerName,
+        ErrorCode::CertificateError,
+        ErrorCode::NetworkError,
+        ErrorCode::TransportError,
+        ErrorCode::MutexLockFailed,
+        ErrorCode::ThreadJoinFailed,
+        ErrorCode::ChannelSendError,
+        ErrorCode::ChannelReceiveError,
+        ErrorCode::ConfigError,
+    ];
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        code as u32
+    }
+}
+
+impl TryFrom<u32> for ErrorCode {
+    type Error = u32;
+
+    /// Look up a code, returning it back if it's unknown.
+    fn try_from(value: u32) -> Result<Self, u32> {
+        ErrorCode::ALL
+            .into_iter()
+            .find(|code| *code as u32 == value)
+            .ok_or(value)
+    }
+}
+
+impl MycoError {
+    /// The stable code of the error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            MycoError::HkdfExpansionFailed => ErrorCode::HkdfExpansionFailed,
+            MycoError::HkdfFillFailed => ErrorCode::HkdfFillFailed,
+            MycoError::EncryptionFailed => ErrorCode::EncryptionFailed,
+            MycoError::DecryptionFailed => ErrorCode::DecryptionFailed,
+            MycoError::NoMessageFound => ErrorCode::NoMessageFound,
+            MycoError::DecryptAuthFailed => ErrorCode::DecryptAuthFailed,
+            MycoError::SelfTestFailed(_) => ErrorCode::SelfTestFailed,
+            MycoError::TestVectorMismatch(_) => ErrorCode::TestVectorMismatch,
+            MycoError::PrfKeyUnavailable { .. } => ErrorCode::PrfKeyUnavailable,
+            MycoError::EpochOutOfRange { .. } => ErrorCode::EpochOutOfRange,
+            MycoError::TransportError { .. } => ErrorCode::TransportError,
+            MycoError::BucketNotFound => ErrorCode::BucketNotFound,
+            MycoError::MetadataBucketNotFound => ErrorCode::MetadataBucketNotFound,
+            MycoError::BucketIndexError(_) => ErrorCode::BucketIndexError,
+            MycoError::MetadataIndexError(_) => ErrorCode::MetadataIndexError,
+            MycoError::LcaNotFound => ErrorCode::LcaNotFound,
+            MycoError::SerializationFailed(_) => ErrorCode::SerializationFailed,
+            MycoError::DeserializationError(_) => ErrorCode::DeserializationError,
+            MycoError::InvalidCommand => ErrorCode::InvalidCommand,
+            MycoError::IoError(_) => ErrorCode::IoError,
+            MycoError::TlsError(_) => ErrorCode::TlsError,
+            MycoError::InvalidServerName => ErrorCode::InvalidServerName,
+            MycoError::InvalidBatchSize => ErrorCode::InvalidBatchSize,
+            MycoError::MutexLockFailed(_) => ErrorCode::MutexLockFailed,
+            MycoError::ThreadJoinFailed(_) => ErrorCode::ThreadJoinFailed,
+            MycoError::ChannelSendError(_) => ErrorCode::ChannelSendError,
+            MycoError::ChannelReceiveError(_) => ErrorCode::ChannelReceiveError,
+            MycoError::ParseIntError(_) => ErrorCode::ParseIntError,
+            MycoError::ParseFloatError(_) => ErrorCode::ParseFloatError,
+            MycoError::IncompatibleState(_) => ErrorCode::IncompatibleState,
+            MycoError::ConfigError(_) => ErrorCode::ConfigError,
+            MycoError::NetworkError { .. } => ErrorCode::NetworkError,
+            MycoError::ProtocolError(_) => ErrorCode::ProtocolError,
+            MycoError::MalformedRequest(_) => ErrorCode::MalformedRequest,
+            MycoError::CertificateError(_) => ErrorCode::CertificateError,
+            MycoError::UnknownContact => ErrorCode::UnknownContact,
+            MycoError::WriteQuotaExceeded => ErrorCode::WriteQuotaExceeded,
+            MycoError::EpochClosed { .. } => ErrorCode::EpochClosed,
+            MycoError::BatchWriteAborted { .. } => ErrorCode::BatchWriteAborted,
+            MycoError::StaleEpoch { .. } => ErrorCode::StaleEpoch,
+            MycoError::PrfKeyReused => ErrorCode::PrfKeyReused,
+            MycoError::UnknownRegistration => ErrorCode::UnknownRegistration,
+            MycoError::RegistrationLimitReached => ErrorCode::RegistrationLimitReached,
+            MycoError::MailboxAccessDenied => ErrorCode::MailboxAccessDenied,
+            MycoError::EpochInitializing => ErrorCode::EpochInitializing,
+            MycoError::ReadBudgetExceeded { .. } => ErrorCode::ReadBudgetExceeded,
+            MycoError::WriteEpochMismatch { .. } => ErrorCode::WriteEpochMismatch,
+            MycoError::InvalidWriteToken => ErrorCode::InvalidWriteToken,
+            MycoError::Remote { code, .. } => *code,
+        }
+    }
+
+    /// Rebuild an error received as `code` and `message` from a server.
+    pub fn from_remote(code: ErrorCode, message: String) -> Self {
+        match code {
+            ErrorCode::HkdfExpansionFailed => MycoError::HkdfExpansionFailed,
+            ErrorCode::HkdfFillFailed => MycoError::HkdfFillFailed,
+            ErrorCode::EncryptionFailed => MycoError::EncryptionFailed,
+            ErrorCode::DecryptionFailed => MycoError::DecryptionFailed,
+            ErrorCode::DecryptAuthFailed => MycoError::DecryptAuthFailed,
+            ErrorCode::NoMessageFound => MycoError::NoMessageFound,
+            ErrorCode::UnknownContact => MycoError::UnknownContact,
+            ErrorCode::WriteQuotaExceeded => MycoError::WriteQuotaExceeded,
+            ErrorCode::PrfKeyReused => MycoError::PrfKeyReused,
+            ErrorCode::UnknownRegistration => MycoError::UnknownRegistration,
+            ErrorCode::RegistrationLimitReached => MycoError::RegistrationLimitReached,
+            ErrorCode::MailboxAccessDenied => MycoError::MailboxAccessDenied,
+            ErrorCode::InvalidWriteToken => MycoError::InvalidWriteToken,
+            ErrorCode::EpochInitializing => MycoError::EpochInitializing,
+            ErrorCode::InvalidBatchSize => MycoError::InvalidBatchSize,
+            ErrorCode::InvalidCommand => MycoError::InvalidCommand,
+            ErrorCode::BucketNotFound => MycoError::BucketNotFound,
+            ErrorCode::MetadataBucketNotFound => MycoError::MetadataBucketNotFound,
+            ErrorCode::LcaNotFound => MycoError::LcaNotFound,
+            ErrorCode::InvalidServerName => MycoError::InvalidServerName,
+            code => MycoError::Remote { code, message },
+        }
+    }
+
+    /// Wrap the failure of an HTTP request made for `context`.
+    pub fn network(context: impl Into<String>, source: reqwest::Error) -> Self {
+        MycoError::NetworkError {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Wrap the failure of the server call `operation`.
+    pub fn transport(operation: &'static str, source: impl Into<anyhow::Error>) -> Self {
+        MycoError::TransportError {
+            operation,
+            source: source.into(),
+        }
+    }
 }
 
 impl From<std::io::Error> for MycoError {
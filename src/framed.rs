@@ -0,0 +1,224 @@
+//! Framed transport
+//!
+//! An alternative to the HTTPS endpoints: [`Command`]s are bincode-encoded and sent over a plain
+//! TCP or TLS stream as length-prefixed frames (a big-endian `u32` length followed by the payload).
+//! Each request frame is answered with exactly one response frame on the same connection, so a
+//! connection carries one request at a time.
+//!
+//! [`FramedConnection`] is the client side, implementing [`Transport`], and [`serve`] runs a
+//! listener dispatching to [`handle_server1_command`](crate::transport::handle_server1_command) or
+//! [`handle_server2_command`](crate::transport::handle_server2_command), counting its frames
+//! towards a [`BandwidthMeter`] if given one.
+
+use std::{fs::File, future::Future, io::BufReader, path::Path as FsPath, sync::Arc};
+
+use axum::async_trait;
+use bincode::Options;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+use tokio_rustls::{
+    rustls::{self, Certificate, PrivateKey, ServerName},
+    TlsAcceptor, TlsConnector,
+};
+
+use crate::{
+    bandwidth::{BandwidthMeter, Traffic},
+    constants::MAX_EPOCH_WRITE_SIZE,
+    error::MycoError,
+    network::Command,
+    transport::{into_result, Transport},
+};
+
+/// Environment variable holding the address the servers listen on for framed TLS connections.
+/// The framed listener is only started when it is set.
+pub const FRAMED_ADDR_ENV: &str = "MYCO_FRAMED_ADDR";
+
+/// Maximum size of a single frame. Writes aren't chunked over this transport, so a frame can hold
+/// a whole epoch's pathset; the buffer of a frame only grows as its bytes arrive.
+pub const MAX_FRAME_SIZE: usize = MAX_EPOCH_WRITE_SIZE;
+
+/// Write a command as a length-prefixed frame, returning the number of bytes written.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    command: &Command,
+) -> Result<usize, MycoError> {
+    let bytes = bincode::serialize(command).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+    if bytes.len() > MAX_FRAME_SIZE {
+        return Err(MycoError::ProtocolError(format!(
+            "frame of {} bytes exceeds the maximum of {}",
+            bytes.len(),
+            MAX_FRAME_SIZE
+        )));
+    }
+    writer.write_u32(bytes.len() as u32).await?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(4 + bytes.len())
+}
+
+/// Read a length-prefixed frame. Returns `None` if the peer closed the connection between frames.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Command>, MycoError> {
+    Ok(read_sized_frame(reader).await?.map(|(command, _)| command))
+}
+
+/// Like [`read_frame`], also returning the number of bytes read.
+async fn read_sized_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<(Command, usize)>, MycoError> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(MycoError::ProtocolError(format!(
+            "frame of {} bytes exceeds the maximum of {}",
+            len, MAX_FRAME_SIZE
+        )));
+    }
+
+    // The length is the peer's claim, so the buffer only grows as the bytes actually arrive.
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes).await?;
+    if bytes.len() < len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(len as u64)
+        .deserialize(&bytes)
+        .map(|command| Some((command, 4 + len)))
+        .map_err(|e| MycoError::DeserializationError(Some(e)))
+}
+
+/// Serve commands from a single connection until the peer disconnects.
+pub async fn serve_connection<S, H, Fut>(
+    mut stream: S,
+    meter: Option<&BandwidthMeter>,
+    handler: H,
+) -> Result<(), MycoError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: Fn(Command) -> Fut,
+    Fut: Future<Output = Command>,
+{
+    while let Some((command, received)) = read_sized_frame(&mut stream).await? {
+        let traffic = Traffic::of_command(&command);
+        let response = handler(command).await;
+        let sent = write_frame(&mut stream, &response).await?;
+        if let Some(meter) = meter {
+            meter.record(traffic, received, sent);
+        }
+    }
+    Ok(())
+}
+
+/// Accept connections on `listener`, optionally wrapping them in TLS, and serve each on its own task.
+pub async fn serve<H, Fut>(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    meter: Option<Arc<BandwidthMeter>>,
+    handler: H,
+) -> Result<(), MycoError>
+where
+    H: Fn(Command) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Command> + Send,
+{
+    loop {
+        let (tcp, peer) = listener.accept().await?;
+        let handler = handler.clone();
+        let tls = tls.clone();
+        let meter = meter.clone();
+        tokio::spawn(async move {
+            let meter = meter.as_deref();
+            let result = match tls {
+                Some(acceptor) => match acceptor.accept(tcp).await {
+                    Ok(stream) => serve_connection(stream, meter, handler).await,
+                    Err(e) => Err(e.into()),
+                },
+                None => serve_connection(tcp, meter, handler).await,
+            };
+            if let Err(e) = result {
+                tracing::debug!("framed connection from {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Build a TLS acceptor from PEM-encoded certificate chain and PKCS#8 private key files.
+pub fn tls_acceptor(cert_path: &FsPath, key_path: &FsPath) -> Result<TlsAcceptor, MycoError> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))?
+        .into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or_else(|| MycoError::CertificateError("no PKCS#8 private key found".to_string()))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// A byte stream that frames can be sent over.
+pub trait FramedStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> FramedStream for T {}
+
+/// A client connection to a framed transport server.
+pub struct FramedConnection {
+    stream: Mutex<Box<dyn FramedStream>>,
+}
+
+impl FramedConnection {
+    /// Wrap an established stream.
+    pub fn from_stream<S: FramedStream + 'static>(stream: S) -> Self {
+        Self {
+            stream: Mutex::new(Box::new(stream)),
+        }
+    }
+
+    /// Connect over plain TCP.
+    pub async fn connect(addr: &str) -> Result<Self, MycoError> {
+        let tcp = TcpStream::connect(addr).await?;
+        tcp.set_nodelay(true)?;
+        Ok(Self::from_stream(tcp))
+    }
+
+    /// Connect over TLS, verifying the server's certificate against `server_name`.
+    pub async fn connect_tls(
+        addr: &str,
+        connector: &TlsConnector,
+        server_name: &str,
+    ) -> Result<Self, MycoError> {
+        let server_name =
+            ServerName::try_from(server_name).map_err(|_| MycoError::InvalidServerName)?;
+        let tcp = TcpStream::connect(addr).await?;
+        tcp.set_nodelay(true)?;
+        let stream = connector.connect(server_name, tcp).await?;
+        Ok(Self::from_stream(stream))
+    }
+}
+
+#[async_trait]
+impl Transport for FramedConnection {
+    async fn call(&self, command: Command) -> Result<Command, MycoError> {
+        let mut stream = self.stream.lock().await;
+        write_frame(&mut *stream, &command).await?;
+        match read_frame(&mut *stream).await? {
+            Some(response) => into_result(response),
+            None => Err(MycoError::IoError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ))),
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Group sessions
+//!
+//! Writing a group message as one pairwise copy per member costs a write per member. A
+//! [`GroupSession`] uses sender keys instead: every member writes the group's messages under a
+//! sender key of its own, which it distributes once to each other member over the
+//! [`Mailbox`] they share, so every later message is written a single time and read by all
+//! members. Members read each other's messages under the sender keys they were sent, with the
+//! sender's client ID.
+//!
+//! Each sender key is replaced when the membership changes: a member added can't read the messages
+//! still kept in the tree, and a member removed can't read the ones written after. Membership is
+//! agreed on by the application, and every member has to apply a change to its own session, since
+//! a removed member keeps the sender keys it was already sent until their owners rekey.
+//!
+//! A client writes one message per epoch, so distributing a sender key takes one epoch per member;
+//! the session holds queued messages back until its current sender key has reached every member.
+//! Like a [`Conversation`](crate::conversation::Conversation), a session writes in every epoch it
+//! is stepped, a cover message if it has nothing to send, and all members must step their
+//! sessions once in every epoch.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::Client,
+    constants::DELTA,
+    crypto::kdf,
+    dtypes::Key,
+    envelope::{ContentType, Envelope, MAX_PAYLOAD_SIZE},
+    error::MycoError,
+    topic::Mailbox,
+};
+
+/// A sender key, as distributed to the other members.
+#[derive(Serialize, Deserialize)]
+struct SenderKey {
+    /// The group the key is for.
+    group: String,
+    /// Number of times the sender has rekeyed the group.
+    generation: u64,
+    /// The key the sender writes the group's messages under.
+    key: Key,
+}
+
+/// A message received in a group session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupMessage {
+    /// The client ID of the member who sent it.
+    pub sender: String,
+    /// The epoch it was written in.
+    pub epoch: usize,
+    /// The message.
+    pub payload: Vec<u8>,
+}
+
+/// Another member of a group.
+struct Member {
+    /// Key this side sends its sender keys to the member with.
+    channel_send: Key,
+    /// Key the member sends its sender keys with.
+    channel_recv: Key,
+    /// The member's current sender key and its generation.
+    sender_key: Option<(u64, Key)>,
+}
+
+/// The contact key `writer` distributes its sender keys for `group` over, derived from the key of
+/// a mailbox shared with another member.
+pub fn channel_key(mailbox_key: &Key, group: &str, writer: &str) -> Result<Key, MycoError> {
+    Ok(Key::new(kdf(&mailbox_key.0, &format!("GROUP {} {}", group, writer))?))
+}
+
+/// One member's view of a group, with its sender key and the other members'.
+pub struct GroupSession {
+    /// The group's name, the same for all members.
+    group: String,
+    /// The other members, by client ID.
+    members: BTreeMap<String, Member>,
+    /// This side's sender key and its generation.
+    sender_key: Key,
+    generation: u64,
+    /// Members the current sender key still has to be sent to.
+    undistributed: VecDeque<String>,
+    /// Messages waiting to be written.
+    outbox: VecDeque<Vec<u8>>,
+    /// Next epoch to read the other members' writes from.
+    next_read: usize,
+}
+
+impl GroupSession {
+    /// Start a session of `group` with the peers of `members`, in the client's current epoch. The
+    /// members must start it in the same epoch.
+    pub fn new(client: &mut Client, group: &str, members: &[&Mailbox]) -> Result<Self, MycoError> {
+        let sender_key = Key::random(&mut ChaCha20Rng::from_entropy());
+        client.setup(&sender_key)?;
+        let mut session = Self {
+            group: group.to_string(),
+            members: BTreeMap::new(),
+            sender_key,
+            generation: 0,
+            undistributed: VecDeque::new(),
+            outbox: VecDeque::new(),
+            next_read: client.epoch,
+        };
+        for mailbox in members {
+            session.insert_member(client, mailbox)?;
+        }
+        session.undistributed = session.members.keys().cloned().collect();
+        Ok(session)
+    }
+
+    /// The client IDs of the other members.
+    pub fn members(&self) -> impl Iterator<Item = &str> {
+        self.members.keys().map(String::as_str)
+    }
+
+    /// Number of times this side has rekeyed the group.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether the current sender key has been sent to every member.
+    pub fn is_distributed(&self) -> bool {
+        self.undistributed.is_empty()
+    }
+
+    /// Add the peer of `mailbox` to the group, and rekey.
+    pub fn add_member(&mut self, client: &mut Client, mailbox: &Mailbox) -> Result<(), MycoError> {
+        if self.members.contains_key(&mailbox.peer) {
+            return Err(MycoError::ConfigError(format!("{} is already a member", mailbox.peer)));
+        }
+        self.insert_member(client, mailbox)?;
+        self.rekey(client)
+    }
+
+    /// Remove the member `id` from the group, forgetting its sender key, and rekey.
+    pub fn remove_member(&mut self, client: &mut Client, id: &str) -> Result<(), MycoError> {
+        let member = self.members.remove(id).ok_or(MycoError::UnknownContact)?;
+        client.forget(&member.channel_send);
+        client.forget(&member.channel_recv);
+        if let Some((_, key)) = &member.sender_key {
+            client.forget(key);
+        }
+        self.rekey(client)
+    }
+
+    /// Queue a message to the group. It is written by a following call to [`GroupSession::step`]
+    /// once the current sender key has reached every member.
+    pub fn send(&mut self, payload: &[u8]) -> Result<(), MycoError> {
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(MycoError::ProtocolError(format!(
+                "group messages are limited to {} bytes",
+                MAX_PAYLOAD_SIZE
+            )));
+        }
+        self.outbox.push_back(payload.to_vec());
+        Ok(())
+    }
+
+    /// Run one epoch of the session: read the other members' sender keys and messages from the
+    /// epochs since the last call, then write this epoch's sender key distribution, message or
+    /// cover message. Returns the messages received, oldest first.
+    pub fn step(&mut self, client: &mut Client) -> Result<Vec<GroupMessage>, MycoError> {
+        let current = client.epoch;
+        // Writes older than DELTA epochs have expired from the tree.
+        let first = self.next_read.max(current.saturating_sub(DELTA));
+        let mut messages = Vec::new();
+        for epoch in first..current {
+            let epoch_past = current - 1 - epoch;
+            self.receive_sender_keys(client, epoch_past)?;
+            for (id, member) in &self.members {
+                let Some((_, key)) = &member.sender_key else {
+                    continue;
+                };
+                match read(client, key, id, epoch_past)? {
+                    Some(envelope) if envelope.header.content_type != ContentType::Group => {
+                        messages.push(GroupMessage {
+                            sender: id.clone(),
+                            epoch,
+                            payload: envelope.payload,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.next_read = current;
+        self.write(client)?;
+        Ok(messages)
+    }
+
+    /// Take in the sender keys the members distributed `epoch_past` epochs ago.
+    fn receive_sender_keys(&mut self, client: &mut Client, epoch_past: usize) -> Result<(), MycoError> {
+        for (id, member) in self.members.iter_mut() {
+            let Some(envelope) = read(client, &member.channel_recv, id, epoch_past)? else {
+                continue;
+            };
+            let sender_key: SenderKey = bincode::deserialize(&envelope.payload)
+                .map_err(|e| MycoError::DeserializationError(Some(e)))?;
+            let newer = member
+                .sender_key
+                .as_ref()
+                .is_none_or(|(generation, _)| sender_key.generation > *generation);
+            if sender_key.group != self.group || !newer {
+                continue;
+            }
+            client.setup(&sender_key.key)?;
+            if let Some((_, old)) = member.sender_key.replace((sender_key.generation, sender_key.key)) {
+                client.forget(&old);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write this epoch's envelope: the current sender key to the next member still missing it, or
+    /// else the next queued message, or else a cover message.
+    fn write(&mut self, client: &mut Client) -> Result<(), MycoError> {
+        if let Some(id) = self.undistributed.pop_front() {
+            let sender_key = SenderKey {
+                group: self.group.clone(),
+                generation: self.generation,
+                key: self.sender_key.clone(),
+            };
+            let payload =
+                bincode::serialize(&sender_key).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+            let envelope = Envelope::new(payload).with_content_type(ContentType::Group);
+            return match self.members.get(&id) {
+                Some(member) => client.write_envelope(&envelope, &member.channel_send),
+                None => Ok(()),
+            };
+        }
+        let envelope = match self.outbox.pop_front() {
+            Some(payload) => Envelope::new(payload),
+            None => Envelope::new(vec![]).with_content_type(ContentType::Group),
+        };
+        client.write_envelope(&envelope, &self.sender_key)
+    }
+
+    /// Replace this side's sender key, and queue the new one for every member.
+    fn rekey(&mut self, client: &mut Client) -> Result<(), MycoError> {
+        let sender_key = Key::random(&mut ChaCha20Rng::from_entropy());
+        client.setup(&sender_key)?;
+        client.forget(&std::mem::replace(&mut self.sender_key, sender_key));
+        self.generation += 1;
+        self.undistributed = self.members.keys().cloned().collect();
+        Ok(())
+    }
+
+    fn insert_member(&mut self, client: &mut Client, mailbox: &Mailbox) -> Result<(), MycoError> {
+        let channel_send = channel_key(&mailbox.key, &self.group, &client.id)?;
+        let channel_recv = channel_key(&mailbox.key, &self.group, &mailbox.peer)?;
+        client.setup(&channel_send)?;
+        client.setup(&channel_recv)?;
+        self.members.insert(
+            mailbox.peer.clone(),
+            Member {
+                channel_send,
+                channel_recv,
+                sender_key: None,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Read what `writer` wrote under `k` `epoch_past` epochs ago, if anything.
+fn read(client: &Client, k: &Key, writer: &str, epoch_past: usize) -> Result<Option<Envelope>, MycoError> {
+    match client.read_envelope(k, writer.to_string(), epoch_past) {
+        Ok(envelope) => Ok(Some(envelope)),
+        Err(MycoError::NoMessageFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,166 @@
+//! Request hardening for the RPC servers
+//!
+//! The servers accept bincode-encoded bodies from the network before any authentication takes
+//! place, so every route gets a body limit sized for the largest legitimate request it can receive
+//! (derived from the chunking constants), bodies must be sent as `application/octet-stream`, and
+//! decoding is bounded by the size of the body that was actually received. Rejected requests are
+//! answered with an [`ErrorResponse`] like any other failure. Bodies of [`STREAMED_ROUTES`] are
+//! not buffered; their limit is enforced while the handler reads them.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Request},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    constants::{
+        MAX_CHUNK_WRITE_BODY_SIZE, MAX_CHUNK_WRITE_DELTAS_BODY_SIZE, MAX_CONTROL_BODY_SIZE,
+        MAX_INDICES_BODY_SIZE,
+        MAX_NOTIFICATIONS_BODY_SIZE, MAX_PENDING_EPOCHS_BODY_SIZE, MAX_QUEUE_WRITE_BODY_SIZE, MAX_STORAGE_REPORT_BODY_SIZE,
+        MAX_WRITE_BODY_SIZE,
+    },
+    error::MycoError,
+    rpc_types::ErrorResponse,
+    tenant,
+};
+
+/// The only content type accepted for non-empty request bodies.
+pub const BINCODE_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// The largest body limit of any route, used as the ceiling for axum's body extractors.
+pub const MAX_BODY_SIZE: usize = const_max(
+    const_max(MAX_CHUNK_WRITE_BODY_SIZE, MAX_CHUNK_WRITE_DELTAS_BODY_SIZE),
+    const_max(
+        MAX_WRITE_BODY_SIZE,
+        const_max(MAX_INDICES_BODY_SIZE, MAX_QUEUE_WRITE_BODY_SIZE),
+    ),
+);
+
+const fn const_max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Routes whose handlers decode the request body as it arrives, see [`crate::streaming`].
+pub const STREAMED_ROUTES: &[&str] = &["/chunk_write", "/chunk_write_deltas"];
+
+/// Maximum request body size for the given route.
+///
+/// Unknown routes fall back to the control limit, so new endpoints have to opt into larger bodies.
+pub fn max_body_size(route: &str) -> usize {
+    match route {
+        "/write" => MAX_WRITE_BODY_SIZE,
+        "/chunk_write" => MAX_CHUNK_WRITE_BODY_SIZE,
+        "/chunk_write_deltas" => MAX_CHUNK_WRITE_DELTAS_BODY_SIZE,
+        "/read_paths" | "/read_paths_client" | "/chunk_read_paths_client"
+        | "/store_path_indices"
+        | "/store_path_leaves"
+        | "/prefetch_path_leaves" => MAX_INDICES_BODY_SIZE,
+        "/queue_write" => MAX_QUEUE_WRITE_BODY_SIZE,
+        "/notifications" => MAX_NOTIFICATIONS_BODY_SIZE,
+        "/pending_epochs" => MAX_PENDING_EPOCHS_BODY_SIZE,
+        "/storage" => MAX_STORAGE_REPORT_BODY_SIZE,
+        _ => MAX_CONTROL_BODY_SIZE,
+    }
+}
+
+/// Apply the hardened layer set to a router.
+///
+/// This enforces the per-route body limits and the content type check, and replaces any body limit
+/// previously configured on the router.
+pub fn harden<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(middleware::from_fn(enforce_request_limits))
+        .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+}
+
+/// A response rejecting a malformed request with `status`.
+fn reject(status: StatusCode, reason: String) -> Response {
+    (status, ErrorResponse::from(MycoError::MalformedRequest(reason))).into_response()
+}
+
+/// Middleware rejecting requests with a non-bincode content type or an oversized body.
+pub async fn enforce_request_limits(request: Request, next: Next) -> Result<Response, Response> {
+    let limit = max_body_size(tenant::strip_tenant(request.uri().path()));
+    let too_large = || {
+        reject(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("body exceeds {} bytes", limit),
+        )
+    };
+    let (parts, body) = request.into_parts();
+
+    // Reject early when the client announces a body that is too large.
+    if let Some(length) = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+    {
+        if length > limit {
+            return Err(too_large());
+        }
+    }
+
+    if STREAMED_ROUTES.contains(&tenant::strip_tenant(parts.uri.path())) {
+        check_content_type(&parts.headers).map_err(|response| *response)?;
+        let body = Body::new(http_body_util::Limited::new(body, limit));
+        return Ok(next.run(Request::from_parts(parts, body)).await);
+    }
+
+    let bytes = axum::body::to_bytes(body, limit)
+        .await
+        .map_err(|_| too_large())?;
+
+    if !bytes.is_empty() {
+        check_content_type(&parts.headers).map_err(|response| *response)?;
+    }
+
+    Ok(next.run(Request::from_parts(parts, Body::from(bytes))).await)
+}
+
+/// Reject a request that doesn't declare a bincode body.
+fn check_content_type(headers: &HeaderMap) -> Result<(), Box<Response>> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    if content_type != Some(BINCODE_CONTENT_TYPE) {
+        return Err(Box::new(reject(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("content type must be {}", BINCODE_CONTENT_TYPE),
+        )));
+    }
+    Ok(())
+}
+
+/// Decode a bincode request body, refusing to read past the end of the received bytes.
+///
+/// Uses the same encoding as `bincode::deserialize`, but with a byte limit so that a forged length
+/// prefix cannot make the decoder allocate more than the body could possibly contain.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ErrorResponse> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)
+        .map_err(|e| MycoError::MalformedRequest(e.to_string()).into())
+}
+
+/// Encode a response body.
+pub fn encode<T: Serialize>(response: &T) -> Result<Bytes, ErrorResponse> {
+    bincode::serialize(response)
+        .map(Bytes::from)
+        .map_err(|e| MycoError::SerializationFailed(Some(e)).into())
+}
@@ -0,0 +1,151 @@
+//! Idempotency keys
+//!
+//! Requests that change Server1's state (`queue_write`, `batch_init` and `batch_write`) carry a
+//! random [`IdempotencyKey`] picked by the client when it builds the request, in the request body
+//! or, for `batch_write` which has none, in [`IDEMPOTENCY_KEY_HEADER`]. Server1 keeps the response
+//! to each key in a [`ResponseCache`] for [`IDEMPOTENCY_WINDOW`], so a request resent after a
+//! timeout is answered from the cache instead of being applied twice. Only successful responses
+//! are kept: a failed request had no effect and is simply run again.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{body::Bytes, http::HeaderMap};
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::error::MycoError;
+
+/// Request header carrying the idempotency key of a request without a body, hex encoded.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "x-myco-idempotency-key";
+
+/// How long Server1 answers a repeated idempotency key from its cache.
+pub const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(60);
+
+/// Maximum number of cached responses. The oldest are dropped first.
+pub const MAX_CACHED_RESPONSES: usize = 1 << 16;
+
+/// A client-generated key identifying one logical request across its retries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IdempotencyKey(pub [u8; 16]);
+
+impl IdempotencyKey {
+    /// A fresh random key.
+    pub fn random<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut key = [0; 16];
+        rng.fill_bytes(&mut key);
+        IdempotencyKey(key)
+    }
+
+    /// The key in [`IDEMPOTENCY_KEY_HEADER`], if any.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, MycoError> {
+        let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+            return Ok(None);
+        };
+        value
+            .to_str()
+            .map_err(|_| MycoError::MalformedRequest("invalid idempotency key".to_string()))?
+            .parse()
+            .map(Some)
+    }
+}
+
+impl std::fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl FromStr for IdempotencyKey {
+    type Err = MycoError;
+
+    fn from_str(s: &str) -> Result<Self, MycoError> {
+        let mut key = [0; 16];
+        hex::decode_to_slice(s, &mut key)
+            .map_err(|_| MycoError::MalformedRequest("invalid idempotency key".to_string()))?;
+        Ok(IdempotencyKey(key))
+    }
+}
+
+/// Responses of recent requests by route and idempotency key.
+pub struct ResponseCache {
+    window: Duration,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    responses: HashMap<(&'static str, IdempotencyKey), Bytes>,
+    /// Keys in the order they were stored, which is also the order they expire in.
+    order: VecDeque<(Instant, &'static str, IdempotencyKey)>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(IDEMPOTENCY_WINDOW, MAX_CACHED_RESPONSES)
+    }
+}
+
+impl ResponseCache {
+    /// A cache keeping up to `capacity` responses for `window` each.
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Self {
+            window,
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// The response stored for `key` on `route`, if it's still within the window. Requests
+    /// without a key are never answered from the cache.
+    pub fn get(&self, route: &'static str, key: Option<IdempotencyKey>) -> Option<Bytes> {
+        let key = key?;
+        let mut entries = self.entries.lock().unwrap();
+        entries.expire(self.window, self.capacity);
+        entries.responses.get(&(route, key)).cloned()
+    }
+
+    /// Store the response to `key` on `route`. Does nothing for requests without a key.
+    pub fn insert(&self, route: &'static str, key: Option<IdempotencyKey>, response: &Bytes) {
+        let Some(key) = key else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .responses
+            .insert((route, key), response.clone())
+            .is_none()
+        {
+            entries.order.push_back((Instant::now(), route, key));
+        }
+        entries.expire(self.window, self.capacity);
+    }
+
+    /// Number of responses held.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().responses.len()
+    }
+
+    /// Whether no responses are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Entries {
+    /// Drop responses older than `window`, and the oldest ones beyond `capacity`.
+    fn expire(&mut self, window: Duration, capacity: usize) {
+        while let Some(&(stored, route, key)) = self.order.front() {
+            if stored.elapsed() < window && self.order.len() <= capacity {
+                break;
+            }
+            self.order.pop_front();
+            self.responses.remove(&(route, key));
+        }
+    }
+}
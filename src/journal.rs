@@ -0,0 +1,376 @@
+//! Server2 write-ahead journal
+//!
+//! Server2 keeps its tree in memory and only flushes it to a snapshot at shutdown (see
+//! [`crate::serve::SNAPSHOT_PATH_ENV`]), so a crash loses every epoch written since. With a
+//! [`Journal`], Server2 appends each chunk it receives, with its digest, to an append-only file
+//! before applying it, and an epoch's PRF key is only published once a [`JournalRecord::Commit`]
+//! naming the digests of all of the epoch's chunks is in the journal.
+//!
+//! A restarted Server2 loads its snapshot and replays the journal on top
+//! ([`Server2::replay_journal`](crate::server2::Server2::replay_journal)): committed epochs are
+//! rolled forward from their journaled chunks, and an epoch whose chunks were being applied when
+//! the server crashed, and so was never committed, is rolled back by not being replayed. Server1
+//! then writes that epoch again. The journal is compacted whenever a snapshot is saved.
+//!
+//! How often the journal is flushed to disk is set by [`JournalSync`]: a record is only durable
+//! once synced, so [`JournalSync::Never`] leaves it to the OS and may lose the last epochs of a
+//! machine crash, though not of a process crash.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Write},
+    path::{Path as FsPath, PathBuf},
+    str::FromStr,
+};
+
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dtypes::{Bucket, Key},
+    error::MycoError,
+    tenant::{self, TenantId},
+};
+
+/// Environment variable holding the path of Server2's journal. No journal is kept when unset.
+pub const JOURNAL_PATH_ENV: &str = "MYCO_JOURNAL_PATH";
+
+/// Environment variable holding the [`JournalSync`] policy: `always`, `commit` (the default) or
+/// `never`.
+pub const JOURNAL_FSYNC_ENV: &str = "MYCO_JOURNAL_FSYNC";
+
+/// When the journal is flushed to disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JournalSync {
+    /// After every record, so no chunk acknowledged to Server1 is lost.
+    Always,
+    /// After every commit, before the epoch's PRF key is published.
+    #[default]
+    Commit,
+    /// Never, leaving it to the OS.
+    Never,
+}
+
+impl FromStr for JournalSync {
+    type Err = MycoError;
+
+    fn from_str(s: &str) -> Result<Self, MycoError> {
+        match s {
+            "always" => Ok(Self::Always),
+            "commit" => Ok(Self::Commit),
+            "never" => Ok(Self::Never),
+            _ => Err(MycoError::ConfigError(format!(
+                "{} must be always, commit or never, got {}",
+                JOURNAL_FSYNC_ENV, s
+            ))),
+        }
+    }
+}
+
+/// An entry of the journal.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JournalRecord {
+    /// The first chunk of `epoch` is about to be applied to the buckets at `pathset`.
+    Begin {
+        /// The epoch being written.
+        epoch: u64,
+        /// The pathset indices the epoch's buckets are written to.
+        pathset: Vec<usize>,
+    },
+    /// A chunk of the epoch begun last, as it is applied.
+    Chunk {
+        /// The epoch being written.
+        epoch: u64,
+        /// The chunk's index in the pathset.
+        chunk_idx: usize,
+        /// The SHA-256 digest of the serialized buckets.
+        digest: Vec<u8>,
+        /// The chunk's buckets.
+        buckets: Vec<Bucket>,
+    },
+    /// All chunks of `epoch` were applied and its PRF key is about to be published.
+    Commit {
+        /// The epoch written.
+        epoch: u64,
+        /// The index and digest of every chunk of the epoch. A resent chunk counts once, by its
+        /// last digest.
+        chunks: Vec<(usize, Vec<u8>)>,
+        /// The epoch's PRF key.
+        prf_key: Key,
+    },
+}
+
+impl JournalRecord {
+    /// The epoch the record belongs to.
+    pub fn epoch(&self) -> u64 {
+        match self {
+            Self::Begin { epoch, .. } | Self::Chunk { epoch, .. } | Self::Commit { epoch, .. } => {
+                *epoch
+            }
+        }
+    }
+}
+
+/// A committed epoch, assembled from the journal, to be applied on top of the epoch before it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommittedEpoch {
+    /// The epoch.
+    pub epoch: u64,
+    /// The pathset indices its buckets are written to.
+    pub pathset: Vec<usize>,
+    /// Its chunks, by chunk index.
+    pub chunks: BTreeMap<usize, Vec<Bucket>>,
+    /// Its PRF key.
+    pub prf_key: Key,
+}
+
+/// The SHA-256 digest of a chunk of `buckets`.
+pub fn chunk_digest(buckets: &[Bucket]) -> Result<Vec<u8>, MycoError> {
+    let bytes = bincode::serialize(buckets).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+    Ok(digest::digest(&digest::SHA256, &bytes).as_ref().to_vec())
+}
+
+/// The committed epochs in `records`, in order. Epochs begun but never committed are left out.
+pub fn committed_epochs(records: Vec<JournalRecord>) -> Result<Vec<CommittedEpoch>, MycoError> {
+    let mut committed = vec![];
+    let mut pathset: Option<(u64, Vec<usize>)> = None;
+    let mut chunks: BegunChunks = BTreeMap::new();
+    for record in records {
+        match record {
+            JournalRecord::Begin { epoch, pathset: p } => {
+                pathset = Some((epoch, p));
+                chunks.clear();
+            }
+            JournalRecord::Chunk {
+                epoch,
+                chunk_idx,
+                digest,
+                buckets,
+            } => {
+                if pathset.as_ref().is_some_and(|(begun, _)| *begun == epoch) {
+                    chunks.insert((chunk_idx, digest), buckets);
+                }
+            }
+            JournalRecord::Commit {
+                epoch,
+                chunks: digests,
+                prf_key,
+            } => {
+                let pathset = match pathset.take() {
+                    Some((begun, pathset)) if begun == epoch => pathset,
+                    _ if digests.is_empty() => vec![],
+                    _ => return Err(journal_corrupt(epoch, "commit without its chunks")),
+                };
+                let mut epoch_chunks = BTreeMap::new();
+                for (chunk_idx, digest) in digests {
+                    let buckets = chunks
+                        .remove(&(chunk_idx, digest.clone()))
+                        .ok_or_else(|| journal_corrupt(epoch, "a committed chunk is missing"))?;
+                    if chunk_digest(&buckets)? != digest {
+                        return Err(journal_corrupt(epoch, "a chunk doesn't match its digest"));
+                    }
+                    epoch_chunks.insert(chunk_idx, buckets);
+                }
+                chunks.clear();
+                committed.push(CommittedEpoch {
+                    epoch,
+                    pathset,
+                    chunks: epoch_chunks,
+                    prf_key,
+                });
+            }
+        }
+    }
+    Ok(committed)
+}
+
+/// Chunks of the epoch begun last, by index and digest.
+type BegunChunks = BTreeMap<(usize, Vec<u8>), Vec<Bucket>>;
+
+fn journal_corrupt(epoch: u64, reason: &str) -> MycoError {
+    MycoError::IncompatibleState(format!("journal entry of epoch {}: {}", epoch, reason))
+}
+
+/// Server2's append-only journal file.
+///
+/// Records are bincode-encoded and prefixed with their length. A record cut short by a crash is
+/// dropped, with everything after it, when the journal is opened.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    sync: JournalSync,
+    /// The epoch being written and the digests of its chunks so far.
+    pending: Option<(u64, BTreeMap<usize, Vec<u8>>)>,
+}
+
+impl Journal {
+    /// Open the journal at `path`, creating it if needed, with the records it holds.
+    pub fn open(path: &FsPath, sync: JournalSync) -> Result<(Self, Vec<JournalRecord>), MycoError> {
+        let (records, valid_len) = match File::open(path) {
+            Ok(file) => read_records(file)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (vec![], 0),
+            Err(e) => return Err(MycoError::IoError(e)),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(MycoError::IoError)?;
+        // Drop a record cut short by a crash, so new records follow the last whole one.
+        file.set_len(valid_len).map_err(MycoError::IoError)?;
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                file,
+                sync,
+                pending: None,
+            },
+            records,
+        ))
+    }
+
+    /// Record `buckets`, chunk `chunk_idx` of `epoch` written to `pathset`, before it is applied.
+    pub fn record_chunk(
+        &mut self,
+        epoch: u64,
+        pathset: &[usize],
+        chunk_idx: usize,
+        buckets: &[Bucket],
+    ) -> Result<(), MycoError> {
+        if self.pending.as_ref().map(|(pending, _)| *pending) != Some(epoch) {
+            self.append(&JournalRecord::Begin {
+                epoch,
+                pathset: pathset.to_vec(),
+            })?;
+            self.pending = Some((epoch, BTreeMap::new()));
+        }
+        let digest = chunk_digest(buckets)?;
+        self.append(&JournalRecord::Chunk {
+            epoch,
+            chunk_idx,
+            digest: digest.clone(),
+            buckets: buckets.to_vec(),
+        })?;
+        if let Some((_, chunks)) = self.pending.as_mut() {
+            chunks.insert(chunk_idx, digest);
+        }
+        Ok(())
+    }
+
+    /// Forget the chunks recorded so far, as the pathset they were written to was replaced. The
+    /// next chunk begins its epoch anew.
+    pub fn restart_epoch(&mut self) {
+        self.pending = None;
+    }
+
+    /// Commit `epoch` with the chunks recorded for it, before its PRF key is published.
+    pub fn commit(&mut self, epoch: u64, prf_key: &Key) -> Result<(), MycoError> {
+        let chunks = match self.pending.take() {
+            Some((pending, chunks)) if pending == epoch => chunks.into_iter().collect(),
+            _ => vec![],
+        };
+        self.append(&JournalRecord::Commit {
+            epoch,
+            chunks,
+            prf_key: prf_key.clone(),
+        })?;
+        if self.sync == JournalSync::Commit {
+            self.file.sync_data().map_err(MycoError::IoError)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the records of epochs before `epoch`, once a snapshot holds them.
+    pub fn compact(&mut self, epoch: u64) -> Result<(), MycoError> {
+        let (records, _) = read_records(File::open(&self.path).map_err(MycoError::IoError)?)?;
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path).map_err(MycoError::IoError)?;
+        for record in records.iter().filter(|record| record.epoch() >= epoch) {
+            tmp.write_all(&encode_record(record)?)
+                .map_err(MycoError::IoError)?;
+        }
+        tmp.sync_data().map_err(MycoError::IoError)?;
+        fs::rename(&tmp_path, &self.path).map_err(MycoError::IoError)?;
+        self.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(MycoError::IoError)?;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> Result<(), MycoError> {
+        self.file
+            .write_all(&encode_record(record)?)
+            .map_err(MycoError::IoError)?;
+        if self.sync == JournalSync::Always {
+            self.file.sync_data().map_err(MycoError::IoError)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_record(record: &JournalRecord) -> Result<Vec<u8>, MycoError> {
+    let body = bincode::serialize(record).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+    let mut bytes = (body.len() as u64).to_le_bytes().to_vec();
+    bytes.extend(body);
+    Ok(bytes)
+}
+
+/// The whole records in `file` and the length they take up.
+fn read_records(file: File) -> Result<(Vec<JournalRecord>, u64), MycoError> {
+    let mut reader = BufReader::new(file);
+    let mut records = vec![];
+    let mut valid_len = 0;
+    loop {
+        let mut len = [0u8; 8];
+        if reader.read_exact(&mut len).is_err() {
+            break;
+        }
+        let len = u64::from_le_bytes(len);
+        let mut body = vec![];
+        let read = (&mut reader)
+            .take(len)
+            .read_to_end(&mut body)
+            .map_err(MycoError::IoError)?;
+        if read as u64 != len {
+            break;
+        }
+        match bincode::deserialize(&body) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+        valid_len += 8 + len;
+    }
+    Ok((records, valid_len))
+}
+
+/// The journal configured by [`JOURNAL_PATH_ENV`] and [`JOURNAL_FSYNC_ENV`], with its records.
+pub fn journal_from_env() -> Result<Option<(Journal, Vec<JournalRecord>)>, MycoError> {
+    open_from_env(None)
+}
+
+/// The journal of `tenant`, kept next to the one configured by [`JOURNAL_PATH_ENV`] (see
+/// [`tenant::tenant_file`]), with its records.
+pub fn tenant_journal_from_env(
+    tenant: &TenantId,
+) -> Result<Option<(Journal, Vec<JournalRecord>)>, MycoError> {
+    open_from_env(Some(tenant))
+}
+
+fn open_from_env(
+    tenant: Option<&TenantId>,
+) -> Result<Option<(Journal, Vec<JournalRecord>)>, MycoError> {
+    let Ok(path) = std::env::var(JOURNAL_PATH_ENV) else {
+        return Ok(None);
+    };
+    let sync = match std::env::var(JOURNAL_FSYNC_ENV) {
+        Ok(value) => value.trim().to_lowercase().parse()?,
+        Err(_) => JournalSync::default(),
+    };
+    let path = match tenant {
+        Some(tenant) => tenant::tenant_file(FsPath::new(&path), tenant),
+        None => PathBuf::from(path),
+    };
+    Journal::open(&path, sync).map(Some)
+}
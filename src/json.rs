@@ -0,0 +1,182 @@
+//! JSON debug codec
+//!
+//! With the `debug-json` feature, [`debug_routes`] mirrors the RPC routes under [`JSON_PREFIX`],
+//! exchanging the same [`crate::rpc_types`] as JSON instead of bincode, so the servers can be
+//! poked with curl and protocol tooling can be written in other languages. A JSON request is
+//! re-encoded as bincode and handed to the bincode route, so it goes through the same hardening,
+//! metrics and authentication; the response, or its [`ErrorResponse`], is re-encoded as JSON.
+//!
+//! Both bodies are buffered. Routes answering with [`crate::streaming::framed_response`] have no
+//! JSON counterpart.
+
+use std::sync::Arc;
+
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::any,
+    Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+
+use crate::{
+    error::MycoError,
+    hardening::{self, BINCODE_CONTENT_TYPE},
+    rpc_types::ErrorResponse,
+};
+
+/// Prefix of the JSON routes.
+pub const JSON_PREFIX: &str = "/json";
+
+/// Content type of JSON request and response bodies.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// JSON spells every byte out as a decimal number, so a JSON request may be this many times larger
+/// than the bincode body limit of its route.
+const JSON_EXPANSION: usize = 4;
+
+type Transcoder = fn(&[u8]) -> Result<Vec<u8>, MycoError>;
+
+/// A bincode route mirrored as JSON, with the types of its request and response bodies.
+#[derive(Clone)]
+pub struct JsonRoute {
+    method: Method,
+    path: &'static str,
+    request: Option<Transcoder>,
+    response: Transcoder,
+}
+
+impl JsonRoute {
+    /// A route taking a `Req` body and answering with `Resp`.
+    pub fn new<Req, Resp>(method: Method, path: &'static str) -> Self
+    where
+        Req: DeserializeOwned + Serialize,
+        Resp: DeserializeOwned + Serialize,
+    {
+        Self {
+            method,
+            path,
+            request: Some(json_to_bincode::<Req>),
+            response: bincode_to_json::<Resp>,
+        }
+    }
+
+    /// A route without a request body answering with `Resp`.
+    pub fn bodyless<Resp>(method: Method, path: &'static str) -> Self
+    where
+        Resp: DeserializeOwned + Serialize,
+    {
+        Self {
+            method,
+            path,
+            request: None,
+            response: bincode_to_json::<Resp>,
+        }
+    }
+}
+
+fn json_to_bincode<T: DeserializeOwned + Serialize>(json: &[u8]) -> Result<Vec<u8>, MycoError> {
+    let value: T =
+        serde_json::from_slice(json).map_err(|e| MycoError::MalformedRequest(e.to_string()))?;
+    bincode::serialize(&value).map_err(|e| MycoError::SerializationFailed(Some(e)))
+}
+
+fn bincode_to_json<T: DeserializeOwned + Serialize>(bytes: &[u8]) -> Result<Vec<u8>, MycoError> {
+    let value: T =
+        bincode::deserialize(bytes).map_err(|e| MycoError::DeserializationError(Some(e)))?;
+    serde_json::to_vec(&value).map_err(|_| MycoError::SerializationFailed(None))
+}
+
+/// Mirror `routes` of `app` under [`JSON_PREFIX`].
+pub fn debug_routes(app: Router, routes: &[JsonRoute]) -> Router {
+    let mut paths: Vec<&'static str> = routes.iter().map(|route| route.path).collect();
+    paths.sort_unstable();
+    paths.dedup();
+
+    let mut json = Router::new();
+    for path in paths {
+        let methods: Arc<Vec<JsonRoute>> = Arc::new(
+            routes
+                .iter()
+                .filter(|route| route.path == path)
+                .cloned()
+                .collect(),
+        );
+        let inner = app.clone();
+        json = json.route(
+            path,
+            any(move |request: Request| {
+                let inner = inner.clone();
+                let methods = methods.clone();
+                async move {
+                    match forward(inner, &methods, request).await {
+                        Ok(response) => response,
+                        Err(e) => error_response(ErrorResponse::from(e)),
+                    }
+                }
+            }),
+        );
+    }
+    app.nest(JSON_PREFIX, json)
+}
+
+/// Re-encode a JSON request for the bincode route and its response as JSON.
+async fn forward(inner: Router, methods: &[JsonRoute], request: Request) -> Result<Response, MycoError> {
+    let (mut parts, body) = request.into_parts();
+    let Some(route) = methods.iter().find(|route| route.method == parts.method) else {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    };
+
+    parts.uri = Uri::from_static(route.path);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = match route.request {
+        Some(to_bincode) => {
+            let limit = hardening::max_body_size(route.path) * JSON_EXPANSION;
+            let json = body::to_bytes(body, limit)
+                .await
+                .map_err(|_| MycoError::MalformedRequest(format!("body exceeds {} bytes", limit)))?;
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(BINCODE_CONTENT_TYPE),
+            );
+            Body::from(to_bincode(&json)?)
+        }
+        None => {
+            parts.headers.remove(header::CONTENT_TYPE);
+            Body::empty()
+        }
+    };
+
+    let response = match inner.oneshot(Request::from_parts(parts, body)).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    };
+    let (mut parts, body) = response.into_parts();
+    let bytes = body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| MycoError::ProtocolError(format!("failed to read response: {}", e)))?;
+    let json = if parts.status.is_success() {
+        (route.response)(&bytes)?
+    } else if bytes.is_empty() {
+        return Ok(Response::from_parts(parts, Body::empty()));
+    } else {
+        bincode_to_json::<ErrorResponse>(&bytes)?
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(JSON_CONTENT_TYPE),
+    );
+    Ok(Response::from_parts(parts, Body::from(json)))
+}
+
+fn error_response(error: ErrorResponse) -> Response {
+    let status = error.status();
+    match serde_json::to_vec(&error) {
+        Ok(json) => (status, [(header::CONTENT_TYPE, JSON_CONTENT_TYPE)], json).into_response(),
+        Err(_) => status.into_response(),
+    }
+}
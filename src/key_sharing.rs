@@ -0,0 +1,351 @@
+//! Key sharing across S1 replicas
+//!
+//! A Server1 keeps k_s1_t in memory only, so a crash mid-epoch loses the key its open batch places
+//! writes with. [`SharedSecrets`] keeps two 2-of-2 XOR shares of the key instead: one in a file on
+//! the server's own disk ([`FileShareHolder`]) and one with a second S1 process
+//! ([`RemoteShareHolder`], served from that replica's [`ShareVault`] at `/admin/key_share`).
+//! Neither the disk nor the replica learns anything about the key on its own, while a server
+//! restarted mid-epoch recovers it from the two shares with [`SharedSecrets::recover`] and opens
+//! its next batch with it, so writes clients retry land where they would have before the crash.
+//!
+//! The shares are combined again at batch_write, before the key is published to Server2, and
+//! marked as released: an epoch whose shares were lost or don't add up to its key fails its batch
+//! write, and a key that may have been published is never recovered.
+
+use std::{
+    path::{Path as FsPath, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use axum::async_trait;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::ADMIN_TOKEN_ENV,
+    dtypes::{Block, Key},
+    error::MycoError,
+    secrets::{seal_key, unseal_key, HostSecrets, SecretCompute},
+    server1::run_sync,
+    tls,
+    utils,
+};
+
+/// Environment variable holding the path of the file this server keeps its own key share in.
+pub const KEY_SHARE_PATH_ENV: &str = "MYCO_KEY_SHARE_PATH";
+
+/// Environment variable holding the base URL of the S1 replica keeping the other key share.
+pub const KEY_SHARE_PEER_ENV: &str = "MYCO_KEY_SHARE_PEER";
+
+/// Environment variable holding the admin token of the replica at [`KEY_SHARE_PEER_ENV`], by
+/// default this server's own [`ADMIN_TOKEN_ENV`].
+pub const KEY_SHARE_PEER_TOKEN_ENV: &str = "MYCO_KEY_SHARE_PEER_TOKEN";
+
+/// One share of an epoch key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyShare {
+    /// Which key the share belongs to, counting the keys drawn by the server.
+    pub rotation: u64,
+    /// The share itself.
+    pub share: Key,
+    /// Whether the key was released to be published, after which it mustn't be used again.
+    pub released: bool,
+}
+
+/// Split `key` into two shares that XOR to it.
+pub fn split_key<R: RngCore>(key: &Key, rng: &mut R) -> (Key, Key) {
+    let a: Vec<u8> = (0..key.0.len()).map(|_| rng.gen()).collect();
+    let b = key.0.iter().zip(&a).map(|(k, a)| k ^ a).collect();
+    (Key::new(a), Key::new(b))
+}
+
+/// The key two shares of the same rotation make up.
+pub fn combine_shares(a: &KeyShare, b: &KeyShare) -> Result<Key, MycoError> {
+    if a.rotation != b.rotation || a.share.0.len() != b.share.0.len() {
+        return Err(MycoError::ProtocolError(format!(
+            "key shares of rotations {} and {} don't match",
+            a.rotation, b.rotation
+        )));
+    }
+    Ok(Key::new(
+        a.share.0.iter().zip(&b.share.0).map(|(a, b)| a ^ b).collect(),
+    ))
+}
+
+fn serialize_share(share: &KeyShare) -> Result<Vec<u8>, MycoError> {
+    bincode::serialize(share).map_err(|e| MycoError::SerializationFailed(Some(e)))
+}
+
+fn deserialize_share(bytes: &[u8]) -> Result<KeyShare, MycoError> {
+    bincode::deserialize(bytes).map_err(|e| MycoError::DeserializationError(Some(e)))
+}
+
+/// Somewhere a key share is kept.
+#[async_trait]
+pub trait ShareHolder: Send + Sync {
+    /// Keep `share`, replacing the share held so far.
+    async fn hold(&self, share: KeyShare) -> Result<(), MycoError>;
+
+    /// The share held, if any.
+    async fn share(&self) -> Result<Option<KeyShare>, MycoError>;
+}
+
+/// A key share kept in memory, by the replica holding a share for its peer.
+#[derive(Default)]
+pub struct ShareVault {
+    share: Mutex<Option<KeyShare>>,
+}
+
+#[async_trait]
+impl ShareHolder for ShareVault {
+    async fn hold(&self, share: KeyShare) -> Result<(), MycoError> {
+        *self.share.lock()? = Some(share);
+        Ok(())
+    }
+
+    async fn share(&self) -> Result<Option<KeyShare>, MycoError> {
+        Ok(self.share.lock()?.clone())
+    }
+}
+
+/// A key share kept in a file, surviving restarts of the server.
+pub struct FileShareHolder {
+    path: PathBuf,
+}
+
+impl FileShareHolder {
+    /// Keep the share in the file at `path`.
+    pub fn new(path: impl AsRef<FsPath>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+#[async_trait]
+impl ShareHolder for FileShareHolder {
+    async fn hold(&self, share: KeyShare) -> Result<(), MycoError> {
+        // Written durably, so a crash or power loss never leaves half a share.
+        utils::write_durably(&self.path, &serialize_share(&share)?)?;
+        Ok(())
+    }
+
+    async fn share(&self) -> Result<Option<KeyShare>, MycoError> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => Ok(Some(deserialize_share(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// The key share kept by another S1 process, reached through its admin API.
+pub struct RemoteShareHolder {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl RemoteShareHolder {
+    /// Keep the share with the S1 replica at `base_url`, authenticating with its admin `token`.
+    pub fn new(base_url: &str, token: String) -> Result<Self, MycoError> {
+        let (builder, base_url) = tls::client_trust().http_client_builder(base_url)?;
+        let client = builder
+            .build()
+            .map_err(|e| MycoError::ConfigError(format!("building the key share client: {}", e)))?;
+        Ok(Self {
+            client,
+            url: format!("{}/admin/key_share", base_url),
+            token,
+        })
+    }
+}
+
+#[async_trait]
+impl ShareHolder for RemoteShareHolder {
+    async fn hold(&self, share: KeyShare) -> Result<(), MycoError> {
+        self.client
+            .post(&self.url)
+            .bearer_auth(&self.token)
+            .body(serialize_share(&share)?)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| MycoError::transport("key_share", e))?;
+        Ok(())
+    }
+
+    async fn share(&self) -> Result<Option<KeyShare>, MycoError> {
+        let response = self
+            .client
+            .get(&self.url)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| MycoError::transport("key_share", e))?;
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| MycoError::transport("key_share", e))?;
+        bincode::deserialize(&bytes).map_err(|e| MycoError::DeserializationError(Some(e)))
+    }
+}
+
+/// [`SecretCompute`] keeping the epoch key shared between two [`ShareHolder`]s.
+pub struct SharedSecrets {
+    holders: [Arc<dyn ShareHolder>; 2],
+    /// The current key and its rotation.
+    key: RwLock<(u64, Key)>,
+    /// Whether the current key was recovered and the next rotation keeps it.
+    recovered: AtomicBool,
+    host: HostSecrets,
+}
+
+impl SharedSecrets {
+    /// Share the keys drawn from now on between `holders`.
+    pub fn new(holders: [Arc<dyn ShareHolder>; 2]) -> Self {
+        Self {
+            holders,
+            key: RwLock::new((0, Key::new(vec![]))),
+            recovered: AtomicBool::new(false),
+            host: HostSecrets::default(),
+        }
+    }
+
+    /// Resume with the key whose shares `holders` keep, e.g. after a restart mid-epoch. The next
+    /// batch is opened with that key rather than a fresh one. Keys already released are refused.
+    pub async fn recover(holders: [Arc<dyn ShareHolder>; 2]) -> Result<Self, MycoError> {
+        let (a, b) = Self::shares(&holders).await?;
+        if a.released || b.released {
+            return Err(MycoError::ProtocolError(format!(
+                "epoch key {} was already released",
+                a.rotation
+            )));
+        }
+        let key = combine_shares(&a, &b)?;
+        let secrets = Self::new(holders);
+        *secrets.key.write()? = (a.rotation, key);
+        secrets.recovered.store(true, Ordering::SeqCst);
+        Ok(secrets)
+    }
+
+    /// Shares between this server's [`KEY_SHARE_PATH_ENV`] and the replica at
+    /// [`KEY_SHARE_PEER_ENV`], recovering the key they keep if there is one. `None` if no peer is
+    /// set.
+    pub async fn from_env() -> Result<Option<Self>, MycoError> {
+        let Ok(peer) = std::env::var(KEY_SHARE_PEER_ENV) else {
+            return Ok(None);
+        };
+        let path = std::env::var(KEY_SHARE_PATH_ENV).map_err(|_| {
+            MycoError::ConfigError(format!("{} requires {}", KEY_SHARE_PEER_ENV, KEY_SHARE_PATH_ENV))
+        })?;
+        let token = std::env::var(KEY_SHARE_PEER_TOKEN_ENV)
+            .or_else(|_| std::env::var(ADMIN_TOKEN_ENV))
+            .map_err(|_| {
+                MycoError::ConfigError(format!("{} requires {}", KEY_SHARE_PEER_ENV, KEY_SHARE_PEER_TOKEN_ENV))
+            })?;
+        let holders: [Arc<dyn ShareHolder>; 2] = [
+            Arc::new(FileShareHolder::new(path)),
+            Arc::new(RemoteShareHolder::new(&peer, token)?),
+        ];
+        match Self::recover(holders.clone()).await {
+            Ok(secrets) => Ok(Some(secrets)),
+            Err(e) => {
+                tracing::info!("no epoch key to recover from the key shares: {}", e);
+                Ok(Some(Self::new(holders)))
+            }
+        }
+    }
+
+    async fn shares(holders: &[Arc<dyn ShareHolder>; 2]) -> Result<(KeyShare, KeyShare), MycoError> {
+        let missing = || MycoError::ProtocolError("a key share is missing".to_string());
+        let a = holders[0].share().await?.ok_or_else(missing)?;
+        let b = holders[1].share().await?.ok_or_else(missing)?;
+        Ok((a, b))
+    }
+
+    /// Combine the shares of the current key and mark them as released.
+    async fn release(&self, rotation: u64, key: &Key) -> Result<(), MycoError> {
+        let (a, b) = Self::shares(&self.holders).await?;
+        if a.rotation != rotation || combine_shares(&a, &b)? != *key {
+            return Err(MycoError::ProtocolError(format!(
+                "the key shares don't make up epoch key {}",
+                rotation
+            )));
+        }
+        for (holder, share) in self.holders.iter().zip([a, b]) {
+            holder.hold(KeyShare { released: true, ..share }).await?;
+        }
+        Ok(())
+    }
+}
+
+impl SecretCompute for SharedSecrets {
+    fn rotate_epoch_key(&self) {
+        if self.recovered.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let mut rng = ChaCha20Rng::from_entropy();
+        let key = Key::random(&mut rng);
+        let (a, b) = split_key(&key, &mut rng);
+        let mut current = self.key.write().unwrap();
+        let rotation = current.0 + 1;
+        for (holder, share) in self.holders.iter().zip([a, b]) {
+            // A share that can't be handed out fails the epoch's batch write, see epoch_key.
+            let share = KeyShare {
+                rotation,
+                share,
+                released: false,
+            };
+            if let Err(e) = run_sync(holder.hold(share)) {
+                tracing::error!("failed to hand out a share of epoch key {}: {}", rotation, e);
+            }
+        }
+        *current = (rotation, key);
+    }
+
+    fn epoch_key(&self) -> Result<Key, MycoError> {
+        let (rotation, key) = self.key.read()?.clone();
+        run_sync(self.release(rotation, &key))?;
+        Ok(key)
+    }
+
+    fn location(&self, f: &[u8], cs: &[u8]) -> Result<Vec<u8>, MycoError> {
+        crate::crypto::prf(&self.key.read()?.1 .0, &[f, cs].concat())
+            .map_err(|_| MycoError::ProtocolError("PRF failed".to_string()))
+    }
+
+    fn open_block(&self, k_oblv_t: &Key, block: &Block) -> Result<Vec<u8>, MycoError> {
+        self.host.open_block(k_oblv_t, block)
+    }
+
+    fn seal_block(&self, k_oblv_t: &Key, ct: &[u8]) -> Result<Block, MycoError> {
+        self.host.seal_block(k_oblv_t, ct)
+    }
+
+    fn sealed_epoch_key(&self, wrapping_key: &Key) -> Result<Vec<u8>, MycoError> {
+        seal_key(wrapping_key, &self.key.read()?.1)
+    }
+
+    /// Takes the key over and shares it anew, as a rotation of its own.
+    fn unseal_epoch_key(&self, wrapping_key: &Key, sealed: &[u8]) -> Result<(), MycoError> {
+        let key = unseal_key(wrapping_key, sealed)?;
+        let (a, b) = split_key(&key, &mut ChaCha20Rng::from_entropy());
+        let mut current = self.key.write()?;
+        let rotation = current.0 + 1;
+        for (holder, share) in self.holders.iter().zip([a, b]) {
+            run_sync(holder.hold(KeyShare {
+                rotation,
+                share,
+                released: false,
+            }))?;
+        }
+        *current = (rotation, key);
+        Ok(())
+    }
+}
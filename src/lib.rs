@@ -15,15 +15,57 @@
 
 
 // Add module declarations
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod admin;
+pub mod backup;
+pub mod bandwidth;
 pub mod constants;
 pub mod dtypes;
+pub mod envelope;
 pub mod error;
+pub mod framed;
+pub mod group;
+pub mod hardening;
+pub mod idempotency;
+pub mod journal;
+pub mod key_sharing;
+#[cfg(feature = "debug-json")]
+pub mod json;
 pub mod utils;
 pub mod network;
+pub mod notification;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+pub mod pacing;
+pub mod pairing;
 pub mod server1;
 pub mod server2;
 pub mod tree;
 pub mod client;
+pub mod conversation;
 pub mod logging;
+pub mod memory;
+pub mod mailbox;
+pub mod registration;
+pub mod secrets;
 pub mod rpc_types;
 pub mod crypto;
+pub mod device;
+pub mod directory;
+pub mod sequence;
+pub mod serve;
+pub mod shaping;
+pub mod simulation;
+pub mod standby;
+pub mod statsd;
+pub mod store;
+pub mod streaming;
+pub mod tenant;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
+pub mod topic;
+pub mod distributed;
+pub mod tls;
+pub mod transport;
+pub mod write_tokens;
@@ -1,20 +1,53 @@
 //! Logging utilities for tracking latency and bytes metrics.
+//!
+//! Code paths can be timed by hand with [`LatencyMetric`] and [`BytesMetric`]. HTTP routes don't
+//! need to be: [`instrument`] wraps a router so that every route reports its handler latency and
+//! request and response sizes to a [`MetricsSink`].
+//!
+//! Latencies recorded either way are also aggregated in memory per operation for the last
+//! [`LATENCY_EPOCHS`] epochs of each server, see [`latency_breakdown`], so a dashboard can show
+//! where epoch time goes without reading the CSV files. A sink given to [`install_sink`] receives
+//! the hand-timed metrics too, e.g. to send them to a monitoring stack (see [`crate::statsd`]).
 
-use std::sync::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{MatchedPath, Request, State},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use http_body::Frame;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 
 lazy_static! {
     /// Global log for storing latency metrics with operation name, duration in ms, and timestamps
     static ref LATENCY_LOG: Mutex<Vec<(String, f64, u64, u64)>> = Mutex::new(Vec::new());
     /// Global log for storing bytes metrics with operation name and byte count
     static ref BYTES_LOG: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+    /// Latency aggregates of the current and last epochs, see [`latency_breakdown`]
+    static ref LIVE_LATENCIES: Mutex<LiveLatencies> = Mutex::new(LiveLatencies::default());
+    /// Sink receiving the metrics of every [`LatencyMetric`] and [`BytesMetric`], see [`install_sink`]
+    static ref INSTALLED_SINK: RwLock<Option<Arc<dyn MetricsSink>>> = RwLock::new(None);
 }
 
+/// Send the metrics of every [`LatencyMetric`] and [`BytesMetric`] to `sink` as well, replacing
+/// any sink installed before.
+pub fn install_sink(sink: Arc<dyn MetricsSink>) {
+    *INSTALLED_SINK.write().unwrap() = Some(sink);
+}
+
+/// Number of completed epochs per server whose latencies are kept for [`latency_breakdown`].
+pub const LATENCY_EPOCHS: usize = 16;
+
 /// Tracks latency metrics for an operation, with support for pausing/resuming timing
 pub struct LatencyMetric {
     /// Name of the operation being timed
-    #[allow(dead_code)]
     operation: String,
     /// Start time of the operation
     start_time: Instant,
@@ -30,10 +63,8 @@ pub struct LatencyMetric {
 /// Tracks bytes metrics for an operation
 pub struct BytesMetric {
     /// Name of the operation being measured
-    #[allow(dead_code)]
     operation: String,
     /// Number of bytes processed
-    #[allow(dead_code)]
     bytes: usize,
 }
 
@@ -72,16 +103,21 @@ impl LatencyMetric {
     }
 
     /// Finishes timing and logs the final duration.
-    /// Only logs if perf-logging feature is enabled.
+    /// The duration is always added to the [`latency_breakdown`], but only written to the log if
+    /// the perf-logging feature is enabled.
     pub fn finish(self) {
+        let final_duration = if self.is_paused {
+            self.accumulated_duration
+        } else {
+            self.accumulated_duration + self.start_time.elapsed()
+        };
+        record_live_latency(&self.operation, final_duration);
+        if let Some(sink) = INSTALLED_SINK.read().unwrap().as_ref() {
+            sink.record_latency(&self.operation, final_duration);
+        }
+
         #[cfg(feature = "perf-logging")]
         {
-            let final_duration = if self.is_paused {
-                self.accumulated_duration
-            } else {
-                self.accumulated_duration + self.start_time.elapsed()
-            };
-
             let milliseconds = final_duration.as_secs_f64() * 1000.0;
             let end_timestamp = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
@@ -109,8 +145,11 @@ impl BytesMetric {
     }
 
     /// Logs the bytes metric.
-    /// Only logs if perf-logging feature is enabled.
+    /// Only logs if perf-logging feature is enabled, but always reports to the installed sink.
     pub fn log(self) {
+        if let Some(sink) = INSTALLED_SINK.read().unwrap().as_ref() {
+            sink.record_bytes(&self.operation, self.bytes);
+        }
         #[cfg(feature = "perf-logging")]
         {
             log_bytes(&format!(
@@ -122,6 +161,287 @@ impl BytesMetric {
     }
 }
 
+/// Destination for metrics recorded by [`instrument`].
+pub trait MetricsSink: Send + Sync + 'static {
+    /// Record that `operation` took `latency`.
+    fn record_latency(&self, operation: &str, latency: Duration);
+    /// Record that `operation` transferred `bytes` bytes.
+    fn record_bytes(&self, operation: &str, bytes: usize);
+}
+
+/// The in-memory logs written by [`LatencyMetric`] and [`BytesMetric`] and flushed by
+/// `calculate_and_append_averages`. Only records when the perf-logging feature is enabled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PerfLog;
+
+impl MetricsSink for PerfLog {
+    fn record_latency(&self, operation: &str, latency: Duration) {
+        record_live_latency(operation, latency);
+        #[cfg(feature = "perf-logging")]
+        {
+            let end = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros() as u64;
+            let start = end.saturating_sub(latency.as_micros() as u64);
+            LATENCY_LOG.lock().unwrap().push((
+                operation.to_string(),
+                latency.as_secs_f64() * 1000.0,
+                start,
+                end,
+            ));
+        }
+    }
+
+    #[cfg_attr(not(feature = "perf-logging"), allow(unused_variables))]
+    fn record_bytes(&self, operation: &str, bytes: usize) {
+        #[cfg(feature = "perf-logging")]
+        BYTES_LOG.lock().unwrap().push((operation.to_string(), bytes));
+    }
+}
+
+/// A sink passing every metric on to each of several sinks.
+pub struct FanoutSink(pub Vec<Arc<dyn MetricsSink>>);
+
+impl MetricsSink for FanoutSink {
+    fn record_latency(&self, operation: &str, latency: Duration) {
+        for sink in &self.0 {
+            sink.record_latency(operation, latency);
+        }
+    }
+
+    fn record_bytes(&self, operation: &str, bytes: usize) {
+        for sink in &self.0 {
+            sink.record_bytes(operation, bytes);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+/// Aggregate latency of one operation over an epoch
+pub struct OperationLatency {
+    /// Name of the operation, e.g. `server1_batch_write_write_to_server2`
+    pub operation: String,
+    /// Number of times the operation finished
+    pub count: usize,
+    /// Total time spent in the operation, in milliseconds
+    pub total_ms: f64,
+    /// Longest single run, in milliseconds
+    pub max_ms: f64,
+}
+
+impl OperationLatency {
+    /// Mean time per run, in milliseconds.
+    pub fn mean_ms(&self) -> f64 {
+        self.total_ms / self.count.max(1) as f64
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+/// The operation latencies of one epoch, sorted by operation name
+pub struct EpochLatencies {
+    /// The epoch the latencies were recorded in
+    pub epoch: u64,
+    /// Aggregates per operation
+    pub operations: Vec<OperationLatency>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+/// A server's operation latencies for the current epoch and the last [`LATENCY_EPOCHS`]
+pub struct LatencyBreakdown {
+    /// The epoch in progress
+    pub current: EpochLatencies,
+    /// Completed epochs, newest first
+    pub previous: Vec<EpochLatencies>,
+}
+
+#[derive(Default)]
+struct LiveLatencies {
+    /// Aggregates of the epoch in progress, by operation
+    current: HashMap<String, OperationLatency>,
+    /// Completed epochs per server prefix, newest first
+    completed: HashMap<String, VecDeque<EpochLatencies>>,
+}
+
+fn record_live_latency(operation: &str, latency: Duration) {
+    let milliseconds = latency.as_secs_f64() * 1000.0;
+    let mut live = LIVE_LATENCIES.lock().unwrap();
+    let entry = live
+        .current
+        .entry(operation.to_string())
+        .or_insert_with(|| OperationLatency {
+            operation: operation.to_string(),
+            ..Default::default()
+        });
+    entry.count += 1;
+    entry.total_ms += milliseconds;
+    entry.max_ms = entry.max_ms.max(milliseconds);
+}
+
+/// Whether `operation` belongs to the server whose operations are named `<prefix>_...`.
+fn has_prefix(operation: &str, prefix: &str) -> bool {
+    operation
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.starts_with('_'))
+}
+
+fn sorted(mut operations: Vec<OperationLatency>) -> Vec<OperationLatency> {
+    operations.sort_by(|a, b| a.operation.cmp(&b.operation));
+    operations
+}
+
+/// Close `epoch` for the server whose operations are named `<prefix>_...`: its operation
+/// latencies so far move to the completed epochs, of which the last [`LATENCY_EPOCHS`] are kept.
+pub fn finish_latency_epoch(prefix: &str, epoch: u64) {
+    let mut live = LIVE_LATENCIES.lock().unwrap();
+    let names: Vec<String> = live
+        .current
+        .keys()
+        .filter(|operation| has_prefix(operation, prefix))
+        .cloned()
+        .collect();
+    let operations = names
+        .iter()
+        .filter_map(|operation| live.current.remove(operation))
+        .collect();
+    let completed = live.completed.entry(prefix.to_string()).or_default();
+    completed.push_front(EpochLatencies {
+        epoch,
+        operations: sorted(operations),
+    });
+    completed.truncate(LATENCY_EPOCHS);
+}
+
+/// The operation latencies of the server whose operations are named `<prefix>_...`, with
+/// `epoch` being its epoch in progress.
+pub fn latency_breakdown(prefix: &str, epoch: u64) -> LatencyBreakdown {
+    let live = LIVE_LATENCIES.lock().unwrap();
+    let operations = live
+        .current
+        .values()
+        .filter(|latency| has_prefix(&latency.operation, prefix))
+        .cloned()
+        .collect();
+    LatencyBreakdown {
+        current: EpochLatencies {
+            epoch,
+            operations: sorted(operations),
+        },
+        previous: live
+            .completed
+            .get(prefix)
+            .map(|completed| completed.iter().cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+#[derive(Clone)]
+struct RouteMetrics {
+    prefix: &'static str,
+    sink: Arc<dyn MetricsSink>,
+}
+
+/// Record latency and bytes metrics for every route of `router`.
+///
+/// Each request is named after its matched route, e.g. `server2_http_chunk_write` for
+/// `/chunk_write` with prefix `server2`, so path parameters don't create new operations. The
+/// handler latency is recorded under that name, and the body sizes under `<name>_request` and
+/// `<name>_response`. Request sizes are taken from the body's size hint, which is exact for
+/// requests with a `Content-Length`. Responses of unknown length are counted as they are sent and
+/// recorded once the body is dropped.
+pub fn instrument<S>(router: Router<S>, prefix: &'static str, sink: Arc<dyn MetricsSink>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(middleware::from_fn_with_state(
+        RouteMetrics { prefix, sink },
+        record_route_metrics,
+    ))
+}
+
+async fn record_route_metrics(State(metrics): State<RouteMetrics>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().trim_start_matches('/').replace(['/', ':'], "_"))
+        .unwrap_or_else(|| "unmatched".to_string());
+    let operation = format!("{}_http_{}", metrics.prefix, route);
+    let request_bytes = body_size(request.body());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    metrics.sink.record_latency(&operation, start.elapsed());
+
+    metrics.sink.record_bytes(&format!("{}_request", operation), request_bytes);
+    let response_operation = format!("{}_response", operation);
+    match response.body().size_hint().exact() {
+        Some(bytes) => {
+            metrics.sink.record_bytes(&response_operation, bytes as usize);
+            response
+        }
+        // Streamed responses are counted as they are sent.
+        None => response.map(|body| {
+            counted(body, move |bytes| metrics.sink.record_bytes(&response_operation, bytes))
+        }),
+    }
+}
+
+/// Wrap `body` to call `on_drop` with the number of bytes that passed through it once it is
+/// dropped.
+pub(crate) fn counted(body: Body, on_drop: impl FnOnce(usize) + Send + 'static) -> Body {
+    Body::new(CountingBody {
+        inner: body,
+        bytes: 0,
+        on_drop: Some(Box::new(on_drop)),
+    })
+}
+
+/// A body counting the bytes passing through it, reported once it is dropped.
+struct CountingBody {
+    inner: Body,
+    bytes: usize,
+    on_drop: Option<Box<dyn FnOnce(usize) + Send>>,
+}
+
+impl HttpBody for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.bytes += data.len();
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountingBody {
+    fn drop(&mut self) {
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop(self.bytes);
+        }
+    }
+}
+
+fn body_size<B: HttpBody>(body: &B) -> usize {
+    let hint = body.size_hint();
+    hint.exact().unwrap_or(hint.lower()) as usize
+}
+
 /// Internal helper to parse and log a latency metric
 #[cfg(feature = "perf-logging")]
 fn log_latency(message: &str) {
@@ -163,7 +483,7 @@ fn log_bytes(message: &str) {
 /// Z, D and batch size constants.
 #[cfg(feature = "perf-logging")]
 pub fn calculate_and_append_averages(latency_filename: &str, bytes_filename: &str) {
-    use std::fs::{File, OpenOptions};
+    use std::fs::OpenOptions;
     use std::io::Write;
     use std::collections::HashMap;
 
@@ -0,0 +1,136 @@
+//! Mailbox write-access control
+//!
+//! A mailbox owner can restrict who writes to their read location by sharing an access key with
+//! the senders they approve. For every epoch they want to protect, the owner gives Server1 a
+//! [`MailboxGuard`]: the mailbox's address for that epoch (see
+//! [`crate::crypto::mailbox_address`]) and a guard key derived from the access key for that
+//! address alone. Writes to a guarded address must then carry an access tag over their ciphertext
+//! under the guard key, which only holders of the access key can compute; other writes to it are
+//! rejected with [`MycoError::MailboxAccessDenied`]. Writes to unguarded addresses are unaffected.
+//!
+//! A guard can't be replaced by one with a different key, so whoever guards an address first owns
+//! it, and guards are dropped [`MAILBOX_GUARD_EPOCHS`] epochs after they were set. Since addresses
+//! are derived from the contact's per-epoch PRF output, nobody but the two parties of a contact can
+//! compute them ahead of time, and Server1 can't link the guards of different epochs.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{mailbox_access_tag, mailbox_address},
+    dtypes::Key,
+    error::MycoError,
+};
+
+/// Number of epochs a guard is kept after it was set.
+pub const MAILBOX_GUARD_EPOCHS: u64 = 64;
+
+/// Maximum number of guards Server1 keeps at once.
+pub const MAX_MAILBOX_GUARDS: usize = 1 << 20;
+
+/// Size of a mailbox address in bytes.
+pub const MAILBOX_ADDRESS_SIZE: usize = 32;
+
+/// A guard restricting writes to a mailbox address to holders of the access key.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxGuard {
+    /// The mailbox address, see [`crate::crypto::mailbox_address`].
+    pub address: Vec<u8>,
+    /// The guard key of the address, see [`crate::crypto::mailbox_guard_key`].
+    pub key: Key,
+}
+
+// Custom Debug implementation for MailboxGuard to keep the guard key out of logs
+impl std::fmt::Debug for MailboxGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MailboxGuard({})", hex::encode(&self.address))
+    }
+}
+
+/// The mailbox guards held by a Server1.
+#[derive(Default)]
+pub struct MailboxGuards {
+    /// Guard key and last epoch of each guarded address.
+    guards: HashMap<Vec<u8>, (Key, u64)>,
+}
+
+impl MailboxGuards {
+    /// An empty set of guards.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Guard the addresses of `guards` from `epoch` on. Setting the same guard again extends it.
+    /// Either all of `guards` are set or, if one of them is invalid or conflicts with an existing
+    /// guard, none.
+    pub fn guard(&mut self, guards: Vec<MailboxGuard>, epoch: u64) -> Result<(), MycoError> {
+        let mut added = 0;
+        for guard in &guards {
+            if guard.address.len() != MAILBOX_ADDRESS_SIZE {
+                return Err(MycoError::MalformedRequest(format!(
+                    "mailbox address of {} bytes",
+                    guard.address.len()
+                )));
+            }
+            match self.guards.get(&guard.address) {
+                Some((key, _)) if *key != guard.key => return Err(MycoError::MailboxAccessDenied),
+                Some(_) => {}
+                None => added += 1,
+            }
+        }
+        if self.guards.len() + added > MAX_MAILBOX_GUARDS {
+            return Err(MycoError::ProtocolError("too many mailbox guards".to_string()));
+        }
+
+        let until = epoch + MAILBOX_GUARD_EPOCHS;
+        for guard in guards {
+            self.guards.insert(guard.address, (guard.key, until));
+        }
+        Ok(())
+    }
+
+    /// Check a write of `ct` with PRF output `f` and pseudonym `cs` against the guard of its
+    /// address, if there is one.
+    pub fn check(
+        &self,
+        f: &[u8],
+        cs: &[u8],
+        ct: &[u8],
+        access_tag: Option<&[u8]>,
+    ) -> Result<(), MycoError> {
+        self.check_address(&mailbox_address(f, cs), ct, access_tag)
+    }
+
+    /// Check a write of `ct` to mailbox `address` against the guard of the address, if there is
+    /// one.
+    pub fn check_address(
+        &self,
+        address: &[u8],
+        ct: &[u8],
+        access_tag: Option<&[u8]>,
+    ) -> Result<(), MycoError> {
+        let Some((key, _)) = self.guards.get(address) else {
+            return Ok(());
+        };
+        let expected = mailbox_access_tag(&key.0, ct)?;
+        let tag = access_tag.ok_or(MycoError::MailboxAccessDenied)?;
+        ring::constant_time::verify_slices_are_equal(tag, &expected)
+            .map_err(|_| MycoError::MailboxAccessDenied)
+    }
+
+    /// Drop the guards set more than [`MAILBOX_GUARD_EPOCHS`] epochs before `epoch`.
+    pub fn expire(&mut self, epoch: u64) {
+        self.guards.retain(|_, (_, until)| *until > epoch);
+    }
+
+    /// Number of guarded addresses.
+    pub fn len(&self) -> usize {
+        self.guards.len()
+    }
+
+    /// Whether no address is guarded.
+    pub fn is_empty(&self) -> bool {
+        self.guards.is_empty()
+    }
+}
@@ -0,0 +1,157 @@
+//! Memory usage introspection
+//!
+//! Lets operators see where the servers' memory goes without attaching a profiler. [`HeapSize`]
+//! estimates the heap bytes held by the trees and metadata, from the lengths of their buffers.
+//! [`CountingAllocator`] wraps the system allocator and keeps running totals of what is actually
+//! allocated; the server binaries install it as the global allocator, so [`allocator_stats`]
+//! reports `None` wherever it isn't installed.
+//!
+//! Implementing [`GlobalAlloc`] is the crate's only unsafe code. It forwards every call to
+//! [`System`] unchanged and only updates atomic counters around it.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    mem::size_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    dtypes::{AllocatorStats, Block, Bucket, Key, Metadata, Path},
+    tree::{BinaryTree, SparseBinaryTree},
+};
+
+/// Approximate number of heap bytes owned by a value, not counting the value itself.
+pub trait HeapSize {
+    /// The heap bytes owned by `self`.
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for Block {
+    fn heap_size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl HeapSize for Key {
+    fn heap_size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl HeapSize for Path {
+    fn heap_size(&self) -> usize {
+        self.0.len() * size_of::<crate::dtypes::Direction>()
+    }
+}
+
+impl HeapSize for Bucket {
+    fn heap_size(&self) -> usize {
+        self.iter()
+            .map(|block| size_of::<Block>() + block.heap_size())
+            .sum()
+    }
+}
+
+impl HeapSize for Metadata {
+    fn heap_size(&self) -> usize {
+        (0..self.len())
+            .filter_map(|i| self.get(i))
+            .map(|(path, key, timestamp)| {
+                size_of_val(path) + size_of_val(key) + size_of_val(timestamp)
+                    + path.heap_size()
+                    + key.heap_size()
+            })
+            .sum()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for BinaryTree<T> {
+    fn heap_size(&self) -> usize {
+        self.value.heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for SparseBinaryTree<T> {
+    fn heap_size(&self) -> usize {
+        self.packed_buckets.heap_size()
+            + self.packed_indices.capacity() * size_of::<usize>()
+            + self.position_capacity() * 2 * size_of::<usize>()
+    }
+}
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting the bytes it hands out. Install it with
+/// `#[global_allocator] static ALLOCATOR: CountingAllocator = CountingAllocator;`.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn record_alloc(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_dealloc(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+// SAFETY: every method forwards to `System` with the caller's arguments and returns its result,
+// so `System`'s guarantees carry over. The counters are plain atomics and never allocate.
+#[allow(unsafe_code)]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::record_dealloc(layout.size());
+            Self::record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Totals kept by [`CountingAllocator`], or `None` if it isn't the global allocator.
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    (allocations > 0).then(|| AllocatorStats {
+        allocated_bytes: ALLOCATED.load(Ordering::Relaxed),
+        peak_bytes: PEAK.load(Ordering::Relaxed),
+        allocations,
+    })
+}
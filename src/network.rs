@@ -5,55 +5,109 @@
 //! It defines the traits and structures for interacting with the servers over the network.
 use anyhow::Result;
 use axum::async_trait;
-use bincode::{deserialize, serialize};
-use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use std::sync::{Mutex, RwLock};
-use std::{
-    io::{Read, Write},
-    sync::Arc,
-};
-use tokio::io::AsyncWriteExt;
+use std::sync::{Arc, Mutex, RwLock};
 use crate::{
-    dtypes::{Bucket, Key, Path},
-    error::MycoError,
+    distributed::SealedWrite,
+    dtypes::{Bucket, BucketDelta, EpochInfo, Key, Path, SparseBuckets, StorageReport},
+    error::{ErrorCode, MycoError},
     logging::BytesMetric,
-    rpc_types::{
-        ChunkReadPathsClientRequest, ChunkReadPathsClientResponse, ChunkReadPathsRequest,
-        ChunkReadPathsResponse, ChunkWriteRequest, FinalizeEpochRequest, FinalizeEpochResponse,
-        GetPrfKeysResponse, QueueWriteRequest, QueueWriteResponse, ReadPathsClientRequest,
-        ReadPathsResponse, StorePathIndicesRequest, StorePathIndicesResponse, WriteResponse,
-    },
+    mailbox::MailboxGuard,
+    registration::AccountCredentials,
+    rpc_types::ChunkReadPathsClientRequest,
     server1::Server1,
     server2::Server2,
-    constants::{NUM_BUCKETS_PER_BATCH_WRITE_CHUNK, NUM_BUCKETS_PER_READ_PATHS_CHUNK},
+    tls::TlsTrust,
+    transport::{
+        expect_buckets, expect_epoch, expect_notifications, expect_pending_epochs, expect_prf_keys, expect_prf_keys_since, expect_queued, expect_success, expect_write_tokens, HttpsTransport, Transport, TransportOptions},
 };
+#[cfg(feature = "bytes-logging")]
+use crate::rpc_types::{ChunkWriteRequest, StorePathIndicesRequest, StorePathLeavesRequest};
 
 #[derive(Serialize, Deserialize, Debug)]
 /// An enum representing the different types of commands that can be sent to the servers
 pub enum Command {
     /// Command to write to Server1
-    Server1Write(Vec<u8>, Vec<u8>, Key, Vec<u8>),
+    Server1Write(Vec<u8>, Vec<u8>, Key, Vec<u8>, Vec<u8>, Option<Vec<u8>>),
+    /// Command to guard mailbox addresses on Server1
+    Server1GuardMailboxes(Vec<MailboxGuard>),
+    /// Command to manage a registration on Server1
+    Server1Registration(RegistrationType),
+    /// Command to have Server1 issue write tokens to an account, one per epoch and blinded token
+    Server1IssueWriteTokens(AccountCredentials, Vec<(u64, Vec<u8>)>),
     /// Command to write to Server2
     Server2Write(WriteType),
     /// Command to read from Server2
     Server2Read(ReadType),
     /// Command to indicate success
     Success,
+    /// Response carrying buckets read from Server2
+    Buckets(Vec<Bucket>),
+    /// Response carrying buckets read from Server2 for Server1's batch init, empty ones left out
+    SparseBuckets(SparseBuckets),
+    /// Response carrying Server2's PRF keys
+    PrfKeys(Vec<Key>),
+    /// Response indicating that the command failed, with the code and message of the error
+    Error(ErrorCode, String),
+    /// Response carrying the PRF keys published since a cursor, with the number of the first key
+    PrfKeysSince(u64, Vec<Key>),
+    /// Response carrying Server2's epoch and PRF key cursor
+    Epoch(EpochInfo),
+    /// Response rejecting a write that arrived while no epoch was open, with the estimated opening
+    /// time of the next epoch in milliseconds since the Unix epoch
+    EpochClosed(Option<u64>),
+    /// Response carrying the notification index of the newest epoch, if any, with the PRF key
+    /// cursor right after the epoch's key
+    Notifications(Option<(u64, Vec<u8>)>),
+    /// Response carrying the credentials of a registered or rotated account
+    Registered(AccountCredentials),
+    /// Response carrying the epochs that still hold a write for one of the queried read tags
+    PendingEpochs(Vec<u64>),
+    /// Response acknowledging a write, with the epoch it was queued into
+    Queued(u64),
+    /// Response carrying the write tokens Server1 issued, one per epoch and still blinded
+    WriteTokens(Vec<(u64, Vec<u8>)>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A type representing the different registration commands that can be sent to Server1
+pub enum RegistrationType {
+    /// Command to register a new account
+    Register,
+    /// Command to replace the ID and secret of an account
+    Rotate(AccountCredentials),
+    /// Command to delete an account
+    Delete(AccountCredentials),
 }
 
 #[derive(Serialize, Deserialize)]
 /// A type representing the different types of write commands that can be sent to Server2
 pub enum WriteType {
-    /// Command to write to Server2
-    Write(Vec<Bucket>, Key),
+    /// Command to write an epoch's buckets to Server2 and publish its PRF key
+    Write(u64, Vec<Bucket>, Key),
+    /// Command to write an epoch's buckets to Server2 as deltas and publish its PRF key
+    WriteDeltas(u64, Vec<BucketDelta>, Key),
+    /// Command to publish the notification index of the epoch being written
+    Notifications(Vec<u8>),
+    /// Command to publish the storage report of the epoch being written
+    Storage(StorageReport),
+    /// Command to store the pathset of the next write, given as the leaves of its paths
+    PathLeaves(Vec<u32>),
 }
 
 // Custom Debug implementation for WriteType to avoid printing bucket contents
 impl std::fmt::Debug for WriteType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            WriteType::Write(buckets, _) => write!(f, "Write({} buckets)", buckets.len()),
+            WriteType::Write(epoch, buckets, _) => {
+                write!(f, "Write(epoch {}, {} buckets)", epoch, buckets.len())
+            }
+            WriteType::WriteDeltas(epoch, deltas, _) => {
+                write!(f, "WriteDeltas(epoch {}, {} buckets)", epoch, deltas.len())
+            }
+            WriteType::Notifications(index) => write!(f, "Notifications({} bytes)", index.len()),
+            WriteType::Storage(report) => write!(f, "Storage({} tags)", report.len()),
+            WriteType::PathLeaves(leaves) => write!(f, "PathLeaves({} leaves)", leaves.len()),
         }
     }
 }
@@ -63,10 +117,25 @@ impl std::fmt::Debug for WriteType {
 pub enum ReadType {
     /// Command to read a single path
     Read(Path),
-    /// Command to read multiple paths
+    /// Command to read multiple paths, storing them as the pathset for the next write
     ReadPaths(Vec<usize>),
+    /// Command to read the paths to the given leaves, storing them as the pathset for the next
+    /// write
+    ReadLeafPaths(Vec<u32>),
+    /// Command to read the paths to the given leaves without storing them as the pathset
+    PrefetchLeafPaths(Vec<u32>),
+    /// Command to read multiple paths for a client
+    ReadPathsClient(Vec<usize>),
     /// Command to get PRF keys
     GetPrfKeys,
+    /// Command to get the PRF keys published since a cursor
+    GetPrfKeysSince(u64),
+    /// Command to get the current epoch and PRF key cursor
+    GetEpoch,
+    /// Command to get the notification index of the newest epoch
+    GetNotifications,
+    /// Command to find which epochs still hold a write for one of the given per-epoch read tags
+    PendingEpochs(Vec<(u64, Vec<u8>)>),
 }
 
 /// A trait for local communication
@@ -83,8 +152,18 @@ pub(crate) trait Network {
 /// A trait for remote communication with Server2
 #[async_trait]
 pub trait Server2Access: Send + Sync {
+    /// Read the buckets along a single path from Server2
+    async fn read_path(&self, path: Path) -> Result<Vec<Bucket>>;
     /// Read paths from Server2
     async fn read_paths(&self, indices: Vec<usize>) -> Result<Vec<Bucket>>;
+    /// Read the paths to the given leaves from Server2, in the order of
+    /// [`crate::utils::get_leaf_path_indices`]
+    async fn read_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>>;
+    /// Read the paths to the given leaves from Server2 ahead of the batch_init they are for,
+    /// leaving the stored pathset as it is
+    async fn prefetch_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>>;
+    /// Store the pathset of the next write on Server2, given as the leaves of its paths
+    async fn store_leaf_paths(&self, leaves: Vec<u32>) -> Result<()>;
     /// Read paths from Server2 in a client-side chunked manner
     async fn read_paths_client(
         &self,
@@ -97,10 +176,27 @@ pub trait Server2Access: Send + Sync {
         indices: Vec<usize>,
         batch_size: usize,
     ) -> Result<Vec<Bucket>>;
-    /// Write to Server2
-    async fn write(&self, buckets: Vec<Bucket>, prf_key: Key) -> Result<()>;
+    /// Write the buckets of `epoch` to Server2 and publish its PRF key
+    async fn write(&self, epoch: u64, buckets: Vec<Bucket>, prf_key: Key) -> Result<()>;
+    /// Write the buckets of `epoch` to Server2 as deltas from the buckets it holds, and publish
+    /// the epoch's PRF key
+    async fn write_deltas(&self, epoch: u64, deltas: Vec<BucketDelta>, prf_key: Key) -> Result<()>;
     /// Get PRF keys from Server2
     async fn get_prf_keys(&self) -> Result<Vec<Key>>;
+    /// Get the PRF keys Server2 published since `cursor`, together with the number of the first
+    /// key returned (see [`Server2::get_prf_keys_since`])
+    async fn get_prf_keys_since(&self, cursor: u64) -> Result<(u64, Vec<Key>)>;
+    /// Get Server2's current epoch and PRF key cursor
+    async fn get_epoch(&self) -> Result<EpochInfo>;
+    /// Publish the notification index of the epoch being written, ahead of the write
+    async fn publish_notifications(&self, index: Vec<u8>) -> Result<()>;
+    /// Get the notification index of the newest epoch (see [`Server2::notifications`])
+    async fn get_notifications(&self) -> Result<Option<(u64, Vec<u8>)>>;
+    /// Find which of the kept epochs still hold a write for one of the given per-epoch read tags
+    /// (see [`Server2::pending_epochs`])
+    async fn pending_epochs(&self, tags: Vec<(u64, Vec<u8>)>) -> Result<Vec<u64>>;
+    /// Publish the storage report of the epoch being written, ahead of the write
+    async fn publish_storage(&self, report: StorageReport) -> Result<()>;
 }
 
 /// Local access - direct memory access
@@ -126,6 +222,14 @@ impl LocalServer2Access {
 
 #[async_trait]
 impl Server2Access for LocalServer2Access {
+    async fn read_path(&self, path: Path) -> Result<Vec<Bucket>> {
+        self.server
+            .lock()
+            .unwrap()
+            .read(&path)
+            .map_err(|e| e.into())
+    }
+
     /// Read paths from Server2
     async fn read_paths(&self, indices: Vec<usize>) -> Result<Vec<Bucket>> {
         self.server
@@ -135,11 +239,32 @@ impl Server2Access for LocalServer2Access {
             .map_err(|e| e.into())
     }
 
+    async fn read_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>> {
+        self.server
+            .lock()
+            .unwrap()
+            .read_and_store_path_leaves(&leaves)
+            .map_err(|e| e.into())
+    }
+
+    async fn prefetch_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>> {
+        self.server
+            .lock()
+            .unwrap()
+            .prefetch_leaf_paths(&leaves)
+            .map_err(|e| e.into())
+    }
+
+    async fn store_leaf_paths(&self, leaves: Vec<u32>) -> Result<()> {
+        self.server.lock().unwrap().store_path_leaves(&leaves)?;
+        Ok(())
+    }
+
     /// Read paths from Server2 in a client-side chunked manner
     async fn read_paths_client(
         &self,
         indices: Vec<usize>,
-        batch_size: usize,
+        _batch_size: usize,
     ) -> Result<Vec<Bucket>> {
         self.server
             .lock()
@@ -151,7 +276,7 @@ impl Server2Access for LocalServer2Access {
     async fn read_paths_client_chunked(
         &self,
         indices: Vec<usize>,
-        batch_size: usize,
+        _batch_size: usize,
     ) -> Result<Vec<Bucket>> {
         self.server
             .lock()
@@ -160,11 +285,20 @@ impl Server2Access for LocalServer2Access {
             .map_err(|e| e.into())
     }
 
-    async fn write(&self, buckets: Vec<Bucket>, prf_key: Key) -> Result<()> {
-        let mut server = self.server.lock().unwrap();
-        server.write(buckets);
-        server.add_prf_key(&prf_key);
-        Ok(())
+    async fn write(&self, epoch: u64, buckets: Vec<Bucket>, prf_key: Key) -> Result<()> {
+        self.server
+            .lock()
+            .unwrap()
+            .write(epoch, buckets, &prf_key)
+            .map_err(|e| e.into())
+    }
+
+    async fn write_deltas(&self, epoch: u64, deltas: Vec<BucketDelta>, prf_key: Key) -> Result<()> {
+        self.server
+            .lock()
+            .unwrap()
+            .write_deltas(epoch, deltas, &prf_key)
+            .map_err(|e| e.into())
     }
 
     async fn get_prf_keys(&self) -> Result<Vec<Key>> {
@@ -174,55 +308,81 @@ impl Server2Access for LocalServer2Access {
             .get_prf_keys()
             .map_err(|e| e.into())
     }
+
+    async fn get_prf_keys_since(&self, cursor: u64) -> Result<(u64, Vec<Key>)> {
+        self.server
+            .lock()
+            .unwrap()
+            .get_prf_keys_since(cursor)
+            .map_err(|e| e.into())
+    }
+
+    async fn get_epoch(&self) -> Result<EpochInfo> {
+        Ok(self.server.lock().unwrap().epoch_info())
+    }
+
+    async fn publish_notifications(&self, index: Vec<u8>) -> Result<()> {
+        self.server.lock().unwrap().publish_notifications(index);
+        Ok(())
+    }
+
+    async fn get_notifications(&self) -> Result<Option<(u64, Vec<u8>)>> {
+        Ok(self.server.lock().unwrap().notifications())
+    }
+
+    async fn pending_epochs(&self, tags: Vec<(u64, Vec<u8>)>) -> Result<Vec<u64>> {
+        self.server
+            .lock()
+            .unwrap()
+            .pending_epochs(&tags)
+            .map_err(|e| e.into())
+    }
+
+    async fn publish_storage(&self, report: StorageReport) -> Result<()> {
+        self.server.lock().unwrap().publish_storage(report);
+        Ok(())
+    }
 }
 
 /// Remote access - serialized network access
 pub struct RemoteServer2Access {
-    pub(crate) client: reqwest::Client,
-    pub(crate) base_url: String,
+    pub(crate) transport: HttpsTransport,
 }
 
 #[async_trait]
 impl Server2Access for RemoteServer2Access {
-    async fn read_paths(&self, indices: Vec<usize>) -> Result<Vec<Bucket>> {
-        // First store the path indices on the server
-        let store_request = StorePathIndicesRequest {
-            pathset: indices.clone(),
-        };
+    async fn read_path(&self, path: Path) -> Result<Vec<Bucket>> {
+        Ok(expect_buckets(
+            self.transport
+                .call(Command::Server2Read(ReadType::Read(path)))
+                .await?,
+        )?)
+    }
 
+    async fn read_paths(&self, indices: Vec<usize>) -> Result<Vec<Bucket>> {
         // Log the size of the store request if bytes logging is enabled
         #[cfg(feature = "bytes-logging")]
         {
+            let store_request = StorePathIndicesRequest {
+                pathset: indices.clone(),
+            };
             let store_request_bytes =
-                bincode::serialize(&store_request).map_err(|_| MycoError::SerializationFailed)?;
+                bincode::serialize(&store_request).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
             BytesMetric::new("batch_init_store_path_indices", store_request_bytes.len()).log();
         }
 
-        // Send request to store path indices
-        self.post_bincode::<_, StorePathIndicesResponse>("store_path_indices", store_request)
-            .await?;
-
-        // Split indices into chunks for batched reading
-        let chunks: Vec<_> = indices.chunks(NUM_BUCKETS_PER_READ_PATHS_CHUNK).collect();
-        
-        // Create futures for parallel chunk requests
-        let futures = (0..chunks.len()).map(|chunk_idx| {
-            let request = ChunkReadPathsRequest { chunk_idx };
-            self.post_bincode::<_, ChunkReadPathsResponse>("chunk_read_paths", request)
-        });
-
-        // Collect responses from all chunks
-        let mut all_buckets = Vec::new();
-        for response in futures::future::join_all(futures).await {
-            let chunk_response = response?;
-            all_buckets.extend(chunk_response.buckets);
-        }
+        // Store the path indices on the server and read them back in parallel chunks
+        let all_buckets = expect_buckets(
+            self.transport
+                .call(Command::Server2Read(ReadType::ReadPaths(indices)))
+                .await?,
+        )?;
 
         // Log total response size if bytes logging is enabled
         #[cfg(feature = "bytes-logging")]
         {
             let total_response_bytes = bincode::serialize(&all_buckets)
-                .map_err(|_| MycoError::SerializationFailed)?
+                .map_err(|e| MycoError::SerializationFailed(Some(e)))?
                 .len();
             BytesMetric::new("batch_init_read_paths_response", total_response_bytes).log();
         }
@@ -230,6 +390,42 @@ impl Server2Access for RemoteServer2Access {
         Ok(all_buckets)
     }
 
+    async fn read_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>> {
+        // Log the size of the store request if bytes logging is enabled
+        #[cfg(feature = "bytes-logging")]
+        {
+            let store_request = StorePathLeavesRequest {
+                leaves: leaves.clone(),
+            };
+            let store_request_bytes =
+                bincode::serialize(&store_request).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+            BytesMetric::new("batch_init_store_path_leaves", store_request_bytes.len()).log();
+        }
+
+        Ok(expect_buckets(
+            self.transport
+                .call(Command::Server2Read(ReadType::ReadLeafPaths(leaves)))
+                .await?,
+        )?)
+    }
+
+    async fn prefetch_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>> {
+        Ok(expect_buckets(
+            self.transport
+                .call(Command::Server2Read(ReadType::PrefetchLeafPaths(leaves)))
+                .await?,
+        )?)
+    }
+
+    async fn store_leaf_paths(&self, leaves: Vec<u32>) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::PathLeaves(leaves)))
+                .await?,
+        )?)
+    }
+
+    #[cfg_attr(not(feature = "bytes-logging"), allow(unused_variables))]
     async fn read_paths_client_chunked(
         &self,
         indices: Vec<usize>,
@@ -239,7 +435,7 @@ impl Server2Access for RemoteServer2Access {
         #[cfg(feature = "bytes-logging")]
         {
             let indices_bytes =
-                bincode::serialize(&indices).map_err(|_| MycoError::SerializationFailed)?;
+                bincode::serialize(&indices).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
             BytesMetric::new(
                 &format!("client_read_paths_request_{}", batch_size),
                 indices_bytes.len(),
@@ -247,31 +443,33 @@ impl Server2Access for RemoteServer2Access {
             .log();
         }
 
-        // Split indices into chunks based on configured chunk size
-        let chunks: Vec<_> = indices.chunks(NUM_BUCKETS_PER_READ_PATHS_CHUNK).collect();
-        
+        // Split indices into chunks of the size Server2 advertises
+        let num_chunks = indices
+            .len()
+            .div_ceil(self.transport.capabilities().await?.read_chunk_buckets);
+
         // Create futures for parallel chunk requests
-        let futures = (0..chunks.len()).map(|chunk_idx| {
+        let futures = (0..num_chunks).map(|chunk_idx| {
             let request = ChunkReadPathsClientRequest {
                 indices: indices.clone(),
                 chunk_idx,
             };
 
-            self.post_bincode::<_, ChunkReadPathsClientResponse>("chunk_read_paths_client", request)
+            self.transport
+                .post_for_bucket_frames("chunk_read_paths_client", request)
         });
 
         // Collect and combine responses from all chunks
         let mut all_buckets = Vec::<Bucket>::new();
         for response in futures::future::join_all(futures).await {
-            let chunk_response = response?;
-            all_buckets.extend(chunk_response.buckets);
+            all_buckets.extend(response?);
         }
 
         // Log the total size of all responses if bytes logging is enabled
         #[cfg(feature = "bytes-logging")]
         {
             let total_response_bytes = bincode::serialize(&all_buckets)
-                .map_err(|_| MycoError::SerializationFailed)?
+                .map_err(|e| MycoError::SerializationFailed(Some(e)))?
                 .len();
             BytesMetric::new(
                 &format!("client_read_paths_response_{}", batch_size),
@@ -283,6 +481,7 @@ impl Server2Access for RemoteServer2Access {
         Ok(all_buckets)
     }
 
+    #[cfg_attr(not(feature = "bytes-logging"), allow(unused_variables))]
     async fn read_paths_client(
         &self,
         indices: Vec<usize>,
@@ -292,7 +491,7 @@ impl Server2Access for RemoteServer2Access {
         #[cfg(feature = "bytes-logging")]
         {
             let indices_bytes =
-                bincode::serialize(&indices).map_err(|_| MycoError::SerializationFailed)?;
+                bincode::serialize(&indices).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
             BytesMetric::new(
                 &format!("client_read_paths_request_{}", batch_size),
                 indices_bytes.len(),
@@ -300,17 +499,18 @@ impl Server2Access for RemoteServer2Access {
             .log();
         }
 
-        // Create and send request to read paths
-        let request = ReadPathsClientRequest { indices };
-        let response: ReadPathsResponse = self
-            .post_bincode(&format!("read_paths_client"), &request)
-            .await?;
+        // Send request to read paths
+        let buckets = expect_buckets(
+            self.transport
+                .call(Command::Server2Read(ReadType::ReadPathsClient(indices)))
+                .await?,
+        )?;
 
         // Log the total size of the response if bytes logging is enabled
         #[cfg(feature = "bytes-logging")]
         {
-            let total_response_bytes = bincode::serialize(&response.buckets)
-                .map_err(|_| MycoError::SerializationFailed)?
+            let total_response_bytes = bincode::serialize(&buckets)
+                .map_err(|e| MycoError::SerializationFailed(Some(e)))?
                 .len();
             BytesMetric::new(
                 &format!("client_read_paths_response_{}", batch_size),
@@ -319,173 +519,174 @@ impl Server2Access for RemoteServer2Access {
             .log();
         }
 
-        Ok(response.buckets)
+        Ok(buckets)
     }
 
-    async fn write(&self, buckets: Vec<Bucket>, prf_key: Key) -> Result<()> {
+    async fn write(&self, epoch: u64, buckets: Vec<Bucket>, prf_key: Key) -> Result<()> {
         // Measure total request size before chunking
-
         #[cfg(feature = "bytes-logging")]
         {
             let total_request = ChunkWriteRequest {
                 buckets: buckets.clone(),
                 prf_key: prf_key.clone(),
                 chunk_idx: 0,
+                epoch,
             };
             let total_bytes = bincode::serialize(&total_request)
-                .map_err(|_| MycoError::SerializationFailed)?
+                .map_err(|e| MycoError::SerializationFailed(Some(e)))?
                 .len();
             BytesMetric::new("batch_write", total_bytes).log();
         }
 
-        // Set the maximum request size to 10MB, and determine the number of buckets per batch based on this.
-        let batches: Vec<_> = buckets.chunks(NUM_BUCKETS_PER_BATCH_WRITE_CHUNK).collect();
-        let futures = batches.into_iter().enumerate().map(|(chunk_idx, batch)| {
-            let request = ChunkWriteRequest {
-                buckets: batch.to_vec(),
-                prf_key: prf_key.clone(),
-                chunk_idx,
-            };
-            self.post_bincode::<_, WriteResponse>("chunk_write", request)
-        });
-
-        let results = futures::future::join_all(futures).await;
-        for result in results {
-            result?;
-        }
-
-        // Send a new request to finalize the epoch.
-        let request = FinalizeEpochRequest { prf_key };
-        self.post_bincode::<_, FinalizeEpochResponse>("finalize_epoch", request)
-            .await?;
+        // Write the buckets in parallel chunks, then finalize the epoch.
+        expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::Write(epoch, buckets, prf_key)))
+                .await?,
+        )?;
 
         Ok(())
     }
 
+    async fn write_deltas(&self, epoch: u64, deltas: Vec<BucketDelta>, prf_key: Key) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::WriteDeltas(epoch, deltas, prf_key)))
+                .await?,
+        )?)
+    }
+
     async fn get_prf_keys(&self) -> Result<Vec<Key>> {
-        // Make GET request to the PRF keys endpoint
-        let response: GetPrfKeysResponse = self
-            .client
-            .get(&format!("{}/get_prf_keys", self.base_url))
-            .send()
-            .await
-            .map_err(|_| {
-                MycoError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to send request",
-                ))
-            })?
-            // Get response bytes
-            .bytes()
-            .await
-            .map_err(|_| {
-                MycoError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to get response bytes",
-                ))
-            })
-            // Deserialize response bytes into GetPrfKeysResponse
-            .and_then(|bytes| {
-                bincode::deserialize(&bytes).map_err(|_| MycoError::DeserializationError)
-            })?;
-
-        // Return the vector of PRF keys
-        Ok(response.keys)
+        Ok(expect_prf_keys(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetPrfKeys))
+                .await?,
+        )?)
+    }
+
+    async fn get_prf_keys_since(&self, cursor: u64) -> Result<(u64, Vec<Key>)> {
+        Ok(expect_prf_keys_since(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetPrfKeysSince(cursor)))
+                .await?,
+        )?)
+    }
+
+    async fn get_epoch(&self) -> Result<EpochInfo> {
+        Ok(expect_epoch(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetEpoch))
+                .await?,
+        )?)
+    }
+
+    async fn publish_notifications(&self, index: Vec<u8>) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::Notifications(index)))
+                .await?,
+        )?)
+    }
+
+    async fn get_notifications(&self) -> Result<Option<(u64, Vec<u8>)>> {
+        Ok(expect_notifications(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetNotifications))
+                .await?,
+        )?)
+    }
+
+    async fn pending_epochs(&self, tags: Vec<(u64, Vec<u8>)>) -> Result<Vec<u64>> {
+        Ok(expect_pending_epochs(
+            self.transport
+                .call(Command::Server2Read(ReadType::PendingEpochs(tags)))
+                .await?,
+        )?)
+    }
+
+    async fn publish_storage(&self, report: StorageReport) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::Storage(report)))
+                .await?,
+        )?)
     }
 }
 
 impl RemoteServer2Access {
-    /// Create a new RemoteServer2Access instance
-    pub async fn new(base_url: &str) -> Result<Self, MycoError> {
-        let client = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .map_err(|_| {
-                MycoError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to create HTTP client",
-                ))
-            })?;
-
+    /// Create a new RemoteServer2Access instance, verifying the server according to `trust` and
+    /// tuning its connections with `options`
+    pub async fn new(
+        base_url: &str,
+        trust: &TlsTrust,
+        options: &TransportOptions,
+    ) -> Result<Self, MycoError> {
         Ok(Self {
-            client,
-            base_url: base_url.to_string(),
+            transport: HttpsTransport::with_options(base_url, trust, options)?,
         })
     }
-
-    /// Send a bincoded request to the server
-    async fn post_bincode<T: serde::Serialize, R: serde::de::DeserializeOwned>(
-        &self,
-        endpoint: &str,
-        payload: T,
-    ) -> Result<R, MycoError> {
-        let request_bytes =
-            bincode::serialize(&payload).map_err(|_| MycoError::DeserializationError)?;
-
-
-        let response = self
-            .client
-            .post(&format!("{}/{}", self.base_url, endpoint))
-            .header("Content-Type", "application/octet-stream")
-            .body(request_bytes)
-            .send()
-            .await
-            .map_err(|_| {
-                MycoError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to send request",
-                ))
-            })?;
-
-        let bytes = response.bytes().await.map_err(|_| {
-            MycoError::IoError(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to get response bytes",
-            ))
-        })?;
-
-        Ok(bincode::deserialize(&bytes).map_err(|_| MycoError::DeserializationError)?)
-    }
 }
 
 /// Remote access - serialized network access
 pub struct RemoteServer1Access {
-    /// The HTTP client
-    pub(crate) client: reqwest::Client,
-    pub(crate) base_url: String,
+    /// The HTTPS transport
+    pub(crate) transport: HttpsTransport,
 }
 
 impl RemoteServer1Access {
-    /// Create a new RemoteServer1Access instance
-    pub async fn new(server1_addr: &str) -> Result<Self, MycoError> {
-        let client = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .map_err(|_| {
-                MycoError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to create HTTP client",
-                ))
-            })?;
-
+    /// Create a new RemoteServer1Access instance, verifying the server according to `trust` and
+    /// tuning its connections with `options`
+    pub async fn new(
+        server1_addr: &str,
+        trust: &TlsTrust,
+        options: &TransportOptions,
+    ) -> Result<Self, MycoError> {
         Ok(Self {
-            client,
-            base_url: server1_addr.to_string(),
+            transport: HttpsTransport::with_options(server1_addr, trust, options)?,
         })
     }
 }
 
 /// A trait for interacting with Server1
 #[async_trait]
-pub trait Server1Access: Send {
-    /// Queue a write to Server1
+pub trait Server1Access: Send + Sync {
+    /// Queue a write to Server1, with an access tag if the mailbox is guarded. Returns the epoch
+    /// the write was queued into.
     async fn queue_write(
         &self,
         ct: Vec<u8>,
         f: Vec<u8>,
         k_oblv_t: Key,
         cs: Vec<u8>,
-    ) -> Result<(), MycoError>;
+        token: Vec<u8>,
+        access_tag: Option<Vec<u8>>,
+    ) -> Result<u64, MycoError>;
+
+    /// Guard mailbox addresses on Server1 (see [`crate::mailbox`])
+    async fn guard_mailboxes(&self, guards: Vec<MailboxGuard>) -> Result<(), MycoError>;
+
+    /// Have Server1 issue write tokens to a registered account, one for each requested epoch and
+    /// blinded token (see [`crate::write_tokens`]). Returns the epochs Server1 issued a token for,
+    /// each with the evaluated token.
+    async fn issue_write_tokens(
+        &self,
+        credentials: AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError>;
+
+    /// Apply a front S1's location key share to a blinded point, in distributed trust mode (see
+    /// [`crate::distributed`]). Returns the epoch the evaluation is for with the evaluated point.
+    /// Unsupported unless implemented.
+    async fn evaluate_location(&self, _blinded: Vec<u8>) -> Result<(u64, Vec<u8>), MycoError> {
+        Err(MycoError::ConfigError("not a front S1 in distributed trust mode".to_string()))
+    }
+
+    /// Queue a write whose location input and oblivious key are sealed to the back S1, in
+    /// distributed trust mode (see [`crate::distributed`]). Returns the epoch the write was queued
+    /// into. Unsupported unless implemented.
+    async fn queue_sealed_write(&self, _write: SealedWrite) -> Result<u64, MycoError> {
+        Err(MycoError::ConfigError("not an S1 in distributed trust mode".to_string()))
+    }
 }
 
 /// Local access - direct memory access
@@ -510,11 +711,28 @@ impl Server1Access for LocalServer1Access {
         f: Vec<u8>,
         k_oblv_t: Key,
         cs: Vec<u8>,
-    ) -> Result<(), MycoError> {
+        token: Vec<u8>,
+        access_tag: Option<Vec<u8>>,
+    ) -> Result<u64, MycoError> {
+        self.server
+            .write()
+            .unwrap()
+            .queue_write(ct, f, k_oblv_t, cs, token, access_tag)
+    }
+
+    async fn guard_mailboxes(&self, guards: Vec<MailboxGuard>) -> Result<(), MycoError> {
+        self.server.write().unwrap().guard_mailboxes(guards)
+    }
+
+    async fn issue_write_tokens(
+        &self,
+        credentials: AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
         self.server
             .write()
             .unwrap()
-            .queue_write(ct, f, k_oblv_t, cs)
+            .issue_write_tokens(&credentials, requests)
     }
 }
 
@@ -526,47 +744,40 @@ impl Server1Access for RemoteServer1Access {
         f: Vec<u8>,
         k_oblv_t: Key,
         cs: Vec<u8>,
-    ) -> Result<(), MycoError> {
-        // Create the request payload
-        let queue_write_request = QueueWriteRequest {
-            ct,
-            f,
-            k_oblv_t,
-            cs,
-        };
-
-        // Serialize the request and log the size
-        let request_bytes = serialize(&queue_write_request).unwrap();
-        let queue_write_bytes_metric = BytesMetric::new("queue_write_bytes", request_bytes.len());
+        token: Vec<u8>,
+        access_tag: Option<Vec<u8>>,
+    ) -> Result<u64, MycoError> {
+        // Log the size of the request
+        let request_bytes = bincode::serialized_size(&(&ct, &f, &k_oblv_t, &cs, &token, &access_tag))
+            .map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+        let queue_write_bytes_metric = BytesMetric::new("queue_write_bytes", request_bytes as usize);
         queue_write_bytes_metric.log();
 
-        // Send POST request to Server1's queue_write endpoint
-        let response = self
-            .client
-            .post(&format!("{}/queue_write", self.base_url))
-            .header("Content-Type", "application/octet-stream")
-            .body(request_bytes)
-            .send()
-            .await
-            .map_err(|_| {
-                MycoError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Failed to send request to Server1",
-                ))
-            })?;
-
-        // Deserialize the response
-        let queue_write_response: QueueWriteResponse =
-            deserialize(&response.bytes().await.unwrap()).unwrap();
-
-        // Check for success response
-        if queue_write_response.success {
-            Ok(())
-        } else {
-            Err(MycoError::IoError(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Unexpected response from Server1",
-            )))
-        }
+        // Send the write to Server1's queue_write endpoint
+        expect_queued(
+            self.transport
+                .call(Command::Server1Write(ct, f, k_oblv_t, cs, token, access_tag))
+                .await?,
+        )
+    }
+
+    async fn guard_mailboxes(&self, guards: Vec<MailboxGuard>) -> Result<(), MycoError> {
+        expect_success(
+            self.transport
+                .call(Command::Server1GuardMailboxes(guards))
+                .await?,
+        )
+    }
+
+    async fn issue_write_tokens(
+        &self,
+        credentials: AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
+        expect_write_tokens(
+            self.transport
+                .call(Command::Server1IssueWriteTokens(credentials, requests))
+                .await?,
+        )
     }
 }
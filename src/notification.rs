@@ -0,0 +1,108 @@
+//! Notification index
+//!
+//! Finding out whether a contact wrote in the last epoch otherwise takes a full path read per
+//! contact. Instead, S1 derives a read tag from the location of every write it queues and, at the
+//! end of the epoch, inserts the tags into a cuckoo table that S2 publishes along with the epoch's
+//! PRF key. A client downloads the table once and checks the tag of each contact against it, which
+//! only takes the contact's location, i.e. values the client derives for reading anyway.
+//!
+//! Tags are a PRF of the location, so the table reveals nothing to whoever doesn't know the
+//! location. It does tell readers which contacts have nothing new: clients that want to hide that
+//! should keep reading on their usual schedule and use the index to pick which paths are real reads.
+
+use cuckoo::{Item, Table};
+use rand::{rngs::OsRng, Rng};
+
+use crate::{crypto::prf, error::MycoError};
+
+/// Bucket depth of the cuckoo table.
+const BUCKET_DEPTH: usize = 4;
+
+/// Load factor above which the table grows.
+const MAX_LOAD_FACTOR: f64 = 0.85;
+
+/// The tag S1 indexes for a write to location `l`.
+pub fn notification_tag(l: &[u8]) -> Result<Vec<u8>, MycoError> {
+    prf(l, b"NOTIFICATION-TAG")
+}
+
+/// Split a tag into the cuckoo item ID and sequence number it is stored under.
+fn tag_item(tag: &[u8]) -> (u64, u64) {
+    (
+        u64::from_be_bytes(tag[8..16].try_into().unwrap()),
+        u64::from_be_bytes(tag[..8].try_into().unwrap()),
+    )
+}
+
+/// The tags written in one epoch.
+pub struct NotificationIndex {
+    table: Table,
+}
+
+impl NotificationIndex {
+    /// Index `tags` in a table keyed with fresh random keys.
+    pub fn build(tags: &[Vec<u8>]) -> Result<Self, MycoError> {
+        let invalid = |e: cuckoo::Error| MycoError::ProtocolError(format!("notification index: {}", e));
+        let num_buckets = (tags.len() as f64 / (BUCKET_DEPTH as f64 * MAX_LOAD_FACTOR)).ceil() as usize;
+        let mut table = Table::new(
+            num_buckets.max(1),
+            BUCKET_DEPTH,
+            0,
+            None,
+            OsRng.gen(),
+            OsRng.gen::<[u8; 32]>().to_vec(),
+            OsRng.gen::<[u8; 32]>().to_vec(),
+        )
+        .ok_or_else(|| MycoError::ProtocolError("notification index: invalid table".to_string()))?;
+        table.set_max_load_factor(Some(MAX_LOAD_FACTOR)).map_err(invalid)?;
+
+        let items: Vec<Item> = tags
+            .iter()
+            .filter(|tag| tag.len() >= 16)
+            .map(|tag| {
+                let (id, seq_no) = tag_item(tag);
+                Item::new(id, vec![], seq_no, 0, 0)
+            })
+            .collect();
+        table.insert_batch(&items).map_err(invalid)?;
+        Ok(Self { table })
+    }
+
+    /// Number of tags in the index.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Whether the index holds no tags.
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Whether a write to location `l` is indexed.
+    pub fn contains_location(&self, l: &[u8]) -> Result<bool, MycoError> {
+        Ok(self.contains(&notification_tag(l)?))
+    }
+
+    /// Whether `tag` is indexed.
+    pub fn contains(&self, tag: &[u8]) -> bool {
+        if tag.len() < 16 {
+            return false;
+        }
+        let (id, seq_no) = tag_item(tag);
+        self.table.contains(id, seq_no)
+    }
+
+    /// Serialize the index.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MycoError> {
+        self.table
+            .to_bytes()
+            .map_err(|_| MycoError::SerializationFailed(None))
+    }
+
+    /// Restore an index serialized with [`NotificationIndex::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MycoError> {
+        Ok(Self {
+            table: Table::from_bytes(bytes).map_err(|_| MycoError::DeserializationError(None))?,
+        })
+    }
+}
@@ -0,0 +1,84 @@
+//! Pacing of Server1 to Server2 transfers
+//!
+//! A batch write sends the whole pathset to Server2 in chunks. Sending every chunk at once
+//! saturates constrained links until requests time out, so the chunks are sent through a window
+//! of in-flight requests that grows while Server2 answers promptly and shrinks when requests slow
+//! down or fail, in the style of TCP's additive increase, multiplicative decrease.
+
+use std::time::Duration;
+
+/// Number of chunks in flight before anything has been measured.
+pub const INITIAL_WINDOW: f64 = 4.0;
+
+/// Upper bound on the number of chunks in flight.
+pub const MAX_WINDOW: f64 = 64.0;
+
+/// A request taking more than this multiple of the fastest observed request counts as a sign of
+/// congestion.
+const LATENCY_TOLERANCE: f64 = 2.0;
+
+/// Factor the window shrinks by when requests slow down.
+const SLOWDOWN_FACTOR: f64 = 0.75;
+
+/// Factor the window shrinks by when a request fails.
+const FAILURE_FACTOR: f64 = 0.5;
+
+/// Number of times a chunk is resent after a network error.
+pub const MAX_CHUNK_RETRIES: usize = 3;
+
+/// Congestion window for chunked transfers, kept across batch writes so each epoch starts from
+/// what the link sustained during the last one.
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    window: f64,
+    base_latency: Option<Duration>,
+}
+
+impl Pacer {
+    /// Create a pacer starting at [`INITIAL_WINDOW`].
+    pub fn new() -> Self {
+        Self {
+            window: INITIAL_WINDOW,
+            base_latency: None,
+        }
+    }
+
+    /// The number of chunks that may be in flight.
+    pub fn window(&self) -> usize {
+        self.window as usize
+    }
+
+    /// The fastest request observed so far.
+    pub fn base_latency(&self) -> Option<Duration> {
+        self.base_latency
+    }
+
+    /// Record a request that succeeded after `latency`. A prompt answer grows the window by one
+    /// chunk per window's worth of answers; a slow one shrinks it.
+    pub fn on_success(&mut self, latency: Duration) {
+        let base = *self.base_latency.get_or_insert(latency);
+        if latency < base {
+            self.base_latency = Some(latency);
+        }
+        if latency.as_secs_f64() > base.as_secs_f64() * LATENCY_TOLERANCE {
+            self.shrink(SLOWDOWN_FACTOR);
+        } else {
+            self.window = (self.window + 1.0 / self.window).min(MAX_WINDOW);
+        }
+    }
+
+    /// Record a failed request.
+    pub fn on_failure(&mut self) {
+        self.shrink(FAILURE_FACTOR);
+    }
+
+    fn shrink(&mut self, factor: f64) {
+        self.window = (self.window * factor).max(1.0);
+    }
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,185 @@
+//! Pairing with a short code
+//!
+//! Two users who meet in person can set up a contact by exchanging a short code (e.g. six digits)
+//! instead of a full key. The code is stretched into a contact [`Key`] with SPAKE2 over
+//! Ristretto255, a password-authenticated key exchange: an attacker relaying the pairing messages
+//! learns nothing about the code and can only test a single guess per run, and a wrong code is
+//! caught by the key confirmation step rather than producing a silently mismatched key.
+//!
+//! Each side calls [`Pairing::start`] and sends its [`PairingMessage`] to the other, by any channel.
+//! [`Pairing::finish`] then yields the key, pending confirmation, and a confirmation tag to send
+//! back; [`PendingKey::confirm`] checks the other side's tag and releases the key. Each code must
+//! only be used for one pairing.
+
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT, ristretto::CompressedRistretto, RistrettoPoint, Scalar,
+};
+use rand::{rngs::OsRng, Rng};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use zeroize::Zeroize;
+
+use crate::{
+    crypto::{kdf, prf},
+    dtypes::Key,
+    error::MycoError,
+};
+
+/// Number of digits in a code from [`pairing_code`].
+pub const PAIRING_CODE_DIGITS: u32 = 6;
+
+/// Generate a random numeric pairing code.
+pub fn pairing_code() -> String {
+    let code = OsRng.gen_range(0..10u32.pow(PAIRING_CODE_DIGITS));
+    format!("{:0width$}", code, width = PAIRING_CODE_DIGITS as usize)
+}
+
+/// The SPAKE2 blinding points M and N, for the side with the smaller and larger ID respectively.
+/// Nobody knows their discrete logarithms, since they're hashed to the group.
+fn blinding_point(first: bool) -> RistrettoPoint {
+    let label: &[u8] = if first { b"myco-spake2-M" } else { b"myco-spake2-N" };
+    RistrettoPoint::hash_from_bytes::<Sha512>(label)
+}
+
+/// Hash length-prefixed fields together.
+fn hash_fields(fields: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for field in fields {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// A side's public pairing message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairingMessage {
+    /// The sender's client ID.
+    pub id: String,
+    /// The sender's blinded group element.
+    pub element: [u8; 32],
+}
+
+/// One side of a pairing in progress.
+pub struct Pairing {
+    id: String,
+    peer: String,
+    /// The code, hashed to a scalar.
+    password: Scalar,
+    secret: Scalar,
+    message: PairingMessage,
+}
+
+impl Drop for Pairing {
+    fn drop(&mut self) {
+        self.password.zeroize();
+        self.secret.zeroize();
+    }
+}
+
+impl Pairing {
+    /// Start pairing client `id` with client `peer` using `code`. Spaces and dashes in the code
+    /// are ignored. Returns the message to send to the peer.
+    pub fn start(id: &str, peer: &str, code: &str) -> Result<(Self, PairingMessage), MycoError> {
+        if id == peer {
+            return Err(MycoError::ProtocolError("cannot pair with oneself".to_string()));
+        }
+        let mut code: String = code.chars().filter(|c| !matches!(c, ' ' | '-')).collect();
+        if code.is_empty() {
+            return Err(MycoError::ProtocolError("empty pairing code".to_string()));
+        }
+        let (first, second) = if id < peer { (id, peer) } else { (peer, id) };
+        let password = Scalar::hash_from_bytes::<Sha512>(&hash_fields(&[
+            b"myco-pairing",
+            first.as_bytes(),
+            second.as_bytes(),
+            code.as_bytes(),
+        ]));
+        code.zeroize();
+
+        let secret = Scalar::random(&mut OsRng);
+        let element = secret * RISTRETTO_BASEPOINT_POINT + password * blinding_point(id < peer);
+        let message = PairingMessage {
+            id: id.to_string(),
+            element: element.compress().to_bytes(),
+        };
+        let pairing = Self {
+            id: id.to_string(),
+            peer: peer.to_string(),
+            password,
+            secret,
+            message: message.clone(),
+        };
+        Ok((pairing, message))
+    }
+
+    /// Complete the exchange with the peer's message. Returns the key, to be released once the
+    /// peer's confirmation checks out, and this side's confirmation to send to the peer.
+    pub fn finish(self, peer_message: &PairingMessage) -> Result<(PendingKey, Vec<u8>), MycoError> {
+        let invalid = || MycoError::ProtocolError("invalid pairing message".to_string());
+        if peer_message.id != self.peer {
+            return Err(invalid());
+        }
+        let peer_element = CompressedRistretto(peer_message.element)
+            .decompress()
+            .ok_or_else(invalid)?;
+        let shared = self.secret * (peer_element - self.password * blinding_point(self.peer < self.id));
+        if shared == RistrettoPoint::default() {
+            return Err(invalid());
+        }
+
+        let (first, second) = if self.id < self.peer {
+            (&self.message, peer_message)
+        } else {
+            (peer_message, &self.message)
+        };
+        let mut transcript = hash_fields(&[
+            first.id.as_bytes(),
+            second.id.as_bytes(),
+            &first.element,
+            &second.element,
+            shared.compress().as_bytes(),
+            self.password.as_bytes(),
+        ]);
+        let key = Key::new(kdf(&transcript, "PAIRING-KEY")?);
+        let confirmation_key = |id: &str| -> Result<hmac::Key, MycoError> {
+            let label = [b"PAIRING-CONFIRM ".as_slice(), id.as_bytes()].concat();
+            Ok(hmac::Key::new(hmac::HMAC_SHA256, &prf(&transcript, &label)?))
+        };
+        let own_key = confirmation_key(&self.id)?;
+        let peer_key = confirmation_key(&self.peer)?;
+        let confirmation = hmac::sign(&own_key, &transcript).as_ref().to_vec();
+        let pending = PendingKey {
+            key,
+            peer_key,
+            transcript: transcript.clone(),
+        };
+        transcript.zeroize();
+        Ok((pending, confirmation))
+    }
+}
+
+/// A key agreed on by a pairing, waiting for the peer's confirmation.
+pub struct PendingKey {
+    key: Key,
+    peer_key: hmac::Key,
+    transcript: Vec<u8>,
+}
+
+impl Drop for PendingKey {
+    fn drop(&mut self) {
+        self.transcript.zeroize();
+    }
+}
+
+impl PendingKey {
+    /// Check the peer's confirmation and return the contact key. Fails if the two sides used
+    /// different codes or the messages were tampered with.
+    pub fn confirm(self, peer_confirmation: &[u8]) -> Result<Key, MycoError> {
+        hmac::verify(&self.peer_key, &self.transcript, peer_confirmation).map_err(|_| {
+            MycoError::ProtocolError("pairing confirmation failed".to_string())
+        })?;
+        Ok(self.key.clone())
+    }
+}
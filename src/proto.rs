@@ -0,0 +1,1566 @@
+//! Protobuf codec for the RPC types
+//!
+//! With the `protobuf` feature, the [`crate::rpc_types`] can be encoded as the messages of
+//! `proto/myco.proto` instead of bincode, so implementations in other languages can generate
+//! their types from the schema rather than reimplementing bincode's layout. [`pb`] holds the
+//! messages as prost types and [`Protobuf`] converts between them and the RPC types.
+//!
+//! The codec doesn't change what the servers speak: the HTTPS routes and the framed transport
+//! still exchange bincode.
+
+use prost::Message;
+
+use crate::{
+    dtypes::{
+        AllocatorStats, BandwidthStats, Block, Bucket, Direction, EpochInfo, Key, MemoryStats,
+        Path, ReadStats, StorageStats, TrafficBytes, WriteStats,
+    },
+    error::{ErrorCode, MycoError},
+    idempotency::IdempotencyKey,
+    mailbox::MailboxGuard,
+    registration::{AccountCredentials, AccountId},
+    rpc_types::{
+        AdminStatsResponse, AdminStatusResponse, BatchInitRequest, BatchInitResponse,
+        BatchWriteResponse, Capabilities, ChunkReadPathsClientRequest, ChunkReadPathsRequest,
+        ChunkWriteRequest, ChunkWriteResponse, DeleteRegistrationRequest,
+        DeleteRegistrationResponse, EpochNumberResponse, ErrorResponse, GuardMailboxesRequest,
+        GuardMailboxesResponse, IssueWriteTokensRequest, IssueWriteTokensResponse,
+        FinalizeEpochRequest, FinalizeEpochResponse, GetCapabilitiesResponse, GetEpochResponse,
+        GetNotificationsResponse, GetPrfKeysResponse, GetPrfKeysSinceRequest, PendingEpochsRequest,
+        PendingEpochsResponse,
+        GetPrfKeysSinceResponse, GetStatsResponse, MemoryStatsResponse, PrefetchPathLeavesRequest,
+        PublishNotificationsRequest, PublishStorageRequest,
+        QueueWriteRequest, QueueWriteResponse, ReadPathsClientRequest, ReadPathsRequest,
+        ReadPathsResponse, ReadRequest, ReadResponse, RegisterResponse,
+        RotateRegistrationRequest, RotateRegistrationResponse, StorePathIndicesRequest,
+        StorePathIndicesResponse, StorePathLeavesRequest, StorePathLeavesResponse, WriteRequest,
+        WriteResponse,
+    },
+};
+
+/// The messages of `proto/myco.proto`, laid out as prost-build generates them. Messages with the
+/// same fields share a struct, aliased under each message's name.
+#[allow(missing_docs)]
+pub mod pb {
+    pub type BatchInitResponse = SuccessResponse;
+    pub type BatchWriteResponse = SuccessResponse;
+    pub type StorePathIndicesResponse = SuccessResponse;
+    pub type FinalizeEpochResponse = SuccessResponse;
+    pub type ChunkWriteResponse = SuccessResponse;
+    pub type WriteResponse = SuccessResponse;
+    pub type ReadPathsRequest = IndicesRequest;
+    pub type ReadPathsClientRequest = IndicesRequest;
+    pub type StorePathIndicesRequest = IndicesRequest;
+    pub type StorePathLeavesRequest = LeavesRequest;
+    pub type PrefetchPathLeavesRequest = LeavesRequest;
+    pub type ReadPathsResponse = BucketsResponse;
+    pub type ReadResponse = BucketsResponse;
+    pub type DeleteRegistrationResponse = SuccessResponse;
+    pub type GuardMailboxesResponse = SuccessResponse;
+    pub type RegisterResponse = CredentialsMessage;
+    pub type RotateRegistrationRequest = CredentialsMessage;
+    pub type RotateRegistrationResponse = CredentialsMessage;
+    pub type DeleteRegistrationRequest = CredentialsMessage;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Bucket {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub blocks: Vec<Vec<u8>>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Direction {
+        Left = 0,
+        Right = 1,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Path {
+        #[prost(enumeration = "Direction", repeated, tag = "1")]
+        pub directions: Vec<i32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct EpochInfo {
+        #[prost(uint64, tag = "1")]
+        pub epoch: u64,
+        #[prost(uint64, tag = "2")]
+        pub prf_key_cursor: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteStats {
+        #[prost(uint64, tag = "1")]
+        pub epoch: u64,
+        #[prost(uint64, tag = "2")]
+        pub writes: u64,
+        #[prost(uint64, tag = "3")]
+        pub distinct_writers: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ReadStats {
+        #[prost(uint64, tag = "1")]
+        pub epoch: u64,
+        #[prost(uint64, tag = "2")]
+        pub reads: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TrafficBytes {
+        #[prost(uint64, tag = "1")]
+        pub received: u64,
+        #[prost(uint64, tag = "2")]
+        pub sent: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BandwidthStats {
+        #[prost(uint64, tag = "1")]
+        pub epoch: u64,
+        #[prost(message, optional, tag = "2")]
+        pub batch_init: Option<TrafficBytes>,
+        #[prost(message, optional, tag = "3")]
+        pub batch_write: Option<TrafficBytes>,
+        #[prost(message, optional, tag = "4")]
+        pub client_reads: Option<TrafficBytes>,
+        #[prost(message, optional, tag = "5")]
+        pub client_writes: Option<TrafficBytes>,
+        #[prost(message, optional, tag = "6")]
+        pub other: Option<TrafficBytes>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ErrorResponse {
+        #[prost(uint32, tag = "1")]
+        pub code: u32,
+        #[prost(string, tag = "2")]
+        pub message: String,
+        #[prost(uint64, optional, tag = "3")]
+        pub next_epoch_opens_at: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct QueueWriteRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub ct: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub f: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub k_oblv_t: Vec<u8>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub cs: Vec<u8>,
+        #[prost(bytes = "vec", tag = "5")]
+        pub token: Vec<u8>,
+        #[prost(bytes = "vec", optional, tag = "6")]
+        pub idempotency_key: Option<Vec<u8>>,
+        #[prost(bytes = "vec", optional, tag = "7")]
+        pub access_tag: Option<Vec<u8>>,
+    }
+
+    /// The responses that only report success.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SuccessResponse {
+        #[prost(bool, tag = "1")]
+        pub success: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BatchInitRequest {
+        #[prost(uint64, tag = "1")]
+        pub num_writes: u64,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub idempotency_key: Option<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct QueueWriteResponse {
+        #[prost(bool, tag = "1")]
+        pub success: bool,
+        #[prost(uint64, tag = "2")]
+        pub epoch: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct EpochNumberResponse {
+        #[prost(uint64, tag = "1")]
+        pub epoch_number: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AdminStatusResponse {
+        #[prost(uint64, tag = "1")]
+        pub epoch: u64,
+        #[prost(bool, tag = "2")]
+        pub paused: bool,
+        #[prost(bool, tag = "3")]
+        pub draining: bool,
+        #[prost(bool, tag = "4")]
+        pub epoch_open: bool,
+        #[prost(uint64, tag = "5")]
+        pub queue_depth: u64,
+        #[prost(uint64, tag = "6")]
+        pub pathset_size: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AdminStatsResponse {
+        #[prost(message, optional, tag = "1")]
+        pub current: Option<WriteStats>,
+        #[prost(message, optional, tag = "2")]
+        pub previous: Option<WriteStats>,
+        #[prost(message, optional, tag = "3")]
+        pub bandwidth: Option<BandwidthStats>,
+        #[prost(message, optional, tag = "4")]
+        pub previous_bandwidth: Option<BandwidthStats>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AllocatorStats {
+        #[prost(uint64, tag = "1")]
+        pub allocated_bytes: u64,
+        #[prost(uint64, tag = "2")]
+        pub peak_bytes: u64,
+        #[prost(uint64, tag = "3")]
+        pub allocations: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MemoryStats {
+        #[prost(uint64, tag = "1")]
+        pub tree_bytes: u64,
+        #[prost(uint64, tag = "2")]
+        pub metadata_bytes: u64,
+        #[prost(uint64, tag = "3")]
+        pub queue_depth: u64,
+        #[prost(message, optional, tag = "4")]
+        pub allocator: Option<AllocatorStats>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MemoryStatsResponse {
+        #[prost(message, optional, tag = "1")]
+        pub stats: Option<MemoryStats>,
+    }
+
+    /// `ReadPathsRequest`, `ReadPathsClientRequest` and `StorePathIndicesRequest`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IndicesRequest {
+        #[prost(uint64, repeated, tag = "1")]
+        pub indices: Vec<u64>,
+    }
+
+    /// `ReadPathsResponse` and `ReadResponse`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BucketsResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub buckets: Vec<Bucket>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ReadRequest {
+        #[prost(message, optional, tag = "1")]
+        pub path: Option<Path>,
+    }
+
+    /// `StorePathLeavesRequest` and `PrefetchPathLeavesRequest`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct LeavesRequest {
+        #[prost(uint32, repeated, tag = "1")]
+        pub leaves: Vec<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StorePathLeavesResponse {
+        #[prost(uint64, tag = "1")]
+        pub pathset_size: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChunkReadPathsRequest {
+        #[prost(uint64, tag = "1")]
+        pub chunk_idx: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChunkReadPathsClientRequest {
+        #[prost(uint64, repeated, tag = "1")]
+        pub indices: Vec<u64>,
+        #[prost(uint64, tag = "2")]
+        pub chunk_idx: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FinalizeEpochRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub prf_key: Vec<u8>,
+        #[prost(uint64, tag = "2")]
+        pub epoch: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ChunkWriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub buckets: Vec<Bucket>,
+        #[prost(uint64, tag = "2")]
+        pub chunk_idx: u64,
+        #[prost(bytes = "vec", tag = "3")]
+        pub prf_key: Vec<u8>,
+        #[prost(uint64, tag = "4")]
+        pub epoch: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub buckets: Vec<Bucket>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub prf_key: Vec<u8>,
+        #[prost(uint64, tag = "3")]
+        pub epoch: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetPrfKeysResponse {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub keys: Vec<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Capabilities {
+        #[prost(uint64, tag = "1")]
+        pub max_request_size: u64,
+        #[prost(uint64, tag = "2")]
+        pub write_chunk_buckets: u64,
+        #[prost(uint64, tag = "3")]
+        pub read_chunk_buckets: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetCapabilitiesResponse {
+        #[prost(message, optional, tag = "1")]
+        pub capabilities: Option<Capabilities>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetEpochResponse {
+        #[prost(message, optional, tag = "1")]
+        pub info: Option<EpochInfo>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TagBlocks {
+        #[prost(string, tag = "1")]
+        pub tag: String,
+        #[prost(uint64, tag = "2")]
+        pub blocks: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StorageStats {
+        #[prost(uint64, tag = "1")]
+        pub epochs: u64,
+        #[prost(uint64, tag = "2")]
+        pub blocks: u64,
+        #[prost(uint64, tag = "3")]
+        pub tags: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub heaviest: Vec<TagBlocks>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetStatsResponse {
+        #[prost(message, optional, tag = "1")]
+        pub current: Option<ReadStats>,
+        #[prost(message, optional, tag = "2")]
+        pub previous: Option<ReadStats>,
+        #[prost(message, optional, tag = "3")]
+        pub storage: Option<StorageStats>,
+        #[prost(message, optional, tag = "4")]
+        pub bandwidth: Option<BandwidthStats>,
+        #[prost(message, optional, tag = "5")]
+        pub previous_bandwidth: Option<BandwidthStats>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetPrfKeysSinceRequest {
+        #[prost(uint64, tag = "1")]
+        pub cursor: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetPrfKeysSinceResponse {
+        #[prost(uint64, tag = "1")]
+        pub start: u64,
+        #[prost(bytes = "vec", repeated, tag = "2")]
+        pub keys: Vec<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PublishNotificationsRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub index: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StorageEntry {
+        #[prost(bytes = "vec", tag = "1")]
+        pub tag: Vec<u8>,
+        #[prost(uint64, tag = "2")]
+        pub blocks: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PublishStorageRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub report: Vec<StorageEntry>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Notifications {
+        #[prost(uint64, tag = "1")]
+        pub cursor: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub index: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GetNotificationsResponse {
+        #[prost(message, optional, tag = "1")]
+        pub notifications: Option<Notifications>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ReadTag {
+        #[prost(uint64, tag = "1")]
+        pub epoch: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub tag: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PendingEpochsRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub tags: Vec<ReadTag>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PendingEpochsResponse {
+        #[prost(uint64, repeated, tag = "1")]
+        pub epochs: Vec<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AccountCredentials {
+        #[prost(bytes = "vec", tag = "1")]
+        pub account: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub secret: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct MailboxGuard {
+        #[prost(bytes = "vec", tag = "1")]
+        pub address: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub key: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GuardMailboxesRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub guards: Vec<MailboxGuard>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct EpochToken {
+        #[prost(uint64, tag = "1")]
+        pub epoch: u64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub token: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IssueWriteTokensRequest {
+        #[prost(message, optional, tag = "1")]
+        pub credentials: Option<AccountCredentials>,
+        #[prost(message, repeated, tag = "2")]
+        pub requests: Vec<EpochToken>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IssueWriteTokensResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub tokens: Vec<EpochToken>,
+    }
+
+    /// `RegisterResponse`, `RotateRegistrationRequest`, `RotateRegistrationResponse` and
+    /// `DeleteRegistrationRequest`.
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CredentialsMessage {
+        #[prost(message, optional, tag = "1")]
+        pub credentials: Option<AccountCredentials>,
+    }
+}
+
+/// An RPC type with a protobuf message in `proto/myco.proto`.
+pub trait Protobuf: Sized {
+    /// The message the type is encoded as.
+    type Message: Message + Default;
+
+    /// Convert to the message.
+    fn to_message(&self) -> Self::Message;
+
+    /// Convert from the message, rejecting values the RPC type can't hold.
+    fn from_message(message: Self::Message) -> Result<Self, MycoError>;
+}
+
+/// Encode `value` as its protobuf message.
+pub fn encode<T: Protobuf>(value: &T) -> Vec<u8> {
+    value.to_message().encode_to_vec()
+}
+
+/// Decode a protobuf message into its RPC type.
+pub fn decode<T: Protobuf>(bytes: &[u8]) -> Result<T, MycoError> {
+    let message = T::Message::decode(bytes)
+        .map_err(|e| MycoError::MalformedRequest(format!("invalid protobuf message: {}", e)))?;
+    T::from_message(message)
+}
+
+fn size(value: u64) -> Result<usize, MycoError> {
+    usize::try_from(value)
+        .map_err(|_| MycoError::MalformedRequest(format!("{} does not fit in usize", value)))
+}
+
+fn sizes(values: Vec<u64>) -> Result<Vec<usize>, MycoError> {
+    values.into_iter().map(size).collect()
+}
+
+fn required<T>(field: Option<T>, name: &str) -> Result<T, MycoError> {
+    field.ok_or_else(|| MycoError::MalformedRequest(format!("missing {}", name)))
+}
+
+fn idempotency_key_message(key: &Option<IdempotencyKey>) -> Option<Vec<u8>> {
+    key.map(|key| key.0.to_vec())
+}
+
+fn idempotency_key(message: Option<Vec<u8>>) -> Result<Option<IdempotencyKey>, MycoError> {
+    message
+        .map(|bytes| {
+            bytes.try_into().map(IdempotencyKey).map_err(|bytes: Vec<u8>| {
+                MycoError::MalformedRequest(format!("idempotency key of {} bytes", bytes.len()))
+            })
+        })
+        .transpose()
+}
+
+fn bucket_message(bucket: &Bucket) -> pb::Bucket {
+    pb::Bucket {
+        blocks: bucket.iter().map(|block| block.0.clone()).collect(),
+    }
+}
+
+fn bucket(message: pb::Bucket) -> Bucket {
+    let mut bucket = Bucket::default();
+    for block in message.blocks {
+        bucket.push(Block::new(block));
+    }
+    bucket
+}
+
+fn buckets_message(buckets: &[Bucket]) -> Vec<pb::Bucket> {
+    buckets.iter().map(bucket_message).collect()
+}
+
+fn buckets(messages: Vec<pb::Bucket>) -> Vec<Bucket> {
+    messages.into_iter().map(bucket).collect()
+}
+
+fn keys_message(keys: &[Key]) -> Vec<Vec<u8>> {
+    keys.iter().map(|key| key.0.clone()).collect()
+}
+
+fn keys(messages: Vec<Vec<u8>>) -> Vec<Key> {
+    messages.into_iter().map(Key::new).collect()
+}
+
+fn path_message(path: &Path) -> pb::Path {
+    pb::Path {
+        directions: path
+            .0
+            .iter()
+            .map(|direction| match direction {
+                Direction::Left => pb::Direction::Left as i32,
+                Direction::Right => pb::Direction::Right as i32,
+            })
+            .collect(),
+    }
+}
+
+fn path(message: pb::Path) -> Result<Path, MycoError> {
+    message
+        .directions
+        .into_iter()
+        .map(|direction| match pb::Direction::try_from(direction) {
+            Ok(pb::Direction::Left) => Ok(Direction::Left),
+            Ok(pb::Direction::Right) => Ok(Direction::Right),
+            Err(_) => Err(MycoError::MalformedRequest(format!(
+                "invalid direction {}",
+                direction
+            ))),
+        })
+        .collect::<Result<_, _>>()
+        .map(Path::new)
+}
+
+fn epoch_info_message(info: &EpochInfo) -> pb::EpochInfo {
+    pb::EpochInfo {
+        epoch: info.epoch,
+        prf_key_cursor: info.prf_key_cursor,
+    }
+}
+
+fn write_stats_message(stats: &WriteStats) -> pb::WriteStats {
+    pb::WriteStats {
+        epoch: stats.epoch,
+        writes: stats.writes as u64,
+        distinct_writers: stats.distinct_writers as u64,
+    }
+}
+
+fn write_stats(message: pb::WriteStats) -> Result<WriteStats, MycoError> {
+    Ok(WriteStats {
+        epoch: message.epoch,
+        writes: size(message.writes)?,
+        distinct_writers: size(message.distinct_writers)?,
+    })
+}
+
+fn memory_stats_message(stats: &MemoryStats) -> pb::MemoryStats {
+    pb::MemoryStats {
+        tree_bytes: stats.tree_bytes as u64,
+        metadata_bytes: stats.metadata_bytes as u64,
+        queue_depth: stats.queue_depth as u64,
+        allocator: stats.allocator.map(|allocator| pb::AllocatorStats {
+            allocated_bytes: allocator.allocated_bytes as u64,
+            peak_bytes: allocator.peak_bytes as u64,
+            allocations: allocator.allocations as u64,
+        }),
+    }
+}
+
+fn memory_stats(message: pb::MemoryStats) -> Result<MemoryStats, MycoError> {
+    Ok(MemoryStats {
+        tree_bytes: size(message.tree_bytes)?,
+        metadata_bytes: size(message.metadata_bytes)?,
+        queue_depth: size(message.queue_depth)?,
+        allocator: message
+            .allocator
+            .map(|allocator| {
+                Ok::<_, MycoError>(AllocatorStats {
+                    allocated_bytes: size(allocator.allocated_bytes)?,
+                    peak_bytes: size(allocator.peak_bytes)?,
+                    allocations: size(allocator.allocations)?,
+                })
+            })
+            .transpose()?,
+    })
+}
+
+fn read_stats_message(stats: &ReadStats) -> pb::ReadStats {
+    pb::ReadStats {
+        epoch: stats.epoch,
+        reads: stats.reads as u64,
+    }
+}
+
+fn read_stats(message: pb::ReadStats) -> Result<ReadStats, MycoError> {
+    Ok(ReadStats {
+        epoch: message.epoch,
+        reads: size(message.reads)?,
+    })
+}
+
+fn bandwidth_stats_message(stats: &BandwidthStats) -> pb::BandwidthStats {
+    let bytes = |bytes: &TrafficBytes| {
+        Some(pb::TrafficBytes {
+            received: bytes.received as u64,
+            sent: bytes.sent as u64,
+        })
+    };
+    pb::BandwidthStats {
+        epoch: stats.epoch,
+        batch_init: bytes(&stats.batch_init),
+        batch_write: bytes(&stats.batch_write),
+        client_reads: bytes(&stats.client_reads),
+        client_writes: bytes(&stats.client_writes),
+        other: bytes(&stats.other),
+    }
+}
+
+fn bandwidth_stats(message: pb::BandwidthStats) -> Result<BandwidthStats, MycoError> {
+    let bytes = |bytes: Option<pb::TrafficBytes>| {
+        bytes.map_or(Ok(TrafficBytes::default()), |bytes| {
+            Ok::<_, MycoError>(TrafficBytes {
+                received: size(bytes.received)?,
+                sent: size(bytes.sent)?,
+            })
+        })
+    };
+    Ok(BandwidthStats {
+        epoch: message.epoch,
+        batch_init: bytes(message.batch_init)?,
+        batch_write: bytes(message.batch_write)?,
+        client_reads: bytes(message.client_reads)?,
+        client_writes: bytes(message.client_writes)?,
+        other: bytes(message.other)?,
+    })
+}
+
+fn storage_stats_message(stats: &StorageStats) -> pb::StorageStats {
+    pb::StorageStats {
+        epochs: stats.epochs as u64,
+        blocks: stats.blocks as u64,
+        tags: stats.tags as u64,
+        heaviest: stats
+            .heaviest
+            .iter()
+            .map(|(tag, blocks)| pb::TagBlocks {
+                tag: tag.clone(),
+                blocks: *blocks as u64,
+            })
+            .collect(),
+    }
+}
+
+fn storage_stats(message: pb::StorageStats) -> Result<StorageStats, MycoError> {
+    Ok(StorageStats {
+        epochs: size(message.epochs)?,
+        blocks: size(message.blocks)?,
+        tags: size(message.tags)?,
+        heaviest: message
+            .heaviest
+            .into_iter()
+            .map(|entry| Ok((entry.tag, size(entry.blocks)?)))
+            .collect::<Result<_, MycoError>>()?,
+    })
+}
+
+fn credentials_message(credentials: &AccountCredentials) -> pb::AccountCredentials {
+    pb::AccountCredentials {
+        account: credentials.account.0.to_vec(),
+        secret: credentials.secret.0.clone(),
+    }
+}
+
+fn credentials(message: pb::AccountCredentials) -> Result<AccountCredentials, MycoError> {
+    let account = message.account.try_into().map_err(|account: Vec<u8>| {
+        MycoError::MalformedRequest(format!("account ID of {} bytes", account.len()))
+    })?;
+    Ok(AccountCredentials {
+        account: AccountId(account),
+        secret: Key::new(message.secret),
+    })
+}
+
+/// Implement [`Protobuf`] for the responses that only report success.
+macro_rules! success_responses {
+    ($($response:ident),* $(,)?) => {
+        $(
+            impl Protobuf for $response {
+                type Message = pb::SuccessResponse;
+
+                fn to_message(&self) -> pb::SuccessResponse {
+                    pb::SuccessResponse {
+                        success: self.success,
+                    }
+                }
+
+                fn from_message(message: pb::SuccessResponse) -> Result<Self, MycoError> {
+                    Ok(Self {
+                        success: message.success,
+                    })
+                }
+            }
+        )*
+    };
+}
+
+success_responses!(
+    BatchInitResponse,
+    BatchWriteResponse,
+    StorePathIndicesResponse,
+    FinalizeEpochResponse,
+    ChunkWriteResponse,
+    WriteResponse,
+    DeleteRegistrationResponse,
+    GuardMailboxesResponse,
+);
+
+/// Implement [`Protobuf`] for the messages carrying account credentials.
+macro_rules! credentials_messages {
+    ($($message:ident),* $(,)?) => {
+        $(
+            impl Protobuf for $message {
+                type Message = pb::CredentialsMessage;
+
+                fn to_message(&self) -> pb::CredentialsMessage {
+                    pb::CredentialsMessage {
+                        credentials: Some(credentials_message(&self.credentials)),
+                    }
+                }
+
+                fn from_message(message: pb::CredentialsMessage) -> Result<Self, MycoError> {
+                    Ok(Self {
+                        credentials: credentials(required(message.credentials, "credentials")?)?,
+                    })
+                }
+            }
+        )*
+    };
+}
+
+credentials_messages!(
+    RegisterResponse,
+    RotateRegistrationRequest,
+    RotateRegistrationResponse,
+    DeleteRegistrationRequest,
+);
+
+impl Protobuf for ErrorResponse {
+    type Message = pb::ErrorResponse;
+
+    fn to_message(&self) -> pb::ErrorResponse {
+        pb::ErrorResponse {
+            code: self.code.into(),
+            message: self.message.clone(),
+            next_epoch_opens_at: self.next_epoch_opens_at,
+        }
+    }
+
+    fn from_message(message: pb::ErrorResponse) -> Result<Self, MycoError> {
+        let code = ErrorCode::try_from(message.code)
+            .map_err(|code| MycoError::ProtocolError(format!("unknown error code {}", code)))?;
+        Ok(Self {
+            code,
+            message: message.message,
+            next_epoch_opens_at: message.next_epoch_opens_at,
+        })
+    }
+}
+
+impl Protobuf for QueueWriteRequest {
+    type Message = pb::QueueWriteRequest;
+
+    fn to_message(&self) -> pb::QueueWriteRequest {
+        pb::QueueWriteRequest {
+            ct: self.ct.clone(),
+            f: self.f.clone(),
+            k_oblv_t: self.k_oblv_t.0.clone(),
+            cs: self.cs.clone(),
+            token: self.token.clone(),
+            idempotency_key: idempotency_key_message(&self.idempotency_key),
+            access_tag: self.access_tag.clone(),
+        }
+    }
+
+    fn from_message(message: pb::QueueWriteRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            ct: message.ct,
+            f: message.f,
+            k_oblv_t: Key::new(message.k_oblv_t),
+            cs: message.cs,
+            token: message.token,
+            idempotency_key: idempotency_key(message.idempotency_key)?,
+            access_tag: message.access_tag,
+        })
+    }
+}
+
+impl Protobuf for GuardMailboxesRequest {
+    type Message = pb::GuardMailboxesRequest;
+
+    fn to_message(&self) -> pb::GuardMailboxesRequest {
+        pb::GuardMailboxesRequest {
+            guards: self
+                .guards
+                .iter()
+                .map(|guard| pb::MailboxGuard {
+                    address: guard.address.clone(),
+                    key: guard.key.0.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_message(message: pb::GuardMailboxesRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            guards: message
+                .guards
+                .into_iter()
+                .map(|guard| MailboxGuard {
+                    address: guard.address,
+                    key: Key::new(guard.key),
+                })
+                .collect(),
+        })
+    }
+}
+
+fn epoch_tokens(tokens: &[(u64, Vec<u8>)]) -> Vec<pb::EpochToken> {
+    tokens
+        .iter()
+        .map(|(epoch, token)| pb::EpochToken {
+            epoch: *epoch,
+            token: token.clone(),
+        })
+        .collect()
+}
+
+impl Protobuf for IssueWriteTokensRequest {
+    type Message = pb::IssueWriteTokensRequest;
+
+    fn to_message(&self) -> pb::IssueWriteTokensRequest {
+        pb::IssueWriteTokensRequest {
+            credentials: Some(credentials_message(&self.credentials)),
+            requests: epoch_tokens(&self.requests),
+        }
+    }
+
+    fn from_message(message: pb::IssueWriteTokensRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            credentials: credentials(required(message.credentials, "credentials")?)?,
+            requests: message
+                .requests
+                .into_iter()
+                .map(|request| (request.epoch, request.token))
+                .collect(),
+        })
+    }
+}
+
+impl Protobuf for IssueWriteTokensResponse {
+    type Message = pb::IssueWriteTokensResponse;
+
+    fn to_message(&self) -> pb::IssueWriteTokensResponse {
+        pb::IssueWriteTokensResponse {
+            tokens: epoch_tokens(&self.tokens),
+        }
+    }
+
+    fn from_message(message: pb::IssueWriteTokensResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            tokens: message
+                .tokens
+                .into_iter()
+                .map(|token| (token.epoch, token.token))
+                .collect(),
+        })
+    }
+}
+
+impl Protobuf for BatchInitRequest {
+    type Message = pb::BatchInitRequest;
+
+    fn to_message(&self) -> pb::BatchInitRequest {
+        pb::BatchInitRequest {
+            num_writes: self.num_writes as u64,
+            idempotency_key: idempotency_key_message(&self.idempotency_key),
+        }
+    }
+
+    fn from_message(message: pb::BatchInitRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            num_writes: size(message.num_writes)?,
+            idempotency_key: idempotency_key(message.idempotency_key)?,
+        })
+    }
+}
+
+impl Protobuf for QueueWriteResponse {
+    type Message = pb::QueueWriteResponse;
+
+    fn to_message(&self) -> pb::QueueWriteResponse {
+        pb::QueueWriteResponse {
+            success: self.success,
+            epoch: self.epoch,
+        }
+    }
+
+    fn from_message(message: pb::QueueWriteResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            success: message.success,
+            epoch: message.epoch,
+        })
+    }
+}
+
+impl Protobuf for EpochNumberResponse {
+    type Message = pb::EpochNumberResponse;
+
+    fn to_message(&self) -> pb::EpochNumberResponse {
+        pb::EpochNumberResponse {
+            epoch_number: self.epoch_number,
+        }
+    }
+
+    fn from_message(message: pb::EpochNumberResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            epoch_number: message.epoch_number,
+        })
+    }
+}
+
+impl Protobuf for AdminStatusResponse {
+    type Message = pb::AdminStatusResponse;
+
+    fn to_message(&self) -> pb::AdminStatusResponse {
+        pb::AdminStatusResponse {
+            epoch: self.epoch,
+            paused: self.paused,
+            draining: self.draining,
+            epoch_open: self.epoch_open,
+            queue_depth: self.queue_depth as u64,
+            pathset_size: self.pathset_size as u64,
+        }
+    }
+
+    fn from_message(message: pb::AdminStatusResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            epoch: message.epoch,
+            paused: message.paused,
+            draining: message.draining,
+            epoch_open: message.epoch_open,
+            queue_depth: size(message.queue_depth)?,
+            pathset_size: size(message.pathset_size)?,
+        })
+    }
+}
+
+impl Protobuf for AdminStatsResponse {
+    type Message = pb::AdminStatsResponse;
+
+    fn to_message(&self) -> pb::AdminStatsResponse {
+        pb::AdminStatsResponse {
+            current: Some(write_stats_message(&self.current)),
+            previous: self.previous.as_ref().map(write_stats_message),
+            bandwidth: Some(bandwidth_stats_message(&self.bandwidth)),
+            previous_bandwidth: self.previous_bandwidth.as_ref().map(bandwidth_stats_message),
+        }
+    }
+
+    fn from_message(message: pb::AdminStatsResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            current: write_stats(required(message.current, "current")?)?,
+            previous: message.previous.map(write_stats).transpose()?,
+            bandwidth: message
+                .bandwidth
+                .map(bandwidth_stats)
+                .transpose()?
+                .unwrap_or_default(),
+            previous_bandwidth: message.previous_bandwidth.map(bandwidth_stats).transpose()?,
+        })
+    }
+}
+
+impl Protobuf for MemoryStatsResponse {
+    type Message = pb::MemoryStatsResponse;
+
+    fn to_message(&self) -> pb::MemoryStatsResponse {
+        pb::MemoryStatsResponse {
+            stats: Some(memory_stats_message(&self.stats)),
+        }
+    }
+
+    fn from_message(message: pb::MemoryStatsResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            stats: memory_stats(required(message.stats, "stats")?)?,
+        })
+    }
+}
+
+impl Protobuf for ReadPathsRequest {
+    type Message = pb::IndicesRequest;
+
+    fn to_message(&self) -> pb::IndicesRequest {
+        pb::IndicesRequest {
+            indices: self.indices.iter().map(|&index| index as u64).collect(),
+        }
+    }
+
+    fn from_message(message: pb::IndicesRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            indices: sizes(message.indices)?,
+        })
+    }
+}
+
+impl Protobuf for ReadPathsClientRequest {
+    type Message = pb::IndicesRequest;
+
+    fn to_message(&self) -> pb::IndicesRequest {
+        pb::IndicesRequest {
+            indices: self.indices.iter().map(|&index| index as u64).collect(),
+        }
+    }
+
+    fn from_message(message: pb::IndicesRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            indices: sizes(message.indices)?,
+        })
+    }
+}
+
+impl Protobuf for StorePathIndicesRequest {
+    type Message = pb::IndicesRequest;
+
+    fn to_message(&self) -> pb::IndicesRequest {
+        pb::IndicesRequest {
+            indices: self.pathset.iter().map(|&index| index as u64).collect(),
+        }
+    }
+
+    fn from_message(message: pb::IndicesRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            pathset: sizes(message.indices)?,
+        })
+    }
+}
+
+impl Protobuf for ReadPathsResponse {
+    type Message = pb::BucketsResponse;
+
+    fn to_message(&self) -> pb::BucketsResponse {
+        pb::BucketsResponse {
+            buckets: buckets_message(&self.buckets),
+        }
+    }
+
+    fn from_message(message: pb::BucketsResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            buckets: buckets(message.buckets),
+        })
+    }
+}
+
+impl Protobuf for ReadResponse {
+    type Message = pb::BucketsResponse;
+
+    fn to_message(&self) -> pb::BucketsResponse {
+        pb::BucketsResponse {
+            buckets: buckets_message(&self.buckets),
+        }
+    }
+
+    fn from_message(message: pb::BucketsResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            buckets: buckets(message.buckets),
+        })
+    }
+}
+
+impl Protobuf for ReadRequest {
+    type Message = pb::ReadRequest;
+
+    fn to_message(&self) -> pb::ReadRequest {
+        pb::ReadRequest {
+            path: Some(path_message(&self.path)),
+        }
+    }
+
+    fn from_message(message: pb::ReadRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            path: path(required(message.path, "path")?)?,
+        })
+    }
+}
+
+impl Protobuf for StorePathLeavesRequest {
+    type Message = pb::LeavesRequest;
+
+    fn to_message(&self) -> pb::LeavesRequest {
+        pb::LeavesRequest {
+            leaves: self.leaves.clone(),
+        }
+    }
+
+    fn from_message(message: pb::LeavesRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            leaves: message.leaves,
+        })
+    }
+}
+
+impl Protobuf for PrefetchPathLeavesRequest {
+    type Message = pb::LeavesRequest;
+
+    fn to_message(&self) -> pb::LeavesRequest {
+        pb::LeavesRequest {
+            leaves: self.leaves.clone(),
+        }
+    }
+
+    fn from_message(message: pb::LeavesRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            leaves: message.leaves,
+        })
+    }
+}
+
+impl Protobuf for StorePathLeavesResponse {
+    type Message = pb::StorePathLeavesResponse;
+
+    fn to_message(&self) -> pb::StorePathLeavesResponse {
+        pb::StorePathLeavesResponse {
+            pathset_size: self.pathset_size as u64,
+        }
+    }
+
+    fn from_message(message: pb::StorePathLeavesResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            pathset_size: size(message.pathset_size)?,
+        })
+    }
+}
+
+impl Protobuf for ChunkReadPathsRequest {
+    type Message = pb::ChunkReadPathsRequest;
+
+    fn to_message(&self) -> pb::ChunkReadPathsRequest {
+        pb::ChunkReadPathsRequest {
+            chunk_idx: self.chunk_idx as u64,
+        }
+    }
+
+    fn from_message(message: pb::ChunkReadPathsRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            chunk_idx: size(message.chunk_idx)?,
+        })
+    }
+}
+
+impl Protobuf for ChunkReadPathsClientRequest {
+    type Message = pb::ChunkReadPathsClientRequest;
+
+    fn to_message(&self) -> pb::ChunkReadPathsClientRequest {
+        pb::ChunkReadPathsClientRequest {
+            indices: self.indices.iter().map(|&index| index as u64).collect(),
+            chunk_idx: self.chunk_idx as u64,
+        }
+    }
+
+    fn from_message(message: pb::ChunkReadPathsClientRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            indices: sizes(message.indices)?,
+            chunk_idx: size(message.chunk_idx)?,
+        })
+    }
+}
+
+impl Protobuf for FinalizeEpochRequest {
+    type Message = pb::FinalizeEpochRequest;
+
+    fn to_message(&self) -> pb::FinalizeEpochRequest {
+        pb::FinalizeEpochRequest {
+            prf_key: self.prf_key.0.clone(),
+            epoch: self.epoch,
+        }
+    }
+
+    fn from_message(message: pb::FinalizeEpochRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            prf_key: Key::new(message.prf_key),
+            epoch: message.epoch,
+        })
+    }
+}
+
+impl Protobuf for ChunkWriteRequest {
+    type Message = pb::ChunkWriteRequest;
+
+    fn to_message(&self) -> pb::ChunkWriteRequest {
+        pb::ChunkWriteRequest {
+            buckets: buckets_message(&self.buckets),
+            chunk_idx: self.chunk_idx as u64,
+            prf_key: self.prf_key.0.clone(),
+            epoch: self.epoch,
+        }
+    }
+
+    fn from_message(message: pb::ChunkWriteRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            buckets: buckets(message.buckets),
+            chunk_idx: size(message.chunk_idx)?,
+            prf_key: Key::new(message.prf_key),
+            epoch: message.epoch,
+        })
+    }
+}
+
+impl Protobuf for WriteRequest {
+    type Message = pb::WriteRequest;
+
+    fn to_message(&self) -> pb::WriteRequest {
+        pb::WriteRequest {
+            buckets: buckets_message(&self.buckets),
+            prf_key: self.prf_key.0.clone(),
+            epoch: self.epoch,
+        }
+    }
+
+    fn from_message(message: pb::WriteRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            buckets: buckets(message.buckets),
+            prf_key: Key::new(message.prf_key),
+            epoch: message.epoch,
+        })
+    }
+}
+
+impl Protobuf for GetPrfKeysResponse {
+    type Message = pb::GetPrfKeysResponse;
+
+    fn to_message(&self) -> pb::GetPrfKeysResponse {
+        pb::GetPrfKeysResponse {
+            keys: keys_message(&self.keys),
+        }
+    }
+
+    fn from_message(message: pb::GetPrfKeysResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            keys: keys(message.keys),
+        })
+    }
+}
+
+impl Protobuf for Capabilities {
+    type Message = pb::Capabilities;
+
+    fn to_message(&self) -> pb::Capabilities {
+        pb::Capabilities {
+            max_request_size: self.max_request_size as u64,
+            write_chunk_buckets: self.write_chunk_buckets as u64,
+            read_chunk_buckets: self.read_chunk_buckets as u64,
+        }
+    }
+
+    fn from_message(message: pb::Capabilities) -> Result<Self, MycoError> {
+        Ok(Self {
+            max_request_size: size(message.max_request_size)?,
+            write_chunk_buckets: size(message.write_chunk_buckets)?,
+            read_chunk_buckets: size(message.read_chunk_buckets)?,
+        })
+    }
+}
+
+impl Protobuf for GetCapabilitiesResponse {
+    type Message = pb::GetCapabilitiesResponse;
+
+    fn to_message(&self) -> pb::GetCapabilitiesResponse {
+        pb::GetCapabilitiesResponse {
+            capabilities: Some(self.capabilities.to_message()),
+        }
+    }
+
+    fn from_message(message: pb::GetCapabilitiesResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            capabilities: Capabilities::from_message(required(
+                message.capabilities,
+                "capabilities",
+            )?)?,
+        })
+    }
+}
+
+impl Protobuf for GetEpochResponse {
+    type Message = pb::GetEpochResponse;
+
+    fn to_message(&self) -> pb::GetEpochResponse {
+        pb::GetEpochResponse {
+            info: Some(epoch_info_message(&self.info)),
+        }
+    }
+
+    fn from_message(message: pb::GetEpochResponse) -> Result<Self, MycoError> {
+        let info = required(message.info, "info")?;
+        Ok(Self {
+            info: EpochInfo {
+                epoch: info.epoch,
+                prf_key_cursor: info.prf_key_cursor,
+            },
+        })
+    }
+}
+
+impl Protobuf for GetStatsResponse {
+    type Message = pb::GetStatsResponse;
+
+    fn to_message(&self) -> pb::GetStatsResponse {
+        pb::GetStatsResponse {
+            current: Some(read_stats_message(&self.current)),
+            previous: self.previous.as_ref().map(read_stats_message),
+            storage: Some(storage_stats_message(&self.storage)),
+            bandwidth: Some(bandwidth_stats_message(&self.bandwidth)),
+            previous_bandwidth: self.previous_bandwidth.as_ref().map(bandwidth_stats_message),
+        }
+    }
+
+    fn from_message(message: pb::GetStatsResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            current: read_stats(required(message.current, "current")?)?,
+            previous: message.previous.map(read_stats).transpose()?,
+            storage: message
+                .storage
+                .map(storage_stats)
+                .transpose()?
+                .unwrap_or_default(),
+            bandwidth: message
+                .bandwidth
+                .map(bandwidth_stats)
+                .transpose()?
+                .unwrap_or_default(),
+            previous_bandwidth: message.previous_bandwidth.map(bandwidth_stats).transpose()?,
+        })
+    }
+}
+
+impl Protobuf for GetPrfKeysSinceRequest {
+    type Message = pb::GetPrfKeysSinceRequest;
+
+    fn to_message(&self) -> pb::GetPrfKeysSinceRequest {
+        pb::GetPrfKeysSinceRequest {
+            cursor: self.cursor,
+        }
+    }
+
+    fn from_message(message: pb::GetPrfKeysSinceRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            cursor: message.cursor,
+        })
+    }
+}
+
+impl Protobuf for GetPrfKeysSinceResponse {
+    type Message = pb::GetPrfKeysSinceResponse;
+
+    fn to_message(&self) -> pb::GetPrfKeysSinceResponse {
+        pb::GetPrfKeysSinceResponse {
+            start: self.start,
+            keys: keys_message(&self.keys),
+        }
+    }
+
+    fn from_message(message: pb::GetPrfKeysSinceResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            start: message.start,
+            keys: keys(message.keys),
+        })
+    }
+}
+
+impl Protobuf for PublishStorageRequest {
+    type Message = pb::PublishStorageRequest;
+
+    fn to_message(&self) -> pb::PublishStorageRequest {
+        pb::PublishStorageRequest {
+            report: self
+                .report
+                .iter()
+                .map(|(tag, blocks)| pb::StorageEntry {
+                    tag: tag.clone(),
+                    blocks: *blocks as u64,
+                })
+                .collect(),
+        }
+    }
+
+    fn from_message(message: pb::PublishStorageRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            report: message
+                .report
+                .into_iter()
+                .map(|entry| Ok((entry.tag, size(entry.blocks)?)))
+                .collect::<Result<_, MycoError>>()?,
+        })
+    }
+}
+
+impl Protobuf for PublishNotificationsRequest {
+    type Message = pb::PublishNotificationsRequest;
+
+    fn to_message(&self) -> pb::PublishNotificationsRequest {
+        pb::PublishNotificationsRequest {
+            index: self.index.clone(),
+        }
+    }
+
+    fn from_message(message: pb::PublishNotificationsRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            index: message.index,
+        })
+    }
+}
+
+impl Protobuf for GetNotificationsResponse {
+    type Message = pb::GetNotificationsResponse;
+
+    fn to_message(&self) -> pb::GetNotificationsResponse {
+        pb::GetNotificationsResponse {
+            notifications: self
+                .notifications
+                .as_ref()
+                .map(|(cursor, index)| pb::Notifications {
+                    cursor: *cursor,
+                    index: index.clone(),
+                }),
+        }
+    }
+
+    fn from_message(message: pb::GetNotificationsResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            notifications: message
+                .notifications
+                .map(|notifications| (notifications.cursor, notifications.index)),
+        })
+    }
+}
+
+impl Protobuf for PendingEpochsRequest {
+    type Message = pb::PendingEpochsRequest;
+
+    fn to_message(&self) -> pb::PendingEpochsRequest {
+        pb::PendingEpochsRequest {
+            tags: self
+                .tags
+                .iter()
+                .map(|(epoch, tag)| pb::ReadTag {
+                    epoch: *epoch,
+                    tag: tag.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_message(message: pb::PendingEpochsRequest) -> Result<Self, MycoError> {
+        Ok(Self {
+            tags: message.tags.into_iter().map(|tag| (tag.epoch, tag.tag)).collect(),
+        })
+    }
+}
+
+impl Protobuf for PendingEpochsResponse {
+    type Message = pb::PendingEpochsResponse;
+
+    fn to_message(&self) -> pb::PendingEpochsResponse {
+        pb::PendingEpochsResponse {
+            epochs: self.epochs.clone(),
+        }
+    }
+
+    fn from_message(message: pb::PendingEpochsResponse) -> Result<Self, MycoError> {
+        Ok(Self {
+            epochs: message.epochs,
+        })
+    }
+}
@@ -0,0 +1,219 @@
+//! Pseudonymous client registration
+//!
+//! Clients can register a stable account with Server1, e.g. to be billed or to be counted against
+//! a cap on registered clients. An account is a random [`AccountId`] and a secret, both picked by
+//! Server1 when the account is created and handed back together as [`AccountCredentials`]; the
+//! server keeps only a hash of the secret. The account carries no name or contact key, and writes
+//! never mention it: an account is issued one blinded write token per epoch (see
+//! [`crate::write_tokens`]), and its writes are counted under those tokens and under pseudonyms
+//! the client derives from keys Server1 never sees. Server1 can therefore tell how many accounts
+//! exist but not which account made a given write.
+//!
+//! Rotating an account replaces both its ID and its secret, so the old and new IDs can only be
+//! linked by the server at the moment of rotation. Deleting an account forgets it entirely.
+
+use std::{collections::HashMap, time::SystemTime};
+
+use rand::{CryptoRng, RngCore};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::{dtypes::Key, error::MycoError, server1::unix_millis};
+
+/// Environment variable setting the maximum number of accounts Server1 registers.
+pub const MAX_REGISTRATIONS_ENV: &str = "MYCO_MAX_REGISTRATIONS";
+
+/// Read the cap on accounts from [`MAX_REGISTRATIONS_ENV`]. Accounts are uncapped when it isn't
+/// set.
+pub fn limit_from_env() -> Result<Option<usize>, MycoError> {
+    match std::env::var(MAX_REGISTRATIONS_ENV) {
+        Ok(value) => value.parse().map(Some).map_err(|_| {
+            MycoError::ConfigError(format!("invalid {} {}", MAX_REGISTRATIONS_ENV, value))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Size of an account secret in bytes.
+pub const ACCOUNT_SECRET_SIZE: usize = 32;
+
+/// The random, stable identifier of a registered account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AccountId(pub [u8; 16]);
+
+impl AccountId {
+    /// A fresh random account ID.
+    pub fn random<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut id = [0; 16];
+        rng.fill_bytes(&mut id);
+        AccountId(id)
+    }
+}
+
+impl std::fmt::Display for AccountId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+/// The ID and secret of an account, proving ownership when rotating or deleting it.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountCredentials {
+    /// The account ID.
+    pub account: AccountId,
+    /// The account secret.
+    pub secret: Key,
+}
+
+// Custom Debug implementation for AccountCredentials to keep the secret out of logs
+impl std::fmt::Debug for AccountCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AccountCredentials({})", self.account)
+    }
+}
+
+/// What Server1 keeps about an account.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Registration {
+    /// When the account was created, in milliseconds since the Unix epoch. Kept across rotations.
+    pub created_at: u64,
+    /// When the account was last rotated, in milliseconds since the Unix epoch.
+    pub rotated_at: Option<u64>,
+    /// The last epoch a write token was issued for, if any. Kept across rotations.
+    pub tokens_issued_through: Option<u64>,
+    /// SHA-256 of the account secret.
+    secret_hash: Vec<u8>,
+}
+
+/// The registered accounts of a Server1.
+#[derive(Default)]
+pub struct Registry {
+    accounts: HashMap<AccountId, Registration>,
+    /// Maximum number of accounts, if capped.
+    limit: Option<usize>,
+}
+
+fn hash_secret(secret: &Key) -> Vec<u8> {
+    digest::digest(&digest::SHA256, &secret.0).as_ref().to_vec()
+}
+
+impl Registry {
+    /// An empty registry without a cap on accounts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cap the number of accounts, or lift the cap with `None`. Existing accounts are kept.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Create an account.
+    pub fn register<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<AccountCredentials, MycoError> {
+        if self.limit.is_some_and(|limit| self.accounts.len() >= limit) {
+            return Err(MycoError::RegistrationLimitReached);
+        }
+        Ok(self.insert(unix_millis(SystemTime::now()), None, None, rng))
+    }
+
+    /// Replace the ID and secret of the account of `credentials`, keeping its creation time.
+    pub fn rotate<R: RngCore + CryptoRng>(
+        &mut self,
+        credentials: &AccountCredentials,
+        rng: &mut R,
+    ) -> Result<AccountCredentials, MycoError> {
+        self.verify(credentials)?;
+        let registration = self
+            .accounts
+            .remove(&credentials.account)
+            .ok_or(MycoError::UnknownRegistration)?;
+        let rotated_at = unix_millis(SystemTime::now());
+        Ok(self.insert(
+            registration.created_at,
+            Some(rotated_at),
+            registration.tokens_issued_through,
+            rng,
+        ))
+    }
+
+    /// Delete the account of `credentials`.
+    pub fn delete(&mut self, credentials: &AccountCredentials) -> Result<(), MycoError> {
+        self.verify(credentials)?;
+        self.accounts.remove(&credentials.account);
+        Ok(())
+    }
+
+    /// Check that `credentials` belong to a registered account. An unknown account and a wrong
+    /// secret fail alike.
+    pub fn verify(&self, credentials: &AccountCredentials) -> Result<&Registration, MycoError> {
+        self.accounts
+            .get(&credentials.account)
+            .filter(|registration| registration.secret_hash == hash_secret(&credentials.secret))
+            .ok_or(MycoError::UnknownRegistration)
+    }
+
+    /// Claim write tokens for `epochs` on behalf of the account of `credentials`, returning the
+    /// epochs the account may be issued a token for: those after the last epoch it was issued one
+    /// for. Every account gets at most one token per epoch.
+    pub fn claim_write_tokens(
+        &mut self,
+        credentials: &AccountCredentials,
+        epochs: &[u64],
+    ) -> Result<Vec<u64>, MycoError> {
+        self.verify(credentials)?;
+        let registration = self
+            .accounts
+            .get_mut(&credentials.account)
+            .ok_or(MycoError::UnknownRegistration)?;
+        let mut claimed: Vec<u64> = epochs
+            .iter()
+            .copied()
+            .filter(|epoch| registration.tokens_issued_through.is_none_or(|last| *epoch > last))
+            .collect();
+        claimed.sort_unstable();
+        claimed.dedup();
+        if let Some(last) = claimed.last() {
+            registration.tokens_issued_through = Some(*last);
+        }
+        Ok(claimed)
+    }
+
+    /// Number of registered accounts.
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Whether no accounts are registered.
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    fn insert<R: RngCore + CryptoRng>(
+        &mut self,
+        created_at: u64,
+        rotated_at: Option<u64>,
+        tokens_issued_through: Option<u64>,
+        rng: &mut R,
+    ) -> AccountCredentials {
+        let mut account = AccountId::random(rng);
+        while self.accounts.contains_key(&account) {
+            account = AccountId::random(rng);
+        }
+        let mut secret = vec![0; ACCOUNT_SECRET_SIZE];
+        rng.fill_bytes(&mut secret);
+        let secret = Key::new(secret);
+        self.accounts.insert(
+            account,
+            Registration {
+                created_at,
+                rotated_at,
+                tokens_issued_through,
+                secret_hash: hash_secret(&secret),
+            },
+        );
+        AccountCredentials { account, secret }
+    }
+}
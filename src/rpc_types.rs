@@ -1,7 +1,107 @@
 //! RPC types for the server-client communication.
-use crate::dtypes::{Bucket, Key, Path};
+use crate::{
+    backup::Backup,
+    constants::{
+        ENCODED_BUCKET_SIZE, MAX_CHUNK_WRITE_BODY_SIZE, NUM_BUCKETS_PER_BATCH_WRITE_CHUNK,
+        NUM_BUCKETS_PER_READ_PATHS_CHUNK,
+    },
+    dtypes::{
+        BandwidthStats, Bucket, EpochInfo, Key, MemoryStats, Path, ReadStats, StorageReport,
+        StorageStats, WriteStats,
+    },
+    error::{ErrorCode, MycoError},
+    idempotency::IdempotencyKey,
+    mailbox::MailboxGuard,
+    registration::AccountCredentials,
+    transport::NEXT_EPOCH_HEADER,
+};
+use axum::{
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
 use serde::{Deserialize, Serialize};
 
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+/// The body of every failed request, from which the caller rebuilds the server's error.
+pub struct ErrorResponse {
+    /// The code of the error.
+    pub code: ErrorCode,
+    /// The message of the error.
+    pub message: String,
+    /// For a closed epoch, the estimated opening time of the next one in milliseconds since the
+    /// Unix epoch, if known.
+    pub next_epoch_opens_at: Option<u64>,
+}
+
+impl ErrorResponse {
+    /// The HTTP status the error is sent with.
+    pub fn status(&self) -> StatusCode {
+        match self.code {
+            ErrorCode::EpochClosed | ErrorCode::EpochInitializing => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ErrorCode::WriteQuotaExceeded | ErrorCode::RegistrationLimitReached => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ErrorCode::StaleEpoch | ErrorCode::PrfKeyReused => StatusCode::CONFLICT,
+            ErrorCode::MailboxAccessDenied | ErrorCode::InvalidWriteToken => StatusCode::FORBIDDEN,
+            ErrorCode::MalformedRequest
+            | ErrorCode::DeserializationError
+            | ErrorCode::InvalidBatchSize
+            | ErrorCode::InvalidCommand
+            | ErrorCode::EpochOutOfRange => StatusCode::BAD_REQUEST,
+            ErrorCode::NoMessageFound
+            | ErrorCode::PrfKeyUnavailable
+            | ErrorCode::UnknownRegistration => StatusCode::NOT_FOUND,
+            ErrorCode::NetworkError | ErrorCode::TransportError => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Rebuild the error on the caller's side.
+    pub fn into_error(self) -> MycoError {
+        match self.code {
+            ErrorCode::EpochClosed => MycoError::EpochClosed {
+                next_epoch_opens_at: self.next_epoch_opens_at,
+            },
+            code => MycoError::from_remote(code, self.message),
+        }
+    }
+}
+
+impl From<MycoError> for ErrorResponse {
+    fn from(err: MycoError) -> Self {
+        let next_epoch_opens_at = match err {
+            MycoError::EpochClosed { next_epoch_opens_at } => next_epoch_opens_at,
+            _ => None,
+        };
+        Self {
+            code: err.code(),
+            message: err.to_string(),
+            next_epoch_opens_at,
+        }
+    }
+}
+
+impl IntoResponse for ErrorResponse {
+    /// The bincoded error with its status. A closed epoch also carries the next opening time in
+    /// [`NEXT_EPOCH_HEADER`].
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let next_epoch_opens_at = self.next_epoch_opens_at;
+        let mut response = match bincode::serialize(&self) {
+            Ok(body) => (status, body).into_response(),
+            Err(_) => status.into_response(),
+        };
+        if let Some(at) = next_epoch_opens_at {
+            response
+                .headers_mut()
+                .insert(NEXT_EPOCH_HEADER, HeaderValue::from(at));
+        }
+        response
+    }
+}
+
 // Server1 RPC types
 #[derive(Deserialize, Serialize, Debug)]
 /// A request to queue a write operation on Server1.
@@ -12,8 +112,14 @@ pub struct QueueWriteRequest {
     pub f: Vec<u8>,
     /// The temporary ORAM key for this write.
     pub k_oblv_t: Key,
-    /// The encrypted state.
+    /// The client's pseudonym for this epoch, see [`crate::crypto::client_pseudonym`].
     pub cs: Vec<u8>,
+    /// The client's write token for this epoch, counted against Server1's write quota.
+    pub token: Vec<u8>,
+    /// Key under which Server1 remembers the response, so a resent request is only queued once.
+    pub idempotency_key: Option<IdempotencyKey>,
+    /// The access tag of a write to a guarded mailbox, see [`crate::mailbox`].
+    pub access_tag: Option<Vec<u8>>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -21,6 +127,8 @@ pub struct QueueWriteRequest {
 pub struct QueueWriteResponse {
     /// Whether the queue write was successful.
     pub success: bool,
+    /// The epoch the write was queued into.
+    pub epoch: u64,
 }
 
 // Server2 RPC types
@@ -74,21 +182,50 @@ pub struct StorePathIndicesResponse {
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-/// A request to read a chunk of paths from Server2.
+/// A request to store a pathset on Server2 as the leaves its paths lead to. Server2 expands them
+/// into the pathset indices, see [`crate::utils::get_leaf_path_indices`].
+pub struct StorePathLeavesRequest {
+    /// The leaves of the pathset's paths, numbered left to right from 0.
+    pub leaves: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response to storing a pathset by its leaves.
+pub struct StorePathLeavesResponse {
+    /// The number of buckets in the expanded pathset, from which the reader splits its chunked
+    /// reads.
+    pub pathset_size: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// A request to read the paths to the given leaves from Server2 without storing them as the
+/// pathset. The response is laid out like a [`ReadPathsResponse`].
+pub struct PrefetchPathLeavesRequest {
+    /// The leaves of the paths to read, numbered left to right from 0.
+    pub leaves: Vec<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// A request to read a chunk of paths from Server2. The response streams the chunk sparsely as
+/// [`SparsePathFrame`]s, see [`crate::streaming::framed_response`].
 pub struct ChunkReadPathsRequest {
     /// The index of the chunk to read.
     pub chunk_idx: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-/// A response containing buckets from a chunk read.
-pub struct ChunkReadPathsResponse {
-    /// The buckets read from the chunk.
-    pub buckets: Vec<Bucket>,
+#[derive(Deserialize, Serialize, Debug)]
+/// A frame of a chunk_read_paths response: the chunk's presence bitmap comes first, followed by
+/// its non-empty buckets, see [`crate::dtypes::SparseBuckets`].
+pub enum SparsePathFrame {
+    /// The number of buckets in the chunk and its presence bitmap.
+    Present(u64, Vec<u8>),
+    /// The next non-empty bucket.
+    Bucket(Bucket),
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-/// A request from a client to read a chunk of paths from Server2.
+/// A request from a client to read a chunk of paths from Server2. The response streams the
+/// buckets as frames, see [`crate::streaming::framed_response`].
 pub struct ChunkReadPathsClientRequest {
     /// The indices of the paths to read.
     pub indices: Vec<usize>,
@@ -96,18 +233,13 @@ pub struct ChunkReadPathsClientRequest {
     pub chunk_idx: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-/// A response containing buckets from a client chunk read.
-pub struct ChunkReadPathsClientResponse {
-    /// The buckets read from the chunk.
-    pub buckets: Vec<Bucket>,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 /// A request to finalize the epoch by adding the new PRF key and incrementing the epoch.
 pub struct FinalizeEpochRequest {
     /// The PRF key for the next epoch.
     pub prf_key: Key,
+    /// The epoch being finalized.
+    pub epoch: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -119,6 +251,9 @@ pub struct FinalizeEpochResponse {
 
 #[derive(Deserialize, Serialize, Debug)]
 /// A request to write a chunk of buckets to the server.
+///
+/// Sent and received as a stream (see [`crate::streaming`]), which relies on `buckets` being the
+/// first field.
 pub struct ChunkWriteRequest {
     /// The buckets to be written.
     pub buckets: Vec<Bucket>,
@@ -126,6 +261,8 @@ pub struct ChunkWriteRequest {
     pub chunk_idx: usize,
     /// The PRF key for the current epoch.
     pub prf_key: Key,
+    /// The epoch the chunk belongs to.
+    pub epoch: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -142,6 +279,8 @@ pub struct WriteRequest {
     pub buckets: Vec<Bucket>,
     /// The PRF key for the current epoch.
     pub prf_key: Key,
+    /// The epoch being written.
+    pub epoch: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -151,6 +290,20 @@ pub struct WriteResponse {
     pub success: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+/// A request for a backup of Server2, see [`crate::backup`].
+pub struct BackupRequest {
+    /// The epoch of the backup to build on, or `None` for a full backup.
+    pub since: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response carrying a backup of Server2.
+pub struct BackupResponse {
+    /// The backup.
+    pub backup: Backup,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 /// A response containing the PRF keys for the current epoch.
 pub struct GetPrfKeysResponse {
@@ -158,11 +311,137 @@ pub struct GetPrfKeysResponse {
     pub keys: Vec<Key>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// The request sizes a Server2 build accepts, advertised so that clients built with different
+/// constants split their chunked reads and writes the way the server expects.
+pub struct Capabilities {
+    /// The largest chunk_write body the server accepts, in bytes.
+    pub max_request_size: usize,
+    /// The number of buckets per chunk_write request. The server places each chunk's buckets by
+    /// this count, so writers must use it exactly.
+    pub write_chunk_buckets: usize,
+    /// The number of buckets per chunked path read.
+    pub read_chunk_buckets: usize,
+}
+
+impl Capabilities {
+    /// The capabilities of this build.
+    pub fn local() -> Self {
+        Self {
+            max_request_size: MAX_CHUNK_WRITE_BODY_SIZE,
+            write_chunk_buckets: NUM_BUCKETS_PER_BATCH_WRITE_CHUNK,
+            read_chunk_buckets: NUM_BUCKETS_PER_READ_PATHS_CHUNK,
+        }
+    }
+
+    /// Check that the advertised sizes can be used to split requests.
+    pub fn validate(&self) -> Result<(), MycoError> {
+        if self.write_chunk_buckets == 0 || self.read_chunk_buckets == 0 {
+            return Err(MycoError::ProtocolError(format!(
+                "server advertised empty chunks: {:?}",
+                self
+            )));
+        }
+        if self.write_chunk_buckets.saturating_mul(ENCODED_BUCKET_SIZE) > self.max_request_size {
+            return Err(MycoError::ProtocolError(format!(
+                "server chunk of {} buckets exceeds its request limit of {} bytes",
+                self.write_chunk_buckets, self.max_request_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response containing Server2's capabilities.
+pub struct GetCapabilitiesResponse {
+    /// The capabilities.
+    pub capabilities: Capabilities,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response containing Server2's current epoch and PRF key cursor.
+pub struct GetEpochResponse {
+    /// The epoch and PRF key cursor.
+    pub info: EpochInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response containing Server2's read counts, without any per-client detail.
+pub struct GetStatsResponse {
+    /// Read counts of the current epoch so far.
+    pub current: ReadStats,
+    /// Read counts of the last completed epoch.
+    pub previous: Option<ReadStats>,
+    /// Storage use of the live epochs, by anonymous storage tag.
+    pub storage: StorageStats,
+    /// Bytes received and sent in the current epoch so far.
+    pub bandwidth: BandwidthStats,
+    /// Bytes received and sent in the last completed epoch.
+    pub previous_bandwidth: Option<BandwidthStats>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// A request for the PRF keys published since a cursor.
+pub struct GetPrfKeysSinceRequest {
+    /// Number of PRF keys the client has already seen.
+    pub cursor: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response containing the PRF keys published since a cursor.
+pub struct GetPrfKeysSinceResponse {
+    /// Number of the first key returned. Differs from the requested cursor when the client's
+    /// keys are no longer all live, in which case the client should replace its keys.
+    pub start: u64,
+    /// The PRF keys, oldest first.
+    pub keys: Vec<Key>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// A request publishing the notification index of the epoch being written.
+pub struct PublishNotificationsRequest {
+    /// The serialized notification index.
+    pub index: Vec<u8>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// A request publishing the storage report of the epoch being written.
+pub struct PublishStorageRequest {
+    /// The number of blocks written per storage tag.
+    pub report: StorageReport,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response containing the notification index of the newest epoch.
+pub struct GetNotificationsResponse {
+    /// The PRF key cursor right after the epoch's key and the serialized index, or `None` if no
+    /// index was published for the newest epoch.
+    pub notifications: Option<(u64, Vec<u8>)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A request asking which epochs hold writes matching a client's read tags.
+pub struct PendingEpochsRequest {
+    /// Read tags (see [`crate::notification::notification_tag`]), each with the epoch it was
+    /// derived for.
+    pub tags: Vec<(u64, Vec<u8>)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response listing the epochs with writes matching a client's read tags.
+pub struct PendingEpochsResponse {
+    /// The epochs, in ascending order.
+    pub epochs: Vec<u64>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 /// A request to initialize a batch of writes.
 pub struct BatchInitRequest {
     /// The number of writes to be performed in the batch.
     pub num_writes: usize,
+    /// Key under which Server1 remembers the response, so a resent request only starts one batch.
+    pub idempotency_key: Option<IdempotencyKey>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -179,9 +458,118 @@ pub struct BatchWriteResponse {
     pub success: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+/// A response carrying the credentials of a newly registered account.
+pub struct RegisterResponse {
+    /// The ID and secret of the account.
+    pub credentials: AccountCredentials,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A request to replace the ID and secret of an account.
+pub struct RotateRegistrationRequest {
+    /// The current ID and secret of the account.
+    pub credentials: AccountCredentials,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response carrying the new credentials of a rotated account.
+pub struct RotateRegistrationResponse {
+    /// The new ID and secret of the account.
+    pub credentials: AccountCredentials,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A request to delete an account.
+pub struct DeleteRegistrationRequest {
+    /// The ID and secret of the account.
+    pub credentials: AccountCredentials,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response indicating whether deleting an account was successful.
+pub struct DeleteRegistrationResponse {
+    /// Whether deleting the account was successful.
+    pub success: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A request to guard mailbox addresses on Server1.
+pub struct GuardMailboxesRequest {
+    /// The guards to set.
+    pub guards: Vec<MailboxGuard>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response indicating whether guarding the mailboxes was successful.
+pub struct GuardMailboxesResponse {
+    /// Whether guarding the mailboxes was successful.
+    pub success: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A request for write tokens of a registered account.
+pub struct IssueWriteTokensRequest {
+    /// The ID and secret of the account.
+    pub credentials: AccountCredentials,
+    /// The epochs to issue tokens for, each with its blinded token.
+    pub requests: Vec<(u64, Vec<u8>)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A response carrying the issued write tokens.
+pub struct IssueWriteTokensResponse {
+    /// The epochs a token was issued for, each with its evaluated token.
+    pub tokens: Vec<(u64, Vec<u8>)>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 /// A response containing the current epoch number.
 pub struct EpochNumberResponse {
     /// The current epoch number.
     pub epoch_number: u64,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// A response describing Server1's epoch state, returned by the admin endpoints.
+pub struct AdminStatusResponse {
+    /// The current epoch number.
+    pub epoch: u64,
+    /// Whether the epoch scheduler is paused.
+    pub paused: bool,
+    /// Whether the server is draining and rejecting new writes.
+    pub draining: bool,
+    /// Whether a batch has been initialized and is accepting writes.
+    pub epoch_open: bool,
+    /// The number of writes queued for the current epoch.
+    pub queue_depth: usize,
+    /// The number of buckets in the current epoch's pathset.
+    pub pathset_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A response containing Server1's write counts, returned by the admin stats endpoint.
+pub struct AdminStatsResponse {
+    /// Write counts of the current epoch so far.
+    pub current: WriteStats,
+    /// Write counts of the last completed epoch.
+    pub previous: Option<WriteStats>,
+    /// Bytes received and sent in the current epoch so far.
+    pub bandwidth: BandwidthStats,
+    /// Bytes received and sent in the last completed epoch.
+    pub previous_bandwidth: Option<BandwidthStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A response containing a server's memory use, returned by the admin memory endpoints.
+pub struct MemoryStatsResponse {
+    /// The server's memory use.
+    pub stats: MemoryStats,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// A request for a standby Server1 to take over from its primary, sent to `/admin/failover`.
+pub struct FailoverRequest {
+    /// The number of writes the taken over batch is initialized for.
+    pub num_writes: usize,
+}
@@ -0,0 +1,263 @@
+//! Server1 secrets
+//!
+//! Everything Server1 computes from secrets goes through [`SecretCompute`]: drawing the epoch key
+//! k_s1_t, evaluating `prf(k_s1_t, f || cs)` to place writes, and opening and sealing blocks with
+//! the oblivious keys kept in the metadata tree. [`HostSecrets`], the default, does this in the
+//! server process. A deployment that wants to reduce its trust in the S1 operator can instead run
+//! these operations inside an SGX or SEV enclave behind the same trait, see
+//! [`Server1::set_secret_compute`](crate::server1::Server1::set_secret_compute).
+//!
+//! The epoch key leaves the implementation only through [`SecretCompute::epoch_key`], when the
+//! epoch is published to Server2. Keys in the metadata tree are handed over per block, so an
+//! enclave implementation keeping those from the operator too has to seal the tree itself.
+
+use std::sync::{Arc, RwLock};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+
+use crate::{
+    constants::{INNER_BLOCK_SIZE, MESSAGE_SIZE, Z},
+    crypto::{decrypt, encrypt, prf, EncryptionType},
+    dtypes::{Block, Key},
+    error::MycoError,
+    simulation::SimulationMode,
+    utils::{pad, unpad, Padding},
+};
+
+/// The secret-dependent operations of Server1.
+pub trait SecretCompute: Send + Sync {
+    /// Draw a fresh epoch key, replacing the previous one.
+    fn rotate_epoch_key(&self);
+
+    /// The current epoch key, released when the epoch is published to Server2.
+    fn epoch_key(&self) -> Result<Key, MycoError>;
+
+    /// The path of a write to mailbox `f` by pseudonym `cs` in the current epoch,
+    /// `prf(k_s1_t, f || cs)`.
+    fn location(&self, f: &[u8], cs: &[u8]) -> Result<Vec<u8>, MycoError>;
+
+    /// The message ciphertext in `block`, opened with the oblivious key from its metadata.
+    fn open_block(&self, k_oblv_t: &Key, block: &Block) -> Result<Vec<u8>, MycoError>;
+
+    /// A block holding the message ciphertext `ct`, sealed under `k_oblv_t` behind its key hint.
+    fn seal_block(&self, k_oblv_t: &Key, ct: &[u8]) -> Result<Block, MycoError>;
+
+    /// The current epoch key encrypted under `wrapping_key`, for a standby that may have to take
+    /// the epoch over (see [`crate::standby`]). Unsupported unless implemented.
+    fn sealed_epoch_key(&self, _wrapping_key: &Key) -> Result<Vec<u8>, MycoError> {
+        Err(MycoError::ConfigError("the epoch key can't be replicated".to_string()))
+    }
+
+    /// Replace the current epoch key with one sealed by [`SecretCompute::sealed_epoch_key`].
+    fn unseal_epoch_key(&self, _wrapping_key: &Key, _sealed: &[u8]) -> Result<(), MycoError> {
+        Err(MycoError::ConfigError("the epoch key can't be replicated".to_string()))
+    }
+}
+
+/// Stand-in for the block operations on a bucket slot that holds no live message.
+///
+/// Server1 opens and seals `Z` blocks per bucket in every batch, real or not, so the time a batch
+/// takes doesn't reveal how many of its blocks are live. That only holds if a dummy operation does
+/// byte-for-byte the same work as a real one, so both go through the same [`SecretCompute`] calls
+/// with inputs of the same sizes, and their results are handled the same way:
+///
+/// - [`DummyBlock::open`] opens a block genuinely sealed under a throwaway oblivious key. It passes
+///   the key commitment and is decrypted and authenticated in full, as a live block is, instead of
+///   being turned away at the commitment check as a block of zeros would be.
+/// - [`DummyBlock::seal`] seals an `INNER_BLOCK_SIZE` ciphertext behind its key hint, the size of
+///   every message ciphertext Server1 queues.
+///
+/// Neither fails unless the real operation would, so callers propagate their errors exactly like
+/// those of the real ones.
+pub struct DummyBlock {
+    k_oblv_t: Key,
+    ct: Vec<u8>,
+    block: Block,
+}
+
+impl DummyBlock {
+    /// Seal a dummy block under a fresh throwaway key.
+    pub fn new(secrets: &dyn SecretCompute) -> Result<Self, MycoError> {
+        let k_oblv_t = Key::random(&mut ChaCha20Rng::from_entropy());
+        let ct = vec![0u8; INNER_BLOCK_SIZE];
+        let block = secrets.seal_block(&k_oblv_t, &ct)?;
+        Ok(Self { k_oblv_t, ct, block })
+    }
+
+    /// Open the dummy block, the work of opening one live block.
+    pub fn open(&self, secrets: &dyn SecretCompute) -> Result<Vec<u8>, MycoError> {
+        secrets.open_block(&self.k_oblv_t, &self.block)
+    }
+
+    /// Seal the dummy ciphertext, the work of sealing one queued message.
+    pub fn seal(&self, secrets: &dyn SecretCompute) -> Result<Block, MycoError> {
+        secrets.seal_block(&self.k_oblv_t, &self.ct)
+    }
+}
+
+/// The block operations of one Server1 batch, `Z` per bucket.
+///
+/// A bucket's real operations run through the [`BucketOpens`] or [`BucketSeals`] handed to the
+/// closure of [`ObliviousBatch::open_bucket`] or [`ObliviousBatch::seal_bucket`], each spending one
+/// operation of the bucket's budget of `Z`. Once the closure returns, the rest of the budget is
+/// spent on [`DummyBlock`] operations. Callers never see the count: the handles can only be made
+/// here and can't outlive the closure, so every bucket costs exactly `Z` operations however many of
+/// its blocks are live. Without encryption there is nothing to hide and no dummies are run.
+///
+/// The budget is counted at runtime, as the number of live blocks is only known then. A bucket
+/// never holds more than `Z` blocks, so spending past the budget is a bug in the caller: the
+/// operation is refused with an error, in release builds too, and the batch fails rather than run
+/// a bucket that takes longer than the others.
+pub struct ObliviousBatch {
+    secrets: Arc<dyn SecretCompute>,
+    simulation: SimulationMode,
+    dummy: DummyBlock,
+}
+
+/// The openings of one bucket's blocks, see [`ObliviousBatch::open_bucket`].
+pub struct BucketOpens<'a> {
+    secrets: &'a dyn SecretCompute,
+    remaining: usize,
+}
+
+/// The sealings of one bucket's blocks, see [`ObliviousBatch::seal_bucket`].
+pub struct BucketSeals<'a> {
+    secrets: &'a dyn SecretCompute,
+    remaining: usize,
+}
+
+/// Spend one operation of a bucket's budget, or refuse it once the budget is spent.
+fn spend(remaining: &mut usize) -> Result<(), MycoError> {
+    *remaining = remaining.checked_sub(1).ok_or_else(|| {
+        MycoError::ProtocolError(format!("more than Z={} block operations on one bucket", Z))
+    })?;
+    Ok(())
+}
+
+impl BucketOpens<'_> {
+    /// Open a live block, as [`SecretCompute::open_block`].
+    pub fn open(&mut self, k_oblv_t: &Key, block: &Block) -> Result<Vec<u8>, MycoError> {
+        spend(&mut self.remaining)?;
+        self.secrets.open_block(k_oblv_t, block)
+    }
+}
+
+impl BucketSeals<'_> {
+    /// Seal a queued message, as [`SecretCompute::seal_block`].
+    pub fn seal(&mut self, k_oblv_t: &Key, ct: &[u8]) -> Result<Block, MycoError> {
+        spend(&mut self.remaining)?;
+        self.secrets.seal_block(k_oblv_t, ct)
+    }
+}
+
+impl ObliviousBatch {
+    /// Start a batch running its operations in `secrets`, sealing the batch's dummy block.
+    pub fn new(secrets: Arc<dyn SecretCompute>, simulation: SimulationMode) -> Result<Self, MycoError> {
+        let dummy = DummyBlock::new(secrets.as_ref())?;
+        Ok(Self { secrets, simulation, dummy })
+    }
+
+    /// Run `f` over the openings of one bucket's live blocks, then open dummies for the rest.
+    pub fn open_bucket<T>(
+        &self,
+        f: impl FnOnce(&mut BucketOpens) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut opens = BucketOpens { secrets: self.secrets.as_ref(), remaining: Z };
+        let result = f(&mut opens)?;
+        if self.simulation.encrypts() {
+            for _ in 0..opens.remaining {
+                self.dummy
+                    .open(self.secrets.as_ref())
+                    .map_err(|e| format!("decrypting a dummy block: {}", e))?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Run `f` over the sealings of one bucket's queued messages, then seal dummies for the rest.
+    pub fn seal_bucket<T>(
+        &self,
+        f: impl FnOnce(&mut BucketSeals) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut seals = BucketSeals { secrets: self.secrets.as_ref(), remaining: Z };
+        let result = f(&mut seals)?;
+        if self.simulation.encrypts() {
+            for _ in 0..seals.remaining {
+                self.dummy
+                    .seal(self.secrets.as_ref())
+                    .map_err(|e| format!("sealing a dummy block: {}", e))?;
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// `key` encrypted under `wrapping_key`.
+pub fn seal_key(wrapping_key: &Key, key: &Key) -> Result<Vec<u8>, MycoError> {
+    let padded = pad(&key.0, MESSAGE_SIZE, Padding::LengthPrefixed)?;
+    encrypt(&wrapping_key.0, &padded, EncryptionType::Encrypt)
+}
+
+/// The key [`seal_key`] encrypted under `wrapping_key`.
+pub fn unseal_key(wrapping_key: &Key, sealed: &[u8]) -> Result<Key, MycoError> {
+    let padded = decrypt(&wrapping_key.0, sealed)?;
+    Ok(Key::new(unpad(&padded, Padding::LengthPrefixed)?))
+}
+
+/// [`SecretCompute`] in the server process.
+pub struct HostSecrets {
+    simulation: SimulationMode,
+    k_s1_t: RwLock<Key>,
+}
+
+impl HostSecrets {
+    /// Secrets encrypting blocks as `simulation` says, with no epoch key drawn yet.
+    pub fn new(simulation: SimulationMode) -> Self {
+        Self {
+            simulation,
+            k_s1_t: RwLock::new(Key::new(vec![])),
+        }
+    }
+}
+
+impl Default for HostSecrets {
+    fn default() -> Self {
+        Self::new(SimulationMode::Off)
+    }
+}
+
+impl SecretCompute for HostSecrets {
+    fn rotate_epoch_key(&self) {
+        *self.k_s1_t.write().unwrap() = Key::random(&mut ChaCha20Rng::from_entropy());
+    }
+
+    fn epoch_key(&self) -> Result<Key, MycoError> {
+        Ok(self.k_s1_t.read()?.clone())
+    }
+
+    fn location(&self, f: &[u8], cs: &[u8]) -> Result<Vec<u8>, MycoError> {
+        prf(&self.k_s1_t.read().unwrap().0, &[f, cs].concat())
+            .map_err(|_| MycoError::ProtocolError("PRF failed".to_string()))
+    }
+
+    fn open_block(&self, k_oblv_t: &Key, block: &Block) -> Result<Vec<u8>, MycoError> {
+        self.simulation.decrypt(&k_oblv_t.0, block.ciphertext())
+    }
+
+    fn seal_block(&self, k_oblv_t: &Key, ct: &[u8]) -> Result<Block, MycoError> {
+        let c_msg = self
+            .simulation
+            .encrypt(&k_oblv_t.0, ct, EncryptionType::DoubleEncrypt)?;
+        Block::with_hint(&k_oblv_t.0, c_msg)
+    }
+
+    fn sealed_epoch_key(&self, wrapping_key: &Key) -> Result<Vec<u8>, MycoError> {
+        seal_key(wrapping_key, &*self.k_s1_t.read()?)
+    }
+
+    fn unseal_epoch_key(&self, wrapping_key: &Key, sealed: &[u8]) -> Result<(), MycoError> {
+        *self.k_s1_t.write()? = unseal_key(wrapping_key, sealed)?;
+        Ok(())
+    }
+}
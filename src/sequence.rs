@@ -0,0 +1,72 @@
+//! Per-conversation sequence numbers
+//!
+//! Every envelope a client writes to a contact key carries the next number of a sequence the
+//! client keeps for that contact (see [`Header::sequence`](crate::envelope::Header::sequence)).
+//! Readers keep the number they expect next from each sender in a [`SequenceTracker`]: a message
+//! arriving with a later one reveals the messages sent in between that were never read, e.g.
+//! because their epochs were missed, and messages read back from several epochs at once can be put
+//! back in the order they were sent.
+
+use std::{collections::HashMap, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{dtypes::Key, envelope::Envelope};
+
+/// Sequence numbers skipped between the one expected from a sender and the `sequence` of the
+/// message that arrived instead. Empty for the expected message or for a late one.
+pub fn missing(expected: u64, sequence: u64) -> Range<u64> {
+    expected..sequence.max(expected)
+}
+
+/// The next sequence number expected from each sender writing to a contact key.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SequenceTracker {
+    /// Next sequence number expected, by contact key and sender ID.
+    expected: HashMap<(Key, Vec<u8>), u64>,
+}
+
+impl SequenceTracker {
+    /// The next sequence number expected from sender `cs` writing to contact `k`: one past the
+    /// highest passed to [`SequenceTracker::advance`], or 0 if none was.
+    pub fn expected(&self, k: &Key, cs: &[u8]) -> u64 {
+        self.expected
+            .get(&(k.clone(), cs.to_vec()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Move the sequence number expected from sender `cs` writing to contact `k` past `sequence`,
+    /// returning the one expected before. Messages have to be passed in the order they were sent
+    /// for the gaps between them to be reported correctly.
+    pub fn advance(&mut self, k: &Key, cs: &[u8], sequence: u64) -> u64 {
+        let expected = self.expected.entry((k.clone(), cs.to_vec())).or_default();
+        let before = *expected;
+        *expected = (*expected).max(sequence + 1);
+        before
+    }
+
+    /// Forget every sender writing to contact `k`.
+    pub fn forget(&mut self, k: &Key) {
+        self.expected.retain(|(contact, _), _| contact != k);
+    }
+}
+
+/// A message read back together with its place in the sender's sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sequenced {
+    /// The message.
+    pub envelope: Envelope,
+    /// The sequence number the reader expected next from the sender, one past the highest it had
+    /// read from them before.
+    pub expected: u64,
+}
+
+impl Sequenced {
+    /// Sequence numbers the sender used before this message that were never read, e.g. because
+    /// their epochs were missed. Empty for a message sent right after those read so far, or for a
+    /// late one filling an earlier gap.
+    pub fn missing(&self) -> Range<u64> {
+        missing(self.expected, self.envelope.header.sequence)
+    }
+}
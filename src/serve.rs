@@ -0,0 +1,109 @@
+//! Server runners
+//!
+//! Helpers for running the RPC servers over HTTPS with graceful shutdown. On SIGINT or SIGTERM the
+//! runner first invokes the server's shutdown hook (stop accepting writes, finish the in-flight
+//! epoch, flush state to disk) and only then stops the HTTP server, giving in-flight requests
+//! [`SHUTDOWN_GRACE_PERIOD`] to complete. Killing a process mid-epoch therefore no longer loses the
+//! writes already queued on Server1.
+
+use std::{future::Future, net::TcpListener, path::Path as FsPath, time::Duration};
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use tokio::sync::RwLock;
+
+use crate::{
+    admin::{advance_epoch, EpochControl},
+    error::MycoError,
+    server1::Server1,
+    server2::Server2,
+};
+
+/// Time given to in-flight requests to complete once the shutdown hook has run.
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Environment variable holding the path of Server2's snapshot file.
+pub const SNAPSHOT_PATH_ENV: &str = "MYCO_SNAPSHOT_PATH";
+
+/// Resolves when the process receives SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Serve `app` over HTTPS until `signal` resolves, then run `on_shutdown` before stopping.
+///
+/// The listener keeps accepting requests while `on_shutdown` runs, so hooks can still rely on the
+/// server (e.g. Server1 rejecting writes with 503 while it finishes its epoch).
+pub async fn serve_tls<S, F>(
+    listener: TcpListener,
+    config: RustlsConfig,
+    app: Router,
+    signal: S,
+    on_shutdown: F,
+) -> std::io::Result<()>
+where
+    S: Future<Output = ()> + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let handle = Handle::new();
+
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        signal.await;
+        tracing::info!("shutdown requested, flushing state");
+        on_shutdown.await;
+        shutdown_handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+    });
+
+    axum_server::from_tcp_rustls(listener, config)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await
+}
+
+/// Server1's shutdown hook: stop accepting writes and write out the in-flight epoch.
+///
+/// Server2 must still be reachable, so stop Server1 before Server2.
+pub async fn shutdown_server1(
+    server1: &RwLock<Server1>,
+    control: &EpochControl,
+) -> Result<(), MycoError> {
+    control.start_drain();
+    advance_epoch(server1, control).await
+}
+
+/// Server2's shutdown hook: flush the tree and PRF keys to the snapshot file, if one is configured,
+/// and drop the journal records the snapshot holds.
+pub async fn shutdown_server2(
+    server2: &RwLock<Server2>,
+    snapshot_path: Option<&FsPath>,
+) -> Result<(), MycoError> {
+    match snapshot_path {
+        Some(path) => {
+            let mut server2 = server2.write().await;
+            server2.save_snapshot(path)?;
+            server2.compact_journal()
+        }
+        None => Ok(()),
+    }
+}
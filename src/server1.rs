@@ -13,11 +13,14 @@
 #![allow(unused_parens)]
 #![allow(private_bounds)]
 
+pub mod http;
+
 use crate::{
-    client::Client, constants::*, utils::get_path_indices, dtypes::{Block, Bucket, Key, Metadata, Path}, error::MycoError, logging::{BytesMetric, LatencyMetric}, network::{Command, LocalServer1Access, LocalServer2Access, RemoteServer2Access, Server2Access}, tree::{BinaryTree, SparseBinaryTree}, crypto::{encrypt, decrypt, prf, EncryptionType}
+    bandwidth::BandwidthMeter, client::Client, constants::*, utils::get_leaf_path_indices, dtypes::{BandwidthStats, Block, Bucket, BucketDelta, Key, MemoryStats, Metadata, Path, StorageReport, WriteStats}, error::MycoError, logging::{self, BytesMetric, LatencyBreakdown, LatencyMetric, MetricsSink, PerfLog}, memory::{allocator_stats, HeapSize}, network::{Command, LocalServer1Access, LocalServer2Access, RemoteServer2Access, Server2Access}, tree::{BinaryTree, SparseBinaryTree}, crypto::{prf, storage_tag}, notification::{notification_tag, NotificationIndex}, registration::{AccountCredentials, Registry}, mailbox::{MailboxGuard, MailboxGuards}, key_sharing::ShareVault, secrets::{HostSecrets, ObliviousBatch, SecretCompute}, simulation::SimulationMode, standby::{QueuedWrite, Replica, ReplicationEvent, Standby}, write_tokens::{self, TokenIssuer}
 };
 use bincode::{deserialize, serialize};
 use dashmap::DashMap;
+use futures::FutureExt;
 use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
@@ -26,19 +29,195 @@ use rayon::iter::{
 };
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex as TokioMutex;
+use tokio::task::JoinHandle;
+
+/// Environment variable overriding the path sampling factor of the RPC server.
+pub const NU_ENV: &str = "MYCO_NU";
+
+/// Check a path sampling factor against the bounds the pathset and body limits are sized for.
+pub fn validate_nu(nu: usize) -> Result<usize, MycoError> {
+    if (1..=MAX_NU).contains(&nu) {
+        Ok(nu)
+    } else {
+        Err(MycoError::ConfigError(format!(
+            "path sampling factor must be between 1 and {}, got {}",
+            MAX_NU, nu
+        )))
+    }
+}
+
+/// Environment variable setting the number of writes Server1 accepts per write token and epoch.
+pub const WRITE_QUOTA_ENV: &str = "MYCO_WRITE_QUOTA";
+
+/// Read the per-epoch write quota from [`WRITE_QUOTA_ENV`]. No quota is enforced when it isn't set.
+pub fn write_quota_from_env() -> Result<Option<usize>, MycoError> {
+    match std::env::var(WRITE_QUOTA_ENV) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| MycoError::ConfigError(format!("invalid {} {}", WRITE_QUOTA_ENV, value))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Environment variable turning on delta writes to Server2 (see [`Server1::set_delta_writes`]),
+/// set to `true` or `false`. Only simulations without encryption can turn them on.
+pub const DELTA_WRITES_ENV: &str = "MYCO_DELTA_WRITES";
+
+/// Read whether to write deltas to Server2 from [`DELTA_WRITES_ENV`]. Full buckets are written
+/// when it isn't set.
+pub fn delta_writes_from_env() -> Result<bool, MycoError> {
+    match std::env::var(DELTA_WRITES_ENV) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| MycoError::ConfigError(format!("invalid {} {}", DELTA_WRITES_ENV, value))),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Environment variable turning on prefetching of the next epoch's pathset (see
+/// [`Server1::set_prefetch`]), set to `true` or `false`.
+pub const PREFETCH_ENV: &str = "MYCO_PREFETCH";
+
+/// Read whether to prefetch the next epoch's pathset from [`PREFETCH_ENV`]. Pathsets are read at
+/// batch_init when it isn't set.
+pub fn prefetch_from_env() -> Result<bool, MycoError> {
+    match std::env::var(PREFETCH_ENV) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| MycoError::ConfigError(format!("invalid {} {}", PREFETCH_ENV, value))),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Environment variable turning on automatic batch initialization (see
+/// [`Server1::set_auto_batch_init`]), set to the number of writes each automatic batch is sized for.
+pub const AUTO_BATCH_INIT_ENV: &str = "MYCO_AUTO_BATCH_INIT";
+
+/// Read the batch size of automatic batch initialization from [`AUTO_BATCH_INIT_ENV`]. Batches are
+/// only initialized explicitly when it isn't set.
+pub fn auto_batch_init_from_env() -> Result<Option<usize>, MycoError> {
+    match std::env::var(AUTO_BATCH_INIT_ENV) {
+        Ok(value) => value.parse().map(Some).map_err(|_| {
+            MycoError::ConfigError(format!("invalid {} {}", AUTO_BATCH_INIT_ENV, value))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Read the path sampling factor from [`NU_ENV`], falling back to [`NU`] when it isn't set.
+pub fn nu_from_env() -> Result<usize, MycoError> {
+    match std::env::var(NU_ENV) {
+        Ok(value) => validate_nu(value.parse().map_err(|_| {
+            MycoError::ConfigError(format!("invalid {} {}", NU_ENV, value))
+        })?),
+        Err(_) => Ok(NU),
+    }
+}
+
+/// The next epoch's pathset, sampled when a batch is initialized and read from Server2 while the
+/// batch is open.
+struct Prefetch {
+    /// The epoch that was open when the pathset was sampled.
+    epoch: u64,
+    /// The leaves of the pathset's paths.
+    leaves: Vec<u32>,
+    /// The read of the pathset's buckets.
+    read: JoinHandle<anyhow::Result<Vec<Bucket>>>,
+}
+
+/// Run `future` to completion from synchronous code. Futures that are ready right away, such as
+/// those of an in-memory Server2, complete without entering an executor, so this also works when
+/// the caller is itself driven by one, e.g. a write initializing its batch.
+pub(crate) fn run_sync<F: std::future::Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    match (&mut future).now_or_never() {
+        Some(output) => output,
+        None => futures::executor::block_on(future),
+    }
+}
+
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Environment variable setting the number of distinct writers an epoch waits for.
+pub const MIN_WRITERS_ENV: &str = "MYCO_MIN_WRITERS";
+
+/// Environment variable setting how long, in milliseconds, an epoch waits for its minimum number
+/// of writers before it is written out anyway.
+pub const MIN_WRITERS_TIMEOUT_ENV: &str = "MYCO_MIN_WRITERS_TIMEOUT_MS";
+
+/// How long an epoch waits for its minimum number of writers when no timeout is configured.
+pub const DEFAULT_MIN_WRITERS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Policy holding back an epoch's batch write until enough clients have written, since an epoch
+/// with only a handful of writers hides next to nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnonymityGate {
+    /// Number of distinct writers, counted by issued write token, to wait for.
+    pub min_writers: usize,
+    /// How long after batch_init to give up waiting and write the epoch out regardless.
+    pub timeout: Duration,
+}
+
+impl AnonymityGate {
+    /// Read the gate from [`MIN_WRITERS_ENV`] and [`MIN_WRITERS_TIMEOUT_ENV`]. No gate applies
+    /// when the minimum isn't set.
+    pub fn from_env() -> Result<Option<Self>, MycoError> {
+        let parse = |name: &str, value: String| {
+            value
+                .parse::<u64>()
+                .map_err(|_| MycoError::ConfigError(format!("invalid {} {}", name, value)))
+        };
+        let Ok(min_writers) = std::env::var(MIN_WRITERS_ENV) else {
+            return Ok(None);
+        };
+        let timeout = match std::env::var(MIN_WRITERS_TIMEOUT_ENV) {
+            Ok(ms) => Duration::from_millis(parse(MIN_WRITERS_TIMEOUT_ENV, ms)?),
+            Err(_) => DEFAULT_MIN_WRITERS_TIMEOUT,
+        };
+        Ok(Some(Self {
+            min_writers: parse(MIN_WRITERS_ENV, min_writers)? as usize,
+            timeout,
+        }))
+    }
+}
+
+/// Where Server1 is in the life of an epoch, which decides what happens to writes arriving now.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EpochState {
+    /// No batch is open: writes are rejected with [`MycoError::EpochClosed`].
+    #[default]
+    Closed,
+    /// `batch_init` is rebuilding the pathset and trees: writes are rejected with
+    /// [`MycoError::EpochInitializing`] rather than routed through a half-built `pt`. An
+    /// initialization that was cancelled or panicked leaves the server here until the next one.
+    Initializing,
+    /// The batch is open and writes are queued.
+    Accepting,
+    /// `batch_write` is writing the epoch out: writes are rejected with
+    /// [`MycoError::EpochClosed`], as they would land in a stale `pt`.
+    Finalizing,
+}
+
+/// A queued message: ciphertext, oblivious key, expiry timestamp and intended path.
+type QueuedMessage = (Vec<u8>, Key, u64, Path);
 
 /// The main server1 struct.
 pub struct Server1 {
     /// The current epoch of the server.
     pub epoch: u64,
-    /// The key used for server1 transactions.
-    pub k_s1_t: Key,
     /// The number of clients connected to the server.
     pub num_clients: usize,
+    /// Number of paths sampled into the pathset per client write.
+    nu: usize,
     /// Access to Server2.
-    pub s2: Box<dyn Server2Access>,
+    pub s2: Arc<dyn Server2Access>,
     /// Sparse binary tree for storing buckets.
     pub p: SparseBinaryTree<Bucket>,
     /// Sparse binary tree for temporary storage of buckets.
@@ -50,7 +229,62 @@ pub struct Server1 {
     /// Indices of the pathset.
     pub pathset_indices: Vec<usize>,
     /// Queue for storing messages.
-    pub message_queue: DashMap<usize, Vec<(Vec<u8>, Key, u64, Path)>>,
+    pub message_queue: DashMap<usize, Vec<QueuedMessage>>,
+    /// Key shares released by upstream S1 instances in distributed trust mode, in the order their
+    /// PRF layers were applied. Empty when this server runs alone.
+    pub upstream_key_shares: Vec<Key>,
+    /// Maximum number of writes per write token and epoch, if enforced.
+    write_quota: Option<usize>,
+    /// Issues the write tokens of registered accounts and checks them on writes.
+    token_issuer: TokenIssuer,
+    /// Writes seen per issued write token in the current epoch. Cleared at the end of every epoch,
+    /// so no record of a token outlives its epoch.
+    write_tokens: DashMap<Vec<u8>, usize>,
+    /// Number of writes queued in the current epoch.
+    epoch_writes: usize,
+    /// Write counts of the last completed epoch.
+    last_write_stats: Option<WriteStats>,
+    /// Bytes received and sent per epoch.
+    bandwidth: Arc<BandwidthMeter>,
+    /// Minimum-anonymity policy for batch writes, if any.
+    anonymity_gate: Option<AnonymityGate>,
+    /// When the current batch was initialized.
+    batch_opened_at: Instant,
+    /// Where the server is in the current epoch.
+    epoch_state: EpochState,
+    /// When the last batch write started.
+    batch_closed_at: Instant,
+    /// How long the last completed batch write took.
+    last_batch_write: Option<Duration>,
+    /// Estimated opening time of the next epoch, in milliseconds since the Unix epoch.
+    next_epoch_opens_at: Option<u64>,
+    /// Notification tags of the writes queued in the current epoch.
+    notification_tags: Vec<Vec<u8>>,
+    /// Pseudonymous client accounts, kept apart from everything the write path sees.
+    pub registrations: Registry,
+    /// Guards restricting writes to mailboxes to approved senders.
+    mailbox_guards: MailboxGuards,
+    /// Number of writes a batch is initialized for when the first write of an epoch arrives, if
+    /// batches are initialized automatically.
+    auto_batch_init: Option<usize>,
+    /// Whether batches are written to Server2 as deltas from the buckets read at batch_init.
+    delta_writes: bool,
+    /// Whether the next epoch's pathset is prefetched while the current epoch is open.
+    prefetch: bool,
+    /// The prefetched pathset of the next epoch, if any.
+    prefetched: Option<Prefetch>,
+    /// Whether blocks are really encrypted.
+    simulation: SimulationMode,
+    /// Where the epoch key is kept and the operations depending on secrets run.
+    secrets: Arc<dyn SecretCompute>,
+    /// The share of a peer replica's epoch key this server keeps, see [`crate::key_sharing`].
+    peer_key_share: Arc<ShareVault>,
+    /// The standby this server replicates its state to, if any, see [`crate::standby`].
+    replica: Option<Arc<dyn Replica>>,
+    /// The key the epoch key is sealed under for the standby.
+    replication_key: Option<Key>,
+    /// The state of the primary this server stands by for, served at `/admin/replicate`.
+    standby: Arc<Mutex<Standby>>,
 }
 
 impl Server1 {
@@ -58,40 +292,589 @@ impl Server1 {
     pub fn new(s2: Box<dyn Server2Access>) -> Self {
         Self {
             epoch: 0,
-            k_s1_t: Key::new(vec![]),
             num_clients: 0,
-            s2,
+            nu: NU,
+            s2: Arc::from(s2),
             p: SparseBinaryTree::new(),
             pt: SparseBinaryTree::new(),
             metadata_pt: SparseBinaryTree::new(),
             metadata: BinaryTree::new_with_depth(D),
             pathset_indices: vec![],
             message_queue: DashMap::new(),
+            upstream_key_shares: vec![],
+            write_quota: None,
+            token_issuer: TokenIssuer::new(&mut ChaCha20Rng::from_entropy()),
+            write_tokens: DashMap::new(),
+            epoch_writes: 0,
+            last_write_stats: None,
+            bandwidth: Arc::new(BandwidthMeter::new("server1", Arc::new(PerfLog))),
+            anonymity_gate: None,
+            batch_opened_at: Instant::now(),
+            epoch_state: EpochState::Closed,
+            batch_closed_at: Instant::now(),
+            last_batch_write: None,
+            next_epoch_opens_at: None,
+            notification_tags: vec![],
+            registrations: Registry::new(),
+            mailbox_guards: MailboxGuards::new(),
+            auto_batch_init: None,
+            delta_writes: false,
+            prefetch: false,
+            prefetched: None,
+            simulation: SimulationMode::Off,
+            secrets: Arc::new(HostSecrets::default()),
+            peer_key_share: Arc::new(ShareVault::default()),
+            replica: None,
+            replication_key: None,
+            standby: Arc::new(Mutex::new(Standby::default())),
+        }
+    }
+
+    /// Record the key share of an upstream S1 instance for the current epoch.
+    ///
+    /// In distributed trust mode the front S1 releases its share once it has forwarded all of its
+    /// writes, so that the key published to S2 lets readers recompute the full location chain.
+    pub fn add_upstream_key_share(&mut self, share: Key) {
+        self.upstream_key_shares.push(share);
+    }
+
+    /// The epoch key published to Server2: any upstream shares followed by this server's k_s1_t.
+    /// Until the epoch is published, the key stays secret. Afterwards anyone can read it from
+    /// Server2, but only holders of a write's `f` can locate it (see [`crate::distributed`]).
+    pub fn published_key(&self) -> Result<Key, MycoError> {
+        let mut key: Vec<u8> = self
+            .upstream_key_shares
+            .iter()
+            .flat_map(|share| share.0.iter().copied())
+            .collect();
+        key.extend_from_slice(&self.secrets.epoch_key()?.0);
+        Ok(Key::new(key))
+    }
+
+    /// Number of paths sampled into the pathset per client write.
+    pub fn nu(&self) -> usize {
+        self.nu
+    }
+
+    /// Set the path sampling factor used from the next batch_init on.
+    pub fn set_nu(&mut self, nu: usize) -> Result<(), MycoError> {
+        self.nu = validate_nu(nu)?;
+        Ok(())
+    }
+
+    /// Cap the number of writes accepted per write token and epoch, or lift the cap with `None`.
+    pub fn set_write_quota(&mut self, quota: Option<usize>) {
+        self.write_quota = quota;
+    }
+
+    /// Issue write tokens to the account of `credentials`, one for each requested epoch, see
+    /// [`crate::write_tokens`]. Each request is an epoch and a blinded token. Epochs the account
+    /// was already issued a token for, and epochs outside the next [`WRITE_TOKEN_EPOCHS`], are
+    /// left out of the answer.
+    pub fn issue_write_tokens(
+        &mut self,
+        credentials: &AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
+        let requests: Vec<(u64, Vec<u8>)> = requests
+            .into_iter()
+            .filter(|(epoch, _)| write_tokens::issuable(self.epoch, *epoch))
+            .collect();
+        let epochs: Vec<u64> = requests.iter().map(|(epoch, _)| *epoch).collect();
+        let claimed = self.registrations.claim_write_tokens(credentials, &epochs)?;
+        requests
+            .into_iter()
+            .filter(|(epoch, _)| claimed.contains(epoch))
+            .map(|(epoch, blinded)| Ok((epoch, self.token_issuer.issue(epoch, &blinded)?)))
+            .collect()
+    }
+
+    /// Count a write against its token, failing if the token wasn't issued for this epoch or has
+    /// used up this epoch's quota. Writes without a token are only accepted, and not counted as a
+    /// writer, while no quota is set.
+    fn charge_write_token(&mut self, token: Vec<u8>) -> Result<(), MycoError> {
+        if token.is_empty() {
+            if self.write_quota.is_some() {
+                return Err(MycoError::InvalidWriteToken);
+            }
+        } else {
+            self.token_issuer.verify(self.epoch, &token)?;
+            let mut count = self.write_tokens.entry(token).or_default();
+            if self.write_quota.is_some_and(|quota| *count >= quota) {
+                return Err(MycoError::WriteQuotaExceeded);
+            }
+            *count += 1;
+        }
+        self.epoch_writes += 1;
+        Ok(())
+    }
+
+    /// Guard mailbox addresses on behalf of their owner, see [`crate::mailbox`].
+    pub fn guard_mailboxes(&mut self, guards: Vec<MailboxGuard>) -> Result<(), MycoError> {
+        self.mailbox_guards.guard(guards, self.epoch)
+    }
+
+    /// Number of guarded mailbox addresses.
+    pub fn mailbox_guard_count(&self) -> usize {
+        self.mailbox_guards.len()
+    }
+
+    /// Write counts of the current epoch so far.
+    pub fn write_stats(&self) -> WriteStats {
+        WriteStats {
+            epoch: self.epoch,
+            writes: self.epoch_writes,
+            distinct_writers: self.write_tokens.len(),
+        }
+    }
+
+    /// Write counts of the last completed epoch, if any.
+    pub fn last_write_stats(&self) -> Option<WriteStats> {
+        self.last_write_stats
+    }
+
+    /// The meter counting the bytes this server receives and sends per epoch.
+    pub fn bandwidth_meter(&self) -> &Arc<BandwidthMeter> {
+        &self.bandwidth
+    }
+
+    /// Report this server's per-epoch metrics to `sink` instead of [`PerfLog`]. Call it before
+    /// handing out the [`bandwidth_meter`](Self::bandwidth_meter), which it replaces.
+    pub fn set_metrics_sink(&mut self, sink: Arc<dyn MetricsSink>) {
+        self.bandwidth = Arc::new(BandwidthMeter::new("server1", sink));
+    }
+
+    /// Bytes received and sent in the current epoch so far.
+    pub fn bandwidth_stats(&self) -> BandwidthStats {
+        self.bandwidth.stats(self.epoch)
+    }
+
+    /// Latencies of this server's operations in the current and last epochs.
+    pub fn latency_breakdown(&self) -> LatencyBreakdown {
+        logging::latency_breakdown("server1", self.epoch)
+    }
+
+    /// Blocks written in the current epoch per storage tag of the write token (see
+    /// [`storage_tag`]). Writes without a token aren't accounted.
+    fn storage_report(&self) -> StorageReport {
+        self.write_tokens
+            .iter()
+            .map(|entry| (storage_tag(entry.key()), *entry.value()))
+            .collect()
+    }
+
+    /// Close the current epoch's write counts, keeping only the aggregate.
+    fn finish_write_stats(&mut self) {
+        self.last_write_stats = Some(self.write_stats());
+        self.epoch_writes = 0;
+        self.write_tokens.clear();
+    }
+
+    /// Hold batch writes back until enough distinct clients have written, or lift the gate with
+    /// `None`.
+    pub fn set_anonymity_gate(&mut self, gate: Option<AnonymityGate>) {
+        self.anonymity_gate = gate;
+    }
+
+    /// How much longer the current epoch should wait for writers before its batch write, or `None`
+    /// if it can be written out now.
+    ///
+    /// `batch_write` itself doesn't wait: the epoch scheduler and the `/batch_write` endpoint
+    /// consult this first.
+    pub fn anonymity_gate_remaining(&self) -> Option<Duration> {
+        let gate = self.anonymity_gate?;
+        if self.write_tokens.len() >= gate.min_writers {
+            return None;
+        }
+        gate.timeout
+            .checked_sub(self.batch_opened_at.elapsed())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Have the first write of an epoch initialize its batch for `num_clients` writes, as if
+    /// `batch_init(num_clients)` had been called just before it, or go back to requiring explicit
+    /// `batch_init` calls with `None`. Explicit calls keep working either way, e.g. for benchmarks
+    /// that size every batch themselves.
+    pub fn set_auto_batch_init(&mut self, num_clients: Option<usize>) {
+        self.auto_batch_init = num_clients;
+    }
+
+    /// Write batches to Server2 as deltas from the buckets read at batch_init, each falling back
+    /// to the full bucket when all its blocks changed (see [`BucketDelta`]).
+    ///
+    /// Only available with encryption off in a [`SimulationMode`]. Every block Server1 keeps is
+    /// re-encrypted and reshuffled so that Server2 can't tell which blocks stayed in a bucket and
+    /// how many are live; re-encrypting only the blocks that changed would show it exactly that.
+    pub fn set_delta_writes(&mut self, delta_writes: bool) -> Result<(), MycoError> {
+        if delta_writes && self.simulation.encrypts() {
+            return Err(MycoError::ConfigError(
+                "delta writes need encryption off, see SimulationMode".to_string(),
+            ));
+        }
+        self.delta_writes = delta_writes;
+        Ok(())
+    }
+
+    /// Sample the next epoch's pathset as soon as a batch is initialized and read its buckets from
+    /// Server2 while the batch is open, so the next `async_batch_init` needn't wait for the read.
+    /// The paths are sampled uniformly either way, so Server2 can't tell a prefetched pathset
+    /// from one read at batch_init.
+    ///
+    /// Where the pathsets overlap, the prefetched buckets are replaced by the ones the epoch in
+    /// between wrote. A prefetch is dropped, and the pathset read at batch_init as usual, if that
+    /// epoch's write was aborted, if no epoch was written in between, or if the next batch is
+    /// sized differently.
+    pub fn set_prefetch(&mut self, prefetch: bool) {
+        self.prefetch = prefetch;
+        if !prefetch {
+            self.discard_prefetch();
+        }
+    }
+
+    /// Run in `simulation` mode, see [`SimulationMode`]. Without encryption, blocks are stored in
+    /// the clear and buckets are neither padded nor shuffled, so this is only for simulations.
+    /// Replaces the secret compute with [`HostSecrets`] in that mode, so call it before the first
+    /// batch and before [`Server1::set_secret_compute`]. Turning encryption back on turns delta
+    /// writes off.
+    pub fn set_simulation_mode(&mut self, simulation: SimulationMode) {
+        self.simulation = simulation;
+        self.delta_writes &= !simulation.encrypts();
+        self.secrets = Arc::new(HostSecrets::new(simulation));
+    }
+
+    /// Keep the epoch key and run the operations depending on secrets in `secrets`, e.g. an
+    /// enclave, instead of [`HostSecrets`] in the server process. Takes effect at the next
+    /// batch_init, which draws the epoch key.
+    pub fn set_secret_compute(&mut self, secrets: Arc<dyn SecretCompute>) {
+        self.secrets = secrets;
+    }
+
+    /// The share of a peer replica's epoch key this server keeps, served at `/admin/key_share`.
+    pub fn peer_key_share(&self) -> &Arc<ShareVault> {
+        &self.peer_key_share
+    }
+
+    /// Replicate this server's state to `replica`, starting with a snapshot at the next
+    /// batch_init. Requires a replication key, see [`Server1::set_replication_key`].
+    pub fn set_replica(&mut self, replica: Arc<dyn Replica>) {
+        self.replica = Some(replica);
+    }
+
+    /// Seal the epoch key under `key` for the standby, or unseal it taking over as the standby.
+    pub fn set_replication_key(&mut self, key: Key) {
+        self.replication_key = Some(key);
+    }
+
+    /// The state of the primary this server stands by for.
+    pub fn standby(&self) -> &Arc<Mutex<Standby>> {
+        &self.standby
+    }
+
+    /// Send the event `event` builds to the replica, if there is one.
+    fn replicate(&self, event: impl FnOnce() -> ReplicationEvent) {
+        if let Some(replica) = &self.replica {
+            replica.replicate(event());
+        }
+    }
+
+    /// Replicate the batch just initialized, preceded by a snapshot if the replica needs one.
+    fn replicate_batch_opened(&self) {
+        let Some(replica) = &self.replica else {
+            return;
+        };
+        if replica.needs_snapshot() {
+            replica.replicate(ReplicationEvent::Snapshot {
+                epoch: self.epoch,
+                metadata: self.metadata.clone(),
+            });
+        }
+        let sealed_key = self
+            .replication_key
+            .as_ref()
+            .ok_or_else(|| MycoError::ConfigError("no replication key is set".to_string()))
+            .and_then(|key| self.secrets.sealed_epoch_key(key));
+        match sealed_key {
+            Ok(sealed_key) => replica.replicate(ReplicationEvent::BatchOpened {
+                epoch: self.epoch,
+                sealed_key,
+            }),
+            Err(e) => println!("Server1: Error replicating the epoch key: {}", e),
+        }
+    }
+
+    /// Take over from the primary this server stands by for, which is assumed to have crashed.
+    ///
+    /// The epoch counter and metadata tree are restored from the replicated state. If the primary
+    /// had a batch open, a batch is initialized for `num_clients` writes under the primary's epoch
+    /// key and its queued writes are queued again, so clients that wrote in the epoch still find
+    /// their messages once it is written out. Otherwise no batch is initialized.
+    pub async fn take_over(&mut self, num_clients: usize) -> Result<(), MycoError> {
+        let standby = std::mem::take(&mut *self.standby.lock()?);
+        if !standby.is_synced() {
+            return Err(MycoError::ProtocolError(
+                "no state was replicated from the primary".to_string(),
+            ));
+        }
+        self.epoch = standby.epoch;
+        self.metadata = standby.metadata;
+        let Some(sealed_key) = standby.sealed_key else {
+            return Ok(());
+        };
+        let replication_key = self
+            .replication_key
+            .clone()
+            .ok_or_else(|| MycoError::ConfigError("no replication key is set".to_string()))?;
+
+        self.async_batch_init(num_clients).await?;
+        self.secrets.unseal_epoch_key(&replication_key, &sealed_key)?;
+        let t_exp = self.epoch + DELTA as u64;
+        for write in standby.writes {
+            let (lca_idx, _) = self.pt.lca_idx(&write.path).ok_or(MycoError::LcaNotFound)?;
+            self.notification_tags.push(write.tag);
+            self.epoch_writes += 1;
+            self.message_queue.entry(lca_idx).or_default().push((
+                write.ct,
+                write.k_oblv_t,
+                t_exp,
+                write.path,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Start reading the pathset of the batch after the one just initialized, if prefetching is
+    /// on and there is a runtime to read it on.
+    fn start_prefetch<R: Rng>(&mut self, num_clients: usize, rng: &mut R) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if !self.prefetch {
+            return;
+        }
+        let leaves = self.sample_pathset(num_clients, rng);
+        let s2 = Arc::clone(&self.s2);
+        let request = leaves.clone();
+        self.prefetched = Some(Prefetch {
+            epoch: self.epoch,
+            leaves,
+            read: runtime.spawn(async move { s2.prefetch_leaf_paths(request).await }),
+        });
+    }
+
+    fn discard_prefetch(&mut self) {
+        if let Some(prefetch) = self.prefetched.take() {
+            prefetch.read.abort();
+        }
+    }
+
+    /// The prefetched pathset for a batch of `num_clients` writes, as leaves and indices, with its
+    /// buckets as Server2 holds them now. `None` if there is no usable prefetch.
+    async fn take_prefetch(
+        &mut self,
+        num_clients: usize,
+    ) -> Option<(Vec<u32>, Vec<usize>, Vec<Bucket>)> {
+        let prefetch = self.prefetched.take()?;
+        // Only the epoch open at sampling time may have been written since, and completely: an
+        // aborted write can leave some of its chunks on Server2.
+        if self.epoch != prefetch.epoch + 1
+            || prefetch.leaves.len() != self.nu.saturating_mul(num_clients)
+        {
+            prefetch.read.abort();
+            return None;
+        }
+        let mut buckets = match prefetch.read.await {
+            Ok(Ok(buckets)) => buckets,
+            Ok(Err(e)) => {
+                println!("Server1: Error prefetching the pathset: {:?}", e);
+                return None;
+            }
+            Err(e) => {
+                println!("Server1: Prefetch of the pathset failed: {:?}", e);
+                return None;
+            }
+        };
+        let indices = get_leaf_path_indices(&prefetch.leaves).ok()?;
+        if buckets.len() != indices.len() {
+            return None;
+        }
+
+        // The read may have reached Server2 before the last epoch's write, so the buckets that
+        // write covered are taken from what it wrote. Both pathsets are sorted.
+        let mut written = self
+            .pathset_indices
+            .iter()
+            .zip(&self.pt.packed_buckets)
+            .peekable();
+        for (index, bucket) in indices.iter().zip(buckets.iter_mut()) {
+            while written.next_if(|(written_index, _)| *written_index < index).is_some() {}
+            if let Some((_, written_bucket)) =
+                written.next_if(|(written_index, _)| *written_index == index)
+            {
+                *bucket = written_bucket.clone();
+            }
+        }
+        Some((prefetch.leaves, indices, buckets))
+    }
+
+    /// The number of writes a batch should be initialized for before the next write, if batches
+    /// are initialized automatically and none is open. An initialization left unfinished counts as
+    /// none, since nothing else can be running it while the caller holds the server.
+    pub fn pending_auto_batch_init(&self) -> Option<usize> {
+        self.auto_batch_init
+            .filter(|_| matches!(self.epoch_state, EpochState::Closed | EpochState::Initializing))
+    }
+
+    /// Whether a batch is open and accepting writes.
+    pub fn is_batch_open(&self) -> bool {
+        self.epoch_state == EpochState::Accepting
+    }
+
+    /// Where the server is in the current epoch.
+    pub fn epoch_state(&self) -> EpochState {
+        self.epoch_state
+    }
+
+    /// The error returned for writes that arrive while no batch is open.
+    pub fn epoch_closed(&self) -> MycoError {
+        MycoError::EpochClosed {
+            next_epoch_opens_at: self.next_epoch_opens_at,
+        }
+    }
+
+    /// Stop accepting writes for the current epoch.
+    ///
+    /// The next epoch is estimated to open once a batch write as long as the last one has
+    /// finished, which holds when batches are initialized back to back as the epoch scheduler does.
+    fn close_batch(&mut self) {
+        self.epoch_state = EpochState::Finalizing;
+        self.batch_closed_at = Instant::now();
+        self.next_epoch_opens_at = self
+            .last_batch_write
+            .map(|duration| unix_millis(SystemTime::now() + duration));
+    }
+
+    /// Send the epoch's buckets to Server2, as deltas if delta writes are on.
+    async fn write_buckets(&self) -> anyhow::Result<()> {
+        let buckets = self.pt.packed_buckets.clone();
+        if !self.delta_writes {
+            return self.s2.write(self.epoch, buckets, self.published_key()?).await;
+        }
+        let deltas = self
+            .p
+            .packed_buckets
+            .iter()
+            .zip(buckets)
+            .map(|(old, new)| BucketDelta::between(old, new))
+            .collect();
+        self.s2.write_deltas(self.epoch, deltas, self.published_key()?).await
+    }
+
+    /// Record a completed batch write and close the epoch's write counts.
+    fn finish_batch(&mut self) {
+        self.last_batch_write = Some(self.batch_closed_at.elapsed());
+        self.finish_write_stats();
+        self.bandwidth.finish_epoch(self.epoch);
+        logging::finish_latency_epoch("server1", self.epoch);
+        self.mailbox_guards.expire(self.epoch + 1);
+        self.epoch_state = EpochState::Closed;
+    }
+
+    /// Give up on writing out the current epoch. The queued messages are dropped and Server1's
+    /// metadata is left as it was, so the next batch starts from the last completed epoch.
+    fn abort_batch(&mut self, reason: String) -> MycoError {
+        println!("Server1: Aborting batch write of epoch {}: {}", self.epoch, reason);
+        self.message_queue.clear();
+        self.notification_tags.clear();
+        self.epoch_state = EpochState::Closed;
+        self.replicate(|| ReplicationEvent::BatchAborted);
+        MycoError::BatchWriteAborted {
+            epoch: self.epoch,
+            reason,
+        }
+    }
+
+    /// Build the notification index of the epoch's writes, or `None` if it can't be built.
+    fn notification_index(&mut self) -> Option<Vec<u8>> {
+        let tags = std::mem::take(&mut self.notification_tags);
+        match NotificationIndex::build(&tags).and_then(|index| index.to_bytes()) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                println!("Server1: Error building the notification index: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Sample the pathset for an epoch with `num_clients` writes, as the leaves of its paths.
+    fn sample_pathset<R: Rng>(&self, num_clients: usize, rng: &mut R) -> Vec<u32> {
+        (0..self.nu.saturating_mul(num_clients))
+            .map(|_| rng.gen_range(0..1u32 << D))
+            .collect()
+    }
+
+    /// Number of writes queued for the current epoch.
+    pub fn queue_depth(&self) -> usize {
+        self.message_queue.iter().map(|entry| entry.value().len()).sum()
+    }
+
+    /// Number of buckets in the current epoch's pathset.
+    pub fn pathset_size(&self) -> usize {
+        self.pathset_indices.len()
+    }
+
+    /// Memory held by the bucket and metadata trees and the write queue.
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            tree_bytes: self.p.heap_size() + self.pt.heap_size(),
+            metadata_bytes: self.metadata.heap_size() + self.metadata_pt.heap_size(),
+            queue_depth: self.queue_depth(),
+            allocator: allocator_stats(),
         }
     }
 
     /// Initialize the server for a new batch.
-    pub async fn async_batch_init(&mut self, num_clients: usize) {
+    ///
+    /// Fails if the pathset can't be read from Server2. The server is then left initializing, so
+    /// writes are turned away until a later init succeeds.
+    pub async fn async_batch_init(&mut self, num_clients: usize) -> Result<(), MycoError> {
         // Create metrics to track initialization latency
         let end_to_end_latency = LatencyMetric::new("server1_batch_init_end_to_end");
         let mut local_latency = LatencyMetric::new("server1_batch_init_local");
         
+        // Writes are turned away until the trees below are rebuilt.
+        self.epoch_state = EpochState::Initializing;
+
         // Initialize random number generator
         let mut rng = ChaCha20Rng::from_entropy();
 
-        // Generate random paths for each client
-        let paths = (0..(NU * num_clients))
-            .map(|_| Path::random(&mut rng))
-            .collect::<Vec<Path>>();
-        self.pathset_indices = get_path_indices(paths);
-
-        // Pause local latency tracking while reading from Server2
+        // Use the prefetched pathset or sample one, pausing local latency tracking while waiting
+        // on Server2. A prefetch whose pathset can't be stored is dropped for a fresh read.
+        let prefetched = self.take_prefetch(num_clients).await;
         local_latency.pause();
-        let buckets: Vec<Bucket> = self
-            .s2
-            .read_paths(self.pathset_indices.clone())
-            .await
-            .unwrap();
+        let prefetched = match prefetched {
+            Some((leaves, indices, buckets)) => match self.s2.store_leaf_paths(leaves).await {
+                Ok(()) => Some((indices, buckets)),
+                Err(e) => {
+                    println!("Server1: Error storing the prefetched pathset: {:?}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let buckets: Vec<Bucket> = match prefetched {
+            Some((indices, buckets)) => {
+                self.pathset_indices = indices;
+                buckets
+            }
+            None => {
+                let leaves = self.sample_pathset(num_clients, &mut rng);
+                self.pathset_indices = get_leaf_path_indices(&leaves)?;
+                self.s2
+                    .read_leaf_paths(leaves)
+                    .await
+                    .map_err(|e| MycoError::transport("read_leaf_paths", e))?
+            }
+        };
         local_latency.resume();
         
         // Get size of buckets for initializing trees
@@ -110,28 +893,36 @@ impl Server1 {
 
         // Set server state
         self.num_clients = num_clients;
-        self.k_s1_t = Key::random(&mut rng);
+        self.secrets.rotate_epoch_key();
+        self.upstream_key_shares.clear();
+        self.notification_tags.clear();
+        self.batch_opened_at = Instant::now();
+        self.epoch_state = EpochState::Accepting;
+        self.replicate_batch_opened();
+        self.start_prefetch(num_clients, &mut rng);
 
         // Record final latency metrics
         end_to_end_latency.finish();
         local_latency.finish();
+        Ok(())
     }
 
     /// Initialize the server for a new batch.
     pub fn batch_init(&mut self, num_clients: usize) {
+        // Writes are turned away until the trees below are rebuilt.
+        self.epoch_state = EpochState::Initializing;
+
         // Create cryptographically secure random number generator
         let mut rng = ChaCha20Rng::from_entropy();
 
-        // Generate random paths for each client
-        let paths = (0..(NU * num_clients))
-            .map(|_| Path::random(&mut rng))
-            .collect::<Vec<Path>>();
-        // Convert paths to indices
-        self.pathset_indices = get_path_indices(paths);
+        // Generate random paths for each client and convert them to indices. Batches initialized
+        // synchronously read their pathset here, so any prefetch is dropped.
+        self.discard_prefetch();
+        let leaves = self.sample_pathset(num_clients, &mut rng);
+        self.pathset_indices = get_leaf_path_indices(&leaves).unwrap();
 
         // Read buckets from Server2 synchronously by blocking on async call
-        let buckets: Vec<Bucket> =
-            futures::executor::block_on(self.s2.read_paths(self.pathset_indices.clone())).unwrap();
+        let buckets: Vec<Bucket> = run_sync(self.s2.read_leaf_paths(leaves)).unwrap();
         let bucket_size = buckets.len();
 
         // Initialize sparse binary trees:
@@ -150,25 +941,60 @@ impl Server1 {
 
         // Set number of clients and generate new random key for this batch
         self.num_clients = num_clients;
-        self.k_s1_t = Key::random(&mut rng);
+        self.secrets.rotate_epoch_key();
+        self.upstream_key_shares.clear();
+        self.notification_tags.clear();
+        self.batch_opened_at = Instant::now();
+        self.epoch_state = EpochState::Accepting;
+        self.replicate_batch_opened();
     }
 
     /// Queues an individual write. Must be finalized with finalize_batch_write. Every time you finalize
     /// an epoch, each queued write is written to pt and metadata_pt.
+    ///
+    /// The write is counted against `token`, the client's Server1-issued write token for this
+    /// epoch, which may only be left empty while no write quota is set. Writes to a guarded mailbox must carry a valid `access_tag`. With automatic
+    /// batch initialization, a write arriving while no batch is open initializes one first.
+    ///
+    /// Returns the epoch the write was queued into, the client's receipt for it.
     pub fn queue_write(
         &mut self,
         ct: Vec<u8>,
         f: Vec<u8>,
         k_oblv_t: Key,
         cs: Vec<u8>,
-    ) -> Result<(), MycoError> {
+        token: Vec<u8>,
+        access_tag: Option<Vec<u8>>,
+    ) -> Result<u64, MycoError> {
+        if let Some(num_clients) = self.pending_auto_batch_init() {
+            self.batch_init(num_clients);
+        }
+        match self.epoch_state {
+            EpochState::Accepting => {}
+            EpochState::Initializing => return Err(MycoError::EpochInitializing),
+            // Writes arriving after batch_write has started would land in a stale pt.
+            EpochState::Closed | EpochState::Finalizing => return Err(self.epoch_closed()),
+        }
+        self.mailbox_guards
+            .check(&f, &cs, &ct, access_tag.as_deref())?;
         let t_exp = self.epoch + DELTA as u64;
-        let l: Vec<u8> = prf(&self.k_s1_t.0, &[&f[..], &cs[..]].concat()).map_err(|_| MycoError::ProtocolError("PRF failed".to_string()))?;
+        let l: Vec<u8> = self.secrets.location(&f, &cs)?;
+        let tag = notification_tag(&l)?;
         let intended_message_path = Path::from(l);
         let (lca_idx, _) = self
             .pt
             .lca_idx(&intended_message_path)
             .ok_or(MycoError::LcaNotFound)?;
+        self.charge_write_token(token)?;
+        self.replicate(|| {
+            ReplicationEvent::WriteQueued(QueuedWrite {
+                ct: ct.clone(),
+                k_oblv_t: k_oblv_t.clone(),
+                path: intended_message_path.clone(),
+                tag: tag.clone(),
+            })
+        });
+        self.notification_tags.push(tag);
 
         // Queue the write.
         self.message_queue.entry(lca_idx).or_default().push((
@@ -178,101 +1004,106 @@ impl Server1 {
             intended_message_path,
         ));
 
-        Ok(())
+        Ok(self.epoch)
     }
 
     /// Finalize a batch write.
     pub fn batch_write(&mut self) -> Result<(), MycoError> {
+        self.close_batch();
         let mut rng = ChaCha20Rng::from_entropy();
         let seed: [u8; 32] = rng.gen();
+        let simulation = self.simulation;
+        // Every bucket costs Z block operations, real or dummy (see ObliviousBatch).
+        let oblivious = match ObliviousBatch::new(self.secrets.clone(), simulation) {
+            Ok(oblivious) => oblivious,
+            Err(e) => return Err(self.abort_batch(format!("sealing the dummy block: {}", e))),
+        };
 
         // Measure processing of buckets and metadata
         let bucket_processing_start = Instant::now();
-        self.p
+        let queued = self
+            .p
             .zip_with_binary_tree(&self.metadata)
             .par_iter()
-            .for_each(|(bucket, metadata_bucket, _)| {
-                if let (Some(bucket), Some(metadata_bucket)) = (bucket, metadata_bucket) {
-                    let mut real_decrypt_count = 0;
-                    (0..bucket.len()).for_each(|b| {
+            .try_for_each(|(bucket, metadata_bucket, _)| -> Result<(), String> {
+                // A bucket never written to has no metadata, and still costs Z dummy openings.
+                oblivious.open_bucket(|opens| {
+                    let (Some(bucket), Some(metadata_bucket)) = (bucket, metadata_bucket) else {
+                        return Ok(());
+                    };
+                    for b in 0..bucket.len() {
                         if let Some(metadata_block) = metadata_bucket.get(b) {
                             let (l, k_oblv_t, t_exp) = metadata_block;
                             if self.epoch < *t_exp {
-                                let c_msg = bucket.get(b).unwrap();
+                                let c_msg = bucket.get(b).ok_or_else(|| {
+                                    format!("block {} has metadata but no data", b)
+                                })?;
                                 // Real decryption
-                                let ct = decrypt(&k_oblv_t.0, &c_msg.0).unwrap();
-                                let (lca_idx, _) = self.pt.lca_idx(&l).unwrap();
+                                let ct = opens
+                                    .open(k_oblv_t, c_msg)
+                                    .map_err(|e| format!("decrypting block {}: {}", b, e))?;
+                                let (lca_idx, _) = self.pt.lca_idx(l).ok_or_else(|| {
+                                    format!("block {} has no bucket in the pathset", b)
+                                })?;
                                 self.message_queue.entry(lca_idx).or_default().push((
                                     ct,
                                     k_oblv_t.clone(),
                                     *t_exp,
                                     l.clone(),
                                 ));
-                                real_decrypt_count += 1;
                             }
                         }
-                    });
-
-                    // Perform fake decryptions
-                    let fake_decrypt_count = Z - real_decrypt_count;
-                    for _ in 0..fake_decrypt_count {
-                        // Fake decryption
-                        let _ = decrypt(&[0u8; 32], &[0u8; BLOCK_SIZE]).unwrap_or_default();
                     }
-                }
+                    Ok(())
+                })
             });
 
+        if let Err(reason) = queued {
+            return Err(self.abort_batch(reason));
+        }
+
         // This enumerated index doesn't match the index inside of the message queue.
-        self.pt
+        let processed = self
+            .pt
             .zip_mut(&mut self.metadata_pt)
             .enumerate()
             .par_bridge()
-            .for_each(|(idx, (mut bucket, mut metadata_bucket, bucket_path))| {
+            .try_for_each(|(idx, (mut bucket, mut metadata_bucket, bucket_path))| -> Result<(), String> {
                 // Get the original index in the p and metadata tree from the index in pt.
-                let original_idx = self.pathset_indices[idx];
+                let original_idx = *self
+                    .pathset_indices
+                    .get(idx)
+                    .ok_or_else(|| format!("bucket {} is outside the pathset", idx))?;
 
                 // Insert both the new and non-expired messages into the pt and metadata_pt.
-                let mut real_encrypt_count = 0;
-                if let Some(blocks) = self.message_queue.get(&original_idx) {
-                    for (ct, k_oblv_t, t_exp, intended_message_path) in blocks.iter() {
-                        let c_msg = encrypt(&k_oblv_t.0, &ct, EncryptionType::DoubleEncrypt)
-                            .map_err(|_| MycoError::EncryptionFailed)
-                            .unwrap();
-
-                        // Insert the message into the pt bucket.
-                        if let Some(bucket) = bucket.as_mut() {
-                            bucket.push(Block::new(c_msg));
-                        }
+                oblivious.seal_bucket(|seals| {
+                    if let Some(blocks) = self.message_queue.get(&original_idx) {
+                        for (ct, k_oblv_t, t_exp, intended_message_path) in blocks.iter() {
+                            // Insert the message into the pt bucket, behind its key hint.
+                            let block = seals.seal(k_oblv_t, ct).map_err(|e| {
+                                format!("sealing a block for bucket {}: {}", original_idx, e)
+                            })?;
+                            if let Some(bucket) = bucket.as_mut() {
+                                bucket.push(block);
+                            }
 
-                        // Insert the metadata into the metadata_pt bucket.
-                        if let Some(metadata_bucket) = metadata_bucket.as_mut() {
-                            metadata_bucket.push(
-                                intended_message_path.clone(),
-                                k_oblv_t.clone(),
-                                *t_exp,
-                            );
+                            // Insert the metadata into the metadata_pt bucket.
+                            if let Some(metadata_bucket) = metadata_bucket.as_mut() {
+                                metadata_bucket.push(
+                                    intended_message_path.clone(),
+                                    k_oblv_t.clone(),
+                                    *t_exp,
+                                );
+                            }
                         }
-                        real_encrypt_count += 1;
                     }
-                }
-
-                // Perform fake encryptions
-                let fake_encrypt_count = Z - real_encrypt_count;
-                for _ in 0..fake_encrypt_count {
-                    // Fake encryption
-                    let _ = encrypt(&[0u8; 32], &[0u8; BLOCK_SIZE], EncryptionType::DoubleEncrypt)
-                        .unwrap_or_default();
-                }
+                    Ok(())
+                })?;
 
-                // Insert blocks into the pt bucket and metadata_pt bucket.
+                // Insert blocks into the pt bucket and metadata_pt bucket. Without encryption
+                // there is nothing to hide, so buckets are neither padded nor shuffled.
                 if let Some(bucket) = bucket.as_mut() {
-                    #[cfg(feature = "no-enc")]
-                    {
-                        // Just push the block, no padding or shuffling needed
-                    }
-
-                    #[cfg(not(feature = "no-enc"))]
-                    {
+                    if simulation.encrypts() {
                         let mut rng = ChaCha20Rng::from_seed(seed);
                         // Add random padding blocks
                         (bucket.len()..Z).for_each(|_| {
@@ -281,22 +1112,17 @@ impl Server1 {
 
                         bucket.shuffle(&mut rng);
                     }
-                    assert!(
-                        bucket.len() <= Z,
-                        "Bucket length exceeds Z in epoch {}: bucket length={}, expected<={}",
-                        self.epoch,
-                        bucket.len(),
-                        Z
-                    );
+                    if bucket.len() > Z {
+                        return Err(format!(
+                            "bucket {} holds {} blocks, more than Z={}",
+                            original_idx,
+                            bucket.len(),
+                            Z
+                        ));
+                    }
                 }
                 if let Some(metadata_bucket) = metadata_bucket.as_mut() {
-                    #[cfg(feature = "no-enc")]
-                    {
-                        // Just push the metadata, no padding or shuffling needed
-                    }
-
-                    #[cfg(not(feature = "no-enc"))]
-                    {
+                    if simulation.encrypts() {
                         let mut rng = ChaCha20Rng::from_seed(seed);
                         // Add random padding metadata
                         (metadata_bucket.len()..Z).for_each(|_| {
@@ -305,19 +1131,25 @@ impl Server1 {
 
                         metadata_bucket.shuffle(&mut rng);
                     }
-                    assert!(
-                        metadata_bucket.len() <= Z,
-                        "Metadata bucket length exceeds Z: bucket length={}, expected<={}",
-                        metadata_bucket.len(),
-                        Z
-                    );
+                    if metadata_bucket.len() > Z {
+                        return Err(format!(
+                            "metadata bucket {} holds {} entries, more than Z={}",
+                            original_idx,
+                            metadata_bucket.len(),
+                            Z
+                        ));
+                    }
                 }
+                Ok(())
             });
+        if let Err(reason) = processed {
+            return Err(self.abort_batch(reason));
+        }
         let bucket_processing_duration = bucket_processing_start.elapsed();
 
-        // After processing all buckets, find the maximum capacity
-        #[cfg(feature = "no-enc")]
-        {
+        // After processing all buckets, find the maximum capacity. Only unpadded buckets have
+        // one worth reporting.
+        if !self.simulation.encrypts() {
             let mut max_capacity = 0;
             let mut max_depth = 0;
             self.pt.packed_buckets.iter().enumerate().for_each(|(idx, bucket)| {
@@ -333,128 +1165,134 @@ impl Server1 {
         // Reset the message queue
         self.message_queue.clear();
 
-        // Measure metadata overwrite time
-        self.metadata.overwrite_from_sparse(&self.metadata_pt);
+        // The index is published first, so S2 releases it together with the epoch's key.
+        if let Some(index) = self.notification_index() {
+            if let Err(e) = futures::executor::block_on(self.s2.publish_notifications(index)) {
+                println!("Server1: Error publishing the notification index: {:?}", e);
+            }
+        }
+        if let Err(e) = futures::executor::block_on(self.s2.publish_storage(self.storage_report())) {
+            println!("Server1: Error publishing the storage report: {:?}", e);
+        }
 
-        let write_result = futures::executor::block_on(
-            self.s2
-                .write(self.pt.packed_buckets.clone(), self.k_s1_t.clone()),
-        );
-        let result = match write_result {
+        let write_result = futures::executor::block_on(self.write_buckets());
+        match write_result {
             Ok(_) => {
+                // The metadata only moves on once Server2 holds the matching buckets.
+                self.metadata.overwrite_from_sparse(&self.metadata_pt);
+                self.replicate(|| ReplicationEvent::BatchWritten {
+                    epoch: self.epoch,
+                    metadata: self.metadata_pt.clone(),
+                });
+                self.finish_batch();
                 self.epoch += 1;
                 Ok(())
             }
-            Err(e) => {
-                println!("Server1: Error writing to Server2: {:?}", e);
-                Err(e)
-            }
-        };
-
-        result.map_err(|_| MycoError::NoMessageFound)
+            Err(e) => Err(self.abort_batch(format!("writing to Server2: {}", e))),
+        }
     }
 
     /// Finalize a batch write.
     pub async fn async_batch_write(&mut self) -> Result<(), MycoError> {
+        self.close_batch();
         let end_to_end_latency = LatencyMetric::new("server1_batch_write_end_to_end");  
         let local_latency = LatencyMetric::new("server1_batch_write_local");
         let mut rng = ChaCha20Rng::from_entropy();
         let seed: [u8; 32] = rng.gen();
+        let simulation = self.simulation;
+        // Every bucket costs Z block operations, real or dummy (see ObliviousBatch).
+        let oblivious = match ObliviousBatch::new(self.secrets.clone(), simulation) {
+            Ok(oblivious) => oblivious,
+            Err(e) => return Err(self.abort_batch(format!("sealing the dummy block: {}", e))),
+        };
 
         // Measure processing of buckets and metadata
         let queue_old_buckets_latency: LatencyMetric = LatencyMetric::new("server1_batch_write_queue_old_buckets");
-        self.p
+        let queued = self
+            .p
             .zip_with_binary_tree(&self.metadata)
             .par_iter()
-            .for_each(|(bucket, metadata_bucket, _)| {
-                if let (Some(bucket), Some(metadata_bucket)) = (bucket, metadata_bucket) {
-                    let mut real_decrypt_count = 0;
-                    (0..bucket.len()).for_each(|b| {
+            .try_for_each(|(bucket, metadata_bucket, _)| -> Result<(), String> {
+                // A bucket never written to has no metadata, and still costs Z dummy openings.
+                oblivious.open_bucket(|opens| {
+                    let (Some(bucket), Some(metadata_bucket)) = (bucket, metadata_bucket) else {
+                        return Ok(());
+                    };
+                    for b in 0..bucket.len() {
                         if let Some(metadata_block) = metadata_bucket.get(b) {
                             let (l, k_oblv_t, t_exp) = metadata_block;
                             if self.epoch < *t_exp {
-                                let c_msg = bucket.get(b).unwrap();
+                                let c_msg = bucket.get(b).ok_or_else(|| {
+                                    format!("block {} has metadata but no data", b)
+                                })?;
                                 // Real decryption
-                                let ct = decrypt(&k_oblv_t.0, &c_msg.0).unwrap();
-                                let (lca_idx, _) = self.pt.lca_idx(&l).unwrap();
+                                let ct = opens
+                                    .open(k_oblv_t, c_msg)
+                                    .map_err(|e| format!("decrypting block {}: {}", b, e))?;
+                                let (lca_idx, _) = self.pt.lca_idx(l).ok_or_else(|| {
+                                    format!("block {} has no bucket in the pathset", b)
+                                })?;
                                 self.message_queue.entry(lca_idx).or_default().push((
                                     ct,
                                     k_oblv_t.clone(),
                                     *t_exp,
                                     l.clone(),
                                 ));
-                                real_decrypt_count += 1;
                             }
                         }
-                    });
-
-                    // Perform fake decryptions to prevent timing attacks
-                    #[cfg(not(feature = "no-enc"))]
-                    {
-                        let fake_decrypt_count = Z - real_decrypt_count;
-                        for _ in 0..fake_decrypt_count {
-                        // Fake decryption
-                            let _ = decrypt(&[0u8; 32], &[0u8; BLOCK_SIZE]).unwrap_or_default();
-                        }
                     }
-                }
+                    Ok(())
+                })
             });
         queue_old_buckets_latency.finish();
 
+        if let Err(reason) = queued {
+            return Err(self.abort_batch(reason));
+        }
+
         // This enumerated index doesn't match the index inside of the message queue.
         let process_queued_buckets_latency = LatencyMetric::new("server1_batch_write_process_queued_buckets");
-        self.pt
+        let processed = self
+            .pt
             .zip_mut(&mut self.metadata_pt)
             .enumerate()
             .par_bridge()
-            .for_each(|(idx, (mut bucket, mut metadata_bucket, bucket_path))| {
+            .try_for_each(|(idx, (mut bucket, mut metadata_bucket, bucket_path))| -> Result<(), String> {
                 // Get the original index in the p and metadata tree from the index in pt.
-                let original_idx = self.pathset_indices[idx];
+                let original_idx = *self
+                    .pathset_indices
+                    .get(idx)
+                    .ok_or_else(|| format!("bucket {} is outside the pathset", idx))?;
 
                 // Insert both the new and non-expired messages into the pt and metadata_pt.
-                let mut real_encrypt_count = 0;
-                if let Some(blocks) = self.message_queue.get(&original_idx) {
-                    for (ct, k_oblv_t, t_exp, intended_message_path) in blocks.iter() {
-                        let c_msg = encrypt(&k_oblv_t.0, &ct, EncryptionType::DoubleEncrypt)
-                            .map_err(|_| MycoError::EncryptionFailed)
-                            .unwrap();
-
-                        // Insert the message into the pt bucket.
-                        if let Some(bucket) = bucket.as_mut() {
-                            bucket.push(Block::new(c_msg));
-                        }
+                oblivious.seal_bucket(|seals| {
+                    if let Some(blocks) = self.message_queue.get(&original_idx) {
+                        for (ct, k_oblv_t, t_exp, intended_message_path) in blocks.iter() {
+                            // Insert the message into the pt bucket, behind its key hint.
+                            let block = seals.seal(k_oblv_t, ct).map_err(|e| {
+                                format!("sealing a block for bucket {}: {}", original_idx, e)
+                            })?;
+                            if let Some(bucket) = bucket.as_mut() {
+                                bucket.push(block);
+                            }
 
-                        // Insert the metadata into the metadata_pt bucket.
-                        if let Some(metadata_bucket) = metadata_bucket.as_mut() {
-                            metadata_bucket.push(
-                                intended_message_path.clone(),
-                                k_oblv_t.clone(),
-                                *t_exp,
-                            );
+                            // Insert the metadata into the metadata_pt bucket.
+                            if let Some(metadata_bucket) = metadata_bucket.as_mut() {
+                                metadata_bucket.push(
+                                    intended_message_path.clone(),
+                                    k_oblv_t.clone(),
+                                    *t_exp,
+                                );
+                            }
                         }
-                        real_encrypt_count += 1;
                     }
-                }
-
-                // Perform fake encryptions to prevent timing attacks
-                #[cfg(not(feature = "no-enc"))]
-                {
-                    let fake_encrypt_count = Z - real_encrypt_count;
-                    for _ in 0..fake_encrypt_count {
-                    // Fake encryption
-                    let _ = encrypt(&[0u8; 32], &[0u8; BLOCK_SIZE], EncryptionType::DoubleEncrypt)
-                        .unwrap_or_default();
-                }
+                    Ok(())
+                })?;
 
-                // Insert blocks into the pt bucket and metadata_pt bucket.
+                // Insert blocks into the pt bucket and metadata_pt bucket. Without encryption
+                // there is nothing to hide, so buckets are neither padded nor shuffled.
                 if let Some(bucket) = bucket.as_mut() {
-                    #[cfg(feature = "no-enc")]
-                    {
-                        // Just push the block, no padding or shuffling needed
-                    }
-
-                    #[cfg(not(feature = "no-enc"))]
-                    {
+                    if simulation.encrypts() {
                         let mut rng = ChaCha20Rng::from_seed(seed);
                         // Add random padding blocks
                         (bucket.len()..Z).for_each(|_| {
@@ -463,22 +1301,17 @@ impl Server1 {
 
                         bucket.shuffle(&mut rng);
                     }
-                    assert!(
-                        bucket.len() <= Z,
-                        "Bucket length exceeds Z in epoch {}: bucket length={}, expected<={}",
-                        self.epoch,
-                        bucket.len(),
-                        Z
-                    );
+                    if bucket.len() > Z {
+                        return Err(format!(
+                            "bucket {} holds {} blocks, more than Z={}",
+                            original_idx,
+                            bucket.len(),
+                            Z
+                        ));
+                    }
                 }
                 if let Some(metadata_bucket) = metadata_bucket.as_mut() {
-                    #[cfg(feature = "no-enc")]
-                    {
-                        // Just push the metadata, no padding or shuffling needed
-                    }
-
-                    #[cfg(not(feature = "no-enc"))]
-                    {
+                    if simulation.encrypts() {
                         let mut rng = ChaCha20Rng::from_seed(seed);
                         // Add random padding metadata
                         (metadata_bucket.len()..Z).for_each(|_| {
@@ -487,20 +1320,25 @@ impl Server1 {
 
                         metadata_bucket.shuffle(&mut rng);
                     }
-                    assert!(
-                        metadata_bucket.len() <= Z,
-                        "Metadata bucket length exceeds Z: bucket length={}, expected<={}",
-                        metadata_bucket.len(),
-                        Z
-                    );
+                    if metadata_bucket.len() > Z {
+                        return Err(format!(
+                            "metadata bucket {} holds {} entries, more than Z={}",
+                            original_idx,
+                            metadata_bucket.len(),
+                            Z
+                        ));
                     }
                 }
+                Ok(())
             });
+        if let Err(reason) = processed {
+            return Err(self.abort_batch(reason));
+        }
         process_queued_buckets_latency.finish();
 
-        // After processing all buckets, find the maximum capacity
-        #[cfg(feature = "no-enc")]
-        {
+        // After processing all buckets, find the maximum capacity. Only unpadded buckets have
+        // one worth reporting.
+        if !self.simulation.encrypts() {
             let mut max_capacity = 0;
             let mut max_depth = 0;
             self.pt.packed_buckets.iter().enumerate().for_each(|(idx, bucket)| {
@@ -519,32 +1357,38 @@ impl Server1 {
         // Reset the message queue
         self.message_queue.clear();
 
-        // Measure metadata overwrite time
-        let metadata_overwrite_latency = LatencyMetric::new("server1_batch_write_metadata_overwrite");
-        self.metadata.overwrite_from_sparse(&self.metadata_pt);
-        metadata_overwrite_latency.finish();
-
         local_latency.finish();
 
         let write_to_server2_latency = LatencyMetric::new("server1_batch_write_write_to_server2");
-        let write_result = self
-            .s2
-            .write(self.pt.packed_buckets.clone(), self.k_s1_t.clone())
-            .await;
-        let result = match write_result {
+        // The index is published first, so S2 releases it together with the epoch's key.
+        if let Some(index) = self.notification_index() {
+            if let Err(e) = self.s2.publish_notifications(index).await {
+                println!("Server1: Error publishing the notification index: {:?}", e);
+            }
+        }
+        if let Err(e) = self.s2.publish_storage(self.storage_report()).await {
+            println!("Server1: Error publishing the storage report: {:?}", e);
+        }
+        let write_result = self.write_buckets().await;
+        match write_result {
             Ok(_) => {
                 println!("Server1: Successfully wrote to Server2");
-                self.epoch += 1;
+                // The metadata only moves on once Server2 holds the matching buckets.
+                let metadata_overwrite_latency = LatencyMetric::new("server1_batch_write_metadata_overwrite");
+                self.metadata.overwrite_from_sparse(&self.metadata_pt);
+                metadata_overwrite_latency.finish();
+                self.replicate(|| ReplicationEvent::BatchWritten {
+                    epoch: self.epoch,
+                    metadata: self.metadata_pt.clone(),
+                });
+                // Finish the timings first, so they count towards the epoch being closed.
                 end_to_end_latency.finish();
                 write_to_server2_latency.finish();
+                self.finish_batch();
+                self.epoch += 1;
                 Ok(())
             }
-            Err(e) => {
-                println!("Server1: Error writing to Server2: {:?}", e);
-                Err(e)
-            }
-        };
-
-        result.map_err(|_| MycoError::NoMessageFound)
+            Err(e) => Err(self.abort_batch(format!("writing to Server2: {}", e))),
+        }
     }
 }
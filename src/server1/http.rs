@@ -0,0 +1,290 @@
+//! HTTP endpoints for Server1
+//!
+//! The axum router and handlers served by the `rpc_server1` binary. Embedders can mount
+//! [`router`] inside an existing service instead of running the binary.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::HeaderMap,
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use tokio::sync::RwLock;
+
+use crate::{
+    admin::{self, AdminState, EpochControl, OperatorAuth},
+    hardening,
+    idempotency::{IdempotencyKey, ResponseCache},
+    rpc_types::{
+        BatchInitRequest, BatchInitResponse, BatchWriteResponse, DeleteRegistrationRequest,
+        DeleteRegistrationResponse, ErrorResponse, GuardMailboxesRequest, GuardMailboxesResponse,
+        IssueWriteTokensRequest, IssueWriteTokensResponse,
+        QueueWriteRequest, QueueWriteResponse, RegisterResponse, RotateRegistrationRequest, RotateRegistrationResponse,
+    },
+    server1::Server1,
+    transport::NEXT_EPOCH_HEADER,
+};
+#[cfg(feature = "debug-json")]
+use crate::json::JsonRoute;
+#[cfg(feature = "debug-json")]
+use axum::http::Method;
+
+/// State shared by the Server1 handlers.
+#[derive(Clone)]
+pub struct AppState {
+    /// The Server1 instance.
+    pub server1: Arc<RwLock<Server1>>,
+    /// The epoch lifecycle flags shared with the admin API and scheduler.
+    pub control: Arc<EpochControl>,
+    /// Responses to recent requests by idempotency key.
+    pub responses: Arc<ResponseCache>,
+}
+
+impl AppState {
+    /// Create a new AppState wrapping the given Server1 instance.
+    pub fn new(server1: Server1) -> Self {
+        Self {
+            server1: Arc::new(RwLock::new(server1)),
+            control: Arc::new(EpochControl::new()),
+            responses: Arc::new(ResponseCache::default()),
+        }
+    }
+
+    /// State for the admin router, sharing this server and its epoch control.
+    pub fn admin_state(&self, token: String) -> AdminState {
+        AdminState {
+            server1: self.server1.clone(),
+            control: self.control.clone(),
+            token: Arc::new(token),
+        }
+    }
+}
+
+/// Build the Server1 router.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/queue_write", post(handle_queue_write))
+        .route("/batch_write", get(handle_batch_write))
+        .route("/batch_init", post(handle_batch_init))
+        .route("/register", post(handle_register))
+        .route("/register/rotate", post(handle_rotate_registration))
+        .route("/register/delete", post(handle_delete_registration))
+        .route("/guard_mailboxes", post(handle_guard_mailboxes))
+        .route("/write_tokens", post(handle_issue_write_tokens))
+}
+
+/// The routes of [`router`] mirrored by [`crate::json::debug_routes`].
+#[cfg(feature = "debug-json")]
+pub fn json_routes() -> Vec<JsonRoute> {
+    vec![
+        JsonRoute::new::<QueueWriteRequest, QueueWriteResponse>(Method::POST, "/queue_write"),
+        JsonRoute::bodyless::<BatchWriteResponse>(Method::GET, "/batch_write"),
+        JsonRoute::new::<BatchInitRequest, BatchInitResponse>(Method::POST, "/batch_init"),
+        JsonRoute::bodyless::<RegisterResponse>(Method::POST, "/register"),
+        JsonRoute::new::<RotateRegistrationRequest, RotateRegistrationResponse>(
+            Method::POST,
+            "/register/rotate",
+        ),
+        JsonRoute::new::<DeleteRegistrationRequest, DeleteRegistrationResponse>(
+            Method::POST,
+            "/register/delete",
+        ),
+        JsonRoute::new::<GuardMailboxesRequest, GuardMailboxesResponse>(
+            Method::POST,
+            "/guard_mailboxes",
+        ),
+        JsonRoute::new::<IssueWriteTokensRequest, IssueWriteTokensResponse>(
+            Method::POST,
+            "/write_tokens",
+        ),
+    ]
+}
+
+/// Build the router for the benchmark maintenance routes. Every route requires operator
+/// credentials, see [`admin::require_operator`].
+pub fn benchmark_router(auth: OperatorAuth) -> Router<AppState> {
+    Router::new()
+        .route("/finalize_benchmark", post(handle_finalize_benchmark))
+        .route_layer(middleware::from_fn_with_state(auth, admin::require_operator))
+}
+
+/// Queue a write onto Server1. Uses the shared app state for Server1 to queue the write.
+///
+/// Writes arriving while no epoch is open are rejected with 503 Service Unavailable and, when
+/// known, the estimated opening time of the next epoch, both in the [`ErrorResponse`] and in
+/// [`NEXT_EPOCH_HEADER`].
+///
+/// With automatic batch initialization (see [`Server1::set_auto_batch_init`]), the first write of
+/// an epoch opens it instead, unless the server is draining.
+///
+/// A request repeating the idempotency key of a queued write is answered with the cached response.
+pub async fn handle_queue_write(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /queue_write");
+    let request: QueueWriteRequest = hardening::decode(&bytes)?;
+    let key = request.idempotency_key;
+
+    // TODO: This should not need a Mutex/RwLock once Server1 is refactored to make the queue_write method threadsafe with DashMap.
+    let mut server1 = state.server1.write().await;
+    if let Some(response) = state.responses.get("/queue_write", key) {
+        return Ok(response);
+    }
+    admin::auto_batch_init(&mut server1, &state.control).await?;
+    if !state.control.accepting_writes() {
+        return Err(server1.epoch_closed().into());
+    }
+    let epoch = server1.queue_write(
+        request.ct,
+        request.f,
+        request.k_oblv_t,
+        request.cs,
+        request.token,
+        request.access_tag,
+    )?;
+
+    let response = hardening::encode(&QueueWriteResponse { success: true, epoch })?;
+    state.responses.insert("/queue_write", key, &response);
+    Ok(response)
+}
+
+/// Write out the current epoch to Server2.
+///
+/// The request has no body, so its idempotency key is read from
+/// [`IDEMPOTENCY_KEY_HEADER`](crate::idempotency::IDEMPOTENCY_KEY_HEADER).
+pub async fn handle_batch_write(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /batch_write");
+    let key = IdempotencyKey::from_headers(&headers)?;
+    // A resent request needn't wait for the gate of the next epoch.
+    if let Some(response) = state.responses.get("/batch_write", key) {
+        return Ok(response);
+    }
+
+    admin::wait_for_anonymity_gate(&state.server1).await;
+    let mut server1 = state.server1.write().await;
+    if let Some(response) = state.responses.get("/batch_write", key) {
+        return Ok(response);
+    }
+    state.control.set_epoch_open(false);
+    server1.async_batch_write().await?;
+
+    let response = hardening::encode(&BatchWriteResponse { success: true })?;
+    state.responses.insert("/batch_write", key, &response);
+    Ok(response)
+}
+
+/// Initialize a new batch of writes.
+pub async fn handle_batch_init(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /batch_init");
+    let request: BatchInitRequest = hardening::decode(&bytes)?;
+    let key = request.idempotency_key;
+
+    // TODO: This should not need a Mutex/RwLock once Server1 is refactored to make the queue_write method threadsafe with DashMap.
+    let mut server1 = state.server1.write().await;
+    if let Some(response) = state.responses.get("/batch_init", key) {
+        return Ok(response);
+    }
+    server1.async_batch_init(request.num_writes).await?;
+    state.control.set_epoch_open(true);
+
+    let response = hardening::encode(&BatchInitResponse { success: true })?;
+    state.responses.insert("/batch_init", key, &response);
+    Ok(response)
+}
+
+/// Register a new pseudonymous account (see [`crate::registration`]).
+pub async fn handle_register(State(state): State<AppState>) -> Result<Bytes, ErrorResponse> {
+    let credentials = state
+        .server1
+        .write()
+        .await
+        .registrations
+        .register(&mut rand::thread_rng())?;
+
+    hardening::encode(&RegisterResponse { credentials })
+}
+
+/// Replace the ID and secret of an account.
+pub async fn handle_rotate_registration(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: RotateRegistrationRequest = hardening::decode(&bytes)?;
+
+    let credentials = state
+        .server1
+        .write()
+        .await
+        .registrations
+        .rotate(&request.credentials, &mut rand::thread_rng())?;
+
+    hardening::encode(&RotateRegistrationResponse { credentials })
+}
+
+/// Delete an account.
+pub async fn handle_delete_registration(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: DeleteRegistrationRequest = hardening::decode(&bytes)?;
+
+    state
+        .server1
+        .write()
+        .await
+        .registrations
+        .delete(&request.credentials)?;
+
+    hardening::encode(&DeleteRegistrationResponse { success: true })
+}
+
+/// Guard mailbox addresses on behalf of their owner (see [`crate::mailbox`]).
+pub async fn handle_guard_mailboxes(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: GuardMailboxesRequest = hardening::decode(&bytes)?;
+
+    state
+        .server1
+        .write()
+        .await
+        .guard_mailboxes(request.guards)?;
+
+    hardening::encode(&GuardMailboxesResponse { success: true })
+}
+
+/// Issue write tokens to a registered account (see [`crate::write_tokens`]).
+pub async fn handle_issue_write_tokens(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: IssueWriteTokensRequest = hardening::decode(&bytes)?;
+
+    let tokens = state
+        .server1
+        .write()
+        .await
+        .issue_write_tokens(&request.credentials, request.requests)?;
+
+    hardening::encode(&IssueWriteTokensResponse { tokens })
+}
+
+/// Write out the averaged benchmark metrics.
+pub async fn handle_finalize_benchmark() -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /finalize_benchmark");
+    #[cfg(feature = "perf-logging")]
+    crate::logging::calculate_and_append_averages("server1_latency.csv", "server1_bytes.csv");
+    Ok(Bytes::from("Benchmark finalized"))
+}
@@ -7,10 +7,25 @@
 //! random path selection. S2 also stores and provides PRF keys for clients to compute message paths, 
 //! ensuring privacy by preventing correlation between writes and reads.
 
-use std::cmp::min;
+pub mod http;
+
+use std::{
+    cmp::{min, Reverse},
+    collections::{HashMap, VecDeque},
+    fs,
+    path::Path as FsPath,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 
 use crate::{
-    constants::{D, DELTA, NUM_BUCKETS_PER_BATCH_WRITE_CHUNK, NUM_BUCKETS_PER_READ_PATHS_CHUNK}, dtypes::{Bucket, Key, Path}, error::MycoError, logging::LatencyMetric, tree::BinaryTree
+    backup::Backup,
+    bandwidth::BandwidthMeter,
+    constants::{D, DELTA, MAX_PENDING_EPOCH_TAGS, NUM_BUCKETS_PER_BATCH_WRITE_CHUNK, NUM_BUCKETS_PER_READ_PATHS_CHUNK, STORAGE_STATS_TOP}, dtypes::{BandwidthStats, Bucket, BucketDelta, EpochInfo, Key, MemoryStats, Path, ReadStats, StorageReport, StorageStats}, error::MycoError, journal::{committed_epochs, Journal, JournalRecord}, logging::{self, LatencyBreakdown, LatencyMetric, MetricsSink, PerfLog}, memory::{allocator_stats, HeapSize}, notification::NotificationIndex, tree::{self, BinaryTree, StateParams}, utils::{self, get_leaf_path_indices}
 };
 
 cfg_if::cfg_if! {
@@ -27,10 +42,120 @@ pub struct Server2 {
     pub tree: BinaryTree<Bucket>,
     /// The PRF keys.
     pub prf_keys: Vec<Key>,
+    /// Number of PRF keys published so far. The keys in `prf_keys` are numbered from
+    /// `prf_key_cursor - prf_keys.len()` up to `prf_key_cursor`.
+    pub prf_key_cursor: u64,
     /// The current epoch.
     pub epoch: u64,
     /// The pathset indices.
     pathset_indices: Vec<usize>,
+    /// Number of client reads served in the current epoch.
+    epoch_reads: AtomicUsize,
+    /// Read counts of the last completed epoch.
+    last_read_stats: Option<ReadStats>,
+    /// Bytes received and sent per epoch.
+    bandwidth: Arc<BandwidthMeter>,
+    /// Notification index of the epoch being written, released with its PRF key.
+    pending_notifications: Option<Vec<u8>>,
+    /// Notification index of the newest epoch, with the PRF key cursor its key was published at.
+    notifications: Option<(u64, Vec<u8>)>,
+    /// Notification indices of the live epochs, oldest first, for pending epoch queries.
+    epoch_notifications: VecDeque<(u64, Option<NotificationIndex>)>,
+    /// Storage report of the epoch being written, accounted once its PRF key is added.
+    pending_storage: Option<StorageReport>,
+    /// Blocks per storage tag of the live epochs, oldest first.
+    storage: VecDeque<HashMap<Vec<u8>, usize>>,
+    /// The write-ahead journal chunks are recorded in before they are applied, if any.
+    journal: Option<Journal>,
+    /// The epoch each bucket of the tree was last written in, by tree index, for incremental
+    /// backups.
+    written_at: Vec<u64>,
+    /// Whether chunks of the current epoch were written, leaving the tree between two epochs.
+    epoch_in_progress: bool,
+}
+
+impl Default for Server2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The chunk `chunk_idx` of `indices`, as split up by chunked path reads. Empty past the end.
+pub fn read_chunk(indices: &[usize], chunk_idx: usize) -> &[usize] {
+    let start = chunk_idx
+        .saturating_mul(NUM_BUCKETS_PER_READ_PATHS_CHUNK)
+        .min(indices.len());
+    let end = start
+        .saturating_add(NUM_BUCKETS_PER_READ_PATHS_CHUNK)
+        .min(indices.len());
+    &indices[start..end]
+}
+
+/// Move `buckets` into `tree` at `indices`, in parallel. Stops at whichever runs out first.
+///
+/// The tree's slots are split along the runs of consecutive indices (see
+/// [`tree::contiguous_runs`]), so every bucket gets its own disjoint `&mut` and the buckets being
+/// replaced are dropped across the thread pool. That takes `indices` sorted, without duplicates
+/// and inside the tree, as pathsets are kept (see [`check_pathset`]); other indices are rejected
+/// before anything is written.
+fn write_runs(
+    tree: &mut BinaryTree<Bucket>,
+    indices: &[usize],
+    buckets: Vec<Bucket>,
+) -> Result<(), MycoError> {
+    check_pathset(indices, tree.value.len())?;
+    let mut slots = Vec::with_capacity(indices.len().min(buckets.len()));
+    let mut rest = tree.value.as_mut_slice();
+    let mut offset = 0;
+    for run in tree::contiguous_runs(indices) {
+        let (_, tail) = std::mem::take(&mut rest).split_at_mut(run.start - offset);
+        let (run_slots, tail) = tail.split_at_mut(run.len());
+        slots.extend(run_slots);
+        rest = tail;
+        offset = run.end;
+    }
+
+    slots
+        .into_par_iter()
+        .zip(buckets)
+        .for_each(|(slot, bucket)| *slot = Some(bucket));
+    Ok(())
+}
+
+/// Check that `pathset` holds tree indices in increasing order, each of them naming a bucket of a
+/// tree of `tree_len` slots. Index 0 is unused, see [`BinaryTree`].
+fn check_pathset(pathset: &[usize], tree_len: usize) -> Result<(), MycoError> {
+    if let Some(&index) = pathset.iter().find(|&&index| index == 0 || index >= tree_len) {
+        return Err(MycoError::MalformedRequest(format!("no bucket at index {}", index)));
+    }
+    if let Some(pair) = pathset.windows(2).find(|pair| pair[0] >= pair[1]) {
+        return Err(MycoError::MalformedRequest(format!(
+            "pathset index {} follows {}, not sorted and deduplicated",
+            pair[1], pair[0]
+        )));
+    }
+    Ok(())
+}
+
+/// The positions of chunk `chunk_idx` of `chunk_size` buckets in a pathset of `len`. Chunks past
+/// the end are rejected, except the first chunk of an empty pathset.
+fn chunk_range(len: usize, chunk_idx: usize, chunk_size: usize) -> Result<std::ops::Range<usize>, MycoError> {
+    let start = chunk_idx
+        .checked_mul(chunk_size)
+        .filter(|&start| start < len || chunk_idx == 0)
+        .ok_or_else(|| {
+            MycoError::MalformedRequest(format!("chunk {} is outside a pathset of {}", chunk_idx, len))
+        })?;
+    Ok(start..min(start + chunk_size, len))
+}
+
+/// Record that the buckets at `indices` were written in `epoch`.
+fn mark_written(written_at: &mut [u64], indices: &[usize], epoch: u64) {
+    for &index in indices {
+        if let Some(slot) = written_at.get_mut(index) {
+            *slot = epoch;
+        }
+    }
 }
 
 impl Server2 {
@@ -43,7 +168,7 @@ impl Server2 {
             tree.fill(Bucket::new_random());
             // Initialize DELTA random PRF keys
             let mut rng = ChaCha20Rng::from_entropy();
-            let prf_keys = (0..DELTA).map(|_| Key::random(&mut rng)).collect();
+            let prf_keys: Vec<Key> = (0..DELTA).map(|_| Key::random(&mut rng)).collect();
             (tree, prf_keys)
         };
 
@@ -54,16 +179,29 @@ impl Server2 {
         };
 
         Server2 {
+            written_at: vec![0; tree.value.len()],
+            epoch_in_progress: false,
             tree,
+            prf_key_cursor: prf_keys.len() as u64,
             prf_keys,
             epoch: 0,
             pathset_indices: vec![],
+            epoch_reads: AtomicUsize::new(0),
+            last_read_stats: None,
+            bandwidth: Arc::new(BandwidthMeter::new("server2", Arc::new(PerfLog))),
+            pending_notifications: None,
+            notifications: None,
+            epoch_notifications: VecDeque::new(),
+            pending_storage: None,
+            storage: VecDeque::new(),
+            journal: None,
         }
     }
 
     /// Read a path from the tree.
     pub fn read(&self, l: &Path) -> Result<Vec<Bucket>, MycoError> {
         let read_latency = LatencyMetric::new("server2_read");
+        self.epoch_reads.fetch_add(1, Ordering::Relaxed);
         let buckets = self.tree.get_all_nodes_along_path(l);
         read_latency.finish();
         Ok(buckets)
@@ -74,8 +212,15 @@ impl Server2 {
         &self.tree
     }
 
-    /// Write a batch of buckets to the tree.
-    pub fn write(&mut self, packed_buckets: Vec<Bucket>) {
+    /// Write a batch of buckets to the tree for `epoch` and publish its PRF key.
+    pub fn write(
+        &mut self,
+        epoch: u64,
+        packed_buckets: Vec<Bucket>,
+        key: &Key,
+    ) -> Result<(), MycoError> {
+        self.check_epoch(epoch)?;
+        self.check_prf_key(key)?;
         let write_latency = LatencyMetric::new("server2_write");
         // Ensure the number of elements in packed_buckets matches the number of pathset_indices
         assert_eq!(
@@ -84,42 +229,191 @@ impl Server2 {
             "Mismatched number of indices and buckets"
         );
 
-        // Iterate over self.pathset_indices and packed_buckets, and overwrite corresponding values in self.tree
-        for (index, bucket) in self.pathset_indices.iter().zip(packed_buckets.iter()) {
-            self.tree.value[*index] = Some(bucket.clone());
+        if let Some(journal) = self.journal.as_mut() {
+            for (chunk_idx, chunk) in packed_buckets
+                .chunks(NUM_BUCKETS_PER_BATCH_WRITE_CHUNK)
+                .enumerate()
+            {
+                journal.record_chunk(epoch, &self.pathset_indices, chunk_idx, chunk)?;
+            }
         }
 
-        // Increment the epoch
-        self.epoch += 1;
+        // Overwrite the buckets of self.tree at self.pathset_indices with packed_buckets
+        write_runs(&mut self.tree, &self.pathset_indices, packed_buckets)?;
+        mark_written(&mut self.written_at, &self.pathset_indices, epoch);
+
+        self.commit_epoch(epoch, key)?;
         write_latency.finish();
+        Ok(())
     }
 
-    /// Write a single chunk of buckets to the server.
-    pub fn chunk_write(&mut self, buckets: Vec<Bucket>, chunk_idx: usize) {
+    /// Write a single chunk of buckets of `epoch` to the server.
+    pub fn chunk_write(
+        &mut self,
+        epoch: u64,
+        buckets: Vec<Bucket>,
+        chunk_idx: usize,
+    ) -> Result<(), MycoError> {
+        self.check_epoch(epoch)?;
         let write_latency = LatencyMetric::new("server2_write");
 
-        // The start and end indices of the chunk within the pathset_indices vector.
-        let start_idx = chunk_idx * NUM_BUCKETS_PER_BATCH_WRITE_CHUNK;
-        let end_idx = start_idx + NUM_BUCKETS_PER_BATCH_WRITE_CHUNK;
+        // The positions of the chunk within the pathset_indices vector. The last chunk may not
+        // have NUM_BUCKETS_PER_BATCH_WRITE_CHUNK buckets.
+        let range = chunk_range(
+            self.pathset_indices.len(),
+            chunk_idx,
+            NUM_BUCKETS_PER_BATCH_WRITE_CHUNK,
+        )?;
+        if buckets.len() > range.len() {
+            return Err(MycoError::MalformedRequest(format!(
+                "{} buckets for chunk {} of a pathset of {}",
+                buckets.len(),
+                chunk_idx,
+                self.pathset_indices.len()
+            )));
+        }
 
-        // The last chunk may not have NUM_BUCKETS_PER_CHUNK buckets.
-        let correct_end_idx = min(end_idx, self.pathset_indices.len());
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record_chunk(epoch, &self.pathset_indices, chunk_idx, &buckets)?;
+        }
 
         // Write buckets to the tree at the indices specified by pathset_indices
-        self.pathset_indices[start_idx..correct_end_idx]
+        let indices = &self.pathset_indices[range];
+        write_runs(&mut self.tree, indices, buckets)?;
+        mark_written(&mut self.written_at, indices, epoch);
+        self.epoch_in_progress = true;
+        write_latency.finish();
+        Ok(())
+    }
+
+    /// Write the bucket at `position` of chunk `chunk_idx` of `epoch`, for chunk writes applied as
+    /// they are received. Rewriting a bucket, as when a chunk is resent, is harmless. Refused
+    /// with a journal, which records chunks whole: use [`Server2::chunk_write`] instead.
+    pub fn write_chunk_bucket(
+        &mut self,
+        epoch: u64,
+        chunk_idx: usize,
+        position: usize,
+        bucket: Bucket,
+    ) -> Result<(), MycoError> {
+        self.check_epoch(epoch)?;
+        if self.journal.is_some() {
+            return Err(MycoError::ProtocolError(
+                "buckets can't be journaled one at a time".to_string(),
+            ));
+        }
+        let index = (position < NUM_BUCKETS_PER_BATCH_WRITE_CHUNK)
+            .then(|| chunk_idx.checked_mul(NUM_BUCKETS_PER_BATCH_WRITE_CHUNK))
+            .flatten()
+            .and_then(|start| self.pathset_indices.get(start + position))
+            .ok_or_else(|| {
+                MycoError::MalformedRequest(format!(
+                    "bucket {} of chunk {} is outside a pathset of {}",
+                    position,
+                    chunk_idx,
+                    self.pathset_indices.len()
+                ))
+            })?;
+        self.tree.value[*index] = Some(bucket);
+        mark_written(&mut self.written_at, &[*index], epoch);
+        self.epoch_in_progress = true;
+        Ok(())
+    }
+
+    /// Write a batch of bucket deltas to the tree for `epoch` and publish its PRF key, like
+    /// [`Server2::write`].
+    pub fn write_deltas(
+        &mut self,
+        epoch: u64,
+        deltas: Vec<BucketDelta>,
+        key: &Key,
+    ) -> Result<(), MycoError> {
+        self.check_epoch(epoch)?;
+        if deltas.len() != self.pathset_indices.len() {
+            return Err(MycoError::MalformedRequest(format!(
+                "{} bucket deltas for a pathset of {}",
+                deltas.len(),
+                self.pathset_indices.len()
+            )));
+        }
+        let buckets = self.apply_deltas(0, deltas)?;
+        self.write(epoch, buckets, key)
+    }
+
+    /// Write a single chunk of bucket deltas of `epoch`, like [`Server2::chunk_write`].
+    pub fn chunk_write_deltas(
+        &mut self,
+        epoch: u64,
+        deltas: Vec<BucketDelta>,
+        chunk_idx: usize,
+    ) -> Result<(), MycoError> {
+        self.check_epoch(epoch)?;
+        let buckets = self.apply_deltas(chunk_idx * NUM_BUCKETS_PER_BATCH_WRITE_CHUNK, deltas)?;
+        self.chunk_write(epoch, buckets, chunk_idx)
+    }
+
+    /// Apply `deltas` to the buckets of the pathset from position `start` on.
+    fn apply_deltas(&self, start: usize, deltas: Vec<BucketDelta>) -> Result<Vec<Bucket>, MycoError> {
+        let indices = self.pathset_indices.get(start..).unwrap_or_default();
+        if deltas.len() > indices.len() {
+            return Err(MycoError::MalformedRequest(format!(
+                "{} bucket deltas past position {} of a pathset of {}",
+                deltas.len(),
+                start,
+                self.pathset_indices.len()
+            )));
+        }
+        indices
             .iter()
-            .zip(buckets)
-            .for_each(|(idx, bucket)| {
-                self.tree.value[*idx] = Some(bucket);
+            .zip(deltas)
+            .map(|(index, delta)| delta.apply(&self.bucket(*index)?))
+            .collect()
+    }
+
+    /// Finish `epoch` and add the new PRF key.
+    pub fn finalize_epoch(&mut self, epoch: u64, key: &Key) -> Result<(), MycoError> {
+        self.check_epoch(epoch)?;
+        self.check_prf_key(key)?;
+        self.commit_epoch(epoch, key)
+    }
+
+    /// Reject writes for an epoch older than the current one, so a replayed or out of date Server1
+    /// can't roll the tree back. A newer epoch is accepted, as Server1 may have moved on while this
+    /// server was restored from an older snapshot.
+    pub fn check_epoch(&self, epoch: u64) -> Result<(), MycoError> {
+        if epoch < self.epoch {
+            return Err(MycoError::StaleEpoch {
+                epoch,
+                current: self.epoch,
             });
-        write_latency.finish();
+        }
+        Ok(())
     }
 
-    /// Increments the epoch and adds the new PRF key.
-    pub fn finalize_epoch(&mut self, key: &Key) {
-        // Increment the epoch.
-        self.epoch += 1;
+    /// Reject a PRF key that is still among the published ones.
+    fn check_prf_key(&self, key: &Key) -> Result<(), MycoError> {
+        if self.prf_keys.contains(key) {
+            return Err(MycoError::PrfKeyReused);
+        }
+        Ok(())
+    }
 
+    /// Commit `epoch` to the journal, if there is one, then close it and publish its PRF key.
+    fn commit_epoch(&mut self, epoch: u64, key: &Key) -> Result<(), MycoError> {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.commit(epoch, key)?;
+        }
+        self.advance_epoch(epoch, key);
+        Ok(())
+    }
+
+    /// Close `epoch` and publish its PRF key.
+    fn advance_epoch(&mut self, epoch: u64, key: &Key) {
+        self.finish_read_stats();
+        self.bandwidth.finish_epoch(self.epoch);
+        logging::finish_latency_epoch("server2", self.epoch);
+        self.epoch = epoch + 1;
+        self.epoch_in_progress = false;
         self.add_prf_key(key);
     }
 
@@ -128,10 +422,92 @@ impl Server2 {
         Ok(self.prf_keys.clone())
     }
 
+    /// The current epoch and PRF key cursor.
+    pub fn epoch_info(&self) -> EpochInfo {
+        EpochInfo {
+            epoch: self.epoch,
+            prf_key_cursor: self.prf_key_cursor,
+        }
+    }
+
+    /// Read counts of the current epoch so far.
+    pub fn read_stats(&self) -> ReadStats {
+        ReadStats {
+            epoch: self.epoch,
+            reads: self.epoch_reads.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Read counts of the last completed epoch, if any.
+    pub fn last_read_stats(&self) -> Option<ReadStats> {
+        self.last_read_stats
+    }
+
+    /// The meter counting the bytes this server receives and sends per epoch.
+    pub fn bandwidth_meter(&self) -> &Arc<BandwidthMeter> {
+        &self.bandwidth
+    }
+
+    /// Report this server's per-epoch metrics to `sink` instead of [`PerfLog`]. Call it before
+    /// handing out the [`bandwidth_meter`](Self::bandwidth_meter), which it replaces.
+    pub fn set_metrics_sink(&mut self, sink: Arc<dyn MetricsSink>) {
+        self.bandwidth = Arc::new(BandwidthMeter::new("server2", sink));
+    }
+
+    /// Bytes received and sent in the current epoch so far.
+    pub fn bandwidth_stats(&self) -> BandwidthStats {
+        self.bandwidth.stats(self.epoch)
+    }
+
+    /// Latencies of this server's operations in the current and last epochs.
+    pub fn latency_breakdown(&self) -> LatencyBreakdown {
+        logging::latency_breakdown("server2", self.epoch)
+    }
+
+    /// Close the current epoch's read counts, keeping only the aggregate.
+    fn finish_read_stats(&mut self) {
+        self.last_read_stats = Some(self.read_stats());
+        self.epoch_reads.store(0, Ordering::Relaxed);
+    }
+
+    /// Get the PRF keys published at or after `cursor`, together with the number of the first key
+    /// returned. If `cursor` is older than the oldest key kept (or ahead of the server, e.g. after
+    /// a restore), all keys are returned and the caller should replace what it has.
+    pub fn get_prf_keys_since(&self, cursor: u64) -> Result<(u64, Vec<Key>), MycoError> {
+        let oldest = self.prf_key_cursor - self.prf_keys.len() as u64;
+        let start = if cursor > self.prf_key_cursor {
+            oldest
+        } else {
+            cursor.max(oldest)
+        };
+        Ok((start, self.prf_keys[(start - oldest) as usize..].to_vec()))
+    }
+
     /// Add a PRF key to the server.
     pub fn add_prf_key(&mut self, key: &Key) {
         let add_prf_key_latency = LatencyMetric::new("server2_add_prf_key");
         self.prf_keys.push(key.clone());
+        self.prf_key_cursor += 1;
+        self.notifications = self
+            .pending_notifications
+            .take()
+            .map(|index| (self.prf_key_cursor, index));
+        // An index that doesn't parse leaves the epoch out of pending epoch queries.
+        let index = self
+            .notifications
+            .as_ref()
+            .and_then(|(_, bytes)| NotificationIndex::from_bytes(bytes).ok());
+        self.epoch_notifications
+            .push_back((self.epoch.saturating_sub(1), index));
+        if self.epoch_notifications.len() > DELTA {
+            self.epoch_notifications.pop_front();
+        }
+        // Epochs without a report still take up a slot, so reports leave with their messages.
+        self.storage
+            .push_back(self.pending_storage.take().unwrap_or_default().into_iter().collect());
+        if self.storage.len() > DELTA {
+            self.storage.pop_front();
+        }
 
         if self.epoch >= DELTA as u64 {
             self.prf_keys.remove(0);
@@ -139,21 +515,166 @@ impl Server2 {
         add_prf_key_latency.finish();
     }
 
-    /// Store the pathset indices.
-    pub fn store_path_indices(&mut self, pathset: Vec<usize>) {
+    /// Store the notification index of the epoch being written. It is served from the moment the
+    /// epoch's PRF key is added.
+    pub fn publish_notifications(&mut self, index: Vec<u8>) {
+        self.pending_notifications = Some(index);
+    }
+
+    /// The notification index of the newest epoch, with the PRF key cursor right after its key, or
+    /// `None` if Server1 didn't publish one.
+    pub fn notifications(&self) -> Option<(u64, Vec<u8>)> {
+        self.notifications.clone()
+    }
+
+    /// The epochs, among the last [`DELTA`], with a write matching one of `tags`, each given with
+    /// the epoch it was derived for, in ascending order. Tags are matched against the notification
+    /// index of their epoch, so an epoch Server1 published no index for is never returned.
+    pub fn pending_epochs(&self, tags: &[(u64, Vec<u8>)]) -> Result<Vec<u64>, MycoError> {
+        if tags.len() > MAX_PENDING_EPOCH_TAGS {
+            return Err(MycoError::MalformedRequest(format!(
+                "{} read tags, at most {} are accepted",
+                tags.len(),
+                MAX_PENDING_EPOCH_TAGS
+            )));
+        }
+        let mut epochs: Vec<u64> = tags
+            .iter()
+            .filter(|(epoch, tag)| {
+                self.epoch_notifications
+                    .binary_search_by_key(epoch, |(epoch, _)| *epoch)
+                    .ok()
+                    .and_then(|i| self.epoch_notifications[i].1.as_ref())
+                    .is_some_and(|index| index.contains(tag))
+            })
+            .map(|(epoch, _)| *epoch)
+            .collect();
+        epochs.sort_unstable();
+        epochs.dedup();
+        Ok(epochs)
+    }
+
+    /// Store the storage report of the epoch being written. It is accounted from the moment the
+    /// epoch's PRF key is added, for as long as the epoch's messages live.
+    pub fn publish_storage(&mut self, report: StorageReport) {
+        self.pending_storage = Some(report);
+    }
+
+    /// Memory held by the bucket tree and the PRF keys.
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            tree_bytes: self.tree.heap_size(),
+            metadata_bytes: self.prf_keys.heap_size(),
+            queue_depth: 0,
+            allocator: allocator_stats(),
+        }
+    }
+
+    /// Approximate storage use of the live epochs, with the [`STORAGE_STATS_TOP`] heaviest storage
+    /// tags.
+    pub fn storage_stats(&self) -> StorageStats {
+        let mut tags: Vec<(&Vec<u8>, usize)> = self
+            .storage
+            .iter()
+            .flat_map(|epoch| epoch.iter().map(|(tag, blocks)| (tag, *blocks)))
+            .collect();
+        let blocks = tags.iter().map(|(_, blocks)| blocks).sum();
+        let count = tags.len();
+        tags.sort_unstable_by_key(|&(tag, blocks)| (Reverse(blocks), tag));
+        StorageStats {
+            epochs: self.storage.len(),
+            blocks,
+            tags: count,
+            heaviest: tags
+                .into_iter()
+                .take(STORAGE_STATS_TOP)
+                .map(|(tag, blocks)| (hex::encode(tag), blocks))
+                .collect(),
+        }
+    }
+
+    /// Store the pathset indices. They have to be sorted, deduplicated and inside the tree, as
+    /// [`get_path_indices`](crate::utils::get_path_indices) returns them.
+    pub fn store_path_indices(&mut self, pathset: Vec<usize>) -> Result<(), MycoError> {
+        self.set_pathset(pathset)
+    }
+
+    /// Store the pathset given as the leaves its paths lead to, see [`get_leaf_path_indices`].
+    /// Returns the number of buckets in the pathset.
+    pub fn store_path_leaves(&mut self, leaves: &[u32]) -> Result<usize, MycoError> {
+        self.set_pathset(get_leaf_path_indices(leaves)?)?;
+        Ok(self.pathset_indices.len())
+    }
+
+    /// Replace the pathset, once it's checked to be sorted, deduplicated and inside the tree.
+    /// Chunks journaled for the old one are no longer part of the epoch.
+    fn set_pathset(&mut self, pathset: Vec<usize>) -> Result<(), MycoError> {
+        check_pathset(&pathset, self.tree.value.len())?;
         self.pathset_indices = pathset;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.restart_epoch();
+        }
+        Ok(())
+    }
+
+    /// The indices of the buckets in a chunk of the stored pathset.
+    pub fn pathset_chunk(&self, chunk_idx: usize) -> &[usize] {
+        read_chunk(&self.pathset_indices, chunk_idx)
+    }
+
+    /// Count a client read for the read statistics. A chunked read counts once, on its first chunk.
+    pub fn count_client_read(&self, chunk_idx: usize) {
+        if chunk_idx == 0 {
+            self.epoch_reads.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Read the bucket at `index` of the tree.
+    pub fn bucket(&self, index: usize) -> Result<Bucket, MycoError> {
+        self.tree
+            .value
+            .get(index)
+            .cloned()
+            .flatten()
+            .ok_or_else(|| MycoError::MalformedRequest(format!("no bucket at index {}", index)))
+    }
+
+    /// The buckets at `indices`, copied a run of consecutive indices at a time (see
+    /// [`tree::contiguous_runs`]). Indices outside the tree are rejected like in
+    /// [`Server2::bucket`].
+    fn buckets_at(&self, indices: &[usize]) -> Result<Vec<Bucket>, MycoError> {
+        if let Some(index) = indices.iter().find(|&&index| index >= self.tree.value.len()) {
+            return Err(MycoError::MalformedRequest(format!("no bucket at index {}", index)));
+        }
+        let mut buckets = Vec::with_capacity(indices.len());
+        for run in tree::contiguous_runs(indices) {
+            for (index, bucket) in run.clone().zip(&self.tree.value[run]) {
+                let bucket = bucket.clone().ok_or_else(|| {
+                    MycoError::MalformedRequest(format!("no bucket at index {}", index))
+                })?;
+                buckets.push(bucket);
+            }
+        }
+        Ok(buckets)
+    }
+
+    /// Whether the bucket at `index` of the tree holds no blocks, i.e. has never been written.
+    pub fn bucket_is_empty(&self, index: usize) -> Result<bool, MycoError> {
+        match self.tree.value.get(index) {
+            Some(Some(bucket)) => Ok(bucket.is_empty()),
+            _ => Err(MycoError::MalformedRequest(format!("no bucket at index {}", index))),
+        }
     }
 
     /// Read a chunk of buckets from the server.
     pub fn read_pathset_chunk(&self, chunk_idx: usize) -> Result<Vec<Bucket>, MycoError> {
         let read_paths_latency: LatencyMetric = LatencyMetric::new("server2_read_paths");
-        let start_idx = chunk_idx * NUM_BUCKETS_PER_READ_PATHS_CHUNK;
-        let end_idx = start_idx + NUM_BUCKETS_PER_READ_PATHS_CHUNK;
-        let correct_end_idx = min(end_idx, self.pathset_indices.len());
-        let buckets = self.pathset_indices[start_idx..correct_end_idx]
-            .iter()
-            .map(|i| self.tree.value[*i].clone().unwrap())
-            .collect();
+        let range = chunk_range(
+            self.pathset_indices.len(),
+            chunk_idx,
+            NUM_BUCKETS_PER_READ_PATHS_CHUNK,
+        )?;
+        let buckets = self.buckets_at(&self.pathset_indices[range])?;
         read_paths_latency.finish();
         Ok(buckets)
     }
@@ -165,13 +686,12 @@ impl Server2 {
         indices: Vec<usize>,
     ) -> Result<Vec<Bucket>, MycoError> {
         let read_paths_latency: LatencyMetric = LatencyMetric::new("server2_read_paths_client");
-        let start_idx = chunk_idx * NUM_BUCKETS_PER_READ_PATHS_CHUNK;
-        let end_idx = start_idx + NUM_BUCKETS_PER_READ_PATHS_CHUNK;
-        let correct_end_idx = min(end_idx, indices.len());
-        let buckets = indices[start_idx..correct_end_idx]
-            .iter()
-            .map(|i| self.tree.value[*i].clone().unwrap())
-            .collect();
+        // A chunked read counts once, on its first chunk.
+        if chunk_idx == 0 {
+            self.epoch_reads.fetch_add(1, Ordering::Relaxed);
+        }
+        let range = chunk_range(indices.len(), chunk_idx, NUM_BUCKETS_PER_READ_PATHS_CHUNK)?;
+        let buckets = self.buckets_at(&indices[range])?;
         read_paths_latency.finish();
         Ok(buckets)
     }
@@ -182,24 +702,202 @@ impl Server2 {
         pathset: Vec<usize>,
     ) -> Result<Vec<Bucket>, MycoError> {
         let read_paths_latency = LatencyMetric::new("server2_read_paths");
-        self.pathset_indices = pathset.clone();
+        self.set_pathset(pathset)?;
 
-        let buckets: Vec<Bucket> = pathset
-            .iter()
-            .map(|i| self.tree.value[*i].clone().unwrap())
-            .collect();
+        let buckets = self.buckets_at(&self.pathset_indices)?;
         read_paths_latency.finish();
         Ok(buckets)
     }
 
+    /// Like [`Server2::read_and_store_path_indices`], with the pathset given as leaves.
+    pub fn read_and_store_path_leaves(&mut self, leaves: &[u32]) -> Result<Vec<Bucket>, MycoError> {
+        let read_paths_latency = LatencyMetric::new("server2_read_paths");
+        self.store_path_leaves(leaves)?;
+        let buckets = self.buckets_at(&self.pathset_indices)?;
+        read_paths_latency.finish();
+        Ok(buckets)
+    }
+
+    /// The buckets on the paths to `leaves`, without storing them as the pathset or counting them
+    /// as a client read. Server1 prefetches the next epoch's pathset with it, see
+    /// [`crate::server1::Server1::set_prefetch`].
+    pub fn prefetch_leaf_paths(&self, leaves: &[u32]) -> Result<Vec<Bucket>, MycoError> {
+        self.buckets_at(&get_leaf_path_indices(leaves)?)
+    }
+
     /// Read a chunk of buckets from the server for a client request.
     pub fn read_paths_client(&self, pathset: Vec<usize>) -> Result<Vec<Bucket>, MycoError> {
         let read_paths_latency = LatencyMetric::new("server2_read_paths_client!");
-        let buckets: Vec<Bucket> = pathset
-            .iter()
-            .map(|i| self.tree.value[*i].clone().unwrap())
-            .collect();
+        self.epoch_reads.fetch_add(1, Ordering::Relaxed);
+        let buckets = self.buckets_at(&pathset)?;
         read_paths_latency.finish();
         Ok(buckets)
     }
+
+    /// Record chunks in `journal` before applying them, see [`crate::journal`]. Replay the records
+    /// the journal was opened with first.
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /// Whether chunks are recorded in a journal.
+    pub fn is_journaling(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Roll the tree forward by the epochs committed in `records` from the current epoch on, as
+    /// read from the journal when it was opened. Epochs that weren't committed are left out, so
+    /// the tree stays at the last committed one. Returns the number of epochs rolled forward.
+    pub fn replay_journal(&mut self, records: Vec<JournalRecord>) -> Result<usize, MycoError> {
+        let mut replayed = 0;
+        for committed in committed_epochs(records)? {
+            if committed.epoch < self.epoch {
+                continue;
+            }
+            for (chunk_idx, buckets) in committed.chunks {
+                let start = chunk_idx.saturating_mul(NUM_BUCKETS_PER_BATCH_WRITE_CHUNK);
+                let indices = committed.pathset.get(start..).unwrap_or_default();
+                if buckets.len() > indices.len() {
+                    return Err(MycoError::IncompatibleState(format!(
+                        "journaled chunk {} of epoch {} is outside its pathset",
+                        chunk_idx, committed.epoch
+                    )));
+                }
+                mark_written(&mut self.written_at, &indices[..buckets.len()], committed.epoch);
+                write_runs(&mut self.tree, indices, buckets).map_err(|e| {
+                    MycoError::IncompatibleState(format!(
+                        "journaled pathset of epoch {}: {}",
+                        committed.epoch, e
+                    ))
+                })?;
+            }
+            self.pathset_indices = committed.pathset;
+            self.advance_epoch(committed.epoch, &committed.prf_key);
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    /// Drop the journal records of the epochs before the current one, once a snapshot holds them.
+    pub fn compact_journal(&mut self) -> Result<(), MycoError> {
+        match self.journal.as_mut() {
+            Some(journal) => journal.compact(self.epoch),
+            None => Ok(()),
+        }
+    }
+
+    /// A backup of the tree and PRF keys as of the current epoch, see [`crate::backup`]. With
+    /// `since`, only the buckets written in that epoch or after it are included, making an
+    /// incremental backup on top of the one taken at `since`.
+    ///
+    /// Refused while an epoch's chunks are being written, so backups always fall between epochs.
+    pub fn backup(&self, since: Option<u64>) -> Result<Backup, MycoError> {
+        if self.epoch_in_progress {
+            return Err(MycoError::ProtocolError(format!(
+                "epoch {} is being written",
+                self.epoch
+            )));
+        }
+        if since.is_some_and(|since| since > self.epoch) {
+            return Err(MycoError::ProtocolError(format!(
+                "a backup since epoch {} is ahead of epoch {}",
+                since.unwrap_or_default(),
+                self.epoch
+            )));
+        }
+        let (indices, buckets) = self
+            .tree
+            .value
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| since.is_none_or(|since| self.written_at[*index] >= since))
+            .filter_map(|(index, bucket)| Some((index, bucket.clone()?)))
+            .unzip();
+        Ok(Backup {
+            epoch: self.epoch,
+            since,
+            indices,
+            buckets,
+            prf_keys: self.prf_keys.clone(),
+            prf_key_cursor: self.prf_key_cursor,
+        })
+    }
+
+    /// Apply `backup`, which must be a full backup or one taken since the current epoch.
+    pub fn apply_backup(&mut self, backup: Backup) -> Result<(), MycoError> {
+        if backup.since.is_some_and(|since| since != self.epoch) {
+            return Err(MycoError::IncompatibleState(format!(
+                "backup of epoch {} builds on epoch {:?}, not {}",
+                backup.epoch, backup.since, self.epoch
+            )));
+        }
+        if backup.indices.len() != backup.buckets.len()
+            || backup.indices.iter().any(|&index| index >= self.tree.value.len())
+        {
+            return Err(MycoError::IncompatibleState(format!(
+                "backup of epoch {} doesn't fit the tree",
+                backup.epoch
+            )));
+        }
+        let written = backup.epoch.saturating_sub(1);
+        for (index, bucket) in backup.indices.into_iter().zip(backup.buckets) {
+            self.tree.value[index] = Some(bucket);
+            self.written_at[index] = written;
+        }
+        self.prf_keys = backup.prf_keys;
+        self.prf_key_cursor = backup.prf_key_cursor;
+        self.epoch = backup.epoch;
+        Ok(())
+    }
+
+    /// Save the tree, PRF keys, PRF key cursor and epoch to a snapshot file, versioned like the
+    /// tree state files (see [`tree::STATE_SCHEMA_VERSION`]).
+    ///
+    /// The snapshot is written with [`utils::write_durably`], so a crash or power loss mid-write
+    /// leaves either the old snapshot or the new one, never a truncated one.
+    pub fn save_snapshot(&self, path: &FsPath) -> Result<(), MycoError> {
+        let bytes = tree::encode_state(
+            StateParams::local(),
+            &(&self.tree, &self.prf_keys, self.prf_key_cursor, self.epoch),
+        )?;
+        utils::write_durably(path, &bytes).map_err(MycoError::IoError)
+    }
+
+    /// Restore a Server2 instance from a snapshot file written by [`Server2::save_snapshot`].
+    ///
+    /// Snapshots written by a build with different tree parameters are rejected with
+    /// [`MycoError::IncompatibleState`]. Unversioned snapshots are accepted if their tree fits
+    /// this build.
+    pub fn load_snapshot(path: &FsPath) -> Result<Self, MycoError> {
+        let bytes = fs::read(path).map_err(MycoError::IoError)?;
+        let params = StateParams::local();
+        let (header, state) = tree::decode_state_header(&bytes)?;
+        if let Some(header) = header {
+            header.params.check_compatible(&params)?;
+        }
+        let (tree, prf_keys, prf_key_cursor, epoch): (BinaryTree<Bucket>, Vec<Key>, u64, u64) =
+            bincode::deserialize(state).map_err(|e| MycoError::DeserializationError(Some(e)))?;
+        if header.is_none() {
+            params.check_buckets(&tree)?;
+        }
+        // When the buckets were written is lost, so they count as written in the last epoch.
+        Ok(Server2 {
+            written_at: vec![epoch.saturating_sub(1); tree.value.len()],
+            epoch_in_progress: false,
+            tree,
+            prf_keys,
+            prf_key_cursor,
+            epoch,
+            pathset_indices: vec![],
+            epoch_reads: AtomicUsize::new(0),
+            last_read_stats: None,
+            bandwidth: Arc::new(BandwidthMeter::new("server2", Arc::new(PerfLog))),
+            pending_notifications: None,
+            notifications: None,
+            epoch_notifications: VecDeque::new(),
+            pending_storage: None,
+            storage: VecDeque::new(),
+            journal: None,
+        })
+    }
 }
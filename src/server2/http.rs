@@ -0,0 +1,624 @@
+//! HTTP endpoints for Server2
+//!
+//! The axum router and handlers served by the `rpc_server2` binary. Embedders can mount
+//! [`router`] inside an existing service instead of running the binary, and services with their
+//! own state can reuse individual handlers by implementing `FromRef` for [`AppState`].
+
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::HeaderMap,
+    middleware,
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{Stream, StreamExt};
+use tokio::sync::RwLock;
+
+use crate::{
+    admin::{self, OperatorAuth},
+    constants::D,
+    dtypes::{Bucket, Key, SparseBuckets},
+    error::MycoError,
+    hardening,
+    logging::LatencyBreakdown,
+    rpc_types::{
+        BackupRequest, BackupResponse, Capabilities, GetCapabilitiesResponse,
+        ChunkReadPathsClientRequest, ChunkReadPathsRequest,
+        ChunkWriteResponse, ErrorResponse, FinalizeEpochRequest,
+        FinalizeEpochResponse, GetEpochResponse, GetNotificationsResponse, GetPrfKeysResponse, GetStatsResponse, GetPrfKeysSinceRequest, MemoryStatsResponse,
+        GetPrfKeysSinceResponse, PendingEpochsRequest, PendingEpochsResponse, PrefetchPathLeavesRequest, PublishNotificationsRequest, PublishStorageRequest, ReadPathsClientRequest, ReadPathsRequest,
+        ReadRequest, ReadResponse, SparsePathFrame, StorePathIndicesRequest, StorePathIndicesResponse,
+        StorePathLeavesRequest, StorePathLeavesResponse, WriteRequest, WriteResponse,
+    },
+    server2::{self, Server2},
+    streaming,
+    transport::CHUNK_HEADER,
+};
+#[cfg(feature = "debug-json")]
+use crate::{
+    json::JsonRoute,
+    rpc_types::{ChunkWriteRequest, ReadPathsResponse},
+};
+#[cfg(feature = "debug-json")]
+use axum::http::Method;
+
+/// State shared by the Server2 handlers.
+#[derive(Clone)]
+pub struct AppState {
+    /// The Server2 instance.
+    pub server2: Arc<RwLock<Server2>>,
+    /// Number of chunked pathset reads served.
+    pub write_count: Arc<Mutex<usize>>,
+}
+
+impl AppState {
+    /// Create a new AppState wrapping the given Server2 instance.
+    pub fn new(server2: Server2) -> Self {
+        Self {
+            server2: Arc::new(RwLock::new(server2)),
+            write_count: Arc::new(Mutex::new(0)),
+        }
+    }
+}
+
+/// Build the Server2 router.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/read", post(handle_read))
+        .route("/read_paths", post(handle_read_paths))
+        .route("/read_paths_client", post(handle_read_paths_client))
+        .route(
+            "/chunk_read_paths_client",
+            post(handle_chunk_read_paths_client),
+        )
+        .route("/write", post(handle_write))
+        .route("/chunk_write", post(handle_chunk_write))
+        .route("/chunk_write_deltas", post(handle_chunk_write_deltas))
+        .route("/chunk_read_paths", post(handle_chunk_read_paths))
+        .route("/store_path_indices", post(handle_store_path_indices))
+        .route("/store_path_leaves", post(handle_store_path_leaves))
+        .route("/prefetch_path_leaves", post(handle_prefetch_path_leaves))
+        .route("/finalize_epoch", post(handle_finalize_epoch))
+        .route("/epoch", get(handle_epoch))
+        .route("/capabilities", get(handle_capabilities))
+        .route("/stats", get(handle_stats))
+        .route("/get_prf_keys", get(handle_get_prf_keys))
+        .route("/get_prf_keys_since", post(handle_get_prf_keys_since))
+        .route(
+            "/notifications",
+            get(handle_get_notifications).post(handle_publish_notifications),
+        )
+        .route("/pending_epochs", post(handle_pending_epochs))
+        .route("/storage", post(handle_publish_storage))
+}
+
+/// The routes of [`router`] mirrored by [`crate::json::debug_routes`]. The chunked path reads answer with
+/// framed responses and are left out.
+#[cfg(feature = "debug-json")]
+pub fn json_routes() -> Vec<JsonRoute> {
+    vec![
+        JsonRoute::new::<ReadRequest, ReadResponse>(Method::POST, "/read"),
+        JsonRoute::new::<ReadPathsRequest, ReadPathsResponse>(Method::POST, "/read_paths"),
+        JsonRoute::new::<ReadPathsClientRequest, ReadPathsResponse>(
+            Method::POST,
+            "/read_paths_client",
+        ),
+        JsonRoute::new::<WriteRequest, WriteResponse>(Method::POST, "/write"),
+        JsonRoute::new::<ChunkWriteRequest, ChunkWriteResponse>(Method::POST, "/chunk_write"),
+        JsonRoute::new::<StorePathIndicesRequest, StorePathIndicesResponse>(
+            Method::POST,
+            "/store_path_indices",
+        ),
+        JsonRoute::new::<StorePathLeavesRequest, StorePathLeavesResponse>(
+            Method::POST,
+            "/store_path_leaves",
+        ),
+        JsonRoute::new::<PrefetchPathLeavesRequest, ReadPathsResponse>(
+            Method::POST,
+            "/prefetch_path_leaves",
+        ),
+        JsonRoute::new::<FinalizeEpochRequest, FinalizeEpochResponse>(
+            Method::POST,
+            "/finalize_epoch",
+        ),
+        JsonRoute::bodyless::<GetEpochResponse>(Method::GET, "/epoch"),
+        JsonRoute::bodyless::<GetCapabilitiesResponse>(Method::GET, "/capabilities"),
+        JsonRoute::bodyless::<GetStatsResponse>(Method::GET, "/stats"),
+        JsonRoute::bodyless::<GetPrfKeysResponse>(Method::GET, "/get_prf_keys"),
+        JsonRoute::new::<GetPrfKeysSinceRequest, GetPrfKeysSinceResponse>(
+            Method::POST,
+            "/get_prf_keys_since",
+        ),
+        JsonRoute::bodyless::<GetNotificationsResponse>(Method::GET, "/notifications"),
+        JsonRoute::new::<PublishNotificationsRequest, WriteResponse>(
+            Method::POST,
+            "/notifications",
+        ),
+        JsonRoute::new::<PendingEpochsRequest, PendingEpochsResponse>(
+            Method::POST,
+            "/pending_epochs",
+        ),
+        JsonRoute::new::<PublishStorageRequest, WriteResponse>(Method::POST, "/storage"),
+        JsonRoute::bodyless::<MemoryStatsResponse>(Method::GET, "/admin/memory"),
+    ]
+}
+
+/// Build the router for the benchmark maintenance routes. Every route requires operator
+/// credentials, see [`admin::require_operator`].
+pub fn benchmark_router(auth: OperatorAuth) -> Router<AppState> {
+    Router::new()
+        .route("/finalize_benchmark", post(handle_finalize_benchmark))
+        .route_layer(middleware::from_fn_with_state(auth, admin::require_operator))
+}
+
+/// Build the router for Server2's admin routes. Every route requires operator credentials, see
+/// [`admin::require_operator`].
+pub fn admin_router(auth: OperatorAuth) -> Router<AppState> {
+    Router::new()
+        .route("/admin/memory", get(handle_memory))
+        .route("/admin/latency", get(handle_latency))
+        .route("/admin/backup", post(handle_backup))
+        .route_layer(middleware::from_fn_with_state(auth, admin::require_operator))
+}
+
+/// Read the buckets along a single path. Paths longer than the tree depth are rejected.
+pub async fn handle_read(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: ReadRequest = hardening::decode(&bytes)?;
+    if request.path.len() > D {
+        return Err(MycoError::MalformedRequest(format!(
+            "path of {} levels is deeper than the tree",
+            request.path.len()
+        ))
+        .into());
+    }
+
+    let buckets = state
+        .server2
+        .read()
+        .await
+        .read(&request.path)
+        ?;
+
+    hardening::encode(&ReadResponse { buckets })
+}
+
+/// Read the pathset buckets and remember the pathset for the following write.
+pub async fn handle_read_paths(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Response, ErrorResponse> {
+    println!("Received request: /read_paths");
+    let request: ReadPathsRequest = hardening::decode(&bytes)?;
+
+    let buckets = state
+        .server2
+        .write()
+        .await
+        .read_and_store_path_indices(request.indices)
+        ?;
+
+    streaming::response(buckets)
+}
+
+/// Store the pathset indices.
+pub async fn handle_store_path_indices(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /store_path_indices");
+    let request: StorePathIndicesRequest = hardening::decode(&bytes)?;
+
+    state
+        .server2
+        .write()
+        .await
+        .store_path_indices(request.pathset)?;
+
+    hardening::encode(&StorePathIndicesResponse { success: true })
+}
+
+/// Store the pathset given as the leaves of its paths, answering with the expanded pathset's size
+/// so the reader can split its chunked reads.
+pub async fn handle_store_path_leaves(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /store_path_leaves");
+    let request: StorePathLeavesRequest = hardening::decode(&bytes)?;
+
+    let pathset_size = state
+        .server2
+        .write()
+        .await
+        .store_path_leaves(&request.leaves)?;
+
+    hardening::encode(&StorePathLeavesResponse { pathset_size })
+}
+
+/// Read the paths to the given leaves without storing them as the pathset, for Server1 to prefetch
+/// the next epoch's pathset while the current one is still being written.
+pub async fn handle_prefetch_path_leaves(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Response, ErrorResponse> {
+    println!("Received request: /prefetch_path_leaves");
+    let request: PrefetchPathLeavesRequest = hardening::decode(&bytes)?;
+
+    let buckets = state.server2.read().await.prefetch_leaf_paths(&request.leaves)?;
+
+    streaming::response(buckets)
+}
+
+/// Read a chunk of the stored pathset. The buckets are streamed as frames, see
+/// [`streaming::framed_response`].
+pub async fn handle_chunk_read_paths(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Response, ErrorResponse> {
+    {
+        let mut count = state.write_count.lock().unwrap();
+        *count += 1;
+    }
+
+    let request: ChunkReadPathsRequest = hardening::decode(&bytes)?;
+
+    // Only Server1 reads the pathset, so the chunk is sent sparsely: its presence bitmap, then
+    // just the buckets that have been written.
+    let (epoch, indices, present) = {
+        let server2 = state.server2.read().await;
+        let indices = server2.pathset_chunk(request.chunk_idx).to_vec();
+        let mut present = Vec::with_capacity(indices.len());
+        for &index in &indices {
+            present.push(!server2.bucket_is_empty(index)?);
+        }
+        (server2.epoch, indices, present)
+    };
+    let head = SparsePathFrame::Present(indices.len() as u64, SparseBuckets::bitmap(present.iter().copied()));
+    let written: Vec<usize> = indices
+        .into_iter()
+        .zip(present)
+        .filter_map(|(index, present)| present.then_some(index))
+        .collect();
+    let count = 1 + written.len();
+    let buckets = stream_buckets_with(&state, epoch, written, |bucket| {
+        // The bitmap was taken under an earlier lock, so check the bucket still matches it.
+        if bucket.is_empty() {
+            return Err(MycoError::ProtocolError("bucket emptied during the read".to_string()));
+        }
+        Ok(SparsePathFrame::Bucket(bucket))
+    });
+    let frames = futures::stream::once(async move { Ok(head) }).chain(buckets);
+    Ok(streaming::framed_response(count, frames))
+}
+
+/// Read the buckets at the given indices for a client.
+pub async fn handle_read_paths_client(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Response, ErrorResponse> {
+    println!("Received request: /read_paths_client");
+    let request: ReadPathsClientRequest = hardening::decode(&bytes)?;
+
+    let buckets = state
+        .server2
+        .read()
+        .await
+        .read_paths_client(request.indices)
+        ?;
+
+    streaming::response(buckets)
+}
+
+/// Read a chunk of the buckets at the given indices for a client. The buckets are streamed as
+/// frames, see [`streaming::framed_response`].
+pub async fn handle_chunk_read_paths_client(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Response, ErrorResponse> {
+    println!("Received request: /chunk_read_paths_client");
+    let request: ChunkReadPathsClientRequest = hardening::decode(&bytes)?;
+
+    let epoch = {
+        let server2 = state.server2.read().await;
+        server2.count_client_read(request.chunk_idx);
+        server2.epoch
+    };
+    let indices = server2::read_chunk(&request.indices, request.chunk_idx).to_vec();
+    Ok(stream_buckets(&state, epoch, indices))
+}
+
+/// Stream the buckets at `indices`, taking the read lock for one bucket at a time so writers are
+/// never held up by a slow reader. The response is cut short if the epoch moves on meanwhile,
+/// rather than mixing buckets of two epochs.
+fn stream_buckets(state: &AppState, epoch: u64, indices: Vec<usize>) -> Response {
+    let count = indices.len();
+    streaming::framed_response(count, stream_buckets_with(state, epoch, indices, Ok))
+}
+
+/// The stream behind [`stream_buckets`], with each bucket turned into a frame by `frame`.
+fn stream_buckets_with<T>(
+    state: &AppState,
+    epoch: u64,
+    indices: Vec<usize>,
+    frame: impl Fn(Bucket) -> Result<T, MycoError> + Send + Sync + 'static,
+) -> impl Stream<Item = Result<T, MycoError>> + Send + 'static
+where
+    T: Send + 'static,
+{
+    let server2 = state.server2.clone();
+    let frame = Arc::new(frame);
+    futures::stream::iter(indices).then(move |index| {
+        let server2 = server2.clone();
+        let frame = frame.clone();
+        async move {
+            let server2 = server2.read().await;
+            if server2.epoch != epoch {
+                return Err(MycoError::ProtocolError(format!(
+                    "epoch {} ended during the read",
+                    epoch
+                )));
+            }
+            server2.bucket(index).and_then(|bucket| frame(bucket))
+        }
+    })
+}
+
+/// Write a chunk of the pathset buckets.
+///
+/// The body is decoded while it arrives. It is laid out like a [`ChunkWriteRequest`](crate::rpc_types::ChunkWriteRequest), so the
+/// fields after the buckets are decoded from the tail. When the request names its chunk in
+/// [`CHUNK_HEADER`], each bucket is moved into the tree as soon as it is decoded rather than after
+/// the whole chunk has arrived, unless Server2 keeps a journal, which records chunks whole.
+pub async fn handle_chunk_write(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Bytes, ErrorResponse> {
+    let header = chunk_header(&headers)?;
+    let journaling = state.server2.read().await.is_journaling();
+    let Some((epoch, chunk_idx)) = header.filter(|_| !journaling) else {
+        let (buckets, tail) = streaming::decode_body(body).await?;
+        let (chunk_idx, _prf_key, epoch): (usize, Key, u64) = hardening::decode(&tail)?;
+
+        state
+            .server2
+            .write()
+            .await
+            .chunk_write(epoch, buckets, chunk_idx)?;
+
+        return hardening::encode(&ChunkWriteResponse { success: true });
+    };
+
+    // Turn a stale epoch away before touching the tree.
+    state.server2.read().await.check_epoch(epoch)?;
+    let mut decoder = streaming::SeqDecoder::<Bucket>::new();
+    let mut position = 0;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| MycoError::MalformedRequest(e.to_string()))?;
+        decoder.feed(&chunk)?;
+        let mut server2 = state.server2.write().await;
+        for bucket in decoder.drain() {
+            server2.write_chunk_bucket(epoch, chunk_idx, position, bucket)?;
+            position += 1;
+        }
+    }
+    let (_, tail) = decoder.finish()?;
+    let (tail_chunk_idx, _prf_key, tail_epoch): (usize, Key, u64) = hardening::decode(&tail)?;
+    if (tail_epoch, tail_chunk_idx) != (epoch, chunk_idx) {
+        return Err(MycoError::MalformedRequest(format!(
+            "{} names chunk {}/{} but the body chunk {}/{}",
+            CHUNK_HEADER, epoch, chunk_idx, tail_epoch, tail_chunk_idx
+        ))
+        .into());
+    }
+
+    hardening::encode(&ChunkWriteResponse { success: true })
+}
+
+/// The epoch and chunk index in [`CHUNK_HEADER`], if the request has one.
+fn chunk_header(headers: &HeaderMap) -> Result<Option<(u64, usize)>, MycoError> {
+    let Some(value) = headers.get(CHUNK_HEADER) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.split_once('/'))
+        .and_then(|(epoch, chunk_idx)| Some((epoch.parse().ok()?, chunk_idx.parse().ok()?)))
+        .map(Some)
+        .ok_or_else(|| MycoError::MalformedRequest(format!("invalid {} header", CHUNK_HEADER)))
+}
+
+/// Write a chunk of the pathset buckets as deltas from the buckets held, see
+/// [`crate::dtypes::BucketDelta`]. The body is laid out like that of [`handle_chunk_write`].
+pub async fn handle_chunk_write_deltas(
+    State(state): State<AppState>,
+    body: Body,
+) -> Result<Bytes, ErrorResponse> {
+    let (deltas, tail) = streaming::decode_body(body).await?;
+    let (chunk_idx, _prf_key, epoch): (usize, Key, u64) = hardening::decode(&tail)?;
+
+    state
+        .server2
+        .write()
+        .await
+        .chunk_write_deltas(epoch, deltas, chunk_idx)?;
+
+    hardening::encode(&ChunkWriteResponse { success: true })
+}
+
+/// Finalize the epoch and publish its PRF key.
+pub async fn handle_finalize_epoch(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /finalize_epoch");
+    let request: FinalizeEpochRequest = hardening::decode(&bytes)?;
+
+    state
+        .server2
+        .write()
+        .await
+        .finalize_epoch(request.epoch, &request.prf_key)?;
+
+    hardening::encode(&FinalizeEpochResponse { success: true })
+}
+
+/// Write the full pathset and publish the epoch's PRF key.
+pub async fn handle_write(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: WriteRequest = hardening::decode(&bytes)?;
+
+    state
+        .server2
+        .write()
+        .await
+        .write(request.epoch, request.buckets, &request.prf_key)?;
+
+    hardening::encode(&WriteResponse { success: true })
+}
+
+/// Take a backup of the tree and PRF keys, in full or since an earlier backup.
+pub async fn handle_backup(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: BackupRequest = hardening::decode(&bytes)?;
+    let backup = state.server2.read().await.backup(request.since)?;
+    hardening::encode(&BackupResponse { backup })
+}
+
+/// Get the current epoch and PRF key cursor.
+pub async fn handle_epoch(State(state): State<AppState>) -> Result<Bytes, ErrorResponse> {
+    let info = state.server2.read().await.epoch_info();
+
+    hardening::encode(&GetEpochResponse { info })
+}
+
+/// Advertise the request sizes this server accepts.
+pub async fn handle_capabilities() -> Result<Bytes, ErrorResponse> {
+    hardening::encode(&GetCapabilitiesResponse {
+        capabilities: Capabilities::local(),
+    })
+}
+
+/// Get the read counts of the current and last epoch, and the storage use of the live epochs.
+pub async fn handle_stats(State(state): State<AppState>) -> Result<Bytes, ErrorResponse> {
+    let server2 = state.server2.read().await;
+
+    hardening::encode(&GetStatsResponse {
+        current: server2.read_stats(),
+        previous: server2.last_read_stats(),
+        storage: server2.storage_stats(),
+        bandwidth: server2.bandwidth_stats(),
+        previous_bandwidth: server2.bandwidth_meter().last_stats(),
+    })
+}
+
+/// Get the memory held by the bucket tree, see [`Server2::memory_stats`].
+pub async fn handle_memory(State(state): State<AppState>) -> Result<Bytes, ErrorResponse> {
+    hardening::encode(&MemoryStatsResponse {
+        stats: state.server2.read().await.memory_stats(),
+    })
+}
+
+/// Get the latencies of Server2's operations in the current and last epochs, as JSON.
+pub async fn handle_latency(State(state): State<AppState>) -> Json<LatencyBreakdown> {
+    Json(state.server2.read().await.latency_breakdown())
+}
+
+/// Get the PRF keys of the live epochs.
+pub async fn handle_get_prf_keys(State(state): State<AppState>) -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /get_prf_keys");
+
+    let keys = state
+        .server2
+        .read()
+        .await
+        .get_prf_keys()
+        ?;
+
+    hardening::encode(&GetPrfKeysResponse { keys })
+}
+
+/// Get the PRF keys published since the client's cursor.
+pub async fn handle_get_prf_keys_since(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: GetPrfKeysSinceRequest = hardening::decode(&bytes)?;
+
+    let (start, keys) = state
+        .server2
+        .read()
+        .await
+        .get_prf_keys_since(request.cursor)
+        ?;
+
+    hardening::encode(&GetPrfKeysSinceResponse { start, keys })
+}
+
+/// Store the notification index of the epoch being written.
+pub async fn handle_publish_notifications(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: PublishNotificationsRequest = hardening::decode(&bytes)?;
+
+    state
+        .server2
+        .write()
+        .await
+        .publish_notifications(request.index);
+
+    hardening::encode(&WriteResponse { success: true })
+}
+
+/// Store the storage report of the epoch being written.
+pub async fn handle_publish_storage(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: PublishStorageRequest = hardening::decode(&bytes)?;
+
+    state.server2.write().await.publish_storage(request.report);
+
+    hardening::encode(&WriteResponse { success: true })
+}
+
+/// Get the notification index of the newest epoch.
+pub async fn handle_get_notifications(State(state): State<AppState>) -> Result<Bytes, ErrorResponse> {
+    let notifications = state.server2.read().await.notifications();
+
+    hardening::encode(&GetNotificationsResponse { notifications })
+}
+
+/// Find which of the kept epochs still hold a write for one of the client's read tags.
+pub async fn handle_pending_epochs(
+    State(state): State<AppState>,
+    bytes: Bytes,
+) -> Result<Bytes, ErrorResponse> {
+    let request: PendingEpochsRequest = hardening::decode(&bytes)?;
+
+    let epochs = state.server2.read().await.pending_epochs(&request.tags)?;
+
+    hardening::encode(&PendingEpochsResponse { epochs })
+}
+
+/// Write out the averaged benchmark metrics.
+pub async fn handle_finalize_benchmark() -> Result<Bytes, ErrorResponse> {
+    println!("Received request: /finalize_benchmark");
+    #[cfg(feature = "perf-logging")]
+    crate::logging::calculate_and_append_averages("server2_latency.csv", "server2_bytes.csv");
+    Ok(Bytes::from("Benchmark finalized"))
+}
@@ -0,0 +1,249 @@
+//! Constant-rate traffic shaping
+//!
+//! Myco only hides who talks to whom if every client writes and reads at a fixed cadence,
+//! independent of what its user does: a client that only writes when it has something to say
+//! gives its conversations away through timing alone. A [`TrafficShaper`] provides that cadence on
+//! top of a [`Client`]. Time is divided into slots of a fixed length, and every slot carries
+//! exactly one write and a fixed number of reads, one by default. Messages queued with
+//! [`TrafficShaper::send`] are written in the next free slot, in order, and slots without a queued
+//! message get a fake write; likewise, reads requested with [`TrafficShaper::watch`] fill the
+//! slots' reads in order, and reads left idle are fake.
+//!
+//! A client coming back online has up to [`DELTA`](crate::constants::DELTA) epochs to catch up
+//! on, and reading them in one burst gives away that it was offline. [`TrafficShaper::catch_up`]
+//! queues those reads instead, to be spread evenly across the next slots (see
+//! [`TrafficShaper::with_catch_up_spread`]) within the same fixed read count, ahead of watched
+//! reads. Catch-up reads that don't fit the read count take longer than the spread. Finding the
+//! epochs to read takes one query of their read tags (see [`Client::pending_epochs`]).
+//!
+//! Slots are either driven by the application with [`TrafficShaper::tick`], e.g. once per epoch,
+//! or on the shaper's own clock with [`TrafficShaper::run`]. A slot that starts late doesn't make
+//! the shaper catch up with a burst: the schedule restarts from the late slot instead.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Client, ReceivedMessage},
+    dtypes::Key,
+    error::MycoError,
+};
+
+/// A read queued with [`TrafficShaper::watch`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadRequest {
+    /// The contact key to read under.
+    pub contact: Key,
+    /// The ID of the client that wrote the message.
+    pub cs: String,
+    /// The epoch the message was written in.
+    pub epoch: usize,
+}
+
+/// What a slot carried.
+#[derive(Debug)]
+pub struct Slot {
+    /// The contact a queued message was written to, or `None` if the slot had a fake write.
+    pub written: Option<Key>,
+    /// The queued reads and their results, catch-up reads first, after those of earlier slots that
+    /// failed. The slot's other reads were fake.
+    pub reads: Vec<(ReadRequest, Result<ReceivedMessage, MycoError>)>,
+}
+
+/// Schedules a client's writes and reads at one of each per slot.
+pub struct TrafficShaper {
+    client: Client,
+    /// Length of a slot.
+    slot: Duration,
+    /// Messages waiting for a slot, with the contact they go to.
+    writes: VecDeque<(Vec<u8>, Key)>,
+    /// Reads waiting for a slot.
+    reads: VecDeque<ReadRequest>,
+    /// Number of reads in every slot, real or fake.
+    reads_per_slot: usize,
+    /// Number of slots catch-up reads are spread across.
+    catch_up_spread: usize,
+    /// Catch-up reads waiting for a slot.
+    catch_up: VecDeque<ReadRequest>,
+    /// Slots left to spread the waiting catch-up reads across.
+    catch_up_slots: usize,
+    /// When the next slot starts, once `run` has started the clock.
+    next_slot: Option<Instant>,
+    /// Reads carried out in slots whose write failed, reported with the next slot that succeeds.
+    unreported: Vec<(ReadRequest, Result<ReceivedMessage, MycoError>)>,
+}
+
+impl TrafficShaper {
+    /// Shape the traffic of `client` into slots of length `slot`.
+    pub fn new(client: Client, slot: Duration) -> Self {
+        Self {
+            client,
+            slot,
+            writes: VecDeque::new(),
+            reads: VecDeque::new(),
+            reads_per_slot: 1,
+            catch_up_spread: 1,
+            catch_up: VecDeque::new(),
+            catch_up_slots: 0,
+            next_slot: None,
+            unreported: Vec::new(),
+        }
+    }
+
+    /// Carry `reads` reads in every slot instead of one. All clients sharing an anonymity set
+    /// should use the same count.
+    pub fn with_reads_per_slot(mut self, reads: usize) -> Self {
+        self.reads_per_slot = reads.max(1);
+        self
+    }
+
+    /// Spread the reads queued by [`TrafficShaper::catch_up`] across `slots` slots instead of
+    /// doing them as fast as the read count allows.
+    pub fn with_catch_up_spread(mut self, slots: usize) -> Self {
+        self.catch_up_spread = slots.max(1);
+        self
+    }
+
+    /// The shaped client.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Stop shaping and return the client. Queued writes and reads are dropped.
+    pub fn into_client(self) -> Client {
+        self.client
+    }
+
+    /// Length of a slot.
+    pub fn slot_length(&self) -> Duration {
+        self.slot
+    }
+
+    /// Number of messages waiting for a slot.
+    pub fn pending_writes(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Number of reads waiting for a slot, catch-up reads included.
+    pub fn pending_reads(&self) -> usize {
+        self.reads.len() + self.catch_up.len()
+    }
+
+    /// Queue `msg` for contact `k`. It's written in the first slot not taken by an earlier message.
+    pub fn send(&mut self, msg: &[u8], k: &Key) -> Result<(), MycoError> {
+        if !self.client.keys.contains_key(k) {
+            return Err(MycoError::UnknownContact);
+        }
+        self.writes.push_back((msg.to_vec(), k.clone()));
+        Ok(())
+    }
+
+    /// Queue a read of the message `cs` wrote to contact `k` in `epoch`. The read waits until the
+    /// client is past `epoch`.
+    pub fn watch(&mut self, k: &Key, cs: String, epoch: usize) {
+        self.reads.push_back(ReadRequest {
+            contact: k.clone(),
+            cs,
+            epoch,
+        });
+    }
+
+    /// Queue reads of everything `contacts` wrote in the epochs the client missed, each contact
+    /// given as a contact key with the ID of the client writing to it, to be spread across the
+    /// next slots. Every contact is read in each epoch that holds a write of one of them, so the
+    /// reads don't tell which of them wrote. Returns the number of reads queued.
+    pub fn catch_up(&mut self, contacts: &[(Key, String)]) -> Result<usize, MycoError> {
+        let pending = futures::executor::block_on(self.client.pending_epochs(contacts))?;
+        let epoch = self.client.epoch;
+        for epoch_past in &pending {
+            for (k, cs) in contacts {
+                self.catch_up.push_back(ReadRequest {
+                    contact: k.clone(),
+                    cs: cs.clone(),
+                    epoch: epoch - 1 - epoch_past,
+                });
+            }
+        }
+        self.catch_up_slots = self.catch_up_spread;
+        Ok(pending.len() * contacts.len())
+    }
+
+    /// Carry out one slot: its reads, of the catch-up reads due and the oldest queued reads, with
+    /// fake ones for the rest, then a write, of the oldest queued message or a fake one. If the
+    /// slot fails, the error is returned and a failed message stays queued, while the reads already
+    /// made are reported with the next slot rather than read again.
+    pub fn tick(&mut self) -> Result<Slot, MycoError> {
+        // Catch-up reads get an even share of the slots left to spread them across.
+        let catch_up = self
+            .catch_up
+            .len()
+            .div_ceil(self.catch_up_slots.max(1))
+            .min(self.reads_per_slot);
+        let mut requests: Vec<_> = self.catch_up.drain(..catch_up).collect();
+        self.catch_up_slots = self.catch_up_slots.saturating_sub(1);
+        // Reads of epochs that haven't been written yet wait for a later slot. The reads come
+        // first, so they see the batch written since the previous slot.
+        let epoch = self.client.epoch;
+        while requests.len() < self.reads_per_slot
+            && self.reads.front().is_some_and(|request| request.epoch < epoch)
+        {
+            requests.push(self.reads.pop_front().unwrap());
+        }
+
+        let reads = requests.len();
+        for request in requests {
+            let epoch_past = epoch - 1 - request.epoch;
+            let result = self
+                .client
+                .read(&request.contact, request.cs.clone(), epoch_past);
+            self.unreported.push((request, result));
+        }
+        for _ in reads..self.reads_per_slot {
+            self.client.fake_read()?;
+        }
+
+        let written = match self.writes.pop_front() {
+            Some((msg, k)) => match self.client.write(&msg, &k) {
+                Ok(()) => Some(k),
+                Err(e) => {
+                    self.writes.push_front((msg, k));
+                    return Err(e);
+                }
+            },
+            None => {
+                self.client.fake_write()?;
+                None
+            }
+        };
+
+        Ok(Slot {
+            written,
+            reads: std::mem::take(&mut self.unreported),
+        })
+    }
+
+    /// Carry out `slots` slots on the shaper's own clock, handing each to `on_slot`. The first
+    /// slot starts right away, or when the previous call's schedule says so.
+    pub fn run(
+        &mut self,
+        slots: usize,
+        mut on_slot: impl FnMut(Slot),
+    ) -> Result<(), MycoError> {
+        for _ in 0..slots {
+            let now = Instant::now();
+            let start = match self.next_slot {
+                Some(next) if next > now => {
+                    std::thread::sleep(next - now);
+                    next
+                }
+                // Late or first slot: restart the schedule from now rather than bursting.
+                _ => now,
+            };
+            self.next_slot = Some(start + self.slot);
+            on_slot(self.tick()?);
+        }
+        Ok(())
+    }
+}
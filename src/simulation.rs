@@ -0,0 +1,75 @@
+//! Simulation mode
+//!
+//! Simulations of large deployments skip message encryption so that they measure bucket usage and
+//! the data path rather than AES. [`SimulationMode`] is a runtime setting on [`Server1`] and
+//! [`Client`] that turns encryption off: blocks are padded but stored in the clear, and Server1
+//! neither pads buckets with random blocks nor shuffles them.
+//!
+//! A deployment must never run this way by accident, so turning encryption off takes both a call
+//! to [`SimulationMode::no_encryption`] and [`SIMULATION_MODE_ENV`] set to `no-enc` in the
+//! process environment. Anything else is [`SimulationMode::Off`], the default.
+//!
+//! [`Server1`]: crate::server1::Server1
+//! [`Client`]: crate::client::Client
+
+use crate::{
+    crypto::{self, EncryptionType},
+    error::MycoError,
+};
+
+/// Environment variable that has to be set to `no-enc` for [`SimulationMode::no_encryption`] to
+/// succeed.
+pub const SIMULATION_MODE_ENV: &str = "MYCO_SIMULATION_MODE";
+
+/// Whether messages are really encrypted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimulationMode {
+    /// Messages are encrypted, as in any deployment.
+    #[default]
+    Off,
+    /// Messages are padded but not encrypted. Only constructed by
+    /// [`SimulationMode::no_encryption`].
+    #[non_exhaustive]
+    NoEncryption {},
+}
+
+impl SimulationMode {
+    /// The mode without encryption, if [`SIMULATION_MODE_ENV`] opts into it.
+    pub fn no_encryption() -> Result<Self, MycoError> {
+        match std::env::var(SIMULATION_MODE_ENV) {
+            Ok(value) if value == "no-enc" => Ok(SimulationMode::NoEncryption {}),
+            _ => Err(MycoError::ConfigError(format!(
+                "simulation without encryption requires {}=no-enc",
+                SIMULATION_MODE_ENV
+            ))),
+        }
+    }
+
+    /// Whether messages are encrypted.
+    pub fn encrypts(self) -> bool {
+        self == SimulationMode::Off
+    }
+
+    /// [`crypto::encrypt`], or just the padding of it when encryption is off.
+    pub fn encrypt(
+        self,
+        key: &[u8],
+        message: &[u8],
+        encryption_type: EncryptionType,
+    ) -> Result<Vec<u8>, MycoError> {
+        if self.encrypts() {
+            crypto::encrypt(key, message, encryption_type)
+        } else {
+            Ok(crypto::pad_plaintext(message, encryption_type))
+        }
+    }
+
+    /// [`crypto::decrypt`], or the ciphertext as it is when encryption is off.
+    pub fn decrypt(self, key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, MycoError> {
+        if self.encrypts() {
+            crypto::decrypt(key, ciphertext)
+        } else {
+            Ok(ciphertext.to_vec())
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Server1 standby replication
+//!
+//! Server1 keeps state that exists nowhere else: the metadata tree, which holds the path, key and
+//! expiry of every live block and so is needed to carry messages forward for DELTA epochs, the
+//! writes queued in the open epoch, the epoch counter and the epoch key k_s1_t. A primary S1 set
+//! up with [`Server1::set_replica`](crate::server1::Server1::set_replica) streams changes to this
+//! state to a standby as [`ReplicationEvent`]s. The standby keeps them in its [`Standby`] and,
+//! should the primary crash, takes the open epoch over with
+//! [`Server1::take_over`](crate::server1::Server1::take_over), at `/admin/failover`.
+//!
+//! The epoch key is only replicated sealed under a key shared by both servers
+//! ([`REPLICATION_KEY_ENV`]), and only unsealed by a standby taking over. Replication is
+//! asynchronous: events are sent in order in the background, so the writes queued in the last
+//! moments before a crash may not have reached the standby. A standby that misses events, e.g.
+//! because it restarted, is sent a fresh [`ReplicationEvent::Snapshot`] at the next batch_init.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    admin::ADMIN_TOKEN_ENV,
+    constants::LAMBDA,
+    dtypes::{Key, Metadata, Path},
+    error::MycoError,
+    tls,
+    tree::{BinaryTree, SparseBinaryTree},
+};
+
+/// Environment variable holding the base URL of the standby a primary S1 replicates to.
+pub const STANDBY_ADDR_ENV: &str = "MYCO_STANDBY_ADDR";
+
+/// Environment variable holding the admin token of the standby at [`STANDBY_ADDR_ENV`], by
+/// default this server's own [`ADMIN_TOKEN_ENV`].
+pub const STANDBY_TOKEN_ENV: &str = "MYCO_STANDBY_TOKEN";
+
+/// Environment variable holding the hex-encoded key the epoch key is sealed under for the
+/// standby. Primary and standby must be given the same key.
+pub const REPLICATION_KEY_ENV: &str = "MYCO_REPLICATION_KEY";
+
+/// Most events sent to the standby in one request.
+const MAX_EVENTS_PER_REQUEST: usize = 1024;
+
+/// How often a request to the standby is retried before it is resynchronized with a snapshot.
+const REPLICATION_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled for each one after.
+const REPLICATION_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A write queued in the open epoch, as the standby re-queues it on taking over.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueuedWrite {
+    /// The message ciphertext.
+    pub ct: Vec<u8>,
+    /// The oblivious key the block is sealed under.
+    pub k_oblv_t: Key,
+    /// The path the write was placed on, `prf(k_s1_t, f || cs)`.
+    pub path: Path,
+    /// The write's notification tag.
+    pub tag: Vec<u8>,
+}
+
+/// A change to the state of a primary S1.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReplicationEvent {
+    /// The full state of the primary between epochs, which the events after it build on.
+    Snapshot {
+        /// The epoch about to be opened.
+        epoch: u64,
+        /// The metadata tree as of the last completed epoch.
+        metadata: BinaryTree<Metadata>,
+    },
+    /// A batch was initialized for `epoch`.
+    BatchOpened {
+        /// The epoch opened.
+        epoch: u64,
+        /// The epoch key, sealed under the replication key.
+        sealed_key: Vec<u8>,
+    },
+    /// A write was queued in the open epoch.
+    WriteQueued(QueuedWrite),
+    /// `epoch` was written to Server2, moving the metadata tree on by `metadata`.
+    BatchWritten {
+        /// The epoch written.
+        epoch: u64,
+        /// The metadata of the epoch's pathset.
+        metadata: SparseBinaryTree<Metadata>,
+    },
+    /// The batch write of the open epoch was aborted and its writes dropped.
+    BatchAborted,
+}
+
+/// The state of a primary S1 as replicated to a standby.
+#[derive(Clone, Debug)]
+pub struct Standby {
+    /// Whether a snapshot was received, without which the other events have nothing to apply to.
+    synced: bool,
+    /// The open epoch, or the next one to be opened.
+    pub epoch: u64,
+    /// The metadata tree as of the last completed epoch.
+    pub metadata: BinaryTree<Metadata>,
+    /// The sealed epoch key of the open epoch, if one is open.
+    pub sealed_key: Option<Vec<u8>>,
+    /// The writes queued in the open epoch.
+    pub writes: Vec<QueuedWrite>,
+}
+
+impl Default for Standby {
+    fn default() -> Self {
+        Self {
+            synced: false,
+            epoch: 0,
+            metadata: BinaryTree { value: vec![] },
+            sealed_key: None,
+            writes: vec![],
+        }
+    }
+}
+
+impl Standby {
+    /// Whether the standby holds the primary's state, i.e. received a snapshot.
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Apply `event` from the primary.
+    pub fn apply(&mut self, event: ReplicationEvent) -> Result<(), MycoError> {
+        if !self.synced && !matches!(event, ReplicationEvent::Snapshot { .. }) {
+            return Err(MycoError::ProtocolError(
+                "the standby hasn't received a snapshot".to_string(),
+            ));
+        }
+        match event {
+            ReplicationEvent::Snapshot { epoch, metadata } => {
+                *self = Self {
+                    synced: true,
+                    epoch,
+                    metadata,
+                    sealed_key: None,
+                    writes: vec![],
+                };
+            }
+            ReplicationEvent::BatchOpened { epoch, sealed_key } => {
+                self.epoch = epoch;
+                self.sealed_key = Some(sealed_key);
+                self.writes.clear();
+            }
+            ReplicationEvent::WriteQueued(write) => self.writes.push(write),
+            ReplicationEvent::BatchWritten { epoch, metadata } => {
+                self.metadata.overwrite_from_sparse(&metadata);
+                self.epoch = epoch + 1;
+                self.sealed_key = None;
+                self.writes.clear();
+            }
+            ReplicationEvent::BatchAborted => {
+                self.sealed_key = None;
+                self.writes.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a primary S1 sends its [`ReplicationEvent`]s.
+pub trait Replica: Send + Sync {
+    /// Send `event`, after all events sent before it. Must not block.
+    fn replicate(&self, event: ReplicationEvent);
+
+    /// Whether the replica has to be sent a [`ReplicationEvent::Snapshot`] before further events.
+    fn needs_snapshot(&self) -> bool;
+}
+
+/// A standby in the same process.
+pub struct LocalReplica {
+    /// The state replicated so far.
+    pub standby: Arc<Mutex<Standby>>,
+}
+
+impl Replica for LocalReplica {
+    fn replicate(&self, event: ReplicationEvent) {
+        let mut standby = self.standby.lock().unwrap();
+        if let Err(e) = standby.apply(event) {
+            println!("Standby: Error applying a replication event: {}", e);
+        }
+    }
+
+    fn needs_snapshot(&self) -> bool {
+        !self.standby.lock().unwrap().is_synced()
+    }
+}
+
+/// A standby S1 reached at its `/admin/replicate` route. Events are sent from a background task
+/// on the runtime it was created on.
+pub struct RemoteReplica {
+    events: UnboundedSender<ReplicationEvent>,
+    needs_snapshot: Arc<AtomicBool>,
+}
+
+impl RemoteReplica {
+    /// Replicate to the standby at `base_url`, authenticating with its admin `token`.
+    pub fn new(base_url: &str, token: String) -> Result<Self, MycoError> {
+        let (builder, base_url) = tls::client_trust().http_client_builder(base_url)?;
+        let client = builder
+            .build()
+            .map_err(|e| MycoError::ConfigError(format!("building the standby client: {}", e)))?;
+        let (events, receiver) = mpsc::unbounded_channel();
+        let needs_snapshot = Arc::new(AtomicBool::new(true));
+        tokio::spawn(send_events(
+            client,
+            format!("{}/admin/replicate", base_url),
+            token,
+            receiver,
+            needs_snapshot.clone(),
+        ));
+        Ok(Self {
+            events,
+            needs_snapshot,
+        })
+    }
+}
+
+impl Replica for RemoteReplica {
+    fn replicate(&self, event: ReplicationEvent) {
+        // The task only stops with the runtime, so there's nothing to do if it's gone.
+        let _ = self.events.send(event);
+    }
+
+    fn needs_snapshot(&self) -> bool {
+        self.needs_snapshot.load(Ordering::SeqCst)
+    }
+}
+
+/// Send the events of a [`RemoteReplica`] in order. When the standby can't be reached or rejects
+/// them, events are dropped up to the next snapshot, which is then asked for.
+async fn send_events(
+    client: reqwest::Client,
+    url: String,
+    token: String,
+    mut receiver: UnboundedReceiver<ReplicationEvent>,
+    needs_snapshot: Arc<AtomicBool>,
+) {
+    let mut resyncing = true;
+    while let Some(event) = receiver.recv().await {
+        let mut events = vec![event];
+        while events.len() < MAX_EVENTS_PER_REQUEST {
+            match receiver.try_recv() {
+                Ok(event) => events.push(event),
+                Err(_) => break,
+            }
+        }
+        if resyncing {
+            match events
+                .iter()
+                .rposition(|event| matches!(event, ReplicationEvent::Snapshot { .. }))
+            {
+                Some(snapshot) => {
+                    events.drain(..snapshot);
+                    resyncing = false;
+                    needs_snapshot.store(false, Ordering::SeqCst);
+                }
+                None => continue,
+            }
+        }
+        if let Err(e) = post_events(&client, &url, &token, &events).await {
+            tracing::error!("Replicating to the standby failed, resynchronizing: {}", e);
+            resyncing = true;
+            needs_snapshot.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Post `events` to the standby, retrying transient failures.
+async fn post_events(
+    client: &reqwest::Client,
+    url: &str,
+    token: &str,
+    events: &[ReplicationEvent],
+) -> Result<(), MycoError> {
+    let body = bincode::serialize(events).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+    let mut backoff = REPLICATION_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let result = client
+            .post(url)
+            .bearer_auth(token)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => return Ok(()),
+            // The standby is missing state the events build on, so retrying won't help.
+            Err(e) if e.status() == Some(reqwest::StatusCode::CONFLICT) => {
+                return Err(MycoError::transport("replicate", e))
+            }
+            Err(e) if attempt == REPLICATION_RETRIES => {
+                return Err(MycoError::transport("replicate", e))
+            }
+            Err(_) => {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// The key the epoch key is sealed under for the standby, from [`REPLICATION_KEY_ENV`].
+pub fn replication_key_from_env() -> Result<Option<Key>, MycoError> {
+    let Ok(value) = std::env::var(REPLICATION_KEY_ENV) else {
+        return Ok(None);
+    };
+    match hex::decode(value.trim()) {
+        Ok(key) if key.len() == LAMBDA / 8 => Ok(Some(Key::new(key))),
+        _ => Err(MycoError::ConfigError(format!(
+            "{} must be {} hex-encoded bytes",
+            REPLICATION_KEY_ENV,
+            LAMBDA / 8
+        ))),
+    }
+}
+
+/// The standby to replicate to, from [`STANDBY_ADDR_ENV`] and [`STANDBY_TOKEN_ENV`]. Must be
+/// called on a runtime.
+pub fn standby_from_env() -> Result<Option<RemoteReplica>, MycoError> {
+    let Ok(addr) = std::env::var(STANDBY_ADDR_ENV) else {
+        return Ok(None);
+    };
+    let token = std::env::var(STANDBY_TOKEN_ENV)
+        .or_else(|_| std::env::var(ADMIN_TOKEN_ENV))
+        .map_err(|_| {
+            MycoError::ConfigError(format!(
+                "{} requires {} or {}",
+                STANDBY_ADDR_ENV, STANDBY_TOKEN_ENV, ADMIN_TOKEN_ENV
+            ))
+        })?;
+    RemoteReplica::new(&addr, token).map(Some)
+}
@@ -0,0 +1,96 @@
+//! StatsD metric sink
+//!
+//! [`StatsdSink`] sends every latency and bytes metric as a StatsD datagram over UDP, for
+//! monitoring stacks fed by StatsD or Graphite. Latencies are sent as timers
+//! (`<prefix>.<operation>:<ms>|ms`) and byte counts as counters (`<prefix>.<operation>:<bytes>|c`).
+//! Sending is best effort: a datagram that can't be sent is dropped rather than holding up the
+//! server.
+//!
+//! The server binaries send to the address in [`STATSD_ADDR_ENV`] if it is set, see
+//! [`metrics_sink_from_env`].
+
+use std::{
+    net::{ToSocketAddrs, UdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    error::MycoError,
+    logging::{self, FanoutSink, MetricsSink, PerfLog},
+};
+
+/// Environment variable holding the `host:port` of the StatsD daemon to send metrics to.
+pub const STATSD_ADDR_ENV: &str = "MYCO_STATSD_ADDR";
+
+/// Environment variable overriding the prefix of every metric name, [`DEFAULT_PREFIX`] by default.
+pub const STATSD_PREFIX_ENV: &str = "MYCO_STATSD_PREFIX";
+
+/// Prefix of every metric name when [`STATSD_PREFIX_ENV`] isn't set.
+pub const DEFAULT_PREFIX: &str = "myco";
+
+/// A [`MetricsSink`] sending metrics to a StatsD daemon over UDP.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdSink {
+    /// Create a sink sending to the daemon at `addr`, naming metrics `<prefix>.<operation>`.
+    pub fn new(addr: &str, prefix: &str) -> Result<Self, MycoError> {
+        let target = addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| MycoError::ConfigError(format!("invalid {} {}", STATSD_ADDR_ENV, addr)))?;
+        let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Create a sink from [`STATSD_ADDR_ENV`] and [`STATSD_PREFIX_ENV`], or `None` if no address is
+    /// set.
+    pub fn from_env() -> Result<Option<Self>, MycoError> {
+        let Ok(addr) = std::env::var(STATSD_ADDR_ENV) else {
+            return Ok(None);
+        };
+        let prefix = std::env::var(STATSD_PREFIX_ENV).unwrap_or_else(|_| DEFAULT_PREFIX.to_string());
+        Self::new(&addr, &prefix).map(Some)
+    }
+
+    fn send(&self, operation: &str, value: &str, kind: &str) {
+        let datagram = format!("{}.{}:{}|{}", self.prefix, operation, value, kind);
+        if let Err(e) = self.socket.send(datagram.as_bytes()) {
+            tracing::debug!("dropped statsd metric {}: {}", operation, e);
+        }
+    }
+}
+
+impl MetricsSink for StatsdSink {
+    fn record_latency(&self, operation: &str, latency: Duration) {
+        self.send(operation, &format!("{:.3}", latency.as_secs_f64() * 1000.0), "ms");
+    }
+
+    fn record_bytes(&self, operation: &str, bytes: usize) {
+        self.send(operation, &bytes.to_string(), "c");
+    }
+}
+
+/// The sink the server binaries report to: [`PerfLog`], and a [`StatsdSink`] as well if
+/// [`STATSD_ADDR_ENV`] is set. The StatsD sink is also installed for the code paths timed by hand
+/// (see [`logging::install_sink`]), so it sees every metric.
+pub fn metrics_sink_from_env() -> Result<Arc<dyn MetricsSink>, MycoError> {
+    Ok(match StatsdSink::from_env()? {
+        Some(statsd) => {
+            let statsd: Arc<dyn MetricsSink> = Arc::new(statsd);
+            logging::install_sink(statsd.clone());
+            Arc::new(FanoutSink(vec![Arc::new(PerfLog), statsd]))
+        }
+        None => Arc::new(PerfLog),
+    })
+}
@@ -0,0 +1,162 @@
+//! Client message store
+//!
+//! Clients record every message they have delivered in a [`MessageStore`], so reading an
+//! overlapping range of epochs (e.g. after a crash) doesn't hand the same message to the
+//! application twice. A message is identified by its contact key, sender, epoch and envelope
+//! sequence number; the epoch is part of the identity so a sender that restarts its sequence
+//! numbers is never mistaken for a duplicate. Entries are pruned once their message has expired
+//! from the tree, since it can no longer be read again.
+//!
+//! The store also keeps the client's place in every conversation: the sequence number expected
+//! next from each sender, so a message arriving with a later one reveals the messages missed in
+//! between (see [`crate::sequence`]), and the sequence number of the next envelope the client
+//! writes to each contact. Both survive a restart with the store, so a restarted sender doesn't
+//! start again from 0 below what its readers already expect.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path as FsPath, PathBuf},
+};
+
+use crate::{constants::DELTA, dtypes::Key, error::MycoError, sequence::SequenceTracker};
+
+/// Environment variable naming the file `rpc_client` persists its message store to.
+pub const STORE_PATH_ENV: &str = "MYCO_CLIENT_STORE_PATH";
+
+/// Identity of a delivered message: contact key, sender ID, epoch and sequence number.
+type MessageId = (Key, Vec<u8>, usize, u64);
+
+/// The store as persisted: delivered messages, the sequence numbers expected from each sender and
+/// the next sequence number written to each contact.
+type StoreFile = (Vec<MessageId>, SequenceTracker, Vec<(Key, u64)>);
+
+/// Record of the messages a client has delivered, optionally persisted to a file.
+#[derive(Debug, Default)]
+pub struct MessageStore {
+    /// Delivered messages.
+    delivered: HashSet<MessageId>,
+    /// Next sequence number expected from each sender. Unlike delivered messages these are never
+    /// pruned, so gaps are still spotted after a sender has been quiet for a while.
+    received: SequenceTracker,
+    /// Sequence number of the next envelope written to each contact.
+    sent: HashMap<Key, u64>,
+    /// File the store is persisted to after every change, if any.
+    path: Option<PathBuf>,
+}
+
+impl MessageStore {
+    /// Create a store kept only in memory.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Open the store persisted at `path`, or start an empty one there if the file doesn't exist.
+    pub fn open(path: &FsPath) -> Result<Self, MycoError> {
+        let (delivered, received, sent): StoreFile = match fs::read(path) {
+            // Stores written before sequence numbers were kept hold only the delivered messages.
+            Ok(bytes) => bincode::deserialize(&bytes).or_else(|_| {
+                bincode::deserialize(&bytes)
+                    .map(|ids| (ids, SequenceTracker::default(), Vec::new()))
+                    .map_err(|e| MycoError::DeserializationError(Some(e)))
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(err) => return Err(MycoError::IoError(err)),
+        };
+        Ok(Self {
+            delivered: delivered.into_iter().collect(),
+            received,
+            sent: sent.into_iter().collect(),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Number of messages recorded.
+    pub fn len(&self) -> usize {
+        self.delivered.len()
+    }
+
+    /// Whether no messages are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.delivered.is_empty()
+    }
+
+    /// Whether a message has already been delivered.
+    pub fn contains(&self, k: &Key, cs: &[u8], epoch: usize, sequence: u64) -> bool {
+        self.delivered
+            .contains(&(k.clone(), cs.to_vec(), epoch, sequence))
+    }
+
+    /// Record a message as delivered, pruning messages that have expired by `epoch`. Returns
+    /// `false` if it had already been delivered.
+    pub fn insert(
+        &mut self,
+        k: &Key,
+        cs: &[u8],
+        epoch: usize,
+        sequence: u64,
+    ) -> Result<bool, MycoError> {
+        let id = (k.clone(), cs.to_vec(), epoch, sequence);
+        if !self.delivered.insert(id) {
+            return Ok(false);
+        }
+        self.prune(epoch);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Forget every message delivered from contact `k`, along with the sequence numbers kept
+    /// for it.
+    pub fn forget(&mut self, k: &Key) -> Result<(), MycoError> {
+        self.delivered.retain(|(contact, _, _, _)| contact != k);
+        self.received.forget(k);
+        self.sent.remove(k);
+        self.save()
+    }
+
+    /// The next sequence number expected from sender `cs` writing to contact `k`, see
+    /// [`SequenceTracker::expected`].
+    pub fn expected(&self, k: &Key, cs: &[u8]) -> u64 {
+        self.received.expected(k, cs)
+    }
+
+    /// Move the sequence number expected from sender `cs` writing to contact `k` past `sequence`,
+    /// returning the one expected before, see [`SequenceTracker::advance`].
+    pub fn advance(&mut self, k: &Key, cs: &[u8], sequence: u64) -> Result<u64, MycoError> {
+        let before = self.received.advance(k, cs, sequence);
+        if before <= sequence {
+            self.save()?;
+        }
+        Ok(before)
+    }
+
+    /// Take the sequence number of the next envelope written to contact `k`.
+    pub fn next_sequence(&mut self, k: &Key) -> Result<u64, MycoError> {
+        let next = self.sent.entry(k.clone()).or_default();
+        let sequence = *next;
+        *next += 1;
+        self.save()?;
+        Ok(sequence)
+    }
+
+    /// Drop messages written more than `DELTA` epochs before `epoch`.
+    fn prune(&mut self, epoch: usize) {
+        let oldest = epoch.saturating_sub(DELTA);
+        self.delivered
+            .retain(|(_, _, message_epoch, _)| *message_epoch >= oldest);
+    }
+
+    /// Write the store to its file, through a temporary file so a crash never truncates it.
+    fn save(&self) -> Result<(), MycoError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let ids: Vec<&MessageId> = self.delivered.iter().collect();
+        let sent: Vec<(&Key, &u64)> = self.sent.iter().collect();
+        let bytes = bincode::serialize(&(ids, &self.received, sent))
+            .map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, bytes).map_err(MycoError::IoError)?;
+        fs::rename(&tmp_path, path).map_err(MycoError::IoError)
+    }
+}
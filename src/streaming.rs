@@ -0,0 +1,306 @@
+//! Streaming bincode bodies
+//!
+//! Chunked pathset writes and path reads move multi-megabyte bucket lists. Buffering such a body
+//! and then decoding it holds the encoded and the decoded buckets at the same time, so these
+//! routes encode and decode the list one item at a time instead. The wire format is unchanged: a
+//! streamed body is the bincode encoding of a struct whose first field is the `Vec` being
+//! streamed, and whatever follows the last item (the encoding of the remaining fields) is handed
+//! back as the tail.
+//!
+//! Chunked pathset writes that name their chunk in [`crate::transport::CHUNK_HEADER`] don't
+//! gather their buckets either: Server2 moves each bucket into the tree as soon as it is decoded.
+//!
+//! Chunked path reads go further and don't gather their buckets before responding. Their
+//! responses are sent with chunked transfer encoding as a `u64` item count followed by one frame
+//! per item, each a `u32` length and the bincode encoding of the item, so Server2 only holds the
+//! bucket it is sending and the reader gets every bucket as soon as its frame is complete.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    response::{IntoResponse, Response},
+};
+use futures::{stream, Stream, StreamExt};
+use http_body::{Frame, SizeHint};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::MycoError, rpc_types::ErrorResponse};
+
+/// Size of the bincode length prefix of a `Vec`, and of the item count of a framed body.
+const LEN_PREFIX_SIZE: usize = 8;
+
+/// Size of the length prefix of a frame.
+const FRAME_PREFIX_SIZE: usize = 4;
+
+/// Incrementally decodes a bincode `Vec<T>` from the chunks of a body as they arrive.
+///
+/// Only the bytes of the item being decoded are buffered; complete items are moved out of the
+/// buffer as soon as they can be decoded.
+pub struct SeqDecoder<T> {
+    buf: Vec<u8>,
+    remaining: Option<u64>,
+    items: Vec<T>,
+}
+
+impl<T: DeserializeOwned> SeqDecoder<T> {
+    /// Create a decoder waiting for the length prefix.
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            remaining: None,
+            items: Vec::new(),
+        }
+    }
+
+    /// Decode as many items as the received bytes allow.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), MycoError> {
+        self.buf.extend_from_slice(data);
+        let mut pos = 0;
+        loop {
+            let remaining = match self.remaining {
+                Some(remaining) => remaining,
+                None if self.buf.len() - pos < LEN_PREFIX_SIZE => break,
+                None => {
+                    let prefix = &self.buf[pos..pos + LEN_PREFIX_SIZE];
+                    pos += LEN_PREFIX_SIZE;
+                    let len = u64::from_le_bytes(prefix.try_into().unwrap());
+                    self.remaining = Some(len);
+                    len
+                }
+            };
+            if remaining == 0 {
+                break;
+            }
+
+            let mut reader = &self.buf[pos..];
+            match bincode::deserialize_from::<_, T>(&mut reader) {
+                Ok(item) => {
+                    pos = self.buf.len() - reader.len();
+                    self.items.push(item);
+                    self.remaining = Some(remaining - 1);
+                }
+                // The item isn't complete yet.
+                Err(e) if is_eof(&e) => break,
+                Err(e) => return Err(MycoError::DeserializationError(Some(e))),
+            }
+        }
+        self.buf.drain(..pos);
+        Ok(())
+    }
+
+    /// Take the items decoded so far, so a caller applying them as they arrive holds at most one
+    /// received chunk's worth.
+    pub fn drain(&mut self) -> std::vec::Drain<'_, T> {
+        self.items.drain(..)
+    }
+
+    /// Return the decoded items and the bytes following them. Fails if the body ended before the
+    /// last item.
+    pub fn finish(self) -> Result<(Vec<T>, Vec<u8>), MycoError> {
+        if self.remaining != Some(0) {
+            return Err(MycoError::DeserializationError(None));
+        }
+        Ok((self.items, self.buf))
+    }
+}
+
+impl<T: DeserializeOwned> Default for SeqDecoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_eof(e: &bincode::Error) -> bool {
+    matches!(&**e, bincode::ErrorKind::Io(io) if io.kind() == std::io::ErrorKind::UnexpectedEof)
+}
+
+/// Decode a streamed request body into its items and tail.
+pub async fn decode_body<T: DeserializeOwned>(body: Body) -> Result<(Vec<T>, Vec<u8>), MycoError> {
+    let mut decoder = SeqDecoder::new();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| MycoError::MalformedRequest(e.to_string()))?;
+        decoder.feed(&chunk)?;
+    }
+    decoder.finish()
+}
+
+/// A response streaming `items`, laid out like a response struct whose only field is the `Vec`.
+pub fn response<T>(items: Vec<T>) -> Result<Response, ErrorResponse>
+where
+    T: Serialize + Send + Sync + 'static,
+{
+    Ok(Body::new(SeqBody::new(items, Vec::new())?).into_response())
+}
+
+/// A body encoding a `Vec<T>` one item per frame, followed by a tail.
+///
+/// The total length is computed up front, so the body reports an exact size and is sent with a
+/// `Content-Length`.
+///
+/// The items are shared rather than consumed, so a failed request can be resent from the same
+/// items without copying them.
+pub struct SeqBody<T> {
+    head: Option<Bytes>,
+    items: Arc<Vec<T>>,
+    next: usize,
+    tail: Option<Bytes>,
+    remaining: u64,
+}
+
+impl<T: Serialize> SeqBody<T> {
+    /// Stream `items`, followed by the already encoded `tail`.
+    pub fn new(items: impl Into<Arc<Vec<T>>>, tail: Vec<u8>) -> Result<Self, MycoError> {
+        let items = items.into();
+        let mut size = (LEN_PREFIX_SIZE + tail.len()) as u64;
+        for item in items.iter() {
+            size += bincode::serialized_size(item).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+        }
+        Ok(Self {
+            head: Some(Bytes::copy_from_slice(&(items.len() as u64).to_le_bytes())),
+            items,
+            next: 0,
+            tail: Some(Bytes::from(tail)).filter(|tail| !tail.is_empty()),
+            remaining: size,
+        })
+    }
+}
+
+impl<T: Serialize> HttpBody for SeqBody<T> {
+    type Data = Bytes;
+    type Error = MycoError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, MycoError>>> {
+        let this = self.get_mut();
+        let data = if let Some(head) = this.head.take() {
+            head
+        } else if let Some(item) = this.items.get(this.next) {
+            this.next += 1;
+            match bincode::serialize(item) {
+                Ok(bytes) => Bytes::from(bytes),
+                Err(e) => return Poll::Ready(Some(Err(MycoError::SerializationFailed(Some(e))))),
+            }
+        } else if let Some(tail) = this.tail.take() {
+            tail
+        } else {
+            return Poll::Ready(None);
+        };
+        this.remaining -= data.len() as u64;
+        Poll::Ready(Some(Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// A chunked response sending `count` items as length-prefixed frames, encoding each item as the
+/// stream yields it. An error ends the response early, which the reader sees as a broken body.
+pub fn framed_response<S, T>(count: usize, items: S) -> Response
+where
+    S: Stream<Item = Result<T, MycoError>> + Send + 'static,
+    T: Serialize,
+{
+    let head = stream::once(async move {
+        Ok::<_, MycoError>(Bytes::copy_from_slice(&(count as u64).to_le_bytes()))
+    });
+    let frames = items.map(|item| item.and_then(|item| encode_frame(&item)));
+    Body::from_stream(head.chain(frames)).into_response()
+}
+
+fn encode_frame<T: Serialize>(item: &T) -> Result<Bytes, MycoError> {
+    let size = bincode::serialized_size(item).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+    let size = u32::try_from(size).map_err(|_| MycoError::SerializationFailed(None))?;
+    let mut frame = Vec::with_capacity(FRAME_PREFIX_SIZE + size as usize);
+    frame.extend_from_slice(&size.to_le_bytes());
+    bincode::serialize_into(&mut frame, item).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+    Ok(Bytes::from(frame))
+}
+
+/// Decodes the frames of a [`framed_response`] as they arrive.
+pub struct FrameDecoder<T> {
+    buf: Vec<u8>,
+    remaining: Option<u64>,
+    max_frame_size: usize,
+    items: Vec<T>,
+}
+
+impl<T: DeserializeOwned> FrameDecoder<T> {
+    /// Create a decoder rejecting frames larger than `max_frame_size` bytes.
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            remaining: None,
+            max_frame_size,
+            items: Vec::new(),
+        }
+    }
+
+    /// Decode every frame completed by `data`.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), MycoError> {
+        self.buf.extend_from_slice(data);
+        let mut pos = 0;
+        loop {
+            let remaining = match self.remaining {
+                Some(remaining) => remaining,
+                None if self.buf.len() - pos < LEN_PREFIX_SIZE => break,
+                None => {
+                    let prefix = &self.buf[pos..pos + LEN_PREFIX_SIZE];
+                    pos += LEN_PREFIX_SIZE;
+                    let count = u64::from_le_bytes(prefix.try_into().unwrap());
+                    self.remaining = Some(count);
+                    count
+                }
+            };
+            if remaining == 0 {
+                if pos < self.buf.len() {
+                    return Err(MycoError::ProtocolError("data after the last frame".to_string()));
+                }
+                break;
+            }
+            if self.buf.len() - pos < FRAME_PREFIX_SIZE {
+                break;
+            }
+
+            let prefix = &self.buf[pos..pos + FRAME_PREFIX_SIZE];
+            let size = u32::from_le_bytes(prefix.try_into().unwrap()) as usize;
+            if size > self.max_frame_size {
+                return Err(MycoError::ProtocolError(format!(
+                    "frame of {} bytes exceeds {} bytes",
+                    size, self.max_frame_size
+                )));
+            }
+            let start = pos + FRAME_PREFIX_SIZE;
+            if self.buf.len() - start < size {
+                break;
+            }
+            let item = bincode::deserialize(&self.buf[start..start + size])
+                .map_err(|e| MycoError::DeserializationError(Some(e)))?;
+            self.items.push(item);
+            self.remaining = Some(remaining - 1);
+            pos = start + size;
+        }
+        self.buf.drain(..pos);
+        Ok(())
+    }
+
+    /// Return the decoded items. Fails if the body ended before the last frame.
+    pub fn finish(self) -> Result<Vec<T>, MycoError> {
+        if self.remaining != Some(0) {
+            return Err(MycoError::DeserializationError(None));
+        }
+        Ok(self.items)
+    }
+}
@@ -0,0 +1,156 @@
+//! Multi-tenant servers
+//!
+//! One Server1/Server2 pair can host several Myco instances that share nothing but the machines:
+//! each tenant gets its own tree, PRF key ring, epoch counter and quotas, and its routes are
+//! served under `/t/<tenant>` ([`tenant_path`]). Clients of a tenant simply use
+//! `https://host/t/<tenant>` as the server address. Messages never cross tenants, so each tenant
+//! is its own anonymity domain: a community that shares servers with others is not hidden among
+//! their users.
+//!
+//! Tenants are listed in [`TENANTS_ENV`]. Quotas can be set per tenant by suffixing the variable
+//! with the tenant ID ([`tenant_var`]), e.g. `MYCO_WRITE_QUOTA_ALPHA`, and fall back to the
+//! global value. The framed transport is not tenanted and keeps serving the servers' default
+//! instance, which is only run when no tenants are configured.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use axum::Router;
+
+use crate::error::MycoError;
+
+/// Environment variable holding the comma-separated IDs of the tenants a server hosts. The server
+/// runs a single, untenanted instance when it isn't set.
+pub const TENANTS_ENV: &str = "MYCO_TENANTS";
+
+/// Prefix of the routes of every tenant.
+pub const TENANT_PREFIX: &str = "/t";
+
+/// Longest tenant ID accepted.
+pub const MAX_TENANT_ID_LEN: usize = 32;
+
+/// The ID of a tenant: 1 to [`MAX_TENANT_ID_LEN`] lowercase ASCII letters, digits and dashes.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TenantId(String);
+
+impl TenantId {
+    /// The ID as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for TenantId {
+    type Err = MycoError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let valid = (1..=MAX_TENANT_ID_LEN).contains(&id.len())
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+        match valid {
+            true => Ok(Self(id.to_string())),
+            false => Err(MycoError::ConfigError(format!("invalid tenant ID {:?}", id))),
+        }
+    }
+}
+
+impl fmt::Display for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Read the tenants to host from [`TENANTS_ENV`]. Returns an empty list when it isn't set.
+pub fn tenants_from_env() -> Result<Vec<TenantId>, MycoError> {
+    match std::env::var(TENANTS_ENV) {
+        Ok(value) => parse_tenants(&value),
+        Err(_) => Ok(vec![]),
+    }
+}
+
+/// Parse a comma-separated list of tenant IDs, rejecting duplicates.
+pub fn parse_tenants(value: &str) -> Result<Vec<TenantId>, MycoError> {
+    let mut tenants: Vec<TenantId> = vec![];
+    for id in value.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        let tenant: TenantId = id.parse()?;
+        if tenants.contains(&tenant) {
+            return Err(MycoError::ConfigError(format!("duplicate tenant {}", tenant)));
+        }
+        tenants.push(tenant);
+    }
+    Ok(tenants)
+}
+
+/// The path the routes of `tenant` are served under.
+pub fn tenant_path(tenant: &TenantId) -> String {
+    format!("{}/{}", TENANT_PREFIX, tenant)
+}
+
+/// The base URL of `tenant` on the server at `base_url`.
+pub fn tenant_url(base_url: &str, tenant: &TenantId) -> String {
+    format!("{}{}", base_url.trim_end_matches('/'), tenant_path(tenant))
+}
+
+/// `route` with the tenant prefix stripped, if it has one.
+pub fn strip_tenant(route: &str) -> &str {
+    route
+        .strip_prefix(TENANT_PREFIX)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .and_then(|rest| rest.find('/').map(|i| &rest[i..]))
+        .unwrap_or(route)
+}
+
+/// The path of `route` on the server at `base_url`, as operator requests are signed over. This
+/// includes the tenant prefix when `base_url` is a tenant's.
+pub fn route_path(base_url: &str, route: &str) -> String {
+    let prefix = reqwest::Url::parse(base_url)
+        .map(|url| url.path().trim_end_matches('/').to_string())
+        .unwrap_or_default();
+    format!("{}{}", prefix, route)
+}
+
+/// The name of the per-tenant override of the environment variable `name`, e.g.
+/// `MYCO_WRITE_QUOTA_ALPHA` for `MYCO_WRITE_QUOTA` and tenant `alpha`.
+pub fn tenant_var(name: &str, tenant: &TenantId) -> String {
+    format!("{}_{}", name, tenant.as_str().to_ascii_uppercase().replace('-', "_"))
+}
+
+/// Read the per-tenant override of the limit in `name`, falling back to `global` when it isn't
+/// set.
+pub fn limit_from_env(
+    name: &str,
+    tenant: &TenantId,
+    global: Option<usize>,
+) -> Result<Option<usize>, MycoError> {
+    let name = tenant_var(name, tenant);
+    match std::env::var(&name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| MycoError::ConfigError(format!("invalid {} {}", name, value))),
+        Err(_) => Ok(global),
+    }
+}
+
+/// The file `tenant` keeps the state configured at `path` in, e.g. `snapshot.alpha.bin` for
+/// `snapshot.bin`, so tenants never share a snapshot or journal.
+pub fn tenant_file(path: &Path, tenant: &TenantId) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, tenant, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, tenant),
+    };
+    path.with_file_name(name)
+}
+
+/// Serve the app of each tenant under its [`tenant_path`].
+pub fn nest_tenants(apps: impl IntoIterator<Item = (TenantId, Router)>) -> Router {
+    apps.into_iter()
+        .fold(Router::new(), |router, (tenant, app)| {
+            router.nest(&tenant_path(&tenant), app)
+        })
+}
@@ -0,0 +1,294 @@
+//! Canonical test vectors
+//!
+//! With the `test-vectors` feature, [`generate`] computes vectors for the derivations a client
+//! has to reproduce bit for bit to interoperate with this crate: the contact keys derived from a
+//! shared key, the per-epoch values `f` and `k_oblv_t` together with the pseudonym and the
+//! location `l` of a write, and the encryption of a message into a block. [`to_json`] publishes
+//! them with all byte strings hex-encoded, and [`verify`] recomputes every output from its inputs,
+//! so an alternative implementation can check its own vectors against the Rust crate and the
+//! crate can check vectors published by an earlier version.
+//!
+//! Epochs enter the derivations as 8-byte big-endian integers. Encryption nonces are random in
+//! normal operation; the vectors fix them so the ciphertexts are reproducible.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::EpochKeys,
+    constants::{KEY_HINT_SIZE, LAMBDA, NONCE_SIZE},
+    crypto::{
+        client_pseudonym, decrypt, encrypt_with_nonce, kdf, key_hint, location_prf, pad_plaintext,
+        EncryptionType,
+    },
+    dtypes::Path,
+    error::MycoError,
+};
+
+/// Version of the vector format, bumped whenever a vector's fields change.
+pub const TEST_VECTORS_VERSION: u32 = 1;
+
+/// A complete set of vectors, as published by `myco-test-vectors emit`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    /// [`TEST_VECTORS_VERSION`] of the crate the vectors were generated with
+    pub version: u32,
+    /// Contact keys derived from shared keys
+    pub keys: Vec<KeyVector>,
+    /// Per-epoch derivations and write locations
+    pub paths: Vec<PathVector>,
+    /// Block encryptions
+    pub blocks: Vec<BlockVector>,
+}
+
+/// The contact keys a client derives from the key `k` it shares with a contact.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyVector {
+    /// Shared key
+    pub k: String,
+    /// `kdf(k, "MSG")`
+    pub k_msg: String,
+    /// `kdf(k, "ORAM")`
+    pub k_oblv: String,
+    /// `kdf(k, "PRF")`
+    pub k_prf: String,
+}
+
+/// The values derived for a write in `epoch` and the path it lands on.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathVector {
+    /// Contact oblivious key
+    pub k_oblv: String,
+    /// Contact PRF key
+    pub k_prf: String,
+    /// Epoch of the write
+    pub epoch: u64,
+    /// Server1 epoch key, possibly a concatenation of several servers' shares
+    pub k_s1_t: String,
+    /// Conversation separator of the write
+    pub cs: String,
+    /// `prf(k_prf, epoch)`
+    pub f: String,
+    /// `kdf(k_oblv, epoch as a decimal string)`
+    pub k_oblv_t: String,
+    /// `prf(k_prf, "client-pseudonym" || epoch || cs)`
+    pub pseudonym: String,
+    /// `location_prf(k_s1_t, f, pseudonym)`
+    pub l: String,
+    /// Tree index of the leaf `l` leads to, reading the bits of `l` least significant first
+    pub leaf: u64,
+}
+
+/// A message encrypted by the client under `k_msg` and then by Server1 under `k_oblv_t` into a
+/// block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockVector {
+    /// Contact message key
+    pub k_msg: String,
+    /// Oblivious key of the write's epoch
+    pub k_oblv_t: String,
+    /// Plaintext, padded with zeros before encryption
+    pub message: String,
+    /// Nonce of the client's encryption
+    pub inner_nonce: String,
+    /// Nonce of Server1's encryption
+    pub outer_nonce: String,
+    /// Client ciphertext, `nonce || commitment || AES-GCM(message)`
+    pub inner: String,
+    /// Block as stored in the tree, `key hint || Server1 ciphertext of inner`
+    pub block: String,
+}
+
+/// `len` bytes counting up from `start`, the inputs of the vectors.
+fn pattern(start: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| start.wrapping_add(i as u8)).collect()
+}
+
+fn nonce(start: u8) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce.copy_from_slice(&pattern(start, NONCE_SIZE));
+    nonce
+}
+
+fn unhex(field: &str, value: &str) -> Result<Vec<u8>, MycoError> {
+    hex::decode(value).map_err(|_| MycoError::TestVectorMismatch(format!("{field} is not hex")))
+}
+
+fn key_vector(k: &[u8]) -> Result<KeyVector, MycoError> {
+    Ok(KeyVector {
+        k: hex::encode(k),
+        k_msg: hex::encode(kdf(k, "MSG")?),
+        k_oblv: hex::encode(kdf(k, "ORAM")?),
+        k_prf: hex::encode(kdf(k, "PRF")?),
+    })
+}
+
+fn path_vector(
+    k_oblv: &[u8],
+    k_prf: &[u8],
+    epoch: u64,
+    k_s1_t: &[u8],
+    cs: &[u8],
+) -> Result<PathVector, MycoError> {
+    let epoch_index = usize::try_from(epoch)
+        .map_err(|_| MycoError::TestVectorMismatch(format!("epoch {epoch} out of range")))?;
+    let EpochKeys { f, k_oblv_t } = EpochKeys::derive(k_oblv, k_prf, epoch_index)?;
+    let pseudonym = client_pseudonym(k_prf, cs, epoch_index)?;
+    let l = location_prf(k_s1_t, &f, &pseudonym)?;
+    let leaf = Path::from(l.clone()).to_index() as u64;
+    Ok(PathVector {
+        k_oblv: hex::encode(k_oblv),
+        k_prf: hex::encode(k_prf),
+        epoch,
+        k_s1_t: hex::encode(k_s1_t),
+        cs: hex::encode(cs),
+        f: hex::encode(f),
+        k_oblv_t: hex::encode(k_oblv_t),
+        pseudonym: hex::encode(pseudonym),
+        l: hex::encode(l),
+        leaf,
+    })
+}
+
+fn block_vector(
+    k_msg: &[u8],
+    k_oblv_t: &[u8],
+    message: &[u8],
+    inner_nonce: &[u8; NONCE_SIZE],
+    outer_nonce: &[u8; NONCE_SIZE],
+) -> Result<BlockVector, MycoError> {
+    let inner = encrypt_with_nonce(
+        k_msg,
+        inner_nonce,
+        pad_plaintext(message, EncryptionType::Encrypt),
+    )?;
+    let outer = encrypt_with_nonce(
+        k_oblv_t,
+        outer_nonce,
+        pad_plaintext(&inner, EncryptionType::DoubleEncrypt),
+    )?;
+    let block = [key_hint(k_oblv_t, &outer)?, outer].concat();
+    Ok(BlockVector {
+        k_msg: hex::encode(k_msg),
+        k_oblv_t: hex::encode(k_oblv_t),
+        message: hex::encode(message),
+        inner_nonce: hex::encode(inner_nonce),
+        outer_nonce: hex::encode(outer_nonce),
+        inner: hex::encode(inner),
+        block: hex::encode(block),
+    })
+}
+
+/// Generate the canonical vectors.
+pub fn generate() -> Result<TestVectors, MycoError> {
+    let shared = [vec![0u8; 16], pattern(0x01, 16), vec![0xff; 16]];
+    let keys = shared.iter().map(|k| key_vector(k)).collect::<Result<Vec<_>, _>>()?;
+
+    let share = LAMBDA / 8;
+    let mut paths = Vec::new();
+    for (k, (epoch, k_s1_t)) in shared.iter().zip([
+        (0, pattern(0x40, share)),
+        (1, pattern(0x50, share)),
+        (u32::MAX as u64 + 1, pattern(0x60, 2 * share)),
+    ]) {
+        let (k_oblv, k_prf) = (kdf(k, "ORAM")?, kdf(k, "PRF")?);
+        for cs in [&b""[..], b"conversation"] {
+            paths.push(path_vector(&k_oblv, &k_prf, epoch, &k_s1_t, cs)?);
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for (i, message) in [&b""[..], b"hello myco", &pattern(0x20, 200)].into_iter().enumerate() {
+        let k = &shared[i];
+        let k_oblv_t = kdf(&kdf(k, "ORAM")?, &i.to_string())?;
+        let seed = 0x80 + 0x10 * i as u8;
+        blocks.push(block_vector(&kdf(k, "MSG")?, &k_oblv_t, message, &nonce(seed), &nonce(seed + 8))?);
+    }
+
+    Ok(TestVectors { version: TEST_VECTORS_VERSION, keys, paths, blocks })
+}
+
+/// Check that `actual`, computed by this crate, matches the published `expected` value.
+fn check(name: &str, index: usize, field: &str, expected: &str, actual: &str) -> Result<(), MycoError> {
+    if expected.eq_ignore_ascii_case(actual) {
+        Ok(())
+    } else {
+        Err(MycoError::TestVectorMismatch(format!(
+            "{name}[{index}].{field}: expected {expected}, computed {actual}"
+        )))
+    }
+}
+
+/// Recompute every vector in `vectors` from its inputs and check the outputs, also decrypting
+/// each block back to its message.
+pub fn verify(vectors: &TestVectors) -> Result<(), MycoError> {
+    if vectors.version != TEST_VECTORS_VERSION {
+        return Err(MycoError::TestVectorMismatch(format!(
+            "version {} is not {}",
+            vectors.version, TEST_VECTORS_VERSION
+        )));
+    }
+
+    for (i, v) in vectors.keys.iter().enumerate() {
+        let computed = key_vector(&unhex("k", &v.k)?)?;
+        check("keys", i, "k_msg", &v.k_msg, &computed.k_msg)?;
+        check("keys", i, "k_oblv", &v.k_oblv, &computed.k_oblv)?;
+        check("keys", i, "k_prf", &v.k_prf, &computed.k_prf)?;
+    }
+
+    for (i, v) in vectors.paths.iter().enumerate() {
+        let computed = path_vector(
+            &unhex("k_oblv", &v.k_oblv)?,
+            &unhex("k_prf", &v.k_prf)?,
+            v.epoch,
+            &unhex("k_s1_t", &v.k_s1_t)?,
+            &unhex("cs", &v.cs)?,
+        )?;
+        check("paths", i, "f", &v.f, &computed.f)?;
+        check("paths", i, "k_oblv_t", &v.k_oblv_t, &computed.k_oblv_t)?;
+        check("paths", i, "pseudonym", &v.pseudonym, &computed.pseudonym)?;
+        check("paths", i, "l", &v.l, &computed.l)?;
+        check("paths", i, "leaf", &v.leaf.to_string(), &computed.leaf.to_string())?;
+    }
+
+    for (i, v) in vectors.blocks.iter().enumerate() {
+        let nonce_field = |field: &str, value: &str| -> Result<[u8; NONCE_SIZE], MycoError> {
+            unhex(field, value)?
+                .try_into()
+                .map_err(|_| MycoError::TestVectorMismatch(format!("blocks[{i}].{field} is not a nonce")))
+        };
+        let (k_msg, k_oblv_t) = (unhex("k_msg", &v.k_msg)?, unhex("k_oblv_t", &v.k_oblv_t)?);
+        let message = unhex("message", &v.message)?;
+        let computed = block_vector(
+            &k_msg,
+            &k_oblv_t,
+            &message,
+            &nonce_field("inner_nonce", &v.inner_nonce)?,
+            &nonce_field("outer_nonce", &v.outer_nonce)?,
+        )?;
+        check("blocks", i, "inner", &v.inner, &computed.inner)?;
+        check("blocks", i, "block", &v.block, &computed.block)?;
+
+        // Decrypt the published block rather than the computed one, as a reader would.
+        let block = unhex("block", &v.block)?;
+        let inner = decrypt(&k_oblv_t, &block[KEY_HINT_SIZE.min(block.len())..])?;
+        let padded = decrypt(&k_msg, &inner)?;
+        check(
+            "blocks",
+            i,
+            "message",
+            &hex::encode(pad_plaintext(&message, EncryptionType::Encrypt)),
+            &hex::encode(padded),
+        )?;
+    }
+    Ok(())
+}
+
+/// Encode `vectors` as pretty-printed JSON.
+pub fn to_json(vectors: &TestVectors) -> String {
+    serde_json::to_string_pretty(vectors).expect("test vectors serialize")
+}
+
+/// Decode vectors published by [`to_json`].
+pub fn from_json(json: &str) -> Result<TestVectors, MycoError> {
+    serde_json::from_str(json).map_err(|e| MycoError::TestVectorMismatch(e.to_string()))
+}
@@ -0,0 +1,223 @@
+//! TLS trust configuration
+//!
+//! [`TlsTrust`] decides which server certificates a client accepts, for both the HTTPS transport
+//! and the framed TLS transport. Certificates are always verified: a deployment either trusts the
+//! system roots plus an optional CA bundle, or pins specific certificates.
+//!
+//! The binaries read their trust configuration from the environment (see [`TlsTrust::from_env`])
+//! and fall back to trusting the self-signed certificate generated in `certs/` for local runs.
+//! With the `acme` feature, servers can instead obtain their certificate over ACME (see
+//! [`server_config`]).
+
+use std::{
+    fs::File,
+    io::BufReader,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use tokio_rustls::{
+    rustls::{self, Certificate, RootCertStore},
+    TlsConnector,
+};
+
+use crate::{error::MycoError, utils::generate_test_certificates};
+
+/// Environment variable holding a PEM bundle of CA certificates to trust.
+pub const CA_BUNDLE_ENV: &str = "MYCO_TLS_CA_BUNDLE";
+
+/// Environment variable holding a comma-separated list of PEM certificates to pin.
+pub const PINNED_CERTS_ENV: &str = "MYCO_TLS_PINNED_CERTS";
+
+/// Environment variable overriding the name sent as SNI and verified against the certificate.
+pub const SERVER_NAME_ENV: &str = "MYCO_TLS_SERVER_NAME";
+
+/// Environment variable holding the PEM certificate chain a server presents.
+pub const CERT_ENV: &str = "MYCO_TLS_CERT";
+
+/// Environment variable holding the PKCS#8 private key a server presents.
+pub const KEY_ENV: &str = "MYCO_TLS_KEY";
+
+/// Subject of the certificates produced by [`generate_test_certificates`].
+pub const TEST_SERVER_NAME: &str = "localhost";
+
+/// Which server certificates a client trusts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsTrust {
+    /// PEM bundle of CA certificates trusted in addition to the system roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// PEM certificates to pin. When any are given, only these are trusted and the system roots
+    /// and CA bundle are ignored.
+    pub pinned_certs: Vec<PathBuf>,
+    /// Name sent as SNI and verified against the certificate instead of the host in the address.
+    pub server_name: Option<String>,
+}
+
+impl TlsTrust {
+    /// Trust the self-signed certificate at `cert_path`, as generated by
+    /// [`generate_test_certificates`].
+    pub fn self_signed(cert_path: &FsPath) -> Self {
+        Self {
+            ca_bundle: None,
+            pinned_certs: vec![cert_path.to_path_buf()],
+            server_name: Some(TEST_SERVER_NAME.to_string()),
+        }
+    }
+
+    /// Read the trust configuration from [`CA_BUNDLE_ENV`], [`PINNED_CERTS_ENV`] and
+    /// [`SERVER_NAME_ENV`]. Returns `None` if none of them are set.
+    pub fn from_env() -> Option<Self> {
+        let ca_bundle = std::env::var(CA_BUNDLE_ENV).ok().map(PathBuf::from);
+        let pinned_certs: Vec<PathBuf> = std::env::var(PINNED_CERTS_ENV)
+            .map(|paths| {
+                paths
+                    .split(',')
+                    .filter(|path| !path.is_empty())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let server_name = std::env::var(SERVER_NAME_ENV).ok();
+
+        if ca_bundle.is_none() && pinned_certs.is_empty() && server_name.is_none() {
+            return None;
+        }
+        Some(Self {
+            ca_bundle,
+            pinned_certs,
+            server_name,
+        })
+    }
+
+    /// Whether any certificates are configured beyond the system roots.
+    pub fn has_roots(&self) -> bool {
+        self.ca_bundle.is_some() || !self.pinned_certs.is_empty()
+    }
+
+    fn trusted_pem_files(&self) -> Vec<&FsPath> {
+        if self.pinned_certs.is_empty() {
+            self.ca_bundle.iter().map(PathBuf::as_path).collect()
+        } else {
+            self.pinned_certs.iter().map(PathBuf::as_path).collect()
+        }
+    }
+
+    /// Build an HTTP client for the server at `base_url`. Returns the builder together with the
+    /// base URL to send requests to, which differs from `base_url` when a server name is
+    /// configured: the host is replaced by the server name and resolved to the original address.
+    pub fn http_client_builder(
+        &self,
+        base_url: &str,
+    ) -> Result<(reqwest::ClientBuilder, String), MycoError> {
+        let mut builder =
+            reqwest::Client::builder().tls_built_in_root_certs(self.pinned_certs.is_empty());
+        for path in self.trusted_pem_files() {
+            let pem = std::fs::read(path)?;
+            for cert in reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| MycoError::CertificateError(e.to_string()))?
+            {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        let Some(server_name) = &self.server_name else {
+            return Ok((builder, base_url.to_string()));
+        };
+        let mut url = reqwest::Url::parse(base_url)
+            .map_err(|e| MycoError::ConfigError(format!("invalid URL {}: {}", base_url, e)))?;
+        let addr = resolve(&url)?;
+        url.set_host(Some(server_name))
+            .map_err(|e| MycoError::ConfigError(format!("invalid server name: {}", e)))?;
+        builder = builder.resolve(server_name, addr);
+        Ok((builder, url.as_str().trim_end_matches('/').to_string()))
+    }
+
+    /// Build a connector for the framed TLS transport. Needs a CA bundle or pinned certificates,
+    /// since the framed transport has no access to the system roots.
+    pub fn connector(&self) -> Result<TlsConnector, MycoError> {
+        if !self.has_roots() {
+            return Err(MycoError::ConfigError(
+                "the framed TLS transport needs a CA bundle or pinned certificates".to_string(),
+            ));
+        }
+        let mut roots = RootCertStore::empty();
+        for path in self.trusted_pem_files() {
+            for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))? {
+                roots
+                    .add(&Certificate(cert))
+                    .map_err(|e| MycoError::CertificateError(e.to_string()))?;
+            }
+        }
+
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    /// The name to verify the certificate of the server at `host` against.
+    pub fn server_name_for<'a>(&'a self, host: &'a str) -> &'a str {
+        self.server_name.as_deref().unwrap_or(host)
+    }
+}
+
+fn resolve(url: &reqwest::Url) -> Result<SocketAddr, MycoError> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| MycoError::ConfigError(format!("URL {} has no host", url)))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| MycoError::ConfigError(format!("URL {} has no port", url)))?;
+    (host.trim_start_matches('[').trim_end_matches(']'), port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| MycoError::ConfigError(format!("could not resolve {}", host)))
+}
+
+/// The certificate chain and private key a server presents: [`CERT_ENV`] and [`KEY_ENV`] if both
+/// are set, otherwise the self-signed certificate in `certs/`, generated if missing.
+pub fn server_identity() -> Result<(PathBuf, PathBuf), MycoError> {
+    if let (Ok(cert_path), Ok(key_path)) = (std::env::var(CERT_ENV), std::env::var(KEY_ENV)) {
+        return Ok((PathBuf::from(cert_path), PathBuf::from(key_path)));
+    }
+
+    let certs = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("certs");
+    let cert_path = certs.join("server-cert.pem");
+    let key_path = certs.join("server-key.pem");
+    if !cert_path.exists() || !key_path.exists() {
+        generate_test_certificates(Some(&certs))?;
+    }
+    Ok((cert_path, key_path))
+}
+
+/// The TLS configuration an HTTPS server presents, together with the paths of its certificate
+/// chain and key.
+///
+/// With the `acme` feature and [`acme::DOMAINS_ENV`](crate::acme::DOMAINS_ENV) set, the certificate
+/// is obtained over ACME and renewed in the background. Otherwise it is [`server_identity`].
+pub async fn server_config() -> Result<(RustlsConfig, PathBuf, PathBuf), MycoError> {
+    #[cfg(feature = "acme")]
+    if let Some(config) = crate::acme::AcmeConfig::from_env()? {
+        let (cert_path, key_path) = (config.cert_path(), config.key_path());
+        return Ok((crate::acme::start(config).await?, cert_path, key_path));
+    }
+
+    let (cert_path, key_path) = server_identity()?;
+    let config = RustlsConfig::from_pem_file(&cert_path, &key_path).await?;
+    Ok((config, cert_path, key_path))
+}
+
+/// The trust configuration from the environment, or trust in the self-signed certificate in
+/// `certs/` if none is set.
+pub fn client_trust() -> TlsTrust {
+    TlsTrust::from_env().unwrap_or_else(|| {
+        TlsTrust::self_signed(
+            &PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("certs")
+                .join("server-cert.pem"),
+        )
+    })
+}
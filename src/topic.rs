@@ -0,0 +1,206 @@
+//! Topic mailboxes
+//!
+//! Talking to a contact takes a contact key, the contact's client ID to read under and the epochs
+//! to read, all of which applications otherwise track themselves. A [`Mailbox`] bundles them under
+//! a human-readable topic, and [`Mailboxes`] keeps a client's mailboxes by topic:
+//!
+//! - [`Mailboxes::create`] sets up a fresh mailbox key for a topic, and [`Mailboxes::share`]
+//!   exports it as a [`ContactBundle`] for the contact to [`Mailboxes::import`] under a topic of
+//!   their own choosing. Topics are local names and are never sent.
+//! - [`Mailbox::send`] writes to the mailbox and [`Mailbox::recv`] reads the contact's messages
+//!   from the epochs since the last call, as far back as the tree keeps them.
+//!
+//! A contact key only carries one message per epoch, so each side of a mailbox writes under its
+//! own key, derived from the shared mailbox key and the writer's client ID ([`direction_key`]).
+//!
+//! Mailboxes are serializable, so applications can persist them next to the client's store.
+
+use std::collections::BTreeMap;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::Client,
+    constants::DELTA,
+    crypto::kdf,
+    dtypes::{ContactBundle, Key},
+    envelope::Envelope,
+    error::MycoError,
+};
+
+/// The contact key the client `writer` writes to the mailbox with key `key` under.
+pub fn direction_key(key: &Key, writer: &str) -> Result<Key, MycoError> {
+    Ok(Key::new(kdf(&key.0, &format!("MAILBOX {}", writer))?))
+}
+
+/// A key shared with one contact, under a human-readable topic.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mailbox {
+    /// The topic the mailbox is known by locally.
+    pub topic: String,
+    /// The mailbox key, as shared with the contact.
+    pub key: Key,
+    /// The client ID the contact writes under.
+    pub peer: String,
+    /// The contact key this side writes with.
+    pub send_key: Key,
+    /// The contact key the contact writes with.
+    pub recv_key: Key,
+    /// The client's epoch when the mailbox was set up; nothing older is read.
+    pub created_at: usize,
+    /// Next epoch to read the contact's messages from.
+    next_read: usize,
+}
+
+// Custom Debug implementation for Mailbox to keep the contact key out of logs
+impl std::fmt::Debug for Mailbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mailbox({:?}, peer {:?})", self.topic, self.peer)
+    }
+}
+
+impl Mailbox {
+    /// The mailbox with key `key` between `client` and `peer`, setting up its contact keys.
+    fn set_up(client: &mut Client, topic: &str, key: Key, peer: &str) -> Result<Self, MycoError> {
+        let send_key = direction_key(&key, &client.id)?;
+        let recv_key = direction_key(&key, peer)?;
+        client.setup(&send_key)?;
+        client.setup(&recv_key)?;
+        Ok(Self {
+            topic: topic.to_string(),
+            key,
+            peer: peer.to_string(),
+            send_key,
+            recv_key,
+            created_at: client.epoch,
+            next_read: client.epoch,
+        })
+    }
+
+    /// Write `msg` to the mailbox in the client's current epoch.
+    pub fn send(&self, client: &mut Client, msg: &[u8]) -> Result<(), MycoError> {
+        client.write(msg, &self.send_key)
+    }
+
+    /// Read the contact's messages from the epochs since the last call, oldest first. Epochs
+    /// older than [`DELTA`] have expired from the tree and are skipped, and messages already
+    /// delivered are not returned again.
+    pub fn recv(&mut self, client: &Client) -> Result<Vec<Envelope>, MycoError> {
+        let current = client.epoch;
+        let first = self.next_read.max(current.saturating_sub(DELTA));
+        let mut messages = Vec::new();
+        for epoch in first..current {
+            match client.read_new(&self.recv_key, self.peer.clone(), current - epoch - 1) {
+                Ok(Some(envelope)) => messages.push(envelope),
+                Ok(None) | Err(MycoError::NoMessageFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.next_read = self.next_read.max(current);
+        Ok(messages)
+    }
+}
+
+/// A client's mailboxes, by topic.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Mailboxes {
+    mailboxes: BTreeMap<String, Mailbox>,
+}
+
+impl Mailboxes {
+    /// No mailboxes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set up a mailbox with `peer` under `topic`, with a fresh mailbox key.
+    pub fn create(&mut self, client: &mut Client, topic: &str, peer: &str) -> Result<&mut Mailbox, MycoError> {
+        self.check_free(topic)?;
+        let key = Key::random(&mut ChaCha20Rng::from_entropy());
+        let mailbox = Mailbox::set_up(client, topic, key, peer)?;
+        Ok(self.insert(mailbox))
+    }
+
+    /// Export the mailbox of `topic` as a [`ContactBundle`] payload, together with the server
+    /// addresses the contact should use.
+    pub fn share(&self, client: &Client, topic: &str, server1: &str, server2: &str) -> Result<String, MycoError> {
+        let mailbox = self.get(topic).ok_or(MycoError::UnknownContact)?;
+        ContactBundle {
+            id: client.id.clone(),
+            identity_key: None,
+            key: mailbox.key.clone(),
+            server1: server1.to_string(),
+            server2: server2.to_string(),
+        }
+        .encode()
+    }
+
+    /// Import a mailbox shared with [`Mailboxes::share`] under `topic`. Returns the mailbox and
+    /// the bundle it was shared in.
+    pub fn import(
+        &mut self,
+        client: &mut Client,
+        topic: &str,
+        encoded: &str,
+    ) -> Result<(&mut Mailbox, ContactBundle), MycoError> {
+        self.check_free(topic)?;
+        let bundle = ContactBundle::decode(encoded)?;
+        let mailbox = Mailbox::set_up(client, topic, bundle.key.clone(), &bundle.id)?;
+        Ok((self.insert(mailbox), bundle))
+    }
+
+    /// Revoke the contact keys of the mailbox of `topic` (see [`Client::revoke`]) and drop it.
+    pub fn remove(&mut self, client: &mut Client, topic: &str, send_tombstone: bool) -> Result<Mailbox, MycoError> {
+        let mailbox = self.mailboxes.remove(topic).ok_or(MycoError::UnknownContact)?;
+        client.revoke(&mailbox.recv_key, false)?;
+        client.revoke(&mailbox.send_key, send_tombstone)?;
+        Ok(mailbox)
+    }
+
+    /// The mailbox of `topic`.
+    pub fn get(&self, topic: &str) -> Option<&Mailbox> {
+        self.mailboxes.get(topic)
+    }
+
+    /// The mailbox of `topic`, to send or receive on.
+    pub fn get_mut(&mut self, topic: &str) -> Option<&mut Mailbox> {
+        self.mailboxes.get_mut(topic)
+    }
+
+    /// The topic of the mailbox with mailbox or contact key `k`, e.g. to file a message read
+    /// with [`Client::async_read`].
+    pub fn topic_of(&self, k: &Key) -> Option<&str> {
+        self.iter()
+            .find(|mailbox| [&mailbox.key, &mailbox.send_key, &mailbox.recv_key].contains(&k))
+            .map(|mailbox| mailbox.topic.as_str())
+    }
+
+    /// The mailboxes, ordered by topic.
+    pub fn iter(&self) -> impl Iterator<Item = &Mailbox> {
+        self.mailboxes.values()
+    }
+
+    /// Number of mailboxes.
+    pub fn len(&self) -> usize {
+        self.mailboxes.len()
+    }
+
+    /// Whether there are no mailboxes.
+    pub fn is_empty(&self) -> bool {
+        self.mailboxes.is_empty()
+    }
+
+    fn check_free(&self, topic: &str) -> Result<(), MycoError> {
+        if topic.is_empty() || self.mailboxes.contains_key(topic) {
+            return Err(MycoError::ConfigError(format!("topic {:?} is empty or taken", topic)));
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, mailbox: Mailbox) -> &mut Mailbox {
+        let topic = mailbox.topic.clone();
+        self.mailboxes.entry(topic).or_insert(mailbox)
+    }
+}
@@ -0,0 +1,1189 @@
+//! Transport abstraction
+//!
+//! Every way of reaching a server (in memory, HTTPS, or the framed TCP/TLS transport) implements
+//! [`Transport`], which exchanges a request [`Command`] for a response [`Command`]. Serialization
+//! and error mapping live in the transport, so [`TransportServer1Access`] and
+//! [`TransportServer2Access`] implement the access traits once for all of them.
+//!
+//! [`TransportConfig`] selects a transport from a server address:
+//!
+//! - `https://host:port` uses the HTTPS endpoints.
+//! - `tls://host:port` uses the framed transport over TLS.
+//! - `tcp://host:port` uses the framed transport over plain TCP.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use axum::async_trait;
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::{
+    admin::{self, EpochControl},
+    constants::{D, ENCODED_BUCKET_SIZE},
+    dtypes::{Bucket, BucketDelta, EpochInfo, Key, Path, SparseBuckets, StorageReport},
+    error::MycoError,
+    framed::FramedConnection,
+    idempotency::IdempotencyKey,
+    mailbox::MailboxGuard,
+    registration::AccountCredentials,
+    pacing::{Pacer, MAX_CHUNK_RETRIES},
+    network::{
+        Command, ReadType, RegistrationType, RemoteServer1Access, RemoteServer2Access, Server1Access, Server2Access,
+        WriteType,
+    },
+    rpc_types::{
+        Capabilities, GetCapabilitiesResponse,
+        ChunkReadPathsRequest, DeleteRegistrationRequest, DeleteRegistrationResponse,
+        GuardMailboxesRequest, GuardMailboxesResponse, IssueWriteTokensRequest, IssueWriteTokensResponse,
+        ErrorResponse,
+        FinalizeEpochRequest,
+        FinalizeEpochResponse, GetEpochResponse, GetNotificationsResponse, GetPrfKeysResponse,
+        GetPrfKeysSinceRequest, GetPrfKeysSinceResponse, PendingEpochsRequest, PendingEpochsResponse,
+        PublishNotificationsRequest,
+        PublishStorageRequest,
+        QueueWriteRequest, QueueWriteResponse,
+        PrefetchPathLeavesRequest, ReadPathsClientRequest, ReadRequest, ReadResponse, RegisterResponse,
+        RotateRegistrationRequest, RotateRegistrationResponse, SparsePathFrame,
+        StorePathIndicesRequest, StorePathIndicesResponse, StorePathLeavesRequest,
+        StorePathLeavesResponse, WriteResponse,
+    },
+    server1::Server1,
+    server2::Server2,
+    streaming::{FrameDecoder, SeqBody, SeqDecoder},
+    tls::TlsTrust,
+};
+
+/// Address scheme selecting the framed transport over TLS.
+pub const TLS_SCHEME: &str = "tls://";
+
+/// Address scheme selecting the framed transport over plain TCP.
+pub const TCP_SCHEME: &str = "tcp://";
+
+/// Response header carrying the estimated opening time of the next epoch, in milliseconds since the
+/// Unix epoch, when Server1 rejects a write with 503 Service Unavailable.
+pub const NEXT_EPOCH_HEADER: &str = "x-myco-next-epoch-opens-at";
+
+/// Request header naming the epoch and chunk of a chunked pathset write as `<epoch>/<chunk_idx>`.
+/// Both are repeated in the body's tail, but that only arrives after the buckets; with the header
+/// Server2 can apply each bucket as it is decoded.
+pub const CHUNK_HEADER: &str = "x-myco-chunk";
+
+
+/// A way of sending commands to a server.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Send a command and wait for its response. A [`Command::Error`] response is returned as an
+    /// error rather than a response.
+    async fn call(&self, command: Command) -> Result<Command, MycoError>;
+}
+
+/// Turn a [`Command::Error`] or [`Command::EpochClosed`] response into an error.
+pub(crate) fn into_result(response: Command) -> Result<Command, MycoError> {
+    match response {
+        Command::Error(code, message) => Err(MycoError::from_remote(code, message)),
+        Command::EpochClosed(next_epoch_opens_at) => {
+            Err(MycoError::EpochClosed { next_epoch_opens_at })
+        }
+        response => Ok(response),
+    }
+}
+
+fn unexpected(response: Command) -> MycoError {
+    MycoError::ProtocolError(format!("unexpected response: {:?}", response))
+}
+
+pub(crate) fn expect_success(response: Command) -> Result<(), MycoError> {
+    match response {
+        Command::Success => Ok(()),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_queued(response: Command) -> Result<u64, MycoError> {
+    match response {
+        Command::Queued(epoch) => Ok(epoch),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_buckets(response: Command) -> Result<Vec<Bucket>, MycoError> {
+    match response {
+        Command::Buckets(buckets) => Ok(buckets),
+        Command::SparseBuckets(buckets) => buckets.into_buckets(),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_prf_keys(response: Command) -> Result<Vec<Key>, MycoError> {
+    match response {
+        Command::PrfKeys(keys) => Ok(keys),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_epoch(response: Command) -> Result<EpochInfo, MycoError> {
+    match response {
+        Command::Epoch(info) => Ok(info),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_registered(response: Command) -> Result<AccountCredentials, MycoError> {
+    match response {
+        Command::Registered(credentials) => Ok(credentials),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_write_tokens(response: Command) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
+    match response {
+        Command::WriteTokens(tokens) => Ok(tokens),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_notifications(
+    response: Command,
+) -> Result<Option<(u64, Vec<u8>)>, MycoError> {
+    match response {
+        Command::Notifications(notifications) => Ok(notifications),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_pending_epochs(response: Command) -> Result<Vec<u64>, MycoError> {
+    match response {
+        Command::PendingEpochs(epochs) => Ok(epochs),
+        response => Err(unexpected(response)),
+    }
+}
+
+pub(crate) fn expect_prf_keys_since(response: Command) -> Result<(u64, Vec<Key>), MycoError> {
+    match response {
+        Command::PrfKeysSince(start, keys) => Ok((start, keys)),
+        response => Err(unexpected(response)),
+    }
+}
+
+/// Execute a command against Server1.
+pub async fn handle_server1_command(
+    server1: &RwLock<Server1>,
+    control: &EpochControl,
+    command: Command,
+) -> Command {
+    match command {
+        Command::Server1Write(ct, f, k_oblv_t, cs, token, access_tag) => {
+            let mut server1 = server1.write().await;
+            let result = if let Err(e) = admin::auto_batch_init(&mut server1, control).await {
+                Err(e)
+            } else if control.accepting_writes() {
+                server1.queue_write(ct, f, k_oblv_t, cs, token, access_tag)
+            } else {
+                Err(server1.epoch_closed())
+            };
+            match result {
+                Ok(epoch) => Command::Queued(epoch),
+                Err(MycoError::EpochClosed { next_epoch_opens_at }) => {
+                    Command::EpochClosed(next_epoch_opens_at)
+                }
+                Err(e) => error_response(&e),
+            }
+        }
+        Command::Server1Registration(registration) => {
+            let mut server1 = server1.write().await;
+            let registrations = &mut server1.registrations;
+            let result = match registration {
+                RegistrationType::Register => registrations
+                    .register(&mut rand::thread_rng())
+                    .map(Command::Registered),
+                RegistrationType::Rotate(credentials) => registrations
+                    .rotate(&credentials, &mut rand::thread_rng())
+                    .map(Command::Registered),
+                RegistrationType::Delete(credentials) => {
+                    registrations.delete(&credentials).map(|()| Command::Success)
+                }
+            };
+            result.unwrap_or_else(|e| error_response(&e))
+        }
+        Command::Server1GuardMailboxes(guards) => match server1.write().await.guard_mailboxes(guards) {
+            Ok(()) => Command::Success,
+            Err(e) => error_response(&e),
+        },
+        Command::Server1IssueWriteTokens(credentials, requests) => {
+            match server1.write().await.issue_write_tokens(&credentials, requests) {
+                Ok(tokens) => Command::WriteTokens(tokens),
+                Err(e) => error_response(&e),
+            }
+        }
+        _ => error_response(&MycoError::InvalidCommand),
+    }
+}
+
+/// Execute a command against Server2.
+pub async fn handle_server2_command(server2: &RwLock<Server2>, command: Command) -> Command {
+    let result = match command {
+        Command::Server2Read(ReadType::Read(path)) if path.len() <= D => {
+            server2.read().await.read(&path).map(Command::Buckets)
+        }
+        Command::Server2Read(ReadType::ReadPaths(indices)) => server2
+            .write()
+            .await
+            .read_and_store_path_indices(indices)
+            .map(|buckets| Command::SparseBuckets(SparseBuckets::new(buckets))),
+        Command::Server2Read(ReadType::ReadLeafPaths(leaves)) => server2
+            .write()
+            .await
+            .read_and_store_path_leaves(&leaves)
+            .map(|buckets| Command::SparseBuckets(SparseBuckets::new(buckets))),
+        Command::Server2Read(ReadType::PrefetchLeafPaths(leaves)) => server2
+            .read()
+            .await
+            .prefetch_leaf_paths(&leaves)
+            .map(|buckets| Command::SparseBuckets(SparseBuckets::new(buckets))),
+        Command::Server2Read(ReadType::ReadPathsClient(indices)) => server2
+            .read()
+            .await
+            .read_paths_client(indices)
+            .map(Command::Buckets),
+        Command::Server2Read(ReadType::GetPrfKeys) => {
+            server2.read().await.get_prf_keys().map(Command::PrfKeys)
+        }
+        Command::Server2Read(ReadType::GetPrfKeysSince(cursor)) => server2
+            .read()
+            .await
+            .get_prf_keys_since(cursor)
+            .map(|(start, keys)| Command::PrfKeysSince(start, keys)),
+        Command::Server2Read(ReadType::GetEpoch) => {
+            Ok(Command::Epoch(server2.read().await.epoch_info()))
+        }
+        Command::Server2Read(ReadType::GetNotifications) => {
+            Ok(Command::Notifications(server2.read().await.notifications()))
+        }
+        Command::Server2Read(ReadType::PendingEpochs(tags)) => server2
+            .read()
+            .await
+            .pending_epochs(&tags)
+            .map(Command::PendingEpochs),
+        Command::Server2Write(WriteType::Write(epoch, buckets, prf_key)) => server2
+            .write()
+            .await
+            .write(epoch, buckets, &prf_key)
+            .map(|()| Command::Success),
+        Command::Server2Write(WriteType::WriteDeltas(epoch, deltas, prf_key)) => server2
+            .write()
+            .await
+            .write_deltas(epoch, deltas, &prf_key)
+            .map(|()| Command::Success),
+        Command::Server2Write(WriteType::Notifications(index)) => {
+            server2.write().await.publish_notifications(index);
+            Ok(Command::Success)
+        }
+        Command::Server2Write(WriteType::Storage(report)) => {
+            server2.write().await.publish_storage(report);
+            Ok(Command::Success)
+        }
+        Command::Server2Write(WriteType::PathLeaves(leaves)) => server2
+            .write()
+            .await
+            .store_path_leaves(&leaves)
+            .map(|_| Command::Success),
+        _ => Err(MycoError::InvalidCommand),
+    };
+    result.unwrap_or_else(|e| error_response(&e))
+}
+
+/// The response to a command that failed with `err`.
+fn error_response(err: &MycoError) -> Command {
+    Command::Error(err.code(), err.to_string())
+}
+
+/// In-memory transport dispatching directly to a Server1 instance.
+#[derive(Clone)]
+pub struct InMemoryServer1Transport {
+    /// The server instance
+    pub server1: Arc<RwLock<Server1>>,
+    /// The epoch control gating writes
+    pub control: Arc<EpochControl>,
+}
+
+#[async_trait]
+impl Transport for InMemoryServer1Transport {
+    async fn call(&self, command: Command) -> Result<Command, MycoError> {
+        into_result(handle_server1_command(&self.server1, &self.control, command).await)
+    }
+}
+
+/// In-memory transport dispatching directly to a Server2 instance.
+#[derive(Clone)]
+pub struct InMemoryServer2Transport {
+    /// The server instance
+    pub server2: Arc<RwLock<Server2>>,
+}
+
+#[async_trait]
+impl Transport for InMemoryServer2Transport {
+    async fn call(&self, command: Command) -> Result<Command, MycoError> {
+        into_result(handle_server2_command(&self.server2, command).await)
+    }
+}
+
+/// Tuning of the HTTPS client's connections. Options left unset keep reqwest's defaults.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransportOptions {
+    /// Interval of TCP keepalive probes on idle connections
+    pub tcp_keepalive: Option<Duration>,
+    /// Time allowed for establishing a connection, including the TLS handshake
+    pub connect_timeout: Option<Duration>,
+    /// Time allowed for a whole request, from sending it until its response body is read
+    pub request_timeout: Option<Duration>,
+    /// How long an idle pooled connection is kept for reuse
+    pub pool_idle_timeout: Option<Duration>,
+}
+
+impl TransportOptions {
+    /// Apply the options to a client builder.
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(interval) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(interval);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.request_timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        builder
+    }
+}
+
+/// Transport over the servers' HTTPS endpoints.
+///
+/// Chunked reads and writes are split according to the server's [`Capabilities`], fetched on
+/// first use. Chunked writes are paced by a [`Pacer`] shared by all writes through the transport.
+pub struct HttpsTransport {
+    client: reqwest::Client,
+    base_url: String,
+    capabilities: OnceCell<Capabilities>,
+    pacer: Mutex<Pacer>,
+}
+
+impl HttpsTransport {
+    /// Create a new HttpsTransport for the server at `base_url`, verifying its certificate
+    /// according to `trust`.
+    pub fn new(base_url: &str, trust: &TlsTrust) -> Result<Self, MycoError> {
+        Self::with_options(base_url, trust, &TransportOptions::default())
+    }
+
+    /// Create a new HttpsTransport whose connections are tuned by `options`.
+    pub fn with_options(
+        base_url: &str,
+        trust: &TlsTrust,
+        options: &TransportOptions,
+    ) -> Result<Self, MycoError> {
+        let (builder, base_url) = trust.http_client_builder(base_url)?;
+        let client = options
+            .apply(builder)
+            .build()
+            .map_err(|e| MycoError::network("failed to create HTTP client", e))?;
+
+        Ok(Self {
+            client,
+            base_url,
+            capabilities: OnceCell::new(),
+            pacer: Mutex::new(Pacer::new()),
+        })
+    }
+
+    /// The server's capabilities. Servers that predate the capabilities handshake are assumed to
+    /// share this build's constants.
+    pub async fn capabilities(&self) -> Result<Capabilities, MycoError> {
+        self.capabilities
+            .get_or_try_init(|| async {
+                let endpoint = "capabilities";
+                let response = self
+                    .client
+                    .get(format!("{}/{}", self.base_url, endpoint))
+                    .send()
+                    .await
+                    .map_err(|e| MycoError::network(endpoint, e))?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(Capabilities::local());
+                }
+                let response: GetCapabilitiesResponse =
+                    Self::decode(endpoint, Self::check(endpoint, response).await?).await?;
+                response.capabilities.validate()?;
+                Ok(response.capabilities)
+            })
+            .await
+            .copied()
+    }
+
+    /// Send a bincoded request to an endpoint and decode the response.
+    pub(crate) async fn post_bincode<T: serde::Serialize, R: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        payload: T,
+    ) -> Result<R, MycoError> {
+        Self::send(endpoint, self.bincode_request(endpoint, &payload)?).await
+    }
+
+    /// Fetch an endpoint and decode the response.
+    pub(crate) async fn get_bincode<R: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &str,
+    ) -> Result<R, MycoError> {
+        let request = self.client.get(format!("{}/{}", self.base_url, endpoint));
+        Self::send(endpoint, request).await
+    }
+
+    /// Send a request whose body is streamed and decode the response.
+    pub(crate) async fn post_streamed<T, R>(
+        &self,
+        endpoint: &str,
+        body: SeqBody<T>,
+        (epoch, chunk_idx): (u64, usize),
+    ) -> Result<R, MycoError>
+    where
+        T: serde::Serialize + Unpin + Send + Sync + 'static,
+        R: serde::de::DeserializeOwned,
+    {
+        let request = self
+            .client
+            .post(format!("{}/{}", self.base_url, endpoint))
+            .header("Content-Type", "application/octet-stream")
+            .header(CHUNK_HEADER, format!("{}/{}", epoch, chunk_idx))
+            .body(reqwest::Body::wrap(body));
+        Self::send(endpoint, request).await
+    }
+
+    /// Send a bincoded request to an endpoint whose response is a bucket list, decoding the
+    /// buckets as they arrive.
+    pub(crate) async fn post_for_buckets<T: serde::Serialize>(
+        &self,
+        endpoint: &str,
+        payload: T,
+    ) -> Result<Vec<Bucket>, MycoError> {
+        let mut response = Self::checked(endpoint, self.bincode_request(endpoint, &payload)?).await?;
+        let mut decoder = SeqDecoder::new();
+        while let Some(chunk) = Self::next_chunk(endpoint, &mut response).await? {
+            decoder.feed(&chunk)?;
+        }
+        let (buckets, _) = decoder.finish()?;
+        Ok(buckets)
+    }
+
+    /// Send a bincoded request to an endpoint that streams its buckets as frames, decoding each
+    /// bucket as soon as its frame is complete.
+    pub(crate) async fn post_for_bucket_frames<T: serde::Serialize>(
+        &self,
+        endpoint: &str,
+        payload: T,
+    ) -> Result<Vec<Bucket>, MycoError> {
+        let mut response = Self::checked(endpoint, self.bincode_request(endpoint, &payload)?).await?;
+        let mut decoder = FrameDecoder::new(ENCODED_BUCKET_SIZE);
+        while let Some(chunk) = Self::next_chunk(endpoint, &mut response).await? {
+            decoder.feed(&chunk)?;
+        }
+        decoder.finish()
+    }
+
+    /// Post a request whose response streams buckets sparsely as [`SparsePathFrame`]s, and put
+    /// the empty buckets back in.
+    pub(crate) async fn post_for_sparse_frames<T: serde::Serialize>(
+        &self,
+        endpoint: &str,
+        payload: T,
+    ) -> Result<Vec<Bucket>, MycoError> {
+        let mut response = Self::checked(endpoint, self.bincode_request(endpoint, &payload)?).await?;
+        // The tag of the frame's variant comes on top of the bucket.
+        let mut decoder = FrameDecoder::new(ENCODED_BUCKET_SIZE + 4);
+        while let Some(chunk) = Self::next_chunk(endpoint, &mut response).await? {
+            decoder.feed(&chunk)?;
+        }
+        let mut frames = decoder.finish()?.into_iter();
+        let Some(SparsePathFrame::Present(len, present)) = frames.next() else {
+            return Err(MycoError::ProtocolError(format!(
+                "{} didn't start with a presence bitmap",
+                endpoint
+            )));
+        };
+        let buckets = frames
+            .map(|frame| match frame {
+                SparsePathFrame::Bucket(bucket) => Ok(bucket),
+                SparsePathFrame::Present(..) => Err(MycoError::ProtocolError(format!(
+                    "{} sent a second presence bitmap",
+                    endpoint
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        SparseBuckets::from_parts(len, present, buckets).into_buckets()
+    }
+
+    fn bincode_request<T: serde::Serialize>(
+        &self,
+        endpoint: &str,
+        payload: &T,
+    ) -> Result<reqwest::RequestBuilder, MycoError> {
+        let request_bytes =
+            bincode::serialize(payload).map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+        Ok(self
+            .client
+            .post(format!("{}/{}", self.base_url, endpoint))
+            .header("Content-Type", "application/octet-stream")
+            .body(request_bytes))
+    }
+
+    async fn next_chunk(
+        endpoint: &str,
+        response: &mut reqwest::Response,
+    ) -> Result<Option<axum::body::Bytes>, MycoError> {
+        response
+            .chunk()
+            .await
+            .map_err(|e| MycoError::network(endpoint, e))
+    }
+
+    /// Send a request, turning a non-success response into the error it carries.
+    async fn checked(
+        endpoint: &str,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, MycoError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| MycoError::network(endpoint, e))?;
+        Self::check(endpoint, response).await
+    }
+
+    /// Turn a non-success response into the error it carries.
+    async fn check(
+        endpoint: &str,
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, MycoError> {
+        let status = response.status();
+        if !status.is_success() {
+            // Servers answer failures with an `ErrorResponse`; anything else (e.g. a proxy's error
+            // page) is reported with its status.
+            let next_epoch_opens_at = response
+                .headers()
+                .get(NEXT_EPOCH_HEADER)
+                .and_then(|value| value.to_str().ok()?.parse().ok());
+            let body = response.bytes().await.unwrap_or_default();
+            if let Ok(error) = bincode::deserialize::<ErrorResponse>(&body) {
+                return Err(error.into_error());
+            }
+            if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+                return Err(MycoError::EpochClosed { next_epoch_opens_at });
+            }
+            return Err(MycoError::ProtocolError(format!(
+                "{} returned {}",
+                endpoint, status
+            )));
+        }
+        Ok(response)
+    }
+
+    async fn send<R: serde::de::DeserializeOwned>(
+        endpoint: &str,
+        request: reqwest::RequestBuilder,
+    ) -> Result<R, MycoError> {
+        Self::decode(endpoint, Self::checked(endpoint, request).await?).await
+    }
+
+    async fn decode<R: serde::de::DeserializeOwned>(
+        endpoint: &str,
+        response: reqwest::Response,
+    ) -> Result<R, MycoError> {
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| MycoError::network(endpoint, e))?;
+        bincode::deserialize(&bytes).map_err(|e| MycoError::DeserializationError(Some(e)))
+    }
+
+    async fn read_paths(&self, indices: Vec<usize>) -> Result<Vec<Bucket>, MycoError> {
+        // Store the pathset first, then fetch it back in parallel chunks.
+        let pathset_size = indices.len();
+        self.post_bincode::<_, StorePathIndicesResponse>(
+            "store_path_indices",
+            StorePathIndicesRequest { pathset: indices },
+        )
+        .await?;
+        self.read_stored_pathset(pathset_size).await
+    }
+
+    async fn read_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>, MycoError> {
+        // Server2 expands the leaves, and tells us how many buckets that came to.
+        let response: StorePathLeavesResponse = self
+            .post_bincode("store_path_leaves", StorePathLeavesRequest { leaves })
+            .await?;
+        self.read_stored_pathset(response.pathset_size).await
+    }
+
+    /// Fetch the pathset stored on Server2 in parallel chunks.
+    async fn read_stored_pathset(&self, pathset_size: usize) -> Result<Vec<Bucket>, MycoError> {
+        let num_chunks = pathset_size.div_ceil(self.capabilities().await?.read_chunk_buckets);
+        let futures = (0..num_chunks).map(|chunk_idx| {
+            self.post_for_sparse_frames("chunk_read_paths", ChunkReadPathsRequest { chunk_idx })
+        });
+        let mut buckets = Vec::new();
+        for response in futures::future::join_all(futures).await {
+            buckets.extend(response?);
+        }
+        Ok(buckets)
+    }
+
+    async fn write(&self, epoch: u64, buckets: Vec<Bucket>, prf_key: Key) -> Result<(), MycoError> {
+        self.write_chunks("chunk_write", epoch, buckets, prf_key).await
+    }
+
+    async fn write_deltas(
+        &self,
+        epoch: u64,
+        deltas: Vec<BucketDelta>,
+        prf_key: Key,
+    ) -> Result<(), MycoError> {
+        self.write_chunks("chunk_write_deltas", epoch, deltas, prf_key).await
+    }
+
+    /// Send the buckets of `epoch`, or their deltas, to `endpoint` in chunks, then finalize the
+    /// epoch.
+    async fn write_chunks<T: serde::Serialize + Unpin + Send + Sync + 'static>(
+        &self,
+        endpoint: &str,
+        epoch: u64,
+        buckets: Vec<T>,
+        prf_key: Key,
+    ) -> Result<(), MycoError> {
+        // Hand each chunk its buckets rather than copies, and stream them out without encoding the
+        // whole chunk first. The body is laid out like a `ChunkWriteRequest`.
+        let chunk_buckets = self.capabilities().await?.write_chunk_buckets;
+        let mut pending = VecDeque::new();
+        let mut buckets = buckets.into_iter().peekable();
+        while buckets.peek().is_some() {
+            let batch: Vec<_> = buckets.by_ref().take(chunk_buckets).collect();
+            let chunk_idx = pending.len();
+            let tail = bincode::serialize(&(chunk_idx, &prf_key, epoch))
+                .map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+            pending.push_back((chunk_idx, Arc::new(batch), tail, 0));
+        }
+
+        // Keep as many chunks in flight as the pacer allows, resending chunks lost to network
+        // errors.
+        let mut in_flight = FuturesUnordered::new();
+        while !pending.is_empty() || !in_flight.is_empty() {
+            let window = self.pacer.lock().unwrap().window();
+            while in_flight.len() < window {
+                let Some((chunk_idx, batch, tail, attempts)) = pending.pop_front() else {
+                    break;
+                };
+                in_flight.push(async move {
+                    let start = Instant::now();
+                    let result = match SeqBody::new(Arc::clone(&batch), tail.clone()) {
+                        Ok(body) => {
+                            self.post_streamed::<_, WriteResponse>(endpoint, body, (epoch, chunk_idx))
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    (result, start.elapsed(), (chunk_idx, batch, tail, attempts))
+                });
+            }
+
+            let Some((result, latency, (chunk_idx, batch, tail, attempts))) = in_flight.next().await
+            else {
+                break;
+            };
+            match result {
+                Ok(_) => self.pacer.lock().unwrap().on_success(latency),
+                Err(e) => {
+                    self.pacer.lock().unwrap().on_failure();
+                    if !matches!(e, MycoError::NetworkError { .. }) || attempts >= MAX_CHUNK_RETRIES {
+                        return Err(e);
+                    }
+                    tracing::warn!("Resending chunk after a network error: {}", e);
+                    pending.push_back((chunk_idx, batch, tail, attempts + 1));
+                }
+            }
+        }
+
+        self.post_bincode::<_, FinalizeEpochResponse>(
+            "finalize_epoch",
+            FinalizeEpochRequest { prf_key, epoch },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for HttpsTransport {
+    async fn call(&self, command: Command) -> Result<Command, MycoError> {
+        match command {
+            Command::Server1Write(ct, f, k_oblv_t, cs, token, access_tag) => {
+                // The key is picked once per write, so resending the request can't queue it twice.
+                let request = QueueWriteRequest {
+                    ct,
+                    f,
+                    k_oblv_t,
+                    cs,
+                    token,
+                    idempotency_key: Some(IdempotencyKey::random(&mut rand::thread_rng())),
+                    access_tag,
+                };
+                let response: QueueWriteResponse =
+                    self.post_bincode("queue_write", request).await?;
+                if response.success {
+                    Ok(Command::Queued(response.epoch))
+                } else {
+                    Err(MycoError::ProtocolError(
+                        "Server1 rejected the write".to_string(),
+                    ))
+                }
+            }
+            Command::Server1GuardMailboxes(guards) => {
+                self.post_bincode::<_, GuardMailboxesResponse>(
+                    "guard_mailboxes",
+                    GuardMailboxesRequest { guards },
+                )
+                .await?;
+                Ok(Command::Success)
+            }
+            Command::Server1IssueWriteTokens(credentials, requests) => {
+                let response: IssueWriteTokensResponse = self
+                    .post_bincode("write_tokens", IssueWriteTokensRequest { credentials, requests })
+                    .await?;
+                Ok(Command::WriteTokens(response.tokens))
+            }
+            Command::Server1Registration(RegistrationType::Register) => {
+                let response: RegisterResponse = self.post_bincode("register", ()).await?;
+                Ok(Command::Registered(response.credentials))
+            }
+            Command::Server1Registration(RegistrationType::Rotate(credentials)) => {
+                let response: RotateRegistrationResponse = self
+                    .post_bincode("register/rotate", RotateRegistrationRequest { credentials })
+                    .await?;
+                Ok(Command::Registered(response.credentials))
+            }
+            Command::Server1Registration(RegistrationType::Delete(credentials)) => {
+                self.post_bincode::<_, DeleteRegistrationResponse>(
+                    "register/delete",
+                    DeleteRegistrationRequest { credentials },
+                )
+                .await?;
+                Ok(Command::Success)
+            }
+            Command::Server2Read(ReadType::Read(path)) => {
+                let response: ReadResponse =
+                    self.post_bincode("read", ReadRequest { path }).await?;
+                Ok(Command::Buckets(response.buckets))
+            }
+            Command::Server2Read(ReadType::ReadPaths(indices)) => {
+                self.read_paths(indices).await.map(Command::Buckets)
+            }
+            Command::Server2Read(ReadType::ReadLeafPaths(leaves)) => {
+                self.read_leaf_paths(leaves).await.map(Command::Buckets)
+            }
+            Command::Server2Read(ReadType::PrefetchLeafPaths(leaves)) => {
+                let buckets = self
+                    .post_for_buckets("prefetch_path_leaves", PrefetchPathLeavesRequest { leaves })
+                    .await?;
+                Ok(Command::Buckets(buckets))
+            }
+            Command::Server2Read(ReadType::ReadPathsClient(indices)) => {
+                let buckets = self
+                    .post_for_buckets("read_paths_client", ReadPathsClientRequest { indices })
+                    .await?;
+                Ok(Command::Buckets(buckets))
+            }
+            Command::Server2Read(ReadType::GetPrfKeys) => {
+                let response: GetPrfKeysResponse = self.get_bincode("get_prf_keys").await?;
+                Ok(Command::PrfKeys(response.keys))
+            }
+            Command::Server2Read(ReadType::GetPrfKeysSince(cursor)) => {
+                let response: GetPrfKeysSinceResponse = self
+                    .post_bincode("get_prf_keys_since", GetPrfKeysSinceRequest { cursor })
+                    .await?;
+                Ok(Command::PrfKeysSince(response.start, response.keys))
+            }
+            Command::Server2Read(ReadType::GetEpoch) => {
+                let response: GetEpochResponse = self.get_bincode("epoch").await?;
+                Ok(Command::Epoch(response.info))
+            }
+            Command::Server2Read(ReadType::GetNotifications) => {
+                let response: GetNotificationsResponse = self.get_bincode("notifications").await?;
+                Ok(Command::Notifications(response.notifications))
+            }
+            Command::Server2Read(ReadType::PendingEpochs(tags)) => {
+                let response: PendingEpochsResponse = self
+                    .post_bincode("pending_epochs", PendingEpochsRequest { tags })
+                    .await?;
+                Ok(Command::PendingEpochs(response.epochs))
+            }
+            Command::Server2Write(WriteType::Write(epoch, buckets, prf_key)) => {
+                self.write(epoch, buckets, prf_key).await?;
+                Ok(Command::Success)
+            }
+            Command::Server2Write(WriteType::WriteDeltas(epoch, deltas, prf_key)) => {
+                self.write_deltas(epoch, deltas, prf_key).await?;
+                Ok(Command::Success)
+            }
+            Command::Server2Write(WriteType::Notifications(index)) => {
+                self.post_bincode::<_, WriteResponse>(
+                    "notifications",
+                    PublishNotificationsRequest { index },
+                )
+                .await?;
+                Ok(Command::Success)
+            }
+            Command::Server2Write(WriteType::Storage(report)) => {
+                self.post_bincode::<_, WriteResponse>("storage", PublishStorageRequest { report })
+                    .await?;
+                Ok(Command::Success)
+            }
+            Command::Server2Write(WriteType::PathLeaves(leaves)) => {
+                self.post_bincode::<_, StorePathLeavesResponse>(
+                    "store_path_leaves",
+                    StorePathLeavesRequest { leaves },
+                )
+                .await?;
+                Ok(Command::Success)
+            }
+            _ => Err(MycoError::InvalidCommand),
+        }
+    }
+}
+
+/// Access to Server1 over any transport.
+pub struct TransportServer1Access {
+    transport: Box<dyn Transport>,
+}
+
+impl TransportServer1Access {
+    /// Create a new TransportServer1Access over the given transport.
+    pub fn new(transport: Box<dyn Transport>) -> Self {
+        Self { transport }
+    }
+
+    /// Register a new pseudonymous account (see [`crate::registration`]).
+    pub async fn register(&self) -> Result<AccountCredentials, MycoError> {
+        expect_registered(
+            self.transport
+                .call(Command::Server1Registration(RegistrationType::Register))
+                .await?,
+        )
+    }
+
+    /// Replace the ID and secret of an account, returning the new credentials.
+    pub async fn rotate_registration(
+        &self,
+        credentials: AccountCredentials,
+    ) -> Result<AccountCredentials, MycoError> {
+        expect_registered(
+            self.transport
+                .call(Command::Server1Registration(RegistrationType::Rotate(credentials)))
+                .await?,
+        )
+    }
+
+    /// Delete an account.
+    pub async fn delete_registration(&self, credentials: AccountCredentials) -> Result<(), MycoError> {
+        expect_success(
+            self.transport
+                .call(Command::Server1Registration(RegistrationType::Delete(credentials)))
+                .await?,
+        )
+    }
+}
+
+#[async_trait]
+impl Server1Access for TransportServer1Access {
+    async fn queue_write(
+        &self,
+        ct: Vec<u8>,
+        f: Vec<u8>,
+        k_oblv_t: Key,
+        cs: Vec<u8>,
+        token: Vec<u8>,
+        access_tag: Option<Vec<u8>>,
+    ) -> Result<u64, MycoError> {
+        expect_queued(
+            self.transport
+                .call(Command::Server1Write(ct, f, k_oblv_t, cs, token, access_tag))
+                .await?,
+        )
+    }
+
+    async fn guard_mailboxes(&self, guards: Vec<MailboxGuard>) -> Result<(), MycoError> {
+        expect_success(
+            self.transport
+                .call(Command::Server1GuardMailboxes(guards))
+                .await?,
+        )
+    }
+
+    async fn issue_write_tokens(
+        &self,
+        credentials: AccountCredentials,
+        requests: Vec<(u64, Vec<u8>)>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, MycoError> {
+        expect_write_tokens(
+            self.transport
+                .call(Command::Server1IssueWriteTokens(credentials, requests))
+                .await?,
+        )
+    }
+}
+
+/// Access to Server2 over any transport.
+pub struct TransportServer2Access {
+    transport: Box<dyn Transport>,
+}
+
+impl TransportServer2Access {
+    /// Create a new TransportServer2Access over the given transport.
+    pub fn new(transport: Box<dyn Transport>) -> Self {
+        Self { transport }
+    }
+
+    async fn read(&self, read: ReadType) -> Result<Vec<Bucket>, MycoError> {
+        expect_buckets(self.transport.call(Command::Server2Read(read)).await?)
+    }
+}
+
+#[async_trait]
+impl Server2Access for TransportServer2Access {
+    async fn read_path(&self, path: Path) -> Result<Vec<Bucket>> {
+        Ok(self.read(ReadType::Read(path)).await?)
+    }
+
+    async fn read_paths(&self, indices: Vec<usize>) -> Result<Vec<Bucket>> {
+        Ok(self.read(ReadType::ReadPaths(indices)).await?)
+    }
+
+    async fn read_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>> {
+        Ok(self.read(ReadType::ReadLeafPaths(leaves)).await?)
+    }
+
+    async fn prefetch_leaf_paths(&self, leaves: Vec<u32>) -> Result<Vec<Bucket>> {
+        Ok(self.read(ReadType::PrefetchLeafPaths(leaves)).await?)
+    }
+
+    async fn store_leaf_paths(&self, leaves: Vec<u32>) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::PathLeaves(leaves)))
+                .await?,
+        )?)
+    }
+
+    async fn read_paths_client(
+        &self,
+        indices: Vec<usize>,
+        _batch_size: usize,
+    ) -> Result<Vec<Bucket>> {
+        Ok(self.read(ReadType::ReadPathsClient(indices)).await?)
+    }
+
+    async fn read_paths_client_chunked(
+        &self,
+        indices: Vec<usize>,
+        _batch_size: usize,
+    ) -> Result<Vec<Bucket>> {
+        Ok(self.read(ReadType::ReadPathsClient(indices)).await?)
+    }
+
+    async fn write(&self, epoch: u64, buckets: Vec<Bucket>, prf_key: Key) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::Write(epoch, buckets, prf_key)))
+                .await?,
+        )?)
+    }
+
+    async fn write_deltas(&self, epoch: u64, deltas: Vec<BucketDelta>, prf_key: Key) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::WriteDeltas(epoch, deltas, prf_key)))
+                .await?,
+        )?)
+    }
+
+    async fn get_prf_keys(&self) -> Result<Vec<Key>> {
+        Ok(expect_prf_keys(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetPrfKeys))
+                .await?,
+        )?)
+    }
+
+    async fn get_prf_keys_since(&self, cursor: u64) -> Result<(u64, Vec<Key>)> {
+        Ok(expect_prf_keys_since(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetPrfKeysSince(cursor)))
+                .await?,
+        )?)
+    }
+
+    async fn get_epoch(&self) -> Result<EpochInfo> {
+        Ok(expect_epoch(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetEpoch))
+                .await?,
+        )?)
+    }
+
+    async fn publish_notifications(&self, index: Vec<u8>) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::Notifications(index)))
+                .await?,
+        )?)
+    }
+
+    async fn get_notifications(&self) -> Result<Option<(u64, Vec<u8>)>> {
+        Ok(expect_notifications(
+            self.transport
+                .call(Command::Server2Read(ReadType::GetNotifications))
+                .await?,
+        )?)
+    }
+
+    async fn pending_epochs(&self, tags: Vec<(u64, Vec<u8>)>) -> Result<Vec<u64>> {
+        Ok(expect_pending_epochs(
+            self.transport
+                .call(Command::Server2Read(ReadType::PendingEpochs(tags)))
+                .await?,
+        )?)
+    }
+
+    async fn publish_storage(&self, report: StorageReport) -> Result<()> {
+        Ok(expect_success(
+            self.transport
+                .call(Command::Server2Write(WriteType::Storage(report)))
+                .await?,
+        )?)
+    }
+}
+
+/// Which transport to use to reach a server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransportConfig {
+    /// The HTTPS endpoints at the given base URL
+    Https {
+        /// Base URL of the server, e.g. `https://127.0.0.1:3003`
+        base_url: String,
+        /// Certificates trusted for the server
+        trust: TlsTrust,
+        /// Tuning of the client's connections
+        options: TransportOptions,
+    },
+    /// The framed transport over plain TCP
+    Tcp {
+        /// Address of the framed listener
+        addr: String,
+    },
+    /// The framed transport over TLS
+    Tls {
+        /// Address of the framed listener
+        addr: String,
+        /// Certificates trusted for the server
+        trust: TlsTrust,
+    },
+}
+
+impl TransportConfig {
+    /// Select a transport from a server address, verifying TLS servers according to `trust`.
+    pub fn from_addr(addr: &str, trust: &TlsTrust) -> Result<Self, MycoError> {
+        if let Some(addr) = addr.strip_prefix(TLS_SCHEME) {
+            if !trust.has_roots() {
+                return Err(MycoError::ConfigError(format!(
+                    "{}{} needs a CA bundle or pinned certificates",
+                    TLS_SCHEME, addr
+                )));
+            }
+            Ok(Self::Tls {
+                addr: addr.to_string(),
+                trust: trust.clone(),
+            })
+        } else if let Some(addr) = addr.strip_prefix(TCP_SCHEME) {
+            Ok(Self::Tcp {
+                addr: addr.to_string(),
+            })
+        } else if addr.starts_with("https://") || addr.starts_with("http://") {
+            Ok(Self::Https {
+                base_url: addr.to_string(),
+                trust: trust.clone(),
+                options: TransportOptions::default(),
+            })
+        } else {
+            Err(MycoError::ConfigError(format!(
+                "unsupported server address: {}",
+                addr
+            )))
+        }
+    }
+
+    /// Tune the HTTPS client's connections with `options`. The framed transports ignore them.
+    pub fn with_options(mut self, options: TransportOptions) -> Self {
+        if let Self::Https { options: current, .. } = &mut self {
+            *current = options;
+        }
+        self
+    }
+
+    /// Connect to the server.
+    pub async fn connect(&self) -> Result<Box<dyn Transport>, MycoError> {
+        Ok(match self {
+            Self::Https {
+                base_url,
+                trust,
+                options,
+            } => Box::new(HttpsTransport::with_options(base_url, trust, options)?),
+            Self::Tcp { addr } => Box::new(FramedConnection::connect(addr).await?),
+            Self::Tls { addr, trust } => {
+                let host = addr
+                    .rsplit_once(':')
+                    .map_or(addr.as_str(), |(host, _)| host);
+                let server_name =
+                    trust.server_name_for(host.trim_start_matches('[').trim_end_matches(']'));
+                Box::new(
+                    FramedConnection::connect_tls(addr, &trust.connector()?, server_name).await?,
+                )
+            }
+        })
+    }
+
+    /// Connect to Server1. HTTPS keeps using [`RemoteServer1Access`] for its request metrics.
+    pub async fn server1_access(&self) -> Result<Box<dyn Server1Access>, MycoError> {
+        Ok(match self {
+            Self::Https {
+                base_url,
+                trust,
+                options,
+            } => Box::new(RemoteServer1Access::new(base_url, trust, options).await?),
+            _ => Box::new(TransportServer1Access::new(self.connect().await?)),
+        })
+    }
+
+    /// Connect to Server2. HTTPS keeps using [`RemoteServer2Access`] for its chunked client reads
+    /// and request metrics.
+    pub async fn server2_access(&self) -> Result<Box<dyn Server2Access>, MycoError> {
+        Ok(match self {
+            Self::Https {
+                base_url,
+                trust,
+                options,
+            } => Box::new(RemoteServer2Access::new(base_url, trust, options).await?),
+            _ => Box::new(TransportServer2Access::new(self.connect().await?)),
+        })
+    }
+}
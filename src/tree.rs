@@ -4,9 +4,35 @@
 //! - `BinaryTree<T>`: A dense binary tree implementation that stores values of type T
 //! - `SparseBinaryTree<T>`: A sparse binary tree that only stores non-empty nodes
 //! - `TreeValue`: A trait for values that can be stored in binary trees
+//!
+//! Both trees address their nodes the same way: the root has index [`ROOT_INDEX`] and the
+//! children of node `i` are `2i` (left) and `2i + 1` (right), so a [`Path`] of directions from
+//! the root names exactly one index. [`child_index`], [`parent_index`], [`depth_of`],
+//! [`path_index`] and [`path_indices`] do this index math, and are what Server1's pathset and
+//! Server2's chunked reads are expressed in.
+//!
+//! ```
+//! use myco_rs::{
+//!     dtypes::{Direction, Path},
+//!     tree::{self, BinaryTree, SparseBinaryTree},
+//! };
+//!
+//! let path = Path::new(vec![Direction::Left, Direction::Right]);
+//! assert_eq!(tree::path_index(&path), 5);
+//! assert_eq!(tree::path_indices(&path).collect::<Vec<_>>(), vec![1, 2, 5]);
+//!
+//! let mut dense = BinaryTree::new_with_depth(2);
+//! dense.write("leaf", path.clone());
+//! assert_eq!(dense.get(&path), Some("leaf"));
+//!
+//! // A sparse tree holding the same node, e.g. the part of a tree read for a pathset.
+//! let sparse = SparseBinaryTree::new_with_data(vec!["leaf"], vec![5]);
+//! assert_eq!(sparse.iter().collect::<Vec<_>>(), dense.iter().collect::<Vec<_>>());
+//! ```
 
 use std::{
     cmp::max,
+    collections::HashMap,
     fmt::{self, Debug},
     fs::File,
     io::{Read, Write},
@@ -14,7 +40,79 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::dtypes::{Bucket, Metadata, Path};
+use crate::{
+    constants::{BLOCK_SIZE, D, Z},
+    dtypes::{Bucket, Direction, Metadata, Path},
+    error::MycoError,
+};
+
+/// Index of the root node in both tree types. Index 0 is unused.
+pub const ROOT_INDEX: usize = 1;
+
+/// Index of the child of node `index` in `direction`.
+pub fn child_index(index: usize, direction: Direction) -> usize {
+    2 * index + u8::from(direction) as usize
+}
+
+/// Index of the parent of node `index`, or `None` for the root.
+pub fn parent_index(index: usize) -> Option<usize> {
+    (index > ROOT_INDEX).then_some(index / 2)
+}
+
+/// Depth of node `index`, the root being at depth 0.
+///
+/// # Panics
+/// Panics for index 0, which isn't a node.
+pub fn depth_of(index: usize) -> usize {
+    index.ilog2() as usize
+}
+
+/// Index of the node reached by following `path` from the root, see [`Path::to_index`].
+pub fn path_index(path: &Path) -> usize {
+    path.to_index()
+}
+
+/// Indices of the nodes along `path`, from the root down to [`path_index`].
+///
+/// ```
+/// use myco_rs::{dtypes::{Direction, Path}, tree};
+///
+/// let path = Path::new(vec![Direction::Right, Direction::Right]);
+/// let indices: Vec<usize> = tree::path_indices(&path).collect();
+/// assert_eq!(indices, vec![1, 3, 7]);
+/// assert_eq!(tree::parent_index(7), Some(3));
+/// assert_eq!(tree::depth_of(7), path.len());
+/// ```
+pub fn path_indices(path: &Path) -> impl Iterator<Item = usize> + '_ {
+    std::iter::once(ROOT_INDEX).chain(path.into_iter().scan(ROOT_INDEX, |index, &direction| {
+        *index = child_index(*index, direction);
+        Some(*index)
+    }))
+}
+
+/// Splits `indices` into runs of consecutive indices, returned as the ranges of indices they
+/// cover, in order. Sorted pathsets (see [`crate::utils::get_path_indices`]) have long runs
+/// near the root, where every node of a level is present.
+///
+/// ```
+/// use myco_rs::tree;
+///
+/// let runs: Vec<_> = tree::contiguous_runs(&[1, 2, 3, 5, 6, 11]).collect();
+/// assert_eq!(runs, vec![1..4, 5..7, 11..12]);
+/// ```
+pub fn contiguous_runs(indices: &[usize]) -> impl Iterator<Item = std::ops::Range<usize>> + '_ {
+    let mut rest = indices;
+    std::iter::from_fn(move || {
+        let (&start, _) = rest.split_first()?;
+        let len = rest
+            .iter()
+            .enumerate()
+            .take_while(|&(offset, &index)| index == start + offset)
+            .count();
+        rest = &rest[len..];
+        Some(start..start + len)
+    })
+}
 
 /// A binary tree implementation that stores values of type T.
 /// 
@@ -24,6 +122,9 @@ use crate::dtypes::{Bucket, Metadata, Path};
 /// - For any node at index i:
 ///   - Left child is at index 2i
 ///   - Right child is at index 2i + 1
+///
+/// Nodes aren't allocated individually: walking a path reads one contiguous vector, which is
+/// what keeps Server2's full-depth tree cache-friendly.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BinaryTree<T> {
     /// Vector storing the tree nodes, with None representing empty nodes
@@ -36,7 +137,7 @@ pub trait TreeValue: Clone + Debug + PartialEq + Default {
     fn new_random() -> Self;
 }
 
-impl<T: TreeValue> BinaryTree<T> {
+impl<T: Clone> BinaryTree<T> {
     /// Creates a new binary tree with a single value at the root
     pub fn new(value: T) -> Self {
         BinaryTree {
@@ -68,11 +169,11 @@ impl<T: TreeValue> BinaryTree<T> {
 
     /// Inserts values along a path in the tree
     pub fn insert_path(&mut self, path: Path, values: Vec<T>) {
-        let mut idx: usize = 1;
-        self.value[1] = Some(values[0].clone());
+        let mut idx: usize = ROOT_INDEX;
+        self.value[ROOT_INDEX] = Some(values[0].clone());
 
         for (direction, value) in path.zip(&values[1..]) {
-            idx = 2 * idx + u8::from(direction) as usize;
+            idx = child_index(idx, direction);
             if idx + 1 >= self.value.len() {
                 self.value.resize((idx + 1).next_power_of_two(), None);
             }
@@ -81,10 +182,7 @@ impl<T: TreeValue> BinaryTree<T> {
     }
 
     /// Creates a tree from a vector of (values, path) pairs
-    pub fn from_vec_with_paths(items: Vec<(Vec<T>, Path)>) -> Self
-    where
-        T: TreeValue,
-    {
+    pub fn from_vec_with_paths(items: Vec<(Vec<T>, Path)>) -> Self {
         let mut tree = BinaryTree::new_empty();
         for (values, path) in items {
             tree.insert_path(path, values);
@@ -111,34 +209,57 @@ impl<T: TreeValue> BinaryTree<T> {
 
     /// Gets the value at a given path
     pub fn get(&self, path: &Path) -> Option<T> {
-        let mut current = self.value[1].clone();
-        let mut idx = 1;
-        for &direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
-            if idx >= self.value.len() {
-                return None;
-            }
-            current = self.value[idx].clone();
-        }
-        current
+        self.get_by_index(path_index(path)).cloned()
+    }
+
+    /// Gets the value at a given index, see [`path_index`]
+    pub fn get_by_index(&self, index: usize) -> Option<&T> {
+        self.value.get(index)?.as_ref()
+    }
+
+    /// Gets a mutable reference to the value at a given index
+    pub fn get_by_index_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.value.get_mut(index)?.as_mut()
     }
 
-    /// Gets the index for a given path
+    /// Gets the index for a given path, or the root's index if the path leaves the tree
     pub fn get_index(&self, path: &Path) -> usize {
-        let mut idx = 1;
-        for &direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
-            if idx >= self.value.len() {
-                return 1;
-            }
+        match path_index(path) {
+            idx if idx < self.value.len() => idx,
+            _ => ROOT_INDEX,
         }
-        idx
+    }
+
+    /// Number of non-empty nodes
+    pub fn len(&self) -> usize {
+        self.value.iter().flatten().count()
+    }
+
+    /// Whether every node is empty
+    pub fn is_empty(&self) -> bool {
+        self.value.iter().all(Option::is_none)
+    }
+
+    /// Iterates over the non-empty nodes and their indices, in index order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.value
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, value)| value.as_ref().map(|value| (idx, value)))
+    }
+
+    /// Iterates mutably over the non-empty nodes and their indices, in index order
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.value
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, value)| value.as_mut().map(|value| (idx, value)))
     }
 
     /// Gets all nodes along a given path
     pub fn get_all_nodes_along_path(&self, path: &Path) -> Vec<T> {
         let mut nodes = vec![];
-        let mut idx = 1;
+        let mut idx = ROOT_INDEX;
 
         // Include the root node if it has a value
         if let Some(value) = &self.value[1] {
@@ -146,7 +267,7 @@ impl<T: TreeValue> BinaryTree<T> {
         }
 
         for &direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
+            idx = child_index(idx, direction);
 
             if idx >= self.value.len() || self.value[idx].is_none() {
                 return nodes;
@@ -160,10 +281,10 @@ impl<T: TreeValue> BinaryTree<T> {
     /// Finds the lowest common ancestor for a given path
     pub fn lca(&mut self, path: &Path) -> Option<(&mut T, Path)> {
         let mut current_path = Path::new(Vec::new());
-        let mut idx = 1;
+        let mut idx = ROOT_INDEX;
 
         for &direction in path {
-            let next_idx = 2 * idx + u8::from(direction) as usize;
+            let next_idx = child_index(idx, direction);
             if next_idx >= self.value.len() || self.value[next_idx].is_none() {
                 return self.value[idx].as_mut().map(|value| (value, current_path));
             }
@@ -176,9 +297,9 @@ impl<T: TreeValue> BinaryTree<T> {
 
     /// Writes a value at a given path
     pub fn write(&mut self, value: T, path: Path) {
-        let mut idx = 1;
+        let mut idx = ROOT_INDEX;
         for direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
+            idx = child_index(idx, direction);
             if idx >= self.value.len() {
                 self.value.resize((idx + 1).next_power_of_two(), None);
             }
@@ -222,6 +343,9 @@ impl<T: TreeValue> BinaryTree<T> {
     }
 
     /// Zips this tree with another tree, returning tuples of values and paths
+    ///
+    /// Only the nodes that are non-empty in `self` are returned, paired with the node at the same
+    /// index in `rhs`, if any. The path is built from the index with `Path::from`.
     pub fn zip<S: Clone>(&self, rhs: &BinaryTree<S>) -> Vec<(Option<T>, Option<S>, Path)> {
         let len = max(self.value.len(), rhs.value.len());
         let mut lhs = self.value.clone();
@@ -266,42 +390,92 @@ impl fmt::Display for BinaryTree<Bucket> {
 /// only stores the non-empty nodes in a compressed format using:
 /// - packed_buckets: Vector of actual values
 /// - packed_indices: Vector of indices where those values belong
+///
+/// A map from index to packed position is built alongside, so lookups and LCA searches don't
+/// scan `packed_indices`. The tree's methods keep it up to date; if `packed_indices` is changed
+/// directly, lookups fall back to scanning until the tree is rebuilt with `new_with_data`.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PackedSparseTree<T>")]
 pub struct SparseBinaryTree<T> {
     /// List of non-None buckets
     pub packed_buckets: Vec<T>,
     /// Indices corresponding to these buckets
     pub packed_indices: Vec<usize>,
+    /// Position of each index in `packed_indices`
+    #[serde(skip)]
+    positions: HashMap<usize, usize>,
+}
+
+/// The serialized fields of a [`SparseBinaryTree`], from which the position map is rebuilt.
+#[derive(Deserialize)]
+struct PackedSparseTree<T> {
+    packed_buckets: Vec<T>,
+    packed_indices: Vec<usize>,
+}
+
+impl<T> From<PackedSparseTree<T>> for SparseBinaryTree<T> {
+    fn from(packed: PackedSparseTree<T>) -> Self {
+        let mut positions = HashMap::with_capacity(packed.packed_indices.len());
+        for (pos, &index) in packed.packed_indices.iter().enumerate() {
+            // Keep the first position of a repeated index, as a scan would find it.
+            positions.entry(index).or_insert(pos);
+        }
+        SparseBinaryTree {
+            packed_buckets: packed.packed_buckets,
+            packed_indices: packed.packed_indices,
+            positions,
+        }
+    }
 }
 
-impl<T> SparseBinaryTree<T>
-where
-    T: Clone + Debug + PartialEq + Default,
-{
+impl<T> SparseBinaryTree<T> {
+    /// Number of entries the index map has room for, for memory accounting.
+    pub(crate) fn position_capacity(&self) -> usize {
+        self.positions.capacity()
+    }
+}
+
+impl<T: Clone> SparseBinaryTree<T> {
     /// Creates a new empty sparse binary tree
     pub fn new() -> Self {
         SparseBinaryTree {
             packed_buckets: Vec::new(),
             packed_indices: Vec::new(),
+            positions: HashMap::new(),
         }
     }
 
     /// Creates a new sparse binary tree with provided packed_buckets and packed_indices
     pub fn new_with_data(packed_buckets: Vec<T>, packed_indices: Vec<usize>) -> Self {
-        SparseBinaryTree {
+        PackedSparseTree {
             packed_buckets,
             packed_indices,
         }
+        .into()
     }
 
-    // Helper function to add a bucket and its index
-    fn add_bucket(&mut self, index: usize, value: T) {
+    /// Position of `index` in `packed_indices`.
+    fn position(&self, index: usize) -> Option<usize> {
+        // The map misses indices pushed directly onto `packed_indices` (or repeated ones), which
+        // shows in its size.
+        if self.positions.len() == self.packed_indices.len() {
+            self.positions.get(&index).copied()
+        } else {
+            self.packed_indices.iter().position(|&i| i == index)
+        }
+    }
+
+    /// Set the value at the given index, replacing the value already there
+    pub fn insert(&mut self, index: usize, value: T) {
         // Check if the index already exists
-        if let Some(pos) = self.packed_indices.iter().position(|&i| i == index) {
+        if let Some(pos) = self.position(index) {
             // If it exists, replace the corresponding value in packed_buckets
             self.packed_buckets[pos] = value;
         } else {
             // Otherwise, add the new value and index
+            if self.positions.len() == self.packed_indices.len() {
+                self.positions.insert(index, self.packed_indices.len());
+            }
             self.packed_buckets.push(value);
             self.packed_indices.push(index);
         }
@@ -309,63 +483,70 @@ where
 
     /// Retrieve the value at the given path in a sparse binary tree
     pub fn get(&self, path: &Path) -> Option<&T> {
-        let mut idx = 1; // Start at the root
-        for &direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
-        }
-        self.get_by_index(idx)
+        self.get_by_index(path_index(path))
     }
 
     /// Get the index for a given path
     pub fn get_index(&self, path: &Path) -> usize {
-        let mut idx = 1; // Start at the root
-        for &direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
-        }
-        idx
+        path_index(path)
     }
 
     /// Retrieves value by index
     pub fn get_by_index(&self, index: usize) -> Option<&T> {
-        self.packed_indices
-            .iter()
-            .position(|&i| i == index)
-            .map(|pos| &self.packed_buckets[pos])
+        self.position(index).map(|pos| &self.packed_buckets[pos])
     }
 
     /// Retrieves a mutable reference to the bucket by index
     pub fn get_by_index_mut(&mut self, index: usize) -> Option<&mut T> {
-        self.packed_indices
-            .iter()
-            .position(|&i| i == index)
+        self.position(index)
             .map(move |pos| &mut self.packed_buckets[pos]) // Returns mutable reference
     }
 
     /// Write a value into the sparse tree at the specified path
     pub fn write(&mut self, value: T, path: Path) {
-        let mut idx = 1; // Start at the root
+        self.insert(path_index(&path), value);
+    }
 
-        for direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
-        }
+    /// Number of non-empty nodes
+    pub fn len(&self) -> usize {
+        self.packed_indices.len()
+    }
 
-        self.add_bucket(idx, value);
+    /// Whether the tree has no non-empty nodes
+    pub fn is_empty(&self) -> bool {
+        self.packed_indices.is_empty()
     }
 
-    /// Find the lowest common ancestor (LCA) index of a given path
-    pub fn lca_idx(&self, path: &Path) -> Option<(usize, Path)> {
-        let mut current_path = Path::new(Vec::new());
-        let mut idx = 1; // Start at the root
+    /// Iterates over the nodes and their indices, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.packed_indices.iter().copied().zip(&self.packed_buckets)
+    }
 
-        for &direction in path {
-            let next_idx = 2 * idx + u8::from(direction) as usize;
-            if self.get_by_index(next_idx).is_none() {
-                return Some((idx, current_path.clone()));
+    /// Iterates mutably over the nodes and their indices, in the order they were added
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.packed_indices.iter().copied().zip(&mut self.packed_buckets)
+    }
+
+    /// Depth and index of the deepest node along `path` whose ancestors below the root are all
+    /// present, i.e. where the walk down `path` leaves the tree.
+    fn lca_depth(&self, path: &Path) -> (usize, usize) {
+        let mut idx = ROOT_INDEX;
+        for (depth, &direction) in path.into_iter().enumerate() {
+            let next_idx = child_index(idx, direction);
+            if self.position(next_idx).is_none() {
+                return (depth, idx);
             }
             idx = next_idx;
-            current_path.push(direction);
         }
-        Some((idx, current_path.clone()))
+        (path.len(), idx)
+    }
+
+    /// Find the lowest common ancestor (LCA) index of a given path
+    ///
+    /// Takes one map lookup per level, so it costs O(D) whatever the size of the tree.
+    pub fn lca_idx(&self, path: &Path) -> Option<(usize, Path)> {
+        let (depth, idx) = self.lca_depth(path);
+        Some((idx, Path::new(path.0[..depth].to_vec())))
     }
 
     /// Find the lowest common ancestor (LCA) of a given path and return a mutable reference to its value
@@ -379,30 +560,16 @@ where
     ///   - A mutable reference to the LCA node's value
     ///   - The path from root to the LCA node
     pub fn lca(&mut self, path: &Path) -> Option<(&mut T, Path)> {
-        // Initialize empty path starting from root
-        let mut current_path = Path::new(Vec::new());
-        let mut idx = 1; // Start at the root
-
-        // Traverse down the tree following the given path
-        for &direction in path {
-            let next_idx = 2 * idx + u8::from(direction) as usize;
-            // If next node doesn't exist, current node is the LCA
-            if self.get_by_index(next_idx).is_none() {
-                return self
-                    .get_by_index_mut(idx)
-                    .map(|value| (value, current_path.clone()));
-            }
-            // Continue down the path
-            idx = next_idx;
-            current_path.push(direction);
-        }
-
-        // If we reach the end of the path, that node is the LCA
+        let (depth, idx) = self.lca_depth(path);
         self.get_by_index_mut(idx)
-            .map(|value| (value, current_path.clone()))
+            .map(|value| (value, Path::new(path.0[..depth].to_vec())))
     }
 
     /// Zips two sparse binary trees together
+    ///
+    /// Nodes are paired by their position in the packed vectors, not by index, so both trees
+    /// should have been built over the same indices in the same order, as Server1 does for its
+    /// bucket and metadata trees. The path is that of the node in `self`.
     pub fn zip<S: Clone>(&self, rhs: &SparseBinaryTree<S>) -> Vec<(Option<T>, Option<S>, Path)> {
         let mut results = Vec::new();
 
@@ -422,6 +589,10 @@ where
     }
 
     /// Returns an iterator that zips two sparse binary trees together with mutable references
+    ///
+    /// # Panics
+    /// Panics if the trees have a different number of nodes, or, while iterating, if nodes at
+    /// the same position have different indices.
     pub fn zip_mut<'a, S>(
         &'a mut self,
         rhs: &'a mut SparseBinaryTree<S>,
@@ -430,6 +601,8 @@ where
     }
 
     /// Zips this sparse tree with a regular binary tree
+    ///
+    /// Every node of `self` is paired with the node at the same index in `rhs`, if any.
     pub fn zip_with_binary_tree<S: Clone>(
         &self,
         rhs: &BinaryTree<S>,
@@ -460,7 +633,7 @@ where
     /// Gets all nodes along a given path
     pub fn get_all_nodes_along_path(&self, path: &Path) -> Vec<&T> {
         let mut nodes = Vec::new();
-        let mut idx = 1;  // Start at root
+        let mut idx = ROOT_INDEX;
 
         // Check root
         if let Some(value) = self.get_by_index(idx) {
@@ -469,7 +642,7 @@ where
 
         // Check each node along the path
         for &direction in path {
-            idx = 2 * idx + u8::from(direction) as usize;
+            idx = child_index(idx, direction);
             if let Some(value) = self.get_by_index(idx) {
                 nodes.push(value);
             }
@@ -479,6 +652,12 @@ where
     }
 }
 
+impl<T: Clone> Default for SparseBinaryTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Helper struct to track which parts of the slices we're working with
 struct SlicePair<'a, T, S> {
     left_indices: &'a [usize],
@@ -523,7 +702,7 @@ impl<'a, T, S> Iterator for ZipMutIterator<'a, T, S> {
     /// - Optional mutable reference to left tree bucket
     /// - Optional mutable reference to right tree bucket  
     /// - Path representing the current position
-    type Item = (Box<Option<&'a mut T>>, Box<Option<&'a mut S>>, Path);
+    type Item = (Option<&'a mut T>, Option<&'a mut S>, Path);
 
     /// Advances the iterator and returns the next pair of corresponding buckets
     fn next(&mut self) -> Option<Self::Item> {
@@ -551,17 +730,126 @@ impl<'a, T, S> Iterator for ZipMutIterator<'a, T, S> {
 
         // Indices should match since we verified equal lengths in new()
         if left_idx == right_idx {
-            Some((
-                Box::new(Some(left_bucket)),
-                Box::new(Some(right_bucket)), 
-                Path::from(*left_idx),
-            ))
+            Some((Some(left_bucket), Some(right_bucket), Path::from(*left_idx)))
         } else {
             panic!("Indices don't match in the same-length trees.");
         }
     }
 }
 
+/// Prefix of versioned state files. Files written before versioning start directly with the
+/// bincoded state.
+const STATE_MAGIC: [u8; 8] = *b"MYCOSTAT";
+
+/// Schema version of the state files written by this build.
+///
+/// - Version 1: the bincoded state without a header, written before versioning.
+/// - Version 2: [`STATE_MAGIC`], a bincoded [`StateHeader`], then the bincoded state.
+///
+/// Version 1 files are migrated on load by checking the tree against the parameters the caller
+/// expects, since they don't record their own. Saving them again writes the current version.
+pub const STATE_SCHEMA_VERSION: u32 = 2;
+
+/// The build parameters a serialized tree depends on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateParams {
+    /// Depth of the tree
+    pub depth: usize,
+    /// Maximum number of blocks in a bucket
+    pub bucket_size: usize,
+    /// Size of an encrypted block in bytes
+    pub block_size: usize,
+}
+
+impl StateParams {
+    /// The parameters of this build.
+    pub fn local() -> Self {
+        Self {
+            depth: D,
+            bucket_size: Z,
+            block_size: BLOCK_SIZE,
+        }
+    }
+
+    /// Check that state written with these parameters can be loaded where `expected` is needed.
+    pub fn check_compatible(&self, expected: &StateParams) -> Result<(), MycoError> {
+        if self != expected {
+            return Err(MycoError::IncompatibleState(format!(
+                "state was written with {:?}, expected {:?}",
+                self, expected
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that the shape of `tree` fits these parameters. Used for version 1 files, which
+    /// don't record their parameters. Block contents aren't checked, as their size depends on
+    /// the encryption features of the writer.
+    pub fn check_tree<T>(&self, tree: &BinaryTree<T>) -> Result<(), MycoError> {
+        if tree.value.len() != 1 << (self.depth + 1) {
+            return Err(MycoError::IncompatibleState(format!(
+                "tree of {} nodes does not have depth {}",
+                tree.value.len(),
+                self.depth
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that no bucket of `tree` holds more than `bucket_size` blocks.
+    pub fn check_buckets(&self, tree: &BinaryTree<Bucket>) -> Result<(), MycoError> {
+        self.check_tree(tree)?;
+        if let Some(bucket) = tree.value.iter().flatten().find(|b| b.len() > self.bucket_size) {
+            return Err(MycoError::IncompatibleState(format!(
+                "bucket of {} blocks exceeds bucket size {}",
+                bucket.len(),
+                self.bucket_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Header of a versioned state file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateHeader {
+    /// Schema version the file was written with
+    pub version: u32,
+    /// Parameters of the build that wrote the file
+    pub params: StateParams,
+}
+
+/// Encode `state` behind a header of the current schema version.
+pub fn encode_state<T: Serialize>(params: StateParams, state: &T) -> Result<Vec<u8>, MycoError> {
+    let header = StateHeader {
+        version: STATE_SCHEMA_VERSION,
+        params,
+    };
+    let mut bytes = STATE_MAGIC.to_vec();
+    bincode::serialize_into(&mut bytes, &header)
+        .and_then(|()| bincode::serialize_into(&mut bytes, state))
+        .map_err(|e| MycoError::SerializationFailed(Some(e)))?;
+    Ok(bytes)
+}
+
+/// Split a state file into its header and the encoded state. Version 1 files have no header.
+///
+/// Fails for files written by a newer schema version.
+pub fn decode_state_header(bytes: &[u8]) -> Result<(Option<StateHeader>, &[u8]), MycoError> {
+    let Some(mut rest) = bytes.strip_prefix(&STATE_MAGIC) else {
+        return Ok((None, bytes));
+    };
+    let header: StateHeader = bincode::deserialize_from(&mut rest)
+        .map_err(|e| MycoError::DeserializationError(Some(e)))?;
+    if header.version > STATE_SCHEMA_VERSION {
+        return Err(MycoError::IncompatibleState(format!(
+            "state was written with schema version {}, this build reads up to version {}",
+            header.version, STATE_SCHEMA_VERSION
+        )));
+    }
+    Ok((Some(header), rest))
+}
+
 /// State of the database trees
 #[derive(Serialize, Deserialize)]
 struct DBState {
@@ -583,40 +871,67 @@ pub struct DBStateParams {
     pub timestamp: u64,
 }
 
+impl DBStateParams {
+    fn file_path(&self) -> String {
+        format!("{}/{}.bin", self.dir_path(), self.timestamp)
+    }
+
+    fn dir_path(&self) -> String {
+        format!(
+            "db/state_{}_{}_{}_{}",
+            self.bucket_size, self.num_iters, self.depth, self.num_clients
+        )
+    }
+
+    /// The parameters the trees are checked against.
+    fn state_params(&self) -> StateParams {
+        StateParams {
+            depth: self.depth,
+            bucket_size: self.bucket_size,
+            block_size: BLOCK_SIZE,
+        }
+    }
+}
+
 /// Serialize the trees into a file.
 ///
-/// State is saved in the format state_{bucket_size}_{num_iters}_{depth}_{num_clients}.bin to db/
+/// State is saved in the format state_{bucket_size}_{num_iters}_{depth}_{num_clients}.bin to db/,
+/// behind a header of the current [`STATE_SCHEMA_VERSION`].
 pub fn serialize_trees(
     tree: &BinaryTree<Bucket>,
     metadata: &BinaryTree<Metadata>,
     params: &DBStateParams,
-) {
+) -> Result<(), MycoError> {
     let db_state = DBState {
         tree: tree.clone(),
         metadata: metadata.clone(),
     };
-    let serialized_db_state = bincode::serialize(&db_state).unwrap();
-    let dir_path = format!(
-        "db/state_{}_{}_{}_{}",
-        params.bucket_size, params.num_iters, params.depth, params.num_clients
-    );
-    std::fs::create_dir_all(&dir_path).unwrap();
-    let file_path = format!("{}/{}.bin", dir_path, params.timestamp);
-    let mut file = File::create(file_path).unwrap();
-    file.write_all(&serialized_db_state).unwrap();
+    let bytes = encode_state(params.state_params(), &db_state)?;
+    std::fs::create_dir_all(params.dir_path())?;
+    let mut file = File::create(params.file_path())?;
+    file.write_all(&bytes)?;
+    Ok(())
 }
 
-/// Deserialize the trees from a file.
-pub fn deserialize_trees(params: &DBStateParams) -> (BinaryTree<Bucket>, BinaryTree<Metadata>) {
-    let mut file = File::open(format!(
-        "db/state_{}_{}_{}_{}/{}.bin",
-        params.bucket_size, params.num_iters, params.depth, params.num_clients, params.timestamp
-    ))
-    .unwrap();
+/// Deserialize the trees from a file, checking that they were written with `params`.
+pub fn deserialize_trees(
+    params: &DBStateParams,
+) -> Result<(BinaryTree<Bucket>, BinaryTree<Metadata>), MycoError> {
+    let mut file = File::open(params.file_path())?;
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).unwrap();
+    file.read_to_end(&mut buffer)?;
 
-    let db_state: DBState = bincode::deserialize(&buffer).unwrap();
+    let expected = params.state_params();
+    let (header, state) = decode_state_header(&buffer)?;
+    if let Some(header) = header {
+        header.params.check_compatible(&expected)?;
+    }
+    let db_state: DBState =
+        bincode::deserialize(state).map_err(|e| MycoError::DeserializationError(Some(e)))?;
+    if header.is_none() {
+        expected.check_buckets(&db_state.tree)?;
+        expected.check_tree(&db_state.metadata)?;
+    }
 
-    (db_state.tree, db_state.metadata)
+    Ok((db_state.tree, db_state.metadata))
 }
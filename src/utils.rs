@@ -1,17 +1,18 @@
 //! Utility functions for the Myco protocol.
 
 use crate::{
+    constants::{D, LENGTH_PREFIX_SIZE},
     dtypes::*,
-    tree::BinaryTree,
+    error::MycoError,
+    tree::{self, BinaryTree},
     crypto::decrypt,
+    simulation::SimulationMode,
 };
 
-use std::{
-    collections::HashSet,
-    fs,
-    path::Path as StdPath,
-    process::Command,
-};
+use rcgen::{CertificateParams, DnType, KeyPair};
+use rustls::{Certificate, PrivateKey};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path as StdPath};
 
 
 /// Pads a message to a target length by appending zeros.
@@ -47,31 +48,201 @@ pub fn trim_zeros(buffer: &[u8]) -> Vec<u8> {
     buf.into_iter().rev().collect()
 }
 
+/// How a plaintext is padded to a fixed length before it's encrypted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Padding {
+    /// Trailing zeros, removed again with [`trim_zeros`]. Loses any trailing zeros of the
+    /// plaintext itself, so it's only kept for plaintexts written that way.
+    Zeros,
+    /// A big-endian [`LENGTH_PREFIX_SIZE`]-byte length prefix, followed by the plaintext and zeros.
+    /// The plaintext comes back byte for byte.
+    #[default]
+    LengthPrefixed,
+    /// Like `LengthPrefixed`, but the prefix only records the plaintext length rounded up to its
+    /// PADMÉ size (see [`padme_length`]), and the plaintext comes back with the zeros up to that
+    /// size. Readers learn the size bucket rather than the exact length, so this is meant for
+    /// self-delimiting plaintexts such as envelopes.
+    Padme,
+}
+
+/// Rounds `length` up to its PADMÉ size: all but the top `log2(log2(length)) + 1` bits are
+/// cleared, which wastes at most 12% of the padded size while leaving only `O(log log length)`
+/// bits of the length to leak.
+pub fn padme_length(length: usize) -> usize {
+    if length < 2 {
+        return length;
+    }
+    let exponent = length.ilog2();
+    let significant = exponent.ilog2() + 1;
+    let mask = (1usize << (exponent - significant)) - 1;
+    (length + mask) & !mask
+}
+
+/// Pads a plaintext to `target_length` with the given scheme.
+///
+/// # Arguments
+/// * `message` - The plaintext to pad
+/// * `target_length` - The length after padding
+/// * `padding` - The padding scheme
+///
+/// # Returns
+/// The padded plaintext, or an error if the plaintext doesn't fit
+pub fn pad(message: &[u8], target_length: usize, padding: Padding) -> Result<Vec<u8>, MycoError> {
+    let length = match padding {
+        Padding::Zeros => return Ok(pad_message(message, target_length)),
+        Padding::LengthPrefixed => message.len(),
+        Padding::Padme => padme_length(message.len()).min(target_length - LENGTH_PREFIX_SIZE),
+    };
+    if LENGTH_PREFIX_SIZE + message.len() > target_length {
+        return Err(MycoError::ProtocolError(format!(
+            "plaintext of {} bytes exceeds the {} bytes a padded message holds",
+            message.len(),
+            target_length - LENGTH_PREFIX_SIZE
+        )));
+    }
+    let prefix = (length as u16).to_be_bytes();
+    Ok(pad_message(&[&prefix[..], message].concat(), target_length))
+}
+
+/// Removes the padding [`pad`] added with the given scheme.
+pub fn unpad(buffer: &[u8], padding: Padding) -> Result<Vec<u8>, MycoError> {
+    if padding == Padding::Zeros {
+        return Ok(trim_zeros(buffer));
+    }
+    let (prefix, rest) = buffer
+        .split_first_chunk::<LENGTH_PREFIX_SIZE>()
+        .ok_or(MycoError::DeserializationError(None))?;
+    let length = u16::from_be_bytes(*prefix) as usize;
+    rest.get(..length)
+        .map(<[u8]>::to_vec)
+        .ok_or(MycoError::DeserializationError(None))
+}
+
+/// RFC 4648 base32 alphabet. Every character is in the QR code alphanumeric set.
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes bytes as unpadded RFC 4648 base32.
+///
+/// # Arguments
+/// * `bytes` - The bytes to encode
+///
+/// # Returns
+/// The encoded string, using uppercase letters and digits only
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes unpadded RFC 4648 base32, accepting lowercase letters.
+///
+/// # Arguments
+/// * `encoded` - The string to decode
+///
+/// # Returns
+/// The decoded bytes, or an error if the string contains other characters or leftover bits
+pub fn base32_decode(encoded: &str) -> Result<Vec<u8>, MycoError> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for c in encoded.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())
+            .ok_or(MycoError::DeserializationError(None))?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return Err(MycoError::DeserializationError(None));
+    }
+    Ok(decoded)
+}
+
+/// Replace the file at `path` with `bytes` so that it survives a crash or power loss either whole
+/// or not at all: the bytes are written to a temporary file next to it and synced, the temporary
+/// file is renamed into place, and the directory is synced so the rename is on disk too.
+pub fn write_durably(path: &StdPath, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp = fs::File::create(&tmp_path)?;
+    std::io::Write::write_all(&mut tmp, bytes)?;
+    tmp.sync_all()?;
+    drop(tmp);
+    fs::rename(&tmp_path, path)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => StdPath::new("."),
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
 /// Helper function to get the indices of the paths.
+///
+/// The indices are sorted in ascending order and each appears once, so the root comes first and
+/// the nodes of each level follow left to right. Server1, Server2 and clients rely on this order:
+/// the buckets of a pathset are exchanged in it and chunked reads and writes split it into the
+/// same chunks on every run. Neighbouring nodes of a level are adjacent, which lets Server2 copy
+/// them as runs (see [`tree::contiguous_runs`]).
 pub fn get_path_indices(paths: Vec<Path>) -> Vec<usize> {
-    // Initialize empty set to store unique node indices, starting with root (index 1)
-    let mut pathset: HashSet<usize> = HashSet::new();
-    pathset.insert(1);
-
-    // For each path, traverse from root to leaf and collect node indices
-    paths.iter().for_each(|p| {
-        p.clone().into_iter().fold(1, |acc, d| {
-            // Calculate child index: left child is 2*parent, right child is 2*parent + 1
-            let idx = 2 * acc + u8::from(d) as usize;
-            pathset.insert(idx);
-            idx
-        });
-    });
+    // Start with the root (index 1), which is part of every pathset
+    let mut pathset = vec![tree::ROOT_INDEX];
+
+    // For each path, collect the indices of the nodes from root to leaf
+    paths.iter().for_each(|p| pathset.extend(tree::path_indices(p)));
+
+    pathset.sort_unstable();
+    pathset.dedup();
+    pathset
+}
 
-    // Convert set to vector and return
-    pathset.into_iter().collect()
+/// Helper function to get the indices of the paths to the given leaves, in the order of
+/// [`get_path_indices`]. Leaves are numbered left to right from 0 to 2^D - 1, so a pathset of
+/// full-depth paths can be sent as one number per path rather than every index along it.
+pub fn get_leaf_path_indices(leaves: &[u32]) -> Result<Vec<usize>, MycoError> {
+    let mut pathset = Vec::with_capacity(leaves.len() * (D + 1));
+    for &leaf in leaves {
+        let leaf = leaf as usize;
+        if leaf >= 1 << D {
+            return Err(MycoError::MalformedRequest(format!(
+                "leaf {} is outside a tree of depth {}",
+                leaf, D
+            )));
+        }
+        // The leaf's index, followed by each of its ancestors up to the root
+        let mut index = (1 << D) | leaf;
+        while index >= tree::ROOT_INDEX {
+            pathset.push(index);
+            index >>= 1;
+        }
+    }
+    pathset.push(tree::ROOT_INDEX);
+
+    pathset.sort_unstable();
+    pathset.dedup();
+    Ok(pathset)
 }
 
-/// Helper function to calculate the bucket usage of the server.
+/// Helper function to calculate the bucket usage of the server. Blocks count as messages if they
+/// decrypt under `k_msg`, or, with encryption off in `simulation`, if they are there at all.
 pub fn calculate_bucket_usage(
     server2_tree: &BinaryTree<Bucket>,
     metadata_tree: &BinaryTree<Metadata>,
     k_msg: &[u8],
+    simulation: SimulationMode,
 ) -> (usize, usize, f64, f64, f64) {
     // Track bucket usage statistics
     let mut bucket_usage = Vec::new();
@@ -86,30 +257,25 @@ pub fn calculate_bucket_usage(
         .for_each(|(bucket, metadata_bucket, path)| {
             if let (Some(bucket), Some(metadata_bucket)) = (bucket, metadata_bucket) {
                 // Count messages in this bucket based on encryption mode
-                let messages_in_bucket = {
-                    #[cfg(feature = "no-enc")]
-                    {
-                        // In no-enc mode, just count non-empty blocks
-                        bucket.len()
-                    }
-                    #[cfg(not(feature = "no-enc"))]
-                    {
-                        // With encryption, check if messages are decryptable
-                        let mut decryptable_messages = 0;
-                        for b in 0..bucket.len() {
-                            if let Some((_l, k_oblv_t, _t_exp)) = metadata_bucket.get(b) {
-                                if let Some(c_msg) = bucket.get(b) {
-                                    // Try to decrypt with both keys to verify message
-                                    if let Ok(ct) = decrypt(&k_oblv_t.0, &c_msg.0) {
-                                        if decrypt(k_msg, &ct).is_ok() {
-                                            decryptable_messages += 1;
-                                        }
+                let messages_in_bucket = if !simulation.encrypts() {
+                    // Without encryption, just count non-empty blocks
+                    bucket.len()
+                } else {
+                    // With encryption, check if messages are decryptable
+                    let mut decryptable_messages = 0;
+                    for b in 0..bucket.len() {
+                        if let Some((_l, k_oblv_t, _t_exp)) = metadata_bucket.get(b) {
+                            if let Some(c_msg) = bucket.get(b) {
+                                // Try to decrypt with both keys to verify message
+                                if let Ok(ct) = decrypt(&k_oblv_t.0, c_msg.ciphertext()) {
+                                    if decrypt(k_msg, &ct).is_ok() {
+                                        decryptable_messages += 1;
                                     }
                                 }
                             }
                         }
-                        decryptable_messages
                     }
+                    decryptable_messages
                 };
 
                 // Update statistics
@@ -155,80 +321,30 @@ pub fn calculate_bucket_usage(
     (max_usage, max_depth, average_usage, median_usage, std_dev)
 }
 
-/// Generates self-signed TLS certificates for testing purposes.
-/// Creates a certificate and private key in the 'certs' directory.
-pub fn generate_test_certificates() -> Result<(), Box<dyn std::error::Error>> {
-    // Use StdPath instead of Path
-    if !StdPath::new("certs").exists() {
-        fs::create_dir("certs")?;
-    }
-    if StdPath::new("certs/server-cert.pem").exists() && StdPath::new("certs/server-key.pem").exists() {
-        // Clean up old certificates to ensure we have fresh ones
-        fs::remove_file("certs/server-cert.pem")?;
-        fs::remove_file("certs/server-key.pem")?;
+/// Generates a self-signed TLS certificate for `localhost` for testing purposes.
+/// Returns the certificate and its PKCS#8 private key. If `dir` is given, they are also written
+/// there as `server-cert.pem` and `server-key.pem`, replacing any existing files.
+pub fn generate_test_certificates(
+    dir: Option<&StdPath>,
+) -> Result<(Certificate, PrivateKey), MycoError> {
+    let mut params = CertificateParams::new(vec!["localhost".to_string()])
+        .map_err(|e| MycoError::CertificateError(e.to_string()))?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "localhost");
+    let key_pair = KeyPair::generate().map_err(|e| MycoError::CertificateError(e.to_string()))?;
+    let cert = params
+        .self_signed(&key_pair)
+        .map_err(|e| MycoError::CertificateError(e.to_string()))?;
+
+    if let Some(dir) = dir {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("server-cert.pem"), cert.pem())?;
+        fs::write(dir.join("server-key.pem"), key_pair.serialize_pem())?;
     }
 
-    // Create a config file for OpenSSL
-    fs::write(
-        "openssl.cnf",
-        r#"
-[req]
-distinguished_name = req_distinguished_name
-x509_extensions = v3_req
-prompt = no
-
-[req_distinguished_name]
-CN = localhost
-
-[v3_req]
-basicConstraints = CA:FALSE
-keyUsage = nonRepudiation, digitalSignature, keyEncipherment
-subjectAltName = @alt_names
-
-[alt_names]
-DNS.1 = localhost
-"#,
-    )?;
-
-    // Generate private key and self-signed certificate using OpenSSL
-    Command::new("openssl")
-        .args([
-            "req",
-            "-x509",
-            "-newkey",
-            "rsa:4096",
-            "-keyout",
-            "certs/server-key.pem",
-            "-out",
-            "certs/server-cert.pem",
-            "-days",
-            "365",
-            "-nodes",
-            "-config",
-            "openssl.cnf",
-            "-extensions",
-            "v3_req",
-        ])
-        .output()?;
-
-    // Convert the key to PKCS8 format which rustls expects
-    Command::new("openssl")
-        .args([
-            "pkcs8",
-            "-topk8",
-            "-nocrypt",
-            "-in",
-            "certs/server-key.pem",
-            "-out",
-            "certs/server-key.pem.tmp",
-        ])
-        .output()?;
-
-    // Replace the original key with the PKCS8 version
-    fs::rename("certs/server-key.pem.tmp", "certs/server-key.pem")?;
-
-    // Clean up the config file
-    fs::remove_file("openssl.cnf")?;
-
-    Ok(())
-}
\ No newline at end of file
+    Ok((
+        Certificate(cert.der().to_vec()),
+        PrivateKey(key_pair.serialize_der()),
+    ))
+}
@@ -0,0 +1,133 @@
+//! Server1-issued write tokens
+//!
+//! Server1 counts writes per write token, both to enforce its write quota and to count the
+//! distinct writers of an epoch for the [`AnonymityGate`](crate::server1::AnonymityGate) and the
+//! write stats. Those counts only mean something if clients can't mint tokens of their own, so
+//! Server1 issues them: one per registered account (see [`crate::registration`]) and epoch, for
+//! the current epoch and up to [`WRITE_TOKEN_EPOCHS`] ahead. A write carrying a token Server1
+//! never issued for the epoch is rejected.
+//!
+//! Issuance is blind, so Server1 can't tell which account a token spent on a write was issued to.
+//! A token is a random nonce `t` together with `k_e · H(e, t)`, where `H` hashes to the
+//! Ristretto255 group and `k_e` is Server1's token key for epoch `e`. The client sends the point
+//! blinded as `r · H(e, t)` ([`TokenRequest`]), Server1 answers with `k_e · r · H(e, t)`
+//! ([`TokenIssuer::issue`]) and the client strips `r` off again. When the token is spent, Server1
+//! recomputes `k_e · H(e, t)` from the nonce ([`TokenIssuer::verify`]).
+//!
+//! Clients can't check that Server1 evaluates every account's request under the same `k_e`. A
+//! Server1 using a key per account would recognize the account when its token is spent, so
+//! blinding keeps writes unlinkable to accounts against a curious Server1, not a malicious one.
+//! Token keys live only in the Server1 that issued them: tokens don't survive a restart or a
+//! failover to a standby, and clients fetch new ones.
+
+use curve25519_dalek::{ristretto::CompressedRistretto, RistrettoPoint, Scalar};
+use rand::{CryptoRng, RngCore};
+use sha2::Sha512;
+
+use crate::{
+    constants::{WRITE_TOKEN_EPOCHS, WRITE_TOKEN_SIZE},
+    dtypes::Key,
+    error::MycoError,
+};
+
+/// Size of a token's nonce in bytes. The rest of the token is the compressed evaluated point.
+const NONCE_SIZE: usize = 32;
+
+/// The point a token's nonce is hashed to for `epoch`.
+fn token_point(epoch: u64, nonce: &[u8]) -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(
+        &[&b"myco-write-token"[..], &epoch.to_be_bytes(), nonce].concat(),
+    )
+}
+
+/// Decompress a point sent by the other side, rejecting the identity.
+fn decompress(bytes: &[u8]) -> Option<RistrettoPoint> {
+    CompressedRistretto::from_slice(bytes)
+        .ok()?
+        .decompress()
+        .filter(|point| *point != RistrettoPoint::default())
+}
+
+/// Server1's side of write tokens: issues them and checks them when they're spent.
+pub struct TokenIssuer {
+    /// Secret the per-epoch token keys are derived from.
+    secret: Key,
+}
+
+impl TokenIssuer {
+    /// An issuer with a fresh random secret.
+    pub fn new<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        Self {
+            secret: Key::random(rng),
+        }
+    }
+
+    /// The token key of `epoch`.
+    fn epoch_key(&self, epoch: u64) -> Scalar {
+        Scalar::hash_from_bytes::<Sha512>(
+            &[&b"myco-write-token-key"[..], &self.secret.0, &epoch.to_be_bytes()].concat(),
+        )
+    }
+
+    /// Evaluate a client's blinded token request for `epoch`.
+    pub fn issue(&self, epoch: u64, blinded: &[u8]) -> Result<Vec<u8>, MycoError> {
+        let blinded = decompress(blinded)
+            .ok_or_else(|| MycoError::MalformedRequest("invalid blinded write token".to_string()))?;
+        Ok((self.epoch_key(epoch) * blinded).compress().to_bytes().to_vec())
+    }
+
+    /// Check that `token` was issued for `epoch`.
+    pub fn verify(&self, epoch: u64, token: &[u8]) -> Result<(), MycoError> {
+        if token.len() != WRITE_TOKEN_SIZE {
+            return Err(MycoError::InvalidWriteToken);
+        }
+        let (nonce, evaluated) = token.split_at(NONCE_SIZE);
+        let expected = self.epoch_key(epoch) * token_point(epoch, nonce);
+        if decompress(evaluated) != Some(expected) {
+            return Err(MycoError::InvalidWriteToken);
+        }
+        Ok(())
+    }
+}
+
+/// A client's request for a write token, kept until Server1 has evaluated it.
+pub struct TokenRequest {
+    /// The epoch the token is for.
+    pub epoch: u64,
+    nonce: [u8; NONCE_SIZE],
+    blind: Scalar,
+}
+
+impl TokenRequest {
+    /// A request for a token for `epoch`.
+    pub fn new<R: RngCore + CryptoRng>(epoch: u64, rng: &mut R) -> Self {
+        let mut nonce = [0; NONCE_SIZE];
+        rng.fill_bytes(&mut nonce);
+        Self {
+            epoch,
+            nonce,
+            blind: Scalar::random(rng),
+        }
+    }
+
+    /// The blinded point to send to Server1.
+    pub fn blinded(&self) -> Vec<u8> {
+        (self.blind * token_point(self.epoch, &self.nonce))
+            .compress()
+            .to_bytes()
+            .to_vec()
+    }
+
+    /// Unblind Server1's answer into the token.
+    pub fn finish(self, evaluated: &[u8]) -> Result<Vec<u8>, MycoError> {
+        let evaluated = decompress(evaluated)
+            .ok_or_else(|| MycoError::ProtocolError("invalid write token from Server1".to_string()))?;
+        let unblinded = self.blind.invert() * evaluated;
+        Ok([&self.nonce[..], unblinded.compress().as_bytes()].concat())
+    }
+}
+
+/// Whether Server1 at `current` issues tokens for `epoch`.
+pub fn issuable(current: u64, epoch: u64) -> bool {
+    epoch >= current && epoch < current + WRITE_TOKEN_EPOCHS as u64
+}
@@ -0,0 +1,53 @@
+#[cfg(all(test, feature = "acme"))]
+mod acme_tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use myco_rs::acme::{challenge_router, AcmeConfig, ChallengeTokens};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_challenge_router_serves_pending_tokens() {
+        let tokens = ChallengeTokens::default();
+        tokens.insert("token".to_string(), "token.thumbprint".to_string());
+
+        let response = challenge_router(tokens.clone())
+            .oneshot(
+                Request::get("/.well-known/acme-challenge/token")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"token.thumbprint");
+
+        let response = challenge_router(tokens)
+            .oneshot(
+                Request::get("/.well-known/acme-challenge/unknown")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_needs_renewal() {
+        let mut config = AcmeConfig::new(vec!["myco.example".to_string()]);
+        config.cache_dir = std::env::temp_dir().join(format!("myco-acme-{}", std::process::id()));
+        std::fs::create_dir_all(&config.cache_dir).unwrap();
+        assert!(config.needs_renewal());
+
+        std::fs::write(config.key_path(), "key").unwrap();
+        std::fs::write(config.cert_path(), "cert").unwrap();
+        assert!(!config.needs_renewal());
+
+        std::fs::remove_dir_all(&config.cache_dir).unwrap();
+    }
+}
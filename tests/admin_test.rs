@@ -0,0 +1,432 @@
+#[cfg(test)]
+mod admin_tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use axum::{
+        body::Body,
+        http::{header, HeaderMap, Method, Request, StatusCode},
+        Router,
+    };
+    use myco_rs::{
+        admin::{
+            self, AdminState, EpochControl, OperatorAuth, NONCE_HEADER, NONCE_SIZE, SIGNATURE_HEADER,
+            SIGNATURE_MAX_AGE, TIMESTAMP_HEADER,
+        },
+        crypto::encrypt,
+        crypto::EncryptionType,
+        dtypes::Key,
+        logging::LatencyBreakdown,
+        network::LocalServer2Access,
+        rpc_types::{AdminStatsResponse, AdminStatusResponse, MemoryStatsResponse},
+        server1::{self, AnonymityGate, Server1},
+        server2::Server2,
+        write_tokens::TokenRequest,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tokio::sync::RwLock;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "admin-secret";
+
+    fn setup() -> (Router, AdminState) {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let state = AdminState {
+            server1: Arc::new(RwLock::new(Server1::new(s2_access))),
+            control: Arc::new(EpochControl::new()),
+            token: Arc::new(TOKEN.to_string()),
+        };
+        (
+            Router::new().nest("/admin", admin::router(state.clone())),
+            state,
+        )
+    }
+
+    /// A write token Server1 issued for its current epoch to a fresh account.
+    fn issue_token(server1: &mut Server1, rng: &mut ChaCha20Rng) -> Vec<u8> {
+        let account = server1.registrations.register(rng).unwrap();
+        let request = TokenRequest::new(server1.epoch, rng);
+        let issued = server1
+            .issue_write_tokens(&account, vec![(request.epoch, request.blinded())])
+            .unwrap();
+        request.finish(&issued[0].1).unwrap()
+    }
+
+    async fn call(
+        app: &Router,
+        method: &str,
+        route: &str,
+        token: Option<&str>,
+    ) -> (StatusCode, Option<AdminStatusResponse>) {
+        let mut request = Request::builder().method(method).uri(route);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, bincode::deserialize(&bytes).ok())
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_token() {
+        let (app, _) = setup();
+        assert_eq!(
+            call(&app, "GET", "/admin/status", None).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            call(&app, "GET", "/admin/status", Some("wrong")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            call(&app, "POST", "/admin/drain", Some("wrong")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            call(&app, "GET", "/admin/status", Some(TOKEN)).await.0,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume() {
+        let (app, state) = setup();
+        let (_, status) = call(&app, "POST", "/admin/pause", Some(TOKEN)).await;
+        assert!(status.unwrap().paused);
+        assert!(state.control.is_paused());
+
+        let (_, status) = call(&app, "POST", "/admin/resume", Some(TOKEN)).await;
+        let status = status.unwrap();
+        assert!(!status.paused);
+        assert!(status.epoch_open);
+    }
+
+    #[tokio::test]
+    async fn test_force_batch_write_and_drain() {
+        let (app, state) = setup();
+
+        // Nothing to write before a batch has been initialized.
+        assert_eq!(
+            call(&app, "POST", "/admin/batch_write", Some(TOKEN))
+                .await
+                .0,
+            StatusCode::CONFLICT
+        );
+
+        state.server1.write().await.async_batch_init(2).await.expect("Batch init failed");
+        state.control.set_epoch_open(true);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_oblv_t = Key::random(&mut rng);
+        let ct = encrypt(&Key::random(&mut rng).0, &[1], EncryptionType::Encrypt).unwrap();
+        state
+            .server1
+            .write()
+            .await
+            .queue_write(ct, vec![0; 32], k_oblv_t, b"Alice".to_vec(), vec![], None)
+            .unwrap();
+
+        let (_, status) = call(&app, "GET", "/admin/status", Some(TOKEN)).await;
+        let status = status.unwrap();
+        assert_eq!(status.queue_depth, 1);
+        assert!(status.pathset_size > 0);
+
+        let (code, status) = call(&app, "POST", "/admin/batch_write", Some(TOKEN)).await;
+        assert_eq!(code, StatusCode::OK);
+        let status = status.unwrap();
+        assert_eq!(status.epoch, 1);
+        assert_eq!(status.queue_depth, 0);
+        assert!(status.epoch_open);
+
+        let (_, status) = call(&app, "POST", "/admin/drain", Some(TOKEN)).await;
+        let status = status.unwrap();
+        assert_eq!(status.epoch, 2);
+        assert!(status.draining && status.paused && !status.epoch_open);
+        assert!(!state.control.accepting_writes());
+    }
+
+    async fn get_stats(app: &Router) -> AdminStatsResponse {
+        let request = Request::builder()
+            .uri("/admin/stats")
+            .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        bincode::deserialize(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_distinct_writers() {
+        let (app, state) = setup();
+        state.server1.write().await.async_batch_init(3).await.expect("Batch init failed");
+        state.control.set_epoch_open(true);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (first, second) = {
+            let mut server1 = state.server1.write().await;
+            (issue_token(&mut server1, &mut rng), issue_token(&mut server1, &mut rng))
+        };
+        for token in [first, second.clone(), second] {
+            let ct = encrypt(&Key::random(&mut rng).0, &[1], EncryptionType::Encrypt).unwrap();
+            state
+                .server1
+                .write()
+                .await
+                .queue_write(ct, vec![0; 32], Key::random(&mut rng), b"Alice".to_vec(), token, None)
+                .unwrap();
+        }
+
+        let stats = get_stats(&app).await;
+        assert_eq!((stats.current.writes, stats.current.distinct_writers), (3, 2));
+        assert!((stats.current.padding_ratio() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.previous, None);
+
+        call(&app, "POST", "/admin/batch_write", Some(TOKEN)).await;
+        let stats = get_stats(&app).await;
+        assert_eq!(stats.current.epoch, 1);
+        assert_eq!((stats.current.writes, stats.current.distinct_writers), (0, 0));
+        let previous = stats.previous.unwrap();
+        assert_eq!((previous.epoch, previous.writes, previous.distinct_writers), (0, 3, 2));
+    }
+
+    #[tokio::test]
+    async fn test_memory_stats() {
+        let (app, state) = setup();
+        let (status, _) = call(&app, "GET", "/admin/memory", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let get_memory = || async {
+            let request = Request::builder()
+                .uri("/admin/memory")
+                .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            bincode::deserialize::<MemoryStatsResponse>(&bytes).unwrap().stats
+        };
+        let empty = get_memory().await;
+        assert_eq!((empty.tree_bytes, empty.queue_depth), (0, 0));
+        // The test binary doesn't install the counting allocator.
+        assert_eq!(empty.allocator, None);
+
+        state.server1.write().await.async_batch_init(2).await.expect("Batch init failed");
+        let mut rng = ChaCha20Rng::from_entropy();
+        for _ in 0..2 {
+            let ct = encrypt(&Key::random(&mut rng).0, &[1], EncryptionType::Encrypt).unwrap();
+            state
+                .server1
+                .write()
+                .await
+                .queue_write(ct, vec![0; 32], Key::random(&mut rng), b"Alice".to_vec(), vec![], None)
+                .unwrap();
+        }
+        let stats = get_memory().await;
+        assert_eq!(stats, state.server1.read().await.memory_stats());
+        assert_eq!(stats.queue_depth, 2);
+        assert!(stats.tree_bytes > empty.tree_bytes);
+    }
+
+    #[tokio::test]
+    async fn test_latency_breakdown() {
+        let (app, state) = setup();
+        let (status, _) = call(&app, "GET", "/admin/latency", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        state.server1.write().await.async_batch_init(1).await.expect("Batch init failed");
+        state.control.set_epoch_open(true);
+        let (code, _) = call(&app, "POST", "/admin/batch_write", Some(TOKEN)).await;
+        assert_eq!(code, StatusCode::OK);
+
+        let request = Request::builder()
+            .uri("/admin/latency")
+            .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let breakdown: LatencyBreakdown = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(breakdown.current.epoch, 1);
+        // Other tests in this binary close Server1 epochs too, so any of them may hold the
+        // operations of this one.
+        let operations: Vec<_> = breakdown
+            .previous
+            .iter()
+            .flat_map(|epoch| &epoch.operations)
+            .collect();
+        for name in [
+            "server1_batch_init_end_to_end",
+            "server1_batch_write_end_to_end",
+            "server1_batch_write_write_to_server2",
+        ] {
+            assert!(operations.iter().any(|op| op.operation == name && op.count >= 1), "{} missing", name);
+        }
+        assert!(operations.iter().all(|op| op.operation.starts_with("server1_")));
+    }
+
+    #[tokio::test]
+    async fn test_anonymity_gate() {
+        let (_, state) = setup();
+        let mut rng = ChaCha20Rng::from_entropy();
+        {
+            let mut server1 = state.server1.write().await;
+            server1.set_anonymity_gate(Some(AnonymityGate {
+                min_writers: 2,
+                timeout: Duration::from_secs(60),
+            }));
+            server1.async_batch_init(2).await.expect("Batch init failed");
+        }
+        let (alice, bob) = {
+            let mut server1 = state.server1.write().await;
+            (issue_token(&mut server1, &mut rng), issue_token(&mut server1, &mut rng))
+        };
+        for token in [alice.clone(), alice] {
+            let ct = encrypt(&Key::random(&mut rng).0, &[1], EncryptionType::Encrypt).unwrap();
+            state
+                .server1
+                .write()
+                .await
+                .queue_write(ct, vec![0; 32], Key::random(&mut rng), b"Alice".to_vec(), token, None)
+                .unwrap();
+        }
+        // Two writes from the same client don't open the gate.
+        assert!(state.server1.read().await.anonymity_gate_remaining().is_some());
+
+        let ct = encrypt(&Key::random(&mut rng).0, &[1], EncryptionType::Encrypt).unwrap();
+        state
+            .server1
+            .write()
+            .await
+            .queue_write(ct, vec![0; 32], Key::random(&mut rng), b"Bob".to_vec(), bob, None)
+            .unwrap();
+        assert_eq!(state.server1.read().await.anonymity_gate_remaining(), None);
+    }
+
+    #[tokio::test]
+    async fn test_anonymity_gate_times_out() {
+        let (_, state) = setup();
+        let timeout = Duration::from_millis(200);
+        {
+            let mut server1 = state.server1.write().await;
+            server1.set_anonymity_gate(Some(AnonymityGate { min_writers: 5, timeout }));
+            server1.async_batch_init(1).await.expect("Batch init failed");
+        }
+        let start = Instant::now();
+        admin::wait_for_anonymity_gate(&state.server1).await;
+        assert!(start.elapsed() >= timeout - Duration::from_millis(50));
+        assert_eq!(state.server1.read().await.anonymity_gate_remaining(), None);
+    }
+
+    async fn signed_call(app: &Router, route: &str, headers: HeaderMap, body: &[u8]) -> StatusCode {
+        let mut request = Request::post(route);
+        *request.headers_mut().unwrap() = headers;
+        app.clone()
+            .oneshot(request.body(Body::from(body.to_vec())).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_signed_requests() {
+        let (app, _) = setup();
+        let auth = OperatorAuth::new(TOKEN);
+        let headers = auth.signed_headers(&Method::POST, "/admin/pause", &[]);
+        assert_eq!(signed_call(&app, "/admin/pause", headers.clone(), &[]).await, StatusCode::OK);
+        // A signed request is accepted once.
+        assert_eq!(
+            signed_call(&app, "/admin/pause", headers.clone(), &[]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let mut unsigned_nonce = auth.signed_headers(&Method::POST, "/admin/pause", &[]);
+        unsigned_nonce.insert(NONCE_HEADER, hex::encode([0u8; NONCE_SIZE]).parse().unwrap());
+        assert_eq!(
+            signed_call(&app, "/admin/pause", unsigned_nonce, &[]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let mut no_nonce = auth.signed_headers(&Method::POST, "/admin/pause", &[]);
+        no_nonce.remove(NONCE_HEADER);
+        assert_eq!(
+            signed_call(&app, "/admin/pause", no_nonce, &[]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // The signature covers the path and body.
+        assert_eq!(
+            signed_call(&app, "/admin/drain", headers.clone(), &[]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            signed_call(&app, "/admin/pause", headers, b"extra").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let wrong = OperatorAuth::new("wrong");
+        let headers = wrong.signed_headers(&Method::POST, "/admin/pause", &[]);
+        assert_eq!(
+            signed_call(&app, "/admin/pause", headers, &[]).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let stale = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+            - SIGNATURE_MAX_AGE.as_secs()
+            - 60;
+        let nonce = [1u8; NONCE_SIZE];
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, stale.into());
+        headers.insert(NONCE_HEADER, hex::encode(nonce).parse().unwrap());
+        headers.insert(
+            SIGNATURE_HEADER,
+            auth.sign(&Method::POST, "/admin/pause", stale, &nonce, &[]).parse().unwrap(),
+        );
+        assert_eq!(
+            signed_call(&app, "/admin/pause", headers, &[]).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_benchmark_routes_require_operator() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let state = server1::http::AppState::new(Server1::new(s2_access));
+        let auth = OperatorAuth::new(TOKEN);
+        let app = server1::http::router()
+            .merge(server1::http::benchmark_router(auth.clone()))
+            .with_state(state);
+
+        assert_eq!(
+            signed_call(&app, "/finalize_benchmark", HeaderMap::new(), &[]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let headers = auth.signed_headers(&Method::POST, "/finalize_benchmark", &[]);
+        assert_eq!(
+            signed_call(&app, "/finalize_benchmark", headers, &[]).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(&app, "POST", "/finalize_benchmark", Some(TOKEN)).await.0,
+            StatusCode::OK
+        );
+    }
+}
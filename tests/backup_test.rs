@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod backup_tests {
+    use std::path::PathBuf;
+
+    use myco_rs::{
+        backup::{self, BackupEntry},
+        dtypes::{Block, Bucket, Key},
+        server2::Server2,
+        utils::get_leaf_path_indices,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn backup_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("myco-backup-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write an epoch to the paths to `leaves`.
+    fn write_epoch(server2: &mut Server2, leaves: &[u32]) -> usize {
+        let pathset = get_leaf_path_indices(leaves).unwrap();
+        let buckets = (0..pathset.len())
+            .map(|_| {
+                let mut bucket = Bucket::default();
+                bucket.push(Block::new_random());
+                bucket
+            })
+            .collect();
+        let written = pathset.len();
+        server2.store_path_indices(pathset).unwrap();
+        let epoch = server2.epoch;
+        server2
+            .write(epoch, buckets, &Key::random(&mut ChaCha20Rng::from_entropy()))
+            .unwrap();
+        written
+    }
+
+    #[test]
+    fn test_restore_any_kept_epoch() {
+        let dir = backup_dir("restore");
+        let mut server2 = Server2::new();
+        write_epoch(&mut server2, &[0, 9]);
+        backup::write_backup(&dir, &server2.backup(None).unwrap()).unwrap();
+        let first = (server2.tree.clone(), server2.prf_keys.clone());
+
+        let written = write_epoch(&mut server2, &[3]);
+        write_epoch(&mut server2, &[3]);
+        let incremental = server2.backup(backup::latest_epoch(&dir).unwrap()).unwrap();
+        assert_eq!(incremental.buckets.len(), written);
+        backup::write_backup(&dir, &incremental).unwrap();
+
+        let restored = backup::restore(&dir, 3).unwrap();
+        assert_eq!(restored.epoch, 3);
+        assert_eq!(restored.tree, server2.tree);
+        assert_eq!(restored.prf_keys, server2.prf_keys);
+        let restored = backup::restore(&dir, 1).unwrap();
+        assert_eq!((restored.tree, restored.prf_keys), first);
+        assert!(backup::restore(&dir, 2).is_err());
+    }
+
+    #[test]
+    fn test_backup_refused_mid_epoch() {
+        let mut server2 = Server2::new();
+        server2.store_path_indices(get_leaf_path_indices(&[1]).unwrap()).unwrap();
+        server2.chunk_write(0, vec![Bucket::default()], 0).unwrap();
+        assert!(server2.backup(None).is_err());
+        server2
+            .finalize_epoch(0, &Key::random(&mut ChaCha20Rng::from_entropy()))
+            .unwrap();
+        assert!(server2.backup(Some(0)).is_ok());
+        assert!(server2.backup(Some(2)).is_err());
+    }
+
+    #[test]
+    fn test_prune_folds_into_full_backup() {
+        let dir = backup_dir("prune");
+        let mut server2 = Server2::new();
+        for leaf in 0..3 {
+            write_epoch(&mut server2, &[leaf]);
+            let since = backup::latest_epoch(&dir).unwrap();
+            backup::write_backup(&dir, &server2.backup(since).unwrap()).unwrap();
+        }
+
+        assert_eq!(backup::prune(&dir, 2).unwrap(), 2);
+        let entries = backup::list_backups(&dir).unwrap();
+        let kept: Vec<(u64, Option<u64>)> = entries
+            .iter()
+            .map(|BackupEntry { epoch, since, .. }| (*epoch, *since))
+            .collect();
+        assert_eq!(kept, vec![(2, None), (3, Some(2))]);
+        assert_eq!(backup::restore(&dir, 3).unwrap().tree, server2.tree);
+    }
+}
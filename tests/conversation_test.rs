@@ -0,0 +1,107 @@
+#[cfg(test)]
+mod conversation_tests {
+    use myco_rs::{
+        client::Client,
+        conversation::{Conversation, Message, FRAGMENT_SIZE, RETRANSMIT_EPOCHS},
+        dtypes::Key,
+        envelope::ContentType,
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use std::sync::{Arc, Mutex, RwLock};
+
+    fn setup() -> (Arc<RwLock<Server1>>, Conversation, Conversation) {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (k_ab, k_ba) = (Key::random(&mut rng), Key::random(&mut rng));
+        let alice = Conversation::new(alice, "Bob".to_string(), k_ab.clone(), k_ba.clone());
+        let bob = Conversation::new(bob, "Alice".to_string(), k_ba, k_ab);
+        (s1, alice, bob)
+    }
+
+    /// Run one epoch in which both sides take part, returning what each received.
+    fn step(
+        s1: &RwLock<Server1>,
+        alice: &mut Conversation,
+        bob: &mut Conversation,
+    ) -> (Vec<Message>, Vec<Message>) {
+        s1.write().unwrap().batch_init(2);
+        let received = (alice.recv().expect("Alice recv failed"), bob.recv().expect("Bob recv failed"));
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        received
+    }
+
+    #[test]
+    fn test_fragmented_messages_delivered_in_order() {
+        let (s1, mut alice, mut bob) = setup();
+        let long: Vec<u8> = (0..FRAGMENT_SIZE * 2 + 5).map(|i| (i % 251) as u8).collect();
+        alice.send(&long).unwrap();
+        alice.send(b"hi").unwrap();
+        alice.send(&[]).unwrap();
+        assert_eq!(alice.unacknowledged(), 5);
+
+        let mut received = Vec::new();
+        for _ in 0..8 {
+            received.extend(step(&s1, &mut alice, &mut bob).1);
+        }
+        assert_eq!(
+            received,
+            vec![
+                Message { sequence: 0, content_type: ContentType::Binary, payload: long },
+                Message { sequence: 1, content_type: ContentType::Binary, payload: b"hi".to_vec() },
+                Message { sequence: 2, content_type: ContentType::Binary, payload: vec![] },
+            ]
+        );
+        assert_eq!(alice.unacknowledged(), 0);
+        // Each epoch's ratcheted key is forgotten once used.
+        assert!(alice.client().keys.is_empty());
+        assert!(bob.client().keys.is_empty());
+    }
+
+    #[test]
+    fn test_both_directions() {
+        let (s1, mut alice, mut bob) = setup();
+        alice.send(b"ping").unwrap();
+        bob.send(b"pong").unwrap();
+
+        let (mut to_alice, mut to_bob) = (Vec::new(), Vec::new());
+        for _ in 0..3 {
+            let (a, b) = step(&s1, &mut alice, &mut bob);
+            to_alice.extend(a.into_iter().map(|message| message.payload));
+            to_bob.extend(b.into_iter().map(|message| message.payload));
+        }
+        assert_eq!(to_alice, vec![b"pong".to_vec()]);
+        assert_eq!(to_bob, vec![b"ping".to_vec()]);
+        assert_eq!(alice.unacknowledged(), 0);
+        assert_eq!(bob.unacknowledged(), 0);
+    }
+
+    #[test]
+    fn test_lost_frame_retransmitted() {
+        let (s1, mut alice, mut bob) = setup();
+        alice.send(b"again").unwrap();
+
+        // Alice's first frame arrives while no epoch is open and is lost.
+        assert!(matches!(alice.recv(), Err(MycoError::EpochClosed { .. })));
+        s1.write().unwrap().batch_init(1);
+        assert!(bob.recv().unwrap().is_empty());
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        let mut received = Vec::new();
+        for _ in 0..RETRANSMIT_EPOCHS + 2 {
+            received.extend(step(&s1, &mut alice, &mut bob).1);
+        }
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].payload, b"again".to_vec());
+    }
+}
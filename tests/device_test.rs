@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod device_tests {
+    use myco_rs::{
+        client::Client,
+        device::{DeviceLink, Identity, ReadDuty},
+        dtypes::Key,
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use std::sync::{Arc, Mutex, RwLock};
+
+    #[test]
+    fn test_link_device() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut phone = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let laptop = Client::new("Laptop".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (k_bob, k_carol) = (Key::random(&mut rng), Key::random(&mut rng));
+        phone.setup(&k_bob).unwrap();
+        phone.setup(&k_carol).unwrap();
+        bob.setup(&k_bob).unwrap();
+
+        let (code, mut existing) = DeviceLink::offer(phone).expect("Offer failed");
+        let mut new = DeviceLink::accept(laptop, &code).expect("Accept failed");
+        let (mut existing_done, mut new_done) = (false, false);
+        for _ in 0..6 {
+            s1.write().unwrap().batch_init(2);
+            existing_done = existing.step().expect("Existing device step failed");
+            new_done = new.step().expect("New device step failed");
+            s1.write().unwrap().batch_write().expect("Batch write failed");
+            if existing_done {
+                break;
+            }
+        }
+        assert!(existing_done && new_done);
+        let mut phone = existing.into_client().unwrap();
+        let mut laptop = new.into_client().unwrap();
+        assert_eq!(laptop.id, "Alice");
+        assert_eq!(laptop.epoch, phone.epoch);
+        assert_eq!(Identity::of(&laptop), Identity::of(&phone));
+
+        // Both devices derive the same read paths for the shared identity.
+        bob.epoch = phone.epoch;
+        s1.write().unwrap().batch_init(1);
+        bob.write(&[4, 2], &k_bob).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        phone.epoch += 1;
+        laptop.epoch += 1;
+        assert_eq!(phone.read(&k_bob, "Bob".to_string(), 0).expect("Read failed").payload, vec![4, 2]);
+        assert_eq!(laptop.read(&k_bob, "Bob".to_string(), 0).expect("Read failed").payload, vec![4, 2]);
+    }
+
+    #[test]
+    fn test_accept_requires_identity() {
+        let s2_access = Box::new(LocalServer2Access { server: Arc::new(Mutex::new(Server2::new())) });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1 });
+        let phone = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let laptop = Client::new("Laptop".to_string(), s1_access, s2_access);
+
+        let (code, _existing) = DeviceLink::offer(phone).expect("Offer failed");
+        let new = DeviceLink::accept(laptop, &code).expect("Accept failed");
+        assert!(matches!(new.into_client(), Err(MycoError::ProtocolError(_))));
+    }
+
+    #[test]
+    fn test_read_duty_divides_contacts() {
+        let s2_access = Box::new(LocalServer2Access { server: Arc::new(Mutex::new(Server2::new())) });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let mut client = Client::new("Alice".to_string(), Box::new(LocalServer1Access { server: s1 }), s2_access);
+        let mut rng = ChaCha20Rng::from_entropy();
+        for _ in 0..32 {
+            client.setup(&Key::random(&mut rng)).unwrap();
+        }
+
+        let all = ReadDuty::All.contacts(&client).unwrap();
+        assert_eq!(all.len(), 32);
+        let first = ReadDuty::Share { index: 0, devices: 2 }.contacts(&client).unwrap();
+        let second = ReadDuty::Share { index: 1, devices: 2 }.contacts(&client).unwrap();
+        assert_eq!(first.len() + second.len(), all.len());
+        assert!(first.iter().all(|k| !second.contains(k)));
+
+        let invalid = ReadDuty::Share { index: 2, devices: 2 };
+        assert!(matches!(invalid.reads(&all[0]), Err(MycoError::ConfigError(_))));
+    }
+}
@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod directory_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use myco_rs::{
+        client::Client,
+        directory::{self, HttpKeyDirectory, IdentityKeys, KeyDirectory, MemoryKeyDirectory},
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+        tls::TlsTrust,
+    };
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_both_sides_derive_the_same_contact_key() {
+        let alice = IdentityKeys::generate("Alice");
+        let bob = IdentityKeys::generate("Bob");
+        let carol = IdentityKeys::generate("Carol");
+
+        let k = alice.contact_key(&bob.bundle()).unwrap();
+        assert_eq!(bob.contact_key(&alice.bundle()).unwrap(), k);
+        assert_ne!(carol.contact_key(&bob.bundle()).unwrap(), k);
+    }
+
+    #[test]
+    fn test_tampered_bundle_rejected() {
+        let alice = IdentityKeys::generate("Alice");
+        let mallory = IdentityKeys::generate("Bob");
+        let mut bundle = IdentityKeys::generate("Bob").bundle();
+        bundle.prekey = mallory.bundle().prekey;
+        assert!(matches!(bundle.verify(), Err(MycoError::ProtocolError(_))));
+        assert!(matches!(alice.contact_key(&bundle), Err(MycoError::ProtocolError(_))));
+
+        let mut renamed = alice.bundle();
+        renamed.id = "Bob".to_string();
+        assert!(renamed.verify().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_directory_pins_identity_key() {
+        let directory = MemoryKeyDirectory::default();
+        let bob = IdentityKeys::generate("Bob");
+        directory.publish(&bob.bundle()).await.unwrap();
+        directory.publish(&bob.bundle()).await.unwrap();
+        assert!(directory.publish(&IdentityKeys::generate("Bob").bundle()).await.is_err());
+        assert_eq!(directory.lookup("Bob").await.unwrap(), bob.bundle());
+        assert!(directory.lookup("Carol").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_directory_bootstraps_contacts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = directory::router().with_state(MemoryKeyDirectory::default());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let directory = HttpKeyDirectory::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap();
+
+        let alice_keys = IdentityKeys::generate("Alice");
+        let bob_keys = IdentityKeys::generate("Bob Smith/1");
+        directory.publish(&alice_keys.bundle()).await.unwrap();
+        directory.publish(&bob_keys.bundle()).await.unwrap();
+        assert_eq!(directory.lookup("Bob Smith/1").await.unwrap(), bob_keys.bundle());
+        assert!(directory.lookup("Carol").await.is_err());
+        assert!(directory
+            .publish(&IdentityKeys::generate("Alice").bundle())
+            .await
+            .is_err());
+
+        let s2_access = Box::new(LocalServer2Access { server: Arc::new(Mutex::new(Server2::new())) });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob Smith/1".to_string(), s1_access, s2_access);
+        let k = directory::add_contact(&mut alice, &alice_keys, &directory, "Bob Smith/1")
+            .await
+            .unwrap();
+        let k_bob = directory::add_contact(&mut bob, &bob_keys, &directory, "Alice")
+            .await
+            .unwrap();
+        assert_eq!(k, k_bob);
+
+        let s1_task = s1.clone();
+        tokio::task::spawn_blocking(move || {
+            s1_task.write().unwrap().batch_init(1);
+            alice.write(&[5], &k).expect("Write failed");
+            s1_task.write().unwrap().batch_write().expect("Batch write failed");
+            bob.epoch += 1;
+            assert_eq!(bob.read(&k_bob, "Alice".to_string(), 0).expect("Read failed").payload, vec![5]);
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod distributed_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use myco_rs::{
+        crypto::{location_prf, prf},
+        distributed::{
+            front_layer, seal_location, FrontServer1, LocalBackServer1Access, LocationRequest,
+            SealedWrite,
+        },
+        dtypes::Key,
+        error::MycoError,
+        network::LocalServer2Access,
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn new_deployment() -> (Arc<RwLock<Server1>>, LocalBackServer1Access, FrontServer1) {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let back = Arc::new(RwLock::new(Server1::new(Box::new(LocalServer2Access { server: s2 }))));
+        let back_access = LocalBackServer1Access::new(back.clone());
+        let front = FrontServer1::new(Box::new(back_access.clone()));
+        (back, back_access, front)
+    }
+
+    #[test]
+    fn test_blind_evaluation_matches_published_key() {
+        let (_, _, mut front) = new_deployment();
+        let mut rng = ChaCha20Rng::from_entropy();
+        front.batch_init();
+
+        let (f, cs) = (b"f".to_vec(), b"pseudonym".to_vec());
+        let request = LocationRequest::new(&f, &cs, &mut rng);
+        let (epoch, evaluated) = front.evaluate_location(&request.blinded()).expect("Evaluation failed");
+        assert_eq!(epoch, 0);
+        let x = request.finish(&evaluated).expect("Unblinding failed");
+
+        // Once published, the front's share gives readers the same layer without the front.
+        let k_front = front.flush().expect("Flush failed");
+        assert_eq!(front_layer(&k_front.0, &f, &cs), x);
+        let k_back = Key::random(&mut rng);
+        let published = [&k_front.0[..], &k_back.0[..]].concat();
+        assert_eq!(
+            location_prf(&published, &f, &cs).expect("Location failed"),
+            prf(&k_back.0, &x).expect("PRF failed")
+        );
+    }
+
+    #[test]
+    fn test_blinded_points_hide_the_location_input() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let first = LocationRequest::new(b"f", b"pseudonym", &mut rng);
+        let second = LocationRequest::new(b"f", b"pseudonym", &mut rng);
+        assert_ne!(first.blinded(), second.blinded());
+    }
+
+    #[test]
+    fn test_front_rejects_writes_from_a_closed_epoch() {
+        let (_, back_access, mut front) = new_deployment();
+        let mut rng = ChaCha20Rng::from_entropy();
+        front.batch_init();
+
+        let request = LocationRequest::new(b"f", b"pseudonym", &mut rng);
+        let (epoch, evaluated) = front.evaluate_location(&request.blinded()).expect("Evaluation failed");
+        let x = request.finish(&evaluated).expect("Unblinding failed");
+        front.flush().expect("Flush failed");
+
+        // The location input was evaluated under a share that has been released since.
+        let write = SealedWrite {
+            epoch,
+            ct: vec![0; 16],
+            sealed: seal_location(&back_access.public_key(), &x, &Key::random(&mut rng))
+                .expect("Sealing failed"),
+            address: vec![0; 32],
+            token: vec![],
+            access_tag: None,
+        };
+        assert!(matches!(
+            front.queue_sealed_write(write),
+            Err(MycoError::EpochClosed { .. })
+        ));
+        assert!(matches!(
+            front.evaluate_location(&evaluated),
+            Err(MycoError::EpochClosed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_front_forwards_sealed_writes_only() {
+        let (back, back_access, mut front) = new_deployment();
+        let mut rng = ChaCha20Rng::from_entropy();
+        back.write().unwrap().batch_init(1);
+        front.batch_init();
+
+        let request = LocationRequest::new(b"f", b"pseudonym", &mut rng);
+        let (epoch, evaluated) = front.evaluate_location(&request.blinded()).expect("Evaluation failed");
+        let x = request.finish(&evaluated).expect("Unblinding failed");
+        let write = SealedWrite {
+            epoch,
+            ct: vec![0; 16],
+            sealed: seal_location(&back_access.public_key(), &x, &Key::random(&mut rng))
+                .expect("Sealing failed"),
+            address: vec![0; 32],
+            token: vec![],
+            access_tag: None,
+        };
+        front.queue_sealed_write(write.clone()).expect("Write failed");
+
+        // A box the back can't open fails the forward rather than landing at a random location.
+        let mut tampered = write;
+        *tampered.sealed.last_mut().unwrap() ^= 1;
+        front.queue_sealed_write(tampered).expect("Write failed");
+        assert_eq!(front.queue_len(), 2);
+        assert!(matches!(front.flush(), Err(MycoError::MalformedRequest(_))));
+    }
+}
@@ -9,7 +9,7 @@
 
 #[cfg(test)]
 mod dtypes_tests {
-    use myco_rs::{dtypes::{Path, Direction}, constants::D};
+    use myco_rs::{dtypes::{Path, Direction}, constants::D, tree, utils::get_path_indices};
     #[test]
     fn test_into_vecu8_empty_path() {
         let path = Path(Vec::new());
@@ -240,4 +240,81 @@ mod dtypes_tests {
             );
         }
     }
+
+    #[test]
+    fn test_contact_bundle_round_trip() {
+        use myco_rs::{dtypes::{ContactBundle, Key, CONTACT_BUNDLE_PREFIX}, error::MycoError};
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+
+        let mut bundle = ContactBundle {
+            id: "Alice".to_string(),
+            identity_key: None,
+            key: Key::random(&mut ChaCha20Rng::from_entropy()),
+            server1: "https://s1.example:3001".to_string(),
+            server2: "https://s2.example:3002".to_string(),
+        };
+        for identity_key in [None, Some([7; 32])] {
+            bundle.identity_key = identity_key;
+            let encoded = bundle.encode().unwrap();
+            assert!(encoded.starts_with(CONTACT_BUNDLE_PREFIX));
+            // Only characters of the QR code alphanumeric mode.
+            assert!(encoded.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == ':'));
+            assert_eq!(ContactBundle::decode(&encoded).unwrap(), bundle);
+            assert_eq!(ContactBundle::decode(&encoded.to_lowercase()).unwrap(), bundle);
+        }
+
+        let encoded = bundle.encode().unwrap();
+        let mut corrupted = encoded.clone().into_bytes();
+        let last = corrupted.len() - 3;
+        corrupted[last] = if corrupted[last] == b'A' { b'B' } else { b'A' };
+        let corrupted = String::from_utf8(corrupted).unwrap();
+        assert!(matches!(ContactBundle::decode(&corrupted), Err(MycoError::DeserializationError(None))));
+        assert!(matches!(
+            ContactBundle::decode(&encoded.replacen("MYCO1", "MYCO2", 1)),
+            Err(MycoError::ProtocolError(_))
+        ));
+        assert!(ContactBundle::decode("MYCO1:").is_err());
+
+        bundle.server1 = "x".repeat(256);
+        assert!(matches!(bundle.encode(), Err(MycoError::SerializationFailed(None))));
+    }
+
+    #[test]
+    fn test_path_index_math() {
+        use Direction::{Left, Right};
+
+        let path = Path::new(vec![Left, Right, Right]);
+        assert_eq!(path.to_index(), 11);
+        assert_eq!(Path::from_index(11), path);
+        assert_eq!(Path::from(11), path);
+        assert_eq!(path.parent(), Some(Path::new(vec![Left, Right])));
+        assert_eq!(path.sibling(), Some(Path::new(vec![Left, Right, Left])));
+        assert_eq!(Path::new(vec![]).parent(), None);
+        assert_eq!(Path::new(vec![]).sibling(), None);
+        assert_eq!(Path::from_index(0), Path::new(vec![]));
+        assert_eq!(
+            path.ancestors().collect::<Vec<_>>(),
+            vec![Path::new(vec![Left, Right]), Path::new(vec![Left]), Path::new(vec![])]
+        );
+
+        // The path helpers agree with the tree's index math for every node of a small tree.
+        for index in 1..1 << 10 {
+            let path = Path::from_index(index);
+            assert_eq!(path.to_index(), index);
+            assert_eq!(path.len(), tree::depth_of(index));
+            assert_eq!(path.parent().map(|p| p.to_index()), tree::parent_index(index));
+            if index > 1 {
+                assert_eq!(path.sibling().unwrap().to_index(), index ^ 1);
+            }
+            let mut indices: Vec<usize> = path.ancestors().map(|p| p.to_index()).collect();
+            indices.reverse();
+            indices.push(index);
+            assert_eq!(indices, tree::path_indices(&path).collect::<Vec<_>>());
+        }
+
+        let mut pathset = get_path_indices(vec![path.clone(), Path::new(vec![Right])]);
+        pathset.sort();
+        assert_eq!(pathset, vec![1, 2, 3, 5, 11]);
+    }
 }
@@ -14,7 +14,7 @@ mod e2e_tests {
     };
 
     use myco_rs::{
-        client::Client, constants::{D, DELTA, NUM_CLIENTS, Z}, dtypes::{Bucket, Key, Metadata, Path}, error::MycoError, network::{LocalServer1Access, LocalServer2Access}, server1::Server1, server2::Server2, tree::{self, deserialize_trees, serialize_trees, BinaryTree, DBStateParams}, crypto::{decrypt, encrypt, kdf, prf, EncryptionType}, utils::trim_zeros
+        client::{Client, EpochKeys, PrfKeyCache}, constants::{D, DELTA, MAX_NU, NUM_CLIENTS, PRECOMPUTE_EPOCHS, STORAGE_TAG_SIZE, WRITE_TOKEN_SIZE, Z}, distributed::{FrontServer1, LocalBackServer1Access, LocalFrontServer1Access}, dtypes::{Bucket, EpochInfo, Key, Metadata, Path}, envelope::{ContentType, Envelope}, error::MycoError, store::MessageStore, network::{LocalServer1Access, LocalServer2Access}, server1::Server1, server2::Server2, tree::{self, deserialize_trees, serialize_trees, BinaryTree, DBStateParams}, crypto::{client_pseudonym, decrypt, encrypt, kdf, prf, EncryptionType}, utils::{trim_zeros, unpad, Padding}, write_tokens::{TokenIssuer, TokenRequest}
     };
     use rand::{Rng, RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
@@ -26,7 +26,7 @@ mod e2e_tests {
     ) -> Result<Vec<u8>, MycoError> {
         for bucket in path {
             for block in bucket {
-                if let Ok(c_msg) = decrypt(&k_oblv_t.0, &block.0) {
+                if let Ok(c_msg) = decrypt(&k_oblv_t.0, block.ciphertext()) {
                     return decrypt(&k_msg.0, &c_msg);
                 }
             }
@@ -66,10 +66,1159 @@ mod e2e_tests {
         alice.write(&[1], &k).expect("Write failed");
         s1.write().unwrap().batch_write();
 
-        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed");
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
         assert_eq!(msg, vec![1]);
     }
 
+    #[test]
+    fn test_write_and_read_with_padme_padding() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+        alice.set_padding(Padding::Padme);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1, 0, 0], &k).expect("Write failed");
+        s1.write().unwrap().batch_write();
+
+        // The envelope delimits the payload, so its trailing zeros survive the bucketing.
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
+        assert_eq!(msg, vec![1, 0, 0]);
+    }
+
+    #[test]
+    fn test_delta_writes_need_encryption_off() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let mut server1 = Server1::new(s2_access);
+        assert!(matches!(server1.set_delta_writes(true), Err(MycoError::ConfigError(_))));
+        server1.set_delta_writes(false).expect("Turning delta writes off failed");
+    }
+
+    #[tokio::test]
+    async fn test_read_reports_provenance() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        for msg in 1..=2 {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], &k).expect("Write failed");
+            s1.write().unwrap().batch_write();
+        }
+
+        let received = alice.read(&k, "Alice".to_string(), 1).expect("Read failed");
+        assert_eq!((received.payload, received.contact, received.epoch), (vec![1], k.clone(), 0));
+        assert_eq!(received.path_len, D + 1);
+        assert!(received.found_at <= D);
+
+        let received = alice
+            .async_read(vec![k.clone()], "Alice".to_string(), 0, 1)
+            .await
+            .expect("Read failed");
+        assert_eq!((received[0].payload.clone(), received[0].epoch), (vec![2], 1));
+        assert_eq!(received[0].path_len, D + 1);
+    }
+
+    #[test]
+    fn test_batch_write_aborts_on_corrupt_metadata() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        // Claim a live message in the root bucket under a key that doesn't decrypt it.
+        let mut server1 = s1.write().unwrap();
+        server1.metadata.value[1] = Some(Metadata::new(
+            Path::new(vec![]),
+            Key::random(&mut rng),
+            u64::MAX,
+        ));
+        let metadata = server1.metadata.clone();
+        server1.batch_init(1);
+        match server1.batch_write() {
+            Err(MycoError::BatchWriteAborted { epoch, reason }) => {
+                assert_eq!(epoch, 1);
+                assert!(reason.contains("decrypting block 0"), "{}", reason);
+            }
+            result => panic!("expected an aborted batch write, got {:?}", result),
+        }
+        assert_eq!(server1.epoch, 1);
+        assert!(server1.message_queue.is_empty());
+        assert_eq!(server1.metadata, metadata);
+        drop(server1);
+
+        // The aborted epoch never reached Server2.
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
+        assert_eq!(msg, vec![1]);
+    }
+
+    #[test]
+    fn test_read_error_variants() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+        assert!(matches!(
+            alice.read(&k, "Alice".to_string(), 0),
+            Err(MycoError::EpochOutOfRange { epoch_past: 0, epoch: 0 })
+        ));
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        // Only one PRF key has been published, so older epochs have none.
+        alice.epoch = 3;
+        assert!(matches!(
+            alice.read(&k, "Alice".to_string(), 1),
+            Err(MycoError::PrfKeyUnavailable { epoch_past: 1 })
+        ));
+
+        let ct = encrypt(&k.0, &[1], EncryptionType::Encrypt).unwrap();
+        assert!(matches!(
+            decrypt(&Key::random(&mut rng).0, &ct),
+            Err(MycoError::DecryptAuthFailed)
+        ));
+        assert!(matches!(decrypt(&k.0, &ct[..8]), Err(MycoError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_prf_key_delta_sync() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let keys: Vec<Key> = (0..3).map(|_| Key::random(&mut rng)).collect();
+        let mut s2 = Server2::new();
+        for key in &keys {
+            s2.add_prf_key(key);
+        }
+        assert_eq!(s2.get_prf_keys_since(0).unwrap(), (0, keys.clone()));
+        assert_eq!(s2.get_prf_keys_since(2).unwrap(), (2, keys[2..].to_vec()));
+        assert_eq!(s2.get_prf_keys_since(3).unwrap(), (3, vec![]));
+        // A cursor ahead of the server gets every key back.
+        assert_eq!(s2.get_prf_keys_since(10).unwrap(), (0, keys.clone()));
+
+        let mut cache = PrfKeyCache::default();
+        cache.apply(0, keys[..2].to_vec());
+        cache.apply(2, keys[2..].to_vec());
+        assert_eq!(cache.cursor(), 3);
+        assert_eq!(cache.get(0), Some(&keys[2]));
+        assert_eq!(cache.get(2), Some(&keys[0]));
+        assert_eq!(cache.get(3), None);
+        // Keys that don't continue the cache replace it.
+        cache.apply(5, keys[..1].to_vec());
+        assert_eq!((cache.start, cache.cursor()), (5, 6));
+        assert_eq!(cache.get(1), None);
+    }
+
+    #[test]
+    fn test_read_syncs_prf_keys_incrementally() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        for msg in 1..=2u8 {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], &k).expect("Write failed");
+            s1.write().unwrap().batch_write();
+
+            assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![msg]);
+            assert_eq!(alice.prf_keys.lock().unwrap().cursor(), msg as u64);
+        }
+        assert_eq!(alice.read(&k, "Alice".to_string(), 1).expect("Read failed").payload, vec![1]);
+    }
+
+    #[test]
+    fn test_prf_key_cache_invalidated_on_rollback() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut cache = PrfKeyCache::default();
+        cache.apply(0, vec![Key::random(&mut rng), Key::random(&mut rng)]);
+        cache.synced = EpochInfo {
+            epoch: 2,
+            prf_key_cursor: 2,
+        };
+        assert!(cache.is_current(&EpochInfo {
+            epoch: 2,
+            prf_key_cursor: 2
+        }));
+        assert!(!cache.is_current(&EpochInfo {
+            epoch: 3,
+            prf_key_cursor: 3
+        }));
+        assert!(cache.is_rolled_back(&EpochInfo {
+            epoch: 1,
+            prf_key_cursor: 2
+        }));
+
+        // A client keeps reading after Server2 is restored from an older state.
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        for msg in 1..=2u8 {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], &k).expect("Write failed");
+            s1.write().unwrap().batch_write();
+            alice.read(&k, "Alice".to_string(), 0).expect("Read failed");
+        }
+
+        *s2.lock().unwrap() = Server2::new();
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[3], &k).expect("Write failed");
+        s1.write().unwrap().batch_write();
+        assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![3]);
+        let cache = alice.prf_keys.lock().unwrap();
+        assert_eq!((cache.start, cache.cursor()), (0, 1));
+        assert_eq!(cache.synced, s2.lock().unwrap().epoch_info());
+    }
+
+    #[test]
+    fn test_precomputed_epoch_keys() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+        alice.precompute_epochs(PRECOMPUTE_EPOCHS);
+
+        let (_, k_oblv, k_prf) = alice.keys.get(&k).unwrap().clone();
+        for epoch in 0..PRECOMPUTE_EPOCHS {
+            let cached = alice.epoch_keys.get(&(k.clone(), epoch)).expect("Epoch not precomputed");
+            assert_eq!(*cached, EpochKeys::derive(&k_oblv, &k_prf, epoch).unwrap());
+        }
+
+        for msg in 1..=2u8 {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], &k).expect("Write failed");
+            s1.write().unwrap().batch_write();
+            assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![msg]);
+        }
+    }
+
+    #[test]
+    fn test_server1_reports_storage_by_token() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = || Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access(), s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        for client in [&mut alice, &mut bob] {
+            let credentials = s1.write().unwrap().registrations.register(&mut rng).unwrap();
+            client.set_registration(Some(credentials));
+        }
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+        bob.setup(&k).expect("Setup failed");
+
+        s1.write().unwrap().batch_init(2);
+        // Alice writes three times under the same epoch's token.
+        for _ in 0..3 {
+            alice.epoch = 0;
+            alice.write(&[1], &k).expect("Write failed");
+        }
+        bob.write(&[2], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().unwrap();
+
+        let storage = s2.lock().unwrap().storage_stats();
+        assert_eq!((storage.epochs, storage.blocks, storage.tags), (1, 4, 2));
+        // Tags are hex encoded and their counts sorted largest first.
+        let counts: Vec<usize> = storage.heaviest.iter().map(|(_, blocks)| *blocks).collect();
+        assert_eq!(counts, vec![3, 1]);
+        assert!(storage.heaviest.iter().all(|(tag, _)| tag.len() == 2 * STORAGE_TAG_SIZE));
+    }
+
+    #[test]
+    fn test_write_quota() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+        s1.write().unwrap().set_write_quota(Some(1));
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let credentials = s1.write().unwrap().registrations.register(&mut rng).unwrap();
+        alice.set_registration(Some(credentials));
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k).expect("Write failed");
+        // A second write under the same epoch's token is rejected.
+        alice.epoch -= 1;
+        assert!(matches!(alice.write(&[2], &k), Err(MycoError::WriteQuotaExceeded)));
+        // The rejected write leaves the client's epoch alone.
+        assert_eq!(alice.epoch, 0);
+        alice.epoch += 1;
+        // Writes without a token Server1 issued are rejected while a quota is set.
+        let result = s1.write().unwrap().queue_write(vec![0; 32], vec![1; 32], k.clone(), vec![], vec![], None);
+        assert!(matches!(result, Err(MycoError::InvalidWriteToken)));
+        s1.write().unwrap().batch_write();
+        assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![1]);
+
+        // The next epoch comes with a fresh token and counts.
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[3], &k).expect("Write failed");
+        s1.write().unwrap().batch_write();
+        assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![3]);
+    }
+
+    #[test]
+    fn test_write_tokens_unlinkable_across_epochs() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let issuer = TokenIssuer::new(&mut rng);
+        let tokens: Vec<Vec<u8>> = (0..2)
+            .map(|epoch| {
+                let request = TokenRequest::new(epoch, &mut rng);
+                let evaluated = issuer.issue(epoch, &request.blinded()).unwrap();
+                request.finish(&evaluated).unwrap()
+            })
+            .collect();
+        assert!(tokens.iter().all(|token| token.len() == WRITE_TOKEN_SIZE));
+        // Neither the nonce nor the evaluated point carries over from one epoch to the next.
+        assert_ne!(tokens[0][..32], tokens[1][..32]);
+        assert_ne!(tokens[0][32..], tokens[1][32..]);
+        assert!(issuer.verify(0, &tokens[0]).is_ok());
+        assert!(issuer.verify(1, &tokens[1]).is_ok());
+    }
+
+    #[test]
+    fn test_client_pseudonyms_unlinkable_across_epochs() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_prf = Key::random(&mut rng);
+        let pseudonym = client_pseudonym(&k_prf.0, b"Alice", 0).unwrap();
+        assert_eq!(pseudonym, client_pseudonym(&k_prf.0, b"Alice", 0).unwrap());
+        assert_ne!(pseudonym, client_pseudonym(&k_prf.0, b"Alice", 1).unwrap());
+        assert_ne!(pseudonym, client_pseudonym(&k_prf.0, b"Bob", 0).unwrap());
+        // Without the contact's PRF key, the ID can't be checked against the pseudonym.
+        let other = Key::random(&mut rng);
+        assert_ne!(pseudonym, client_pseudonym(&other.0, b"Alice", 0).unwrap());
+    }
+
+    #[test]
+    fn test_late_write_rejected_with_next_epoch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let mut s1 = Server1::new(s2_access);
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut write = |s1: &mut Server1| {
+            s1.queue_write(vec![0; 32], vec![1; 32], Key::random(&mut rng), vec![], vec![], None)
+        };
+
+        // Before the first batch_init nothing is known about the next epoch.
+        assert!(matches!(
+            write(&mut s1),
+            Err(MycoError::EpochClosed { next_epoch_opens_at: None })
+        ));
+
+        s1.batch_init(1);
+        write(&mut s1).expect("Write failed");
+        s1.batch_write().expect("Batch write failed");
+
+        // Once a batch write has been timed, late writes get an estimate.
+        s1.batch_init(1);
+        s1.batch_write().expect("Batch write failed");
+        assert!(matches!(
+            write(&mut s1),
+            Err(MycoError::EpochClosed { next_epoch_opens_at: Some(_) })
+        ));
+    }
+
+
+    #[test]
+    fn test_first_write_initializes_batch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+        s1.write().unwrap().set_auto_batch_init(Some(1));
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        // No batch_init anywhere: each epoch's first write opens it.
+        for msg in 1..=2 {
+            assert!(!s1.read().unwrap().is_batch_open());
+            alice.write(&[msg], &k).expect("Write failed");
+            assert_eq!(s1.read().unwrap().num_clients, 1);
+            s1.write().unwrap().batch_write().expect("Batch write failed");
+            assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![msg]);
+        }
+
+        // An explicit batch_init still sizes the batch, and turning the option off restores the
+        // old behavior.
+        s1.write().unwrap().batch_init(3);
+        alice.write(&[3], &k).expect("Write failed");
+        assert_eq!(s1.read().unwrap().num_clients, 3);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        s1.write().unwrap().set_auto_batch_init(None);
+        assert!(matches!(
+            s1.write().unwrap().queue_write(vec![0; 32], vec![1; 32], k.clone(), vec![], vec![], None),
+            Err(MycoError::EpochClosed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cancelled_batch_init_rejects_writes() {
+        use futures::FutureExt;
+        use myco_rs::{
+            server1::EpochState,
+            transport::{InMemoryServer2Transport, TransportServer2Access},
+        };
+
+        let s2 = Arc::new(tokio::sync::RwLock::new(Server2::new()));
+        let s2_access = TransportServer2Access::new(Box::new(InMemoryServer2Transport {
+            server2: s2.clone(),
+        }));
+        let mut s1 = Server1::new(Box::new(s2_access));
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+
+        // Holding Server2 stalls the init on its read, and dropping it there leaves the trees
+        // half rebuilt.
+        {
+            let _s2 = futures::executor::block_on(s2.read());
+            assert!(s1.async_batch_init(1).now_or_never().is_none());
+        }
+        assert_eq!(s1.epoch_state(), EpochState::Initializing);
+        assert!(matches!(
+            s1.queue_write(vec![0; 32], vec![1; 32], k.clone(), vec![], vec![], None),
+            Err(MycoError::EpochInitializing)
+        ));
+
+        // A completed init opens the batch again.
+        s1.batch_init(1);
+        assert_eq!(s1.epoch_state(), EpochState::Accepting);
+        s1.batch_write().expect("Batch write failed");
+        assert_eq!(s1.epoch_state(), EpochState::Closed);
+    }
+
+    #[test]
+    fn test_writes_racing_epochs_are_queued_or_rejected() {
+        const WRITERS: usize = 4;
+        const EPOCHS: usize = 5;
+
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        // One write per writer and epoch keeps the batches within the size they're built for.
+        s1.write().unwrap().set_write_quota(Some(1));
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        // Every writer holds a token for each epoch, and one past the last.
+        let mut rng = ChaCha20Rng::from_entropy();
+        let tokens: Vec<Vec<Vec<u8>>> = (0..WRITERS)
+            .map(|_| {
+                let mut s1 = s1.write().unwrap();
+                let account = s1.registrations.register(&mut rng).unwrap();
+                let requests: Vec<TokenRequest> =
+                    (0..=EPOCHS as u64).map(|epoch| TokenRequest::new(epoch, &mut rng)).collect();
+                let blinded = requests.iter().map(|r| (r.epoch, r.blinded())).collect();
+                let issued = s1.issue_write_tokens(&account, blinded).unwrap();
+                requests
+                    .into_iter()
+                    .zip(issued)
+                    .map(|(request, (_, evaluated))| request.finish(&evaluated).unwrap())
+                    .collect()
+            })
+            .collect();
+
+        // Writers hammer Server1 while the epochs below are opened and written out under them.
+        let writers: Vec<_> = (0..WRITERS)
+            .zip(tokens)
+            .map(|(i, tokens)| {
+                let s1 = s1.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let mut rng = ChaCha20Rng::from_entropy();
+                    let k = Key::random(&mut rng);
+                    let mut accepted = 0;
+                    while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                        let mut f = vec![0; 32];
+                        rng.fill_bytes(&mut f);
+                        let mut s1 = s1.write().unwrap();
+                        let token = tokens[s1.epoch as usize].clone();
+                        let result =
+                            s1.queue_write(vec![0; 32], f, k.clone(), vec![i as u8], token, None);
+                        drop(s1);
+                        match result {
+                            Ok(_) => accepted += 1,
+                            Err(
+                                MycoError::EpochClosed { .. }
+                                | MycoError::EpochInitializing
+                                | MycoError::WriteQuotaExceeded,
+                            ) => {}
+                            Err(e) => panic!("Unexpected write error: {:?}", e),
+                        }
+                        std::thread::yield_now();
+                    }
+                    accepted
+                })
+            })
+            .collect();
+
+        let mut written = 0;
+        for _ in 0..EPOCHS {
+            s1.write().unwrap().batch_init(WRITERS);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            s1.write().unwrap().batch_write().expect("Batch write failed");
+            written += s1.read().unwrap().last_write_stats().unwrap().writes;
+        }
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        let accepted: usize = writers.into_iter().map(|w| w.join().unwrap()).sum();
+
+        // Every accepted write made it into exactly one batch.
+        assert!(written > 0);
+        assert_eq!(accepted, written);
+    }
+
+    #[test]
+    fn test_client_requeues_late_write() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        // The write arrives before the epoch opens and is requeued until it does.
+        let opener = {
+            let s1 = s1.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                s1.write().unwrap().batch_init(1);
+            })
+        };
+        alice.write(&[1], &k).expect("Write failed");
+        opener.join().unwrap();
+        s1.write().unwrap().batch_write();
+
+        assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![1]);
+    }
+
+    #[test]
+    fn test_write_missing_epoch_close_lands_in_next_epoch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        // Epoch 1 closes before Alice's write for it arrives, and the write is requeued into
+        // epoch 2 once that opens.
+        s1.write().unwrap().batch_init(1);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        assert_eq!(alice.epoch, 1);
+        let opener = {
+            let s1 = s1.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                s1.write().unwrap().batch_init(1);
+            })
+        };
+        alice.write(&[2], &k).expect("Write failed");
+        opener.join().unwrap();
+        assert_eq!(alice.epoch, 3);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed");
+        assert_eq!((msg.payload, msg.epoch), (vec![2], 2));
+    }
+
+    #[tokio::test]
+    async fn test_async_write_missing_epoch_close_lands_in_next_epoch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        s1.write().unwrap().batch_init(1);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        let opener = {
+            let s1 = s1.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                s1.write().unwrap().batch_init(1);
+            })
+        };
+        alice.async_write(&[2], &k).await.expect("Write failed");
+        opener.join().unwrap();
+        assert_eq!(alice.epoch, 3);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed");
+        assert_eq!((msg.payload, msg.epoch), (vec![2], 2));
+    }
+
+    #[test]
+    fn test_write_receipt_tracks_server_epoch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        // A write rejected before the epoch opens leaves the client's epoch alone.
+        assert!(alice.write(&[1], &k).is_err());
+        assert_eq!(alice.epoch, 0);
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k).expect("Write failed");
+        assert_eq!(alice.epoch, 1);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        // Server1 moves on without the client, which finds out from its next write's receipt.
+        s1.write().unwrap().batch_init(1);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        s1.write().unwrap().batch_init(1);
+        assert!(matches!(
+            alice.write(&[2], &k),
+            Err(MycoError::WriteEpochMismatch { written: 1, accepted: 2 })
+        ));
+        assert_eq!(alice.epoch, 3);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        // Back in step, the write is found where its recipient looks.
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[3], &k).expect("Write failed");
+        assert_eq!(alice.epoch, 4);
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![3]);
+    }
+
+    #[test]
+    fn test_envelope_sequence_numbers() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        for (sequence, msg) in [vec![1, 0, 0], vec![2]].into_iter().enumerate() {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&msg, &k).expect("Write failed");
+            s1.write().unwrap().batch_write();
+
+            let envelope = alice.read_envelope(&k, "Alice".to_string(), 0).expect("Read failed");
+            assert_eq!(envelope.header.sequence, sequence as u64);
+            assert_eq!(envelope.header.content_type, ContentType::Binary);
+            assert_eq!(envelope.payload, msg);
+        }
+
+        s1.write().unwrap().batch_init(1);
+        let envelope = Envelope::new(b"{}".to_vec()).with_content_type(ContentType::Json);
+        alice.write_envelope(&envelope, &k).expect("Write failed");
+        s1.write().unwrap().batch_write();
+        assert_eq!(alice.read_envelope(&k, "Alice".to_string(), 0).expect("Read failed"), envelope);
+    }
+
+    #[tokio::test]
+    async fn test_reads_suppress_duplicates() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        for msg in [1, 2] {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], &k).expect("Write failed");
+            s1.write().unwrap().batch_write();
+        }
+
+        let first = alice.read_new(&k, "Alice".to_string(), 1).expect("Read failed");
+        assert_eq!(first.map(|envelope| envelope.payload), Some(vec![1]));
+        // Reading an overlapping range again only delivers the message not seen yet.
+        assert_eq!(alice.read_new(&k, "Alice".to_string(), 1).expect("Read failed"), None);
+        let read = alice.async_read(vec![k.clone()], "Alice".to_string(), 0, 1).await.expect("Read failed");
+        assert_eq!(read.len(), 1);
+        assert_eq!((read[0].payload.clone(), read[0].contact.clone()), (vec![2], k.clone()));
+        let read = alice.async_read(vec![k.clone()], "Alice".to_string(), 0, 1).await.expect("Read failed");
+        assert!(read.is_empty());
+        // Plain reads are not deduplicated.
+        assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_sync_catches_up_in_order() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut carol = Client::new("Carol".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (k_ab, k_cb, k_other) = (Key::random(&mut rng), Key::random(&mut rng), Key::random(&mut rng));
+        for (client, keys) in [(&mut alice, [&k_ab, &k_other]), (&mut carol, [&k_cb, &k_other]), (&mut bob, [&k_ab, &k_cb])] {
+            for k in keys {
+                client.setup(k).expect("Setup failed");
+            }
+        }
+
+        // Alice and Carol write to Bob in some of three epochs while Bob is away.
+        for (from_alice, from_carol) in [(Some(1), None), (None, Some(2)), (Some(3), Some(4))] {
+            s1.write().unwrap().batch_init(2);
+            for (client, msg, k) in [(&mut alice, from_alice, &k_ab), (&mut carol, from_carol, &k_cb)] {
+                match msg {
+                    Some(msg) => client.write(&[msg], k),
+                    None => client.write(&[0], &k_other),
+                }
+                .expect("Write failed");
+            }
+            s1.write().unwrap().batch_write();
+        }
+        bob.epoch = alice.epoch;
+
+        let contacts = [(k_ab.clone(), "Alice".to_string()), (k_cb.clone(), "Carol".to_string())];
+        let messages = bob.sync(&contacts).await.expect("Sync failed");
+        let received: Vec<_> = messages
+            .iter()
+            .map(|message| (message.payload.clone(), message.epoch, message.contact == k_ab))
+            .collect();
+        // Grouped by contact in the order given, each in the order it was sent.
+        assert_eq!(
+            received,
+            vec![(vec![1], 0, true), (vec![3], 2, true), (vec![2], 1, false), (vec![4], 2, false)]
+        );
+        assert!(messages.iter().all(|message| message.missing().is_empty()));
+        // Messages already delivered aren't returned again.
+        assert!(bob.sync(&contacts).await.expect("Sync failed").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sync_orders_by_sequence_and_reports_gaps() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        bob.setup(&k).expect("Setup failed");
+
+        // Alice's messages reach the tree out of the order she sent them, and message 2 never
+        // does.
+        for sequence in [1, 0, 3] {
+            s1.write().unwrap().batch_init(1);
+            let envelope = Envelope::new(vec![sequence as u8]).with_sequence(sequence);
+            alice.write_envelope(&envelope, &k).expect("Write failed");
+            s1.write().unwrap().batch_write().expect("Batch write failed");
+        }
+        bob.epoch = alice.epoch;
+
+        let contacts = [(k.clone(), "Alice".to_string())];
+        let messages = bob.sync(&contacts).await.expect("Sync failed");
+        let received: Vec<_> = messages
+            .iter()
+            .map(|message| (message.sequence, message.epoch, message.missing()))
+            .collect();
+        assert_eq!(received, vec![(0, 1, 0..0), (1, 0, 1..1), (3, 2, 2..3)]);
+        assert_eq!(messages[2].expected, 2);
+
+        // Message 2 turning up late fills the gap rather than opening another.
+        s1.write().unwrap().batch_init(1);
+        alice.write_envelope(&Envelope::new(vec![2]).with_sequence(2), &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        bob.epoch = alice.epoch;
+        let messages = bob.sync(&contacts).await.expect("Sync failed");
+        assert_eq!(messages.len(), 1);
+        assert_eq!((messages[0].sequence, messages[0].expected), (2, 4));
+        assert!(messages[0].missing().is_empty());
+    }
+
+    #[test]
+    fn test_repeated_reads_report_gaps_once() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        bob.setup(&k).expect("Setup failed");
+
+        // Messages 0 and 1 never reach the tree.
+        s1.write().unwrap().batch_init(1);
+        alice.write_envelope(&Envelope::new(vec![2]).with_sequence(2), &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        bob.epoch = alice.epoch;
+
+        let message = bob.read(&k, "Alice".to_string(), 0).expect("Read failed");
+        assert_eq!((message.sequence, message.missing()), (2, 0..2));
+        // Reading the same message again doesn't report the gap a second time.
+        let message = bob.read(&k, "Alice".to_string(), 0).expect("Read failed");
+        assert_eq!((message.sequence, message.expected), (2, 3));
+        assert!(message.missing().is_empty());
+    }
+
+    #[test]
+    fn test_restarted_sender_continues_sequence() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut bob = Client::new("Bob".to_string(), s1_access.clone(), s2_access.clone());
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        bob.setup(&k).expect("Setup failed");
+        let path = std::env::temp_dir().join(format!("myco-e2e-store-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        for msg in [1, 2] {
+            // Alice restarts before every write, keeping only her store.
+            let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+            alice.set_store(MessageStore::open(&path).expect("Open failed"));
+            alice.setup(&k).expect("Setup failed");
+            alice.epoch = bob.epoch;
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], &k).expect("Write failed");
+            s1.write().unwrap().batch_write().expect("Batch write failed");
+            bob.epoch = alice.epoch;
+
+            let message = bob.read(&k, "Alice".to_string(), 0).expect("Read failed");
+            assert_eq!(message.sequence, u64::from(msg) - 1);
+            assert!(message.missing().is_empty());
+        }
+        std::fs::remove_file(&path).expect("Remove failed");
+    }
+
+    #[test]
+    fn test_read_budget_enforced() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+        alice.set_read_budget(Some(3));
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        // An epoch without reads is topped up with fake ones when the write ends it.
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k).expect("Write failed");
+        s1.write().unwrap().batch_write();
+        let counts = alice.last_read_counts().expect("No counts for the first epoch");
+        assert_eq!((counts.epoch, counts.real, counts.fake), (0, 0, 3));
+
+        for _ in 0..3 {
+            assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![1]);
+        }
+        assert!(matches!(
+            alice.read(&k, "Alice".to_string(), 0),
+            Err(MycoError::ReadBudgetExceeded { epoch: 1, budget: 3 })
+        ));
+        assert!(alice.fake_read().expect("Fake read failed").is_empty());
+        let counts = alice.read_counts();
+        assert_eq!((counts.epoch, counts.real, counts.fake, counts.refused), (1, 3, 0, 2));
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[2], &k).expect("Write failed");
+        assert_eq!(alice.last_read_counts().map(|counts| counts.total()), Some(3));
+        assert_eq!(alice.read_counts().total(), 0);
+    }
+
+    #[test]
+    fn test_revoke_contact() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (k, k_carol) = (Key::random(&mut rng), Key::random(&mut rng));
+        alice.setup(&k).expect("Setup failed");
+        alice.setup(&k_carol).expect("Setup failed");
+        bob.setup(&k).expect("Setup failed");
+
+        // Alice writes to Carol, since both sides of a contact writing in the same epoch can read
+        // each other's message instead of the one they're after.
+        s1.write().unwrap().batch_init(2);
+        alice.write(&[1], &k_carol).expect("Write failed");
+        bob.write(&[7], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        assert_eq!(alice.read(&k, "Bob".to_string(), 0).expect("Read failed").payload, vec![7]);
+
+        s1.write().unwrap().batch_init(1);
+        alice.revoke(&k, true).expect("Revoke failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        // Alice's keys for the contact are gone, so she can no longer decrypt Bob's messages.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!alice.keys.contains_key(&k));
+        assert!(alice.keys.contains_key(&k_carol));
+        assert!(!alice.epoch_keys.iter().any(|entry| entry.key().0 == k));
+        assert!(matches!(alice.read(&k, "Bob".to_string(), 1), Err(MycoError::UnknownContact)));
+        assert!(matches!(alice.write(&[2], &k), Err(MycoError::UnknownContact)));
+        assert!(matches!(alice.revoke(&k, false), Err(MycoError::UnknownContact)));
+
+        // Bob receives the tombstone.
+        bob.epoch += 1;
+        let tombstone = bob.read_envelope(&k, "Alice".to_string(), 0).expect("Read failed");
+        assert_eq!(tombstone.header.content_type, ContentType::Tombstone);
+        assert!(tombstone.payload.is_empty());
+    }
+
+    #[test]
+    fn test_contact_bundle_import_export() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        assert!(matches!(
+            alice.export_contact(&k, "https://s1", "https://s2", None),
+            Err(MycoError::UnknownContact)
+        ));
+        alice.setup(&k).expect("Setup failed");
+        let qr = alice.export_contact(&k, "https://s1", "https://s2", None).expect("Export failed");
+        let bundle = bob.import_contact(&qr).expect("Import failed");
+        assert_eq!(bundle.id, "Alice");
+        assert_eq!(bundle.server1, "https://s1");
+        assert!(bob.keys.contains_key(&k));
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[3], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        bob.epoch += 1;
+        assert_eq!(bob.read(&k, bundle.id, 0).expect("Read failed").payload, vec![3]);
+    }
+
+    #[test]
+    fn test_catch_up_orders_by_sequence_and_reports_gaps() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        bob.setup(&k).expect("Setup failed");
+
+        // Alice's messages reach the tree out of the order she sent them, and message 2 never
+        // does.
+        for sequence in [1, 0, 3] {
+            s1.write().unwrap().batch_init(1);
+            let envelope = Envelope::new(vec![sequence as u8]).with_sequence(sequence);
+            alice.write_envelope(&envelope, &k).expect("Write failed");
+            s1.write().unwrap().batch_write();
+        }
+        bob.epoch = alice.epoch;
+
+        let messages = bob.catch_up(&k, "Alice".to_string(), 3).expect("Catch-up failed");
+        let received: Vec<_> = messages
+            .iter()
+            .map(|message| (message.envelope.header.sequence, message.missing()))
+            .collect();
+        assert_eq!(received, vec![(0, 0..0), (1, 1..1), (3, 2..3)]);
+        assert_eq!(messages[2].expected, 2);
+
+        // Message 2 turning up late fills the gap rather than opening another.
+        s1.write().unwrap().batch_init(1);
+        alice.write_envelope(&Envelope::new(vec![2]).with_sequence(2), &k).expect("Write failed");
+        s1.write().unwrap().batch_write();
+        bob.epoch = alice.epoch;
+        let message = bob.read_sequenced(&k, "Alice".to_string(), 0).expect("Read failed");
+        assert_eq!((message.envelope.header.sequence, message.expected), (2, 4));
+        assert!(message.missing().is_empty());
+    }
+
+    #[test]
+    fn test_nu_validation() {
+        let s2_access = Box::new(LocalServer2Access { server: Arc::new(Mutex::new(Server2::new())) });
+        let mut s1 = Server1::new(s2_access);
+        assert_eq!(s1.nu(), 1);
+        assert!(matches!(s1.set_nu(0), Err(MycoError::ConfigError(_))));
+        assert!(matches!(s1.set_nu(MAX_NU + 1), Err(MycoError::ConfigError(_))));
+        assert_eq!(s1.nu(), 1);
+        s1.set_nu(MAX_NU).expect("Set nu failed");
+        assert_eq!(s1.nu(), MAX_NU);
+    }
+
+    fn test_write_and_read_with_nu(nu: usize) {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        s1.write().unwrap().set_nu(nu).expect("Set nu failed");
+
+        let num_clients = 3;
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut clients: Vec<(Client, Key)> = (0..num_clients)
+            .map(|i| {
+                let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+                let mut client = Client::new(format!("Client_{}", i), s1_access, s2_access.clone());
+                let k = Key::random(&mut rng);
+                client.setup(&k).expect("Setup failed");
+                (client, k)
+            })
+            .collect();
+
+        for epoch in 0..2u8 {
+            s1.write().unwrap().batch_init(num_clients);
+            // Every sampled path contributes D + 1 buckets, shared towards the root.
+            let pathset_size = s1.read().unwrap().pathset_size();
+            assert!(pathset_size <= nu * num_clients * (D + 1));
+            assert!(pathset_size > D * nu);
+
+            for (i, (client, k)) in clients.iter_mut().enumerate() {
+                client.write(&[epoch + 1, i as u8 + 1], k).expect("Write failed");
+            }
+            s1.write().unwrap().batch_write();
+
+            for (i, (client, k)) in clients.iter().enumerate() {
+                let msg = client.read(k, client.id.clone(), 0).expect("Read failed").payload;
+                assert_eq!(msg, vec![epoch + 1, i as u8 + 1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_nu_2() {
+        test_write_and_read_with_nu(2);
+    }
+
+    #[test]
+    fn test_write_and_read_nu_4() {
+        test_write_and_read_with_nu(4);
+    }
+
+    #[test]
+    fn test_distributed_trust_write_and_read() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let back = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let back_access = LocalBackServer1Access::new(back.clone());
+        let back_key = back_access.public_key();
+        let front = Arc::new(Mutex::new(FrontServer1::new(Box::new(back_access))));
+        let front_access = Box::new(LocalFrontServer1Access::new(front.clone()));
+
+        let mut alice = Client::new("Alice".to_string(), front_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), front_access.clone(), s2_access.clone());
+        let mut carol = Client::new("Carol".to_string(), front_access.clone(), s2_access.clone());
+        for client in [&mut alice, &mut bob, &mut carol] {
+            client.set_distributed_trust(back_key);
+        }
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_alice = Key::random(&mut rng);
+        let k_bob = Key::random(&mut rng);
+        alice.setup(&k_alice).expect("Setup failed");
+        bob.setup(&k_bob).expect("Setup failed");
+
+        for epoch in 0..2 {
+            back.write().unwrap().batch_init(3);
+            front.lock().unwrap().batch_init();
+
+            alice.write(&[epoch as u8, 1], &k_alice).expect("Write failed");
+            bob.write(&[epoch as u8, 2], &k_bob).expect("Write failed");
+            carol.fake_write().expect("Fake write failed");
+
+            let share = front.lock().unwrap().flush().expect("Flush failed");
+            back.write().unwrap().add_upstream_key_share(share);
+            back.write().unwrap().batch_write();
+
+            let msg = alice.read(&k_alice, "Alice".to_string(), 0).expect("Read failed").payload;
+            assert_eq!(msg, vec![epoch as u8, 1]);
+            let msg = bob.read(&k_bob, "Bob".to_string(), 0).expect("Read failed").payload;
+            assert_eq!(msg, vec![epoch as u8, 2]);
+        }
+
+        // Without the back's sealing key a client's writes are turned away by the front.
+        let mut dave = Client::new("Dave".to_string(), front_access, s2_access);
+        dave.setup(&k_alice).expect("Setup failed");
+        dave.epoch = alice.epoch;
+        back.write().unwrap().batch_init(1);
+        front.lock().unwrap().batch_init();
+        assert!(matches!(dave.write(&[3], &k_alice), Err(MycoError::ConfigError(_))));
+    }
+
     #[test]
     fn test_multiple_clients_one_epoch() {
         let s2 = Arc::new(Mutex::new(Server2::new()));
@@ -103,10 +1252,10 @@ mod e2e_tests {
 
         s1.write().unwrap().batch_write();
 
-        let msg = alice.read(&k2, "Bob".to_string(), 0).expect("Read failed");
+        let msg = alice.read(&k2, "Bob".to_string(), 0).expect("Read failed").payload;
         assert_eq!(msg, vec![2]);
 
-        let msg = bob.read(&k1, "Alice".to_string(), 0).expect("Read failed");
+        let msg = bob.read(&k1, "Alice".to_string(), 0).expect("Read failed").payload;
         assert_eq!(msg, vec![1]);
     }
 
@@ -130,7 +1279,7 @@ mod e2e_tests {
             s1.write().unwrap().batch_init(1);
             alice.write(&msg, &k).expect("Write failed");
             s1.write().unwrap().batch_write();
-            let read_msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed");
+            let read_msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
 
             assert_eq!(
                 read_msg, msg,
@@ -182,7 +1331,7 @@ mod e2e_tests {
 
             let alice_read = alice
                 .read(&k_bob_to_alice, "Bob".to_string(), 0)
-                .expect("Read failed");
+                .expect("Read failed").payload;
             assert_eq!(
                 bob_msg, alice_read,
                 "Read message doesn't match written message for bob"
@@ -190,7 +1339,7 @@ mod e2e_tests {
 
             let bob_read = bob
                 .read(&k_alice_to_bob, "Alice".to_string(), 0)
-                .expect("Read failed");
+                .expect("Read failed").payload;
             assert_eq!(
                 alice_msg, bob_read,
                 "Read message doesn't match written message for alice"
@@ -219,7 +1368,7 @@ mod e2e_tests {
 
         let alice_read_epoch1: Vec<u8> = alice
             .read(&key, "Alice".to_string(), 0) // Read from epoch 1
-            .expect("Read failed");
+            .expect("Read failed").payload;
 
         assert_eq!(
             alice_msg_epoch1, alice_read_epoch1,
@@ -238,7 +1387,7 @@ mod e2e_tests {
         // Alice reads from epoch 1
         let alice_read_epoch1: Vec<u8> = alice
             .read(&key, "Alice".to_string(), 1) // Read from epoch 1
-            .expect("Read failed");
+            .expect("Read failed").payload;
 
         assert_eq!(
             alice_msg_epoch1, alice_read_epoch1,
@@ -302,11 +1451,11 @@ mod e2e_tests {
 
         let alice_read_epoch1: Vec<u8> = alice
             .read(&key_bob_to_alice, "Bob".to_string(), 1) // Read from epoch 1
-            .expect("Alice read failed from epoch 1");
+            .expect("Alice read failed from epoch 1").payload;
 
         let bob_read_epoch1: Vec<u8> = bob
             .read(&key_alice_to_bob, "Alice".to_string(), 1) // Bob reads Alice's message from epoch 1
-            .expect("Bob read failed from epoch 1");
+            .expect("Bob read failed from epoch 1").payload;
 
         assert_eq!(
             bob_msg_epoch1, alice_read_epoch1,
@@ -349,7 +1498,7 @@ mod e2e_tests {
         // Alice reads from epoch 1
         let alice_read_epoch1_epoch2: Vec<u8> = alice
             .read(&key, "Alice".to_string(), 1) // Read from epoch 1
-            .expect("Read failed in epoch 2");
+            .expect("Read failed in epoch 2").payload;
 
         assert_eq!(
             alice_msg_epoch1, alice_read_epoch1_epoch2,
@@ -367,7 +1516,7 @@ mod e2e_tests {
         // Alice reads from epoch 1 again
         let alice_read_epoch1_epoch3: Vec<u8> = alice
             .read(&key, "Alice".to_string(), 2) // Read from epoch 1
-            .expect("Read failed in epoch 3");
+            .expect("Read failed in epoch 3").payload;
 
         assert_eq!(
             alice_msg_epoch1, alice_read_epoch1_epoch3,
@@ -385,7 +1534,7 @@ mod e2e_tests {
         // Alice reads from epoch 1 again in epoch 4
         let alice_read_epoch1_epoch4: Vec<u8> = alice
             .read(&key, "Alice".to_string(), 3) // Read from epoch 1 in epoch 4
-            .expect("Read failed in epoch 4");
+            .expect("Read failed in epoch 4").payload;
 
         assert_eq!(
             alice_msg_epoch1, alice_read_epoch1_epoch4,
@@ -400,7 +1549,7 @@ mod e2e_tests {
         let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
         let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
 
-        let num_epochs = DELTA as usize;
+        let num_epochs = DELTA;
         let num_clients = 1;
 
         // Create a vector of unique messages and keys
@@ -438,9 +1587,10 @@ mod e2e_tests {
                                 .get(b)
                                 .ok_or(MycoError::MetadataIndexError(b))?;
                             let c_msg = bucket.get(b).ok_or(MycoError::BucketIndexError(b))?;
-                            if let Ok(ct) = decrypt(&k_oblv_t.0, &c_msg.0) {
+                            if let Ok(ct) = decrypt(&k_oblv_t.0, c_msg.ciphertext()) {
                                 if let Ok(decrypted) = decrypt(&k_msg, &ct) {
-                                    decrypted_messages.push(trim_zeros(&decrypted));
+                                    let envelope = Envelope::decode(&unpad(&decrypted, Padding::default())?)?;
+                                    decrypted_messages.push(envelope.payload);
                                 }
                             }
                             Ok(())
@@ -487,18 +1637,18 @@ mod e2e_tests {
 
         // Doing a client write manually and extracting the intended path of this message
         let epoch = client.epoch;
-        let cs = client.id.clone().into_bytes();
         let (k_msg, k_oblv, k_prf) = client.keys.get(&key).unwrap();
+        let cs = client_pseudonym(k_prf, client.id.as_bytes(), epoch).expect("PRF failed");
         let f: Vec<u8> = prf(k_prf, &epoch.to_be_bytes()).expect("PRF failed");
         let k_oblv_t = kdf(k_oblv, &epoch.to_string()).expect("KDF failed");
         let ct = encrypt(k_msg, &message, EncryptionType::Encrypt).expect("Encryption failed");
         client.epoch += 1;
         client
             .s1
-            .queue_write(ct, f.clone(), Key::new(k_oblv_t), cs.clone())
+            .queue_write(ct, f.clone(), Key::new(k_oblv_t), cs.clone(), vec![], None)
             .await
             .expect("Initial write failed");
-        let k_s1_t = s1.read().unwrap().k_s1_t.0.clone();
+        let k_s1_t = s1.read().unwrap().published_key().unwrap().0.clone();
         let l = prf(
             k_s1_t.as_slice(),
             &[f.clone().as_slice(), cs.clone().as_slice()].concat(),
@@ -528,7 +1678,7 @@ mod e2e_tests {
                     .get(b)
                     .ok_or(MycoError::BucketIndexError(b))
                     .expect("Failed to get bucket item");
-                if let Ok(ct) = decrypt(&k_oblv_t.0, &c_msg.0) {
+                if let Ok(ct) = decrypt(&k_oblv_t.0, c_msg.ciphertext()) {
                     if let Some((k_msg, _, _)) = client.keys.get(&key) {
                         if let Ok(decrypted) = decrypt(k_msg, &ct) {
                             let trimmed = trim_zeros(&decrypted);
@@ -546,7 +1696,7 @@ mod e2e_tests {
 
         // Verify message at LCA
         assert!(
-            verify_message_at_lca(&lca_bucket, &lca_path),
+            verify_message_at_lca(lca_bucket, &lca_path),
             "Message not found at LCA in epoch {}",
             epoch
         );
@@ -576,7 +1726,7 @@ mod e2e_tests {
 
                 // Verify message at LCA
                 assert!(
-                    verify_message_at_lca(&lca_bucket, &lca_path),
+                    verify_message_at_lca(lca_bucket, &lca_path),
                     "Message not found at LCA in epoch {}",
                     epoch
                 );
@@ -650,7 +1800,7 @@ mod e2e_tests {
             for client in clients.iter() {
                 let _: Vec<u8> = client
                     .read(&key, client.id.clone(), 0)
-                    .expect(&format!("Read failed in epoch {}", epoch));
+                    .unwrap_or_else(|_| panic!("Read failed in epoch {}", epoch)).payload;
             }
         }
     }
@@ -690,10 +1840,11 @@ mod e2e_tests {
             &s2.lock().unwrap().tree,
             &s1.lock().unwrap().metadata,
             &state_params,
-        );
+        )
+        .unwrap();
 
         let (server2_tree_deserialized, server1_metadata_deserialized) =
-            deserialize_trees(&state_params);
+            deserialize_trees(&state_params).unwrap();
 
         // Assert that the deserialized server 1 metadata and the server 2 tree are the same as the original ones.
         assert_eq!(s1.lock().unwrap().metadata, server1_metadata_deserialized);
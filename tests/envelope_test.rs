@@ -0,0 +1,67 @@
+#[cfg(test)]
+mod envelope_tests {
+    use myco_rs::{
+        constants::MESSAGE_SIZE,
+        envelope::{ContentType, Envelope, ENVELOPE_VERSION, MAX_PAYLOAD_SIZE},
+        error::MycoError,
+    };
+
+    #[test]
+    fn test_roundtrip_keeps_trailing_zeros() {
+        let envelope = Envelope::new(vec![7, 0, 0])
+            .with_content_type(ContentType::Text)
+            .with_sequence(42);
+        let mut bytes = envelope.encode().expect("Encode failed");
+        bytes.resize(MESSAGE_SIZE, 0);
+
+        let decoded = Envelope::decode(&bytes).expect("Decode failed");
+        assert_eq!(decoded, envelope);
+        assert_eq!(decoded.payload, vec![7, 0, 0]);
+    }
+
+    #[test]
+    fn test_max_payload_fits_message() {
+        let envelope = Envelope::new(vec![1; MAX_PAYLOAD_SIZE])
+            .with_content_type(ContentType::Application(u16::MAX))
+            .with_sequence(u64::MAX)
+            .with_fragment(u16::MAX - 1, u16::MAX);
+        let bytes = envelope.encode().expect("Encode failed");
+        assert!(bytes.len() <= MESSAGE_SIZE);
+
+        let oversized = Envelope::new(vec![1; MAX_PAYLOAD_SIZE + 1]);
+        assert!(matches!(oversized.encode(), Err(MycoError::ProtocolError(_))));
+    }
+
+    #[test]
+    fn test_rejects_invalid_envelopes() {
+        let invalid_fragment = Envelope::new(vec![1]).with_fragment(1, 1);
+        assert!(matches!(invalid_fragment.encode(), Err(MycoError::ProtocolError(_))));
+
+        let mut future = Envelope::new(vec![1]);
+        future.header.version = ENVELOPE_VERSION + 1;
+        let bytes = future.encode().expect("Encode failed");
+        assert!(matches!(Envelope::decode(&bytes), Err(MycoError::ProtocolError(_))));
+
+        assert!(matches!(Envelope::decode(&[]), Err(MycoError::DeserializationError(Some(_)))));
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let payload: Vec<u8> = (0..MAX_PAYLOAD_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let mut fragments = Envelope::split(&payload, ContentType::Json, 3).expect("Split failed");
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments.iter().all(|envelope| envelope.header.sequence == 3
+            && envelope.header.content_type == ContentType::Json
+            && envelope.header.fragment.count == 3));
+
+        fragments.reverse();
+        assert_eq!(Envelope::join(fragments.clone()).expect("Join failed"), payload);
+
+        fragments.pop();
+        assert!(matches!(Envelope::join(fragments), Err(MycoError::ProtocolError(_))));
+        assert!(matches!(Envelope::join(vec![]), Err(MycoError::ProtocolError(_))));
+
+        let empty = Envelope::split(&[], ContentType::Binary, 0).expect("Split failed");
+        assert_eq!(Envelope::join(empty).expect("Join failed"), Vec::<u8>::new());
+    }
+}
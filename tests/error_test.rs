@@ -0,0 +1,51 @@
+#[cfg(test)]
+mod error_tests {
+    use std::{collections::HashSet, error::Error};
+
+    use myco_rs::{
+        envelope::Envelope,
+        error::{ErrorCode, MycoError},
+    };
+
+    #[test]
+    fn test_codes_are_unique_and_round_trip() {
+        let mut seen = HashSet::new();
+        for code in ErrorCode::ALL {
+            let value = u32::from(code);
+            assert!(seen.insert(value), "code {} is used twice", value);
+            assert_eq!(ErrorCode::try_from(value), Ok(code));
+        }
+        assert_eq!(ErrorCode::try_from(0), Err(0));
+        assert_eq!(u32::from(ErrorCode::NoMessageFound), 200);
+        assert_eq!(u32::from(ErrorCode::WriteQuotaExceeded), 204);
+    }
+
+    #[test]
+    fn test_remote_errors_keep_their_code() {
+        let err = MycoError::WriteQuotaExceeded;
+        let rebuilt = MycoError::from_remote(err.code(), err.to_string());
+        assert!(matches!(rebuilt, MycoError::WriteQuotaExceeded));
+
+        let err = MycoError::BatchWriteAborted {
+            epoch: 3,
+            reason: "writing to Server2".to_string(),
+        };
+        let rebuilt = MycoError::from_remote(err.code(), err.to_string());
+        assert_eq!(rebuilt.code(), ErrorCode::BatchWriteAborted);
+        assert_eq!(rebuilt.to_string(), err.to_string());
+    }
+
+    #[test]
+    fn test_sources_are_chained() {
+        let err = Envelope::decode(&[]).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::DeserializationError);
+        assert!(err.source().is_some_and(|source| source.is::<bincode::Error>()));
+
+        let err = MycoError::from(std::io::Error::new(std::io::ErrorKind::NotFound, "snapshot"));
+        assert!(err.source().is_some_and(|source| source.is::<std::io::Error>()));
+
+        let err = MycoError::transport("get_epoch", MycoError::UnknownContact);
+        assert_eq!(err.code(), ErrorCode::TransportError);
+        assert!(err.source().is_some());
+    }
+}
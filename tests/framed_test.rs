@@ -0,0 +1,182 @@
+#[cfg(test)]
+mod framed_tests {
+    use std::sync::Arc;
+
+    use myco_rs::{
+        admin::EpochControl,
+        client::Client,
+        dtypes::Key,
+        framed::{self, read_frame, write_frame, FramedConnection, MAX_FRAME_SIZE},
+        memory::{allocator_stats, CountingAllocator},
+        network::{Command, ReadType},
+        server1::Server1,
+        server2::Server2,
+        tls::TlsTrust,
+        transport::{self, Transport, TransportServer1Access, TransportServer2Access},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tokio::{io::AsyncWriteExt, net::TcpListener, sync::RwLock};
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    async fn spawn_server2(server2: Arc<RwLock<Server2>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(framed::serve(listener, None, None, move |command| {
+            let server2 = server2.clone();
+            async move { transport::handle_server2_command(&server2, command).await }
+        }));
+        addr
+    }
+
+    async fn spawn_server1(server1: Arc<RwLock<Server1>>, control: Arc<EpochControl>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(framed::serve(listener, None, None, move |command| {
+            let server1 = server1.clone();
+            let control = control.clone();
+            async move { transport::handle_server1_command(&server1, &control, command).await }
+        }));
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_frame_round_trip() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        write_frame(&mut a, &Command::Server2Read(ReadType::GetPrfKeys))
+            .await
+            .unwrap();
+        drop(a);
+        assert!(matches!(
+            read_frame(&mut b).await.unwrap(),
+            Some(Command::Server2Read(ReadType::GetPrfKeys))
+        ));
+        // A clean close between frames is not an error.
+        assert!(read_frame(&mut b).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_rejected() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        a.write_u32(MAX_FRAME_SIZE as u32 + 1).await.unwrap();
+        assert!(read_frame(&mut b).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_frame_length_does_not_allocate_ahead_of_the_bytes() {
+        // A peer announcing the largest frame allowed and then sending a few bytes only gets a
+        // buffer the size of what it sent.
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        a.write_u32(MAX_FRAME_SIZE as u32).await.unwrap();
+        a.write_all(&[0; 16]).await.unwrap();
+        drop(a);
+        let before = allocator_stats().unwrap().peak_bytes;
+        assert!(matches!(
+            read_frame(&mut b).await,
+            Err(myco_rs::error::MycoError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+        let grown = allocator_stats().unwrap().peak_bytes.saturating_sub(before);
+        assert!(grown < MAX_FRAME_SIZE / 16, "read grew the heap by {} bytes", grown);
+    }
+
+    #[tokio::test]
+    async fn test_tls_with_generated_certificate() {
+        let dir = std::env::temp_dir().join(format!("myco-framed-certs-{}", std::process::id()));
+        myco_rs::utils::generate_test_certificates(Some(&dir)).unwrap();
+        let cert_path = dir.join("server-cert.pem");
+        let acceptor = framed::tls_acceptor(&cert_path, &dir.join("server-key.pem")).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server2 = Arc::new(RwLock::new(Server2::new()));
+        tokio::spawn(framed::serve(listener, Some(acceptor), None, move |command| {
+            let server2 = server2.clone();
+            async move { transport::handle_server2_command(&server2, command).await }
+        }));
+
+        let trust = TlsTrust::self_signed(&cert_path);
+        let connection =
+            FramedConnection::connect_tls(&addr, &trust.connector().unwrap(), "localhost")
+                .await
+                .unwrap();
+        assert!(matches!(
+            connection
+                .call(Command::Server2Read(ReadType::GetPrfKeys))
+                .await
+                .unwrap(),
+            Command::PrfKeys(_)
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_command_returns_error() {
+        let addr = spawn_server2(Arc::new(RwLock::new(Server2::new()))).await;
+        let connection = FramedConnection::connect(&addr).await.unwrap();
+        assert!(connection
+            .call(Command::Server1Write(
+                vec![],
+                vec![],
+                Key::new(vec![]),
+                vec![],
+                vec![],
+                None
+            ))
+            .await
+            .is_err());
+        // The connection stays usable after an error response.
+        assert!(matches!(
+            connection
+                .call(Command::Server2Read(ReadType::GetPrfKeys))
+                .await
+                .unwrap(),
+            Command::PrfKeys(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_over_framed_transport() {
+        let s2_addr = spawn_server2(Arc::new(RwLock::new(Server2::new()))).await;
+        let s2_access = TransportServer2Access::new(Box::new(
+            FramedConnection::connect(&s2_addr).await.unwrap(),
+        ));
+        let server1 = Arc::new(RwLock::new(Server1::new(Box::new(s2_access))));
+        let control = Arc::new(EpochControl::new());
+        let s1_addr = spawn_server1(server1.clone(), control.clone()).await;
+
+        let mut alice = Client::new(
+            "Alice".to_string(),
+            Box::new(TransportServer1Access::new(Box::new(
+                FramedConnection::connect(&s1_addr).await.unwrap(),
+            ))),
+            Box::new(TransportServer2Access::new(Box::new(
+                FramedConnection::connect(&s2_addr).await.unwrap(),
+            ))),
+        );
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        // Writes are rejected until a batch is open.
+        assert!(alice.async_write(&[1], &k).await.is_err());
+
+        server1.write().await.async_batch_init(1).await.expect("Batch init failed");
+        control.set_epoch_open(true);
+        alice.async_write(&[1], &k).await.expect("Write failed");
+        server1
+            .write()
+            .await
+            .async_batch_write()
+            .await
+            .expect("Batch write failed");
+
+        let msgs = alice
+            .async_read(vec![k], "Alice".to_string(), 0, 1)
+            .await
+            .expect("Read failed");
+        let payloads: Vec<_> = msgs.into_iter().map(|msg| msg.payload).collect();
+        assert_eq!(payloads, vec![vec![1]]);
+    }
+}
@@ -0,0 +1,122 @@
+#[cfg(test)]
+mod group_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use myco_rs::{
+        client::Client,
+        group::{GroupMessage, GroupSession},
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+        topic::Mailboxes,
+    };
+
+    const NAMES: [&str; 3] = ["Alice", "Bob", "Carol"];
+
+    struct Member {
+        client: Client,
+        mailboxes: Mailboxes,
+    }
+
+    fn setup() -> (Arc<RwLock<Server1>>, Vec<Member>) {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut members: Vec<Member> = NAMES
+            .iter()
+            .map(|name| Member {
+                client: Client::new(name.to_string(), s1_access.clone(), s2_access.clone()),
+                mailboxes: Mailboxes::new(),
+            })
+            .collect();
+
+        // Every pair of members shares a mailbox, named after the other member.
+        for (i, a_name) in NAMES.iter().enumerate() {
+            for (j, b_name) in NAMES.iter().enumerate().skip(i + 1) {
+                let (left, right) = members.split_at_mut(j);
+                let (a, b) = (&mut left[i], &mut right[0]);
+                a.mailboxes.create(&mut a.client, b_name, b_name).unwrap();
+                let shared = a.mailboxes.share(&a.client, b_name, "https://s1", "https://s2").unwrap();
+                b.mailboxes.import(&mut b.client, a_name, &shared).unwrap();
+            }
+        }
+        (s1, members)
+    }
+
+    fn start(member: &mut Member) -> GroupSession {
+        let mailboxes: Vec<_> = member.mailboxes.iter().cloned().collect();
+        let mailboxes: Vec<_> = mailboxes.iter().collect();
+        GroupSession::new(&mut member.client, "friends", &mailboxes).unwrap()
+    }
+
+    /// Step every session in one epoch, returning what each received.
+    fn epoch(
+        s1: &RwLock<Server1>,
+        members: &mut [Member],
+        sessions: &mut [GroupSession],
+    ) -> Vec<Vec<GroupMessage>> {
+        s1.write().unwrap().batch_init(members.len());
+        let received = members
+            .iter_mut()
+            .zip(sessions.iter_mut())
+            .map(|(member, session)| session.step(&mut member.client).unwrap())
+            .collect();
+        s1.write().unwrap().batch_write().unwrap();
+        received
+    }
+
+    fn payloads(received: &[GroupMessage]) -> Vec<(&str, &[u8])> {
+        received
+            .iter()
+            .map(|message| (message.sender.as_str(), message.payload.as_slice()))
+            .collect()
+    }
+
+    #[test]
+    fn test_message_written_once_reaches_every_member() {
+        let (s1, mut members) = setup();
+        let mut sessions: Vec<_> = members.iter_mut().map(start).collect();
+        sessions[0].send(b"hello").unwrap();
+
+        // The sender keys take one epoch per member to distribute.
+        for _ in 0..2 {
+            let received = epoch(&s1, &mut members, &mut sessions);
+            assert!(received.iter().all(Vec::is_empty));
+        }
+        assert!(sessions.iter().all(GroupSession::is_distributed));
+        // Alice's message is a single write, like everyone's cover message.
+        epoch(&s1, &mut members, &mut sessions);
+        assert_eq!(s1.read().unwrap().last_write_stats().unwrap().writes, members.len());
+
+        let received = epoch(&s1, &mut members, &mut sessions);
+        assert!(received[0].is_empty());
+        assert_eq!(payloads(&received[1]), vec![("Alice", &b"hello"[..])]);
+        assert_eq!(payloads(&received[2]), vec![("Alice", &b"hello"[..])]);
+    }
+
+    #[test]
+    fn test_removed_member_cannot_read_after_rekey() {
+        let (s1, mut members) = setup();
+        let mut sessions: Vec<_> = members.iter_mut().map(start).collect();
+        for _ in 0..2 {
+            epoch(&s1, &mut members, &mut sessions);
+        }
+
+        // Alice and Bob remove Carol, who doesn't learn of it.
+        for i in 0..2 {
+            sessions[i].remove_member(&mut members[i].client, "Carol").unwrap();
+            assert_eq!(sessions[i].generation(), 1);
+        }
+        sessions[0].send(b"without carol").unwrap();
+        let mut received = vec![vec![]; members.len()];
+        for _ in 0..3 {
+            for (all, new) in received.iter_mut().zip(epoch(&s1, &mut members, &mut sessions)) {
+                all.extend(new);
+            }
+        }
+        assert_eq!(payloads(&received[1]), vec![("Alice", &b"without carol"[..])]);
+        assert!(received[2].is_empty());
+        assert_eq!(sessions[0].members().collect::<Vec<_>>(), vec!["Bob"]);
+    }
+}
@@ -0,0 +1,99 @@
+#[cfg(test)]
+mod hardening_tests {
+    use axum::{
+        body::{Body, Bytes},
+        http::{header, Request, StatusCode},
+        routing::post,
+        Router,
+    };
+    use myco_rs::{
+        constants::{MAX_CHUNK_WRITE_BODY_SIZE, MAX_CONTROL_BODY_SIZE, MAX_QUEUE_WRITE_BODY_SIZE},
+        error::ErrorCode,
+        hardening::{self, max_body_size, BINCODE_CONTENT_TYPE},
+        rpc_types::QueueWriteRequest,
+    };
+    use tower::ServiceExt;
+
+    fn router() -> Router {
+        hardening::harden(
+            Router::new()
+                .route("/queue_write", post(|bytes: Bytes| async move { bytes.len().to_string() }))
+                .route("/chunk_write", post(|bytes: Bytes| async move { bytes.len().to_string() })),
+        )
+    }
+
+    async fn status(route: &str, content_type: Option<&str>, body: Vec<u8>) -> StatusCode {
+        let mut request = Request::post(route);
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        router()
+            .oneshot(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[test]
+    fn test_route_limits() {
+        assert_eq!(max_body_size("/queue_write"), MAX_QUEUE_WRITE_BODY_SIZE);
+        assert_eq!(max_body_size("/chunk_write"), MAX_CHUNK_WRITE_BODY_SIZE);
+        // Unchunked writes get no more room than a chunk.
+        assert_eq!(max_body_size("/write"), MAX_CHUNK_WRITE_BODY_SIZE);
+        assert_eq!(max_body_size("/unknown"), MAX_CONTROL_BODY_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_body_limit_enforced_per_route() {
+        let body = vec![0u8; MAX_QUEUE_WRITE_BODY_SIZE + 1];
+        assert_eq!(
+            status("/queue_write", Some(BINCODE_CONTENT_TYPE), body.clone()).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status("/chunk_write", Some(BINCODE_CONTENT_TYPE), body).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_content_type_enforced() {
+        assert_eq!(
+            status("/queue_write", Some("application/json"), vec![1, 2, 3]).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            status("/queue_write", None, vec![1, 2, 3]).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        // Empty bodies don't need a content type.
+        assert_eq!(status("/queue_write", None, vec![]).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_decode_rejects_forged_length() {
+        // A Vec<u8> length prefix claiming far more bytes than the body contains.
+        let mut bytes = u64::MAX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0u8; 16]);
+        let err = hardening::decode::<QueueWriteRequest>(&bytes).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.code, ErrorCode::MalformedRequest);
+    }
+
+    #[test]
+    fn test_decode_matches_bincode() {
+        let request = QueueWriteRequest {
+            ct: vec![1; 32],
+            f: vec![2; 32],
+            k_oblv_t: myco_rs::dtypes::Key::new(vec![3; 16]),
+            cs: b"Alice".to_vec(),
+            token: vec![4; 32],
+            idempotency_key: None,
+            access_tag: None,
+        };
+        let bytes = bincode::serialize(&request).unwrap();
+        let decoded: QueueWriteRequest = hardening::decode(&bytes).unwrap();
+        assert_eq!(decoded.ct, request.ct);
+        assert_eq!(decoded.cs, request.cs);
+    }
+}
@@ -0,0 +1,544 @@
+#[cfg(test)]
+mod http_tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{
+        body::{Body, Bytes},
+        http::{header, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use myco_rs::{
+        bandwidth::{self, BandwidthMeter, Traffic},
+        constants::D,
+        dtypes::{BandwidthStats, Direction, Key, Path, TrafficBytes},
+        error::{ErrorCode, MycoError},
+        idempotency::{IdempotencyKey, IDEMPOTENCY_KEY_HEADER},
+        logging::{self, MetricsSink},
+        network::LocalServer2Access,
+        rpc_types::{
+            BatchInitRequest, ChunkWriteRequest, FinalizeEpochRequest, GetEpochResponse, GetPrfKeysResponse, GetPrfKeysSinceRequest,
+            ErrorResponse, GetPrfKeysSinceResponse, GetStatsResponse, QueueWriteRequest,
+            PublishStorageRequest, ReadPathsClientRequest, ReadRequest, ReadResponse,
+        },
+        server1::{self, Server1},
+        server2::{self, Server2},
+        write_tokens::TokenRequest,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use serde::Serialize;
+    use tower::ServiceExt;
+
+    /// A write token Server1 issued for its current epoch to a fresh account.
+    fn issue_token(server1: &mut Server1, rng: &mut ChaCha20Rng) -> Vec<u8> {
+        let account = server1.registrations.register(rng).unwrap();
+        let request = TokenRequest::new(server1.epoch, rng);
+        let issued = server1
+            .issue_write_tokens(&account, vec![(request.epoch, request.blinded())])
+            .unwrap();
+        request.finish(&issued[0].1).unwrap()
+    }
+
+    async fn post<T: Serialize>(app: &Router, route: &str, body: &T) -> (StatusCode, Bytes) {
+        let request = Request::post(route)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(Body::from(bincode::serialize(body).unwrap()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        (
+            status,
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_server2_router_mounts_in_existing_service() {
+        let state = server2::http::AppState::new(Server2::new());
+        let app = Router::new()
+            .route("/health", get(|| async { "ok" }))
+            .nest("/myco", server2::http::router().with_state(state.clone()));
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let key = Key::random(&mut rng);
+        let (status, _) = post(
+            &app,
+            "/myco/finalize_epoch",
+            &FinalizeEpochRequest {
+                prf_key: key.clone(),
+                epoch: 0,
+            },
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.server2.read().await.epoch, 1);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/myco/get_prf_keys")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let keys: GetPrfKeysResponse = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(keys.keys, vec![key]);
+
+        let (status, bytes) = post(
+            &app,
+            "/myco/get_prf_keys_since",
+            &GetPrfKeysSinceRequest { cursor: 1 },
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let delta: GetPrfKeysSinceResponse = bincode::deserialize(&bytes).unwrap();
+        assert_eq!((delta.start, delta.keys), (1, vec![]));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/myco/epoch").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let epoch: GetEpochResponse = bincode::deserialize(&bytes).unwrap();
+        assert_eq!((epoch.info.epoch, epoch.info.prf_key_cursor), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_server2_read_single_path() {
+        let state = server2::http::AppState::new(Server2::new());
+        let app = server2::http::router().with_state(state.clone());
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let path = Path::random(&mut rng);
+        let (status, bytes) = post(&app, "/read", &ReadRequest { path: path.clone() }).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: ReadResponse = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(
+            response.buckets,
+            state.server2.read().await.read(&path).unwrap()
+        );
+
+        // Paths deeper than the tree are rejected.
+        let (status, _) = post(
+            &app,
+            "/read",
+            &ReadRequest {
+                path: Path::new(vec![Direction::Left; D + 1]),
+            },
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_server2_rejects_client_reads_outside_tree() {
+        let state = server2::http::AppState::new(Server2::new());
+        let app = server2::http::router().with_state(state.clone());
+        // The root is at index 1, so the tree's last bucket is at 2^(D + 1) - 1.
+        let end = 1 << (D + 1);
+
+        let (status, _) = post(
+            &app,
+            "/read_paths_client",
+            &ReadPathsClientRequest { indices: vec![1, end - 1] },
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        for indices in [vec![1, end], vec![0], vec![usize::MAX]] {
+            let (status, body) =
+                post(&app, "/read_paths_client", &ReadPathsClientRequest { indices }).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let error: ErrorResponse = bincode::deserialize(&body).unwrap();
+            assert_eq!(error.code, ErrorCode::MalformedRequest);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server2_stats_count_reads() {
+        let state = server2::http::AppState::new(Server2::new());
+        let app = server2::http::router().with_state(state.clone());
+        let stats = || async {
+            let response = app
+                .clone()
+                .oneshot(Request::get("/stats").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            bincode::deserialize::<GetStatsResponse>(&bytes).unwrap()
+        };
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        for _ in 0..2 {
+            let (status, _) = post(&app, "/read", &ReadRequest { path: Path::random(&mut rng) }).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let response = stats().await;
+        assert_eq!((response.current.epoch, response.current.reads), (0, 2));
+        assert_eq!(response.previous, None);
+
+        let prf_key = Key::random(&mut rng);
+        post(&app, "/finalize_epoch", &FinalizeEpochRequest { prf_key, epoch: 0 }).await;
+        let response = stats().await;
+        assert_eq!((response.current.epoch, response.current.reads), (1, 0));
+        assert_eq!(response.previous.map(|stats| stats.reads), Some(2));
+        assert_eq!(response.storage.epochs, 1);
+        assert_eq!(response.storage.blocks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_server2_stats_account_storage() {
+        let state = server2::http::AppState::new(Server2::new());
+        let app = server2::http::router().with_state(state.clone());
+        let mut rng = ChaCha20Rng::from_entropy();
+
+        let report = PublishStorageRequest {
+            report: vec![(vec![1; 8], 3), (vec![2; 8], 1)],
+        };
+        let (status, _) = post(&app, "/storage", &report).await;
+        assert_eq!(status, StatusCode::OK);
+        // The report is only accounted once its epoch is written.
+        assert_eq!(state.server2.read().await.storage_stats().blocks, 0);
+
+        let prf_key = Key::random(&mut rng);
+        post(&app, "/finalize_epoch", &FinalizeEpochRequest { prf_key, epoch: 0 }).await;
+        let storage = state.server2.read().await.storage_stats();
+        assert_eq!((storage.epochs, storage.blocks, storage.tags), (1, 4, 2));
+        assert_eq!(storage.heaviest[0], (hex::encode([1; 8]), 3));
+    }
+
+    #[tokio::test]
+    async fn test_server2_rejects_epoch_rollback() {
+        let state = server2::http::AppState::new(Server2::new());
+        let app = server2::http::router().with_state(state.clone());
+        let conflict = |(status, bytes): (StatusCode, Bytes)| {
+            assert_eq!(status, StatusCode::CONFLICT);
+            bincode::deserialize::<ErrorResponse>(&bytes).unwrap().code
+        };
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let key = Key::random(&mut rng);
+        let finalize = FinalizeEpochRequest {
+            prf_key: key.clone(),
+            epoch: 0,
+        };
+        let (status, _) = post(&app, "/finalize_epoch", &finalize).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A replayed finalization and a stale chunk write leave the tree alone.
+        let code = conflict(post(&app, "/finalize_epoch", &finalize).await);
+        assert_eq!(code, ErrorCode::StaleEpoch);
+        let chunk = ChunkWriteRequest {
+            buckets: vec![],
+            chunk_idx: 0,
+            prf_key: key.clone(),
+            epoch: 0,
+        };
+        let code = conflict(post(&app, "/chunk_write", &chunk).await);
+        assert_eq!(code, ErrorCode::StaleEpoch);
+
+        // The next epoch needs a fresh key.
+        let reused = FinalizeEpochRequest {
+            prf_key: key.clone(),
+            epoch: 1,
+        };
+        let code = conflict(post(&app, "/finalize_epoch", &reused).await);
+        assert_eq!(code, ErrorCode::PrfKeyReused);
+
+        let server2 = state.server2.read().await;
+        assert_eq!(server2.epoch, 1);
+        assert_eq!(server2.prf_keys, vec![key]);
+    }
+
+    #[tokio::test]
+    async fn test_server1_router_rejects_writes_outside_epoch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let state = server1::http::AppState::new(Server1::new(s2_access));
+        let app = server1::http::router().with_state(state.clone());
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let write = QueueWriteRequest {
+            ct: vec![0; 32],
+            f: vec![1; 32],
+            k_oblv_t: Key::random(&mut rng),
+            cs: b"Alice".to_vec(),
+            token: vec![],
+            idempotency_key: None,
+            access_tag: None,
+        };
+        let (status, body) = post(&app, "/queue_write", &write).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let error: ErrorResponse = bincode::deserialize(&body).unwrap();
+        assert_eq!(error.code, ErrorCode::EpochClosed);
+        assert!(matches!(error.into_error(), MycoError::EpochClosed { .. }));
+
+        let (status, body) = post(&app, "/batch_init", &[0u8; 3]).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = bincode::deserialize(&body).unwrap();
+        assert_eq!(error.code, ErrorCode::MalformedRequest);
+
+        let init = BatchInitRequest {
+            num_writes: 1,
+            idempotency_key: None,
+        };
+        let (status, _) = post(&app, "/batch_init", &init).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post(&app, "/queue_write", &write).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.server1.read().await.queue_depth(), 1);
+    }
+
+
+    #[tokio::test]
+    async fn test_server1_router_opens_epoch_on_first_write() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let mut server1 = Server1::new(s2_access);
+        server1.set_auto_batch_init(Some(2));
+        let state = server1::http::AppState::new(server1);
+        let app = server1::http::router().with_state(state.clone());
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let write = QueueWriteRequest {
+            ct: vec![0; 32],
+            f: vec![1; 32],
+            k_oblv_t: Key::random(&mut rng),
+            cs: b"Alice".to_vec(),
+            token: vec![],
+            idempotency_key: None,
+            access_tag: None,
+        };
+        let (status, _) = post(&app, "/queue_write", &write).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.control.is_epoch_open());
+        assert_eq!(state.server1.read().await.num_clients, 2);
+
+        // A draining server stays closed.
+        state.server1.write().await.async_batch_write().await.unwrap();
+        state.control.set_epoch_open(false);
+        state.control.start_drain();
+        let (status, _) = post(&app, "/queue_write", &write).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!state.server1.read().await.is_batch_open());
+    }
+    #[tokio::test]
+    async fn test_server1_router_enforces_write_quota() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let mut server1 = Server1::new(s2_access);
+        server1.set_write_quota(Some(1));
+        let mut rng = ChaCha20Rng::from_entropy();
+        let token = issue_token(&mut server1, &mut rng);
+        let state = server1::http::AppState::new(server1);
+        let app = server1::http::router().with_state(state.clone());
+
+        let write = QueueWriteRequest {
+            ct: vec![0; 32],
+            f: vec![1; 32],
+            k_oblv_t: Key::random(&mut rng),
+            cs: b"Alice".to_vec(),
+            token,
+            idempotency_key: None,
+            access_tag: None,
+        };
+        let init = BatchInitRequest {
+            num_writes: 1,
+            idempotency_key: None,
+        };
+        let (status, _) = post(&app, "/batch_init", &init).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post(&app, "/queue_write", &write).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = post(&app, "/queue_write", &write).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let error: ErrorResponse = bincode::deserialize(&body).unwrap();
+        assert!(matches!(error.into_error(), MycoError::WriteQuotaExceeded));
+        assert_eq!(state.server1.read().await.queue_depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_server1_router_replays_idempotent_requests() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let mut server1 = Server1::new(s2_access);
+        server1.set_write_quota(Some(1));
+        let mut rng = ChaCha20Rng::from_entropy();
+        let token = issue_token(&mut server1, &mut rng);
+        let state = server1::http::AppState::new(server1);
+        let app = server1::http::router().with_state(state.clone());
+
+        let init = BatchInitRequest {
+            num_writes: 1,
+            idempotency_key: Some(IdempotencyKey::random(&mut rng)),
+        };
+        for _ in 0..2 {
+            let (status, _) = post(&app, "/batch_init", &init).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        // A resent write is answered from the cache instead of being queued or counted again.
+        let write = QueueWriteRequest {
+            ct: vec![0; 32],
+            f: vec![1; 32],
+            k_oblv_t: Key::random(&mut rng),
+            cs: b"Alice".to_vec(),
+            token,
+            idempotency_key: Some(IdempotencyKey::random(&mut rng)),
+            access_tag: None,
+        };
+        for _ in 0..2 {
+            let (status, _) = post(&app, "/queue_write", &write).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert_eq!(state.server1.read().await.queue_depth(), 1);
+
+        let key = IdempotencyKey::random(&mut rng);
+        for _ in 0..2 {
+            let request = Request::get("/batch_write")
+                .header(IDEMPOTENCY_KEY_HEADER, key.to_string())
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(state.server1.read().await.epoch, 1);
+        assert_eq!(s2.lock().unwrap().epoch, 1);
+
+        let request = Request::get("/batch_write")
+            .header(IDEMPOTENCY_KEY_HEADER, "not hex")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        latencies: Mutex<Vec<String>>,
+        bytes: Mutex<Vec<(String, usize)>>,
+    }
+
+    impl MetricsSink for RecordingSink {
+        fn record_latency(&self, operation: &str, _latency: Duration) {
+            self.latencies.lock().unwrap().push(operation.to_string());
+        }
+
+        fn record_bytes(&self, operation: &str, bytes: usize) {
+            self.bytes.lock().unwrap().push((operation.to_string(), bytes));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_instrumented_router_records_every_route() {
+        let sink = Arc::new(RecordingSink::default());
+        let state = server2::http::AppState::new(Server2::new());
+        let app = logging::instrument(server2::http::router(), "server2", sink.clone())
+            .with_state(state);
+
+        let request = ReadRequest {
+            path: Path::new(vec![Direction::Left; D]),
+        };
+        let (status, body) = post(&app, "/read", &request).await;
+        assert_eq!(status, StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(Request::get("/epoch").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(
+            *sink.latencies.lock().unwrap(),
+            vec!["server2_http_read", "server2_http_epoch"]
+        );
+        let bytes = sink.bytes.lock().unwrap();
+        assert!(bytes.contains(&(
+            "server2_http_read_request".to_string(),
+            bincode::serialized_size(&request).unwrap() as usize
+        )));
+        assert!(bytes.contains(&("server2_http_read_response".to_string(), body.len())));
+        assert!(bytes.contains(&("server2_http_epoch_request".to_string(), 0)));
+    }
+
+    #[tokio::test]
+    async fn test_metered_router_accounts_bandwidth_per_epoch() {
+        let server2 = Server2::new();
+        let meter = server2.bandwidth_meter().clone();
+        let state = server2::http::AppState::new(server2);
+        let app = bandwidth::meter(server2::http::router(), meter).with_state(state);
+
+        let request = ReadRequest {
+            path: Path::new(vec![Direction::Left; D]),
+        };
+        let (status, body) = post(&app, "/read", &request).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut rng = ChaCha20Rng::from_entropy();
+        let finalize = FinalizeEpochRequest {
+            prf_key: Key::random(&mut rng),
+            epoch: 0,
+        };
+        let (status, _) = post(&app, "/finalize_epoch", &finalize).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/stats").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let stats = bincode::deserialize::<GetStatsResponse>(&bytes).unwrap();
+        // The finalize response is sent once the epoch has advanced, so it counts towards the next.
+        assert_eq!(stats.bandwidth.epoch, 1);
+        assert_eq!(stats.bandwidth.client_reads, TrafficBytes::default());
+        assert_eq!(stats.bandwidth.batch_write.received, 0);
+        let previous = stats.previous_bandwidth.unwrap();
+        assert_eq!(previous.epoch, 0);
+        assert_eq!(
+            previous.client_reads,
+            TrafficBytes {
+                received: bincode::serialized_size(&request).unwrap() as usize,
+                sent: body.len(),
+            }
+        );
+        assert_eq!(
+            previous.batch_write.received,
+            bincode::serialized_size(&finalize).unwrap() as usize
+        );
+        assert_eq!(previous.client_writes, TrafficBytes::default());
+    }
+
+    #[test]
+    fn test_bandwidth_meter_reports_epoch_totals() {
+        let sink = Arc::new(RecordingSink::default());
+        let meter = BandwidthMeter::new("server2", sink.clone());
+        meter.record(Traffic::BatchInit, 10, 4096);
+        meter.record(Traffic::BatchInit, 10, 4096);
+
+        let stats = meter.finish_epoch(3);
+        assert_eq!(stats.batch_init, TrafficBytes { received: 20, sent: 8192 });
+        assert_eq!(meter.last_stats(), Some(stats));
+        assert_eq!(meter.stats(4), BandwidthStats { epoch: 4, ..Default::default() });
+        let bytes = sink.bytes.lock().unwrap();
+        assert!(bytes.contains(&("server2_epoch_batch_init_received".to_string(), 20)));
+        assert!(bytes.contains(&("server2_epoch_batch_init_sent".to_string(), 8192)));
+        assert!(bytes.contains(&("server2_epoch_client_writes_sent".to_string(), 0)));
+    }
+}
@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod idempotency_tests {
+    use std::time::Duration;
+
+    use axum::{
+        body::Bytes,
+        http::{HeaderMap, HeaderValue},
+    };
+    use myco_rs::{
+        error::MycoError,
+        idempotency::{IdempotencyKey, ResponseCache, IDEMPOTENCY_KEY_HEADER},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_cache_is_scoped_by_route_and_key() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let key = Some(IdempotencyKey::random(&mut rng));
+        let cache = ResponseCache::default();
+        let response = Bytes::from_static(b"ok");
+
+        cache.insert("/queue_write", key, &response);
+        assert_eq!(cache.get("/queue_write", key), Some(response.clone()));
+        assert_eq!(cache.get("/batch_init", key), None);
+        assert_eq!(
+            cache.get("/queue_write", Some(IdempotencyKey::random(&mut rng))),
+            None
+        );
+
+        // Requests without a key are never cached.
+        cache.insert("/queue_write", None, &response);
+        assert_eq!(cache.get("/queue_write", None), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_cache_forgets_old_responses() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let response = Bytes::from_static(b"ok");
+
+        let cache = ResponseCache::new(Duration::ZERO, 16);
+        let key = Some(IdempotencyKey::random(&mut rng));
+        cache.insert("/queue_write", key, &response);
+        assert_eq!(cache.get("/queue_write", key), None);
+        assert!(cache.is_empty());
+
+        let cache = ResponseCache::new(Duration::from_secs(60), 2);
+        let keys: Vec<_> = (0..3)
+            .map(|_| Some(IdempotencyKey::random(&mut rng)))
+            .collect();
+        for key in &keys {
+            cache.insert("/queue_write", *key, &response);
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("/queue_write", keys[0]), None);
+        assert_eq!(cache.get("/queue_write", keys[2]), Some(response));
+    }
+
+    #[test]
+    fn test_key_header_round_trip() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let key = IdempotencyKey::random(&mut rng);
+        let mut headers = HeaderMap::new();
+        assert_eq!(IdempotencyKey::from_headers(&headers).unwrap(), None);
+
+        headers.insert(
+            IDEMPOTENCY_KEY_HEADER,
+            HeaderValue::from_str(&key.to_string()).unwrap(),
+        );
+        assert_eq!(IdempotencyKey::from_headers(&headers).unwrap(), Some(key));
+
+        for invalid in ["abcd", "zz"] {
+            headers.insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_static(invalid));
+            assert!(matches!(
+                IdempotencyKey::from_headers(&headers),
+                Err(MycoError::MalformedRequest(_))
+            ));
+        }
+    }
+}
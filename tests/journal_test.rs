@@ -0,0 +1,139 @@
+#[cfg(test)]
+mod journal_tests {
+    use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+    use myco_rs::{
+        dtypes::{Block, Bucket, Key},
+        journal::{chunk_digest, committed_epochs, Journal, JournalRecord, JournalSync},
+        server2::Server2,
+        utils::get_leaf_path_indices,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("myco-journal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn buckets(n: usize) -> Vec<Bucket> {
+        (0..n)
+            .map(|_| {
+                let mut bucket = Bucket::default();
+                bucket.push(Block::new_random());
+                bucket
+            })
+            .collect()
+    }
+
+    fn key() -> Key {
+        Key::random(&mut ChaCha20Rng::from_entropy())
+    }
+
+    #[test]
+    fn test_replay_rolls_committed_epochs_forward_and_drops_the_rest() {
+        let path = journal_path("replay");
+        let pathset = get_leaf_path_indices(&[0, 5]).unwrap();
+        let mut server2 = Server2::new();
+        let (journal, records) = Journal::open(&path, JournalSync::Always).unwrap();
+        assert!(records.is_empty());
+        server2.set_journal(journal);
+
+        let written = buckets(pathset.len());
+        server2.store_path_indices(pathset.clone()).unwrap();
+        server2.chunk_write(0, written.clone(), 0).unwrap();
+        server2.finalize_epoch(0, &key()).unwrap();
+        // The server crashes while applying epoch 1.
+        server2.store_path_indices(pathset.clone()).unwrap();
+        server2.chunk_write(1, buckets(pathset.len()), 0).unwrap();
+        let prf_keys = server2.get_prf_keys().unwrap();
+        drop(server2);
+
+        let (_, records) = Journal::open(&path, JournalSync::Always).unwrap();
+        let mut restarted = Server2::new();
+        assert_eq!(restarted.replay_journal(records).unwrap(), 1);
+        assert_eq!(restarted.epoch, 1);
+        assert_eq!(restarted.get_prf_keys().unwrap(), prf_keys);
+        for (index, bucket) in pathset.iter().zip(&written) {
+            assert_eq!(&restarted.bucket(*index).unwrap(), bucket);
+        }
+    }
+
+    #[test]
+    fn test_open_drops_torn_record() {
+        let path = journal_path("torn");
+        let (mut journal, _) = Journal::open(&path, JournalSync::Commit).unwrap();
+        journal.record_chunk(0, &[1, 2], 0, &buckets(2)).unwrap();
+        journal.commit(0, &key()).unwrap();
+        drop(journal);
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[200, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3])
+            .unwrap();
+
+        let (mut journal, records) = Journal::open(&path, JournalSync::Commit).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(committed_epochs(records).unwrap().len(), 1);
+
+        // New records follow the last whole one.
+        journal.commit(1, &key()).unwrap();
+        drop(journal);
+        let (_, records) = Journal::open(&path, JournalSync::Commit).unwrap();
+        assert_eq!(committed_epochs(records).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_chunk_not_matching_its_digest_is_rejected() {
+        let chunk = buckets(2);
+        let records = vec![
+            JournalRecord::Begin {
+                epoch: 0,
+                pathset: vec![1, 2],
+            },
+            JournalRecord::Chunk {
+                epoch: 0,
+                chunk_idx: 0,
+                digest: chunk_digest(&chunk).unwrap(),
+                buckets: buckets(2),
+            },
+            JournalRecord::Commit {
+                epoch: 0,
+                chunks: vec![(0, chunk_digest(&chunk).unwrap())],
+                prf_key: key(),
+            },
+        ];
+        assert!(committed_epochs(records).is_err());
+    }
+
+    #[test]
+    fn test_compact_keeps_current_epoch() {
+        let path = journal_path("compact");
+        let (mut journal, _) = Journal::open(&path, JournalSync::Never).unwrap();
+        for epoch in 0..3 {
+            journal.record_chunk(epoch, &[1], 0, &buckets(1)).unwrap();
+            journal.commit(epoch, &key()).unwrap();
+        }
+        journal.record_chunk(3, &[1], 0, &buckets(1)).unwrap();
+        journal.compact(2).unwrap();
+        journal.commit(3, &key()).unwrap();
+        drop(journal);
+
+        let (_, records) = Journal::open(&path, JournalSync::Never).unwrap();
+        let epochs: Vec<u64> = committed_epochs(records)
+            .unwrap()
+            .iter()
+            .map(|committed| committed.epoch)
+            .collect();
+        assert_eq!(epochs, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_sync_policy_parses() {
+        assert_eq!("always".parse::<JournalSync>().unwrap(), JournalSync::Always);
+        assert_eq!("never".parse::<JournalSync>().unwrap(), JournalSync::Never);
+        assert!("sometimes".parse::<JournalSync>().is_err());
+    }
+}
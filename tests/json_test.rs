@@ -0,0 +1,110 @@
+#[cfg(all(test, feature = "debug-json"))]
+mod json_tests {
+    use axum::{
+        body::{Body, Bytes},
+        http::{header, Request, StatusCode},
+        Router,
+    };
+    use myco_rs::{
+        constants::D,
+        dtypes::{Direction, Path},
+        error::ErrorCode,
+        hardening,
+        json::{self, JSON_CONTENT_TYPE},
+        rpc_types::{ErrorResponse, GetEpochResponse, ReadResponse},
+        server2::{self, Server2},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tower::ServiceExt;
+
+    fn app(state: server2::http::AppState) -> Router {
+        let app = hardening::harden(server2::http::router()).with_state(state);
+        json::debug_routes(app, &server2::http::json_routes())
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Bytes) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        if status != StatusCode::METHOD_NOT_ALLOWED {
+            assert_eq!(
+                response.headers().get(header::CONTENT_TYPE).unwrap(),
+                JSON_CONTENT_TYPE
+            );
+        }
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body)
+    }
+
+    fn post_json(route: &str, json: String) -> Request<Body> {
+        Request::post(route)
+            .header(header::CONTENT_TYPE, JSON_CONTENT_TYPE)
+            .body(Body::from(json))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_routes_mirror_bincode_routes() {
+        let state = server2::http::AppState::new(Server2::new());
+        let app = app(state.clone());
+
+        let (status, body) = send(
+            &app,
+            Request::get("/json/epoch").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let epoch: GetEpochResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(epoch.info.epoch, 0);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let path = Path::random(&mut rng);
+        let request = serde_json::json!({ "path": path });
+        let (status, body) = send(&app, post_json("/json/read", request.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: ReadResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            response.buckets,
+            state.server2.read().await.read(&path).unwrap()
+        );
+
+        // The bincode routes are unaffected.
+        let response = app
+            .clone()
+            .oneshot(Request::get("/epoch").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(bincode::deserialize::<GetEpochResponse>(&body).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_json_routes_answer_errors_as_json() {
+        let app = app(server2::http::AppState::new(Server2::new()));
+
+        // Errors raised by the bincode handler.
+        let request = serde_json::json!({ "path": Path::new(vec![Direction::Left; D + 1]) });
+        let (status, body) = send(&app, post_json("/json/read", request.to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, ErrorCode::MalformedRequest);
+        assert!(error.message.contains("deeper than the tree"));
+
+        // Errors raised while transcoding the request.
+        let (status, body) = send(&app, post_json("/json/read", "{".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, ErrorCode::MalformedRequest);
+
+        let (status, _) = send(
+            &app,
+            Request::delete("/json/epoch").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod key_sharing_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        Router,
+    };
+    use futures::executor::block_on;
+    use myco_rs::{
+        admin::{self, AdminState, EpochControl},
+        client::Client,
+        crypto::prf,
+        dtypes::Key,
+        key_sharing::{
+            combine_shares, split_key, FileShareHolder, KeyShare, ShareHolder, ShareVault,
+            SharedSecrets,
+        },
+        network::{LocalServer1Access, LocalServer2Access},
+        secrets::SecretCompute,
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tower::ServiceExt;
+
+    fn holders(name: &str) -> [Arc<dyn ShareHolder>; 2] {
+        let path = std::env::temp_dir().join(format!("myco-key-share-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        [Arc::new(FileShareHolder::new(path)), Arc::new(ShareVault::default())]
+    }
+
+    #[test]
+    fn test_split_and_combine() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let key = Key::random(&mut rng);
+        let (a, b) = split_key(&key, &mut rng);
+        assert_ne!(a, key);
+        assert_ne!(b, key);
+
+        let share = |rotation, share| KeyShare {
+            rotation,
+            share,
+            released: false,
+        };
+        assert_eq!(combine_shares(&share(3, a.clone()), &share(3, b.clone())).unwrap(), key);
+        assert!(combine_shares(&share(3, a), &share(4, b)).is_err());
+    }
+
+    #[test]
+    fn test_restarted_server_recovers_epoch_key() {
+        let holders = holders("recover");
+        let secrets = SharedSecrets::new(holders.clone());
+        secrets.rotate_epoch_key();
+        let location = secrets.location(b"f", b"cs").unwrap();
+        drop(secrets);
+
+        // The restarted server opens its next batch with the recovered key.
+        let recovered = block_on(SharedSecrets::recover(holders.clone())).unwrap();
+        recovered.rotate_epoch_key();
+        assert_eq!(recovered.location(b"f", b"cs").unwrap(), location);
+
+        // Once released to be published, the key is never recovered again.
+        let key = recovered.epoch_key().unwrap();
+        assert_eq!(recovered.location(b"f", b"cs").unwrap(), prf(&key.0, b"fcs").unwrap());
+        assert!(block_on(SharedSecrets::recover(holders.clone())).is_err());
+        recovered.rotate_epoch_key();
+        assert_ne!(recovered.location(b"f", b"cs").unwrap(), location);
+    }
+
+    #[test]
+    fn test_lost_share_fails_release() {
+        let holders = holders("lost");
+        let secrets = SharedSecrets::new(holders.clone());
+        secrets.rotate_epoch_key();
+
+        // The replica comes back holding a share of another key.
+        let mut rng = ChaCha20Rng::from_entropy();
+        block_on(holders[1].hold(KeyShare {
+            rotation: 1,
+            share: Key::random(&mut rng),
+            released: false,
+        }))
+        .unwrap();
+        assert!(secrets.epoch_key().is_err());
+    }
+
+    #[test]
+    fn test_server1_writes_with_shared_key() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let mut server1 = Server1::new(s2_access.clone());
+        server1.set_secret_compute(Arc::new(SharedSecrets::new(holders("server1"))));
+        let s1 = Arc::new(RwLock::new(server1));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[4, 2], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
+        assert_eq!(msg, vec![4, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_admin_keeps_peer_key_share() {
+        const TOKEN: &str = "admin-secret";
+        let s2_access = Box::new(LocalServer2Access {
+            server: Arc::new(Mutex::new(Server2::new())),
+        });
+        let state = AdminState {
+            server1: Arc::new(tokio::sync::RwLock::new(Server1::new(s2_access))),
+            control: Arc::new(EpochControl::new()),
+            token: Arc::new(TOKEN.to_string()),
+        };
+        let app = Router::new().nest("/admin", admin::router(state.clone()));
+        let share = KeyShare {
+            rotation: 7,
+            share: Key::random(&mut ChaCha20Rng::from_entropy()),
+            released: false,
+        };
+
+        let request = |method: &str, token: Option<&str>, body: Vec<u8>| {
+            let mut request = Request::builder().method(method).uri("/admin/key_share");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            request.body(Body::from(body)).unwrap()
+        };
+        let body = bincode::serialize(&share).unwrap();
+        let response = app.clone().oneshot(request("POST", None, body.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(request("POST", Some(TOKEN), body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.oneshot(request("GET", Some(TOKEN), vec![])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let held: Option<KeyShare> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(held, Some(share.clone()));
+        assert_eq!(
+            state.server1.read().await.peer_key_share().share().await.unwrap(),
+            Some(share)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+#[cfg(test)]
+mod mailbox_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use myco_rs::{
+        client::Client,
+        crypto::{mailbox_access_tag, mailbox_address, mailbox_guard_key},
+        dtypes::Key,
+        error::MycoError,
+        mailbox::{MailboxGuard, MailboxGuards, MAILBOX_GUARD_EPOCHS},
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn guard(f: &[u8], cs: &[u8], k_access: &Key) -> MailboxGuard {
+        let address = mailbox_address(f, cs);
+        let key = Key::new(mailbox_guard_key(&k_access.0, &address).unwrap());
+        MailboxGuard { address, key }
+    }
+
+    #[test]
+    fn test_guarded_address_requires_access_tag() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_access = Key::random(&mut rng);
+        let mut guards = MailboxGuards::new();
+        let guarded = guard(&[1; 32], b"Alice", &k_access);
+        guards.guard(vec![guarded.clone()], 0).unwrap();
+
+        let ct = vec![7; 32];
+        let tag = mailbox_access_tag(&guarded.key.0, &ct).unwrap();
+        assert!(guards.check(&[1; 32], b"Alice", &ct, Some(&tag)).is_ok());
+        for access_tag in [None, Some(&[0; 32][..])] {
+            assert!(matches!(
+                guards.check(&[1; 32], b"Alice", &ct, access_tag),
+                Err(MycoError::MailboxAccessDenied)
+            ));
+        }
+        // The tag covers the ciphertext, so it can't be moved to another write.
+        assert!(guards.check(&[1; 32], b"Alice", &[8; 32], Some(&tag)).is_err());
+        // Other addresses aren't guarded.
+        assert!(guards.check(&[1; 32], b"Bob", &ct, None).is_ok());
+    }
+
+    #[test]
+    fn test_guards_conflict_and_expire() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_access = Key::random(&mut rng);
+        let mut guards = MailboxGuards::new();
+        guards.guard(vec![guard(&[1; 32], b"Alice", &k_access)], 0).unwrap();
+
+        // A guard under another key is rejected, together with the rest of its request.
+        let other = guard(&[1; 32], b"Alice", &Key::random(&mut rng));
+        let fresh = guard(&[2; 32], b"Alice", &k_access);
+        assert!(matches!(
+            guards.guard(vec![fresh, other], 0),
+            Err(MycoError::MailboxAccessDenied)
+        ));
+        assert_eq!(guards.len(), 1);
+
+        let short = MailboxGuard {
+            address: vec![0; 16],
+            key: k_access.clone(),
+        };
+        assert!(matches!(
+            guards.guard(vec![short], 0),
+            Err(MycoError::MalformedRequest(_))
+        ));
+
+        // Setting the same guard again extends it.
+        guards.guard(vec![guard(&[1; 32], b"Alice", &k_access)], 1).unwrap();
+        guards.expire(MAILBOX_GUARD_EPOCHS);
+        assert_eq!(guards.len(), 1);
+        guards.expire(MAILBOX_GUARD_EPOCHS + 1);
+        assert!(guards.is_empty());
+    }
+
+    #[test]
+    fn test_client_writes_to_guarded_mailbox() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = || Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access(), s2_access.clone());
+        // Mallory knows the contact key and Alice's ID, but not the access key.
+        let mut mallory = Client::new("Alice".to_string(), s1_access(), s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        let k_access = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+        mallory.setup(&k).expect("Setup failed");
+        futures::executor::block_on(alice.guard_mailbox(&k, "Alice".to_string(), &k_access, 0..1))
+            .expect("Guard failed");
+        assert_eq!(s1.read().unwrap().mailbox_guard_count(), 1);
+
+        // Mallory can neither replace the guard nor write without a tag.
+        assert!(matches!(
+            futures::executor::block_on(mallory.guard_mailbox(
+                &k,
+                "Alice".to_string(),
+                &Key::random(&mut rng),
+                0..1
+            )),
+            Err(MycoError::MailboxAccessDenied)
+        ));
+        s1.write().unwrap().batch_init(2);
+        assert!(matches!(
+            mallory.write(&[2], &k),
+            Err(MycoError::MailboxAccessDenied)
+        ));
+
+        alice.set_mailbox_access(&k, Some(k_access));
+        alice.write(&[1], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().unwrap();
+        assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![1]);
+    }
+}
@@ -0,0 +1,52 @@
+#[cfg(test)]
+mod memory_tests {
+    use myco_rs::{
+        constants::Z,
+        dtypes::{Block, Bucket, Key, Metadata, Path},
+        memory::{allocator_stats, CountingAllocator, HeapSize},
+        server2::Server2,
+        tree::{BinaryTree, SparseBinaryTree, TreeValue},
+    };
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn test_heap_size() {
+        let block = Block::new(vec![0; 100]);
+        assert_eq!(block.heap_size(), 100);
+
+        let bucket = Bucket::new_random();
+        assert_eq!(bucket.len(), Z);
+        assert!(bucket.heap_size() > Z * block.heap_size());
+
+        let tree = BinaryTree {
+            value: vec![None, Some(bucket.clone()), None],
+        };
+        assert_eq!(
+            tree.heap_size(),
+            tree.value.capacity() * std::mem::size_of::<Option<Bucket>>() + bucket.heap_size()
+        );
+
+        let sparse = SparseBinaryTree::new_with_data(vec![bucket.clone(), bucket.clone()], vec![1, 2]);
+        assert!(sparse.heap_size() > 2 * bucket.heap_size());
+
+        let metadata = Metadata::new(Path::new(vec![]), Key::new(vec![0; 16]), 0);
+        assert!(metadata.heap_size() >= 16);
+    }
+
+    #[test]
+    fn test_allocator_stats() {
+        let before = allocator_stats().unwrap();
+        let buffer = vec![0u8; 1 << 20];
+        let during = allocator_stats().unwrap();
+        assert!(during.allocations > before.allocations);
+        assert!(during.peak_bytes >= buffer.len());
+        drop(buffer);
+
+        let stats = Server2::new().memory_stats();
+        assert!(stats.tree_bytes > 0);
+        assert_eq!(stats.queue_depth, 0);
+        assert!(stats.allocator.is_some());
+    }
+}
@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod notification_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use cuckoo::{BucketHasher, MycoPrf};
+    use myco_rs::{
+        client::Client,
+        crypto::prf,
+        constants::MAX_PENDING_EPOCH_TAGS,
+        dtypes::Key,
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        notification::{notification_tag, NotificationIndex},
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_cuckoo_prf_matches_myco_prf() {
+        let key = b"notification key";
+        for seq_no in [0u64, 1, 42, u64::MAX] {
+            let expected = prf(key, &seq_no.to_be_bytes()).unwrap();
+            let expected = usize::from_be_bytes(expected[..8].try_into().unwrap());
+            assert_eq!(MycoPrf.hash(key, seq_no).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_index_round_trip() {
+        let locations: Vec<Vec<u8>> = (0..500u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let tags: Vec<Vec<u8>> = locations[..250]
+            .iter()
+            .map(|l| notification_tag(l).unwrap())
+            .collect();
+
+        let index = NotificationIndex::build(&tags).unwrap();
+        let index = NotificationIndex::from_bytes(&index.to_bytes().unwrap()).unwrap();
+        assert_eq!(index.len(), 250);
+        for l in &locations[..250] {
+            assert!(index.contains_location(l).unwrap());
+        }
+        let false_positives = locations[250..]
+            .iter()
+            .filter(|l| index.contains_location(l).unwrap())
+            .count();
+        assert_eq!(false_positives, 0);
+
+        let empty = NotificationIndex::build(&[]).unwrap();
+        assert!(empty.is_empty());
+        assert!(!empty.contains_location(&locations[0]).unwrap());
+    }
+
+    #[test]
+    fn test_client_notifications() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_ab = Key::random(&mut rng);
+        let k_ac = Key::random(&mut rng);
+        alice.setup(&k_ab).unwrap();
+        alice.setup(&k_ac).unwrap();
+        bob.setup(&k_ab).unwrap();
+
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[1], &k_ab).unwrap();
+        s1.write().unwrap().batch_write().unwrap();
+        bob.epoch = alice.epoch;
+
+        let index = futures::executor::block_on(bob.notifications())
+            .unwrap()
+            .expect("no notification index");
+        assert!(bob.has_notification(&index, &k_ab, "Alice").unwrap());
+
+        let index = futures::executor::block_on(alice.notifications())
+            .unwrap()
+            .expect("no notification index");
+        assert!(alice.has_notification(&index, &k_ab, "Alice").unwrap());
+        assert!(!alice.has_notification(&index, &k_ac, "Alice").unwrap());
+        assert_eq!(bob.read(&k_ab, "Alice".to_string(), 0).unwrap().payload, vec![1]);
+    }
+
+    #[test]
+    fn test_pending_epochs() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_ab = Key::random(&mut rng);
+        let k_ac = Key::random(&mut rng);
+        alice.setup(&k_ab).unwrap();
+        alice.setup(&k_ac).unwrap();
+        bob.setup(&k_ab).unwrap();
+
+        // Alice writes to Bob in the first and last of three epochs, while Bob is away.
+        for (msg, k) in [(1, &k_ab), (2, &k_ac), (3, &k_ab)] {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], k).unwrap();
+            s1.write().unwrap().batch_write().unwrap();
+        }
+        bob.epoch = alice.epoch;
+
+        let contacts = [(k_ab.clone(), "Alice".to_string())];
+        let pending = futures::executor::block_on(bob.pending_epochs(&contacts)).unwrap();
+        assert_eq!(pending, vec![2, 0]);
+        let payloads: Vec<_> = pending
+            .into_iter()
+            .map(|epoch_past| bob.read(&k_ab, "Alice".to_string(), epoch_past).unwrap().payload)
+            .collect();
+        assert_eq!(payloads, vec![vec![1], vec![3]]);
+
+        let tags = vec![(0, vec![0; 32]); MAX_PENDING_EPOCH_TAGS + 1];
+        assert!(matches!(
+            s2.lock().unwrap().pending_epochs(&tags),
+            Err(MycoError::MalformedRequest(_))
+        ));
+    }
+}
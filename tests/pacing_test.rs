@@ -0,0 +1,44 @@
+#[cfg(test)]
+mod pacing_tests {
+    use std::time::Duration;
+
+    use myco_rs::pacing::{Pacer, INITIAL_WINDOW, MAX_WINDOW};
+
+    const RTT: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn test_window_grows_while_requests_are_prompt() {
+        let mut pacer = Pacer::new();
+        assert_eq!(pacer.window(), INITIAL_WINDOW as usize);
+        for _ in 0..100 {
+            pacer.on_success(RTT);
+        }
+        assert!(pacer.window() > INITIAL_WINDOW as usize);
+        assert_eq!(pacer.base_latency(), Some(RTT));
+
+        for _ in 0..100_000 {
+            pacer.on_success(RTT);
+        }
+        assert_eq!(pacer.window(), MAX_WINDOW as usize);
+    }
+
+    #[test]
+    fn test_window_shrinks_on_slow_or_failed_requests() {
+        let mut pacer = Pacer::new();
+        for _ in 0..200 {
+            pacer.on_success(RTT);
+        }
+        let window = pacer.window();
+
+        pacer.on_success(RTT * 5);
+        assert!(pacer.window() < window);
+        let window = pacer.window();
+        pacer.on_failure();
+        assert!(pacer.window() <= window / 2 + 1);
+
+        for _ in 0..20 {
+            pacer.on_failure();
+        }
+        assert_eq!(pacer.window(), 1);
+    }
+}
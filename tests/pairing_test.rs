@@ -0,0 +1,60 @@
+#[cfg(test)]
+mod pairing_tests {
+    use myco_rs::{
+        error::MycoError,
+        pairing::{pairing_code, Pairing, PAIRING_CODE_DIGITS},
+    };
+
+    #[test]
+    fn test_matching_codes_agree_on_key() {
+        let code = pairing_code();
+        assert_eq!(code.len(), PAIRING_CODE_DIGITS as usize);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+
+        let (alice, to_bob) = Pairing::start("Alice", "Bob", &code).unwrap();
+        let (bob, to_alice) = Pairing::start("Bob", "Alice", &code).unwrap();
+        let (alice_key, alice_confirmation) = alice.finish(&to_alice).unwrap();
+        let (bob_key, bob_confirmation) = bob.finish(&to_bob).unwrap();
+
+        let k_alice = alice_key.confirm(&bob_confirmation).expect("Alice confirm failed");
+        let k_bob = bob_key.confirm(&alice_confirmation).expect("Bob confirm failed");
+        assert_eq!(k_alice, k_bob);
+        assert_eq!(k_alice.0.len(), 16);
+    }
+
+    #[test]
+    fn test_code_formatting_ignored() {
+        let (alice, to_bob) = Pairing::start("Alice", "Bob", "123-456").unwrap();
+        let (bob, to_alice) = Pairing::start("Bob", "Alice", "123 456").unwrap();
+        let (alice_key, _) = alice.finish(&to_alice).unwrap();
+        let (_, bob_confirmation) = bob.finish(&to_bob).unwrap();
+        assert!(alice_key.confirm(&bob_confirmation).is_ok());
+    }
+
+    #[test]
+    fn test_wrong_code_fails_confirmation() {
+        let (alice, to_bob) = Pairing::start("Alice", "Bob", "123456").unwrap();
+        let (bob, to_alice) = Pairing::start("Bob", "Alice", "123457").unwrap();
+        let (alice_key, alice_confirmation) = alice.finish(&to_alice).unwrap();
+        let (bob_key, bob_confirmation) = bob.finish(&to_bob).unwrap();
+        assert!(matches!(alice_key.confirm(&bob_confirmation), Err(MycoError::ProtocolError(_))));
+        assert!(matches!(bob_key.confirm(&alice_confirmation), Err(MycoError::ProtocolError(_))));
+    }
+
+    #[test]
+    fn test_invalid_messages_rejected() {
+        assert!(Pairing::start("Alice", "Alice", "123456").is_err());
+        assert!(Pairing::start("Alice", "Bob", " - ").is_err());
+
+        let (_, to_bob) = Pairing::start("Alice", "Bob", "123456").unwrap();
+        let (bob, _) = Pairing::start("Bob", "Alice", "123456").unwrap();
+        let mut invalid = to_bob.clone();
+        invalid.element = [0xff; 32];
+        assert!(bob.finish(&invalid).is_err());
+
+        let (bob, _) = Pairing::start("Bob", "Alice", "123456").unwrap();
+        let mut impostor = to_bob;
+        impostor.id = "Carol".to_string();
+        assert!(bob.finish(&impostor).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+#[cfg(all(test, feature = "protobuf"))]
+mod proto_tests {
+    use myco_rs::{
+        dtypes::{
+            AllocatorStats, BandwidthStats, Block, Bucket, Direction, Key, MemoryStats, Path,
+            TrafficBytes, WriteStats,
+        },
+        error::{ErrorCode, MycoError},
+        mailbox::MailboxGuard,
+        proto::{self, pb},
+        registration::{AccountCredentials, AccountId},
+        rpc_types::{
+            AdminStatsResponse, BatchInitRequest, ChunkWriteRequest, ErrorResponse,
+            GetNotificationsResponse, GuardMailboxesRequest, IssueWriteTokensRequest,
+            IssueWriteTokensResponse, PendingEpochsRequest, MemoryStatsResponse, QueueWriteResponse, ReadRequest, RotateRegistrationRequest,
+            StorePathIndicesRequest, StorePathLeavesRequest, StorePathLeavesResponse,
+        },
+    };
+    use prost::Message;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn random_bucket() -> Bucket {
+        let mut bucket = Bucket::default();
+        bucket.push(Block::new_random());
+        bucket.push(Block::new_random());
+        bucket
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let write = ChunkWriteRequest {
+            buckets: vec![random_bucket(), Bucket::default(), random_bucket()],
+            chunk_idx: 7,
+            prf_key: Key::random(&mut rng),
+            epoch: 12,
+        };
+        let decoded: ChunkWriteRequest = proto::decode(&proto::encode(&write)).unwrap();
+        assert_eq!(decoded.buckets, write.buckets);
+        assert_eq!(decoded.chunk_idx, 7);
+        assert_eq!(decoded.prf_key, write.prf_key);
+        assert_eq!(decoded.epoch, 12);
+
+        let error = ErrorResponse {
+            code: ErrorCode::EpochClosed,
+            message: "epoch closed".to_string(),
+            next_epoch_opens_at: Some(1_700_000_000_000),
+        };
+        let decoded: ErrorResponse = proto::decode(&proto::encode(&error)).unwrap();
+        assert_eq!(decoded, error);
+
+        let receipt = QueueWriteResponse { success: true, epoch: 41 };
+        let decoded: QueueWriteResponse = proto::decode(&proto::encode(&receipt)).unwrap();
+        assert!(decoded.success);
+        assert_eq!(decoded.epoch, 41);
+
+        let stats = AdminStatsResponse {
+            current: WriteStats {
+                epoch: 3,
+                writes: 10,
+                distinct_writers: 4,
+            },
+            previous: None,
+            bandwidth: BandwidthStats {
+                epoch: 3,
+                batch_write: TrafficBytes { received: 4096, sent: 12 },
+                ..Default::default()
+            },
+            previous_bandwidth: Some(BandwidthStats::default()),
+        };
+        let decoded: AdminStatsResponse = proto::decode(&proto::encode(&stats)).unwrap();
+        assert_eq!(decoded, stats);
+
+        for allocator in [
+            None,
+            Some(AllocatorStats {
+                allocated_bytes: 1 << 20,
+                peak_bytes: 1 << 21,
+                allocations: 42,
+            }),
+        ] {
+            let memory = MemoryStatsResponse {
+                stats: MemoryStats {
+                    tree_bytes: 4096,
+                    metadata_bytes: 512,
+                    queue_depth: 3,
+                    allocator,
+                },
+            };
+            let decoded: MemoryStatsResponse = proto::decode(&proto::encode(&memory)).unwrap();
+            assert_eq!(decoded, memory);
+        }
+
+        let guards = GuardMailboxesRequest {
+            guards: vec![MailboxGuard {
+                address: vec![1; 32],
+                key: Key::random(&mut rng),
+            }],
+        };
+        let decoded: GuardMailboxesRequest = proto::decode(&proto::encode(&guards)).unwrap();
+        assert_eq!(decoded.guards, guards.guards);
+
+        let leaves = StorePathLeavesRequest {
+            leaves: vec![0, 7, (1 << 18) - 1],
+        };
+        let decoded: StorePathLeavesRequest = proto::decode(&proto::encode(&leaves)).unwrap();
+        assert_eq!(decoded.leaves, leaves.leaves);
+        let decoded: StorePathLeavesResponse =
+            proto::decode(&proto::encode(&StorePathLeavesResponse { pathset_size: 37 })).unwrap();
+        assert_eq!(decoded.pathset_size, 37);
+
+        for notifications in [None, Some((5, vec![1, 2, 3]))] {
+            let response = GetNotificationsResponse { notifications };
+            let decoded: GetNotificationsResponse =
+                proto::decode(&proto::encode(&response)).unwrap();
+            assert_eq!(decoded.notifications, response.notifications);
+        }
+
+        let request = PendingEpochsRequest {
+            tags: vec![(3, vec![1; 32]), (4, vec![])],
+        };
+        let decoded: PendingEpochsRequest = proto::decode(&proto::encode(&request)).unwrap();
+        assert_eq!(decoded.tags, request.tags);
+
+        let request = IssueWriteTokensRequest {
+            credentials: AccountCredentials {
+                account: AccountId([7; 16]),
+                secret: Key::random(&mut rng),
+            },
+            requests: vec![(3, vec![1; 32]), (4, vec![2; 32])],
+        };
+        let decoded: IssueWriteTokensRequest = proto::decode(&proto::encode(&request)).unwrap();
+        assert_eq!(decoded.credentials, request.credentials);
+        assert_eq!(decoded.requests, request.requests);
+        let response = IssueWriteTokensResponse {
+            tokens: vec![(3, vec![3; 32])],
+        };
+        let decoded: IssueWriteTokensResponse = proto::decode(&proto::encode(&response)).unwrap();
+        assert_eq!(decoded.tokens, response.tokens);
+    }
+
+    #[test]
+    fn test_encoding_follows_schema() {
+        // num_writes is field 1, a varint.
+        assert_eq!(
+            proto::encode(&BatchInitRequest {
+                num_writes: 300,
+                idempotency_key: None
+            }),
+            vec![0x08, 0xac, 0x02]
+        );
+
+        // path is field 1, a Path message whose directions are a packed enum in its field 1.
+        let request = ReadRequest {
+            path: Path::new(vec![Direction::Left, Direction::Right]),
+        };
+        assert_eq!(
+            proto::encode(&request),
+            vec![0x0a, 0x04, 0x0a, 0x02, 0x00, 0x01]
+        );
+
+        // Messages with the same fields share a struct but keep their field names on the Rust side.
+        let request = StorePathIndicesRequest {
+            pathset: vec![1, 2],
+        };
+        let message = pb::StorePathIndicesRequest::decode(&*proto::encode(&request)).unwrap();
+        assert_eq!(message.indices, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_invalid_messages_are_rejected() {
+        assert!(matches!(
+            proto::decode::<BatchInitRequest>(&[0x08]),
+            Err(MycoError::MalformedRequest(_))
+        ));
+
+        // Required nested messages.
+        assert!(matches!(
+            proto::decode::<ReadRequest>(&[]),
+            Err(MycoError::MalformedRequest(_))
+        ));
+
+        let path = pb::ReadRequest {
+            path: Some(pb::Path {
+                directions: vec![2],
+            }),
+        };
+        assert!(matches!(
+            proto::decode::<ReadRequest>(&path.encode_to_vec()),
+            Err(MycoError::MalformedRequest(_))
+        ));
+
+        let init = pb::BatchInitRequest {
+            num_writes: 1,
+            idempotency_key: Some(vec![0; 3]),
+        };
+        assert!(matches!(
+            proto::decode::<BatchInitRequest>(&init.encode_to_vec()),
+            Err(MycoError::MalformedRequest(_))
+        ));
+
+        let rotate = pb::RotateRegistrationRequest {
+            credentials: Some(pb::AccountCredentials {
+                account: vec![0; 15],
+                secret: vec![0; 32],
+            }),
+        };
+        assert!(matches!(
+            proto::decode::<RotateRegistrationRequest>(&rotate.encode_to_vec()),
+            Err(MycoError::MalformedRequest(_))
+        ));
+
+        let error = pb::ErrorResponse {
+            code: u32::MAX,
+            message: String::new(),
+            next_epoch_opens_at: None,
+        };
+        assert!(matches!(
+            proto::decode::<ErrorResponse>(&error.encode_to_vec()),
+            Err(MycoError::ProtocolError(_))
+        ));
+    }
+}
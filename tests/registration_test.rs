@@ -0,0 +1,124 @@
+#[cfg(test)]
+mod registration_tests {
+    use std::sync::Arc;
+
+    use myco_rs::{
+        dtypes::Key,
+        error::MycoError,
+        registration::{AccountCredentials, Registry},
+        server1::{self, Server1},
+        server2::Server2,
+        tls::TlsTrust,
+        transport::{
+            HttpsTransport, InMemoryServer2Transport, TransportServer1Access,
+            TransportServer2Access,
+        },
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tokio::{net::TcpListener, sync::RwLock};
+
+    #[test]
+    fn test_register_and_verify() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut registry = Registry::new();
+        let alice = registry.register(&mut rng).unwrap();
+        let bob = registry.register(&mut rng).unwrap();
+        assert_ne!(alice.account, bob.account);
+        assert_eq!(registry.len(), 2);
+
+        assert!(registry.verify(&alice).is_ok());
+        let forged = AccountCredentials {
+            account: alice.account,
+            secret: bob.secret.clone(),
+        };
+        assert!(matches!(
+            registry.verify(&forged),
+            Err(MycoError::UnknownRegistration)
+        ));
+        assert!(!format!("{:?}", alice).contains(&format!("{:?}", alice.secret.0)));
+    }
+
+    #[test]
+    fn test_rotate_replaces_id_and_secret() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut registry = Registry::new();
+        let old = registry.register(&mut rng).unwrap();
+        let created_at = registry.verify(&old).unwrap().created_at;
+
+        let new = registry.rotate(&old, &mut rng).unwrap();
+        assert_ne!(new.account, old.account);
+        assert_ne!(new.secret, old.secret);
+        assert_eq!(registry.len(), 1);
+        let registration = registry.verify(&new).unwrap();
+        assert_eq!(registration.created_at, created_at);
+        assert!(registration.rotated_at.is_some());
+
+        // The old credentials are gone, so they can neither rotate nor delete the account.
+        assert!(matches!(
+            registry.rotate(&old, &mut rng),
+            Err(MycoError::UnknownRegistration)
+        ));
+        assert!(matches!(
+            registry.delete(&old),
+            Err(MycoError::UnknownRegistration)
+        ));
+    }
+
+    #[test]
+    fn test_delete_and_limit() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut registry = Registry::new();
+        registry.set_limit(Some(1));
+        let account = registry.register(&mut rng).unwrap();
+        assert!(matches!(
+            registry.register(&mut rng),
+            Err(MycoError::RegistrationLimitReached)
+        ));
+
+        // A wrong secret doesn't delete the account.
+        let wrong = AccountCredentials {
+            account: account.account,
+            secret: Key::random(&mut rng),
+        };
+        assert!(registry.delete(&wrong).is_err());
+        assert_eq!(registry.len(), 1);
+
+        registry.delete(&account).unwrap();
+        assert!(registry.is_empty());
+        assert!(registry.register(&mut rng).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_registration_over_https() {
+        let s2 = InMemoryServer2Transport {
+            server2: Arc::new(RwLock::new(Server2::new())),
+        };
+        let state = server1::http::AppState::new(Server1::new(Box::new(
+            TransportServer2Access::new(Box::new(s2)),
+        )));
+        state.server1.write().await.registrations.set_limit(Some(1));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = server1::http::router().with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let server1 = TransportServer1Access::new(Box::new(
+            HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+        ));
+        let credentials = server1.register().await.unwrap();
+        assert!(matches!(
+            server1.register().await,
+            Err(MycoError::RegistrationLimitReached)
+        ));
+
+        let rotated = server1.rotate_registration(credentials.clone()).await.unwrap();
+        assert_ne!(rotated.account, credentials.account);
+        assert!(matches!(
+            server1.delete_registration(credentials).await,
+            Err(MycoError::UnknownRegistration)
+        ));
+        server1.delete_registration(rotated).await.unwrap();
+        assert!(state.server1.read().await.registrations.is_empty());
+    }
+}
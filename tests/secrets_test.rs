@@ -0,0 +1,189 @@
+#[cfg(test)]
+mod secrets_tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    };
+
+    use myco_rs::{
+        client::Client,
+        constants::{BLOCK_SIZE, INNER_BLOCK_SIZE, Z},
+        crypto::{encrypt, prf, EncryptionType},
+        dtypes::{Block, Key},
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        secrets::{DummyBlock, HostSecrets, ObliviousBatch, SecretCompute},
+        simulation::{SimulationMode, SIMULATION_MODE_ENV},
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    /// Host secrets counting the calls made through the trait, standing in for an enclave.
+    #[derive(Default)]
+    struct CountingSecrets {
+        inner: HostSecrets,
+        rotations: AtomicUsize,
+        locations: AtomicUsize,
+        opens: AtomicUsize,
+        seals: AtomicUsize,
+    }
+
+    impl SecretCompute for CountingSecrets {
+        fn rotate_epoch_key(&self) {
+            self.rotations.fetch_add(1, Ordering::SeqCst);
+            self.inner.rotate_epoch_key();
+        }
+
+        fn epoch_key(&self) -> Result<Key, MycoError> {
+            self.inner.epoch_key()
+        }
+
+        fn location(&self, f: &[u8], cs: &[u8]) -> Result<Vec<u8>, MycoError> {
+            self.locations.fetch_add(1, Ordering::SeqCst);
+            self.inner.location(f, cs)
+        }
+
+        fn open_block(&self, k_oblv_t: &Key, block: &Block) -> Result<Vec<u8>, MycoError> {
+            self.opens.fetch_add(1, Ordering::SeqCst);
+            self.inner.open_block(k_oblv_t, block)
+        }
+
+        fn seal_block(&self, k_oblv_t: &Key, ct: &[u8]) -> Result<Block, MycoError> {
+            self.seals.fetch_add(1, Ordering::SeqCst);
+            self.inner.seal_block(k_oblv_t, ct)
+        }
+    }
+
+    #[test]
+    fn test_host_secrets_location_is_prf_of_epoch_key() {
+        let secrets = HostSecrets::default();
+        secrets.rotate_epoch_key();
+        let key = secrets.epoch_key().unwrap();
+        assert!(!key.0.is_empty());
+        assert_eq!(
+            secrets.location(b"f", b"cs").unwrap(),
+            prf(&key.0, b"fcs").unwrap()
+        );
+
+        secrets.rotate_epoch_key();
+        assert_ne!(secrets.epoch_key().unwrap(), key);
+    }
+
+    #[test]
+    fn test_server1_runs_secret_operations_through_trait() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let secrets = Arc::new(CountingSecrets::default());
+        let mut server1 = Server1::new(s2_access.clone());
+        server1.set_secret_compute(secrets.clone());
+        let s1 = Arc::new(RwLock::new(server1));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        s1.write().unwrap().batch_init(1);
+        assert_eq!(s1.read().unwrap().published_key().unwrap(), secrets.epoch_key().unwrap());
+        alice.write(&[1, 2, 3], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        assert_eq!(secrets.rotations.load(Ordering::SeqCst), 1);
+        assert_eq!(secrets.locations.load(Ordering::SeqCst), 1);
+        // Every slot of the pathset is sealed, live or not, on top of the batch's dummy block.
+        let seals = secrets.seals.load(Ordering::SeqCst);
+        assert!(seals > Z && seals % Z == 1);
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
+        assert_eq!(msg, vec![1, 2, 3]);
+
+        // The next batch opens every slot of the buckets it rewrites, live or not.
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[4, 5, 6], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+        let opens = secrets.opens.load(Ordering::SeqCst);
+        assert!(opens >= Z && opens % Z == 0);
+    }
+
+    #[test]
+    fn test_dummy_block_does_the_work_of_a_live_block() {
+        let secrets = HostSecrets::default();
+        let dummy = DummyBlock::new(&secrets).unwrap();
+
+        // A live block holds a client ciphertext sealed under its epoch's oblivious key.
+        let k_oblv_t = Key::random(&mut ChaCha20Rng::from_entropy());
+        let ct = encrypt(&[7u8; 16], &[1, 2, 3], EncryptionType::Encrypt).unwrap();
+        assert_eq!(ct.len(), INNER_BLOCK_SIZE);
+        let live = secrets.seal_block(&k_oblv_t, &ct).unwrap();
+
+        // The dummy block is sealed and opened with inputs and outputs of the same sizes, and
+        // opening it succeeds rather than failing early at the key commitment.
+        let sealed = dummy.seal(&secrets).unwrap();
+        assert_eq!(sealed.0.len(), BLOCK_SIZE);
+        assert_eq!(sealed.0.len(), live.0.len());
+        let opened = dummy.open(&secrets).unwrap();
+        assert_eq!(opened.len(), secrets.open_block(&k_oblv_t, &live).unwrap().len());
+        assert_eq!(opened.len(), INNER_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_oblivious_batch_spends_z_operations_per_bucket() {
+        let secrets = Arc::new(CountingSecrets::default());
+        let batch = ObliviousBatch::new(secrets.clone(), SimulationMode::Off).unwrap();
+        assert_eq!(secrets.seals.load(Ordering::SeqCst), 1);
+        let k_oblv_t = Key::random(&mut ChaCha20Rng::from_entropy());
+        let ct = encrypt(&[7u8; 16], &[1, 2, 3], EncryptionType::Encrypt).unwrap();
+
+        // Live blocks and dummies add up to Z, however many blocks are live.
+        for live in [0, 3, Z] {
+            let before = secrets.seals.load(Ordering::SeqCst);
+            let blocks = batch
+                .seal_bucket(|seals| {
+                    (0..live)
+                        .map(|_| seals.seal(&k_oblv_t, &ct).map_err(|e| e.to_string()))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .unwrap();
+            assert_eq!(secrets.seals.load(Ordering::SeqCst) - before, Z);
+
+            let before = secrets.opens.load(Ordering::SeqCst);
+            batch
+                .open_bucket(|opens| {
+                    for block in &blocks {
+                        assert_eq!(opens.open(&k_oblv_t, block).unwrap(), ct);
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(secrets.opens.load(Ordering::SeqCst) - before, Z);
+        }
+    }
+
+    #[test]
+    fn test_oblivious_batch_refuses_operations_past_the_budget() {
+        let secrets = Arc::new(CountingSecrets::default());
+        let batch = ObliviousBatch::new(secrets.clone(), SimulationMode::Off).unwrap();
+        let k_oblv_t = Key::random(&mut ChaCha20Rng::from_entropy());
+        let before = secrets.seals.load(Ordering::SeqCst);
+        let result = batch.seal_bucket(|seals| {
+            for _ in 0..=Z {
+                seals.seal(&k_oblv_t, &[0; 16]).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        });
+        assert!(result.unwrap_err().contains("more than Z"));
+        assert_eq!(secrets.seals.load(Ordering::SeqCst) - before, Z);
+    }
+
+    #[test]
+    fn test_oblivious_batch_skips_dummies_without_encryption() {
+        let secrets = Arc::new(CountingSecrets::default());
+        std::env::set_var(SIMULATION_MODE_ENV, "no-enc");
+        let simulation = SimulationMode::no_encryption().unwrap();
+        let batch = ObliviousBatch::new(secrets.clone(), simulation).unwrap();
+        batch.open_bucket(|_| Ok(())).unwrap();
+        batch.seal_bucket(|_| Ok(())).unwrap();
+        assert_eq!(secrets.opens.load(Ordering::SeqCst), 0);
+        assert_eq!(secrets.seals.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,36 @@
+#[cfg(test)]
+mod sequence_tests {
+    use myco_rs::{
+        dtypes::Key,
+        envelope::Envelope,
+        sequence::{SequenceTracker, Sequenced},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_expected_sequence_tracks_highest_read() {
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        let mut tracker = SequenceTracker::default();
+
+        assert_eq!(tracker.expected(&k, b"Alice"), 0);
+        assert_eq!(tracker.advance(&k, b"Alice", 0), 0);
+        // Skipping sequence numbers moves past them, and a late message doesn't move back.
+        assert_eq!(tracker.advance(&k, b"Alice", 3), 1);
+        assert_eq!(tracker.advance(&k, b"Alice", 2), 4);
+        assert_eq!(tracker.expected(&k, b"Alice"), 4);
+        // Senders are tracked separately.
+        assert_eq!(tracker.expected(&k, b"Bob"), 0);
+    }
+
+    #[test]
+    fn test_missing_sequences() {
+        let sequenced = |sequence, expected| Sequenced {
+            envelope: Envelope::new(vec![]).with_sequence(sequence),
+            expected,
+        };
+        assert_eq!(sequenced(3, 1).missing(), 1..3);
+        assert!(sequenced(1, 1).missing().is_empty());
+        assert!(sequenced(2, 4).missing().is_empty());
+    }
+}
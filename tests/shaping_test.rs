@@ -0,0 +1,152 @@
+#[cfg(test)]
+mod shaping_tests {
+    use std::{
+        sync::{Arc, Mutex, RwLock},
+        time::{Duration, Instant},
+    };
+
+    use myco_rs::{
+        client::Client,
+        dtypes::Key,
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+        shaping::TrafficShaper,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn shaper(slot: Duration) -> (TrafficShaper, Arc<RwLock<Server1>>, Key) {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        (TrafficShaper::new(alice, slot), s1, k)
+    }
+
+    #[test]
+    fn test_every_slot_writes_and_reads() {
+        let (mut shaper, s1, k) = shaper(Duration::ZERO);
+        shaper.send(&[1], &k).unwrap();
+        shaper.send(&[2], &k).unwrap();
+        shaper.watch(&k, "Alice".to_string(), 1);
+        assert!(matches!(
+            shaper.send(&[3], &Key::random(&mut ChaCha20Rng::from_entropy())),
+            Err(MycoError::UnknownContact)
+        ));
+
+        let mut slots = Vec::new();
+        for _ in 0..3 {
+            s1.write().unwrap().batch_init(1);
+            slots.push(shaper.tick().expect("Slot failed"));
+            s1.write().unwrap().batch_write().unwrap();
+        }
+
+        // Queued messages go out one per slot, and the third slot is filled with a fake write.
+        let written: Vec<_> = slots.iter().map(|slot| slot.written.is_some()).collect();
+        assert_eq!(written, vec![true, true, false]);
+        assert_eq!(shaper.client().epoch, 3);
+        // The read of epoch 1 waits until that epoch has been written.
+        assert!(slots[0].reads.is_empty());
+        let (request, result) = slots[2].reads.first().expect("No read in the third slot");
+        assert_eq!(request.epoch, 1);
+        assert_eq!(result.as_ref().unwrap().payload, vec![2]);
+        assert_eq!((shaper.pending_writes(), shaper.pending_reads()), (0, 0));
+    }
+
+    #[test]
+    fn test_failed_write_keeps_the_reads() {
+        let (mut shaper, s1, k) = shaper(Duration::ZERO);
+        shaper.send(&[1], &k).unwrap();
+        s1.write().unwrap().batch_init(1);
+        shaper.tick().expect("Slot failed");
+        s1.write().unwrap().batch_write().unwrap();
+
+        // Server1 has no open batch, so the slot's write fails after its read is made.
+        shaper.send(&[2], &k).unwrap();
+        shaper.watch(&k, "Alice".to_string(), 0);
+        assert!(shaper.tick().is_err());
+        assert_eq!((shaper.pending_writes(), shaper.pending_reads()), (1, 0));
+
+        // The retried slot only repeats the write, and reports the read made before it failed.
+        s1.write().unwrap().batch_init(1);
+        let slot = shaper.tick().expect("Slot failed");
+        s1.write().unwrap().batch_write().unwrap();
+        assert!(slot.written.is_some());
+        let payloads: Vec<_> = slot
+            .reads
+            .iter()
+            .map(|(request, result)| (request.epoch, result.as_ref().unwrap().payload.clone()))
+            .collect();
+        assert_eq!(payloads, vec![(0, vec![1])]);
+        assert_eq!((shaper.pending_writes(), shaper.pending_reads()), (0, 0));
+    }
+
+    #[test]
+    fn test_run_keeps_the_cadence() {
+        let slot = Duration::from_millis(20);
+        let (mut shaper, s1, k) = shaper(slot);
+        s1.write().unwrap().batch_init(4);
+        shaper.send(&[1], &k).unwrap();
+
+        let start = Instant::now();
+        let mut written = Vec::new();
+        shaper
+            .run(4, |slot| written.push(slot.written.is_some()))
+            .expect("Run failed");
+        assert!(start.elapsed() >= 3 * slot);
+        assert_eq!(written, vec![true, false, false, false]);
+    }
+
+    #[test]
+    fn test_catch_up_spread_within_read_count() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let mut bob = Client::new("Bob".to_string(), s1_access, s2_access);
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (k, k_other) = (Key::random(&mut rng), Key::random(&mut rng));
+        alice.setup(&k).expect("Setup failed");
+        bob.setup(&k).expect("Setup failed");
+        bob.setup(&k_other).expect("Setup failed");
+
+        // Bob writes to Alice in two of three epochs while she is offline.
+        for (msg, key) in [(1, &k), (0, &k_other), (3, &k)] {
+            s1.write().unwrap().batch_init(1);
+            bob.write(&[msg], key).expect("Write failed");
+            s1.write().unwrap().batch_write().unwrap();
+        }
+        alice.epoch = bob.epoch;
+
+        let mut shaper = TrafficShaper::new(alice, Duration::ZERO)
+            .with_reads_per_slot(2)
+            .with_catch_up_spread(2);
+        assert_eq!(shaper.catch_up(&[(k.clone(), "Bob".to_string())]).unwrap(), 2);
+        shaper.watch(&k, "Bob".to_string(), 2);
+
+        let mut slots = Vec::new();
+        for _ in 0..3 {
+            s1.write().unwrap().batch_init(1);
+            slots.push(shaper.tick().expect("Slot failed"));
+            s1.write().unwrap().batch_write().unwrap();
+        }
+        // One catch-up read per slot over two slots, with the watched read in the first one.
+        let reads: Vec<Vec<_>> = slots
+            .iter()
+            .map(|slot| {
+                slot.reads
+                    .iter()
+                    .map(|(request, result)| (request.epoch, result.as_ref().unwrap().payload.clone()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(reads, vec![vec![(0, vec![1]), (2, vec![3])], vec![(2, vec![3])], vec![]]);
+        assert_eq!(shaper.pending_reads(), 0);
+    }
+}
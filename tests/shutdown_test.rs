@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod shutdown_tests {
+    use std::sync::{Arc, Mutex};
+
+    use myco_rs::{
+        admin::EpochControl,
+        constants::D,
+        crypto::{encrypt, EncryptionType},
+        dtypes::{Bucket, Key},
+        error::MycoError,
+        network::LocalServer2Access,
+        serve::{shutdown_server1, shutdown_server2},
+        server1::Server1,
+        server2::Server2,
+        tree::{self, BinaryTree, StateParams, STATE_SCHEMA_VERSION},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_shutdown_finishes_in_flight_epoch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let server1 = RwLock::new(Server1::new(s2_access));
+        let control = EpochControl::new();
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        server1.write().await.async_batch_init(1).await.expect("Batch init failed");
+        control.set_epoch_open(true);
+        let ct = encrypt(&Key::random(&mut rng).0, &[7], EncryptionType::Encrypt).unwrap();
+        server1
+            .write()
+            .await
+            .queue_write(ct, vec![0; 32], Key::random(&mut rng), b"Alice".to_vec(), vec![], None)
+            .unwrap();
+
+        shutdown_server1(&server1, &control)
+            .await
+            .expect("Shutdown failed");
+        assert!(!control.accepting_writes());
+        assert!(!control.is_epoch_open());
+        assert_eq!(server1.read().await.epoch, 1);
+        assert_eq!(server1.read().await.queue_depth(), 0);
+        // The queued write reached Server2 along with the epoch's PRF key.
+        assert_eq!(s2.lock().unwrap().epoch, 1);
+        assert_eq!(s2.lock().unwrap().prf_keys.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut server2 = Server2::new();
+        server2.finalize_epoch(0, &Key::random(&mut rng)).unwrap();
+        server2.finalize_epoch(1, &Key::random(&mut rng)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("myco_snapshot_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server2.snapshot");
+
+        let server2 = RwLock::new(server2);
+        shutdown_server2(&server2, Some(&path))
+            .await
+            .expect("Snapshot failed");
+        // Without a configured path the hook is a no-op.
+        shutdown_server2(&server2, None).await.unwrap();
+
+        let restored = Server2::load_snapshot(&path).expect("Restore failed");
+        let original = server2.read().await;
+        assert_eq!(restored.epoch, original.epoch);
+        assert_eq!(restored.prf_keys, original.prf_keys);
+        assert_eq!(restored.tree, original.tree);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_versioning() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut server2 = Server2::new();
+        server2.finalize_epoch(0, &Key::random(&mut rng)).unwrap();
+        let state = (&server2.tree, &server2.prf_keys, server2.prf_key_cursor, server2.epoch);
+
+        let dir = std::env::temp_dir().join(format!("myco_versioning_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server2.snapshot");
+        let load = |bytes: Vec<u8>| {
+            std::fs::write(&path, bytes).unwrap();
+            Server2::load_snapshot(&path)
+        };
+
+        // Unversioned snapshots are migrated if their tree fits this build.
+        let restored = load(bincode::serialize(&state).unwrap()).expect("Migration failed");
+        assert_eq!(restored.epoch, server2.epoch);
+        assert_eq!(restored.tree, server2.tree);
+        let shallow: BinaryTree<Bucket> = BinaryTree::new_with_depth(D - 1);
+        let result = load(bincode::serialize(&(&shallow, &server2.prf_keys, 0u64, 0u64)).unwrap());
+        assert!(matches!(result, Err(MycoError::IncompatibleState(_))));
+
+        // Snapshots written with other parameters are rejected.
+        let params = StateParams {
+            depth: D + 1,
+            ..StateParams::local()
+        };
+        let result = load(tree::encode_state(params, &state).unwrap());
+        assert!(matches!(result, Err(MycoError::IncompatibleState(_))));
+
+        // So are snapshots of a newer schema version. The version follows the 8 byte prefix.
+        let mut bytes = tree::encode_state(StateParams::local(), &state).unwrap();
+        bytes[8..12].copy_from_slice(&(STATE_SCHEMA_VERSION + 1).to_le_bytes());
+        let result = load(bytes);
+        assert!(matches!(result, Err(MycoError::IncompatibleState(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
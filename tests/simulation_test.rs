@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod simulation_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use myco_rs::{
+        client::Client,
+        dtypes::Key,
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+        simulation::{SimulationMode, SIMULATION_MODE_ENV},
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_no_encryption_requires_env_and_round_trips() {
+        // One test, as it sets the process environment.
+        std::env::remove_var(SIMULATION_MODE_ENV);
+        assert!(matches!(
+            SimulationMode::no_encryption(),
+            Err(MycoError::ConfigError(_))
+        ));
+        std::env::set_var(SIMULATION_MODE_ENV, "yes");
+        assert!(SimulationMode::no_encryption().is_err());
+        assert!(SimulationMode::default().encrypts());
+
+        std::env::set_var(SIMULATION_MODE_ENV, "no-enc");
+        let mode = SimulationMode::no_encryption().unwrap();
+        assert!(!mode.encrypts());
+
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let mut server1 = Server1::new(s2_access.clone());
+        server1.set_simulation_mode(mode);
+        let s1 = Arc::new(RwLock::new(server1));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access);
+        alice.set_simulation_mode(mode);
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        s1.write().unwrap().batch_init(1);
+        alice.write(&[7, 7, 7], &k).expect("Write failed");
+        s1.write().unwrap().batch_write().expect("Batch write failed");
+
+        // Buckets aren't padded, so the write is the only block left in the pathset.
+        let blocks: usize = s1.read().unwrap().pt.packed_buckets.iter().map(|b| b.len()).sum();
+        assert_eq!(blocks, 1);
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
+        assert_eq!(msg, vec![7, 7, 7]);
+
+        // Blocks carry over unchanged, so later epochs can be written as deltas. The second
+        // epoch's pathset overlaps the first's, so its deltas apply to written buckets.
+        s1.write().unwrap().set_delta_writes(true).expect("Delta writes refused");
+        for msg in 1..=2 {
+            s1.write().unwrap().batch_init(1);
+            alice.write(&[msg], &k).expect("Write failed");
+            s1.write().unwrap().batch_write().expect("Batch write failed");
+            assert_eq!(alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload, vec![msg]);
+        }
+        s1.write().unwrap().set_simulation_mode(SimulationMode::Off);
+        assert!(s1.write().unwrap().set_delta_writes(true).is_err());
+    }
+}
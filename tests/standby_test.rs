@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod standby_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        Router,
+    };
+    use futures::executor::block_on;
+    use myco_rs::{
+        admin::{self, AdminState, EpochControl},
+        client::Client,
+        dtypes::Key,
+        network::{LocalServer1Access, LocalServer2Access},
+        rpc_types::FailoverRequest,
+        server1::Server1,
+        server2::Server2,
+        standby::{LocalReplica, ReplicationEvent, Standby},
+        tree::SparseBinaryTree,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tower::ServiceExt;
+
+    fn replication_key() -> Key {
+        Key::random(&mut ChaCha20Rng::from_entropy())
+    }
+
+    #[test]
+    fn test_standby_needs_snapshot_first() {
+        let mut standby = Standby::default();
+        assert!(standby.apply(ReplicationEvent::BatchAborted).is_err());
+        assert!(!standby.is_synced());
+
+        let mut primary = Server1::new(Box::new(LocalServer2Access {
+            server: Arc::new(Mutex::new(Server2::new())),
+        }));
+        primary.epoch = 4;
+        standby
+            .apply(ReplicationEvent::Snapshot {
+                epoch: 4,
+                metadata: primary.metadata.clone(),
+            })
+            .unwrap();
+        standby
+            .apply(ReplicationEvent::BatchOpened {
+                epoch: 4,
+                sealed_key: vec![1, 2, 3],
+            })
+            .unwrap();
+        standby
+            .apply(ReplicationEvent::BatchWritten {
+                epoch: 4,
+                metadata: SparseBinaryTree::new(),
+            })
+            .unwrap();
+        assert_eq!(standby.epoch, 5);
+        assert_eq!(standby.sealed_key, None);
+    }
+
+    #[test]
+    fn test_standby_takes_over_open_epoch() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2.clone() });
+        let key = replication_key();
+
+        let standby = Arc::new(Mutex::new(Standby::default()));
+        let mut primary = Server1::new(s2_access.clone());
+        primary.set_replication_key(key.clone());
+        primary.set_replica(Arc::new(LocalReplica {
+            standby: standby.clone(),
+        }));
+        let primary = Arc::new(RwLock::new(primary));
+        let s1_access = Box::new(LocalServer1Access {
+            server: primary.clone(),
+        });
+        let mut alice = Client::new("Alice".to_string(), s1_access, s2_access.clone());
+
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        alice.setup(&k).expect("Setup failed");
+        primary.write().unwrap().batch_init(1);
+        alice.write(&[1, 1], &k).expect("Write failed");
+        primary.write().unwrap().batch_write().expect("Batch write failed");
+        primary.write().unwrap().batch_init(1);
+        alice.write(&[2, 2], &k).expect("Write failed");
+        {
+            let standby = standby.lock().unwrap();
+            assert_eq!(standby.epoch, 1);
+            assert_eq!(standby.writes.len(), 1);
+        }
+
+        // The primary crashes with the second epoch open.
+        drop(primary);
+        let mut server1 = Server1::new(s2_access);
+        server1.set_replication_key(key);
+        *server1.standby().lock().unwrap() = standby.lock().unwrap().clone();
+        block_on(server1.take_over(1)).expect("Take over failed");
+        assert_eq!(server1.epoch, 1);
+        assert_eq!(server1.queue_depth(), 1);
+        server1.batch_write().expect("Batch write failed");
+
+        let msg = alice.read(&k, "Alice".to_string(), 0).expect("Read failed").payload;
+        assert_eq!(msg, vec![2, 2]);
+        let msg = alice.read(&k, "Alice".to_string(), 1).expect("Read failed").payload;
+        assert_eq!(msg, vec![1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_admin_failover() {
+        const TOKEN: &str = "admin-secret";
+        let s2_access = Box::new(LocalServer2Access {
+            server: Arc::new(Mutex::new(Server2::new())),
+        });
+        let state = AdminState {
+            server1: Arc::new(tokio::sync::RwLock::new(Server1::new(s2_access))),
+            control: Arc::new(EpochControl::new()),
+            token: Arc::new(TOKEN.to_string()),
+        };
+        let app = Router::new().nest("/admin", admin::router(state.clone()));
+        let request = |uri: &str, body: Vec<u8>| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let failover = bincode::serialize(&FailoverRequest { num_writes: 1 }).unwrap();
+
+        // Nothing was replicated yet.
+        let events = bincode::serialize(&vec![ReplicationEvent::BatchAborted]).unwrap();
+        let response = app.clone().oneshot(request("/admin/replicate", events)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = app
+            .clone()
+            .oneshot(request("/admin/failover", failover.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let metadata = state.server1.read().await.metadata.clone();
+        let events = bincode::serialize(&vec![ReplicationEvent::Snapshot { epoch: 3, metadata }]).unwrap();
+        let response = app.clone().oneshot(request("/admin/replicate", events)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.oneshot(request("/admin/failover", failover)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.server1.read().await.epoch, 3);
+        assert!(!state.control.is_epoch_open());
+    }
+}
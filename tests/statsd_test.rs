@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod statsd_tests {
+    use std::{net::UdpSocket, sync::Arc, time::Duration};
+
+    use myco_rs::{
+        error::MycoError,
+        logging::{self, BytesMetric, LatencyMetric, MetricsSink},
+        statsd::StatsdSink,
+    };
+
+    fn daemon() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        (socket, addr)
+    }
+
+    fn receive(socket: &UdpSocket) -> String {
+        let mut buf = [0u8; 512];
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_sink_sends_timers_and_counters() {
+        let (socket, addr) = daemon();
+        let sink = StatsdSink::new(&addr, "myco").unwrap();
+
+        sink.record_latency("server2_http_read", Duration::from_micros(1500));
+        assert_eq!(receive(&socket), "myco.server2_http_read:1.500|ms");
+        sink.record_bytes("server2_http_read_response", 4096);
+        assert_eq!(receive(&socket), "myco.server2_http_read_response:4096|c");
+    }
+
+    #[test]
+    fn test_installed_sink_receives_hand_timed_metrics() {
+        let (socket, addr) = daemon();
+        logging::install_sink(Arc::new(StatsdSink::new(&addr, "test").unwrap()));
+
+        LatencyMetric::new("server1_batch_write_local").finish();
+        assert!(receive(&socket).starts_with("test.server1_batch_write_local:"));
+        BytesMetric::new("server1_batch_write_bytes", 12).log();
+        assert_eq!(receive(&socket), "test.server1_batch_write_bytes:12|c");
+    }
+
+    #[test]
+    fn test_rejects_invalid_address() {
+        assert!(matches!(
+            StatsdSink::new("not an address", "myco"),
+            Err(MycoError::ConfigError(_))
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+#[cfg(test)]
+mod store_tests {
+    use myco_rs::{constants::DELTA, dtypes::Key, store::MessageStore};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_insert_suppresses_duplicates() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        let mut store = MessageStore::in_memory();
+
+        assert!(store.insert(&k, b"Alice", 3, 0).unwrap());
+        assert!(!store.insert(&k, b"Alice", 3, 0).unwrap());
+        // Same sequence number from another epoch, sender or contact is a different message.
+        assert!(store.insert(&k, b"Alice", 4, 0).unwrap());
+        assert!(store.insert(&k, b"Bob", 3, 0).unwrap());
+        assert!(store.insert(&Key::random(&mut rng), b"Alice", 3, 0).unwrap());
+        assert_eq!(store.len(), 4);
+    }
+
+    #[test]
+    fn test_expired_messages_pruned() {
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        let mut store = MessageStore::in_memory();
+
+        store.insert(&k, b"Alice", 0, 0).unwrap();
+        store.insert(&k, b"Alice", DELTA, 1).unwrap();
+        assert!(store.contains(&k, b"Alice", 0, 0));
+        store.insert(&k, b"Alice", DELTA + 1, 2).unwrap();
+        assert!(!store.contains(&k, b"Alice", 0, 0));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_forget_contact() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (k, other) = (Key::random(&mut rng), Key::random(&mut rng));
+        let mut store = MessageStore::in_memory();
+
+        store.insert(&k, b"Alice", 1, 0).unwrap();
+        store.insert(&other, b"Alice", 1, 0).unwrap();
+        store.forget(&k).unwrap();
+        assert!(!store.contains(&k, b"Alice", 1, 0));
+        assert!(store.contains(&other, b"Alice", 1, 0));
+    }
+
+    #[test]
+    fn test_expected_sequence_tracks_highest_delivered() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        let mut store = MessageStore::in_memory();
+
+        assert_eq!(store.expected(&k, b"Alice"), 0);
+        assert_eq!(store.advance(&k, b"Alice", 0).unwrap(), 0);
+        // Skipping sequence numbers moves past them, and a late message doesn't move back.
+        assert_eq!(store.advance(&k, b"Alice", 3).unwrap(), 1);
+        assert_eq!(store.advance(&k, b"Alice", 2).unwrap(), 4);
+        assert_eq!(store.expected(&k, b"Alice"), 4);
+        // Senders are tracked separately, and forgotten with their contact.
+        assert_eq!(store.expected(&k, b"Bob"), 0);
+        store.forget(&k).unwrap();
+        assert_eq!(store.expected(&k, b"Alice"), 0);
+    }
+
+    #[test]
+    fn test_next_sequence_counts_per_contact() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let (k, other) = (Key::random(&mut rng), Key::random(&mut rng));
+        let mut store = MessageStore::in_memory();
+
+        assert_eq!(store.next_sequence(&k).unwrap(), 0);
+        assert_eq!(store.next_sequence(&k).unwrap(), 1);
+        assert_eq!(store.next_sequence(&other).unwrap(), 0);
+        store.forget(&k).unwrap();
+        assert_eq!(store.next_sequence(&k).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_store_persists() {
+        let path = std::env::temp_dir().join(format!("myco-store-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+
+        let mut store = MessageStore::open(&path).expect("Open failed");
+        assert!(store.is_empty());
+        assert!(store.insert(&k, b"Alice", 1, 7).unwrap());
+        store.advance(&k, b"Alice", 7).unwrap();
+        assert_eq!(store.next_sequence(&k).unwrap(), 0);
+
+        let mut reopened = MessageStore::open(&path).expect("Reopen failed");
+        assert!(reopened.contains(&k, b"Alice", 1, 7));
+        assert!(!reopened.insert(&k, b"Alice", 1, 7).unwrap());
+        assert_eq!(reopened.expected(&k, b"Alice"), 8);
+        assert_eq!(reopened.next_sequence(&k).unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_store_opens_files_without_sequences() {
+        let path = std::env::temp_dir().join(format!("myco-store-old-{}.bin", std::process::id()));
+        let k = Key::random(&mut ChaCha20Rng::from_entropy());
+        let ids = vec![(k.clone(), b"Alice".to_vec(), 1usize, 7u64)];
+        std::fs::write(&path, bincode::serialize(&ids).unwrap()).unwrap();
+
+        let mut store = MessageStore::open(&path).expect("Open failed");
+        assert!(store.contains(&k, b"Alice", 1, 7));
+        assert_eq!(store.expected(&k, b"Alice"), 0);
+        assert_eq!(store.next_sequence(&k).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
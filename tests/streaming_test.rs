@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod streaming_tests {
+    use axum::body::{Body, HttpBody};
+    use myco_rs::{
+        constants::ENCODED_BUCKET_SIZE,
+        dtypes::{Block, Bucket, Key},
+        error::MycoError,
+        rpc_types::ChunkWriteRequest,
+        streaming::{decode_body, framed_response, FrameDecoder, SeqBody, SeqDecoder},
+    };
+
+    fn random_bucket() -> Bucket {
+        let mut bucket = Bucket::default();
+        for _ in 0..3 {
+            bucket.push(Block::new_random());
+        }
+        bucket
+    }
+
+    fn request() -> ChunkWriteRequest {
+        ChunkWriteRequest {
+            buckets: (0..5).map(|_| random_bucket()).collect(),
+            chunk_idx: 3,
+            prf_key: Key::new(vec![7; 16]),
+            epoch: 9,
+        }
+    }
+
+    #[test]
+    fn test_decoder_matches_bincode_at_any_split() {
+        let request = request();
+        let bytes = bincode::serialize(&request).unwrap();
+        for chunk_size in [1, 7, 1000, bytes.len()] {
+            let mut decoder = SeqDecoder::<Bucket>::new();
+            for chunk in bytes.chunks(chunk_size) {
+                decoder.feed(chunk).unwrap();
+            }
+            let (buckets, tail) = decoder.finish().unwrap();
+            assert_eq!(buckets, request.buckets);
+            let (chunk_idx, prf_key, epoch): (usize, Key, u64) =
+                bincode::deserialize(&tail).unwrap();
+            assert_eq!(chunk_idx, request.chunk_idx);
+            assert_eq!(prf_key, request.prf_key);
+            assert_eq!(epoch, request.epoch);
+        }
+    }
+
+    #[test]
+    fn test_decoder_drains_items_as_they_arrive() {
+        let request = request();
+        let bytes = bincode::serialize(&request).unwrap();
+        let mut decoder = SeqDecoder::<Bucket>::new();
+        let mut buckets = Vec::new();
+        for chunk in bytes.chunks(100) {
+            decoder.feed(chunk).unwrap();
+            buckets.extend(decoder.drain());
+        }
+        let (rest, tail) = decoder.finish().unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(buckets, request.buckets);
+        assert_eq!(
+            bincode::deserialize::<(usize, Key, u64)>(&tail).unwrap(),
+            (request.chunk_idx, request.prf_key, request.epoch)
+        );
+    }
+
+    #[test]
+    fn test_decoder_rejects_truncated_body() {
+        let bytes = bincode::serialize(&request().buckets).unwrap();
+        let mut decoder = SeqDecoder::<Bucket>::new();
+        decoder.feed(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(
+            decoder.finish(),
+            Err(MycoError::DeserializationError(None))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_body_matches_bincode() {
+        let request = request();
+        let tail =
+            bincode::serialize(&(request.chunk_idx, &request.prf_key, request.epoch)).unwrap();
+        let body = SeqBody::new(request.buckets.clone(), tail).unwrap();
+        let expected = bincode::serialize(&request).unwrap();
+        assert_eq!(body.size_hint().exact(), Some(expected.len() as u64));
+
+        let bytes = axum::body::to_bytes(Body::new(body), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], &expected[..]);
+
+        let (buckets, _) = decode_body::<Bucket>(Body::from(bytes)).await.unwrap();
+        assert_eq!(buckets, request.buckets);
+    }
+
+    async fn framed_bytes(items: Vec<Result<Bucket, MycoError>>) -> Result<Vec<u8>, axum::Error> {
+        let count = items.len();
+        let response = framed_response(count, futures::stream::iter(items));
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .map(|bytes| bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_frames_round_trip() {
+        let buckets = request().buckets;
+        let bytes = framed_bytes(buckets.iter().cloned().map(Ok).collect())
+            .await
+            .unwrap();
+        for chunk_size in [1, 13, bytes.len()] {
+            let mut decoder = FrameDecoder::<Bucket>::new(ENCODED_BUCKET_SIZE);
+            for chunk in bytes.chunks(chunk_size) {
+                decoder.feed(chunk).unwrap();
+            }
+            assert_eq!(decoder.finish().unwrap(), buckets);
+        }
+
+        let mut decoder = FrameDecoder::<Bucket>::new(ENCODED_BUCKET_SIZE);
+        decoder.feed(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(decoder.finish().is_err());
+
+        let mut decoder = FrameDecoder::<Bucket>::new(16);
+        assert!(matches!(
+            decoder.feed(&bytes),
+            Err(MycoError::ProtocolError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_frame_error_breaks_the_body() {
+        let items = vec![Ok(random_bucket()), Err(MycoError::UnknownContact)];
+        assert!(framed_bytes(items).await.is_err());
+    }
+}
@@ -0,0 +1,125 @@
+#[cfg(test)]
+mod tenant_tests {
+    use std::path::Path;
+
+    use axum::{
+        body::{Body, Bytes},
+        http::{header, Method, Request, StatusCode},
+        routing::post,
+        Router,
+    };
+    use myco_rs::{
+        bandwidth::Traffic,
+        constants::MAX_CONTROL_BODY_SIZE,
+        dtypes::{Bucket, Key},
+        hardening::{self, BINCODE_CONTENT_TYPE},
+        network::{RemoteServer2Access, Server2Access},
+        server2::{http, Server2},
+        tenant::{self, TenantId},
+        tls::TlsTrust,
+        transport::TransportOptions,
+        utils::get_leaf_path_indices,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    fn tenant(id: &str) -> TenantId {
+        id.parse().unwrap()
+    }
+
+    #[test]
+    fn test_tenant_ids_validated() {
+        assert_eq!(
+            tenant::parse_tenants("alpha, beta-2,").unwrap(),
+            vec![tenant("alpha"), tenant("beta-2")]
+        );
+        assert!(tenant::parse_tenants("alpha,alpha").is_err());
+        for id in ["", "Alpha", "a/b", "a.b", &"a".repeat(tenant::MAX_TENANT_ID_LEN + 1)] {
+            assert!(id.parse::<TenantId>().is_err(), "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn test_tenant_paths_and_config() {
+        let alpha = tenant("alpha-1");
+        assert_eq!(tenant::tenant_url("https://host:3003/", &alpha), "https://host:3003/t/alpha-1");
+        assert_eq!(tenant::strip_tenant("/t/alpha-1/chunk_write"), "/chunk_write");
+        assert_eq!(tenant::strip_tenant("/chunk_write"), "/chunk_write");
+        assert_eq!(
+            tenant::route_path("https://host/t/alpha-1", "/admin/backup"),
+            "/t/alpha-1/admin/backup"
+        );
+        assert_eq!(tenant::route_path("https://host", "/admin/backup"), "/admin/backup");
+        assert_eq!(tenant::tenant_var("MYCO_WRITE_QUOTA", &alpha), "MYCO_WRITE_QUOTA_ALPHA_1");
+        assert_eq!(
+            tenant::tenant_file(Path::new("/var/myco/snapshot.bin"), &alpha),
+            Path::new("/var/myco/snapshot.alpha-1.bin")
+        );
+        assert_eq!(
+            tenant::limit_from_env("MYCO_TENANT_TEST_UNSET", &alpha, Some(3)).unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_tenant_routes_keep_their_traffic_class() {
+        assert_eq!(Traffic::of_route(&Method::POST, "/t/alpha/chunk_write"), Traffic::BatchWrite);
+        assert_eq!(Traffic::of_route(&Method::POST, "/t/alpha/queue_write"), Traffic::ClientWrites);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_routes_keep_their_body_limits() {
+        let app = || {
+            hardening::harden(
+                Router::new().route("/chunk_write", post(|bytes: Bytes| async move { bytes.len().to_string() })),
+            )
+        };
+        let router = tenant::nest_tenants([(tenant("alpha"), app()), (tenant("beta"), app())]);
+        let request = Request::post("/t/beta/chunk_write")
+            .header(header::CONTENT_TYPE, BINCODE_CONTENT_TYPE)
+            .body(Body::from(vec![0u8; MAX_CONTROL_BODY_SIZE + 1]))
+            .unwrap();
+        assert_eq!(router.oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_tenants_do_not_share_state() {
+        let alpha = http::AppState::new(Server2::new());
+        let beta = http::AppState::new(Server2::new());
+        let app = tenant::nest_tenants([
+            (tenant("alpha"), http::router().with_state(alpha.clone())),
+            (tenant("beta"), http::router().with_state(beta.clone())),
+        ]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // Finish an epoch on alpha only.
+        let key = Key::random(&mut ChaCha20Rng::from_entropy());
+        {
+            let mut server2 = alpha.server2.write().await;
+            let pathset = get_leaf_path_indices(&[4]).unwrap();
+            let buckets = vec![Bucket::default(); pathset.len()];
+            server2.store_path_indices(pathset).unwrap();
+            server2.write(0, buckets, &key).unwrap();
+        }
+
+        let access = |tenant: TenantId| {
+            let url = tenant::tenant_url(&base_url, &tenant);
+            async move {
+                RemoteServer2Access::new(&url, &TlsTrust::default(), &TransportOptions::default())
+                    .await
+                    .unwrap()
+            }
+        };
+        let alpha_access = access(tenant("alpha")).await;
+        let beta_access = access(tenant("beta")).await;
+        assert_eq!(alpha_access.get_epoch().await.unwrap().epoch, 1);
+        assert_eq!(alpha_access.get_prf_keys().await.unwrap(), vec![key]);
+        assert_eq!(beta_access.get_epoch().await.unwrap().epoch, 0);
+        assert!(beta_access.get_prf_keys().await.unwrap().is_empty());
+        assert_eq!(beta.server2.read().await.epoch, 0);
+    }
+}
@@ -0,0 +1,65 @@
+#[cfg(all(test, feature = "test-vectors"))]
+mod test_vectors_tests {
+    use myco_rs::{
+        client::EpochKeys,
+        crypto::kdf,
+        error::MycoError,
+        test_vectors::{self, TEST_VECTORS_VERSION},
+    };
+
+    #[test]
+    fn test_vectors_verify_after_json_round_trip() {
+        let vectors = test_vectors::generate().unwrap();
+        assert!(!vectors.keys.is_empty() && !vectors.paths.is_empty() && !vectors.blocks.is_empty());
+        let decoded = test_vectors::from_json(&test_vectors::to_json(&vectors)).unwrap();
+        assert_eq!(decoded, vectors);
+        test_vectors::verify(&decoded).unwrap();
+    }
+
+    #[test]
+    fn test_vectors_are_deterministic() {
+        assert_eq!(test_vectors::generate().unwrap(), test_vectors::generate().unwrap());
+    }
+
+    #[test]
+    fn test_vectors_match_client_derivations() {
+        let vectors = test_vectors::generate().unwrap();
+        let v = &vectors.keys[1];
+        let k = hex::decode(&v.k).unwrap();
+        assert_eq!(v.k_msg, hex::encode(kdf(&k, "MSG").unwrap()));
+
+        let p = &vectors.paths[0];
+        let keys = EpochKeys::derive(
+            &hex::decode(&p.k_oblv).unwrap(),
+            &hex::decode(&p.k_prf).unwrap(),
+            p.epoch as usize,
+        )
+        .unwrap();
+        assert_eq!(p.f, hex::encode(keys.f));
+        assert_eq!(p.k_oblv_t, hex::encode(keys.k_oblv_t));
+    }
+
+    #[test]
+    fn test_vectors_reject_tampering() {
+        let vectors = test_vectors::generate().unwrap();
+
+        let mut tampered = vectors.clone();
+        tampered.paths[2].l.replace_range(0..2, "00");
+        if tampered.paths[2].l == vectors.paths[2].l {
+            tampered.paths[2].l.replace_range(0..2, "01");
+        }
+        assert!(matches!(
+            test_vectors::verify(&tampered),
+            Err(MycoError::TestVectorMismatch(msg)) if msg.starts_with("paths[2].l")
+        ));
+
+        let mut tampered = vectors.clone();
+        tampered.blocks[1].block.pop();
+        tampered.blocks[1].block.push('x');
+        assert!(matches!(test_vectors::verify(&tampered), Err(MycoError::TestVectorMismatch(_))));
+
+        let mut tampered = vectors;
+        tampered.version = TEST_VECTORS_VERSION + 1;
+        assert!(test_vectors::verify(&tampered).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+#[cfg(test)]
+mod timing_tests {
+    //! Checks that Server1's batch write does as much work whatever its buckets hold.
+    //!
+    //! One Server1 runs two kinds of epoch, interleaved at random: idle ones, in which no client
+    //! writes, so every slot it seals is a dummy, and busy ones, in which every client writes, so
+    //! it seals live blocks. Both read and rewrite the same number of buckets of the same tree,
+    //! whose top levels the busy epochs keep filled, and have to cost the same.
+    //!
+    //! [`test_batch_write_operation_counts_independent_of_live_blocks`] counts the block
+    //! operations of epochs of each kind, and [`test_batch_write_timing_close_on_small_sample`]
+    //! compares the median `batch_write` times of a few of each, loosely enough for a debug build
+    //! on a busy machine; both run with the rest of the suite. The dudect-style
+    //! [`test_batch_write_timing_independent_of_live_blocks`] compares the two distributions of
+    //! [`SAMPLES`] epochs each with Welch's t-test, raw and cropped at a few upper percentiles to
+    //! shed scheduling noise. A `|t|` above [`T_THRESHOLD`] means the timing depends on how many
+    //! blocks are live, e.g. because dummy operations skipped work again. With D = 18 only the top
+    //! levels of the tree fill up, so the timing tests only see differences that stand out against
+    //! the noise of a whole batch write; the counts are the precise check. The t-test takes a while
+    //! even in release builds and wants a quiet machine, so it's ignored by default:
+    //!
+    //! ```text
+    //! cargo test --release --test timing_test -- --ignored --nocapture
+    //! ```
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex, RwLock,
+        },
+        time::Instant,
+    };
+
+    use myco_rs::{
+        client::Client,
+        constants::Z,
+        dtypes::{Block, Key},
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        secrets::{HostSecrets, SecretCompute},
+        server1::Server1,
+        server2::Server2,
+    };
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    /// Clients writing in every busy epoch, and the batch size of every epoch.
+    const NUM_CLIENTS: usize = 16;
+
+    /// Busy epochs run before measuring, enough to fill the tree with live blocks.
+    const WARMUP_EPOCHS: usize = 20;
+
+    /// Measured epochs per class.
+    const SAMPLES: usize = 200;
+
+    /// Measured epochs per class of the test that runs with the rest of the suite.
+    const SMALL_SAMPLES: usize = 6;
+
+    /// How far apart the median idle and busy epochs of the small sample may be.
+    const MEDIAN_RATIO: f64 = 1.25;
+
+    /// dudect's threshold for a timing leak.
+    const T_THRESHOLD: f64 = 4.5;
+
+    /// Host secrets counting the block operations made through them.
+    #[derive(Default)]
+    struct CountingSecrets {
+        inner: HostSecrets,
+        opens: AtomicUsize,
+        seals: AtomicUsize,
+    }
+
+    impl SecretCompute for CountingSecrets {
+        fn rotate_epoch_key(&self) {
+            self.inner.rotate_epoch_key();
+        }
+
+        fn epoch_key(&self) -> Result<Key, MycoError> {
+            self.inner.epoch_key()
+        }
+
+        fn location(&self, f: &[u8], cs: &[u8]) -> Result<Vec<u8>, MycoError> {
+            self.inner.location(f, cs)
+        }
+
+        fn open_block(&self, k_oblv_t: &Key, block: &Block) -> Result<Vec<u8>, MycoError> {
+            self.opens.fetch_add(1, Ordering::SeqCst);
+            self.inner.open_block(k_oblv_t, block)
+        }
+
+        fn seal_block(&self, k_oblv_t: &Key, ct: &[u8]) -> Result<Block, MycoError> {
+            self.seals.fetch_add(1, Ordering::SeqCst);
+            self.inner.seal_block(k_oblv_t, ct)
+        }
+    }
+
+    /// A Server1 with its own Server2 and [`NUM_CLIENTS`] clients set up to write to it.
+    struct Deployment {
+        s1: Arc<RwLock<Server1>>,
+        secrets: Arc<CountingSecrets>,
+        clients: Vec<(Client, Key)>,
+    }
+
+    impl Deployment {
+        fn new() -> Self {
+            let s2 = Arc::new(Mutex::new(Server2::new()));
+            let s2_access = Box::new(LocalServer2Access { server: s2 });
+            let secrets = Arc::new(CountingSecrets::default());
+            let mut server1 = Server1::new(s2_access.clone());
+            server1.set_secret_compute(secrets.clone());
+            let s1 = Arc::new(RwLock::new(server1));
+            let clients = (0..NUM_CLIENTS)
+                .map(|i| {
+                    let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+                    let mut client =
+                        Client::new(format!("client{}", i), s1_access, s2_access.clone());
+                    let k = Key::random(&mut ChaCha20Rng::from_entropy());
+                    client.setup(&k).expect("Setup failed");
+                    (client, k)
+                })
+                .collect();
+            Self { s1, secrets, clients }
+        }
+
+        /// Run one epoch sized for [`NUM_CLIENTS`] writes, with a write from every client if it's
+        /// `busy` and none otherwise, and return how long the batch write took in microseconds.
+        fn epoch(&mut self, busy: bool) -> f64 {
+            self.s1.write().unwrap().batch_init(NUM_CLIENTS);
+            if busy {
+                let epoch = self.s1.read().unwrap().epoch as usize;
+                for (client, k) in self.clients.iter_mut() {
+                    client.epoch = epoch;
+                    client.write(&[0x42; 64], k).expect("Write failed");
+                }
+            }
+            let start = Instant::now();
+            self.s1.write().unwrap().batch_write().expect("Batch write failed");
+            start.elapsed().as_secs_f64() * 1e6
+        }
+
+        /// Block operations made so far, opens and seals.
+        fn operations(&self) -> (usize, usize) {
+            (
+                self.secrets.opens.load(Ordering::SeqCst),
+                self.secrets.seals.load(Ordering::SeqCst),
+            )
+        }
+
+        /// `samples` batch write times of idle and of busy epochs, run in random order.
+        fn sample(&mut self, samples: usize) -> (Vec<f64>, Vec<f64>) {
+            let mut rng = ChaCha20Rng::from_entropy();
+            let (mut idle, mut busy) = (Vec::new(), Vec::new());
+            while idle.len() < samples || busy.len() < samples {
+                let busy_epoch = if idle.len() == samples {
+                    true
+                } else if busy.len() == samples {
+                    false
+                } else {
+                    rng.gen()
+                };
+                if busy_epoch {
+                    busy.push(self.epoch(true));
+                } else {
+                    idle.push(self.epoch(false));
+                }
+            }
+            (idle, busy)
+        }
+    }
+
+    /// Welch's t statistic of two samples.
+    fn welch_t(a: &[f64], b: &[f64]) -> f64 {
+        let moments = |xs: &[f64]| {
+            let n = xs.len() as f64;
+            let mean = xs.iter().sum::<f64>() / n;
+            let var = xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            (n, mean, var)
+        };
+        let ((na, ma, va), (nb, mb, vb)) = (moments(a), moments(b));
+        (ma - mb) / (va / na + vb / nb).sqrt()
+    }
+
+    /// The median of `xs`.
+    fn median(xs: &[f64]) -> f64 {
+        let mut sorted = xs.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        sorted[sorted.len() / 2]
+    }
+
+    /// The measurements of `xs` up to its `percentile`.
+    fn crop(xs: &[f64], percentile: f64) -> Vec<f64> {
+        let mut sorted = xs.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let cutoff = sorted[((sorted.len() - 1) as f64 * percentile) as usize];
+        xs.iter().copied().filter(|&x| x <= cutoff).collect()
+    }
+
+    #[test]
+    fn test_welch_t_separates_shifted_samples() {
+        let a: Vec<f64> = (0..100).map(|i| (i % 10) as f64).collect();
+        let b: Vec<f64> = a.iter().map(|x| x + 5.0).collect();
+        assert!(welch_t(&a, &a).abs() < 1e-9);
+        assert!(welch_t(&a, &b).abs() > T_THRESHOLD);
+        assert_eq!(crop(&a, 0.5).len(), 50);
+        assert_eq!(median(&b), 10.0);
+    }
+
+    #[test]
+    fn test_batch_write_operation_counts_independent_of_live_blocks() {
+        let mut deployment = Deployment::new();
+        deployment.epoch(true);
+
+        // Every bucket of the pathset is opened and sealed Z times, on top of sealing the
+        // batch's dummy block, whether its slots are all dummies or hold live blocks.
+        for busy in [false, true, false] {
+            let (opens, seals) = deployment.operations();
+            deployment.epoch(busy);
+            let (opens, seals) = {
+                let (after_opens, after_seals) = deployment.operations();
+                (after_opens - opens, after_seals - seals)
+            };
+            let buckets = deployment.s1.read().unwrap().pathset_size();
+            assert_eq!(opens, Z * buckets);
+            assert_eq!(seals, Z * buckets + 1);
+        }
+    }
+
+    #[test]
+    fn test_batch_write_timing_close_on_small_sample() {
+        let mut deployment = Deployment::new();
+        deployment.epoch(true);
+
+        // Too few epochs for the t-test, but enough for the medians to give away an idle epoch
+        // that skips its dummy work.
+        let (idle, busy) = deployment.sample(SMALL_SAMPLES);
+        let ratio = median(&busy) / median(&idle);
+        assert!(
+            (1.0 / MEDIAN_RATIO..MEDIAN_RATIO).contains(&ratio),
+            "busy epochs take {:.2} times as long as idle ones",
+            ratio
+        );
+    }
+
+    #[test]
+    #[ignore = "statistical timing test, run with --release --ignored on a quiet machine"]
+    fn test_batch_write_timing_independent_of_live_blocks() {
+        let mut deployment = Deployment::new();
+        for _ in 0..WARMUP_EPOCHS {
+            deployment.epoch(true);
+        }
+
+        let (idle, busy) = deployment.sample(SAMPLES);
+        for percentile in [1.0, 0.9, 0.75, 0.5] {
+            let t = welch_t(&crop(&idle, percentile), &crop(&busy, percentile));
+            println!("batch_write timing, cropped at {:.2}: t = {:.2}", percentile, t);
+            assert!(
+                t.abs() < T_THRESHOLD,
+                "batch_write timing depends on live blocks (t = {:.2} at percentile {:.2})",
+                t,
+                percentile
+            );
+        }
+    }
+}
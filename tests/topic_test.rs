@@ -0,0 +1,74 @@
+#[cfg(test)]
+mod topic_tests {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use myco_rs::{
+        client::Client,
+        error::MycoError,
+        network::{LocalServer1Access, LocalServer2Access},
+        server1::Server1,
+        server2::Server2,
+        topic::Mailboxes,
+    };
+
+    fn setup() -> (Arc<RwLock<Server1>>, Client, Client) {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let s1 = Arc::new(RwLock::new(Server1::new(s2_access.clone())));
+        let s1_access = Box::new(LocalServer1Access { server: s1.clone() });
+        let alice = Client::new("Alice".to_string(), s1_access.clone(), s2_access.clone());
+        let bob = Client::new("Bob".to_string(), s1_access, s2_access);
+        (s1, alice, bob)
+    }
+
+    #[test]
+    fn test_shared_mailbox_carries_messages_both_ways() {
+        let (s1, mut alice, mut bob) = setup();
+        let (mut alice_boxes, mut bob_boxes) = (Mailboxes::new(), Mailboxes::new());
+        alice_boxes.create(&mut alice, "family", "Bob").unwrap();
+        let shared = alice_boxes.share(&alice, "family", "https://s1", "https://s2").unwrap();
+        let (mailbox, bundle) = bob_boxes.import(&mut bob, "home", &shared).unwrap();
+        assert_eq!((mailbox.peer.as_str(), bundle.server2.as_str()), ("Alice", "https://s2"));
+        assert_eq!(bob_boxes.topic_of(&alice_boxes.get("family").unwrap().key), Some("home"));
+
+        for (from_alice, from_bob) in [("hi bob", "hi alice"), ("bye bob", "bye alice")] {
+            s1.write().unwrap().batch_init(2);
+            alice_boxes.get("family").unwrap().send(&mut alice, from_alice.as_bytes()).unwrap();
+            bob_boxes.get("home").unwrap().send(&mut bob, from_bob.as_bytes()).unwrap();
+            s1.write().unwrap().batch_write().unwrap();
+
+            let received = bob_boxes.get_mut("home").unwrap().recv(&bob).unwrap();
+            let payloads: Vec<_> = received.into_iter().map(|envelope| envelope.payload).collect();
+            assert_eq!(payloads, vec![from_alice.as_bytes().to_vec()]);
+            let received = alice_boxes.get_mut("family").unwrap().recv(&alice).unwrap();
+            let payloads: Vec<_> = received.into_iter().map(|envelope| envelope.payload).collect();
+            assert_eq!(payloads, vec![from_bob.as_bytes().to_vec()]);
+        }
+        // Epochs already read aren't read again.
+        assert!(bob_boxes.get_mut("home").unwrap().recv(&bob).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mailboxes_enumerated_by_topic() {
+        let (_, mut alice, _) = setup();
+        let mut mailboxes = Mailboxes::new();
+        for topic in ["work", "family", "book club"] {
+            mailboxes.create(&mut alice, topic, "Bob").unwrap();
+        }
+        assert!(matches!(
+            mailboxes.create(&mut alice, "work", "Carol"),
+            Err(MycoError::ConfigError(_))
+        ));
+        let topics: Vec<_> = mailboxes.iter().map(|mailbox| mailbox.topic.as_str()).collect();
+        assert_eq!(topics, vec!["book club", "family", "work"]);
+
+        let work = mailboxes.remove(&mut alice, "work", false).unwrap();
+        assert!(!alice.keys.contains_key(&work.send_key));
+        assert!(!alice.keys.contains_key(&work.recv_key));
+        assert_eq!(mailboxes.len(), 2);
+        assert!(matches!(
+            mailboxes.share(&alice, "work", "https://s1", "https://s2"),
+            Err(MycoError::UnknownContact)
+        ));
+    }
+}
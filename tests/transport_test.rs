@@ -0,0 +1,557 @@
+#[cfg(test)]
+mod transport_tests {
+    use std::sync::Arc;
+
+    use myco_rs::{
+        admin::EpochControl,
+        client::Client,
+        dtypes::{Block, Bucket, BucketDelta, Key, Path},
+        error::MycoError,
+        hardening,
+        constants::{D, ENCODED_BUCKET_SIZE},
+        network::{Command, RemoteServer2Access, Server2Access, WriteType},
+        rpc_types::{
+            Capabilities, ChunkWriteRequest, FinalizeEpochResponse, GetCapabilitiesResponse,
+            WriteResponse,
+        },
+        server1::{self, Server1},
+        server2::{self, Server2},
+        tls::TlsTrust,
+        utils::{get_leaf_path_indices, get_path_indices},
+        transport::{
+            HttpsTransport, InMemoryServer1Transport, InMemoryServer2Transport, Transport,
+            TransportConfig, TransportOptions, TransportServer1Access, TransportServer2Access,
+            CHUNK_HEADER,
+        },
+    };
+    use axum::{
+        body::Bytes,
+        routing::{get, post},
+        Router,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use tokio::{net::TcpListener, sync::RwLock};
+
+    #[test]
+    fn test_transport_config_from_addr() {
+        let system = TlsTrust::default();
+        let pinned = TlsTrust::self_signed(std::path::Path::new("ca.pem"));
+        assert_eq!(
+            TransportConfig::from_addr("https://127.0.0.1:3003", &system).unwrap(),
+            TransportConfig::Https {
+                base_url: "https://127.0.0.1:3003".to_string(),
+                trust: system.clone(),
+                options: TransportOptions::default(),
+            }
+        );
+        assert_eq!(
+            TransportConfig::from_addr("tcp://127.0.0.1:3005", &system).unwrap(),
+            TransportConfig::Tcp {
+                addr: "127.0.0.1:3005".to_string()
+            }
+        );
+        assert!(matches!(
+            TransportConfig::from_addr("tls://127.0.0.1:3005", &pinned).unwrap(),
+            TransportConfig::Tls { addr, trust } if addr == "127.0.0.1:3005" && trust == pinned
+        ));
+        assert!(TransportConfig::from_addr("tls://127.0.0.1:3005", &system).is_err());
+        assert!(TransportConfig::from_addr("grpc://127.0.0.1:3005", &system).is_err());
+    }
+
+    #[test]
+    fn test_tls_trust_server_name() {
+        let trust = TlsTrust {
+            server_name: Some("myco.example".to_string()),
+            ..TlsTrust::default()
+        };
+        assert_eq!(trust.server_name_for("127.0.0.1"), "myco.example");
+        assert_eq!(TlsTrust::default().server_name_for("127.0.0.1"), "127.0.0.1");
+
+        // The configured name replaces the host in requests and is resolved to the original address.
+        let (_, base_url) = trust.http_client_builder("https://127.0.0.1:3003").unwrap();
+        assert_eq!(base_url, "https://myco.example:3003");
+        let (_, base_url) = TlsTrust::default()
+            .http_client_builder("https://127.0.0.1:3003")
+            .unwrap();
+        assert_eq!(base_url, "https://127.0.0.1:3003");
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_transport_write_and_read() {
+        let s2 = InMemoryServer2Transport {
+            server2: Arc::new(RwLock::new(Server2::new())),
+        };
+        let s1 = InMemoryServer1Transport {
+            server1: Arc::new(RwLock::new(Server1::new(Box::new(
+                TransportServer2Access::new(Box::new(s2.clone())),
+            )))),
+            control: Arc::new(EpochControl::new()),
+        };
+
+        let mut alice = Client::new(
+            "Alice".to_string(),
+            Box::new(TransportServer1Access::new(Box::new(s1.clone()))),
+            Box::new(TransportServer2Access::new(Box::new(s2.clone()))),
+        );
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        s1.server1.write().await.async_batch_init(1).await.expect("Batch init failed");
+        s1.control.set_epoch_open(true);
+        alice.async_write(&[2], &k).await.expect("Write failed");
+        s1.server1
+            .write()
+            .await
+            .async_batch_write()
+            .await
+            .expect("Batch write failed");
+
+        let msgs = alice
+            .async_read(vec![k], "Alice".to_string(), 0, 1)
+            .await
+            .expect("Read failed");
+        let payloads: Vec<_> = msgs.into_iter().map(|msg| msg.payload).collect();
+        assert_eq!(payloads, vec![vec![2]]);
+    }
+
+    #[tokio::test]
+    async fn test_https_transport_maps_http_errors() {
+        let s2 = InMemoryServer2Transport {
+            server2: Arc::new(RwLock::new(Server2::new())),
+        };
+        let state = server1::http::AppState::new(Server1::new(Box::new(
+            TransportServer2Access::new(Box::new(s2)),
+        )));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = server1::http::router().with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let transport = HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap();
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k_oblv_t = Key::random(&mut rng);
+        let write = || Command::Server1Write(vec![0; 32], vec![1; 32], k_oblv_t.clone(), vec![], vec![], None);
+
+        // Outside an epoch the endpoint answers 503, which surfaces as a closed epoch.
+        assert!(matches!(
+            transport.call(write()).await,
+            Err(MycoError::EpochClosed { next_epoch_opens_at: None })
+        ));
+
+        state.server1.write().await.async_batch_init(1).await.expect("Batch init failed");
+        state.control.set_epoch_open(true);
+        // An accepted write is answered with the epoch it was queued into.
+        assert!(matches!(
+            transport.call(write()).await,
+            Ok(Command::Queued(0))
+        ));
+
+        // Commands without an HTTP endpoint are rejected locally.
+        assert!(matches!(
+            transport.call(Command::Success).await,
+            Err(MycoError::InvalidCommand)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_prefetched_pathsets_match_server2() {
+        let s2 = InMemoryServer2Transport {
+            server2: Arc::new(RwLock::new(Server2::new())),
+        };
+        let s1 = InMemoryServer1Transport {
+            server1: Arc::new(RwLock::new(Server1::new(Box::new(
+                TransportServer2Access::new(Box::new(s2.clone())),
+            )))),
+            control: Arc::new(EpochControl::new()),
+        };
+        s1.server1.write().await.set_prefetch(true);
+        let mut alice = Client::new(
+            "Alice".to_string(),
+            Box::new(TransportServer1Access::new(Box::new(s1.clone()))),
+            Box::new(TransportServer2Access::new(Box::new(s2.clone()))),
+        );
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).expect("Setup failed");
+
+        // Every pathset holds the root, so each prefetch overlaps the epoch written after it was
+        // read. Initializing twice in a row drops the prefetch of the first batch.
+        s1.server1.write().await.async_batch_init(1).await.expect("Batch init failed");
+        for msg in 1..=3u8 {
+            s1.server1.write().await.async_batch_init(1).await.expect("Batch init failed");
+            // Let the prefetch read Server2 before this epoch is written.
+            tokio::task::yield_now().await;
+            {
+                let server1 = s1.server1.read().await;
+                let server2 = s2.server2.read().await;
+                let held: Vec<Bucket> = server1
+                    .pathset_indices
+                    .iter()
+                    .map(|&index| server2.tree.value[index].clone().unwrap())
+                    .collect();
+                assert_eq!(server1.p.packed_buckets, held);
+                let stored = server2.pathset_chunk(0);
+                assert_eq!(stored, &server1.pathset_indices[..stored.len()]);
+            }
+            s1.control.set_epoch_open(true);
+            alice.async_write(&[msg], &k).await.expect("Write failed");
+            s1.server1
+                .write()
+                .await
+                .async_batch_write()
+                .await
+                .expect("Batch write failed");
+
+            let msgs = alice
+                .async_read(vec![k.clone()], "Alice".to_string(), 0, 1)
+                .await
+                .expect("Read failed");
+            assert_eq!(msgs[0].payload, vec![msg]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_a_transport_error() {
+        // Nothing listens on the port of a listener that was just dropped.
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let transport = || {
+            Box::new(HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap())
+        };
+        let client = Client::new(
+            "Alice".to_string(),
+            Box::new(TransportServer1Access::new(transport())),
+            Box::new(TransportServer2Access::new(transport())),
+        );
+
+        match client.sync_prf_keys().await {
+            Err(MycoError::TransportError { operation, source }) => {
+                assert_eq!(operation, "get_epoch");
+                assert!(source.to_string().contains("epoch"), "{}", source);
+            }
+            result => panic!("expected a transport error, got {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_init_fails_when_server2_is_unreachable() {
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let s2_access = TransportServer2Access::new(Box::new(
+            HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+        ));
+        let mut s1 = Server1::new(Box::new(s2_access));
+
+        // The pathset read fails instead of panicking, and writes are still turned away.
+        assert!(matches!(
+            s1.async_batch_init(1).await,
+            Err(MycoError::TransportError { operation: "read_leaf_paths", .. })
+        ));
+        assert_eq!(s1.epoch_state(), server1::EpochState::Initializing);
+    }
+
+    #[tokio::test]
+    async fn test_https_transport_read_path() {
+        let state = server2::http::AppState::new(Server2::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = server2::http::router().with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let s2_access = TransportServer2Access::new(Box::new(
+            HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+        ));
+        let mut rng = ChaCha20Rng::from_entropy();
+        let path = Path::random(&mut rng);
+        assert_eq!(
+            s2_access.read_path(path.clone()).await.unwrap(),
+            state.server2.read().await.read(&path).unwrap()
+        );
+    }
+
+    fn random_bucket() -> Bucket {
+        let mut bucket = Bucket::default();
+        for _ in 0..3 {
+            bucket.push(Block::new_random());
+        }
+        bucket
+    }
+
+    #[tokio::test]
+    async fn test_https_transport_streams_pathset_write_and_read() {
+        let state = server2::http::AppState::new(Server2::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = hardening::harden(server2::http::router()).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let s2_access = TransportServer2Access::new(Box::new(
+            HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+        ));
+        let mut rng = ChaCha20Rng::from_entropy();
+        let indices = get_path_indices((0..4).map(|_| Path::random(&mut rng)).collect());
+        let buckets = s2_access.read_paths(indices.clone()).await.unwrap();
+        assert_eq!(buckets.len(), indices.len());
+
+        let written: Vec<Bucket> = (0..indices.len()).map(|_| random_bucket()).collect();
+        s2_access
+            .write(0, written.clone(), Key::random(&mut rng))
+            .await
+            .unwrap();
+        assert_eq!(
+            s2_access.read_paths_client(indices.clone(), 1).await.unwrap(),
+            written
+        );
+        // Chunked reads stream the buckets as frames.
+        let remote = TransportConfig::from_addr(&format!("http://{}", addr), &TlsTrust::default())
+            .unwrap()
+            .server2_access()
+            .await
+            .unwrap();
+        assert_eq!(
+            remote.read_paths_client_chunked(indices, 1).await.unwrap(),
+            written
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pathset_reads_leave_out_empty_buckets() {
+        let state = server2::http::AppState::new(Server2::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = hardening::harden(server2::http::router()).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let https = TransportServer2Access::new(Box::new(
+            HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+        ));
+        let in_memory = TransportServer2Access::new(Box::new(InMemoryServer2Transport {
+            server2: state.server2.clone(),
+        }));
+
+        // Write every other bucket of the pathset, leaving the rest empty.
+        let mut rng = ChaCha20Rng::from_entropy();
+        let indices = get_path_indices((0..4).map(|_| Path::random(&mut rng)).collect());
+        assert!(https.read_paths(indices.clone()).await.unwrap().iter().all(Bucket::is_empty));
+        let written: Vec<Bucket> = (0..indices.len())
+            .map(|i| if i % 2 == 0 { random_bucket() } else { Bucket::default() })
+            .collect();
+        https.write(0, written.clone(), Key::random(&mut rng)).await.unwrap();
+
+        assert_eq!(https.read_paths(indices.clone()).await.unwrap(), written);
+        assert_eq!(in_memory.read_paths(indices).await.unwrap(), written);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_writes_apply_as_they_arrive() {
+        let state = server2::http::AppState::new(Server2::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = hardening::harden(server2::http::router()).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let indices = get_path_indices((0..2).map(|_| Path::random(&mut rng)).collect());
+        state.server2.write().await.store_path_indices(indices.clone()).unwrap();
+        let client = reqwest::Client::new();
+        let post = |buckets: Vec<Bucket>, epoch: u64, header: Option<&str>| {
+            let body = bincode::serialize(&ChunkWriteRequest {
+                buckets,
+                chunk_idx: 0,
+                prf_key: Key::random(&mut ChaCha20Rng::from_entropy()),
+                epoch,
+            })
+            .unwrap();
+            let mut request = client
+                .post(format!("http://{}/chunk_write", addr))
+                .header("Content-Type", hardening::BINCODE_CONTENT_TYPE)
+                .body(body);
+            if let Some(header) = header {
+                request = request.header(CHUNK_HEADER, header);
+            }
+            request.send()
+        };
+        let read = || async { state.server2.read().await.read_paths_client(indices.clone()).unwrap() };
+
+        // With the header every bucket is applied as it is decoded, without it once the chunk is in.
+        let streamed: Vec<Bucket> = (0..indices.len()).map(|_| random_bucket()).collect();
+        assert!(post(streamed.clone(), 0, Some("0/0")).await.unwrap().status().is_success());
+        assert_eq!(read().await, streamed);
+        let buffered: Vec<Bucket> = (0..indices.len()).map(|_| random_bucket()).collect();
+        assert!(post(buffered.clone(), 0, None).await.unwrap().status().is_success());
+        assert_eq!(read().await, buffered);
+
+        // A header that disagrees with the body or can't be parsed is rejected.
+        assert!(post(streamed.clone(), 0, Some("0/1")).await.unwrap().status().is_client_error());
+        assert!(post(streamed.clone(), 0, Some("zero")).await.unwrap().status().is_client_error());
+
+        // A stale epoch is turned away before any bucket is applied.
+        state.server2.write().await.finalize_epoch(1, &Key::random(&mut rng)).unwrap();
+        assert!(!post(streamed, 0, Some("0/0")).await.unwrap().status().is_success());
+        assert_eq!(read().await, buffered);
+    }
+
+    #[tokio::test]
+    async fn test_leaf_path_reads_match_index_reads() {
+        let state = server2::http::AppState::new(Server2::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = hardening::harden(server2::http::router()).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let https = TransportServer2Access::new(Box::new(
+            HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+        ));
+        let in_memory = TransportServer2Access::new(Box::new(InMemoryServer2Transport {
+            server2: state.server2.clone(),
+        }));
+
+        let leaves = vec![0, 5, 5, (1 << D) - 1];
+        let indices = get_leaf_path_indices(&leaves).unwrap();
+        https.read_leaf_paths(leaves.clone()).await.unwrap();
+        let written: Vec<Bucket> = (0..indices.len()).map(|_| random_bucket()).collect();
+        let mut rng = ChaCha20Rng::from_entropy();
+        https.write(0, written.clone(), Key::random(&mut rng)).await.unwrap();
+
+        // The leaves store the same pathset the indices would.
+        assert_eq!(https.read_leaf_paths(leaves.clone()).await.unwrap(), written);
+        assert_eq!(in_memory.read_leaf_paths(leaves.clone()).await.unwrap(), written);
+        assert_eq!(https.read_paths(indices.clone()).await.unwrap(), written);
+        assert!(https.read_leaf_paths(vec![1 << D]).await.is_err());
+
+        // Prefetching reads the same buckets but leaves the stored pathset alone.
+        https.store_leaf_paths(vec![1]).await.unwrap();
+        assert_eq!(https.prefetch_leaf_paths(leaves).await.unwrap(), written);
+        assert_ne!(state.server2.read().await.pathset_chunk(0), &indices[..]);
+    }
+
+    #[tokio::test]
+    async fn test_https_transport_writes_deltas() {
+        let state = server2::http::AppState::new(Server2::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = hardening::harden(server2::http::router()).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let s2_access = TransportServer2Access::new(Box::new(
+            HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+        ));
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let indices = get_path_indices((0..4).map(|_| Path::random(&mut rng)).collect());
+        s2_access.read_paths(indices.clone()).await.unwrap();
+        let old: Vec<Bucket> = (0..indices.len()).map(|_| random_bucket()).collect();
+        s2_access.write(0, old.clone(), Key::random(&mut rng)).await.unwrap();
+
+        // Change one block of the first bucket and replace the second; the rest stay as they are.
+        let held = s2_access.read_paths(indices.clone()).await.unwrap();
+        let mut new = old.clone();
+        new[0][0] = Block::new_random();
+        new[1] = random_bucket();
+        let deltas: Vec<BucketDelta> = held
+            .iter()
+            .zip(new.clone())
+            .map(|(old, new)| BucketDelta::between(old, new))
+            .collect();
+        assert!(matches!(deltas[0], BucketDelta::Blocks(ref blocks) if blocks.len() == 1));
+        s2_access
+            .write_deltas(1, deltas, Key::random(&mut rng))
+            .await
+            .unwrap();
+        assert_eq!(s2_access.read_paths_client(indices, 1).await.unwrap(), new);
+    }
+
+    #[tokio::test]
+    async fn test_https_transport_uses_advertised_chunk_sizes() {
+        let advertised = Capabilities {
+            max_request_size: 3 * ENCODED_BUCKET_SIZE,
+            write_chunk_buckets: 3,
+            read_chunk_buckets: 2,
+        };
+        let chunks = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = chunks.clone();
+        let app = Router::new()
+            .route(
+                "/capabilities",
+                get(move || async move {
+                    bincode::serialize(&GetCapabilitiesResponse {
+                        capabilities: advertised,
+                    })
+                    .unwrap()
+                }),
+            )
+            .route(
+                "/chunk_write",
+                post(move |body: Bytes| async move {
+                    let request: ChunkWriteRequest = bincode::deserialize(&body).unwrap();
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push((request.chunk_idx, request.buckets.len()));
+                    bincode::serialize(&WriteResponse { success: true }).unwrap()
+                }),
+            )
+            .route(
+                "/finalize_epoch",
+                post(|| async { bincode::serialize(&FinalizeEpochResponse { success: true }).unwrap() }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let transport = HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap();
+        assert_eq!(transport.capabilities().await.unwrap(), advertised);
+        let buckets = (0..7).map(|_| random_bucket()).collect();
+        let mut rng = ChaCha20Rng::from_entropy();
+        transport
+            .call(Command::Server2Write(WriteType::Write(0, buckets, Key::random(&mut rng))))
+            .await
+            .unwrap();
+        let mut chunks = chunks.lock().unwrap().clone();
+        chunks.sort();
+        assert_eq!(chunks, vec![(0, 3), (1, 3), (2, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_default_to_local_constants() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, Router::new()).await });
+        let transport = HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap();
+        assert_eq!(transport.capabilities().await.unwrap(), Capabilities::local());
+
+        let empty = Capabilities {
+            read_chunk_buckets: 0,
+            ..Capabilities::local()
+        };
+        assert!(matches!(empty.validate(), Err(MycoError::ProtocolError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_surfaces_as_network_error() {
+        let app = Router::new().route(
+            "/epoch",
+            get(|| async {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                Bytes::new()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let options = TransportOptions {
+            request_timeout: Some(std::time::Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let access = TransportConfig::from_addr(&format!("http://{}", addr), &TlsTrust::default())
+            .unwrap()
+            .with_options(options.clone());
+        assert!(matches!(&access, TransportConfig::Https { options: o, .. } if *o == options));
+
+        let access = RemoteServer2Access::new(&format!("http://{}", addr), &TlsTrust::default(), &options)
+            .await
+            .unwrap();
+        let started = std::time::Instant::now();
+        let err = access.get_epoch().await.unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert!(matches!(err.downcast_ref::<MycoError>(), Some(MycoError::NetworkError { .. })));
+    }
+}
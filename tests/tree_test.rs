@@ -1,5 +1,5 @@
 use myco_rs::{
-    tree::{BinaryTree, TreeValue},
+    tree::{self, BinaryTree, SparseBinaryTree, TreeValue},
     dtypes::{Direction, Path},
 };
 use rand_chacha::ChaCha20Rng;
@@ -21,6 +21,7 @@ mod tree_tests {
     }
 
     #[test]
+    #[allow(clippy::absurd_extreme_comparisons)]
     fn test_int_wrapper() {
         // Test that IntWrapper implements TreeValue
         let random_value = IntWrapper::new_random();
@@ -705,4 +706,109 @@ mod tree_tests {
             "Overwriting with a tree that partially overlaps did not result in the expected tree"
         );
     }
+
+    #[test]
+    fn test_index_math() {
+        use Direction::{Left, Right};
+
+        assert_eq!(tree::child_index(tree::ROOT_INDEX, Left), 2);
+        assert_eq!(tree::child_index(tree::ROOT_INDEX, Right), 3);
+        assert_eq!(tree::parent_index(tree::ROOT_INDEX), None);
+        assert_eq!(tree::parent_index(6), Some(3));
+        assert_eq!(tree::depth_of(tree::ROOT_INDEX), 0);
+
+        let path = Path::new(vec![Right, Left, Right]);
+        let indices: Vec<usize> = tree::path_indices(&path).collect();
+        assert_eq!(indices, vec![1, 3, 6, 13]);
+        assert_eq!(tree::path_index(&path), 13);
+        assert_eq!(tree::depth_of(13), path.len());
+        for pair in indices.windows(2) {
+            assert_eq!(tree::parent_index(pair[1]), Some(pair[0]));
+        }
+
+        // The index math agrees with how both trees place nodes.
+        let mut dense = BinaryTree::<IntWrapper>::new_with_depth(3);
+        dense.write(IntWrapper(7), path.clone());
+        assert_eq!(dense.get_by_index(13), Some(&IntWrapper(7)));
+        assert_eq!(dense.get_index(&path), 13);
+        let mut sparse = SparseBinaryTree::new();
+        sparse.write(IntWrapper(7), path.clone());
+        assert_eq!(sparse.get_by_index(13), Some(&IntWrapper(7)));
+    }
+
+    #[test]
+    fn test_iterators() {
+        let mut dense = BinaryTree::<IntWrapper>::new_with_depth(2);
+        assert!(dense.is_empty());
+        dense.write(IntWrapper(1), Path::new(vec![]));
+        dense.write(IntWrapper(5), Path::new(vec![Direction::Left, Direction::Right]));
+        assert_eq!(dense.len(), 2);
+        assert_eq!(
+            dense.iter().collect::<Vec<_>>(),
+            vec![(1, &IntWrapper(1)), (5, &IntWrapper(5))]
+        );
+        dense.iter_mut().for_each(|(index, value)| value.0 += index as i32);
+        assert_eq!(dense.get_by_index(5), Some(&IntWrapper(10)));
+        assert_eq!(dense.get_by_index(100), None);
+
+        let mut sparse = SparseBinaryTree::new_with_data(vec![IntWrapper(5)], vec![5]);
+        sparse.insert(1, IntWrapper(1));
+        sparse.insert(5, IntWrapper(10));
+        assert_eq!(sparse.len(), 2);
+        assert_eq!(
+            sparse.iter().collect::<Vec<_>>(),
+            vec![(5, &IntWrapper(10)), (1, &IntWrapper(1))]
+        );
+        sparse.iter_mut().for_each(|(_, value)| value.0 = 0);
+        assert!(sparse.iter().all(|(_, value)| value.0 == 0));
+        assert!(SparseBinaryTree::<IntWrapper>::default().is_empty());
+    }
+
+    #[test]
+    fn test_sparse_lookups_after_rebuild() {
+        use Direction::{Left, Right};
+
+        let indices = vec![1, 2, 5, 3];
+        let sparse = SparseBinaryTree::new_with_data(
+            indices.iter().map(|&i| IntWrapper(i as i32)).collect(),
+            indices.clone(),
+        );
+        let path = Path::new(vec![Left, Right, Left]);
+        let (lca, lca_path) = sparse.lca_idx(&path).unwrap();
+        assert_eq!((lca, lca_path), (5, Path::new(vec![Left, Right])));
+        assert_eq!(sparse.lca_idx(&Path::new(vec![Right, Right])).unwrap().0, 3);
+
+        // Deserializing rebuilds the position map.
+        let tree = SparseBinaryTree::new_with_data(vec![7, 8], vec![1, 3]);
+        let decoded: SparseBinaryTree<i32> =
+            bincode::deserialize(&bincode::serialize(&tree).unwrap()).unwrap();
+        assert_eq!(decoded.get_by_index(3), Some(&8));
+        assert_eq!(decoded, tree);
+
+        // Indices pushed without going through the tree are still found.
+        let mut pushed = sparse.clone();
+        pushed.packed_indices.push(10);
+        pushed.packed_buckets.push(IntWrapper(10));
+        assert_eq!(pushed.get_by_index(10), Some(&IntWrapper(10)));
+        assert_eq!(pushed.lca_idx(&path).unwrap().0, 10);
+
+        // A repeated index resolves to its first position.
+        let repeated = SparseBinaryTree::new_with_data(vec![IntWrapper(1), IntWrapper(2)], vec![4, 4]);
+        assert_eq!(repeated.get_by_index(4), Some(&IntWrapper(1)));
+    }
+
+    #[test]
+    fn test_contiguous_runs() {
+        assert_eq!(tree::contiguous_runs(&[]).count(), 0);
+        assert_eq!(tree::contiguous_runs(&[4]).collect::<Vec<_>>(), vec![4..5]);
+        assert_eq!(
+            tree::contiguous_runs(&[1, 2, 3, 4, 6, 7, 13]).collect::<Vec<_>>(),
+            vec![1..5, 6..8, 13..14]
+        );
+        // Unsorted indices still come out in order, in shorter runs.
+        assert_eq!(
+            tree::contiguous_runs(&[3, 2, 5, 6]).collect::<Vec<_>>(),
+            vec![3..4, 2..3, 5..7]
+        );
+    }
 }
@@ -1,7 +1,19 @@
 use myco_rs::{crypto::{kdf, prf, encrypt, decrypt, EncryptionType}, dtypes::Key, utils::trim_zeros};
 #[cfg(test)]
 mod util_tests {
-    use myco_rs::constants::INNER_BLOCK_SIZE;
+    use myco_rs::{
+        constants::{
+            BLOCK_SIZE, COMMITMENT_SIZE, D, INNER_BLOCK_SIZE, MESSAGE_SIZE, NONCE_SIZE,
+            NUM_BUCKETS_PER_BATCH_WRITE_CHUNK, NUM_BUCKETS_PER_READ_PATHS_CHUNK, TAG_SIZE,
+        },
+        dtypes::{Block, Bucket, BucketDelta, Path, SparseBuckets},
+        error::MycoError,
+        server2::{read_chunk, Server2},
+        utils::{
+            get_leaf_path_indices, get_path_indices, pad, padme_length, unpad, write_durably,
+            Padding,
+        },
+    };
     use rand::{seq::SliceRandom, thread_rng, RngCore, SeedableRng};
 
     #[test]
@@ -78,6 +90,49 @@ mod util_tests {
         }
     }
 
+    #[test]
+    fn test_encryption_commits_to_key() {
+        let mut rng = thread_rng();
+        let key = Key::random(&mut rng);
+        let other = Key::random(&mut rng);
+        let ciphertext = encrypt(&key.0, b"message", EncryptionType::Encrypt).unwrap();
+        assert_eq!(ciphertext.len(), MESSAGE_SIZE + NONCE_SIZE + COMMITMENT_SIZE + TAG_SIZE);
+
+        assert!(matches!(
+            decrypt(&other.0, &ciphertext),
+            Err(MycoError::DecryptAuthFailed)
+        ));
+        // Flipping a bit of the commitment is caught even under the right key.
+        let mut tampered = ciphertext.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(matches!(
+            decrypt(&key.0, &tampered),
+            Err(MycoError::DecryptAuthFailed)
+        ));
+        assert!(decrypt(&key.0, &ciphertext[..NONCE_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_block_key_hint() {
+        let mut rng = thread_rng();
+        let k_oblv = Key::random(&mut rng);
+        let ciphertext = encrypt(&k_oblv.0, b"ct", EncryptionType::DoubleEncrypt).unwrap();
+        let block = Block::with_hint(&k_oblv.0, ciphertext.clone()).unwrap();
+        assert_eq!(block.0.len(), BLOCK_SIZE);
+        assert_eq!(block.ciphertext(), &ciphertext[..]);
+        assert!(block.matches_hint(&k_oblv.0));
+        assert!(!block.matches_hint(&Key::random(&mut rng).0));
+
+        // Re-encrypting the same payload under the same key gives a fresh hint.
+        let again = encrypt(&k_oblv.0, b"ct", EncryptionType::DoubleEncrypt).unwrap();
+        assert_ne!(Block::with_hint(&k_oblv.0, again).unwrap().hint(), block.hint());
+    }
+
+    #[test]
+    fn test_crypto_self_test_passes() {
+        myco_rs::crypto::self_test().expect("Self-test failed");
+    }
+
     use super::*;
     use rand_chacha::ChaCha20Rng;
 
@@ -92,4 +147,251 @@ mod util_tests {
         v2.shuffle(&mut rng2);
         assert_eq!(v1, v2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_generate_test_certificates() {
+        let dir = std::env::temp_dir().join(format!("myco-certs-{}", std::process::id()));
+        let (cert, key) = myco_rs::utils::generate_test_certificates(Some(&dir))
+            .expect("Certificate generation failed");
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .expect("Generated certificate rejected");
+
+        // The written PEM files load as a TLS identity too.
+        myco_rs::framed::tls_acceptor(&dir.join("server-cert.pem"), &dir.join("server-key.pem"))
+            .expect("Written certificate rejected");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_padding_keeps_trailing_zeros() {
+        let message = [7, 0, 0];
+        let padded = pad(&message, MESSAGE_SIZE, Padding::LengthPrefixed).unwrap();
+        assert_eq!(padded.len(), MESSAGE_SIZE);
+        assert_eq!(unpad(&padded, Padding::LengthPrefixed).unwrap(), message);
+
+        // Zero padding can't tell the message's zeros from its own.
+        let padded = pad(&message, MESSAGE_SIZE, Padding::Zeros).unwrap();
+        assert_eq!(unpad(&padded, Padding::Zeros).unwrap(), vec![7]);
+
+        assert!(pad(&[1; MESSAGE_SIZE - 1], MESSAGE_SIZE, Padding::LengthPrefixed).is_err());
+        assert!(unpad(&[0, 9, 1], Padding::LengthPrefixed).is_err());
+    }
+
+    #[test]
+    fn test_padme_buckets_lengths() {
+        assert_eq!(
+            [0, 1, 9, 100, 200].map(padme_length),
+            [0, 1, 10, 104, 208]
+        );
+        // Lengths in the same bucket pad to the same plaintext.
+        let short = unpad(&pad(&[1; 101], MESSAGE_SIZE, Padding::Padme).unwrap(), Padding::Padme);
+        let long = unpad(&pad(&[1; 104], MESSAGE_SIZE, Padding::Padme).unwrap(), Padding::Padme);
+        assert_eq!(short.unwrap().len(), 104);
+        assert_eq!(long.unwrap().len(), 104);
+        // Buckets beyond the message size are cut off.
+        let largest = pad(&[1; MESSAGE_SIZE - 2], MESSAGE_SIZE, Padding::Padme).unwrap();
+        assert_eq!(unpad(&largest, Padding::Padme).unwrap().len(), MESSAGE_SIZE - 2);
+    }
+
+    #[test]
+    fn test_sparse_buckets() {
+        let mut full = Bucket::default();
+        full.push(Block::new_random());
+        let buckets: Vec<Bucket> = (0..11)
+            .map(|i| if i % 3 == 0 { full.clone() } else { Bucket::default() })
+            .collect();
+        let sparse = SparseBuckets::new(buckets.clone());
+        assert_eq!((sparse.len(), sparse.present()), (11, 4));
+        assert_eq!(sparse.into_buckets().unwrap(), buckets);
+        assert!(SparseBuckets::new(vec![]).into_buckets().unwrap().is_empty());
+
+        // The bitmap has to cover exactly the buckets, and mark as many as were sent.
+        let malformed = [
+            SparseBuckets::from_parts(11, vec![0b1001_0001], vec![full.clone(); 3]),
+            SparseBuckets::from_parts(3, vec![0b1001], vec![full.clone(); 2]),
+            SparseBuckets::from_parts(3, vec![0b011], vec![full.clone()]),
+            SparseBuckets::from_parts(3, vec![0b001], vec![Bucket::default()]),
+        ];
+        for sparse in malformed {
+            assert!(matches!(sparse.into_buckets(), Err(MycoError::ProtocolError(_))));
+        }
+    }
+
+    #[test]
+    fn test_bucket_delta() {
+        let mut old = Bucket::default();
+        (0..3).for_each(|_| old.push(Block::new_random()));
+
+        // Only the changed blocks are sent.
+        let mut new = old.clone();
+        new[1] = Block::new_random();
+        let delta = BucketDelta::between(&old, new.clone());
+        assert_eq!(delta, BucketDelta::Blocks(vec![(1, new[1].clone())]));
+        assert_eq!(delta.apply(&old).unwrap(), new);
+        assert_eq!(BucketDelta::between(&old, old.clone()), BucketDelta::Blocks(vec![]));
+
+        // Buckets that changed entirely or in length are sent in full.
+        let mut fresh = Bucket::default();
+        (0..3).for_each(|_| fresh.push(Block::new_random()));
+        assert_eq!(BucketDelta::between(&old, fresh.clone()), BucketDelta::Full(fresh.clone()));
+        assert_eq!(
+            BucketDelta::between(&Bucket::default(), fresh.clone()),
+            BucketDelta::Full(fresh)
+        );
+
+        let out_of_range = BucketDelta::Blocks(vec![(3, Block::new_random())]);
+        assert!(matches!(out_of_range.apply(&old), Err(MycoError::MalformedRequest(_))));
+    }
+
+    #[test]
+    fn test_path_indices_are_sorted() {
+        let mut rng = rand_chacha::ChaCha20Rng::from_entropy();
+        let mut paths: Vec<Path> = (0..200).map(|_| Path::random(&mut rng)).collect();
+        let indices = get_path_indices(paths.clone());
+        assert_eq!(indices[0], 1);
+        assert!(indices.windows(2).all(|pair| pair[0] < pair[1]));
+        for path in &paths {
+            assert!(indices.binary_search(&path.to_index()).is_ok());
+        }
+
+        // The order, and so every chunk boundary, doesn't depend on the order of the paths.
+        paths.shuffle(&mut thread_rng());
+        let shuffled = get_path_indices(paths);
+        assert_eq!(shuffled, indices);
+        assert_eq!(read_chunk(&shuffled, 1), read_chunk(&indices, 1));
+    }
+
+    #[test]
+    fn test_leaf_path_indices() {
+        let mut rng = rand_chacha::ChaCha20Rng::from_entropy();
+        let paths: Vec<Path> = (0..200).map(|_| Path::random(&mut rng)).collect();
+        let leaves: Vec<u32> = paths
+            .iter()
+            .map(|path| (path.to_index() - (1 << D)) as u32)
+            .collect();
+        assert_eq!(get_leaf_path_indices(&leaves).unwrap(), get_path_indices(paths));
+        assert_eq!(get_leaf_path_indices(&[]).unwrap(), vec![1]);
+
+        assert!(get_leaf_path_indices(&[(1 << D) - 1]).is_ok());
+        assert!(matches!(
+            get_leaf_path_indices(&[0, 1 << D]),
+            Err(MycoError::MalformedRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_server2_pathset_round_trip() {
+        let mut rng = rand_chacha::ChaCha20Rng::from_entropy();
+        let paths: Vec<Path> = (0..50).map(|_| Path::random(&mut rng)).collect();
+        let indices = get_path_indices(paths);
+        let buckets: Vec<Bucket> = indices
+            .iter()
+            .map(|&i| {
+                let mut bucket = Bucket::default();
+                bucket.push(Block::new(i.to_le_bytes().to_vec()));
+                bucket
+            })
+            .collect();
+
+        let mut server2 = Server2::new();
+        server2.store_path_indices(indices.clone()).unwrap();
+        for (chunk_idx, chunk) in buckets.chunks(NUM_BUCKETS_PER_BATCH_WRITE_CHUNK).enumerate() {
+            server2.chunk_write(0, chunk.to_vec(), chunk_idx).unwrap();
+        }
+        for (chunk_idx, chunk) in buckets.chunks(NUM_BUCKETS_PER_READ_PATHS_CHUNK).enumerate() {
+            assert_eq!(server2.read_pathset_chunk(chunk_idx).unwrap(), chunk);
+        }
+        assert_eq!(server2.read_paths_client(indices.clone()).unwrap(), buckets);
+        for (index, bucket) in indices.iter().zip(&buckets) {
+            assert_eq!(&server2.bucket(*index).unwrap(), bucket);
+        }
+    }
+
+    #[test]
+    fn test_server2_rejects_invalid_pathsets() {
+        let mut server2 = Server2::new();
+        let end = 1 << (D + 1);
+        for pathset in [vec![2, 1], vec![1, 1], vec![0, 1], vec![1, end], vec![usize::MAX]] {
+            assert!(matches!(
+                server2.store_path_indices(pathset.clone()),
+                Err(MycoError::MalformedRequest(_))
+            ));
+            assert!(matches!(
+                server2.read_and_store_path_indices(pathset),
+                Err(MycoError::MalformedRequest(_))
+            ));
+        }
+
+        // The stored pathset is left as it was.
+        server2.store_path_indices(vec![1, 2, 3]).unwrap();
+        assert!(server2.store_path_indices(vec![3, 2]).is_err());
+        assert_eq!(server2.pathset_chunk(0), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_server2_rejects_chunks_outside_pathset() {
+        let mut server2 = Server2::new();
+        server2.store_path_indices(vec![1, 2, 3]).unwrap();
+
+        for chunk_idx in [1, usize::MAX] {
+            assert!(matches!(
+                server2.chunk_write(0, vec![Bucket::default()], chunk_idx),
+                Err(MycoError::MalformedRequest(_))
+            ));
+            assert!(matches!(
+                server2.read_pathset_chunk(chunk_idx),
+                Err(MycoError::MalformedRequest(_))
+            ));
+            assert!(matches!(
+                server2.read_paths_client_chunk(chunk_idx, vec![1, 2, 3]),
+                Err(MycoError::MalformedRequest(_))
+            ));
+        }
+        // A chunk can't carry more buckets than the pathset has left.
+        assert!(matches!(
+            server2.chunk_write(0, vec![Bucket::default(); 4], 0),
+            Err(MycoError::MalformedRequest(_))
+        ));
+        server2.chunk_write(0, vec![Bucket::default(); 3], 0).unwrap();
+    }
+
+    #[test]
+    fn test_base32() {
+        use myco_rs::utils::{base32_decode, base32_encode};
+
+        // RFC 4648 test vectors, without padding.
+        for (decoded, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32_encode(decoded.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), decoded.as_bytes());
+        }
+        assert_eq!(base32_decode("mzxw6ytboi").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW6YTBO1").is_err());
+        assert!(base32_decode("MZ=").is_err());
+        // Leftover bits must be zero, and never make up a whole character.
+        assert!(base32_decode("MZ").is_err());
+        assert!(base32_decode("MZXW6Y").is_err());
+    }
+
+    #[test]
+    fn test_write_durably_replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!("myco-write-durably-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.bin");
+        write_durably(&path, b"old").unwrap();
+        write_durably(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!path.with_extension("tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
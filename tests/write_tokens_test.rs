@@ -0,0 +1,238 @@
+#[cfg(test)]
+mod write_tokens_tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use myco_rs::{
+        client::Client,
+        constants::{WRITE_TOKEN_EPOCHS, WRITE_TOKEN_SIZE},
+        dtypes::Key,
+        error::MycoError,
+        network::LocalServer2Access,
+        registration::AccountCredentials,
+        server1::{self, AnonymityGate, Server1},
+        server2::Server2,
+        tls::TlsTrust,
+        transport::{HttpsTransport, TransportServer1Access},
+        write_tokens::{TokenIssuer, TokenRequest},
+    };
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use tokio::net::TcpListener;
+
+    fn new_server1() -> Server1 {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        Server1::new(Box::new(LocalServer2Access { server: s2 }))
+    }
+
+    /// Have `server1` issue a token for its current epoch to `account`.
+    fn issue(server1: &mut Server1, account: &AccountCredentials, rng: &mut ChaCha20Rng) -> Vec<u8> {
+        let request = TokenRequest::new(server1.epoch, rng);
+        let issued = server1
+            .issue_write_tokens(account, vec![(request.epoch, request.blinded())])
+            .unwrap();
+        assert_eq!(issued.len(), 1);
+        request.finish(&issued[0].1).unwrap()
+    }
+
+    fn queue(server1: &mut Server1, token: Vec<u8>, rng: &mut ChaCha20Rng) -> Result<u64, MycoError> {
+        let mut f = vec![0; 32];
+        rng.fill_bytes(&mut f);
+        server1.queue_write(vec![0; 32], f, Key::random(rng), b"Alice".to_vec(), token, None)
+    }
+
+    #[test]
+    fn test_issued_tokens_verify_for_their_epoch_only() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let issuer = TokenIssuer::new(&mut rng);
+        let finish = |request: TokenRequest| {
+            let evaluated = issuer.issue(request.epoch, &request.blinded()).unwrap();
+            request.finish(&evaluated).unwrap()
+        };
+
+        let request = TokenRequest::new(3, &mut rng);
+        let blinded = request.blinded();
+        let token = finish(request);
+        assert_eq!(token.len(), WRITE_TOKEN_SIZE);
+        assert!(issuer.verify(3, &token).is_ok());
+        // The issuer never sees the token it signed.
+        assert!(!token.windows(blinded.len()).any(|window| window == blinded));
+
+        assert!(matches!(issuer.verify(4, &token), Err(MycoError::InvalidWriteToken)));
+        let other = TokenIssuer::new(&mut rng);
+        assert!(matches!(other.verify(3, &token), Err(MycoError::InvalidWriteToken)));
+        assert!(matches!(issuer.verify(3, &token[1..]), Err(MycoError::InvalidWriteToken)));
+
+        // Tokens of the same epoch are unlinkable to each other.
+        let second = finish(TokenRequest::new(3, &mut rng));
+        assert_ne!(second, token);
+        assert!(issuer.verify(3, &second).is_ok());
+
+        assert!(matches!(
+            issuer.issue(3, &[0; 32]),
+            Err(MycoError::MalformedRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_tokens_server1_never_issued_are_rejected() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut server1 = new_server1();
+        let account = server1.registrations.register(&mut rng).unwrap();
+        server1.batch_init(4);
+
+        let mut forged = vec![0; WRITE_TOKEN_SIZE];
+        rng.fill_bytes(&mut forged);
+        assert!(matches!(
+            queue(&mut server1, forged, &mut rng),
+            Err(MycoError::InvalidWriteToken)
+        ));
+
+        // A well-formed token issued by another Server1 is no better.
+        let mut elsewhere = new_server1();
+        let stranger = elsewhere.registrations.register(&mut rng).unwrap();
+        let token = issue(&mut elsewhere, &stranger, &mut rng);
+        assert!(matches!(
+            queue(&mut server1, token, &mut rng),
+            Err(MycoError::InvalidWriteToken)
+        ));
+        assert_eq!(server1.write_stats().writes, 0);
+
+        let token = issue(&mut server1, &account, &mut rng);
+        assert_eq!(queue(&mut server1, token, &mut rng).unwrap(), 0);
+        let stats = server1.write_stats();
+        assert_eq!((stats.writes, stats.distinct_writers), (1, 1));
+    }
+
+    #[test]
+    fn test_tokens_are_issued_once_per_account_and_epoch() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut server1 = new_server1();
+        let account = server1.registrations.register(&mut rng).unwrap();
+        let requests = |epochs: &[u64], rng: &mut ChaCha20Rng| {
+            epochs
+                .iter()
+                .map(|epoch| (*epoch, TokenRequest::new(*epoch, rng).blinded()))
+                .collect::<Vec<_>>()
+        };
+
+        let beyond = WRITE_TOKEN_EPOCHS as u64;
+        let issued = server1
+            .issue_write_tokens(&account, requests(&[0, 1, beyond], &mut rng))
+            .unwrap();
+        let epochs: Vec<u64> = issued.iter().map(|(epoch, _)| *epoch).collect();
+        assert_eq!(epochs, vec![0, 1]);
+        assert!(server1
+            .issue_write_tokens(&account, requests(&[0, 1], &mut rng))
+            .unwrap()
+            .is_empty());
+
+        // Rotating the account doesn't reset its claims, and unregistered clients get nothing.
+        let rotated = server1.registrations.rotate(&account, &mut rng).unwrap();
+        let issued = server1
+            .issue_write_tokens(&rotated, requests(&[1, 2], &mut rng))
+            .unwrap();
+        assert_eq!(issued.iter().map(|(epoch, _)| *epoch).collect::<Vec<_>>(), vec![2]);
+        assert!(matches!(
+            server1.issue_write_tokens(&account, requests(&[3], &mut rng)),
+            Err(MycoError::UnknownRegistration)
+        ));
+    }
+
+    #[test]
+    fn test_write_quota_requires_issued_tokens() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut server1 = new_server1();
+        server1.set_write_quota(Some(1));
+        let account = server1.registrations.register(&mut rng).unwrap();
+        server1.batch_init(2);
+
+        assert!(matches!(
+            queue(&mut server1, vec![], &mut rng),
+            Err(MycoError::InvalidWriteToken)
+        ));
+        let token = issue(&mut server1, &account, &mut rng);
+        queue(&mut server1, token.clone(), &mut rng).unwrap();
+        assert!(matches!(
+            queue(&mut server1, token.clone(), &mut rng),
+            Err(MycoError::WriteQuotaExceeded)
+        ));
+
+        // The next epoch needs a token of its own.
+        server1.batch_write().unwrap();
+        server1.batch_init(2);
+        assert!(matches!(
+            queue(&mut server1, token, &mut rng),
+            Err(MycoError::InvalidWriteToken)
+        ));
+        let token = issue(&mut server1, &account, &mut rng);
+        assert_eq!(queue(&mut server1, token, &mut rng).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_only_issued_tokens_open_the_anonymity_gate() {
+        let mut rng = ChaCha20Rng::from_entropy();
+        let mut server1 = new_server1();
+        server1.set_anonymity_gate(Some(AnonymityGate {
+            min_writers: 2,
+            timeout: Duration::from_secs(60),
+        }));
+        server1.batch_init(4);
+
+        for _ in 0..2 {
+            let mut forged = vec![0; WRITE_TOKEN_SIZE];
+            rng.fill_bytes(&mut forged);
+            assert!(queue(&mut server1, forged, &mut rng).is_err());
+        }
+        // Writes without a token are accepted but don't count as writers.
+        queue(&mut server1, vec![], &mut rng).unwrap();
+        assert!(server1.anonymity_gate_remaining().is_some());
+
+        for _ in 0..2 {
+            let account = server1.registrations.register(&mut rng).unwrap();
+            let token = issue(&mut server1, &account, &mut rng);
+            queue(&mut server1, token, &mut rng).unwrap();
+        }
+        assert_eq!(server1.anonymity_gate_remaining(), None);
+        assert_eq!(server1.write_stats().distinct_writers, 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_fetches_tokens_over_https() {
+        let s2 = Arc::new(Mutex::new(Server2::new()));
+        let s2_access = Box::new(LocalServer2Access { server: s2 });
+        let mut server1 = Server1::new(s2_access.clone());
+        server1.set_write_quota(Some(1));
+        let state = server1::http::AppState::new(server1);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = server1::http::router().with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let transport = || {
+            TransportServer1Access::new(Box::new(
+                HttpsTransport::new(&format!("http://{}", addr), &TlsTrust::default()).unwrap(),
+            ))
+        };
+        let credentials = transport().register().await.unwrap();
+        let mut alice = Client::new("Alice".to_string(), Box::new(transport()), s2_access);
+        assert!(matches!(
+            alice.fetch_write_tokens().await,
+            Err(MycoError::UnknownRegistration)
+        ));
+        alice.set_registration(Some(credentials));
+        assert_eq!(alice.fetch_write_tokens().await.unwrap(), WRITE_TOKEN_EPOCHS);
+        assert_eq!(alice.fetch_write_tokens().await.unwrap(), 0);
+
+        let mut rng = ChaCha20Rng::from_entropy();
+        let k = Key::random(&mut rng);
+        alice.setup(&k).unwrap();
+        state.server1.write().await.async_batch_init(1).await.unwrap();
+        state.control.set_epoch_open(true);
+        alice.async_write(&[1], &k).await.unwrap();
+        let stats = state.server1.read().await.write_stats();
+        assert_eq!((stats.writes, stats.distinct_writers), (1, 1));
+    }
+}